@@ -0,0 +1,306 @@
+use bitbeam_client::{Client, FileQuery, UploadOptions};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+mod profile;
+
+use profile::{Profile, Profiles};
+
+/// This struct represents the command line of `bbm`.
+/// The server is taken from `--url`, else from the chosen profile of the config file.
+#[derive(Parser, Debug)]
+#[command(name = "bbm", version, about = "Upload to and manage files on a bitBeam server")]
+struct Cli {
+    /// Path of the config file with the profiles (defaults to ~/.config/bbm/config.toml)
+    #[arg(long, env = "BBM_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Profile to use instead of the default one
+    #[arg(long, short = 'p', env = "BBM_PROFILE", global = true)]
+    profile: Option<String>,
+    /// URL of the server, overrides the profile
+    #[arg(long, env = "BBM_URL", global = true)]
+    url: Option<String>,
+    /// Key or API token to authenticate with, overrides the profile
+    #[arg(long, env = "BBM_KEY", global = true, hide_env_values = true)]
+    key: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// This enum represents the commands of `bbm`.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Upload files and print their download links
+    Put {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Remove the files after this long, like 3600, 90m, 12h, 7d or never
+        #[arg(long)]
+        expires: Option<String>,
+        /// Number of downloads before the files are removed, 0 for unlimited
+        #[arg(long)]
+        limit: Option<i32>,
+        /// Name to store the file under instead of its own, only for a single file
+        #[arg(long)]
+        name: Option<String>,
+        /// Comma separated list of tags
+        #[arg(long)]
+        tags: Option<String>,
+        /// Only the owner and holders of a share token can download the files
+        #[arg(long)]
+        private: bool,
+        /// Note shown with the files, like what a build contains
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Download a file
+    Get {
+        id: String,
+        /// Path to write the file to, - for standard output (defaults to the name of the file)
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+    },
+    /// List your files
+    Ls {
+        /// Only list files with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// List the files in the trash instead
+        #[arg(long)]
+        trash: bool,
+        /// List the files of every user, needs an admin key
+        #[arg(long)]
+        all: bool,
+        /// List at most this many files, newest first
+        #[arg(long)]
+        limit: Option<i64>,
+    },
+    /// Move files to the trash
+    Rm {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Manage the profiles of the servers you use
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+}
+
+/// This enum represents the `bbm profile` commands.
+#[derive(Subcommand, Debug)]
+enum ProfileCommand {
+    /// Add a server or change its profile
+    Add {
+        name: String,
+        /// URL of the server, like https://files.example.com
+        #[arg(long)]
+        url: String,
+        /// Key or API token to authenticate with
+        #[arg(long)]
+        key: Option<String>,
+        /// Use the profile by default
+        #[arg(long)]
+        default: bool,
+    },
+    /// List the profiles
+    List,
+    /// Use a profile by default
+    Use { name: String },
+    /// Remove a profile
+    Remove { name: String },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("bbm: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs a command, a failed one returns the message to print.
+async fn run(cli: Cli) -> Result<(), String> {
+    let path = profile::path(cli.config)?;
+    let mut profiles = Profiles::load(&path)?;
+    if let Command::Profile { command } = cli.command {
+        return manage_profiles(command, &mut profiles, &path);
+    }
+    let server = match cli.url {
+        Some(url) => Profile { url, key: None },
+        None => profiles.get(cli.profile.as_deref())?.clone(),
+    };
+    let mut client = Client::new(server.url);
+    if let Some(key) = cli.key.or(server.key) {
+        client = client.with_key(key);
+    }
+
+    match cli.command {
+        Command::Put {
+            files,
+            expires,
+            limit,
+            name,
+            tags,
+            private,
+            description,
+        } => {
+            if name.is_some() && files.len() > 1 {
+                return Err("--name only works with a single file".to_string());
+            }
+            let options = UploadOptions {
+                file_name: name,
+                download_limit: limit,
+                tags,
+                visibility: private.then(|| "private".to_string()),
+                max_age: expires,
+                description,
+                ..Default::default()
+            };
+            put(&client, &files, &options).await
+        }
+        Command::Get { id, output } => get(&client, &id, output).await,
+        Command::Ls { tag, trash, all, limit } => {
+            let query = FileQuery {
+                all: all.then_some(true),
+                trash: trash.then_some(true),
+                tag,
+                ..Default::default()
+            };
+            ls(&client, query, limit).await
+        }
+        Command::Rm { ids } => rm(&client, &ids).await,
+        Command::Profile { .. } => unreachable!("profiles are managed before a server is chosen"),
+    }
+}
+
+/// Uploads the files one after the other and prints a download link per file.
+/// A failed upload does not stop the others, but makes the command fail.
+async fn put(client: &Client, files: &[PathBuf], options: &UploadOptions) -> Result<(), String> {
+    let mut failed = 0;
+    for file in files {
+        match client.upload_file(file, options).await {
+            Ok(uploaded) => println!("{}", uploaded.links.download),
+            Err(e) => {
+                eprintln!("bbm: could not upload {}: {}", file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} uploads failed", failed, files.len())),
+    }
+}
+
+/// Downloads a file, to the name it was uploaded under unless another path is given.
+async fn get(client: &Client, id: &str, output: Option<PathBuf>) -> Result<(), String> {
+    if output.as_deref() == Some(std::path::Path::new("-")) {
+        let written = client.download(id, &mut tokio::io::stdout()).await;
+        return written.map(|_| ()).map_err(|e| e.to_string());
+    }
+    let output = match output {
+        Some(output) => output,
+        None => {
+            let info = client.file_info(id).await.map_err(|e| e.to_string())?;
+            // only the last part of the name, a stored name must not write outside of the current directory
+            match std::path::Path::new(&info.file_name).file_name() {
+                Some(name) => PathBuf::from(name),
+                None => PathBuf::from(id),
+            }
+        }
+    };
+    let written = client.download_to_file(id, &output).await.map_err(|e| e.to_string())?;
+    eprintln!("Wrote {} bytes to {}", written, output.display());
+    Ok(())
+}
+
+/// Lists the files, newest first, following the pages until `limit` files or the last page.
+async fn ls(client: &Client, mut query: FileQuery, limit: Option<i64>) -> Result<(), String> {
+    let mut listed = 0;
+    loop {
+        let remaining = limit.map(|limit| limit - listed);
+        query.limit = Some(remaining.unwrap_or(1000).clamp(1, 1000));
+        let page = client.list_files(&query).await.map_err(|e| e.to_string())?;
+        for file in &page.files {
+            let downloads = match file.download_limit {
+                0 => format!("{}/-", file.download_count),
+                limit => format!("{}/{}", file.download_count, limit),
+            };
+            println!("{}  {:>9}  {:>7}  {}", file.id, size(file.file_size), downloads, file.file_name);
+        }
+        listed += page.files.len() as i64;
+        match page.next {
+            Some(next) if limit.is_none_or(|limit| listed < limit) => query.after = Some(next),
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Moves the files to the trash, a failed one does not stop the others.
+async fn rm(client: &Client, ids: &[String]) -> Result<(), String> {
+    let mut failed = 0;
+    for id in ids {
+        match client.delete_file(id).await {
+            Ok(file) => println!("Removed {} ({})", file.id, file.file_name),
+            Err(e) => {
+                eprintln!("bbm: could not remove {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} removals failed", failed, ids.len())),
+    }
+}
+
+/// Runs a `bbm profile` command and saves the changed profiles.
+fn manage_profiles(command: ProfileCommand, profiles: &mut Profiles, path: &PathBuf) -> Result<(), String> {
+    match command {
+        ProfileCommand::Add { name, url, key, default } => {
+            if default || profiles.profiles.is_empty() {
+                profiles.default = Some(name.clone());
+            }
+            profiles.profiles.insert(name, Profile { url, key });
+        }
+        ProfileCommand::List => {
+            for (name, profile) in &profiles.profiles {
+                let marker = if profiles.default.as_ref() == Some(name) { "*" } else { " " };
+                println!("{} {}  {}", marker, name, profile.url);
+            }
+            return Ok(());
+        }
+        ProfileCommand::Use { name } => {
+            if !profiles.profiles.contains_key(&name) {
+                return Err(format!("there is no profile {}", name));
+            }
+            profiles.default = Some(name);
+        }
+        ProfileCommand::Remove { name } => {
+            if profiles.profiles.remove(&name).is_none() {
+                return Err(format!("there is no profile {}", name));
+            }
+            if profiles.default.as_ref() == Some(&name) {
+                profiles.default = None;
+            }
+        }
+    }
+    profiles.save(path)
+}
+
+/// Returns a size in bytes as a short human readable text, like 1.5M.
+fn size(bytes: i64) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{}B", bytes),
+        _ => format!("{:.1}{}", value, units[unit]),
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// This struct represents a server `bbm` talks to, with the key it authenticates with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub url: String,
+    pub key: Option<String>,
+}
+
+/// This struct represents the config file of `bbm`, the profiles by name and the one used by default.
+/// It lives at `$XDG_CONFIG_HOME/bbm/config.toml`, `~/.config/bbm/config.toml` without it:
+///
+/// ```toml
+/// default = "home"
+///
+/// [profiles.home]
+/// url = "https://files.example.com"
+/// key = "<key>"
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Profiles {
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Returns the path of the config file, `--config` or `BBM_CONFIG` win over the default location.
+pub fn path(config: Option<PathBuf>) -> Result<PathBuf, String> {
+    if let Some(config) = config {
+        return Ok(config);
+    }
+    let directory = match std::env::var_os("XDG_CONFIG_HOME").filter(|directory| !directory.is_empty()) {
+        Some(directory) => PathBuf::from(directory),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return Err("neither XDG_CONFIG_HOME nor HOME is set, pass --config".to_string()),
+        },
+    };
+    Ok(directory.join("bbm").join("config.toml"))
+}
+
+impl Profiles {
+    /// Reads the config file, a missing one has no profiles.
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| format!("{} is not a valid config: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Profiles::default()),
+            Err(e) => Err(format!("could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Writes the config file, only the user may read it as it holds keys.
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("could not create {}: {}", parent.display(), e))?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let write = |mut file: std::fs::File| std::io::Write::write_all(&mut file, contents.as_bytes());
+        options
+            .open(path)
+            .and_then(write)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+
+    /// Returns the profile of the given name, the default one without a name.
+    pub fn get(&self, name: Option<&str>) -> Result<&Profile, String> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(name) => name,
+            None if self.profiles.len() == 1 => return Ok(self.profiles.values().next().unwrap()),
+            None => return Err("no profile chosen, pass --profile, set a default or pass --url".to_string()),
+        };
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("there is no profile {}, add it with bbm profile add", name))
+    }
+}
@@ -0,0 +1,259 @@
+//! An async client for the HTTP API of a bitBeam server.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), bitbeam_client::Error> {
+//! let client = bitbeam_client::Client::new("https://files.example.com").with_key("<key>");
+//! let uploaded = client.upload_file("report.pdf", &Default::default()).await?;
+//! println!("{}", uploaded.links.download);
+//! client.download_to_file(&uploaded.file.id, "copy.pdf").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use http_body::Frame;
+use http_body_util::StreamBody;
+use reqwest::{header, Body, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+mod types;
+
+pub use types::*;
+
+/// This enum represents the errors of the client.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent or the response not read.
+    Http(reqwest::Error),
+    /// A local file could not be read or written.
+    Io(std::io::Error),
+    /// The server refused the request, `error` is the code of its JSON error, empty if it answered with text.
+    Api {
+        status: u16,
+        error: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Api { status, error, message } if error.is_empty() => write!(f, "{} {}", status, message),
+            Error::Api { status, error, message } => write!(f, "{} {}: {}", status, error, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// This struct is a client of one bitBeam server, authenticated with the key of a user if one is set.
+/// It is cheap to clone, clones share their connections.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    key: Option<String>,
+}
+
+impl Client {
+    /// Returns a client of the server at `base_url`, like `https://files.example.com` or with its `base_path`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            key: None,
+        }
+    }
+
+    /// Returns the client authenticated with a user key or an API token.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Returns the client using the given `reqwest::Client`, for proxies, timeouts or custom certificates.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Registers a user and returns their key, use it with `with_key`.
+    pub async fn register(&self, username: &str, password: &str, email: Option<&str>) -> Result<RegisteredUser, Error> {
+        let credentials = serde_json::json!({ "username": username, "password": password, "email": email });
+        json(self.request(Method::POST, "/user/register").json(&credentials)).await
+    }
+
+    /// Uploads the file at `path`, streamed from disk, named after the path unless `options` name it.
+    pub async fn upload_file(&self, path: impl AsRef<Path>, options: &UploadOptions) -> Result<UploadedFile, Error> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let mut options = options.clone();
+        if options.file_name.is_none() {
+            options.file_name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        }
+        self.upload_reader(file, Some(length), &options).await
+    }
+
+    /// Uploads the contents of a reader as they are read, without holding them in memory.
+    /// A known `length` is sent as `Content-Length`, which lets the server refuse a file that is too large right away.
+    pub async fn upload_reader<R>(
+        &self,
+        reader: R,
+        length: Option<u64>,
+        options: &UploadOptions,
+    ) -> Result<UploadedFile, Error>
+    where
+        R: AsyncRead + Send + Sync + 'static,
+    {
+        let stream = ReaderStream::new(reader).map_ok(Frame::data);
+        let mut request = self.upload_request(options).body(Body::wrap(StreamBody::new(stream)));
+        if let Some(length) = length {
+            request = request.header(header::CONTENT_LENGTH, length);
+        }
+        json(request).await
+    }
+
+    /// Uploads a file held in memory.
+    pub async fn upload_bytes(&self, data: impl Into<Bytes>, options: &UploadOptions) -> Result<UploadedFile, Error> {
+        json(self.upload_request(options).body(data.into())).await
+    }
+
+    /// Returns the request of an upload with its options.
+    fn upload_request(&self, options: &UploadOptions) -> RequestBuilder {
+        let request = self.request(Method::POST, "/upload").query(options);
+        match &options.content_type {
+            Some(content_type) => request.header(header::CONTENT_TYPE, content_type),
+            None => request,
+        }
+    }
+
+    /// Downloads a file into `writer` as it arrives and returns the number of bytes written.
+    /// Every download counts towards the download limit of the file.
+    pub async fn download<W>(&self, uuid: &str, writer: &mut W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut response = send(self.request(Method::GET, &format!("/download/{}", uuid))).await?;
+        let mut written = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(written)
+    }
+
+    /// Downloads a file to `path` and returns the number of bytes written, an existing file is replaced.
+    pub async fn download_to_file(&self, uuid: &str, path: impl AsRef<Path>) -> Result<u64, Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        self.download(uuid, &mut file).await
+    }
+
+    /// Returns a page of the files of the user, see `FileQuery` for the filters.
+    pub async fn list_files(&self, query: &FileQuery) -> Result<FilePage, Error> {
+        json(self.request(Method::GET, "/files").query(query)).await
+    }
+
+    /// Returns the public metadata of a file, it does not count as a download.
+    pub async fn file_info(&self, uuid: &str) -> Result<FileInfo, Error> {
+        json(self.request(Method::GET, &format!("/file/{}/info", uuid))).await
+    }
+
+    /// Moves a file of the user to the trash and returns it.
+    pub async fn delete_file(&self, uuid: &str) -> Result<File, Error> {
+        json(self.request(Method::DELETE, &format!("/file/{}", uuid))).await
+    }
+
+    /// Changes the description of one of the user's files and returns the file.
+    pub async fn update_file(&self, uuid: &str, update: &FileUpdate) -> Result<File, Error> {
+        json(self.request(Method::PATCH, &format!("/file/{}", uuid)).json(update)).await
+    }
+
+    /// Returns the settings of the user.
+    pub async fn profile(&self) -> Result<Profile, Error> {
+        json(self.request(Method::GET, "/user/me")).await
+    }
+
+    /// Changes the settings of the user and returns all of them.
+    pub async fn update_profile(&self, update: &ProfileUpdate) -> Result<Profile, Error> {
+        json(self.request(Method::PATCH, "/user/me").json(update)).await
+    }
+
+    /// Deletes the account of the user together with all of their files.
+    pub async fn delete_account(&self) -> Result<DeletionReceipt, Error> {
+        json(self.request(Method::DELETE, "/user/me")).await
+    }
+
+    /// Returns every user with their storage usage, it needs an admin key.
+    pub async fn list_users(&self) -> Result<Vec<UserInfo>, Error> {
+        json(self.request(Method::GET, "/admin/users")).await
+    }
+
+    /// Deletes a user and returns their deleted files, it needs an admin key.
+    pub async fn delete_user(&self, username: &str) -> Result<Vec<File>, Error> {
+        json(self.request(Method::DELETE, &format!("/admin/users/{}", username))).await
+    }
+
+    /// Sets the upload limit of a user in bytes, `None` removes it, it needs an admin key.
+    pub async fn set_max_upload_bytes(&self, username: &str, max_upload_bytes: Option<i64>) -> Result<(), Error> {
+        let body = serde_json::json!({ "max_upload_bytes": max_upload_bytes });
+        let path = format!("/admin/users/{}/max_upload_bytes", username);
+        send(self.request(Method::PUT, &path).json(&body)).await.map(|_| ())
+    }
+
+    /// Returns a request to a path of the API with the key of the client.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.key {
+            Some(key) => request.header("key", key),
+            None => request,
+        }
+    }
+}
+
+/// Sends a request and returns the response, or the error the server answered with.
+async fn send(request: RequestBuilder) -> Result<Response, Error> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().await?;
+    let (error, message) = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(body) => (
+            body["error"].as_str().unwrap_or_default().to_string(),
+            body["message"].as_str().unwrap_or(&text).to_string(),
+        ),
+        Err(_) => (String::new(), text),
+    };
+    Err(Error::Api {
+        status: status.as_u16(),
+        error,
+        message,
+    })
+}
+
+/// Sends a request and returns its JSON response.
+async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+    Ok(send(request).await?.json().await?)
+}
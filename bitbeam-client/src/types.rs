@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+
+/// This struct represents a file as the server returns it, see `data::File` of the server.
+/// `download_limit` is 0 for unlimited downloads, `expires` and `deleted_at` are unix timestamps.
+/// The flags `encrypted`, `verified`, `published`, `blob_missing` and `pinned` are 1 if set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct File {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub upload_time: i64,
+    pub download_limit: i32,
+    pub download_count: i32,
+    pub file_size: i64,
+    pub download_url: String,
+    pub owner: String,
+    pub content_hash: Option<String>,
+    pub slug: Option<String>,
+    pub encrypted: i32,
+    pub detected_content_type: Option<String>,
+    pub visibility: String,
+    pub expires: Option<i64>,
+    pub deleted_at: Option<i64>,
+    pub version: i32,
+    pub cid: Option<String>,
+    pub expire_if_unused_days: Option<i32>,
+    pub last_downloaded_at: Option<i64>,
+    #[serde(default)]
+    pub verified: i32,
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    #[serde(default)]
+    pub published: i32,
+    #[serde(default)]
+    pub blob_missing: i32,
+    #[serde(default)]
+    pub pinned: i32,
+    #[serde(default)]
+    pub exhausted_at: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub human: Option<HumanFile>,
+}
+
+/// This struct represents the fields of a file formatted for people, see `data::HumanFile` of the server.
+/// The server only sends them when asked to with `human`, like `14.2 MiB` or `3 days ago`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HumanFile {
+    pub file_size: String,
+    pub upload_time: String,
+    pub downloads: String,
+    pub expires: Option<String>,
+    pub last_downloaded_at: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+/// This struct represents the public metadata of a file, see `data::FileInfo` of the server.
+/// `downloads_remaining` is `None` for unlimited downloads, `expires_in` the seconds until the file expires.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FileInfo {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub file_size: i64,
+    pub upload_time: i64,
+    pub download_limit: i32,
+    pub download_count: i32,
+    pub downloads_remaining: Option<i32>,
+    pub download_url: String,
+    pub encrypted: bool,
+    pub visibility: String,
+    pub expires: Option<i64>,
+    pub expires_in: Option<i64>,
+    pub limited_by: String,
+    pub version: i32,
+    pub verified: bool,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub human: Option<HumanFile>,
+}
+
+/// This struct represents the response to a successful upload, see `data::UploadedFile` of the server.
+/// `delete_token` deletes the file once without a key, only the response to the upload carries it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UploadedFile {
+    #[serde(flatten)]
+    pub file: File,
+    pub links: UploadLinks,
+    pub delete_token: Option<String>,
+    pub content_md5: Option<String>,
+    pub share_url_template: Option<String>,
+}
+
+/// This struct represents the links of an uploaded file, `view` is `None` for end to end encrypted files.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UploadLinks {
+    pub download: String,
+    pub view: Option<String>,
+    pub share: String,
+    pub delete: Option<String>,
+    pub qr: String,
+}
+
+/// This struct represents the options of an upload, see `data::UploadOptions` of the server.
+/// Missing options keep the defaults of the user and the server.
+/// They are sent as query parameters, so file names don't have to be valid header values.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UploadOptions {
+    /// The name of the file, `upload_file` takes the name of the path if it is missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// The content type of the file, sent as the `Content-Type` of the upload
+    #[serde(skip)]
+    pub content_type: Option<String>,
+    /// The download limit of the file, 0 for unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_limit: Option<i32>,
+    /// A unique vanity name to download the file from `/d/{slug}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    /// `true` if the body was encrypted by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    /// A comma separated list of tags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    /// `public` or `private`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    /// The number of days without a download after which the file is removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_if_unused_days: Option<i32>,
+    /// A unix timestamp or RFC 3339 date after which the file is removed, `never` to keep it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// The seconds or a duration like `7d` after the upload after which the file is removed, `never` to keep it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<String>,
+    /// The `Cache-Control` header of downloads of the file, like `public, max-age=3600`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>,
+    /// `true` to list the file in the public directory at `/public`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish: Option<bool>,
+    /// A note of up to 1000 characters shown with the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// This struct represents a change of the metadata of a file.
+/// A missing description is left as it is, `Some(None)` removes it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FileUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<Option<String>>,
+}
+
+/// This struct represents the filters of a file listing, see `data::AllFilesQuery` of the server.
+/// `all` lists the files of every user and needs an admin key.
+/// `limit` returns the listing in pages, `after` is the `next` of the previous page.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FileQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_contains: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human: Option<bool>,
+}
+
+/// This struct represents a page of files, `next` is `None` on the last page.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FilePage {
+    pub files: Vec<File>,
+    pub next: Option<String>,
+}
+
+/// This struct represents the response to a registration, `key` authenticates the user from now on.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegisteredUser {
+    pub key: String,
+    pub username: String,
+    pub email_verified: bool,
+}
+
+/// This struct represents the settings of a user, see `data::Profile` of the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Profile {
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub display_email: Option<String>,
+    pub is_admin: bool,
+    pub default_download_limit: Option<i32>,
+    pub default_expiry: Option<i64>,
+    pub default_visibility: Option<String>,
+    pub removal_notices: bool,
+}
+
+/// This struct represents a change of the settings of a user.
+/// Missing fields are left as they are, `Some(None)` resets a setting to the server default.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ProfileUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_email: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_download_limit: Option<Option<i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_expiry: Option<Option<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_visibility: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removal_notices: Option<bool>,
+}
+
+/// This struct represents the receipt of a deleted account.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeletionReceipt {
+    pub username: String,
+    pub deleted_at: i64,
+    pub files: Vec<String>,
+    pub bytes_deleted: i64,
+    pub audit_entries_anonymized: u64,
+}
+
+/// This struct represents a user as admins see them, with the number of files and bytes they store.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub is_admin: i32,
+    pub max_upload_bytes: Option<i64>,
+    pub storage_quota: Option<i64>,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
@@ -0,0 +1,148 @@
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use chrono::Local;
+use serde_json::json;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::error;
+
+use crate::data;
+use crate::logging;
+use std::net::SocketAddr;
+
+tokio::task_local! {
+    /// The user a request authenticated as, filled in by the authentication helpers.
+    static USER: RefCell<Option<String>>;
+}
+
+/// This struct is the access log file of the server.
+/// It is separate from the application log and gets exactly one line per request.
+#[derive(Clone)]
+pub struct AccessLog {
+    file: Option<Arc<Mutex<File>>>,
+    json: bool,
+}
+
+impl AccessLog {
+    /// Opens the access log configured with `access_log`,
+    /// without one the access log is disabled.
+    pub fn from_config(config: &data::Config) -> std::io::Result<Self> {
+        let file = match &config.access_log {
+            Some(path) => Some(Arc::new(Mutex::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| std::io::Error::new(e.kind(), format!("access log {}: {}", path, e)))?,
+            ))),
+            None => None,
+        };
+        Ok(AccessLog {
+            file,
+            json: config.access_log_format == "json",
+        })
+    }
+}
+
+/// Remembers the user the current request authenticated as for its access log line.
+/// Outside of a request, or with the access log disabled, it does nothing.
+pub(crate) fn set_user(username: &str) {
+    let _ = USER.try_with(|user| *user.borrow_mut() = Some(username.to_string()));
+}
+
+/// This middleware writes one line per request to the access log
+/// with the method, path, status, latency, response size, client IP and user of the request.
+/// The path is the route the request matched and the query string is left out,
+/// they can carry keys and share tokens, see `logging::logged_path`.
+/// Lines are written in the combined log format followed by the latency in seconds,
+/// or as one JSON object per line if `access_log_format` is `json`.
+pub async fn log(State(access_log): State<AccessLog>, request: Request, next: Next) -> Response {
+    let Some(file) = access_log.file else {
+        return next.run(request).await;
+    };
+
+    let start = Instant::now();
+    let time = Local::now();
+    let method = request.method().to_string();
+    let path = logging::logged_path(&request);
+    let version = format!("{:?}", request.version());
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let headers = request.headers();
+    let referer = headers
+        .get(header::REFERER)
+        .and_then(|hv| hv.to_str().ok())
+        .map(str::to_string);
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|hv| hv.to_str().ok())
+        .map(str::to_string);
+
+    let (response, user) = USER
+        .scope(RefCell::new(None), async {
+            let response = next.run(request).await;
+            (response, USER.with(|user| user.borrow_mut().take()))
+        })
+        .await;
+
+    let latency = start.elapsed().as_secs_f64();
+    let status = response.status().as_u16();
+    // streamed and compressed responses don't know their size up front
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+        .or_else(|| response.body().size_hint().exact());
+
+    let line = if access_log.json {
+        json!({
+            "time": time.to_rfc3339(),
+            "client_ip": client_ip,
+            "user": user,
+            "method": method,
+            "path": path,
+            "status": status,
+            "bytes": bytes,
+            "latency": latency,
+            "referer": referer,
+            "user_agent": user_agent,
+        })
+        .to_string()
+    } else {
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {:.3}",
+            client_ip,
+            user.as_deref().unwrap_or("-"),
+            time.format("%d/%b/%Y:%H:%M:%S %z"),
+            method,
+            path,
+            version,
+            status,
+            bytes.map(|bytes| bytes.to_string()).unwrap_or_else(|| "-".to_string()),
+            referer.as_deref().unwrap_or("-"),
+            user_agent.as_deref().unwrap_or("-"),
+            latency,
+        )
+    };
+    match file.lock() {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "{}", line) {
+                error!("Could not write access log: {}", e);
+            }
+        }
+        Err(e) => error!("Access log lock error: {}", e),
+    }
+    response
+}
@@ -0,0 +1,745 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+
+use chrono::Utc;
+use serde_json::json;
+use tracing::{error, info, warn};
+use sqlx::{Any, AnyPool, QueryBuilder};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::capacity;
+use crate::data;
+use crate::db;
+use crate::events;
+use crate::ids;
+use crate::parts;
+use crate::storage;
+use crate::webhook;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Helper to authenticate an admin request
+/// This function looks up the user that owns the supplied key
+/// and makes sure the user has the admin flag set.
+/// It returns the admin user,
+/// or a ready-made error response if the key is invalid or the user is not an admin.
+pub(crate) async fn require_admin(pool: &AnyPool, headers: &HeaderMap, ip: &str) -> Result<data::User, Response> {
+    let user = api::authenticate(pool, headers, ip).await?;
+    if !user.is_admin() {
+        warn!("Non admin user {} tried to use the admin API", user.username);
+        return Err((StatusCode::FORBIDDEN, "Admin privileges required").into_response());
+    }
+    Ok(user)
+}
+
+/// Handler to list all users
+/// This function returns every user together with the number of files
+/// and the amount of bytes they store.
+/// Keys and passwords are never returned.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/users
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every user", body = [data::UserInfo]),
+        (status = 403, description = "The key does not belong to an admin"),
+    ),
+    security(("key" = []))
+)]
+pub async fn list_users(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin list users request from IP: {}", ip);
+
+    if let Err(response) = require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    match sqlx::query_as::<_, data::UserInfo>(&format!(
+        r#"
+        SELECT users.username AS username,
+               users.is_admin AS is_admin,
+               users.max_upload_bytes AS max_upload_bytes,
+               users.storage_quota AS storage_quota,
+               COUNT(files.id) AS file_count,
+               {} AS total_bytes
+        FROM users
+        LEFT JOIN files ON files.owner = users.username
+        GROUP BY users.username, users.is_admin, users.max_upload_bytes, users.storage_quota
+        ORDER BY users.username
+        "#,
+        db::sum(&replica, "files.file_size"),
+    ))
+    .fetch_all(&replica)
+    .await
+    {
+        Ok(users) => (StatusCode::OK, Json(users)).into_response(),
+        Err(e) => {
+            error!("DB select users error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to delete a user
+/// This function deletes a user together with all of their files,
+/// both from the database and from the server's file system.
+/// example request: curl -X DELETE -H "key: <admin key>" http://localhost:3000/admin/users/<username>
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - name: the username of the user to delete (not optional)
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "The username of the user to delete")),
+    responses(
+        (status = 200, description = "The deleted files of the user", body = [data::File]),
+        (status = 403, description = "The key does not belong to an admin"),
+        (status = 404, description = "The user does not exist"),
+    ),
+    security(("key" = []))
+)]
+pub async fn delete_user(
+    Path(name): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin delete user request for {} from IP: {}", name, ip);
+
+    let admin = match require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match remove_user(&pool, &config, &name).await {
+        Ok(Some(files)) => {
+            info!(
+                "User {} and {} files deleted by admin {}",
+                name,
+                files.len(),
+                admin.username
+            );
+            audit::record(&pool, audit::Action::AdminDeleteUser, Some(&admin.username), Some(&name), &ip)
+                .await;
+            Json(files).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Helper to delete a user together with everything they own
+/// This function removes the files and collections of the user,
+/// ends their sessions and tokens and deletes the account.
+/// It returns the removed files, or `None` if there is no user with that name.
+pub(crate) async fn remove_user(
+    pool: &AnyPool,
+    config: &data::Config,
+    name: &str,
+) -> Result<Option<Vec<data::File>>, Response> {
+    // remove all files of the user first
+    let files = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE owner = ?
+        "#,
+        data::File::COLUMNS,
+    ))
+    .bind(name)
+    .fetch_all(pool)
+    .await;
+    let files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            error!("DB select error {}: {}", name, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    for file in &files {
+        api::remove_stored_file(pool, config, file).await?;
+        webhook::emit(webhook::EventKind::Deleted, file);
+    }
+
+    // the collections of the user are empty now, drop them too
+    if let Err(e) = sqlx::query(
+        r#"
+        DELETE FROM collections
+        WHERE owner = ?
+        "#,
+    )
+    .bind(name)
+    .execute(pool)
+    .await
+    {
+        warn!("DB delete collections error {}: {}", name, e);
+    }
+
+    // the keys are looked up in their table, so the cached user goes first
+    cache::forget_user(pool, name).await;
+    let keys = db::active_keys(pool, name).await.unwrap_or_else(|e| {
+        warn!("DB select keys error {}: {}", name, e);
+        Vec::new()
+    });
+
+    // end the sessions, keys, tokens, upload URLs, notifications, feeds and transfer of the user,
+    // a new user with the same name must not inherit them
+    for table in [
+        "sessions",
+        "api_keys",
+        "tokens",
+        "presigned_uploads",
+        "notifications",
+        "feeds",
+        "usage_periods",
+    ] {
+        if let Err(e) = sqlx::query(&format!("DELETE FROM {} WHERE username = ?", table))
+            .bind(name)
+            .execute(pool)
+            .await
+        {
+            error!("DB delete {} error {}: {}", table, name, e);
+            return Err(db::error_response(&e, "Database delete error"));
+        }
+    }
+
+    // a retried upload of a new user with the same name must not get these files back
+    if let Err(e) = sqlx::query("DELETE FROM idempotency_keys WHERE owner = ?")
+        .bind(name)
+        .execute(pool)
+        .await
+    {
+        warn!("DB delete idempotency keys error {}: {}", name, e);
+    }
+    if let Err(e) = parts::remove_user_uploads(pool, config, name).await {
+        warn!("DB delete multipart uploads error {}: {}", name, e);
+    }
+
+    match sqlx::query(
+        r#"
+        DELETE FROM users
+        WHERE username = ?
+        "#,
+    )
+    .bind(name)
+    .execute(pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => Ok(None),
+        Ok(_) => {
+            // other instances drop their cached user as well
+            for key in keys {
+                events::publish(events::Event::KeyRevoked { key });
+            }
+            Ok(Some(files))
+        }
+        Err(e) => {
+            error!("DB delete error {}: {}", name, e);
+            Err(db::error_response(&e, "Database delete error"))
+        }
+    }
+}
+
+/// Handler to set the upload size limit of a user
+/// This function sets a per user upload limit in bytes
+/// that overrides the global `max_upload_size`, it can be larger or smaller.
+/// A null value removes the per user limit again.
+/// example request: curl -X PUT -H "key: <admin key>" -H "content-type: application/json" -d '{"max_upload_bytes": 1073741824}' http://localhost:3000/admin/users/<username>/max_upload_bytes
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - name: the username of the user (not optional)
+///
+/// requires the following JSON body:
+/// - max_upload_bytes: the upload limit in bytes (optional, removes the limit if null)
+pub async fn set_max_upload_bytes(
+    Path(name): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::UploadLimitRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin upload limit request for {} from IP: {}", name, ip);
+
+    let admin = match require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    if request.max_upload_bytes.is_some_and(|limit| limit < 0) {
+        return (StatusCode::BAD_REQUEST, "max_upload_bytes must not be negative").into_response();
+    }
+
+    match sqlx::query(
+        r#"
+        UPDATE users
+        SET max_upload_bytes = ?
+        WHERE username = ?
+        "#,
+    )
+    .bind(request.max_upload_bytes)
+    .bind(&name)
+    .execute(&pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            (StatusCode::NOT_FOUND, "User not found").into_response()
+        }
+        Ok(_) => {
+            cache::forget_user(&pool, &name).await;
+            info!(
+                "Upload limit of {} set to {:?} by admin {}",
+                name, request.max_upload_bytes, admin.username
+            );
+            audit::record(
+                &pool,
+                audit::Action::AdminSetUploadLimit,
+                Some(&admin.username),
+                Some(&name),
+                &ip,
+            )
+            .await;
+            Json(json!({
+                "username": name,
+                "max_upload_bytes": request.max_upload_bytes,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("DB update error {}: {}", name, e);
+            db::error_response(&e, "Database update error")
+        }
+    }
+}
+
+/// Handler to delete any file
+/// This function deletes a file regardless of its owner
+/// and returns the deleted file metadata as a JSON response.
+/// example request: curl -X DELETE -H "key: <admin key>" http://localhost:3000/admin/files/<uuid>
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+pub async fn delete_file(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin delete file request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let admin = match require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    let file = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE id = ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(&uuid)
+    .fetch_optional(&pool)
+    .await;
+    let file = match file {
+        Ok(Some(file)) => file,
+        Ok(None) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    if let Err(response) = api::remove_stored_file(&pool, &config, &file).await {
+        return response;
+    }
+    info!("File {} of {} deleted by admin {}", uuid, file.owner, admin.username);
+    audit::record(&pool, audit::Action::AdminDeleteFile, Some(&admin.username), Some(&uuid), &ip).await;
+    webhook::emit(webhook::EventKind::Deleted, &file);
+
+    Json(file).into_response()
+}
+
+/// The number of users listed in the `top_users` of the instance statistics.
+const TOP_USERS: i64 = 10;
+
+/// Handler to return instance statistics
+/// This function returns the number of users and files,
+/// the amount of bytes stored and the number of downloads served
+/// for files that are still stored.
+/// It also returns how full the store is compared to `max_store_bytes`
+/// and raises `store_alert` before uploads are refused.
+/// The uploads and downloads of the last 24 hours and 7 days, the users storing the most
+/// and the health of the database and the data path are included as well.
+/// Everything is counted by the database, no rows are loaded.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/stats
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+pub async fn stats(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin stats request from IP: {}", ip);
+
+    if let Err(response) = require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    match instance_stats(&pool, &replica, &config).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => {
+            error!("DB stats error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// The number of duplicate groups listed in the `top_groups` of the deduplication report.
+const TOP_DUPLICATE_GROUPS: i64 = 20;
+
+/// The blobs referenced by files and their older versions, with the size of one copy.
+const BLOB_REFERENCES: &str = r#"
+    SELECT content_hash, file_size FROM files WHERE content_hash IS NOT NULL
+    UNION ALL
+    SELECT content_hash, file_size FROM file_versions WHERE content_hash IS NOT NULL
+"#;
+
+/// Handler to return the deduplication report
+/// This function counts how many files and versions share a blob with others
+/// by their content hash, how many bytes that saves compared to storing every copy
+/// and lists the duplicate groups that save the most.
+/// Everything is counted by the database on the replica, no rows are loaded.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/dedup/report
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+pub async fn dedup_report(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a dedup report request from IP: {}", ip);
+
+    if let Err(response) = require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    match dedup_stats(&replica).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            error!("DB dedup report error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Helper to gather the deduplication report shown at `/admin/dedup/report`.
+async fn dedup_stats(replica: &AnyPool) -> Result<data::DedupReport, sqlx::Error> {
+    let (unique_blobs, logical_files, shared_blobs, shared_files, stored_bytes, logical_bytes) =
+        sqlx::query_as::<_, (i64, i64, i64, i64, i64, i64)>(&format!(
+            r#"
+            SELECT COUNT(*),
+                   {},
+                   {},
+                   {},
+                   {},
+                   {}
+            FROM (
+                SELECT COUNT(*) AS files, MAX(file_size) AS file_size
+                FROM ({}) refs
+                GROUP BY content_hash
+            ) blobs
+            "#,
+            db::sum(replica, "files"),
+            db::sum(replica, "CASE WHEN files > 1 THEN 1 ELSE 0 END"),
+            db::sum(replica, "CASE WHEN files > 1 THEN files ELSE 0 END"),
+            db::sum(replica, "file_size"),
+            db::sum(replica, "files * file_size"),
+            BLOB_REFERENCES,
+        ))
+        .fetch_one(replica)
+        .await?;
+
+    let top_groups = sqlx::query_as::<_, data::DuplicateGroup>(&format!(
+        r#"
+        SELECT content_hash, COUNT(*) AS files, MAX(file_size) AS file_size,
+               (COUNT(*) - 1) * MAX(file_size) AS saved_bytes
+        FROM ({}) refs
+        GROUP BY content_hash
+        HAVING COUNT(*) > 1
+        ORDER BY saved_bytes DESC
+        LIMIT ?
+        "#,
+        BLOB_REFERENCES,
+    ))
+    .bind(TOP_DUPLICATE_GROUPS)
+    .fetch_all(replica)
+    .await?;
+
+    Ok(data::DedupReport {
+        logical_files,
+        unique_blobs,
+        shared_blobs,
+        shared_files,
+        logical_bytes,
+        stored_bytes,
+        saved_bytes: logical_bytes - stored_bytes,
+        top_groups,
+    })
+}
+
+/// Helper to gather the instance statistics shown at `/admin/stats`.
+/// The counts are read from the replica, the latency is the one of the primary database.
+async fn instance_stats(pool: &AnyPool, replica: &AnyPool, config: &data::Config) -> Result<data::Stats, sqlx::Error> {
+    // a trivial query tells how long the database takes to answer at all
+    let started = Instant::now();
+    sqlx::query("SELECT 1").execute(pool).await?;
+    let database_latency_ms = started.elapsed().as_millis() as u64;
+
+    let total_users = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users")
+        .fetch_one(replica)
+        .await?;
+    let (total_files, total_bytes, total_downloads) = sqlx::query_as::<_, (i64, i64, i64)>(&format!(
+        r#"
+        SELECT COUNT(*),
+               {},
+               {}
+        FROM files
+        "#,
+        db::sum(replica, "file_size"),
+        db::sum(replica, "download_count"),
+    ))
+    .fetch_one(replica)
+    .await?;
+
+    // uploads and downloads of removed files are still in the audit log
+    let now = Utc::now().timestamp();
+    let (day, week) = (now - 24 * 60 * 60, now - 7 * 24 * 60 * 60);
+    let (uploads_last_24h, uploads_last_7d, downloads_last_24h, downloads_last_7d) =
+        sqlx::query_as::<_, (i64, i64, i64, i64)>(&format!(
+            r#"
+            SELECT {},
+                   {},
+                   {},
+                   {}
+            FROM audit_log
+            WHERE time >= ? AND action IN ('file.upload', 'file.download')
+            "#,
+            db::sum(replica, "CASE WHEN action = 'file.upload' AND time >= ? THEN 1 ELSE 0 END"),
+            db::sum(replica, "CASE WHEN action = 'file.upload' THEN 1 ELSE 0 END"),
+            db::sum(replica, "CASE WHEN action = 'file.download' AND time >= ? THEN 1 ELSE 0 END"),
+            db::sum(replica, "CASE WHEN action = 'file.download' THEN 1 ELSE 0 END"),
+        ))
+        .bind(day)
+        .bind(day)
+        .bind(week)
+        .fetch_one(replica)
+        .await?;
+
+    let top_users = sqlx::query_as::<_, data::UserStorage>(&format!(
+        r#"
+        SELECT owner AS username, COUNT(*) AS files, {} AS bytes
+        FROM files
+        GROUP BY owner
+        ORDER BY bytes DESC
+        LIMIT ?
+        "#,
+        db::sum(replica, "file_size"),
+    ))
+    .bind(TOP_USERS)
+    .fetch_all(replica)
+    .await?;
+    let integrity_issues = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM integrity_issues")
+        .fetch_one(replica)
+        .await?;
+    let storage_writable = match storage::check_writable(config).await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Data path {} is not writable: {}", config.data_path, e);
+            false
+        }
+    };
+
+    let stored_bytes = capacity::stored_bytes(u64::try_from(total_bytes).unwrap_or_default());
+    let disk_usage = capacity::disk_usage();
+    Ok(data::Stats {
+        total_users,
+        total_files,
+        total_bytes,
+        total_downloads,
+        stored_bytes,
+        max_store_bytes: config.max_store_bytes,
+        disk_bytes: disk_usage.map(|(disk_bytes, _)| disk_bytes),
+        disk_checked_at: disk_usage.map(|(_, checked_at)| checked_at),
+        store_alert: capacity::alert(config, stored_bytes),
+        uploads_last_24h,
+        uploads_last_7d,
+        downloads_last_24h,
+        downloads_last_7d,
+        top_users,
+        health: data::Health {
+            database_latency_ms,
+            storage_writable,
+            integrity_issues,
+        },
+    })
+}
+
+/// The number of audit log entries returned when the request doesn't ask for a limit.
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+
+/// The maximum number of audit log entries returned at once.
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+/// Handler to read the audit log
+/// This function returns the newest audit log entries first,
+/// optionally filtered by user, action and time range.
+/// example request: curl -X GET -H "key: <admin key>" "http://localhost:3000/admin/audit?user=<username>&action=file.download&since=2024-01-01T00:00:00Z"
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// accepts the following query parameters:
+/// - user: only return actions of this user (optional)
+/// - action: only return this action, like `file.upload` or `user.login_failed` (optional)
+/// - since: only return actions at or after this unix timestamp or RFC 3339 date (optional)
+/// - until: only return actions before this unix timestamp or RFC 3339 date (optional)
+/// - limit: the maximum number of entries, 100 by default and at most 1000 (optional)
+pub async fn audit_log(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::AuditQuery>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin audit log request from IP: {}", ip);
+
+    if let Err(response) = require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    let mut range = [None, None];
+    for (bound, value) in range.iter_mut().zip([&query.since, &query.until]) {
+        if let Some(value) = value {
+            match api::parse_timestamp(value) {
+                Some(timestamp) => *bound = Some(timestamp),
+                None => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        "since and until must be unix timestamps or RFC 3339 dates",
+                    )
+                        .into_response();
+                }
+            }
+        }
+    }
+    let [since, until] = range;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    let mut select = QueryBuilder::<Any>::new("SELECT * FROM audit_log WHERE 1 = 1");
+    if let Some(user) = &query.user {
+        select.push(" AND username = ").push_bind(user.clone());
+    }
+    if let Some(action) = &query.action {
+        select.push(" AND action = ").push_bind(action.clone());
+    }
+    if let Some(since) = since {
+        select.push(" AND time >= ").push_bind(since);
+    }
+    if let Some(until) = until {
+        select.push(" AND time < ").push_bind(until);
+    }
+    select.push(" ORDER BY time DESC LIMIT ").push_bind(limit);
+
+    match select
+        .build_query_as::<data::AuditEntry>()
+        .fetch_all(&replica)
+        .await
+    {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            error!("DB select audit log error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to check that the audit log was not tampered with
+/// This function walks the hash chain of the audit log and returns whether it is intact,
+/// together with the hash of the newest entry to keep somewhere else, see `audit::verify`.
+/// It reads the primary database, a replica may not have the newest entries yet.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/audit/verify
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+pub async fn verify_audit_log(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin audit log verification request from IP: {}", ip);
+
+    if let Err(response) = require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    match audit::verify(&pool).await {
+        Ok(verification) => {
+            if let Some(problem) = &verification.problem {
+                warn!("The audit log does not verify: {}", problem);
+            }
+            Json(verification).into_response()
+        }
+        Err(e) => {
+            error!("DB select audit log error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Multipart, Query, Request},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use http_body_util::Limited;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use std::net::SocketAddr;
+
+/// The owner of anonymous uploads, no account can have an empty username.
+pub(crate) const ANONYMOUS_OWNER: &str = "";
+
+/// The window the daily quota of an IP address is counted over, in seconds.
+pub(crate) const QUOTA_WINDOW: i64 = 24 * 60 * 60;
+
+/// Helper to sum up the bytes an IP address uploaded anonymously in the last day.
+async fn used_quota(pool: &AnyPool, ip: &str) -> Result<u64, Response> {
+    let used = sqlx::query_scalar::<_, i64>(&format!(
+        r#"
+        SELECT {}
+        FROM anonymous_uploads
+        WHERE ip = ? AND upload_time > ?
+        "#,
+        db::sum(pool, "file_size"),
+    ))
+    .bind(ip)
+    .bind(Utc::now().timestamp() - QUOTA_WINDOW)
+    .fetch_one(pool)
+    .await;
+    match used {
+        Ok(used) => Ok(u64::try_from(used).unwrap_or_default()),
+        Err(e) => {
+            error!("DB select anonymous quota error {}: {}", ip, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to build the error response of an upload over the daily quota.
+fn quota_exceeded(config: &data::Config, used: u64) -> Response {
+    warn!("Anonymous upload over the daily quota refused");
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({
+            "error": "quota_exceeded",
+            "daily_quota_bytes": config.anonymous_daily_quota,
+            "used_bytes": used,
+        })),
+    )
+        .into_response()
+}
+
+/// Handler to upload a file without an account
+/// This function stores a file like `/upload` does, but needs no key.
+/// It only works if `anonymous_uploads` is enabled.
+/// Anonymous uploads are public, are limited to `anonymous_max_upload_size`
+/// and always expire after `anonymous_expiry` seconds.
+/// Every IP address can upload `anonymous_daily_quota` bytes a day.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "file_name: <file_name>" -H "content-type: <content_type>" --data-binary @<file_path> http://localhost:3000/upload/anonymous
+/// example multipart request: curl -X POST -F "file=@<file_path>" http://localhost:3000/upload/anonymous
+/// requires the following headers:
+/// - file_name: the name of the file (optional, taken from the part for multipart)
+/// - content-type: the content type of the file (optional, taken from the part for multipart)
+/// - download_limit: the download limit of the file (optional, can also be a multipart field)
+/// - expires_in: the number of seconds until the file expires, at most `anonymous_expiry` (optional)
+/// - encrypted: `true` if the body was encrypted by the client (optional, can also be a multipart field)
+/// - accept: `text/plain` to get only the download URL back instead of JSON (optional)
+///
+/// accepts the following query parameters:
+/// - format: `txt` to get only the download URL back instead of JSON (optional)
+#[instrument(skip_all)]
+pub async fn upload_anonymous(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received anonymous upload from IP: {}", ip);
+
+    if !config.anonymous_uploads {
+        return (StatusCode::FORBIDDEN, "Anonymous uploads are disabled").into_response();
+    }
+
+    // the body is cut off at whatever is smaller, the size cap or what is left of the quota
+    let used = match used_quota(&pool, &ip).await {
+        Ok(used) => used,
+        Err(response) => return response,
+    };
+    let remaining = config.anonymous_daily_quota.saturating_sub(used);
+    if remaining == 0 {
+        return quota_exceeded(&config, used);
+    }
+    let limit = config.anonymous_max_upload_size.min(remaining);
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    // answers which limit an upload of the given size broke
+    let over_limit = |length: u64| match length > config.anonymous_max_upload_size {
+        true => api::too_large(config.anonymous_max_upload_size).into_response(),
+        false => quota_exceeded(&config, used),
+    };
+    if let Some(length) = content_length.filter(|&length| length > limit) {
+        return over_limit(length);
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+
+    let now = Utc::now().timestamp();
+    let expires_in = headers
+        .get("expires_in")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .filter(|&seconds| seconds > 0)
+        .unwrap_or(config.anonymous_expiry)
+        .min(config.anonymous_expiry);
+    let expires = now.saturating_add(expires_in as i64);
+    let mut new_file = data::NewFile {
+        file_name: headers
+            .get("file_name")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+        content_type: headers
+            .get("content-type")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+        download_limit: headers
+            .get("download_limit")
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(|s| s.parse::<i32>().ok())
+            .unwrap_or(config.default_download_limit),
+        owner: ANONYMOUS_OWNER.to_string(),
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: headers
+            .get("encrypted")
+            .and_then(|hv| hv.to_str().ok())
+            .is_some_and(|s| s.trim().eq_ignore_ascii_case("true")),
+        tags: Vec::new(),
+        visibility: "public".to_string(),
+        allowed_content_types: Vec::new(),
+        expires: Some(expires),
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: None,
+        description: None,
+    };
+
+    let body = if new_file.content_type.starts_with("multipart/form-data") {
+        let multipart = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart,
+            Err(e) => {
+                warn!("Multipart parse error: {}", e);
+                return e.into_response();
+            }
+        };
+        match api::read_multipart(multipart, &mut new_file).await {
+            Ok(body) => body,
+            Err(response) if response.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return over_limit(limit + 1)
+            }
+            Err(response) => return response,
+        }
+    } else {
+        match Bytes::from_request(request, &()).await {
+            Ok(body) => body,
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return over_limit(limit + 1),
+            Err(e) => {
+                warn!("Body read error: {}", e);
+                return e.into_response();
+            }
+        }
+    };
+    // nobody could ever read a private file without an owner
+    new_file.visibility = "public".to_string();
+    new_file.tags.clear();
+
+    let file_size = body.len() as i64;
+    match api::store_file(&pool, &config, &ip, new_file, body).await {
+        Ok(uploaded_file) => {
+            let file = &uploaded_file.file;
+            if let Err(e) = sqlx::query(
+                r#"
+                INSERT INTO anonymous_uploads
+                    (ip, file_size, upload_time)
+                VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(&ip)
+            .bind(file_size)
+            .bind(now)
+            .execute(&pool)
+            .await
+            {
+                error!("DB insert anonymous upload error {}: {}", ip, e);
+            }
+            info!("Anonymous upload {} expires at {}", file.id, expires);
+            audit::record(&pool, audit::Action::Upload, None, Some(&file.id), &ip).await;
+            api::upload_response(&headers, &query, uploaded_file)
+        }
+        Err(response) => response,
+    }
+}
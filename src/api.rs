@@ -1,418 +1,3021 @@
 use axum::{
-    body::Bytes,
-    extract::{ConnectInfo, Path},
-    http::{HeaderMap, StatusCode},
+    body::{Body, Bytes},
+    extract::{multipart::MultipartError, ConnectInfo, FromRequest, Multipart, Path, Query, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Extension, Json,
 };
 
-use chrono::Utc;
-use log::{error, info, warn};
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use http_body_util::Limited;
+use lettre::message::Mailbox;
+use tracing::{error, info, instrument, warn};
 use rand::Rng;
 use sqlx::AnyPool;
-use std::path::Path as PathBuf;
-use tokio::fs;
 use uuid::Uuid;
 
+use crate::access_log;
+use crate::anonymous;
+use crate::audit;
+use crate::cache;
+use crate::capacity;
+use crate::captcha;
+use crate::changes;
+use crate::clamav;
+use crate::cli;
+use crate::compression;
 use crate::data;
+use crate::db;
+use crate::directory;
+use crate::email;
+use crate::error::ApiError;
+use crate::events;
+use crate::extract::{AuthUser, DeleteScope, ListScope, UploadScope};
+use crate::idempotency;
+use crate::human;
+use crate::ids;
+use crate::ip_quota;
+use crate::ipfs;
+use crate::keys;
+use crate::lockout;
+use crate::markdown;
+use crate::memory_cache;
+use crate::player;
+use crate::progress;
+use crate::registration;
+use crate::reports;
+use crate::s3;
+use crate::session;
+use crate::share;
+use crate::signature;
+use crate::stats;
+use crate::storage;
+use crate::thumbnail;
+use crate::tokens;
+use crate::transfer;
+use crate::throttle;
+use crate::unpack;
+use crate::versions;
+use crate::webhook;
+use std::collections::HashMap;
+use std::fmt::Write;
 use std::net::SocketAddr;
-use serde_json::json;
+use std::ops::Range;
+use serde_json::{json, Value};
+
+/// The maximum number of tags a file can have.
+const MAX_TAGS: usize = 20;
+
+/// The maximum length of a tag in bytes.
+const MAX_TAG_LENGTH: usize = 64;
+
+/// The maximum length of the description of a file in characters.
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// The most files `/all_files`, `/files` and `/public` return in one page.
+pub(crate) const MAX_PAGE_SIZE: i64 = 1000;
+
+/// The number of files on a page of `/files` if the client doesn't ask for another.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// Helper to authenticate a request by its `key` header or its session cookie
+/// This function looks up the user that owns the supplied key,
+/// or the user of the session if there is no key header.
+/// It returns the user if the key or session is valid,
+/// or a ready-made error response if both are missing or the key is unknown.
+/// Unknown keys are recorded in the audit log together with the IP address of the client.
+/// Handlers usually take an `extract::AuthUser` instead, which calls this once per request.
+pub(crate) async fn authenticate(pool: &AnyPool, headers: &HeaderMap, ip: &str) -> Result<data::User, Response> {
+    //get the key from the headers
+    let key = match headers.get("key") {
+        // scoped tokens only work for the few requests that accept them
+        Some(hv) if hv.to_str().is_ok_and(|key| key.starts_with(tokens::TOKEN_PREFIX)) => {
+            return Err(ApiError::Forbidden("Scoped tokens can't be used for this request".to_string()).into_response());
+        }
+        Some(hv) => hv.to_str().unwrap_or("unknown").to_string(),
+        None => {
+            // browsers log in once and send the session cookie instead
+            if let Some(id) = session::session_id(headers) {
+                return session::user_by_session(pool, id).await;
+            }
+            return Err(ApiError::MissingKey.into_response());
+        }
+    };
+    user_by_key(pool, &key, ip).await
+}
+
+/// Returns a Content-Disposition header that offers the file under the given name (RFC 6266)
+/// Path separators, quotes and control characters are stripped from the name first,
+/// and an empty name falls back to `fallback`.
+/// The name is sent twice, as a plain ASCII `filename` for old clients
+/// and percent encoded as UTF-8 in `filename*` (RFC 5987), which clients prefer if they know it.
+pub(crate) fn content_disposition(disposition: &str, name: &str, fallback: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '"' && !c.is_control())
+        .collect::<String>()
+        .trim()
+        .trim_start_matches('.')
+        .to_string();
+    let name = if name.is_empty() { fallback } else { name.as_str() };
+
+    let ascii: String = name.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => encoded.push(byte as char),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", disposition, ascii, encoded)
+}
+
+/// Helper to look up the user that owns a key
+/// It returns the user if the key is valid,
+/// or a ready-made error response if the key is unknown or the lookup failed.
+/// Clients that sent too many unknown keys are locked out, see the lockout module.
+pub(crate) async fn user_by_key(pool: &AnyPool, key: &str, ip: &str) -> Result<data::User, Response> {
+    if let Some(response) = lockout::check(ip, None) {
+        return Err(response);
+    }
+    //check if the user exists
+    match cache::user_by_key(pool, key).await {
+        Ok(Some(user)) => {
+            info!("User found in DB: {}", user.username);
+            access_log::set_user(&user.username);
+            // recording the use must not slow the request down
+            let (pool, key) = (pool.clone(), key.to_string());
+            tokio::spawn(async move {
+                if let Err(e) = db::touch_api_key(&pool, &key).await {
+                    warn!("DB update last_used_at error: {}", e);
+                }
+            });
+            Ok(user)
+        }
+        Ok(None) => {
+            warn!("Invalid key {}", keys::fingerprint(key));
+            lockout::failed(ip, None);
+            audit::record_failed_login(pool, None, None, ip);
+            Err(ApiError::InvalidKey.into_response())
+        }
+        Err(e) => {
+            error!("DB select user error {}: {}", keys::fingerprint(key), e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
 
 /// Handler to return all files as JSON
-/// This function retrieves all files from the database
+/// This function retrieves the files of the requesting user from the database
 /// and returns them as a JSON response.
+/// Admin users can pass `?all=true` to retrieve the files of every user.
+/// With `limit` the files are returned in pages, oldest first, and the `x-next-cursor` header
+/// of a page is passed as `after` to get the next one. The last page has no `x-next-cursor`.
+/// Without `limit` every file is returned at once, clients with many files should use `/files` instead.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/all_files
+/// example paged request: curl -X GET -i -H "key: <key>" "http://localhost:3000/all_files?limit=100&after=<x-next-cursor>"
+/// returns a JSON array of files
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+///
+/// accepts the following query parameters:
+/// - all: return the files of all users, admin only (optional)
+/// - trash: `true` to return the files in the trash instead (optional)
+/// - tag: only return files with this tag (optional)
+/// - name_contains: only return files whose name contains this text (optional)
+/// - content_type: only return files of this content type, `image/*` matches a whole group (optional)
+/// - uploaded_after: only return files uploaded after this unix timestamp or RFC 3339 date (optional)
+/// - limit: the maximum number of files, at most 1000, all files if missing (optional)
+/// - after: the `x-next-cursor` of the previous page (optional)
+/// - order: `asc` for the oldest files first, `desc` for the newest first, `asc` if missing (optional)
+/// - human: `true` to add a `human` object to every file with its size, times and downloads formatted
+///   like `14.2 MiB` and `3 days ago`, for scripts and terminals that show them as they are (optional)
+#[utoipa::path(
+    get,
+    path = "/all_files",
+    tag = "files",
+    params(data::AllFilesQuery),
+    responses(
+        (status = 200, description = "The files of the user", body = [data::File]),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn all_files(
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::AllFilesQuery>,
+    AuthUser { user, .. }: AuthUser<ListScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an all_files request from IP: {}", ip);
+
+    let (files, next_cursor) = list_page(&replica, &user, &query, None, "asc").await?;
+    let mut response = (StatusCode::OK, Json(files)).into_response();
+    if let Some(cursor) = next_cursor.and_then(|cursor| HeaderValue::from_str(&cursor).ok()) {
+        response.headers_mut().insert("x-next-cursor", cursor);
+    }
+    Ok(response)
+}
+
+/// Handler to list the files of a user page by page
+/// This function returns one page of the files of the requesting user, the newest first,
+/// so clients with thousands of files never fetch or render all of them at once.
+/// Pages continue after the upload time and ID of the last file of the previous page,
+/// so uploads and deletes between two pages never skip or repeat a file.
+/// The `next` of a page is passed as `after` to get the next one, the last page has no `next`.
+/// It takes the same filters as `/all_files`.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" "http://localhost:3000/files?limit=50"
+/// example next page: curl -X GET -H "key: <key>" "http://localhost:3000/files?limit=50&after=<next>"
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+///
+/// accepts the following query parameters:
+/// - limit: the maximum number of files, at most 1000, 100 if missing (optional)
+/// - after: the `next` of the previous page (optional)
+/// - order: `desc` for the newest files first, `asc` for the oldest first, `desc` if missing (optional)
+/// - all, trash, tag, name_contains, content_type, uploaded_after, human: see `/all_files` (optional)
+#[utoipa::path(
+    get,
+    path = "/files",
+    tag = "files",
+    params(data::AllFilesQuery),
+    responses(
+        (status = 200, description = "A page of the files of the user", body = data::FilePage),
+        (status = 400, description = "A filter or the cursor is invalid"),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn list_files(
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::AllFilesQuery>,
+    AuthUser { user, .. }: AuthUser<ListScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a files request from IP: {}", ip);
+
+    let (files, next) = list_page(&replica, &user, &query, Some(DEFAULT_PAGE_SIZE), "desc").await?;
+    Ok(Json(data::FilePage { files, next }).into_response())
+}
+
+/// Helper to read a page of files for `/all_files` and `/files`
+/// This function applies the filters of the query and returns the files with their tags,
+/// together with the cursor of the next page if there is one.
+/// `limit` and `order` are used if the query has none, no limit returns every file.
+async fn list_page(
+    replica: &AnyPool,
+    user: &data::User,
+    query: &data::AllFilesQuery,
+    limit: Option<i64>,
+    order: &str,
+) -> Result<(Vec<data::File>, Option<String>), ApiError> {
+    // only admins are allowed to list the files of every user
+    let all = query.all.unwrap_or(false);
+    if all && !user.is_admin() {
+        warn!("Non admin user {} requested all files", user.username);
+        return Err(ApiError::Forbidden("Only admins can list all files".to_string()));
+    }
+
+    // filter on the query parameters, user input is only ever bound, never formatted in
+    let uploaded_after = match query.uploaded_after.as_deref().map(parse_timestamp) {
+        Some(Some(timestamp)) => Some(timestamp),
+        Some(None) => {
+            return Err(ApiError::BadRequest(
+                "uploaded_after must be a unix timestamp or an RFC 3339 date".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let limit = query.limit.or(limit).map(|limit| limit.clamp(1, MAX_PAGE_SIZE));
+    let descending = match query.order.as_deref().unwrap_or(order) {
+        "asc" => false,
+        "desc" => true,
+        _ => return Err(ApiError::BadRequest("order must be asc or desc".to_string())),
+    };
+    let after = match query.after.as_deref().map(parse_cursor) {
+        Some(Some(after)) => Some(after),
+        Some(None) => {
+            return Err(ApiError::BadRequest(
+                "after must be the cursor of the previous page".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let filter = db::FileFilter {
+        owner: (!all).then_some(user.username.as_str()),
+        trash: query.trash.unwrap_or(false),
+        tag: query.tag.as_deref(),
+        name_contains: query.name_contains.as_deref(),
+        content_type: query.content_type.as_deref(),
+        uploaded_after,
+        after: after.as_ref().map(|(upload_time, id)| (*upload_time, id.as_str())),
+        descending,
+        // one more file than asked for tells whether there is a next page
+        limit: limit.map(|limit| limit + 1),
+    };
+    let files = db::list_files(replica, &filter).await;
+    let mut next_cursor = None;
+    let files = match files {
+        Ok(mut files) => {
+            if let Some(limit) = limit.filter(|limit| files.len() as i64 > *limit) {
+                files.truncate(limit as usize);
+                next_cursor = files.last().map(|file| format!("{}.{}", file.upload_time, file.id));
+            }
+            if query.human.unwrap_or(false) {
+                for file in &mut files {
+                    file.human = Some(human::file(file));
+                }
+            }
+            attach_tags(replica, &mut files).await.map(|()| files)
+        }
+        Err(e) => Err(e),
+    };
+    match files {
+        Ok(files) => {
+            info!("DB select all success");
+            Ok((files, next_cursor))
+        }
+        Err(e) => {
+            warn!("DB select all error: {}", e);
+            Err(db::error(&e, "Database select all error"))
+        }
+    }
+}
+
+/// Returns the upload time and ID of the last file of a page from an `x-next-cursor`.
+pub(crate) fn parse_cursor(cursor: &str) -> Option<(i64, String)> {
+    let (upload_time, id) = cursor.split_once('.')?;
+    let upload_time = upload_time.parse().ok()?;
+    ids::is_valid_file_id(id).then(|| (upload_time, id.to_string()))
+}
+
+/// Returns a unix timestamp from either a number or an RFC 3339 date.
+pub(crate) fn parse_timestamp(value: &str) -> Option<i64> {
+    value.trim().parse::<i64>().ok().or_else(|| {
+        DateTime::parse_from_rfc3339(value.trim())
+            .ok()
+            .map(|date| date.timestamp())
+    })
+}
+
+/// Helper to fill in the tags of listed files.
+pub(crate) async fn attach_tags(pool: &AnyPool, files: &mut [data::File]) -> Result<(), sqlx::Error> {
+    let ids: Vec<&str> = files.iter().map(|file| file.id.as_str()).collect();
+    let tags = db::file_tags(pool, &ids).await?;
+    let mut by_file: HashMap<String, Vec<String>> = HashMap::new();
+    for (file_id, tag) in tags {
+        by_file.entry(file_id).or_default().push(tag);
+    }
+    for file in files.iter_mut() {
+        file.tags = by_file.remove(&file.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// Returns the tags of a comma separated list, trimmed, lowercased and without duplicates.
+/// It returns `None` if there are too many tags or one of them is too long.
+pub(crate) fn parse_tags(list: &str) -> Option<Vec<String>> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in list.split(',').map(|tag| tag.trim().to_lowercase()) {
+        if tag.is_empty() || tags.contains(&tag) {
+            continue;
+        }
+        if tag.len() > MAX_TAG_LENGTH {
+            return None;
+        }
+        tags.push(tag);
+    }
+    (tags.len() <= MAX_TAGS).then_some(tags)
+}
+
+/// Returns the trimmed description of a file, `None` for a missing or empty one.
+/// It refuses descriptions longer than `MAX_DESCRIPTION_LENGTH` characters.
+pub(crate) fn parse_description(description: Option<&str>) -> Result<Option<String>, String> {
+    match description.map(str::trim) {
+        Some("") | None => Ok(None),
+        Some(description) if description.chars().count() > MAX_DESCRIPTION_LENGTH => Err(format!(
+            "The description can be at most {} characters long",
+            MAX_DESCRIPTION_LENGTH
+        )),
+        Some(description) => Ok(Some(description.to_string())),
+    }
+}
+
+/// Content types browsers render inline as markup, which can run scripts in the origin of bitBeam.
+const RISKY_CONTENT_TYPES: [&str; 3] = ["text/html", "application/xhtml+xml", "image/svg+xml"];
+
+/// Returns true if the declared and detected content types disagree
+/// and one of them is a type browsers render as markup.
+fn is_risky_mismatch(declared: &str, detected: &str) -> bool {
+    let declared = declared
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    // SVG files starting with an XML declaration are detected as XML
+    if declared == detected || (declared == "image/svg+xml" && detected == "text/xml") {
+        return false;
+    }
+    RISKY_CONTENT_TYPES.contains(&declared.as_str()) || RISKY_CONTENT_TYPES.contains(&detected)
+}
+
+/// Returns the error for a tag list that `parse_tags` refused.
+pub(crate) fn invalid_tags() -> ApiError {
+    ApiError::BadRequest(format!(
+        "Invalid tags, use at most {} tags of up to {} bytes",
+        MAX_TAGS, MAX_TAG_LENGTH
+    ))
+}
+
+/// Returns the error for a `cache` option that `parse_cache_control` refused.
+pub(crate) fn invalid_cache_control() -> ApiError {
+    ApiError::BadRequest(format!(
+        "Invalid cache, use a comma separated list of {}",
+        CACHE_DIRECTIVES
+            .iter()
+            .map(|(name, takes_seconds)| match takes_seconds {
+                true => format!("{}=<seconds>", name),
+                false => name.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+/// Handler to upload a file
+/// This function handles the file upload process.
+/// It receives the file data either as the raw request body
+/// or as a multipart/form-data form,
+/// saves it to the server's file system,
+/// and stores the file metadata in the database.
+/// A file ends once it reached its download limit or its expiry date, whichever comes first,
+/// uploads can set either, both or neither. Without an expiry option
+/// files expire after the `default_expiry` the user set at `/user/me`, if any.
+/// The JSON response carries `links` to download, view and delete the file and to a QR code of it,
+/// and the `delete_token` that deletes it once without a key with `DELETE /download/<uuid>?token=<delete_token>`.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "file_name: <file_name>" -H "content-type: <content_type>" -H "download_limit: <download_limit>" --data-binary @<file_path> http://localhost:3000/upload
+/// example multipart request: curl -X POST -H "key: <key>" -F "file=@<file_path>" -F "download_limit=<download_limit>" http://localhost:3000/upload
+/// example query request: curl -X POST -H "key: <key>" --data-binary @<file_path> "http://localhost:3000/upload?file_name=<file_name>&download_limit=<download_limit>"
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - file_name: the name of the file (optional, taken from the part for multipart)
+/// - content-type: the content type of the file (optional, taken from the part for multipart)
+/// - download_limit: the download limit of the file, 0 or `unlimited` for unlimited, the default of the user from `/user/me` or `default_download_limit` if missing (optional, can also be a multipart field)
+/// - slug: a unique vanity name to download the file from `/d/<slug>` (optional)
+/// - encrypted: `true` if the body was encrypted by the client (optional, can also be a multipart field)
+/// - extract: `true` to unpack a zip, tar or tar.gz archive into a collection of its files, answers with the collection
+///   and the files instead of a single file, not together with `encrypted` or `slug` (optional)
+/// - tags: a comma separated list of tags to find the file by (optional, can also be a multipart field)
+/// - visibility: `public` or `private`, private files need the owner's key or a share token to download (optional, defaults to the `default_visibility` of the user from `/user/me` or public, can also be a multipart field)
+/// - upload_id: an ID of the client's choice to follow the upload at `/upload/<upload_id>/progress` (optional)
+/// - expire_if_unused_days: remove the file once it was not downloaded for this many days (optional)
+/// - expires_at: a unix timestamp or RFC 3339 date to remove the file at, `never` to keep it (optional)
+/// - max_age: the seconds or a duration like `7d` after the upload to remove the file after, `never` to keep it,
+///   the earlier of `expires_at` and `max_age` wins (optional)
+/// - idempotency-key: a key of the client's choice, a retry with the same key within 24 hours gets the first file back
+///   with `idempotent-replayed: true` instead of storing it again (optional)
+/// - cache: the `Cache-Control` header downloads of the file are sent with, like `public, max-age=3600`,
+///   shared caches can only be allowed for public files with unlimited downloads (optional)
+/// - publish: `true` to list the file in the public directory at `/public`, only for public files (optional, can also be a multipart field)
+/// - description: a note of up to 1000 characters shown with the file, change it later with `PATCH /file/<uuid>`
+///   (optional, can also be a multipart field or a query parameter for text that isn't a valid header)
+/// - accept: `text/plain` to get only the download URL back instead of JSON (optional)
+///
+/// accepts the following query parameters:
+/// - format: `txt` to get only the download URL back instead of JSON (optional)
+/// - file_name, download_limit, slug, encrypted, extract, tags, visibility, upload_id, expire_if_unused_days, expires_at, max_age, cache, publish, description:
+///   like the headers, a header wins (optional)
+#[utoipa::path(
+    post,
+    path = "/upload",
+    tag = "files",
+    request_body(content = Vec<u8>, description = "The file as the raw body or as the `file` part of a multipart form"),
+    params(
+        data::UploadQuery,
+        data::UploadOptions,
+        ("file_name" = Option<String>, Header, description = "The name of the file"),
+        ("download_limit" = Option<String>, Header, description = "The download limit of the file, 0 or `unlimited` for unlimited"),
+        ("slug" = Option<String>, Header, description = "A unique vanity name to download the file from `/d/<slug>`"),
+        ("encrypted" = Option<bool>, Header, description = "`true` if the body was encrypted by the client"),
+        ("extract" = Option<bool>, Header, description = "`true` to unpack a zip or tar archive into a collection of its files"),
+        ("tags" = Option<String>, Header, description = "A comma separated list of tags"),
+        ("visibility" = Option<String>, Header, description = "`public` or `private`"),
+        ("upload_id" = Option<String>, Header, description = "An ID to follow the upload at `/upload/{upload_id}/progress`"),
+        ("expire_if_unused_days" = Option<i32>, Header, description = "The number of days without a download after which the file is removed"),
+        ("expires_at" = Option<String>, Header, description = "A unix timestamp or RFC 3339 date after which the file is removed, `never` to keep it"),
+        ("max_age" = Option<String>, Header, description = "The seconds or a duration like `7d` after which the file is removed, `never` to keep it"),
+        ("cache" = Option<String>, Header, description = "The `Cache-Control` header of downloads of the file, like `public, max-age=3600`"),
+        ("publish" = Option<bool>, Header, description = "`true` to list the file in the public directory at `/public`"),
+        ("description" = Option<String>, Header, description = "A note of up to 1000 characters shown with the file"),
+        ("idempotency-key" = Option<String>, Header, description = "A retry with the same key within 24 hours gets the first file back"),
+    ),
+    responses(
+        (status = 200, description = "The stored file, or the collection and files of an archive with `extract`", body = data::UploadedFile),
+        (status = 400, description = "The upload is invalid"),
+        (status = 401, description = "The key is invalid"),
+        (status = 413, description = "The file is larger than the upload limit or the storage quota"),
+        (status = 507, description = "The server reached its storage cap"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn upload(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    options: data::UploadOptions,
+    AuthUser { user, token, .. }: AuthUser<UploadScope>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received update from IP: {}", ip);
+
+    let headers = request.headers().clone();
+
+    if !user.is_verified() {
+        return Err(unverified(&user));
+    }
+
+    // a retry with the same `Idempotency-Key` gets the file of the first upload back
+    let reservation = match headers.get("idempotency-key").and_then(|hv| hv.to_str().ok()) {
+        Some(key) => match idempotency::claim(&pool, &user.username, key.trim()).await? {
+            idempotency::Claim::New(reservation) => Some(reservation),
+            idempotency::Claim::Replay(file) => {
+                let share_url_template =
+                    (file.encrypted != 0).then(|| public_url(&config, &format!("e/{}#key={{key}}", file.id)));
+                // the delete token was only handed out with the first response
+                let uploaded_file = data::UploadedFile {
+                    links: upload_links(&config, &file, None),
+                    delete_token: None,
+                    file: *file,
+                    content_md5: None,
+                    share_url_template,
+                };
+                let mut response = upload_response(&headers, &query, uploaded_file);
+                response
+                    .headers_mut()
+                    .insert("idempotent-replayed", HeaderValue::from_static("true"));
+                return Ok(response);
+            }
+        },
+        None => None,
+    };
+
+    // refuse uploads that announce a size over the limit before reading them,
+    // the body is cut off at the limit in case the announced size is wrong
+    let limit = upload_limit(&config, &user, token.as_ref());
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return Err(too_large(limit));
+    }
+    // the progress of uploads with an ID can be followed at `/upload/<upload_id>/progress`
+    let request = match options.upload_id.as_deref() {
+        Some(upload_id) if !progress::is_valid_id(upload_id) => {
+            return Err(ApiError::BadRequest(
+                "Invalid upload_id, use 1 to 64 letters, digits, '-' or '_'".to_string(),
+            ));
+        }
+        Some(upload_id) => match progress::track(upload_id, &user.username, content_length) {
+            Some(tracker) => request.map(|body| tracker.wrap(body)),
+            None => return Err(ApiError::Conflict("An upload with this upload_id is running".to_string())),
+        },
+        None => request,
+    };
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+
+    // gets the content type from the headers
+    let content_type = headers
+        .get("content-type")
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+    // gets the download limit from the options, 0 or `unlimited` means unlimited
+    let download_limit = options
+        .download_limit
+        .and_then(|s| parse_download_limit(&s))
+        .unwrap_or_else(|| default_download_limit(&config, &user));
+    //get filename from the options
+    let file_name = options.file_name.unwrap_or_else(|| "unknown".to_string());
+
+    // gets the optional checksums to verify the upload against
+    let expected_sha256 = headers
+        .get("content-sha256")
+        .and_then(|hv| hv.to_str().ok())
+        .map(|s| s.trim().to_string());
+    let expected_md5 = headers
+        .get("content-md5")
+        .and_then(|hv| hv.to_str().ok())
+        .map(|s| s.trim().to_string());
+
+    // gets the optional vanity slug
+    let slug = options.slug.map(|s| s.trim().to_string());
+    if let Some(slug) = &slug {
+        if !is_valid_slug(slug) {
+            return Err(ApiError::BadRequest(
+                "Invalid slug, use 1 to 64 letters, digits, '-', '_' or '.'".to_string(),
+            ));
+        }
+    }
+
+    // end to end encrypted uploads are stored as they are, the server never sees the key
+    let encrypted = options
+        .encrypted
+        .is_some_and(|s| s.trim().eq_ignore_ascii_case("true"));
+
+    // archives are unpacked into a collection, the server can't look into encrypted ones
+    // and a single slug can't name all of their files
+    let extract = options
+        .extract
+        .is_some_and(|s| s.trim().eq_ignore_ascii_case("true"));
+    if extract && (encrypted || slug.is_some()) {
+        return Err(ApiError::BadRequest(
+            "extract can't be combined with encrypted or slug".to_string(),
+        ));
+    }
+
+    // gets the optional tags
+    let tags = match options.tags.as_deref().map(parse_tags) {
+        Some(Some(tags)) => tags,
+        Some(None) => return Err(invalid_tags()),
+        None => Vec::new(),
+    };
+
+    let visibility = match options.visibility.as_deref() {
+        Some(visibility) => match share::parse_visibility(visibility) {
+            Some(visibility) => visibility,
+            None => return Err(share::invalid_visibility().into()),
+        },
+        None => default_visibility(&user),
+    };
+
+    // files that nobody downloads for this many days are removed by the cleanup task
+    let expire_if_unused_days = match options.expire_if_unused_days.as_deref().map(|s| s.trim().parse::<i32>()) {
+        Some(Ok(days)) if days > 0 => Some(days),
+        Some(_) => {
+            return Err(ApiError::BadRequest(
+                "expire_if_unused_days must be a positive number of days".to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    // the file can end by its download limit, by a date, by both or by neither
+    let expires = upload_expires(&user, options.expires_at.as_deref(), options.max_age.as_deref())
+        .map_err(ApiError::BadRequest)?;
+    let cache_control = match options.cache.as_deref().map(parse_cache_control) {
+        Some(Some(cache_control)) => Some(cache_control),
+        Some(None) => return Err(invalid_cache_control()),
+        None => None,
+    };
+    let description = parse_description(options.description.as_deref()).map_err(ApiError::BadRequest)?;
+    let published = options
+        .publish
+        .is_some_and(|s| s.trim().eq_ignore_ascii_case("true"));
+    let mut new_file = data::NewFile {
+        file_name,
+        content_type,
+        download_limit,
+        owner: user.username,
+        expected_sha256,
+        expected_md5,
+        slug,
+        encrypted,
+        tags,
+        visibility,
+        allowed_content_types: tokens::allowed_content_types(token.as_ref()),
+        expires,
+        expire_if_unused_days,
+        cache_control,
+        published,
+        collection_id: tokens::collection(token.as_ref()),
+        description,
+    };
+
+    // multipart uploads carry the file name and content type in the part itself
+    let body = if new_file.content_type.starts_with("multipart/form-data") {
+        let multipart = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart,
+            Err(e) => {
+                warn!("Multipart parse error: {}", e);
+                return Err(ApiError::BadRequest(e.body_text()));
+            }
+        };
+        match read_multipart(multipart, &mut new_file).await {
+            Ok(body) => body,
+            Err(response) if response.status() == StatusCode::PAYLOAD_TOO_LARGE => return Err(too_large(limit)),
+            Err(response) => return Err(response.into()),
+        }
+    } else {
+        match Bytes::from_request(request, &()).await {
+            Ok(body) => body,
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return Err(too_large(limit)),
+            Err(e) => {
+                warn!("Body read error: {}", e);
+                return Err(ApiError::BadRequest(e.body_text()));
+            }
+        }
+    };
+
+    if extract && new_file.encrypted {
+        // the multipart form can mark the body as encrypted as well
+        return Err(ApiError::BadRequest(
+            "extract can't be combined with encrypted or slug".to_string(),
+        ));
+    }
+    if extract {
+        // an archive is not a single file an idempotent retry could get back
+        drop(reservation);
+        return unpack::store_archive(&pool, &config, &ip, new_file, body).await;
+    }
+    let uploaded_file = store_file(&pool, &config, &ip, new_file, body).await?;
+    let file = &uploaded_file.file;
+    if let Some(reservation) = reservation {
+        reservation.finish(&file.id).await;
+    }
+    audit::record(&pool, audit::Action::Upload, Some(&file.owner), Some(&file.id), &ip).await;
+    Ok(upload_response(&headers, &query, uploaded_file))
+}
+
+/// Handler to upload a file to a named path
+/// This function stores the body like `/upload` does, with the last path segment as the file name,
+/// so `curl -T <file_path>` works the way scripts written for transfer.sh expect.
+/// Every other option is read from the query parameters or headers of `/upload`.
+/// It answers with only the download URL unless JSON is asked for with `format=json` or `Accept: application/json`.
+/// Names that are routes of their own, like `batch` or `init`, can't be uploaded this way.
+/// example request: curl -H "key: <key>" -T <file_path> http://localhost:3000/upload/
+/// example request with options: curl -H "key: <key>" -T report.pdf "http://localhost:3000/upload/report.pdf?download_limit=5&max_age=7d"
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+///
+/// requires the following path parameter:
+/// - file_name: the name the file is stored under (not optional)
+///
+/// accepts the following query parameters:
+/// - format: `json` to get the file metadata back instead of the download URL (optional)
+/// - the options of `/upload`, except file_name (optional)
+#[instrument(skip_all)]
+pub async fn upload_named(
+    Path(file_name): Path<String>,
+    pool: Extension<AnyPool>,
+    addr: ConnectInfo<SocketAddr>,
+    config: Extension<data::Config>,
+    mut options: data::UploadOptions,
+    auth: AuthUser<UploadScope>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    options.file_name = Some(file_name);
+    let Query(mut query) = Query::<data::UploadQuery>::try_from_uri(request.uri())
+        .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+    // scripts written for transfer.sh read the download URL from the body
+    let accept_json = request
+        .headers()
+        .get("accept")
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|accept| accept.trim_start().starts_with("application/json"));
+    if query.format.is_none() && !accept_json {
+        query.format = Some("txt".to_string());
+    }
+    upload(pool, addr, config, Query(query), options, auth, request).await
+}
+
+/// Returns the maximum upload size of a user in bytes.
+/// A per user `max_upload_bytes` overrides the global `max_upload_size`,
+/// the `max_bytes` of a scoped token can only lower it.
+pub(crate) fn upload_limit(config: &data::Config, user: &data::User, token: Option<&data::ApiToken>) -> u64 {
+    let limit = user.max_upload_bytes
+        .and_then(|limit| u64::try_from(limit).ok())
+        .unwrap_or(config.max_upload_size);
+    match token.and_then(|token| token.max_bytes).and_then(|max| u64::try_from(max).ok()) {
+        Some(max) => limit.min(max),
+        None => limit,
+    }
+}
+
+/// Helper to sum up the files a user stores
+/// This function returns the number of files of the user and their size in bytes.
+/// Files in the trash still count, they are stored until they are purged.
+pub(crate) async fn storage_usage(pool: &AnyPool, owner: &str) -> Result<(i64, i64), ApiError> {
+    db::storage_usage(pool, owner).await.map_err(|e| {
+        error!("DB select usage error {}: {}", owner, e);
+        db::error(&e, "Database select error")
+    })
+}
+
+/// Returns the storage quota of a user in bytes, `None` if they can store as much as they like.
+/// A per user `storage_quota` overrides the global one, 0 means unlimited for both.
+pub(crate) fn storage_quota(config: &data::Config, user_quota: Option<i64>) -> Option<u64> {
+    let quota = user_quota
+        .and_then(|quota| u64::try_from(quota).ok())
+        .unwrap_or(config.storage_quota);
+    (quota > 0).then_some(quota)
+}
+
+/// Returns the download limit of uploads that don't set one,
+/// the `default_download_limit` of the user from `/user/me` or else the server's.
+pub(crate) fn default_download_limit(config: &data::Config, user: &data::User) -> i32 {
+    user.default_download_limit.unwrap_or(config.default_download_limit)
+}
+
+/// Returns the visibility of uploads that don't set one,
+/// the `default_visibility` of the user from `/user/me` or else public.
+pub(crate) fn default_visibility(user: &data::User) -> String {
+    user.default_visibility.clone().unwrap_or_else(|| "public".to_string())
+}
+
+/// Returns the expiry time of an upload made now, from the `default_expiry` of the user,
+/// `None` if their files don't expire.
+pub(crate) fn default_expires(user: &data::User) -> Option<i64> {
+    user.default_expiry
+        .filter(|expiry| *expiry > 0)
+        .map(|expiry| Utc::now().timestamp().saturating_add(expiry))
+}
+
+/// Returns the download limit of an upload option, `unlimited` is the same as 0.
+pub(crate) fn parse_download_limit(value: &str) -> Option<i32> {
+    let value = value.trim();
+    match value.eq_ignore_ascii_case("unlimited") {
+        true => Some(0),
+        false => value.parse().ok(),
+    }
+}
+
+/// The `Cache-Control` directives an uploader can set, and whether they take a number of seconds.
+const CACHE_DIRECTIVES: [(&str, bool); 12] = [
+    ("public", false),
+    ("private", false),
+    ("no-cache", false),
+    ("no-store", false),
+    ("no-transform", false),
+    ("must-revalidate", false),
+    ("proxy-revalidate", false),
+    ("immutable", false),
+    ("max-age", true),
+    ("s-maxage", true),
+    ("stale-while-revalidate", true),
+    ("stale-if-error", true),
+];
+
+/// Returns the `Cache-Control` header of the `cache` upload option, lowercased and with single spaces,
+/// `None` if it has a directive that is not in `CACHE_DIRECTIVES` or a malformed number of seconds.
+pub(crate) fn parse_cache_control(value: &str) -> Option<String> {
+    let mut directives = Vec::new();
+    for directive in value.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        let directive = directive.to_ascii_lowercase();
+        let (name, seconds) = match directive.split_once('=') {
+            Some((name, seconds)) => (name.trim(), Some(seconds.trim().parse::<u64>().ok()?)),
+            None => (directive.as_str(), None),
+        };
+        let (name, takes_seconds) = CACHE_DIRECTIVES.into_iter().find(|(known, _)| *known == name)?;
+        match seconds {
+            Some(seconds) if takes_seconds => directives.push(format!("{}={}", name, seconds)),
+            None if !takes_seconds => directives.push(name.to_string()),
+            _ => return None,
+        }
+    }
+    (!directives.is_empty()).then(|| directives.join(", "))
+}
+
+/// Returns true if a `Cache-Control` header lets shared caches like CDNs keep the file.
+fn shared_cacheable(cache_control: &str) -> bool {
+    cache_control
+        .split(", ")
+        .any(|directive| directive == "public" || directive.starts_with("s-maxage="))
+}
+
+/// Returns the expiry time of an upload made now from its `expires_at` and `max_age` options.
+/// If both are set the earlier one wins, `never` leaves that option out,
+/// so a file with both set to `never` only ends by its download limit.
+/// Without either option the `default_expiry` of the user applies.
+/// The error is the message of a 400, an expiry in the past or a malformed value.
+pub(crate) fn upload_expires(
+    user: &data::User,
+    expires_at: Option<&str>,
+    max_age: Option<&str>,
+) -> Result<Option<i64>, String> {
+    if expires_at.is_none() && max_age.is_none() {
+        return Ok(default_expires(user));
+    }
+    let now = Utc::now().timestamp();
+    let never = |value: &str| value.trim().eq_ignore_ascii_case("never");
+    let expires_at = match expires_at.filter(|value| !never(value)) {
+        Some(value) => match parse_timestamp(value) {
+            Some(expires_at) if expires_at > now => Some(expires_at),
+            Some(_) => return Err("expires_at must be in the future".to_string()),
+            None => return Err("expires_at must be a unix timestamp, an RFC 3339 date or never".to_string()),
+        },
+        None => None,
+    };
+    let expires_after = match max_age.filter(|value| !never(value)) {
+        Some(value) => match cli::parse_duration(value) {
+            Ok(seconds) if seconds > 0 => Some(now.saturating_add(seconds)),
+            _ => return Err("max_age must be a positive number of seconds, a duration like 7d or never".to_string()),
+        },
+        None => None,
+    };
+    Ok(match (expires_at, expires_after) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (expires, None) | (None, expires) => expires,
+    })
+}
+
+/// Helper to check that storing `additional` more bytes keeps a user within their storage quota.
+/// Anonymous uploads have their own daily quota and are not checked.
+pub(crate) async fn check_storage_quota(
+    pool: &AnyPool,
+    config: &data::Config,
+    owner: &str,
+    additional: i64,
+) -> Result<(), Response> {
+    if owner == anonymous::ANONYMOUS_OWNER || additional <= 0 {
+        return Ok(());
+    }
+    let user_quota = match db::user_storage_quota(pool, owner).await {
+        Ok(user_quota) => user_quota,
+        Err(e) => {
+            error!("DB select quota error {}: {}", owner, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    let Some(quota) = storage_quota(config, user_quota) else {
+        return Ok(());
+    };
+    let (_, used) = storage_usage(pool, owner).await.map_err(IntoResponse::into_response)?;
+    let used = u64::try_from(used).unwrap_or_default();
+    if used.saturating_add(additional as u64) <= quota {
+        return Ok(());
+    }
+    warn!("Upload of {} over the storage quota refused", owner);
+    Err(ApiError::QuotaExceeded { quota, used }.into_response())
+}
+
+/// Handler to show the storage usage of a user
+/// This function returns how many files the user stores, how many bytes they take up,
+/// their storage quota and how much of it is left.
+/// `quota_bytes` and `remaining_bytes` are null if there is no quota.
+/// `transfer` holds the bytes the user uploaded and the bytes their files were downloaded with this month,
+/// the monthly caps of the instance, null if there are none, and the transfer of the last months.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/user/usage
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+#[utoipa::path(
+    get,
+    path = "/user/usage",
+    tag = "users",
+    responses(
+        (status = 200, description = "The storage usage of the user", body = data::Usage),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn user_usage(
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    AuthUser { user, .. }: AuthUser<ListScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a usage request from IP: {}", ip);
+
+    let (file_count, total_bytes) = storage_usage(&replica, &user.username).await?;
+    let quota_bytes = storage_quota(&config, user.storage_quota);
+    let transfer = match transfer::usage(&replica, &config, &user.username).await {
+        Ok(transfer) => transfer,
+        Err(e) => {
+            error!("DB select usage error {}: {}", user.username, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    Ok(Json(data::Usage {
+        file_count,
+        total_bytes,
+        quota_bytes,
+        remaining_bytes: quota_bytes
+            .map(|quota| quota.saturating_sub(u64::try_from(total_bytes).unwrap_or_default())),
+        transfer,
+    })
+    .into_response())
+}
+
+/// Returns true if the server accepts the download limit.
+/// 0 means unlimited and is only accepted if `max_download_limit` is not set.
+pub(crate) fn download_limit_allowed(config: &data::Config, download_limit: i32) -> bool {
+    match config.max_download_limit {
+        0 => download_limit >= 0,
+        max => (1..=max).contains(&download_limit),
+    }
+}
+
+/// Helper to build the error of an upload with a download limit the server does not accept.
+pub(crate) fn invalid_download_limit(config: &data::Config, download_limit: i32) -> ApiError {
+    warn!("Upload with download limit {} refused", download_limit);
+    ApiError::InvalidDownloadLimit {
+        download_limit,
+        max_download_limit: config.max_download_limit,
+    }
+}
+
+/// Helper to build the 410 of a file that can't be downloaded anymore
+/// The body tells the client why the file ended, `download_limit_reached`, `expired`, `deleted`
+/// or `unavailable` if its blob is missing,
+/// and the limits the file had, so clients can tell a used up link from an expired one.
+pub(crate) fn gone(file: &data::File) -> ApiError {
+    if file.downloads_remaining() == Some(0) {
+        download_limit_reached(file)
+    } else if file.is_expired() {
+        gone_because(file, "expired", "File expired", file.download_count)
+    } else if file.is_blob_missing() && !file.is_trashed() {
+        gone_because(file, "unavailable", "File unavailable", file.download_count)
+    } else {
+        gone_because(file, "deleted", "File deleted", file.download_count)
+    }
+}
+
+/// Helper to build the 410 of a file that used up its download limit,
+/// possibly by downloads that started after the file was looked up.
+pub(crate) fn download_limit_reached(file: &data::File) -> ApiError {
+    let download_count = file.download_count.max(file.download_limit);
+    gone_because(file, "download_limit_reached", "Download limit reached", download_count)
+}
+
+/// Returns the 410 of a file that ended for the given reason.
+fn gone_because(file: &data::File, reason: &str, message: &str, download_count: i32) -> ApiError {
+    ApiError::Rejected {
+        status: StatusCode::GONE,
+        error: "gone",
+        message: message.to_string(),
+        details: json!({
+            "reason": reason,
+            "limited_by": file.limited_by(),
+            "download_limit": file.download_limit,
+            "download_count": download_count,
+            "expires": file.expires,
+        }),
+    }
+}
+
+/// Helper to build the error of an upload over the size limit.
+pub(crate) fn too_large(limit: u64) -> ApiError {
+    warn!("Upload over the size limit of {} bytes refused", limit);
+    ApiError::TooLarge(limit)
+}
+
+/// Returns the error for an upload from an account that has not confirmed its email address.
+pub(crate) fn unverified(user: &data::User) -> ApiError {
+    warn!("Unverified user {} tried to upload", user.username);
+    ApiError::Unverified
+}
+
+/// Helper to build the response of an upload
+/// Clients that ask for plain text with `Accept: text/plain` or `?format=txt`
+/// only get the download URL followed by a newline, so scripts can use it without a JSON parser.
+/// Everybody else gets the file metadata as JSON.
+/// Like transfer.sh, either way the delete link is also sent in the `x-url-delete` header.
+pub(crate) fn upload_response(
+    headers: &HeaderMap,
+    query: &data::UploadQuery,
+    uploaded_file: data::UploadedFile,
+) -> Response {
+    let format_txt = query.format.as_deref() == Some("txt");
+    // only honour text/plain if it is the client's first choice, curl sends */* by default
+    let accept_txt = headers
+        .get("accept")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|accept| accept.split(',').next())
+        .is_some_and(|first| first.trim().starts_with("text/plain"));
+    let delete_url = uploaded_file
+        .links
+        .delete
+        .as_deref()
+        .and_then(|url| HeaderValue::from_str(url).ok());
+    let mut response = if format_txt || accept_txt {
+        (
+            [("content-type", "text/plain; charset=utf-8")],
+            format!("{}\n", uploaded_file.file.download_url),
+        )
+            .into_response()
+    } else {
+        Json(uploaded_file).into_response()
+    };
+    if let Some(delete_url) = delete_url {
+        response.headers_mut().insert("x-url-delete", delete_url);
+    }
+    response
+}
+
+/// Helper to read an upload from a multipart/form-data form
+/// This function returns the contents of the first part that carries a file name
+/// and fills in the file name and content type of `new_file` from that part.
+/// Text fields named `download_limit`, `encrypted`, `publish`, `tags`, `visibility` and `description`
+/// override the matching headers.
+pub(crate) async fn read_multipart(
+    mut multipart: Multipart,
+    new_file: &mut data::NewFile,
+) -> Result<Bytes, Response> {
+    let mut body = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Multipart field error: {}", e);
+                return Err(multipart_error(e));
+            }
+        };
+        // plain form fields only carry options
+        if field.file_name().is_none() {
+            match field.name() {
+                Some("download_limit") => {
+                    if let Some(limit) = field.text().await.ok().and_then(|s| parse_download_limit(&s)) {
+                        new_file.download_limit = limit;
+                    }
+                }
+                Some("encrypted") => {
+                    if let Ok(value) = field.text().await {
+                        new_file.encrypted = value.trim().eq_ignore_ascii_case("true");
+                    }
+                }
+                Some("publish") => {
+                    if let Ok(value) = field.text().await {
+                        new_file.published = value.trim().eq_ignore_ascii_case("true");
+                    }
+                }
+                Some("visibility") => {
+                    if let Ok(value) = field.text().await {
+                        match share::parse_visibility(&value) {
+                            Some(visibility) => new_file.visibility = visibility,
+                            None => return Err(share::invalid_visibility()),
+                        }
+                    }
+                }
+                Some("tags") => {
+                    if let Ok(value) = field.text().await {
+                        match parse_tags(&value) {
+                            Some(tags) => new_file.tags = tags,
+                            None => return Err(invalid_tags().into_response()),
+                        }
+                    }
+                }
+                Some("description") => {
+                    if let Ok(value) = field.text().await {
+                        new_file.description = parse_description(Some(&value))
+                            .map_err(|e| ApiError::BadRequest(e).into_response())?;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if body.is_some() {
+            // only one file per upload, ignore the rest
+            continue;
+        }
+        new_file.file_name = field.file_name().unwrap_or("unknown").to_string();
+        new_file.content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        match field.bytes().await {
+            Ok(bytes) => body = Some(bytes),
+            Err(e) => {
+                warn!("Multipart read error: {}", e);
+                return Err(multipart_error(e));
+            }
+        }
+    }
+    match body {
+        Some(body) => Ok(body),
+        None => Err(ApiError::BadRequest("No file part in multipart form".to_string()).into_response()),
+    }
+}
+
+/// Returns the response for a multipart form that could not be read.
+/// Bodies over the size limit keep their status, so callers can answer with `too_large`.
+fn multipart_error(e: MultipartError) -> Response {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => e.into_response(),
+        _ => ApiError::BadRequest(e.body_text()).into_response(),
+    }
+}
+
+/// Helper to compare a client supplied digest with the computed one.
+/// The client may send the digest hex or base64 encoded.
+pub(crate) fn digest_matches(expected: &str, computed: &[u8]) -> bool {
+    let expected = hex::decode(expected)
+        .ok()
+        .filter(|bytes| bytes.len() == computed.len())
+        .or_else(|| BASE64_STANDARD.decode(expected).ok());
+    expected.as_deref() == Some(computed)
+}
+
+/// Helper to build the error for an upload whose checksum did not match.
+pub(crate) fn checksum_mismatch(algorithm: &str, expected: &str, computed: &str) -> ApiError {
+    warn!("Checksum mismatch for {}: expected {} computed {}", algorithm, expected, computed);
+    ApiError::ChecksumMismatch {
+        algorithm: algorithm.to_string(),
+        expected: expected.to_string(),
+        computed: computed.to_string(),
+    }
+}
+
+/// Helper to store an uploaded file
+/// This function stores a single file with `store_files`.
+/// It returns the metadata of the stored file,
+/// or a ready-made error response if anything fails.
+pub(crate) async fn store_file(
+    pool: &AnyPool,
+    config: &data::Config,
+    ip: &str,
+    new_file: data::NewFile,
+    body: Bytes,
+) -> Result<data::UploadedFile, Response> {
+    let mut uploaded = store_files(pool, config, ip, vec![(new_file, body)]).await?;
+    Ok(uploaded.remove(0))
+}
+
+/// Helper to store uploaded files
+/// This function verifies the optional checksums of every upload,
+/// sniffs its real content type,
+/// saves the file data to the server's file system
+/// and stores the metadata of all files in a single database transaction,
+/// so either every file is stored or none is.
+/// It returns the metadata of the stored files in order,
+/// or a ready-made error response if anything fails.
+/// Blobs are written to part files first and only moved into place once the rows are inserted,
+/// so a failed upload or a crash never leaves a blob without a file behind.
+/// The uploads count against the daily caps of the IP address `ip` they came from
+/// and against the monthly upload transfer of their owner.
+pub(crate) async fn store_files(
+    pool: &AnyPool,
+    config: &data::Config,
+    ip: &str,
+    uploads: Vec<(data::NewFile, Bytes)>,
+) -> Result<Vec<data::UploadedFile>, Response> {
+    if let Some((new_file, _)) = uploads
+        .iter()
+        .find(|(new_file, _)| !download_limit_allowed(config, new_file.download_limit))
+    {
+        return Err(invalid_download_limit(config, new_file.download_limit).into_response());
+    }
+    // a batch always belongs to a single user
+    if let Some((new_file, _)) = uploads.first() {
+        let additional = uploads.iter().map(|(_, body)| body.len() as i64).sum();
+        check_storage_quota(pool, config, &new_file.owner, additional).await?;
+        capacity::check(pool, config, additional).await?;
+        ip_quota::check(pool, config, ip, uploads.len(), additional).await?;
+        transfer::check(pool, config, &new_file.owner, transfer::Direction::Upload, additional).await?;
+    }
+    let mut staged = Vec::with_capacity(uploads.len());
+    for (new_file, body) in uploads {
+        staged.push(stage_file(pool, config, new_file, body).await?);
+    }
+
+    // every file gets a token to delete it without a key, see `delete_by_token`
+    for staged in &mut staged {
+        staged.delete_token = Some(hex::encode(rand::rng().random::<[u8; 32]>()));
+    }
+    let parts: Vec<_> = staged.iter_mut().map(|staged| staged.part.take()).collect();
+    let blobs = staged
+        .iter()
+        .zip(parts)
+        .map(|(staged, part)| (staged.file.blob_name(), staged.body.as_ref(), part))
+        .collect();
+    let insert = async {
+        let mut transaction = pool.begin().await?;
+        for staged in &staged {
+            db::insert_file(&mut transaction, &staged.file).await?;
+            if let Some(token) = &staged.delete_token {
+                db::insert_delete_token(&mut transaction, &staged.file.id, token).await?;
+            }
+            if let Some(collection_id) = &staged.collection_id {
+                db::insert_collection_file(&mut transaction, collection_id, &staged.file.id).await?;
+            }
+        }
+        transaction.commit().await
+    };
+    if let Err(e) = storage::add_references(pool, config, blobs, insert).await {
+        // another upload may have claimed the slug since it was checked
+        if e.as_database_error().is_some_and(|e| e.is_unique_violation()) {
+            warn!("Slug already in use: {}", e);
+            return Err(ApiError::Conflict("Slug already in use".to_string()).into_response());
+        }
+        error!("DB insert error: {}", e);
+        return Err(db::error_response(&e, "Database insert error"));
+    }
+
+    let sizes: Vec<i64> = staged.iter().map(|staged| staged.file.file_size).collect();
+    ip_quota::record(pool, config, ip, &sizes).await;
+    for staged in &staged {
+        changes::record(pool, &staged.file.owner, &staged.file.id, changes::Change::Created).await;
+    }
+    if let Some(staged) = staged.first() {
+        transfer::record(pool, config, &staged.file.owner, transfer::Direction::Upload, sizes.iter().sum()).await;
+    }
+
+    let mut uploaded = Vec::with_capacity(staged.len());
+    for staged in staged {
+        uploaded.push(finish_file(pool, config, staged).await);
+    }
+    Ok(uploaded)
+}
+
+/// An upload that passed every check and whose blob is written to a part file,
+/// waiting for its `files` row to be inserted.
+/// `part` is `None` if the blob was already stored.
+/// `delete_token` is set once the row is inserted with it, new versions of a file get none.
+/// `collection_id` is the collection the file is added to in the same transaction.
+pub(crate) struct StagedFile {
+    pub(crate) file: data::File,
+    pub(crate) body: Bytes,
+    pub(crate) content_md5: Option<String>,
+    pub(crate) part: Option<storage::Part>,
+    pub(crate) delete_token: Option<String>,
+    pub(crate) collection_id: Option<String>,
+}
+
+/// Helper to check an upload and write its blob
+/// This function writes the blob to a part file on the server's file system while it is hashed,
+/// verifies the optional checksums, scans the upload for viruses,
+/// sniffs its content type and checks the slug.
+/// The part becomes the blob once the `files` row is inserted.
+pub(crate) async fn stage_file(
+    pool: &AnyPool,
+    config: &data::Config,
+    new_file: data::NewFile,
+    body: Bytes,
+) -> Result<StagedFile, Response> {
+    let data::NewFile {
+        file_name,
+        mut content_type,
+        download_limit,
+        owner,
+        expected_sha256,
+        expected_md5,
+        slug,
+        encrypted,
+        tags,
+        visibility,
+        allowed_content_types,
+        expires,
+        expire_if_unused_days,
+        cache_control,
+        published,
+        collection_id,
+        description,
+    } = new_file;
+    // a CDN would hand private files to anybody and limited ones out without counting them
+    if cache_control.as_deref().is_some_and(shared_cacheable) && (visibility == "private" || download_limit != 0) {
+        return Err(ApiError::BadRequest(
+            "Shared caches can only keep public files with unlimited downloads, use private in cache".to_string(),
+        )
+        .into_response());
+    }
+    if published && visibility == "private" {
+        return Err(directory::private_unpublishable().into_response());
+    }
+    // the ID is made in the `id_format` of the server
+    let id = ids::new_file_id(config);
+    // the blob is written while it is hashed, the part is dropped again
+    // if the upload is refused or a blob with the same contents is already stored
+    // end to end encrypted uploads are ciphertext, compressing them saves nothing
+    let compress = config.compress_blobs && !encrypted && compression::is_compressible_type(&content_type);
+    let (part, digests) = match storage::write_part_hashed(config, body.clone(), expected_md5.is_some(), compress).await
+    {
+        Ok(written) => written,
+        Err(e) => {
+            warn!("write error {}: {}", id, e);
+            return Err(ApiError::Internal("File write error").into_response());
+        }
+    };
+    // identical uploads share one blob named after the content hash
+    let content_hash = digests.sha256;
+    if let Some(expected) = &expected_sha256 {
+        let computed = hex::decode(&content_hash).unwrap_or_default();
+        if !digest_matches(expected, &computed) {
+            return Err(checksum_mismatch("sha256", expected, &content_hash).into_response());
+        }
+    }
+    let content_md5 = match (&expected_md5, digests.md5) {
+        (Some(expected), Some(computed)) => {
+            let content_md5 = hex::encode(&computed);
+            if !digest_matches(expected, &computed) {
+                return Err(checksum_mismatch("md5", expected, &content_md5).into_response());
+            }
+            Some(content_md5)
+        }
+        _ => None,
+    };
+
+    // scan the upload for viruses before anything is stored
+    if let Some(clamav_addr) = &config.clamav_addr {
+        match clamav::scan(clamav_addr, &body).await {
+            Ok(clamav::ScanResult::Clean) => info!("Virus scan clean for upload {}", id),
+            Ok(clamav::ScanResult::Infected(signature)) => {
+                warn!("Rejected infected upload {} from {}: {}", id, owner, signature);
+                return Err(ApiError::Rejected {
+                    status: StatusCode::UNPROCESSABLE_ENTITY,
+                    error: "infected",
+                    message: "The upload contains a virus".to_string(),
+                    details: json!({ "signature": signature }),
+                }
+                .into_response());
+            }
+            Err(e) => {
+                // fail closed, nothing is stored without a verdict
+                error!("Virus scan error for upload {}: {}", id, e);
+                return Err(ApiError::Rejected {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    error: "scanner_unavailable",
+                    message: "Virus scanner unavailable".to_string(),
+                    details: Value::Null,
+                }
+                .into_response());
+            }
+        }
+    }
+
+    // the declared type comes from the client, sniff the real one from the first bytes
+    // end to end encrypted uploads are ciphertext, there is nothing to sniff
+    let detected_content_type = match encrypted {
+        true => None,
+        false => infer::get(&body).map(|kind| kind.mime_type().to_string()),
+    };
+    if let Some(detected) = &detected_content_type {
+        if config.reject_mime_mismatch && is_risky_mismatch(&content_type, detected) {
+            warn!("Rejected upload {} declared as {} but detected as {}", id, content_type, detected);
+            return Err(ApiError::Rejected {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                error: "content_type_mismatch",
+                message: "The content of the upload does not match its content type".to_string(),
+                details: json!({ "declared": content_type, "detected": detected }),
+            }
+            .into_response());
+        }
+        // a missing or generic declared type is replaced by the detected one
+        if content_type == "unknown" || content_type == "application/octet-stream" {
+            content_type = detected.clone();
+        }
+    }
+    // the server wide block list applies to the declared and the sniffed type
+    let blocked_type = [Some(content_type.as_str()), detected_content_type.as_deref()]
+        .into_iter()
+        .flatten()
+        .find(|checked| tokens::content_type_matches(&config.blocked_types, checked));
+    if let Some(blocked_type) = blocked_type {
+        warn!("Rejected upload {} of blocked type {}", id, blocked_type);
+        return Err(ApiError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            error: "content_type_blocked",
+            message: "Uploads of this content type are blocked".to_string(),
+            details: json!({ "content_type": blocked_type }),
+        }
+        .into_response());
+    }
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    if let Some(extension) = extension.filter(|extension| config.blocked_extensions.contains(extension)) {
+        warn!("Rejected upload {} with blocked extension {}", id, extension);
+        return Err(ApiError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            error: "extension_blocked",
+            message: "Uploads with this file extension are blocked".to_string(),
+            details: json!({ "extension": extension }),
+        }
+        .into_response());
+    }
+    // the server wide allow list only trusts the sniffed type, content that can't be sniffed
+    // like text or encrypted uploads counts as application/octet-stream whatever the client declared
+    let sniffed_content_type = detected_content_type.as_deref().unwrap_or("application/octet-stream");
+    if !config.allowed_types.is_empty() && !tokens::content_type_matches(&config.allowed_types, sniffed_content_type) {
+        warn!("Rejected upload {} of type {} not in the allow list", id, sniffed_content_type);
+        return Err(ApiError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            error: "content_type_not_allowed",
+            message: "Uploads of this content type are not allowed".to_string(),
+            details: json!({ "content_type": sniffed_content_type, "allowed": config.allowed_types }),
+        }
+        .into_response());
+    }
+    // tokens limited to some content types are checked against the sniffed type if there is one,
+    // the declared type is up to the client
+    let checked_content_type = detected_content_type.as_deref().unwrap_or(&content_type);
+    if !allowed_content_types.is_empty() && !tokens::content_type_matches(&allowed_content_types, checked_content_type) {
+        warn!("Rejected upload {} of type {} not allowed by its token", id, checked_content_type);
+        return Err(ApiError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            error: "content_type_not_allowed",
+            message: "The token does not allow uploads of this content type".to_string(),
+            details: json!({ "content_type": checked_content_type, "allowed": allowed_content_types }),
+        }
+        .into_response());
+    }
+    if let Some(slug) = &slug {
+        if slug_taken(pool, slug).await? {
+            return Err(ApiError::Conflict("Slug already in use".to_string()).into_response());
+        }
+    }
+    info!("File type is {}", content_type);
+
+    let part = match storage::blob_exists(config, &content_hash).await {
+        true => None,
+        false => Some(part),
+    };
+    let file_size = body.len() as i64;
+
+    let upload_time = Utc::now().timestamp(); // i64
+
+    let download_count = 0;
+
+    let download_url = download_url(config, &id, slug.as_deref());
+
+    Ok(StagedFile {
+        file: data::File {
+            id,
+            file_name,
+            content_type,
+            upload_time,
+            download_limit,
+            download_count,
+            file_size,
+            download_url,
+            owner,
+            content_hash: Some(content_hash),
+            slug,
+            encrypted: encrypted as i32,
+            detected_content_type,
+            visibility,
+            expires,
+            deleted_at: None,
+            version: 1,
+            // the blob is only mirrored once its row is stored, see `finish_file`
+            cid: None,
+            expire_if_unused_days,
+            last_downloaded_at: None,
+            verified: 0,
+            cache_control,
+            published: published as i32,
+            blob_missing: 0,
+            pinned: 0,
+            exhausted_at: None,
+            description,
+            tags,
+            human: None,
+        },
+        body,
+        content_md5,
+        part,
+        delete_token: None,
+        collection_id,
+    })
+}
+
+/// Returns the URL a file is downloaded from,
+/// files with a slug are shared by their vanity URL.
+pub(crate) fn download_url(config: &data::Config, id: &str, slug: Option<&str>) -> String {
+    let path = match slug {
+        Some(slug) => format!("d/{}", slug),
+        None => format!("download/{}", id),
+    };
+    public_url(config, &path)
+}
+
+/// Returns the URL clients reach a path of the server at,
+/// below the `base_path` the server is reached under behind a reverse proxy.
+pub(crate) fn public_url(config: &data::Config, path: &str) -> String {
+    match config.use_tls {
+        true => format!("https://{}{}/{}", config.base_url, config.base_path, path),
+        false => format!("http://{}{}/{}", config.base_url, config.base_path, path),
+    }
+}
+
+/// Helper to finish a stored upload
+/// This function mirrors public files to IPFS, generates the thumbnail of images,
+/// emits the upload webhook and builds the response of the upload.
+/// It is only called once the row of the file is committed,
+/// so uploads that are refused or rolled back are never published to IPFS.
+pub(crate) async fn finish_file(pool: &AnyPool, config: &data::Config, staged: StagedFile) -> data::UploadedFile {
+    let StagedFile {
+        mut file,
+        body,
+        content_md5,
+        delete_token,
+        ..
+    } = staged;
+    let encrypted = file.encrypted != 0;
+
+    if let Some(cid) = ipfs::mirror(config, &file.id, &file.visibility, &body).await {
+        match db::set_cid(pool, &file.id, &cid).await {
+            Ok(()) => {
+                cache::forget_file(&file.id).await;
+                file.cid = Some(cid);
+            }
+            Err(e) => {
+                // the node keeps the pin, the blob is just not unpinned once it is released
+                error!("DB update cid error {}: {}", file.id, e);
+            }
+        }
+    }
+
+    // generate a thumbnail for images so previews don't count as downloads
+    // encrypted uploads can't be decoded, so they never get a thumbnail
+    if !encrypted && thumbnail::is_supported(&file.content_type) {
+        let thumbnail_name = thumbnail::thumbnail_name(file.blob_name());
+        if !storage::blob_exists(config, &thumbnail_name).await {
+            match tokio::task::spawn_blocking(move || thumbnail::generate(&body)).await {
+                Ok(Some(png)) => {
+                    if let Err(e) = storage::write_blob(config, &thumbnail_name, &png).await {
+                        warn!("thumbnail write error {}: {}", file.id, e);
+                    }
+                }
+                Ok(None) => info!("Could not decode image {} for a thumbnail", file.id),
+                Err(e) => warn!("thumbnail task error {}: {}", file.id, e),
+            }
+        }
+    }
+
+    webhook::emit(webhook::EventKind::Uploaded, &file);
+
+    // the client appends its key to the fragment, browsers never send the fragment to the server
+    let share_url_template = encrypted.then(|| public_url(config, &format!("e/{}#key={{key}}", file.id)));
+    data::UploadedFile {
+        links: upload_links(config, &file, delete_token.as_deref()),
+        delete_token,
+        file,
+        content_md5,
+        share_url_template,
+    }
+}
+
+/// Returns the links of an uploaded file
+/// Markdown is viewed rendered and audio and video in the player,
+/// anything else is shown inline by the browser.
+/// The delete link is only included if the delete token of the file is known.
+pub(crate) fn upload_links(config: &data::Config, file: &data::File, delete_token: Option<&str>) -> data::UploadLinks {
+    let view = match file.encrypted != 0 {
+        true => None,
+        false if markdown::is_markdown(file) => Some(public_url(config, &format!("view/{}", file.id))),
+        false if player::playable_type(file).is_some() => Some(public_url(config, &format!("play/{}", file.id))),
+        false => Some(public_url(config, &format!("download/{}?inline=true", file.id))),
+    };
+    data::UploadLinks {
+        download: file.download_url.clone(),
+        view,
+        share: public_url(config, &format!("s/{}", file.id)),
+        delete: delete_token.map(|token| public_url(config, &format!("download/{}?token={}", file.id, token))),
+        qr: public_url(config, &format!("qr/{}", file.id)),
+    }
+}
+
+/// This is The file Download handler
+/// This function handles the file download process.
+/// It retrieves the file metadata from the database
+/// and returns the file as a response.
+/// `/download/<uuid>.sig` returns the detached signature of the file instead.
+/// The SHA-256 digest of the file is sent as `x-checksum-sha256`, `Repr-Digest` and `Digest`,
+/// so the recipient can verify that the download matches the upload.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/download/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following for private files:
+/// - key: the key of the owner as a header (optional)
+/// - token: a share token of the file as a query parameter (optional)
+///
+/// accepts the following query parameters:
+/// - version: the number of an earlier version of the file to download (optional)
+/// - stream: the grant of a `/play/<uuid>` page, serves the file inline without counting a download (optional)
+/// - inline: `true` to show the file in the browser instead of saving it (optional)
+#[utoipa::path(
+    get,
+    path = "/download/{uuid}",
+    tag = "files",
+    params(("uuid" = String, Path, description = "The UUID of the file"), data::DownloadQuery),
+    responses(
+        (status = 200, description = "The contents of the file", body = Vec<u8>),
+        (status = 400, description = "The UUID is invalid"),
+        (status = 403, description = "The file is private"),
+        (status = 410, description = "The file does not exist, has expired or reached its download limit"),
+    ),
+    security((), ("key" = []))
+)]
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn download_file(
+    Path(uuid): Path<String>, // Add this extractor
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+    // Remove body: Bytes,         // <-- GET handler shouldn't have a body
+) -> Result<Response, ApiError> {
+
+    // Get UUID directly from path
+    info!("Download request for UUID: {}", uuid);
+    // Log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received download request for {} from IP: {}", uuid, ip);
+
+    // the detached signature of a file is served next to it
+    if let Some(uuid) = uuid.strip_suffix(".sig") {
+        return signature::download_signature(&pool, uuid, &ip, &headers, query.token.as_deref()).await;
+    }
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+
+    // Check if the file exists in the database
+    let file = cache::file(&pool, &uuid).await;
+    let file = match file {
+        Ok(Some(file)) => {
+            info!("File found in DB: {}", uuid);
+            file
+        }
+        Ok(None) if reports::is_blocked(&pool, &uuid).await => return Err(reports::taken_down().into()),
+        Ok(None) => {
+            // the blob may still be on disk while another download removes the row
+            warn!("File not found in DB: {}", uuid);
+            return Err(ApiError::Gone("Download limit reached".to_string()));
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    // the player of a `/play/<uuid>` page seeks without counting downloads
+    if let Some(grant) = query.stream.as_deref() {
+        return player::send_stream(&config, file, &headers, grant).await;
+    }
+    let file = versions::select(&pool, file, query.version).await?;
+
+    let (file_name, id) = (file.file_name.clone(), file.id.clone());
+    let response = send_download(&pool, &config, file, &ip, &headers, query.token.as_deref()).await;
+    Ok(match query.inline {
+        Some(true) => show_inline(response, &file_name, &id),
+        _ => response,
+    })
+}
+
+/// Handler to download a file by its vanity slug
+/// This function looks up the file with the given slug
+/// and serves it exactly like `/download/<uuid>`, counting the download.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/d/<slug>
+/// requires the following path parameter:
+/// - slug: the slug of the file (not optional)
+///
+/// accepts the same key header, token and version query parameters as `/download/<uuid>`
+#[instrument(skip_all, fields(slug = %slug))]
+pub async fn download_slug(
+    Path(slug): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // Log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received download request for slug {} from IP: {}", slug, ip);
+
+    let file = match db::find_file_by_slug(&pool, &slug).await {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            // like UUIDs, slugs disappear once the download limit is reached
+            warn!("Slug not found in DB: {}", slug);
+            return Err(ApiError::Gone("Download limit reached".to_string()));
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", slug, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    let file = versions::select(&pool, file, query.version).await?;
+
+    let (file_name, id) = (file.file_name.clone(), file.id.clone());
+    let response = send_download(&pool, &config, file, &ip, &headers, query.token.as_deref()).await;
+    Ok(match query.inline {
+        Some(true) => show_inline(response, &file_name, &id),
+        _ => response,
+    })
+}
+
+/// Helper to let the browser show a download instead of saving it
+/// The file is sandboxed, so an uploaded HTML page can't run scripts or load anything.
+fn show_inline(mut response: Response, file_name: &str, id: &str) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    if let Ok(disposition) = HeaderValue::from_str(&content_disposition("inline", file_name, id)) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    response.headers_mut().insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox; default-src 'none'; img-src 'self'; media-src 'self'; style-src 'unsafe-inline'"),
+    );
+    response
+}
+
+/// Returns the byte range of a `Range: bytes=` header value within a file of the given size,
+/// `None` if the range can't be satisfied. Only single ranges are supported.
+pub(crate) fn byte_range(value: &str, size: usize) -> Option<Range<usize>> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => size.saturating_sub(suffix.parse().ok()?)..size,
+        (start, "") => start.parse().ok()?..size,
+        (start, end) => start.parse().ok()?..end.parse::<usize>().ok()?.saturating_add(1).min(size),
+    };
+    (range.start < range.end).then_some(range)
+}
+
+/// Helper to send a file to a downloading client
+/// This function checks that the client may download a private file,
+/// counts the download, returns the file contents
+/// and removes the file once its download limit is reached.
+/// A `Range` header asks for a part of the file, every part counts as a download.
+/// With `stream_downloads` a blob that is neither compressed nor encrypted is streamed from disk in large reads
+/// instead of being read into memory first, unless the file is small enough for the memory cache.
+/// With `download_offload` the reverse proxy sends the blob instead, bitBeam only checks the download
+/// and counts it right away, as it can't see whether the proxy sent the file to the end.
+/// With `download_offload = "redirect"` the client is sent to a presigned URL of the blob
+/// in the bucket of `download_redirect_url` and downloads it from there.
+/// Blobs stored compressed with `compress_blobs` are always read whole, and sent as they are
+/// with `Content-Encoding: gzip` to clients that accept it and ask for the whole file.
+/// The download is recorded in the audit log and the download statistics of the file.
+pub(crate) async fn send_download(
+    pool: &AnyPool,
+    config: &data::Config,
+    file: data::File,
+    ip: &str,
+    headers: &HeaderMap,
+    token: Option<&str>,
+) -> Response {
+    let uuid = file.id.clone();
+
+    // the cleanup task removes expired and trashed files, until then they are refused here
+    if !file.is_available() {
+        info!("File {} has expired or is in the trash", uuid);
+        return gone(&file).into_response();
+    }
+    if let Err(response) = share::check_access(pool, &file, headers, token, ip).await {
+        return response;
+    }
+
+    // find the blob of the file in the config.data_path
+    let Some(form) = storage::blob_form(config, file.blob_name()).await else {
+        error!("File not found: {}", storage::blob_path(config, file.blob_name()).display());
+        return ApiError::NotFound("File not found".to_string()).into_response();
+    };
+
+    // an unsatisfiable range is refused before it counts as a download
+    let range = match headers.get("range").and_then(|hv| hv.to_str().ok()) {
+        Some(value) => match byte_range(value, file.file_size as usize) {
+            Some(range) => Some(range),
+            None => return ApiError::RangeNotSatisfiable(file.file_size).into_response(),
+        },
+        None => None,
+    };
+
+    //update download count, it is given back if the file is not sent to the end
+    let pending = match claim_download(pool, config, &file).await {
+        Ok(true) => PendingDownload::new(pool, config, &file, None, ip, headers),
+        Ok(false) => return download_limit_reached(&file).into_response(),
+        Err(response) => return response,
+    };
+
+    // the reverse proxy and the stream would send compressed and encrypted blobs as they are stored
+    let plain = form.is_plain();
+
+    // the bucket answers the range itself, the client sends its `Range` header again after the redirect
+    if config.download_offload == "redirect" && plain {
+        let disposition = content_disposition("attachment", &file.file_name, &uuid);
+        let overrides = [
+            ("response-content-disposition", disposition.as_str()),
+            ("response-content-type", file.content_type.as_str()),
+        ];
+        let location = storage::blob_key(config, file.blob_name())
+            .and_then(|key| s3::presigned_url(config, &key, &overrides));
+        let Some(location) = location else {
+            error!("Could not presign the download of {}", uuid);
+            return ApiError::Internal("File read error").into_response();
+        };
+        // the URL expires, the redirect must not outlive it in a cache
+        let response = axum::response::Response::builder()
+            .status(StatusCode::FOUND)
+            .header("Location", location)
+            .header("Cache-Control", "no-store")
+            .body(Body::empty());
+        return match response {
+            Ok(response) => response.map(|body| pending.body(body, 0)),
+            Err(e) => {
+                error!("Redirect error {}: {}", uuid, e);
+                ApiError::Internal("File read error").into_response()
+            }
+        };
+    }
+
+    // the reverse proxy reads the blob from disk and answers the range itself
+    if let Some((name, value)) = storage::offload_header(config, file.blob_name()).filter(|_| plain) {
+        let response = axum::response::Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Disposition", content_disposition("attachment", &file.file_name, &uuid))
+            .header("Content-Type", &file.content_type)
+            .header(name, value);
+        let response = with_checksum(with_cache_control(response, &file), &file, false);
+        return match response.body(Body::empty()) {
+            Ok(response) => response.map(|body| pending.body(body, 0)),
+            Err(e) => {
+                error!("Offload header error {}: {}", uuid, e);
+                ApiError::Internal("File read error").into_response()
+            }
+        };
+    }
+
+    // plain blobs can be streamed from disk instead of being read into memory first
+    if config.stream_downloads && plain && !memory_cache::fits(file.file_size) {
+        let size = file.file_size.max(0) as u64;
+        let sent = range.map_or(0..size, |range| range.start as u64..range.end as u64);
+        let stream = match storage::stream_blob(config, file.blob_name(), sent.clone()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("File read error {}: {}", uuid, e);
+                return ApiError::Internal("File read error").into_response();
+            }
+        };
+        let length = (sent.end - sent.start) as usize;
+        let response = axum::response::Response::builder()
+            .header("Content-Disposition", content_disposition("attachment", &file.file_name, &uuid))
+            .header("Content-Type", &file.content_type)
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Length", length);
+        let response = with_checksum(with_cache_control(response, &file), &file, false);
+        let response = match sent.end - sent.start < size {
+            true => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("Content-Range", format!("bytes {}-{}/{}", sent.start, sent.end - 1, size)),
+            false => response.status(StatusCode::OK),
+        };
+        return built(response.body(pending.body(throttle::stream_body(stream), length)), &uuid);
+    }
+
+    // a client that accepts gzip gets the compressed blob without decompressing it first
+    let gzip_blob = match config.compress_blobs
+        && range.is_none()
+        && compression::is_compressible_type(&file.content_type)
+        && compression::accepts_gzip(headers)
+    {
+        true => storage::read_gzip_blob(config, file.blob_name()).await,
+        false => Ok(None),
+    };
+    match gzip_blob {
+        Ok(Some(member)) => {
+            let length = member.len();
+            let response = axum::response::Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Disposition", content_disposition("attachment", &file.file_name, &uuid))
+                .header("Content-Type", &file.content_type)
+                .header("Content-Encoding", "gzip")
+                .header("Vary", "Accept-Encoding")
+                .header("Content-Length", length);
+            let response = with_checksum(with_cache_control(response, &file), &file, true);
+            return built(response.body(pending.body(throttle::body(member), length)), &uuid);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("File read error {}: {}", uuid, e);
+            return ApiError::Internal("File read error").into_response();
+        }
+    }
+
+    //rutn file to axum::body::Bytes
+    let file_bytes = match storage::read_cached_blob(config, file.blob_name(), file.file_size.max(0) as u64).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("File read error {}: {}", uuid, e);
+            return ApiError::Internal("File read error").into_response();
+        }
+    };
+
+    // return the file or the requested part of it as a response
+    let response = axum::response::Response::builder()
+        .header("Content-Disposition", content_disposition("attachment", &file.file_name, &uuid))
+        .header("Content-Type", &file.content_type)
+        .header("Accept-Ranges", "bytes");
+    let response = with_checksum(with_cache_control(response, &file), &file, false);
+    let (response, file_bytes) = match range {
+        Some(range) if range.end <= file_bytes.len() => (
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("Content-Range", format!("bytes {}-{}/{}", range.start, range.end - 1, file_bytes.len())),
+            file_bytes.slice(range),
+        ),
+        _ => (response.status(StatusCode::OK), file_bytes),
+    };
+    let length = file_bytes.len();
+    let response = response.header("Content-Length", length);
+    built(response.body(pending.body(throttle::body(file_bytes), length)), &uuid)
+}
+
+/// Handler to inspect a download without counting it
+/// This function answers `HEAD /download/<uuid>` with the headers a download would have,
+/// the size, the content type and the file name,
+/// plus `x-downloads-remaining` with the number of downloads left
+/// and `x-expires-at` with the unix timestamp the file expires at, if it has a limit of that kind,
+/// and the checksum headers of a download.
+/// The download count is not incremented.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -I http://localhost:3000/download/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn download_head(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received head request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    match find_file(&pool, "id", &uuid).await? {
+        Some(file) => {
+            share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+            Ok(head_response(&file))
+        }
+        None if reports::is_blocked(&pool, &uuid).await => Err(reports::taken_down().into()),
+        // like a download, a missing file has most likely reached its limit
+        None => Err(ApiError::Gone("Download limit reached".to_string())),
+    }
+}
+
+/// Handler to inspect a download by its vanity slug without counting it
+/// This function answers `HEAD /d/<slug>` exactly like `HEAD /download/<uuid>`.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -I http://localhost:3000/d/<slug>
+/// requires the following path parameter:
+/// - slug: the slug of the file (not optional)
+#[instrument(skip_all, fields(slug = %slug))]
+pub async fn download_slug_head(
+    Path(slug): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received head request for slug {} from IP: {}", slug, ip);
+
+    match find_file(&pool, "slug", &slug).await? {
+        Some(file) => {
+            share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+            Ok(head_response(&file))
+        }
+        None => Err(ApiError::Gone("Download limit reached".to_string())),
+    }
+}
+
+/// Handler to return the metadata of a file
+/// This function returns the public metadata of a file as JSON,
+/// so anybody with the link can check it before downloading.
+/// Private files need the same key or share token as a download.
+/// The download count is not incremented.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/file/<uuid>/info
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following query parameters:
+/// - token: a share token of a private file (optional)
+/// - human: `true` to add a `human` object with the size, times and downloads formatted, see `/all_files` (optional)
+#[utoipa::path(
+    get,
+    path = "/file/{uuid}/info",
+    tag = "files",
+    params(("uuid" = String, Path, description = "The UUID of the file"), data::DownloadQuery),
+    responses(
+        (status = 200, description = "The metadata of the file", body = data::FileInfo),
+        (status = 403, description = "The file is private"),
+        (status = 404, description = "The file does not exist"),
+    ),
+    security((), ("key" = []))
+)]
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn file_info(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received info request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let file = match find_file(&replica, "id", &uuid).await? {
+        Some(file) => file,
+        None if reports::is_blocked(&replica, &uuid).await => return Err(reports::taken_down().into()),
+        None => return Err(ApiError::NotFound("File not found".to_string())),
+    };
+    share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+    Ok(Json(data::FileInfo {
+        downloads_remaining: file.downloads_remaining(),
+        expires_in: file.expires.map(|expires| (expires - Utc::now().timestamp()).max(0)),
+        limited_by: file.limited_by().to_string(),
+        sha256: file.sha256().map(str::to_string),
+        human: query.human.unwrap_or(false).then(|| human::file(&file)),
+        id: file.id,
+        file_name: file.file_name,
+        content_type: file.content_type,
+        file_size: file.file_size,
+        upload_time: file.upload_time,
+        download_limit: file.download_limit,
+        download_count: file.download_count,
+        download_url: file.download_url,
+        encrypted: file.encrypted != 0,
+        visibility: file.visibility,
+        expires: file.expires,
+        version: file.version,
+        verified: file.verified != 0,
+        description: file.description,
+    })
+    .into_response())
+}
+
+/// Helper to look up a file by its `id` or `slug` column.
+/// Expired and trashed files are treated as if they were already removed.
+pub(crate) async fn find_file(
+    pool: &AnyPool,
+    column: &'static str,
+    value: &str,
+) -> Result<Option<data::File>, Response> {
+    // lookups by ID are the hot path of downloads and go through the cache
+    let file = match column {
+        "slug" => db::find_file_by_slug(pool, value).await,
+        _ => cache::file(pool, value).await,
+    };
+    file.map(|file| file.filter(|file| file.is_available()))
+        .map_err(|e| {
+            error!("DB select error {}: {}", value, e);
+            db::error_response(&e, "Database select error")
+        })
+}
+
+/// Helper to look up the files of a user by name
+/// This function returns the files of the owner with the given name that aren't in the trash,
+/// the newest first. The S3 and WebDAV endpoints treat the newest of them as the file at that path.
+pub(crate) async fn find_named(pool: &AnyPool, owner: &str, file_name: &str) -> Result<Vec<data::File>, Response> {
+    db::find_named_files(pool, owner, file_name).await.map_err(|e| {
+        error!("DB select error {}: {}", file_name, e);
+        db::error_response(&e, "Database select error")
+    })
+}
+
+/// Helper to send a download with the `Cache-Control` header its uploader chose, if any.
+fn with_cache_control(response: axum::http::response::Builder, file: &data::File) -> axum::http::response::Builder {
+    match &file.cache_control {
+        Some(cache_control) => response.header(header::CACHE_CONTROL, cache_control),
+        None => response,
+    }
+}
+
+/// Helper to send the checksum of a file with its download, so the recipient can verify what they received.
+/// `x-checksum-sha256` carries the hex digest, `Repr-Digest` (RFC 9530) and the older `Digest` the base64 one.
+/// Both of those describe the bytes of the representation, so gzip encoded downloads only get `x-checksum-sha256`.
+fn with_checksum(
+    response: axum::http::response::Builder,
+    file: &data::File,
+    gzip: bool,
+) -> axum::http::response::Builder {
+    let Some(sha256) = file.sha256() else {
+        return response;
+    };
+    let response = response.header("x-checksum-sha256", sha256);
+    match (gzip, hex::decode(sha256)) {
+        (false, Ok(digest)) => {
+            let digest = BASE64_STANDARD.encode(digest);
+            response
+                .header("Repr-Digest", format!("sha-256=:{}:", digest))
+                .header("Digest", format!("SHA-256={}", digest))
+        }
+        _ => response,
+    }
+}
+
+/// Returns the built response of a download, or a 500 if it could not be built,
+/// like for a stored content type that is no valid header value.
+pub(crate) fn built(response: Result<Response, axum::http::Error>, id: &str) -> Response {
+    response.unwrap_or_else(|e| {
+        error!("Response error {}: {}", id, e);
+        ApiError::Internal("File read error").into_response()
+    })
+}
+
+/// Returns the headers of a download of the file without its contents.
+fn head_response(file: &data::File) -> Response {
+    let mut response = Response::builder()
+        .header("Content-Disposition", content_disposition("attachment", &file.file_name, &file.id))
+        .header("Content-Type", &file.content_type)
+        .header("Content-Length", file.file_size);
+    response = with_checksum(with_cache_control(response, file), file, false);
+    // files with unlimited downloads have no count to report
+    if let Some(remaining) = file.downloads_remaining() {
+        response = response.header("x-downloads-remaining", remaining);
+    }
+    if let Some(expires) = file.expires {
+        response = response.header("x-expires-at", expires);
+    }
+    built(response.body(Body::empty()), &file.id)
+}
+
+/// Returns true if the slug only uses URL safe characters and is 1 to 64 characters long.
+pub(crate) fn is_valid_slug(slug: &str) -> bool {
+    (1..=64).contains(&slug.len())
+        && !slug.starts_with('.')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Helper to check whether a slug is already used by another file.
+async fn slug_taken(pool: &AnyPool, slug: &str) -> Result<bool, Response> {
+    db::slug_exists(pool, slug).await.map_err(|e| {
+        error!("DB select error {}: {}", slug, e);
+        db::error_response(&e, "Database select error")
+    })
+}
+
+/// Helper to count a download of a file
+/// This function increments the download count of a file
+/// unless its download limit is already reached.
+/// The limit is checked in the same statement so concurrent downloads
+/// can never push the count past the limit.
+/// Files with a download limit of 0 can be downloaded any number of times.
+/// The time of the download is kept for `expire_if_unused_days`.
+/// Downloads of a file whose owner used up their monthly download transfer are refused with 403.
+/// It returns false if the download limit is already reached.
+pub(crate) async fn claim_download(pool: &AnyPool, config: &data::Config, file: &data::File) -> Result<bool, Response> {
+    transfer::check(pool, config, &file.owner, transfer::Direction::Download, file.file_size).await?;
+    let uuid = &file.id;
+    match db::increment_download(pool, uuid).await {
+        Ok(false) => {
+            warn!("Download limit already reached for UUID: {}", uuid);
+            Ok(false)
+        }
+        Ok(true) => {
+            info!("Update Download Count Sucess for UUID: {}", uuid);
+            Ok(true)
+        }
+        Err(e) => {
+            error!("DB update error {}: {}", uuid, e);
+            Err(db::error_response(&e, "Database update error"))
+        }
+    }
+}
+
+/// Helper to give back a claimed download of a file
+/// This function decrements the download count again
+/// when the file was not sent to the end, so an aborted transfer doesn't use up a download.
+pub(crate) async fn release_download(pool: &AnyPool, uuid: &str) {
+    match db::decrement_download(pool, uuid).await {
+        Ok(()) => info!("Download of {} was not completed, it is not counted", uuid),
+        Err(e) => error!("DB update error {}: {}", uuid, e),
+    }
+}
+
+/// This struct is a download that was claimed but not sent yet.
+/// Once its body was sent to the end, the download is recorded and finished, see `finish_download`.
+/// If the client goes away first or the download fails before its body is sent,
+/// the download is given back, see `release_download`.
+pub(crate) struct PendingDownload {
+    pool: AnyPool,
+    config: data::Config,
+    file: data::File,
+    username: Option<String>,
+    ip: String,
+    headers: HeaderMap,
+    finished: bool,
+}
+
+impl PendingDownload {
+    /// Returns the pending download of a file whose download was claimed.
+    /// `username` is the user who downloads the file, `None` for anonymous downloads.
+    pub(crate) fn new(
+        pool: &AnyPool,
+        config: &data::Config,
+        file: &data::File,
+        username: Option<&str>,
+        ip: &str,
+        headers: &HeaderMap,
+    ) -> Self {
+        PendingDownload {
+            pool: pool.clone(),
+            config: config.clone(),
+            file: file.clone(),
+            username: username.map(str::to_string),
+            ip: ip.to_string(),
+            headers: headers.clone(),
+            finished: false,
+        }
+    }
+
+    /// Returns a body of `length` bytes that finishes the download once its last byte was sent.
+    /// The server stops reading a body as soon as `content-length` bytes were sent,
+    /// so the bytes are counted instead of waiting for the end of the body.
+    pub(crate) fn body(self, body: Body, length: usize) -> Body {
+        if length == 0 {
+            tokio::spawn(self.finish());
+            return body;
+        }
+        let mut pending = Some(self);
+        let mut sent = 0;
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                sent += chunk.len();
+                if let Some(pending) = pending.take_if(|_| sent >= length) {
+                    tokio::spawn(pending.finish());
+                }
+            }
+            chunk
+        }))
+    }
+
+    /// This function records the download, counts it against the monthly download transfer of the owner
+    /// and moves the file to the trash if the download count reached the download limit.
+    pub(crate) async fn finish(mut self) {
+        self.finished = true;
+        let file = &self.file;
+        webhook::emit(webhook::EventKind::Downloaded, file);
+        transfer::record(&self.pool, &self.config, &file.owner, transfer::Direction::Download, file.file_size).await;
+        audit::record(&self.pool, audit::Action::Download, self.username.as_deref(), Some(&file.id), &self.ip).await;
+        stats::record_download(&self.pool, &file.id, &self.ip, &self.headers).await;
+        //if the download count reached the download limit delete the file and remove it from the database
+        if finish_download(&self.pool, &self.config, file).await.is_err() {
+            warn!("Could not finish download of {}", file.id);
+        }
+    }
+}
+
+impl Drop for PendingDownload {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let (pool, uuid) = (self.pool.clone(), self.file.id.clone());
+        tokio::spawn(async move { release_download(&pool, &uuid).await });
+    }
+}
+
+/// Helper to finish a counted download of a file
+/// This function marks the file as exhausted once its download count reached the download limit
+/// and tells the owner with the `file.limit_reached` event.
+/// It refuses further downloads and the cleanup task moves it to the trash after `exhausted_grace_period`,
+/// nothing is removed while the download is still being answered.
+/// The count is read again because concurrent downloads may have incremented it too.
+/// A pinned file is not marked, it refuses further downloads until it is unpinned.
+pub(crate) async fn finish_download(
+    pool: &AnyPool,
+    config: &data::Config,
+    file: &data::File,
+) -> Result<(), Response> {
+    let download_count = db::download_count(pool, &file.id).await.unwrap_or_else(|e| {
+        error!("DB select error {}: {}", file.id, e);
+        None
+    });
+    if file.download_limit > 0 && download_count.is_some_and(|count| count >= file.download_limit) {
+        if file.is_pinned() {
+            info!("File {} reached its download limit but is pinned, keeping it", file.id);
+            return Ok(());
+        }
+        match db::mark_exhausted(pool, &file.id).await {
+            Ok(true) => {
+                cache::forget_file(&file.id).await;
+                changes::record(pool, &file.owner, &file.id, changes::Change::Updated).await;
+                info!(
+                    "File {} reached its download limit, it is trashed in {} seconds",
+                    file.id, config.exhausted_grace_period
+                );
+                webhook::emit(webhook::EventKind::LimitReached, file);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                error!("DB exhausted error {}: {}", file.id, e);
+                return Err(db::error_response(&e, "Database update error"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handler to return the thumbnail of an image
+/// This function serves the thumbnail generated when an image was uploaded.
+/// Fetching a thumbnail does not count against the download limit of the file.
+/// Thumbnails of private files need the same key or share token as a download.
 /// It also logs the IP address of the client making the request.
-/// example request: curl -X GET http://localhost:3000/all_files
-/// requires no parameters
-/// returns a JSON array of files
-/// TODO: add user authentication
-pub async fn all_files(
+/// example request: curl -X GET http://localhost:3000/thumbnail/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+pub async fn thumbnail(
+    Path(uuid): Path<String>,
     Extension(pool): Extension<AnyPool>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
     //log the IP address of the client and the call
     let ip = addr.ip().to_string();
-    info!("Received an all_files request from IP: {}", ip);
-    // build the query and map the result to the File struct
-    // and return the result as JSON if successful
-    // or return an error message if not
-    match sqlx::query_as::<_, data::File>(
-        r#"
-        SELECT *
-        FROM files
-        "#,
-    )
-    .fetch_all(&pool)
-    .await
-    {
-        Ok(files) => {
-            info!("DB select all success");
-            (StatusCode::OK, Json(files)).into_response()
-        }
+    info!("Received thumbnail request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+
+    let file = db::find_file(&pool, &uuid).await;
+    let file = match file {
+        Ok(Some(file)) if file.is_available() => file,
+        Ok(_) => return Err(ApiError::NotFound("File not found".to_string())),
         Err(e) => {
-            warn!("DB select all error: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Database select all error",
-            )
-                .into_response()
+            error!("DB select error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database select error"));
         }
+    };
+
+    share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+
+    let thumbnail_name = thumbnail::thumbnail_name(file.blob_name());
+    match storage::read_cached_blob(&config, &thumbnail_name, thumbnail::MAX_THUMBNAIL_BYTES).await {
+        Ok(png) => Ok((
+            [(axum::http::header::CONTENT_TYPE, thumbnail::THUMBNAIL_CONTENT_TYPE)],
+            png,
+        )
+            .into_response()),
+        Err(_) => Err(ApiError::NotFound("No thumbnail for this file".to_string())),
     }
 }
 
-/// Handler to upload a file
-/// This function handles the file upload process.
-/// It receives the file data in the request body,
-/// saves it to the server's file system,
-/// and stores the file metadata in the database.
+/// Handler to delete a file
+/// This function deletes a file owned by the requesting user.
+/// The file is moved to the trash, where it can be restored with `/file/<uuid>/restore`
+/// until the cleanup task purges it after `trash_retention` seconds.
+/// Deleting a file that is already in the trash removes it from the server's file system
+/// and its metadata from the database right away.
+/// It returns the deleted file metadata as a JSON response.
 /// It also logs the IP address of the client making the request.
-/// example request: curl -X POST -H "key: <key>" -H "file_name: <file_name>" -H "content-type: <content_type>" -H "download_limit: <download_limit>" --data-binary @<file_path> http://localhost:3000/upload
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/file/<uuid>
 /// requires the following headers:
-/// - key: the key of the user (not optional)
-/// - file_name: the name of the file (optional)
-/// - content-type: the content type of the file (optional)
-/// - download_limit: the download limit of the file (optional)
-pub async fn upload(
+/// - key: the key of the user or a token with the `delete` scope (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[utoipa::path(
+    delete,
+    path = "/file/{uuid}",
+    tag = "files",
+    params(("uuid" = String, Path, description = "The UUID of the file")),
+    responses(
+        (status = 200, description = "The deleted file", body = data::File),
+        (status = 401, description = "The key is invalid"),
+        (status = 404, description = "The file does not exist or belongs to another user"),
+    ),
+    security(("key" = []))
+)]
+pub async fn delete_file(
+    Path(uuid): Path<String>,
     Extension(pool): Extension<AnyPool>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(config): Extension<data::Config>,
-    headers: HeaderMap,
-    body: Bytes,
-) -> Response {
+    AuthUser { user, .. }: AuthUser<DeleteScope>,
+) -> Result<Response, ApiError> {
     //log the IP address of the client and the call
     let ip = addr.ip().to_string();
-    info!("Received update from IP: {}", ip);
+    info!("Received delete request for {} from IP: {}", uuid, ip);
 
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
 
-        //get the key from the headers
-    let key = match headers.get("key") {
-        Some(hv) => hv.to_str().unwrap_or("unknown").to_string(),
-        None => {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "Key header not supplied",
-            )
-                .into_response();
+    // find the file in the database
+    let file = db::find_file(&pool, &uuid).await;
+    let file = match file {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            info!("File not found in DB: {}", uuid);
+            return Err(ApiError::NotFound("File not found".to_string()));
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database select error"));
         }
     };
 
-    //check if the user exists
-    let owner = sqlx::query_as::<_, data::user>(
-        r#"
-        SELECT *
-        FROM users
-        WHERE key = ?
-        "#,
+    // only the owner is allowed to delete the file
+    if file.owner != user.username {
+        warn!("User {} tried to delete file {} owned by {}", user.username, uuid, file.owner);
+        return Err(ApiError::Forbidden("You do not own this file".to_string()));
+    }
+
+    let removed = match file.is_trashed() {
+        true => remove_stored_file(&pool, &config, &file).await,
+        false => trash_file(&pool, &config, &file).await,
+    };
+    removed?;
+    info!("File deleted by owner {}: {}", user.username, uuid);
+    webhook::emit(webhook::EventKind::Deleted, &file);
+    audit::record(&pool, audit::Action::Delete, Some(&user.username), Some(&uuid), &ip).await;
+
+    Ok(Json(file).into_response())
+}
+
+/// Handler to delete a file with its delete token
+/// This function deletes a file like its owner would, without a key,
+/// so the link returned with the upload can revoke the file from any machine.
+/// The token works once, unknown tokens count towards the lockout of the client.
+/// It returns the deleted file metadata as a JSON response.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE http://localhost:3000/download/<uuid>?token=<delete_token>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// requires the following query parameter:
+/// - token: the delete token returned with the upload (not optional)
+#[utoipa::path(
+    delete,
+    path = "/download/{uuid}",
+    tag = "files",
+    params(("uuid" = String, Path, description = "The UUID of the file"), data::DeleteTokenQuery),
+    responses(
+        (status = 200, description = "The deleted file", body = data::File),
+        (status = 403, description = "The delete token is not valid or was used already"),
+        (status = 404, description = "The file does not exist"),
+        (status = 429, description = "Too many invalid tokens were sent"),
     )
-    .bind(&key)
-    .fetch_one(&pool)
-    .await;
-    let owner = match owner {
-        Ok(user) => {
-            info!("User found in DB: {}", key);
-            user.username
+)]
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn delete_by_token(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DeleteTokenQuery>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received delete by token for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let Some(token) = query.token.as_deref().filter(|token| !token.is_empty()) else {
+        return Err(ApiError::BadRequest("The delete token is missing".to_string()));
+    };
+    if let Some(response) = lockout::check(&ip, None) {
+        return Ok(response);
+    }
+
+    let file = match db::find_file(&pool, &uuid).await {
+        Ok(Some(file)) => file,
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    match db::take_delete_token(&pool, &uuid, token).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Invalid delete token for {} from {}", uuid, ip);
+            lockout::failed(&ip, None);
+            return Err(ApiError::Forbidden("The delete token is not valid".to_string()));
         }
         Err(e) => {
-            error!("DB select error {}: {} Most likely because the Key is not valid", key, e);
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Your key is not valid",
-            )
-                .into_response();
+            error!("DB delete token error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database delete error"));
         }
+    }
+
+    let removed = match file.is_trashed() {
+        true => remove_stored_file(&pool, &config, &file).await,
+        false => trash_file(&pool, &config, &file).await,
     };
+    removed?;
+    info!("File deleted by token: {}", uuid);
+    webhook::emit(webhook::EventKind::Deleted, &file);
+    audit::record(&pool, audit::Action::Delete, None, Some(&uuid), &ip).await;
 
-    // gets the content type from the headers
-    let content_type = headers
-        .get("content-type")
-        .and_then(|hv| hv.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-    // gets the download limit from the headers
-    let download_limit = headers
-        .get("download_limit") // Option<&HeaderValue>
-        .and_then(|hv| hv.to_str().ok()) // Option<&str>
-        .and_then(|s| s.parse::<i32>().ok()) // Option<u32>
-        .unwrap_or(1); // u32
-    //get filename from the headers
-    let file_name = headers
-        .get("file_name")
-        .and_then(|hv| hv.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-    //generate a random UUID for the file ID
-    let id = {
-        // Fallback to random UUID if body is too small
-        let mut rng = rand::rng();
-        Uuid::from_u128(rng.random::<u128>()).to_string()
+    Ok(Json(file).into_response())
+}
+
+/// Handler to restore a file from the trash
+/// This function takes a file of the requesting user out of the trash,
+/// so it can be downloaded again.
+/// A file that was trashed because its download limit was reached gets its download count reset.
+/// Expired files and files the cleanup task already purged can't be restored.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" http://localhost:3000/file/<uuid>/restore
+/// requires the following headers:
+/// - key: the key of the user or a token with the `delete` scope (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn restore_file(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser<DeleteScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received restore request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+
+    let file = match find_file_any(&pool, &uuid).await {
+        Ok(Some(file)) if file.owner == user.username => file,
+        Ok(Some(file)) => {
+            warn!("User {} tried to restore file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(response) => return Err(response.into()),
     };
-    //create the directory if it doesn't exist
-    let dir = PathBuf::new(&config.data_path);
-    if let Err(e) = fs::create_dir_all(dir).await {
-        warn!("could not make dir at {} error: {}", &config.data_path, e);
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Directory creation error",
-        )
-            .into_response();
+    if !file.is_trashed() {
+        return Err(ApiError::Conflict("File is not in the trash".to_string()));
+    }
+    if file.is_expired() {
+        return Err(gone_because(&file, "expired", "File expired", file.download_count));
     }
-    //create the file path
-    // the file path is the directory + the file ID + file type if file type is not application/x-executable
-    info!("File type is {}", content_type);
-    let file_path = dir.join(&id);
 
-    if let Err(e) = fs::write(&file_path, &body).await {
-        warn!("write error {}: {}", id, e);
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "File write error",
-        )
-            .into_response();
+    match db::restore_file(&pool, &uuid).await {
+        Ok(restored) => {
+            cache::forget_file(&uuid).await;
+            changes::record(&pool, &restored.owner, &uuid, changes::Change::Updated).await;
+            info!("File restored by owner {}: {}", user.username, uuid);
+            audit::record(&pool, audit::Action::Restore, Some(&user.username), Some(&uuid), &ip).await;
+            Ok(Json(restored).into_response())
+        }
+        Err(e) => {
+            error!("DB restore error {}: {}", uuid, e);
+            Err(db::error(&e, "Database update error"))
+        }
     }
-    let file_size = body.len() as i64;
+}
 
-    let upload_time = Utc::now().timestamp(); // i64
+/// Handler to give a file to another user
+/// This function makes another user the owner of a file,
+/// for example when somebody leaves a team.
+/// The file leaves the collections of its previous owner, its links and share tokens keep working.
+/// Only the owner of the file or an admin can transfer it.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"to": "<username>"}' http://localhost:3000/file/<uuid>/transfer
+/// requires the following headers:
+/// - key: the key of the owner or of an admin (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// requires the following JSON body:
+/// - to: the username of the new owner (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn transfer_file(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+    Json(request): Json<data::TransferRequest>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received transfer request for {} from IP: {}", uuid, ip);
 
-    let download_count = 0;
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
 
-    let download_url = match config.use_tls {
-        true => format!("https://{}/download/{}", config.base_url, id),
-        false => format!("http://{}/download/{}", config.base_url, id),
+    let file = match find_file_any(&pool, &uuid).await {
+        Ok(Some(file)) if file.owner == user.username || user.is_admin() => file,
+        Ok(Some(file)) => {
+            warn!("User {} tried to transfer file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(response) => return Err(response.into()),
     };
 
-
-    if let Err(e) = sqlx::query(
-        r#"
-        INSERT INTO files
-            (id, content_type, upload_time, download_limit, download_count, file_size, download_url, file_name, owner)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-        "#,
-    )
-    .bind(&id)
-    .bind(&content_type)
-    .bind(&upload_time)
-    .bind(download_limit)
-    .bind(download_count)
-    .bind(file_size as i64)
-    .bind(&download_url)
-    .bind(&file_name)
-    .bind(&owner)
-    .execute(&pool)
-    .await
-    {
-        error!("DB insert error {}: {}", id, e);
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Database insert error",
-        )
-            .into_response();
+    let to = request.to.trim();
+    match db::user_exists(&pool, to).await {
+        Ok(false) => return Err(ApiError::NotFound("User not found".to_string())),
+        Ok(true) => {}
+        Err(e) => {
+            error!("DB select user error {}: {}", to, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    }
+    if to == file.owner {
+        return Err(ApiError::Conflict("The user already owns this file".to_string()));
     }
 
-
-    let uploaded_file = data::File {
-        id,
-        file_name,
-        content_type,
-        upload_time,
-        download_limit,
-        download_count,
-        file_size,
-        download_url,
-        owner,
-    };
-    Json(uploaded_file).into_response()
+    match db::transfer_file(&pool, &uuid, to).await {
+        Ok(transferred) => {
+            cache::forget_file(&uuid).await;
+            changes::record(&pool, &file.owner, &uuid, changes::Change::Deleted).await;
+            changes::record(&pool, &transferred.owner, &uuid, changes::Change::Created).await;
+            info!("File {} transferred from {} to {} by {}", uuid, file.owner, to, user.username);
+            let target = format!("{} from {} to {}", uuid, file.owner, to);
+            audit::record(&pool, audit::Action::Transfer, Some(&user.username), Some(&target), &ip).await;
+            Ok(Json(transferred).into_response())
+        }
+        Err(e) => {
+            error!("DB transfer error {}: {}", uuid, e);
+            Err(db::error(&e, "Database update error"))
+        }
+    }
 }
 
-/// This is The file Download handler
-/// This function handles the file download process.
-/// It retrieves the file metadata from the database
-/// and returns the file as a response.
+/// Handler to change the description of a file
+/// This function sets the note shown with the file in listings and on its share page,
+/// a null or empty description removes it.
+/// Only the owner of the file can change it.
 /// It also logs the IP address of the client making the request.
-/// example request: curl -X GET http://localhost:3000/download/<uuid>
+/// example request: curl -X PATCH -H "key: <key>" -H "content-type: application/json" -d '{"description": "nightly build of main"}' http://localhost:3000/file/<uuid>
+/// requires the following headers:
+/// - key: the key of the owner (not optional)
+///
 /// requires the following path parameter:
 /// - uuid: the UUID of the file (not optional)
-pub async fn download_file(
-    Path(uuid): Path<String>, // Add this extractor
+///
+/// accepts the following JSON body:
+/// - description: a note of up to 1000 characters (optional)
+#[utoipa::path(
+    patch,
+    path = "/file/{uuid}",
+    tag = "files",
+    params(("uuid" = String, Path, description = "The UUID of the file")),
+    request_body = data::FileUpdate,
+    responses(
+        (status = 200, description = "The changed file", body = data::File),
+        (status = 400, description = "The description is invalid"),
+        (status = 401, description = "The key is invalid"),
+        (status = 403, description = "The file belongs to another user"),
+        (status = 404, description = "The file doesn't exist"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn update_file(
+    Path(uuid): Path<String>,
     Extension(pool): Extension<AnyPool>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Extension(config): Extension<data::Config>,
-    // Remove body: Bytes,         // <-- GET handler shouldn't have a body
-) -> Response {
-
-    // Get UUID directly from path
-    info!("Download request for UUID: {}", uuid);
-    // Log the IP address of the client and the call
+    AuthUser { user, .. }: AuthUser,
+    Json(request): Json<data::FileUpdate>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
     let ip = addr.ip().to_string();
-    info!("Received download request for {} from IP: {}", uuid, ip);
+    info!("Received file update for {} from IP: {}", uuid, ip);
 
-    // find file by uuid in the config.data_path
-    let file_path = PathBuf::new(&config.data_path).join(&uuid);
-
-    if !file_path.exists() {
-        error!("File not found: {}", file_path.display());
-        return (
-            axum::http::StatusCode::NOT_FOUND,
-            "File not found",
-        )
-            .into_response();
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
     }
-    // Check if the file exists in the database
-    let file = sqlx::query_as::<_, data::File>(
-        r#"
-        SELECT *
-        FROM files
-        WHERE id = ?
-        "#,
-    )
-    .bind(&uuid)
-    .fetch_one(&pool)
-    .await;
-    let file = match file {
-        Ok(file) => {
-            info!("File found in DB: {}", uuid);
-            file
+    let file = match find_file_any(&pool, &uuid).await {
+        Ok(Some(file)) if file.owner == user.username => file,
+        Ok(Some(file)) => {
+            warn!("User {} tried to update file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(response) => return Err(response.into()),
+    };
+
+    let Some(description) = request.description else {
+        return Ok(Json(file).into_response());
+    };
+    let description = parse_description(description.as_deref()).map_err(ApiError::BadRequest)?;
+    match db::set_description(&pool, &uuid, description.as_deref()).await {
+        Ok(updated) => {
+            cache::forget_file(&uuid).await;
+            changes::record(&pool, &updated.owner, &uuid, changes::Change::Updated).await;
+            info!("Description of {} changed by {}", uuid, user.username);
+            audit::record(&pool, audit::Action::UpdateFile, Some(&user.username), Some(&uuid), &ip).await;
+            Ok(Json(updated).into_response())
         }
         Err(e) => {
-            error!("DB select error {}: {}", uuid, e);
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Database select error",
-            )
-                .into_response();
+            error!("DB update error {}: {}", uuid, e);
+            Err(db::error(&e, "Database update error"))
         }
-    };
+    }
+}
 
-    //update download count
-    if let Err(e) = sqlx::query(
-        r#"
-        UPDATE files
-        SET download_count = download_count + 1
-        WHERE id = ?
-        "#,
-    )
-    .bind(&uuid)
-    .execute(&pool)
+/// Helper to look up a file by its ID, including expired and trashed files.
+pub(crate) async fn find_file_any(pool: &AnyPool, uuid: &str) -> Result<Option<data::File>, Response> {
+    db::find_file(pool, uuid).await.map_err(|e| {
+            error!("DB select error {}: {}", uuid, e);
+            db::error_response(&e, "Database select error")
+        })
+}
 
-    .await
-    {
-        error!("DB update error {}: {}", uuid, e);
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Database update error",
-        )
-            .into_response();
+/// Helper to move a file to the trash
+/// This function marks the file as deleted, it can't be downloaded anymore
+/// but keeps its blob until the cleanup task purges it.
+/// With a `trash_retention` of 0 the file is removed right away instead.
+pub(crate) async fn trash_file(
+    pool: &AnyPool,
+    config: &data::Config,
+    file: &data::File,
+) -> Result<(), Response> {
+    if config.trash_retention == 0 {
+        return remove_stored_file(pool, config, file).await;
     }
-    info!("Update Download Count Sucess for UUID: {}", uuid);
+    if let Err(e) = db::trash_file(pool, &file.id).await {
+        error!("DB trash error {}: {}", file.id, e);
+        return Err(db::error_response(&e, "Database update error"));
+    }
+    cache::forget_file(&file.id).await;
+    changes::record(pool, &file.owner, &file.id, changes::Change::Updated).await;
+    events::publish(events::Event::FileDeleted {
+        id: file.id.clone(),
+        blob: file.blob_name().to_string(),
+    });
+    Ok(())
+}
 
-    //rutn file to axum::body::Bytes
-    let file_bytes = match fs::read(&file_path).await {
-        Ok(file) => file,
+/// Helper to remove a stored file
+/// This function deletes the metadata row of a file from the database
+/// and then drops its reference to the blob on the server's file system.
+/// The row is removed first so the file can not be downloaded anymore,
+/// the blob is only removed once no other file shares it.
+/// The blobs of earlier versions of the file are released too.
+pub(crate) async fn remove_stored_file(
+    pool: &AnyPool,
+    config: &data::Config,
+    file: &data::File,
+) -> Result<(), Response> {
+    // the row tells which blob is current, `file` may be an earlier version
+    let removed = match db::delete_file(pool, &file.id).await {
+        Ok(removed) => {
+            cache::forget_file(&file.id).await;
+            // a concurrent removal already recorded the deletion
+            if let Some(row) = &removed {
+                changes::record(pool, &row.owner, &row.id, changes::Change::Deleted).await;
+            }
+            events::publish(events::Event::FileDeleted {
+                id: file.id.clone(),
+                blob: file.blob_name().to_string(),
+            });
+            removed
+        }
         Err(e) => {
-            error!("File read error {}: {}", uuid, e);
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "File read error",
-            )
-                .into_response();
-        }
-    };
-
-    //if download count is greater or equal to download limit delete the file and remove it from the database
-    if (file.download_count) >= file.download_limit {
-        if let Err(e) = fs::remove_file(&file_path).await {
-            error!("File delete error {}: {}", uuid, e);
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "File delete error",
-            )
-                .into_response();
-        }
-        if let Err(e) = sqlx::query(
-            r#"
-            DELETE FROM files
-            WHERE id = ?
-            "#,
-        )
-        .bind(&uuid)
-        .execute(&pool)
-        .await
-        {
-            error!("DB delete error {}: {}", uuid, e);
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Database delete error",
-            )
-                .into_response();
-        }
-        info!("File deleted from DB because max download limit was reached: {}", uuid);
-    }
-
-    // return the file as a response
-    return (
-        axum::http::StatusCode::OK,
-        axum::response::IntoResponse::into_response(
-            axum::response::Response::builder()
-                .header("Content-Disposition", format!("attachment; filename=\"{}\"", uuid))
-                .header("Content-Type", format!("{}", &file.content_type ))
-                .header("Content-Length", file.file_size)
-                .header("filename", file.file_name)
-                .body(axum::body::Body::from(file_bytes))
-                .unwrap(),
-        ),
-    )
-        .into_response()
+            error!("DB delete error {}: {}", file.id, e);
+            return Err(db::error_response(&e, "Database delete error"));
+        }
+    };
+    let earlier = db::delete_versions(pool, &file.id).await.unwrap_or_else(|e| {
+        warn!("DB delete versions error {}: {}", file.id, e);
+        Vec::new()
+    });
+
+    // the file disappears from every collection it was in, its tags, statistics, share and delete tokens and signature with it
+    for table in db::FILE_TABLES {
+        if let Err(e) = db::delete_file_rows(pool, table, &file.id).await {
+            warn!("DB delete {} error {}: {}", table, file.id, e);
+        }
+    }
+
+    // remove the blobs from disk if this was their last reference
+    // another request that removed the row first also releases its blob
+    if let Some(removed) = &removed {
+        storage::release_blob(pool, config, removed).await;
+        for version in &earlier {
+            storage::release_blob(pool, config, &version.apply(removed)).await;
+        }
+    }
+    Ok(())
 }
 
-/// Handler to upload a file
+/// Handler to register a user
 /// This function registers a new user.
-/// It receives the user data in the request headers,
+/// It receives the user data in the request headers or as a JSON body,
 /// saves it to the database,
 /// and returns the user data as a JSON response.
+/// If `require_email_verification` is set, a confirmation link is sent to the email address
+/// and the account can't upload until the link was opened.
+/// Usernames outside of the username policy and email addresses outside of `allowed_email_domains`
+/// are refused with 422, `details.reason` tells which username rule was broken.
 /// It also logs the IP address of the client making the request.
-///  example request: curl -X POST -H "username: <username>" -H "password: <password>" http://localhost:3000/register
-///  requires the following headers:
+///  example request: curl -X POST -H "username: <username>" -H "password: <password>" -H "email: <email>" http://localhost:3000/register
+///  example JSON request: curl -X POST -H "content-type: application/json" -d '{"username": "<username>", "password": "<password>"}' http://localhost:3000/register
+///  requires the following headers or JSON fields:
 ///  - username: the username of the user (not optional)
 ///  - password: the password of the user (not optional)
-pub async fn register_user (
+///  - email: the email address of the user (optional, not optional if verification is required)
+///  - captcha: the solved challenge from `/user/register/challenge` (optional, not optional if `captcha` is configured)
+#[utoipa::path(
+    post,
+    path = "/user/register",
+    tag = "users",
+    params(
+        ("username" = String, Header, description = "The username of the user"),
+        ("password" = String, Header, description = "The password of the user"),
+        ("email" = Option<String>, Header, description = "The email address of the user"),
+        ("captcha" = Option<String>, Header, description = "The solved challenge from `/user/register/challenge`"),
+    ),
+    request_body(content = Option<data::Credentials>, description = "The credentials as JSON instead of headers", content_type = "application/json"),
+    responses(
+        (status = 200, description = "The registered user and their key", body = data::RegisteredUser),
+        (status = 400, description = "The username is taken or a header is invalid"),
+        (status = 403, description = "Registration is disabled"),
+        (status = 422, description = "The username or the domain of the email address is not allowed"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn register_user(
     Extension(pool): Extension<AnyPool>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(config): Extension<data::Config>,
-    headers: HeaderMap,
-    body: Bytes,
-) -> Response {
+    credentials: data::Credentials,
+) -> Result<Response, ApiError> {
     //log the IP address of the client and the call
     let ip = addr.ip().to_string();
     info!("Received update from IP: {}", ip);
 
     //check if registration is allowed
     if !config.allow_register {
-        return (
-            axum::http::StatusCode::FORBIDDEN,
-            "Registration is not allowed",
-        )
-            .into_response();
+        return Err(ApiError::Forbidden("Registration is not allowed".to_string()));
     }
 
-    // gets the content type from the headers return error if header is not suplyde
-    let username = match headers .get("username") {
-        Some(hv) => hv.to_str().unwrap_or("unknown").to_string(),
-        None => {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "Username header not supplied",
-            )
-                .into_response();
-        }
+    // keep bots out of public instances
+    captcha::check(&config, credentials.captcha.as_deref(), &ip).await?;
+
+    // gets the username from the credentials return error if it is not suplyde
+    let Some(username) = credentials.username else {
+        return Err(ApiError::BadRequest("Username not supplied".to_string()));
     };
-    let password = match headers .get("password") {
-        Some(hv) => hv.to_str().unwrap_or("unknown").to_string(),
-        None => {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "Password header not supplied",
-            )
-                .into_response();
+    // anonymous uploads are owned by the empty username
+    if username.trim().is_empty() {
+        return Err(ApiError::BadRequest("Username must not be empty".to_string()));
+    }
+    // the length, characters and reserved names an operator allows
+    registration::check_username(&config, &username)?;
+    let Some(password) = credentials.password else {
+        return Err(ApiError::BadRequest("Password not supplied".to_string()));
+    };
+
+    let email = credentials.email.as_deref().map(str::trim);
+    let mailbox = match email {
+        Some(email) => match email.parse::<Mailbox>() {
+            Ok(mailbox) => Some(mailbox),
+            Err(e) => {
+                warn!("Invalid email {}: {}", email, e);
+                return Err(ApiError::BadRequest("Invalid email address".to_string()));
+            }
+        },
+        None if config.require_email_verification => {
+            return Err(ApiError::BadRequest("Email not supplied".to_string()));
         }
+        None => None,
+    };
+    if let Some(mailbox) = &mailbox {
+        registration::check_email_domain(&config, mailbox)?;
+    }
+    // the account stays unverified until the link in the email was opened
+    let verification_token = match (&mailbox, config.require_email_verification) {
+        (Some(_), true) => Some(hex::encode(rand::rng().random::<[u8; 32]>())),
+        _ => None,
     };
 
     //generate a random UUID for the user key
@@ -423,58 +3026,119 @@ pub async fn register_user (
     };
 
     // check if the user already exists
-    let user = sqlx::query_as::<_, data::user>(
-        r#"
-        SELECT *
-        FROM users
-        WHERE username = ?
-        "#,
-    )
-    .bind(&username)
-    .fetch_one(&pool)
-    .await;
-    match user {
-        Ok(_) => {
+    match db::user_exists(&pool, &username).await {
+        Ok(true) => {
             info!("User already exists: {}", username);
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "User already exists",
-            )
-                .into_response();
+            return Err(ApiError::BadRequest("User already exists".to_string()));
         }
+        Ok(false) => {}
         Err(e) => {
             warn!("DB select error {}: {}", username, e);
         }
     }
 
     //add the user to the database
-    if let Err(e) = sqlx::query(
-        r#"
-        INSERT INTO users
-            (key, username, password)
-        VALUES (?, ?, ?)
-        "#,
-    )
-    .bind(&key)
-    .bind(&username)
-    .bind(&password)
-    .execute(&pool)
-    .await
-    {
-        error!("DB insert error {}: {}", key, e);
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Database insert error",
-        )
-            .into_response();
+    let user = db::NewUser {
+        key: &key,
+        username: &username,
+        password: &password,
+        // admins are only made through the command line or `admin_users`, never by signing up
+        is_admin: false,
+        email,
+        verification_token: verification_token.as_deref(),
+    };
+    if let Err(e) = db::insert_user(&pool, &user).await {
+        error!("DB insert error {}: {}", username, e);
+        return Err(db::error(&e, "Database insert error"));
+    }
+    if let (Some(mailbox), Some(token)) = (mailbox, &verification_token) {
+        if let Err(e) = email::send_verification(&config, &username, mailbox, token).await {
+            error!("Verification email to {} failed: {}", username, e);
+            // drop the account again so the user can retry with the same name
+            if let Err(e) = db::delete_user_by_key(&pool, &key).await {
+                error!("DB delete error {}: {}", username, e);
+            }
+            return Err(ApiError::Rejected {
+                status: StatusCode::BAD_GATEWAY,
+                error: "email_failed",
+                message: "Could not send the verification email".to_string(),
+                details: Value::Null,
+            });
+        }
     }
     info!("User registered: {}", username);
+    audit::record(&pool, audit::Action::Register, Some(&username), None, &ip).await;
 
     //return the user as a response
-    let registered_user = json!({
-        "key": key,
-        "username": username,
-    });
-    Json(registered_user)
-        .into_response()
+    let registered_user = data::RegisteredUser {
+        key,
+        username,
+        email_verified: verification_token.is_none(),
+    };
+    Ok(Json(registered_user)
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{byte_range, content_disposition};
+
+    #[test]
+    fn reads_byte_ranges() {
+        assert_eq!(byte_range("bytes=0-99", 1000), Some(0..100));
+        assert_eq!(byte_range("bytes=500-", 1000), Some(500..1000));
+        assert_eq!(byte_range("bytes=-100", 1000), Some(900..1000));
+        assert_eq!(byte_range(" bytes=10 - 19 ", 1000), Some(10..20));
+    }
+
+    #[test]
+    fn clamps_byte_ranges_to_the_file() {
+        assert_eq!(byte_range("bytes=900-1999", 1000), Some(900..1000));
+        assert_eq!(byte_range("bytes=-2000", 1000), Some(0..1000));
+        assert_eq!(byte_range("bytes=0-18446744073709551615", 1000), Some(0..1000));
+    }
+
+    #[test]
+    fn refuses_byte_ranges_that_cant_be_satisfied() {
+        for value in ["bytes=1000-", "bytes=50-10", "bytes=-0", "bytes=0-0,5-9", "items=0-9", "bytes=a-9", "bytes=5"] {
+            assert_eq!(byte_range(value, 1000), None, "{} should be refused", value);
+        }
+        assert_eq!(byte_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn offers_a_file_under_its_name() {
+        assert_eq!(
+            content_disposition("attachment", "notes.txt", "id"),
+            "attachment; filename=\"notes.txt\"; filename*=UTF-8''notes.txt"
+        );
+    }
+
+    #[test]
+    fn encodes_names_outside_of_ascii() {
+        assert_eq!(
+            content_disposition("inline", "grüße €.txt", "id"),
+            "inline; filename=\"gr__e _.txt\"; filename*=UTF-8''gr%C3%BC%C3%9Fe%20%E2%82%AC.txt"
+        );
+    }
+
+    #[test]
+    fn strips_paths_quotes_and_control_characters() {
+        assert_eq!(
+            content_disposition("attachment", "../../etc/pass\"wd\r\n", "id"),
+            "attachment; filename=\"passwd\"; filename*=UTF-8''passwd"
+        );
+        assert_eq!(
+            content_disposition("attachment", "C:\\Users\\.hidden", "id"),
+            "attachment; filename=\"hidden\"; filename*=UTF-8''hidden"
+        );
+    }
+
+    #[test]
+    fn falls_back_when_nothing_of_the_name_is_left() {
+        assert_eq!(
+            content_disposition("attachment", "dir/", "a1b2"),
+            "attachment; filename=\"a1b2\"; filename*=UTF-8''a1b2"
+        );
+    }
 }
@@ -0,0 +1,321 @@
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sqlx::AnyPool;
+use std::collections::HashSet;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::collections;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::ids;
+use crate::share;
+use crate::storage;
+use crate::throttle;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// The maximum number of files that can be bundled into one archive.
+const MAX_ARCHIVE_FILES: usize = 1000;
+
+/// The size of the pipe buffer between the ZIP writer and the response body.
+const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Handler to download several files as one ZIP archive
+/// This function bundles the requested files into a ZIP archive
+/// that is built on the fly and streamed to the client.
+/// Files owned by the requester are included without counting a download,
+/// every other file counts against its download limit like a normal download
+/// and is left out if its limit is already reached.
+/// Private files can only be bundled by their owner, or with a collection token of a collection they are in.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"files": ["<uuid>", "<uuid>"]}' http://localhost:3000/download/zip
+/// requires the following headers:
+/// - key: the key of the user (optional, owned files are not counted as downloads)
+///
+/// requires the following JSON body:
+/// - files: the UUIDs of the files to bundle (not optional)
+/// - token: a collection token (optional)
+#[instrument(skip_all)]
+pub async fn download_zip(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    Json(request): Json<data::ArchiveRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!(
+        "Received zip download request for {} files from IP: {}",
+        request.files.len(),
+        ip
+    );
+
+    if request.files.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No files requested").into_response();
+    }
+    if request.files.len() > MAX_ARCHIVE_FILES {
+        return (StatusCode::BAD_REQUEST, "Too many files requested").into_response();
+    }
+
+    // the key is optional, anonymous requests can still bundle public files
+    let user = if share::has_credentials(&headers) {
+        match api::authenticate(&pool, &headers, &ip).await {
+            Ok(user) => Some(user),
+            Err(response) => return response,
+        }
+    } else {
+        None
+    };
+    let token = match &request.token {
+        Some(token) => match tokens::find_collection_token(&pool, token).await {
+            Ok(Some(token)) => Some(token),
+            Ok(None) => {
+                warn!("Invalid collection token in an archive request from {}", ip);
+                return (StatusCode::UNAUTHORIZED, "Your token is not valid").into_response();
+            }
+            Err(e) => {
+                error!("DB select token error: {}", e);
+                return db::error_response(&e, "Database select error");
+            }
+        },
+        None => None,
+    };
+
+    // look up every requested file before counting any download
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    for uuid in &request.files {
+        if !ids::is_valid_file_id(uuid) {
+            return ids::invalid_file_id(uuid).into_response();
+        }
+        if !seen.insert(uuid) {
+            continue;
+        }
+        let file = sqlx::query_as::<_, data::File>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE id = ?
+            "#,
+            data::File::COLUMNS
+        ))
+        .bind(uuid)
+        .fetch_optional(&pool)
+        .await;
+        let file = match file {
+            Ok(Some(file)) if file.is_private() && user.as_ref().is_none_or(|user| user.username != file.owner) => {
+                match in_collection(&pool, token.as_ref(), &file).await {
+                    Ok(true) => Ok(Some(file)),
+                    Ok(false) => {
+                        warn!("Private file {} requested in an archive from {}", uuid, ip);
+                        return (StatusCode::FORBIDDEN, format!("File is private: {}", uuid)).into_response();
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            file => file,
+        };
+        match file {
+            Ok(Some(file)) if file.is_available() => files.push(file),
+            Ok(_) => {
+                return (StatusCode::NOT_FOUND, format!("File not found: {}", uuid)).into_response();
+            }
+            Err(e) => {
+                error!("DB select error {}: {}", uuid, e);
+                return db::error_response(&e, "Database select error");
+            }
+        }
+    }
+
+    // count the downloads of files the requester doesn't own,
+    // they are given back for files that don't make it into the archive
+    let mut entries = Vec::new();
+    for file in files {
+        let owned = user.as_ref().is_some_and(|user| user.username == file.owner);
+        let pending = match owned {
+            true => None,
+            false => match api::claim_download(&pool, &config, &file).await {
+                Ok(true) => {
+                    let username = user.as_ref().map(|user| user.username.as_str());
+                    Some(api::PendingDownload::new(&pool, &config, &file, username, &ip, &headers))
+                }
+                Ok(false) => continue,
+                Err(response) => return response,
+            },
+        };
+        entries.push((file, pending));
+    }
+    if entries.is_empty() {
+        return (StatusCode::GONE, "Download limit reached for all files").into_response();
+    }
+
+    // the archive is written into one end of a pipe while the other end is streamed out
+    let (reader, writer) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let mut zip = ZipFileWriter::with_tokio(writer);
+        let mut names = HashSet::new();
+        for (file, pending) in entries {
+            let data = match storage::read_blob(&config, file.blob_name(), file.file_size.max(0) as u64).await {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("File read error {}: {}", file.id, e);
+                    continue;
+                }
+            };
+            let name = unique_entry_name(&mut names, &file.file_name, &file.id);
+            let entry = ZipEntryBuilder::new(name.into(), Compression::Stored);
+            if let Err(e) = zip.write_entry_whole(entry, &data).await {
+                // the client most likely went away
+                warn!("ZIP write error {}: {}", file.id, e);
+                return;
+            }
+            if let Some(pending) = pending {
+                pending.finish().await;
+            }
+        }
+        if let Err(e) = zip.close().await {
+            warn!("ZIP close error: {}", e);
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"bitbeam.zip\""),
+        ],
+        throttle::stream_body(ReaderStream::new(reader)),
+    )
+        .into_response()
+}
+
+/// Helper to check whether a collection token grants a private file
+/// It returns true if the file is in the collection of the token and owned by its user.
+async fn in_collection(pool: &AnyPool, token: Option<&data::ApiToken>, file: &data::File) -> Result<bool, sqlx::Error> {
+    match token.and_then(|token| token.collection_id.as_deref().filter(|_| token.username == file.owner)) {
+        Some(collection_id) => collections::contains(pool, collection_id, &file.id).await,
+        None => Ok(false),
+    }
+}
+
+/// Handler to download every file of the user as one ZIP archive
+/// This function bundles all files the user has stored, including the ones in the trash,
+/// into a ZIP archive that is built on the fly and streamed to the client.
+/// The files are put in the `files/` directory of the archive and a `manifest.json`
+/// with the metadata and tags of every file is added at the end.
+/// Downloading the archive doesn't count as a download of any file,
+/// so it can be used as a personal backup before deleting the account.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -H "key: <key>" -o backup.zip http://localhost:3000/my_files/archive
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn my_files_archive(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received my_files archive request from IP: {}", ip);
+
+    let user = api::authenticate(&pool, &headers, &ip).await?;
+    let files = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE owner = ?
+        ORDER BY upload_time
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(&user.username)
+    .fetch_all(&pool)
+    .await;
+    let files = match files {
+        Ok(mut files) => api::attach_tags(&pool, &mut files).await.map(|()| files),
+        Err(e) => Err(e),
+    };
+    let files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            error!("DB select error {}: {}", user.username, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    info!("Bundling {} files of {}", files.len(), user.username);
+
+    // the archive is written into one end of a pipe while the other end is streamed out
+    let (reader, writer) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let mut zip = ZipFileWriter::with_tokio(writer);
+        let mut names = HashSet::new();
+        // the manifest only lists the files that made it into the archive
+        let mut manifest = Vec::with_capacity(files.len());
+        for file in files {
+            let data = match storage::read_blob(&config, file.blob_name(), file.file_size.max(0) as u64).await {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("File read error {}: {}", file.id, e);
+                    continue;
+                }
+            };
+            let path = format!("files/{}", unique_entry_name(&mut names, &file.file_name, &file.id));
+            let entry = ZipEntryBuilder::new(path.clone().into(), Compression::Stored);
+            if let Err(e) = zip.write_entry_whole(entry, &data).await {
+                // the client most likely went away
+                warn!("ZIP write error {}: {}", file.id, e);
+                return;
+            }
+            manifest.push(data::ManifestEntry { path, file });
+        }
+        let manifest = match serde_json::to_vec_pretty(&manifest) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("Manifest serialize error: {}", e);
+                return;
+            }
+        };
+        let entry = ZipEntryBuilder::new("manifest.json".to_string().into(), Compression::Deflate);
+        if let Err(e) = zip.write_entry_whole(entry, &manifest).await {
+            warn!("ZIP write error manifest.json: {}", e);
+            return;
+        }
+        if let Err(e) = zip.close().await {
+            warn!("ZIP close error: {}", e);
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"bitbeam-files.zip\""),
+        ],
+        throttle::stream_body(ReaderStream::new(reader)),
+    )
+        .into_response())
+}
+
+/// Helper to pick a unique, path free name for an archive entry.
+/// Directory separators are replaced so entries can't escape the extraction directory,
+/// and duplicate names get the file ID appended.
+fn unique_entry_name(names: &mut HashSet<String>, file_name: &str, id: &str) -> String {
+    let mut name = file_name.replace(['/', '\\'], "_");
+    if name.is_empty() || name == "." || name == ".." || name == "unknown" {
+        name = id.to_string();
+    }
+    if !names.insert(name.clone()) {
+        name = format!("{}-{}", id, name);
+        names.insert(name.clone());
+    }
+    name
+}
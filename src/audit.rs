@@ -0,0 +1,392 @@
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use std::sync::OnceLock;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::data;
+use crate::db;
+
+/// Serializes the entries this process adds to the hash chain,
+/// other processes sharing the database are caught by the unique index on `seq`.
+static CHAIN: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// How often an entry is recorded again after another process took its sequence number.
+const APPEND_ATTEMPTS: usize = 3;
+
+/// The number of failed logins that can wait to be recorded before further ones are dropped.
+const QUEUE_SIZE: usize = 4096;
+
+/// The queue failed logins are sent to, it is set once the writer is started.
+static QUEUE: OnceLock<mpsc::Sender<FailedLogin>> = OnceLock::new();
+
+/// This struct represents a failed login waiting to be recorded.
+struct FailedLogin {
+    time: i64,
+    username: Option<String>,
+    target: Option<String>,
+    ip: String,
+}
+
+impl FailedLogin {
+    /// Records the failed login in the audit log.
+    async fn record(self, pool: &AnyPool) {
+        let (username, target) = (self.username.as_deref(), self.target.as_deref());
+        record_at(pool, self.time, Action::LoginFailed, username, target, &self.ip).await;
+    }
+}
+
+/// The security relevant actions that end up in the audit log.
+#[derive(Clone, Copy)]
+pub enum Action {
+    Register,
+    Login,
+    Logout,
+    LoginFailed,
+    VerifyEmail,
+    SetWebhook,
+    SetNotifications,
+    SetPgpKey,
+    UpdateProfile,
+    DeleteAccount,
+    CreateToken,
+    RevokeToken,
+    CreateKey,
+    RevokeKey,
+    PresignUpload,
+    Upload,
+    UploadVersion,
+    UploadSignature,
+    Download,
+    Delete,
+    Restore,
+    Transfer,
+    UpdateFile,
+    Publish,
+    Unpublish,
+    Pin,
+    Unpin,
+    Report,
+    AdminDeleteUser,
+    AdminDeleteFile,
+    AdminSetUploadLimit,
+    AdminGc,
+    AdminRunJob,
+    AdminDismissReport,
+    AdminTakedown,
+    AdminExport,
+    AdminImport,
+    AdminMaintenance,
+    AdminReload,
+    AdminLogLevel,
+}
+
+impl Action {
+    /// Returns the name of the action as stored in the `audit_log` table.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Register => "user.register",
+            Action::Login => "user.login",
+            Action::Logout => "user.logout",
+            Action::LoginFailed => "user.login_failed",
+            Action::VerifyEmail => "user.verify_email",
+            Action::SetWebhook => "user.set_webhook",
+            Action::SetNotifications => "user.set_notifications",
+            Action::SetPgpKey => "user.set_pgp_key",
+            Action::UpdateProfile => "user.update_profile",
+            Action::DeleteAccount => "user.delete_account",
+            Action::CreateToken => "user.create_token",
+            Action::RevokeToken => "user.revoke_token",
+            Action::CreateKey => "user.create_key",
+            Action::RevokeKey => "user.revoke_key",
+            Action::PresignUpload => "user.presign_upload",
+            Action::Upload => "file.upload",
+            Action::UploadVersion => "file.upload_version",
+            Action::UploadSignature => "file.upload_signature",
+            Action::Download => "file.download",
+            Action::Delete => "file.delete",
+            Action::Restore => "file.restore",
+            Action::Transfer => "file.transfer",
+            Action::UpdateFile => "file.update",
+            Action::Publish => "file.publish",
+            Action::Unpublish => "file.unpublish",
+            Action::Pin => "file.pin",
+            Action::Unpin => "file.unpin",
+            Action::Report => "file.report",
+            Action::AdminDeleteUser => "admin.delete_user",
+            Action::AdminDeleteFile => "admin.delete_file",
+            Action::AdminSetUploadLimit => "admin.set_max_upload_bytes",
+            Action::AdminGc => "admin.gc",
+            Action::AdminRunJob => "admin.run_job",
+            Action::AdminDismissReport => "admin.dismiss_report",
+            Action::AdminTakedown => "admin.takedown",
+            Action::AdminExport => "admin.export",
+            Action::AdminImport => "admin.import",
+            Action::AdminMaintenance => "admin.maintenance",
+            Action::AdminReload => "admin.reload",
+            Action::AdminLogLevel => "admin.log_level",
+        }
+    }
+}
+
+/// This function records an action in the audit log.
+/// `username` is the user who acted, `None` for anonymous requests,
+/// and `target` is what the action was done to, like a file ID or a username.
+/// Every entry is chained to the one before it, see `verify`.
+/// A failing insert is only logged, auditing never fails the request itself.
+pub async fn record(
+    pool: &AnyPool,
+    action: Action,
+    username: Option<&str>,
+    target: Option<&str>,
+    ip: &str,
+) {
+    record_at(pool, Utc::now().timestamp(), action, username, target, ip).await;
+}
+
+/// This function starts the writer that records failed logins in the background, see `record_failed_login`.
+pub fn start(pool: AnyPool) {
+    let (sender, mut receiver) = mpsc::channel::<FailedLogin>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        warn!("Audit log writer already started");
+        return;
+    }
+    tokio::spawn(async move {
+        while let Some(failed) = receiver.recv().await {
+            failed.record(&pool).await;
+        }
+    });
+}
+
+/// This function records a failed login in the audit log without waiting for it.
+/// Failed logins come in bursts when keys are guessed, so they are written one after another
+/// by the writer started with `start` instead of holding up the requests that fail.
+/// Without the writer, like in tests, every entry is recorded by a task of its own.
+/// A failed login that does not fit in the queue is only logged.
+pub fn record_failed_login(pool: &AnyPool, username: Option<&str>, target: Option<&str>, ip: &str) {
+    let failed = FailedLogin {
+        time: Utc::now().timestamp(),
+        username: username.map(str::to_string),
+        target: target.map(str::to_string),
+        ip: ip.to_string(),
+    };
+    let Some(queue) = QUEUE.get() else {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            failed.record(&pool).await;
+        });
+        return;
+    };
+    if let Err(e) = queue.try_send(failed) {
+        warn!("Could not record {} from {} in the audit log: {}", Action::LoginFailed.as_str(), ip, e);
+    }
+}
+
+/// Helper to record an action that happened at `time`, see `record`.
+async fn record_at(
+    pool: &AnyPool,
+    time: i64,
+    action: Action,
+    username: Option<&str>,
+    target: Option<&str>,
+    ip: &str,
+) {
+    let salt = hex::encode(rand::rng().random::<[u8; 16]>());
+    let digest = digest(&salt, username, target, ip);
+    let _chain = CHAIN.lock().await;
+    for attempt in 1..=APPEND_ATTEMPTS {
+        match append(pool, time, action, username, target, ip, &salt, &digest).await {
+            Ok(()) => return,
+            Err(e) if attempt < APPEND_ATTEMPTS => {
+                debug!("Recording {} in the audit log again: {}", action.as_str(), e);
+            }
+            Err(e) => warn!("Could not record {} in the audit log: {}", action.as_str(), e),
+        }
+    }
+}
+
+/// Helper to add an entry after the newest one of the hash chain.
+#[allow(clippy::too_many_arguments)]
+async fn append(
+    pool: &AnyPool,
+    time: i64,
+    action: Action,
+    username: Option<&str>,
+    target: Option<&str>,
+    ip: &str,
+    salt: &str,
+    digest: &str,
+) -> Result<(), sqlx::Error> {
+    let newest = sqlx::query_as::<_, (i64, db::Text)>(
+        "SELECT seq, entry_hash FROM audit_log WHERE seq IS NOT NULL ORDER BY seq DESC LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?;
+    let (seq, previous) = match newest {
+        Some((seq, hash)) => (seq + 1, hash.into()),
+        None => (1, String::new()),
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log
+            (time, username, action, target, ip, seq, salt, digest, entry_hash)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(time)
+    .bind(username)
+    .bind(action.as_str())
+    .bind(target)
+    .bind(ip)
+    .bind(seq)
+    .bind(salt)
+    .bind(digest)
+    .bind(chain_hash(&previous, seq, time, action.as_str(), digest))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the digest of who did an action to what and from where.
+/// The chain covers the digest instead of the fields, so `anonymize` can clear them
+/// without breaking it, and the random salt keeps the digest from being guessed once they are gone.
+fn digest(salt: &str, username: Option<&str>, target: Option<&str>, ip: &str) -> String {
+    let fields = serde_json::json!([salt, username, target, ip]).to_string();
+    hex::encode(Sha256::digest(fields.as_bytes()))
+}
+
+/// Returns the hash of an entry, it covers the hash of the entry before it.
+fn chain_hash(previous: &str, seq: i64, time: i64, action: &str, digest: &str) -> String {
+    let fields = serde_json::json!([previous, seq, time, action, digest]).to_string();
+    hex::encode(Sha256::digest(fields.as_bytes()))
+}
+
+/// This struct represents an entry of the audit log with its place in the hash chain.
+#[derive(sqlx::FromRow)]
+struct ChainedEntry {
+    time: i64,
+    username: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    action: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    target: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    ip: String,
+    seq: i64,
+    #[sqlx(try_from = "db::OptionalText")]
+    salt: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    digest: String,
+    #[sqlx(try_from = "db::Text")]
+    entry_hash: String,
+}
+
+/// This function checks the hash chain of the audit log from its first entry to the newest one.
+/// It finds entries that were changed, removed from the middle of the log or added around the chain.
+/// Entries removed from the end are only found by comparing `head` with a hash kept elsewhere.
+/// Anonymized entries only prove their time and action, their username, target and IP are gone.
+pub async fn verify(pool: &AnyPool) -> Result<data::AuditVerification, sqlx::Error> {
+    // entries recorded before the chain existed have no sequence number
+    let unchained = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM audit_log WHERE seq IS NULL")
+        .fetch_one(pool)
+        .await?;
+    let mut verification = data::AuditVerification {
+        valid: true,
+        entries: 0,
+        anonymized: 0,
+        unchained,
+        head: None,
+        head_seq: None,
+        broken_at: None,
+        problem: None,
+    };
+    let mut entries = sqlx::query_as::<_, ChainedEntry>(
+        r#"
+        SELECT time, username, action, target, ip, seq, salt, digest, entry_hash
+        FROM audit_log
+        WHERE seq IS NOT NULL
+        ORDER BY seq
+        "#,
+    )
+    .fetch(pool);
+    let mut started = None;
+    let mut previous = String::new();
+    while let Some(entry) = entries.try_next().await? {
+        let expected = verification.head_seq.unwrap_or_default() + 1;
+        let problem = if entry.seq == expected + 1 {
+            Some(format!("entry {} is missing", expected))
+        } else if entry.seq != expected {
+            Some(format!("entries {} to {} are missing", expected, entry.seq - 1))
+        } else if entry.entry_hash != chain_hash(&previous, entry.seq, entry.time, &entry.action, &entry.digest) {
+            Some("the entry was changed or does not follow the one before it".to_string())
+        } else {
+            match &entry.salt {
+                Some(salt) => (entry.digest != digest(salt, entry.username.as_deref(), entry.target.as_deref(), &entry.ip))
+                    .then(|| "the username, target or IP of the entry was changed".to_string()),
+                None => {
+                    verification.anonymized += 1;
+                    None
+                }
+            }
+        };
+        if let Some(problem) = problem {
+            verification.valid = false;
+            verification.broken_at = Some(expected);
+            verification.problem = Some(problem);
+            return Ok(verification);
+        }
+        started.get_or_insert(entry.time);
+        previous = entry.entry_hash;
+        verification.entries += 1;
+        verification.head_seq = Some(entry.seq);
+        verification.head = Some(previous.clone());
+    }
+    drop(entries);
+
+    // entries recorded after the chain started must be part of it
+    if let Some(started) = started {
+        let added = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM audit_log WHERE seq IS NULL AND time > ?")
+            .bind(started)
+            .fetch_one(pool)
+            .await?;
+        if added > 0 {
+            verification.valid = false;
+            verification.problem = Some(format!("{} entries outside of the chain were added after it started", added));
+        }
+    }
+    Ok(verification)
+}
+
+/// This function removes a user from the audit log when they delete their account.
+/// The entries are kept so the log stays complete, but their username and IP address are cleared
+/// and entries targeting the username lose their target.
+/// Their salt goes too, the hash chain still holds but the cleared fields can't be checked any more.
+/// It returns the number of entries that were changed.
+pub async fn anonymize(pool: &AnyPool, username: &str) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let acted = sqlx::query(
+        r#"
+        UPDATE audit_log
+        SET username = NULL, ip = '', salt = NULL
+        WHERE username = ?
+        "#,
+    )
+    .bind(username)
+    .execute(&mut *transaction)
+    .await?;
+    let targeted = sqlx::query(
+        r#"
+        UPDATE audit_log
+        SET target = NULL, salt = NULL
+        WHERE target = ?
+        "#,
+    )
+    .bind(username)
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(acted.rows_affected() + targeted.rows_affected())
+}
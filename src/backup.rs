@@ -0,0 +1,350 @@
+use async_zip::{tokio::read::seek::ZipFileReader, tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use sqlx::AnyPool;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::io::BufReader;
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::api;
+use crate::changes;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::storage;
+use std::net::SocketAddr;
+
+/// The format of the dumps this version writes and reads.
+const DUMP_VERSION: u32 = 1;
+
+/// Returns true if the dump was written in a format this version can import.
+pub(crate) fn is_supported(dump: &data::Dump) -> bool {
+    dump.version == DUMP_VERSION
+}
+
+/// This function reads every user and every file, trashed ones included, into a dump.
+pub(crate) async fn export(pool: &AnyPool) -> Result<data::Dump, sqlx::Error> {
+    let users = sqlx::query_as::<_, data::User>("SELECT * FROM users ORDER BY username")
+        .fetch_all(pool)
+        .await?;
+    let mut files = sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files ORDER BY upload_time", data::File::COLUMNS))
+        .fetch_all(pool)
+        .await?;
+    api::attach_tags(pool, &mut files).await?;
+    Ok(data::Dump {
+        version: DUMP_VERSION,
+        exported: Utc::now().timestamp(),
+        users,
+        files,
+    })
+}
+
+/// This function inserts the users and files of a dump in one transaction.
+/// Users whose key or name is taken and files whose ID or slug is taken are skipped,
+/// so a dump can be imported again without duplicating anything.
+/// Files whose content type or cache control is no valid header value are rejected,
+/// they could never be downloaded.
+/// Download URLs are rebuilt for the `base_url` of this server.
+/// The blobs of the files must be copied into the data path separately, see `import_blobs`.
+pub(crate) async fn import(
+    pool: &AnyPool,
+    config: &data::Config,
+    dump: &data::Dump,
+) -> Result<data::ImportSummary, sqlx::Error> {
+    let mut summary = data::ImportSummary::default();
+    let mut transaction = pool.begin().await?;
+
+    for user in &dump.users {
+        let taken = sqlx::query_scalar::<_, i64>(r#"SELECT COUNT(*) FROM users WHERE "key" = ? OR username = ?"#)
+            .bind(&user.key)
+            .bind(&user.username)
+            .fetch_one(&mut *transaction)
+            .await?;
+        if taken > 0 {
+            info!("User {} already exists, skipping", user.username);
+            summary.users_skipped += 1;
+            continue;
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO users
+                ("key", username, password, is_admin, webhook_url, max_upload_bytes, email, email_verified, storage_quota, pgp_key,
+                 display_email, default_download_limit, default_expiry, removal_notices, default_visibility)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&user.key)
+        .bind(&user.username)
+        .bind(&user.password)
+        .bind(user.is_admin)
+        .bind(&user.webhook_url)
+        .bind(user.max_upload_bytes)
+        .bind(&user.email)
+        .bind(user.email_verified)
+        .bind(user.storage_quota)
+        .bind(&user.pgp_key)
+        .bind(&user.display_email)
+        .bind(user.default_download_limit)
+        .bind(user.default_expiry)
+        .bind(user.removal_notices)
+        .bind(&user.default_visibility)
+        .execute(&mut *transaction)
+        .await?;
+        db::insert_api_key(&mut transaction, &user.username, &user.key, Some(db::DEFAULT_KEY_NAME)).await?;
+        summary.users_imported += 1;
+    }
+
+    let mut skipped = HashSet::new();
+    for file in &dump.files {
+        let headers = [Some(&file.content_type), file.cache_control.as_ref(), file.detected_content_type.as_ref()];
+        if let Some(invalid) = headers.into_iter().flatten().find(|value| HeaderValue::from_str(value).is_err()) {
+            warn!("File {} has the invalid header value {:?}, rejecting it", file.id, invalid);
+            summary.files_rejected += 1;
+            skipped.insert(file.id.as_str());
+            continue;
+        }
+        let taken = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM files WHERE id = ? OR slug = ?")
+            .bind(&file.id)
+            .bind(&file.slug)
+            .fetch_one(&mut *transaction)
+            .await?;
+        if taken > 0 {
+            info!("File {} already exists, skipping", file.id);
+            summary.files_skipped += 1;
+            skipped.insert(file.id.as_str());
+            continue;
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO files
+                (id, content_type, upload_time, download_limit, download_count, file_size, download_url, file_name, owner, content_hash, slug, encrypted, detected_content_type, visibility, expires, deleted_at, version, cid, expire_if_unused_days, last_downloaded_at, cache_control, published, description)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&file.id)
+        .bind(&file.content_type)
+        .bind(file.upload_time)
+        .bind(file.download_limit)
+        .bind(file.download_count)
+        .bind(file.file_size)
+        .bind(api::download_url(config, &file.id, file.slug.as_deref()))
+        .bind(&file.file_name)
+        .bind(&file.owner)
+        .bind(&file.content_hash)
+        .bind(&file.slug)
+        .bind(file.encrypted)
+        .bind(&file.detected_content_type)
+        .bind(&file.visibility)
+        .bind(file.expires)
+        .bind(file.deleted_at)
+        .bind(file.version)
+        .bind(&file.cid)
+        .bind(file.expire_if_unused_days)
+        .bind(file.last_downloaded_at)
+        .bind(&file.cache_control)
+        .bind(file.published)
+        .bind(&file.description)
+        .execute(&mut *transaction)
+        .await?;
+        for tag in &file.tags {
+            sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?, ?)")
+                .bind(&file.id)
+                .bind(tag)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        if !storage::blob_exists(config, file.blob_name()).await {
+            warn!("Blob {} of imported file {} is missing", file.blob_name(), file.id);
+            summary.missing_blobs += 1;
+        }
+        summary.files_imported += 1;
+    }
+
+    transaction.commit().await?;
+    for file in dump.files.iter().filter(|file| !skipped.contains(file.id.as_str())) {
+        changes::record(pool, &file.owner, &file.id, changes::Change::Created).await;
+    }
+    Ok(summary)
+}
+
+/// This function writes the blobs of the given files into a ZIP archive, one entry per blob.
+/// Blobs are written decrypted, an importing server encrypts them with its own master key.
+/// Missing blobs are left out with a warning.
+/// It returns the number of blobs written.
+pub(crate) async fn export_blobs(config: &data::Config, files: &[data::File], path: &Path) -> std::io::Result<usize> {
+    let mut zip = ZipFileWriter::with_tokio(tokio::fs::File::create(path).await?);
+    let mut written = HashSet::new();
+    for file in files {
+        let name = file.blob_name();
+        if written.contains(name) {
+            continue;
+        }
+        let data = match storage::read_blob(config, name, file.file_size.max(0) as u64).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Could not read blob {} of {}: {}", name, file.id, e);
+                continue;
+            }
+        };
+        let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Stored);
+        zip.write_entry_whole(entry, &data).await.map_err(std::io::Error::other)?;
+        written.insert(name);
+    }
+    zip.close().await.map_err(std::io::Error::other)?;
+    Ok(written.len())
+}
+
+/// This function stores every blob of a ZIP archive written by `export_blobs` in the data path,
+/// or of a directory laid out like the data path, see `import_blob_directory`.
+/// Blobs that are already stored are kept, entries that are not named like a blob are skipped.
+/// It returns the number of blobs read from the archive.
+pub(crate) async fn import_blobs(config: &data::Config, path: &Path) -> std::io::Result<usize> {
+    if tokio::fs::metadata(path).await?.is_dir() {
+        return import_blob_directory(config, path).await;
+    }
+    let file = BufReader::new(tokio::fs::File::open(path).await?);
+    let mut zip = ZipFileReader::with_tokio(file).await.map_err(std::io::Error::other)?;
+    let mut imported = 0;
+    for index in 0..zip.file().entries().len() {
+        let name = match zip.file().entries()[index].filename().as_str() {
+            Ok(name) if storage::is_blob_name(name) => name.to_string(),
+            _ => {
+                warn!("Skipping archive entry {} that is not a blob", index);
+                continue;
+            }
+        };
+        // the checked read also verifies the size and CRC of the entry
+        let mut data = Vec::new();
+        zip.reader_with_entry(index)
+            .await
+            .map_err(std::io::Error::other)?
+            .read_to_end_checked(&mut data)
+            .await
+            .map_err(std::io::Error::other)?;
+        storage::write_blob(config, &name, &data).await?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// This function stores every blob below a directory laid out like the data path, like the data path of the old server.
+/// The blobs are taken as they are stored, so they must not be encrypted or be encrypted with the same master key,
+/// and they must be laid out by this version, see `storage::check_layout`.
+/// They are reflinked or hard linked where the file system allows and copied otherwise, see `storage::link_blob`,
+/// so a large import on the same file system takes neither time nor space.
+/// It returns the number of blobs found.
+async fn import_blob_directory(config: &data::Config, path: &Path) -> std::io::Result<usize> {
+    let root = path.to_string_lossy();
+    storage::check_layout(&root).await?;
+    let blobs = storage::list_blobs_below(&root).await?;
+    let mut linked = HashMap::new();
+    for (name, _) in &blobs {
+        let source = storage::checked_path_below(&root, name).await?;
+        if let Some(how) = storage::link_blob(config, &source, name).await? {
+            *linked.entry(how).or_insert(0) += 1;
+        }
+    }
+    info!(
+        "Imported {} blobs from {}: {} reflinked, {} hard linked, {} copied, the others were stored already",
+        blobs.len(),
+        path.display(),
+        linked.get(&storage::Linked::Reflink).unwrap_or(&0),
+        linked.get(&storage::Linked::HardLink).unwrap_or(&0),
+        linked.get(&storage::Linked::Copy).unwrap_or(&0),
+    );
+    Ok(blobs.len())
+}
+
+/// Handler to export the metadata of the server
+/// This function returns every user and every file as a JSON dump,
+/// that `/admin/import` or `bitbeam import` read on another server.
+/// The dump contains the keys and passwords of all users.
+/// Blobs are not included, copy the data path or use `bitbeam export --blobs`.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/export -o dump.json
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn export_metadata(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin export request from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match export(&pool).await {
+        Ok(dump) => {
+            warn!("Metadata exported by admin {}", admin.username);
+            audit::record(&pool, audit::Action::AdminExport, Some(&admin.username), None, &ip).await;
+            Json(dump).into_response()
+        }
+        Err(e) => {
+            error!("DB export error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to import a metadata dump
+/// This function adds the users and files of a dump written by `/admin/export` or `bitbeam export`.
+/// Users and files that already exist are skipped, files with a content type or cache control
+/// that can't be sent as a header are rejected.
+/// The blobs must already be in the data path, the response counts the files whose blob is missing.
+/// example request: curl -X POST -H "key: <admin key>" -H "content-type: application/json" --data-binary @dump.json http://localhost:3000/admin/import
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following JSON body:
+/// - the dump (not optional)
+#[instrument(skip_all)]
+pub async fn import_metadata(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    Json(dump): Json<data::Dump>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin import request from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+    if !is_supported(&dump) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported dump version {}, expected {}", dump.version, DUMP_VERSION),
+        )
+            .into_response();
+    }
+
+    match import(&pool, &config, &dump).await {
+        Ok(summary) => {
+            warn!(
+                "Imported {} users and {} files by admin {}",
+                summary.users_imported, summary.files_imported, admin.username
+            );
+            audit::record(&pool, audit::Action::AdminImport, Some(&admin.username), None, &ip).await;
+            Json(summary).into_response()
+        }
+        Err(e) => {
+            error!("DB import error: {}", e);
+            db::error_response(&e, "Database insert error")
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Multipart, Query, Request},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http_body_util::Limited;
+use sqlx::AnyPool;
+use tracing::{info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::share;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// The maximum number of files in one batch.
+const MAX_BATCH_FILES: usize = 1000;
+
+/// The size of a tar header and the block size of tar archives.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Handler to upload several files at once
+/// This function accepts a multipart/form-data form with any number of file parts,
+/// or a tar archive with `content-type: application/x-tar`,
+/// stores every file like a normal upload in a single database transaction
+/// and returns a JSON array with the metadata of the stored files.
+/// If one file is rejected, none of them is stored.
+/// The upload size limit of the user applies to the whole batch.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -F "file=@<file_path>" -F "file=@<file_path>" http://localhost:3000/upload/batch
+/// example tar request: curl -X POST -H "key: <key>" -H "content-type: application/x-tar" --data-binary @<archive.tar> http://localhost:3000/upload/batch
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - content-type: `multipart/form-data` or `application/x-tar` (not optional)
+/// - download_limit: the download limit of every file, 0 or `unlimited` for unlimited (optional, can also be a multipart field)
+/// - expires_at, max_age: when every file expires, like for `/upload` (optional)
+/// - cache: the `Cache-Control` header of every file, like for `/upload` (optional)
+/// - publish: `true` to list every file in the public directory, like for `/upload` (optional)
+/// - tags: a comma separated list of tags for every file (optional, can also be a multipart field)
+/// - visibility: `public` or `private` for every file (optional, can also be a multipart field)
+///
+/// accepts the following query parameters:
+/// - format: `txt` to get only the download URLs back, one per line (optional)
+#[instrument(skip_all)]
+pub async fn upload_batch(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received batch upload from IP: {}", ip);
+
+    let (user, token) = match tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    if !user.is_verified() {
+        return api::unverified(&user).into_response();
+    }
+
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return api::too_large(limit).into_response();
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+
+    // the options apply to every file of the batch
+    let expires_at = headers.get("expires_at").and_then(|hv| hv.to_str().ok());
+    let max_age = headers.get("max_age").and_then(|hv| hv.to_str().ok());
+    let expires = match api::upload_expires(&user, expires_at, max_age) {
+        Ok(expires) => expires,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let cache_control = match headers.get("cache").and_then(|hv| hv.to_str().ok()).map(api::parse_cache_control) {
+        Some(Some(cache_control)) => Some(cache_control),
+        Some(None) => return api::invalid_cache_control().into_response(),
+        None => None,
+    };
+    let visibility = api::default_visibility(&user);
+    let mut template = data::NewFile {
+        file_name: "unknown".to_string(),
+        content_type: "application/octet-stream".to_string(),
+        download_limit: headers
+            .get("download_limit")
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(api::parse_download_limit)
+            .unwrap_or_else(|| api::default_download_limit(&config, &user)),
+        owner: user.username,
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility,
+        allowed_content_types: tokens::allowed_content_types(token.as_ref()),
+        expires,
+        expire_if_unused_days: None,
+        cache_control,
+        published: headers
+            .get("publish")
+            .and_then(|hv| hv.to_str().ok())
+            .is_some_and(|s| s.trim().eq_ignore_ascii_case("true")),
+        collection_id: tokens::collection(token.as_ref()),
+        description: None,
+    };
+    if let Some(tags) = headers.get("tags").and_then(|hv| hv.to_str().ok()) {
+        match api::parse_tags(tags) {
+            Some(tags) => template.tags = tags,
+            None => return api::invalid_tags().into_response(),
+        }
+    }
+    if let Some(visibility) = headers.get("visibility").and_then(|hv| hv.to_str().ok()) {
+        match share::parse_visibility(visibility) {
+            Some(visibility) => template.visibility = visibility,
+            None => return share::invalid_visibility(),
+        }
+    }
+
+    let content_type = headers
+        .get("content-type")
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let entries = if content_type.starts_with("multipart/form-data") {
+        let multipart = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart,
+            Err(e) => {
+                warn!("Multipart parse error: {}", e);
+                return e.into_response();
+            }
+        };
+        read_multipart(multipart, &mut template).await
+    } else if content_type.starts_with("application/x-tar") {
+        match Bytes::from_request(request, &()).await {
+            Ok(body) => read_tar(&body).ok_or_else(|| {
+                (StatusCode::BAD_REQUEST, "Malformed tar archive").into_response()
+            }),
+            Err(e) => Err(e.into_response()),
+        }
+    } else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Send multipart/form-data or application/x-tar",
+        )
+            .into_response();
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(response) if response.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return api::too_large(limit).into_response()
+        }
+        Err(response) => return response,
+    };
+    if entries.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No files in batch").into_response();
+    }
+    if entries.len() > MAX_BATCH_FILES {
+        return (StatusCode::BAD_REQUEST, "Too many files in batch").into_response();
+    }
+
+    let uploads = entries
+        .into_iter()
+        .map(|(file_name, content_type, body)| {
+            let new_file = data::NewFile {
+                file_name,
+                content_type,
+                ..template.clone()
+            };
+            (new_file, body)
+        })
+        .collect();
+    let uploaded = match api::store_files(&pool, &config, &ip, uploads).await {
+        Ok(uploaded) => uploaded,
+        Err(response) => return response,
+    };
+    info!("Stored batch of {} files for {}", uploaded.len(), template.owner);
+    for uploaded in &uploaded {
+        let file = &uploaded.file;
+        audit::record(&pool, audit::Action::Upload, Some(&file.owner), Some(&file.id), &ip).await;
+    }
+
+    if query.format.as_deref() == Some("txt") {
+        let urls: String = uploaded
+            .iter()
+            .map(|uploaded| format!("{}\n", uploaded.file.download_url))
+            .collect();
+        ([("content-type", "text/plain; charset=utf-8")], urls).into_response()
+    } else {
+        Json(uploaded).into_response()
+    }
+}
+
+/// Helper to read the files of a multipart/form-data batch
+/// This function returns the name, content type and contents of every part that carries a file name.
+/// Text fields named `download_limit`, `tags` and `visibility` override the options in `template`.
+async fn read_multipart(
+    mut multipart: Multipart,
+    template: &mut data::NewFile,
+) -> Result<Vec<(String, String, Bytes)>, Response> {
+    let mut entries = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Multipart field error: {}", e);
+                return Err(e.into_response());
+            }
+        };
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            match field.name() {
+                Some("download_limit") => {
+                    if let Some(limit) = field.text().await.ok().and_then(|s| api::parse_download_limit(&s)) {
+                        template.download_limit = limit;
+                    }
+                }
+                Some("tags") => {
+                    if let Ok(value) = field.text().await {
+                        match api::parse_tags(&value) {
+                            Some(tags) => template.tags = tags,
+                            None => return Err(api::invalid_tags().into_response()),
+                        }
+                    }
+                }
+                Some("visibility") => {
+                    if let Ok(value) = field.text().await {
+                        match share::parse_visibility(&value) {
+                            Some(visibility) => template.visibility = visibility,
+                            None => return Err(share::invalid_visibility()),
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        };
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        match field.bytes().await {
+            Ok(bytes) => entries.push((file_name, content_type, bytes)),
+            Err(e) => {
+                warn!("Multipart read error: {}", e);
+                return Err(e.into_response());
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the regular files of a tar archive as name, content type and contents.
+/// The content type is left generic, the upload sniffs the real one.
+/// Directories, links and pax extension headers are skipped,
+/// GNU long names are supported.
+/// It returns `None` if the archive is malformed.
+pub(crate) fn read_tar(archive: &Bytes) -> Option<Vec<(String, String, Bytes)>> {
+    // archives are made of whole blocks, anything else was cut off or isn't a tar archive
+    if !archive.len().is_multiple_of(TAR_BLOCK_SIZE) {
+        return None;
+    }
+    let mut entries = Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    while offset + TAR_BLOCK_SIZE <= archive.len() {
+        let header = &archive[offset..offset + TAR_BLOCK_SIZE];
+        // the archive ends with empty blocks
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !tar_checksum_matches(header) {
+            return None;
+        }
+        let size = usize::try_from(parse_octal(&header[124..136])?).ok()?;
+        let start = offset + TAR_BLOCK_SIZE;
+        let end = start.checked_add(size).filter(|&end| end <= archive.len())?;
+        match header[156] {
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| tar_name(header));
+                entries.push((name, "application/octet-stream".to_string(), archive.slice(start..end)));
+            }
+            b'L' => long_name = Some(c_string(&archive[start..end])),
+            _ => {}
+        }
+        offset = start + size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+    }
+    Some(entries)
+}
+
+/// Returns the path stored in a tar header, joined with the ustar prefix if there is one.
+fn tar_name(header: &[u8]) -> String {
+    let name = c_string(&header[0..100]);
+    let prefix = match &header[257..262] == b"ustar" {
+        true => c_string(&header[345..500]),
+        false => String::new(),
+    };
+    match prefix.is_empty() {
+        true => name,
+        false => format!("{}/{}", prefix, name),
+    }
+}
+
+/// Returns true if the checksum of a tar header matches its contents.
+/// The checksum is the sum of all header bytes with the checksum field counted as spaces.
+fn tar_checksum_matches(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    parse_octal(&header[148..156]) == Some(sum)
+}
+
+/// Returns the number in a NUL or space terminated octal tar field.
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// Returns the text of a NUL terminated tar field.
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
@@ -0,0 +1,140 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument};
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::storage;
+use crate::throttle;
+use std::net::SocketAddr;
+
+/// The contents of a blob never change, so caches may keep them for a year without asking again.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The Content-Security-Policy of every blob, scripts and everything else an uploaded page could load are blocked.
+const SANDBOX: &str = "sandbox; default-src 'none'";
+
+/// Returns true if a blob name is a hex encoded SHA-256 hash.
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Handler to download a blob by its content hash
+/// This function serves the contents with the given SHA-256 hash
+/// with headers that let CDNs and browsers cache them forever,
+/// so bitBeam can be the origin of a CDN for build outputs and other assets.
+/// Only contents of a public file with unlimited downloads that is neither expired
+/// nor in the trash are served, fetching them does not count as a download.
+/// Blobs are sent as attachments with `nosniff` and a sandbox policy, so an uploaded page is never run on this origin,
+/// scripts and stylesheets included by other sites still load.
+/// It only works if `public_blobs` is turned on.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/blob/<sha256>
+/// requires the following path parameter:
+/// - sha256: the hex encoded SHA-256 hash of the contents, as in `content_hash` (not optional)
+///
+/// accepts the following headers:
+/// - if-none-match: the ETag of a cached copy, answered with 304 (optional)
+/// - range: a single byte range of the contents (optional)
+#[instrument(skip_all, fields(sha256 = %sha256))]
+pub async fn blob(
+    Path(sha256): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received blob request for {} from IP: {}", sha256, ip);
+
+    if !config.public_blobs {
+        return (StatusCode::NOT_FOUND, "Public blobs are disabled").into_response();
+    }
+    if !is_sha256(&sha256) {
+        return (StatusCode::BAD_REQUEST, "The hash must be a hex encoded SHA-256").into_response();
+    }
+    let sha256 = sha256.to_ascii_lowercase();
+
+    let files = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE content_hash = ? AND visibility = 'public' AND download_limit = 0 AND deleted_at IS NULL
+        ORDER BY upload_time DESC
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(&sha256)
+    .fetch_all(&pool)
+    .await;
+    let file = match files {
+        Ok(files) => files.into_iter().find(|file| file.is_available()),
+        Err(e) => {
+            error!("DB select blob error {}: {}", sha256, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+    let Some(file) = file else {
+        return (StatusCode::NOT_FOUND, "Blob not found").into_response();
+    };
+
+    let etag = format!("\"{}\"", sha256);
+    let cached = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag));
+    if cached {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag.as_str()), (header::CACHE_CONTROL, CACHE_CONTROL)],
+        )
+            .into_response();
+    }
+
+    let contents = match storage::read_cached_blob(&config, &sha256, file.file_size.max(0) as u64).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Blob read error {}: {}", sha256, e);
+            return (StatusCode::NOT_FOUND, "Blob not found").into_response();
+        }
+    };
+    // anybody can upload HTML or SVG, so a blob opened in the browser is saved instead of run on our origin
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, &file.content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            api::content_disposition("attachment", &file.file_name, &file.id),
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_SECURITY_POLICY, SANDBOX)
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let range = headers.get(header::RANGE).and_then(|hv| hv.to_str().ok());
+    let (response, contents) = match range.map(|value| api::byte_range(value, contents.len())) {
+        Some(Some(range)) => (
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, contents.len())),
+            contents.slice(range),
+        ),
+        Some(None) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", contents.len()))],
+                "The requested range is not satisfiable",
+            )
+                .into_response()
+        }
+        None => (response.status(StatusCode::OK), contents),
+    };
+    let response = response.header(header::CONTENT_LENGTH, contents.len());
+    api::built(response.body(throttle::body(contents)), &file.id)
+}
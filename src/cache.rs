@@ -0,0 +1,177 @@
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    AsyncCommands,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, info, warn};
+
+use crate::data;
+use crate::db;
+
+/// The Redis server and the time in seconds rows stay cached, set once at startup.
+static SETTINGS: OnceLock<Option<(String, u64)>> = OnceLock::new();
+
+/// The connection to Redis, opened on first use and reconnected by the manager.
+static CONNECTION: OnceCell<ConnectionManager> = OnceCell::const_new();
+
+/// When connecting to Redis failed last, requests skip the cache for `RETRY_DELAY` after that.
+static LAST_FAILURE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// How long requests go straight to the database after Redis could not be reached.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long connecting to Redis and every command may take,
+/// a slow cache must not make requests slower than the database would.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// This function enables the metadata cache if `redis_url` is configured.
+/// Without it every lookup goes to the database.
+pub fn init(config: &data::Config) {
+    let settings = config.redis_url.clone().map(|url| (url, config.cache_ttl));
+    if settings.is_some() {
+        info!("Caching file and user lookups in Redis for {} seconds", config.cache_ttl);
+    }
+    if SETTINGS.set(settings).is_err() {
+        warn!("Cache already initialized");
+    }
+}
+
+/// Returns the Redis connection, or `None` if the cache is disabled or Redis is unreachable.
+/// A failed connection is tried again after `RETRY_DELAY`.
+async fn connection() -> Option<ConnectionManager> {
+    let (url, _) = SETTINGS.get()?.as_ref()?;
+    if let Some(connection) = CONNECTION.get() {
+        return Some(connection.clone());
+    }
+    let failed = *LAST_FAILURE.lock().unwrap();
+    if failed.is_some_and(|failed| failed.elapsed() < RETRY_DELAY) {
+        return None;
+    }
+    let connection = CONNECTION
+        .get_or_try_init(|| async {
+            let client = redis::Client::open(url.as_str())?;
+            let config = ConnectionManagerConfig::new()
+                .set_number_of_retries(1)
+                .set_connection_timeout(TIMEOUT)
+                .set_response_timeout(TIMEOUT);
+            ConnectionManager::new_with_config(client, config).await
+        })
+        .await;
+    match connection {
+        Ok(connection) => Some(connection.clone()),
+        Err(e) => {
+            warn!("Could not connect to Redis, using the database for {:?}: {}", RETRY_DELAY, e);
+            *LAST_FAILURE.lock().unwrap() = Some(Instant::now());
+            None
+        }
+    }
+}
+
+/// Helper to read a cached value, a miss or any Redis error returns `None`.
+async fn get<T: DeserializeOwned>(key: &str) -> Option<T> {
+    let mut connection = connection().await?;
+    match connection.get::<_, Option<String>>(key).await {
+        Ok(Some(json)) => serde_json::from_str(&json).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Redis get error {}: {}", key, e);
+            None
+        }
+    }
+}
+
+/// Helper to cache a value for `cache_ttl` seconds.
+async fn set<T: Serialize>(key: &str, value: &T) {
+    let (Some(mut connection), Some(Some((_, ttl)))) = (connection().await, SETTINGS.get()) else {
+        return;
+    };
+    let Ok(json) = serde_json::to_string(value) else {
+        return;
+    };
+    if let Err(e) = connection.set_ex::<_, _, ()>(key, json, *ttl).await {
+        warn!("Redis set error {}: {}", key, e);
+    }
+}
+
+/// Helper to drop a cached value.
+async fn forget(key: &str) {
+    let Some(mut connection) = connection().await else {
+        return;
+    };
+    if let Err(e) = connection.del::<_, ()>(key).await {
+        warn!("Redis delete error {}: {}", key, e);
+    }
+}
+
+/// Returns the cache key of a `files` row.
+fn file_key(id: &str) -> String {
+    format!("bitbeam:file:{}", id)
+}
+
+/// Returns the cache key of the user with an API key.
+/// The key is hashed, so it is neither stored in Redis nor logged with Redis errors.
+fn user_key(key: &str) -> String {
+    format!("bitbeam:user:{}", hex::encode(Sha256::digest(key.as_bytes())))
+}
+
+/// This function looks up a `files` row by its ID, from the cache if possible.
+/// The download count of a cached row can lag behind by up to `cache_ttl`,
+/// download limits are always enforced by the database.
+/// Files that don't exist are not cached.
+pub(crate) async fn file(pool: &AnyPool, id: &str) -> Result<Option<data::File>, sqlx::Error> {
+    let key = file_key(id);
+    if let Some(file) = get::<data::File>(&key).await {
+        debug!("File {} served from the cache", id);
+        return Ok(Some(file));
+    }
+    let file = db::find_file(pool, id).await?;
+    if let Some(file) = &file {
+        set(&key, file).await;
+    }
+    Ok(file)
+}
+
+/// Drops the cached row of a file, call it after the row was changed or deleted.
+pub(crate) async fn forget_file(id: &str) {
+    forget(&file_key(id)).await;
+}
+
+/// This function looks up the user with an API key, from the cache if possible.
+/// Unknown keys are not cached.
+pub(crate) async fn user_by_key(pool: &AnyPool, key: &str) -> Result<Option<data::User>, sqlx::Error> {
+    let cache_key = user_key(key);
+    if let Some(user) = get::<data::User>(&cache_key).await {
+        return Ok(Some(user));
+    }
+    let user = db::find_user_by_key(pool, key).await?;
+    if let Some(user) = &user {
+        set(&cache_key, user).await;
+    }
+    Ok(user)
+}
+
+/// Drops the cached user with an API key.
+pub(crate) async fn forget_key(key: &str) {
+    forget(&user_key(key)).await;
+}
+
+/// Drops the cached user with the given name under every key of the user,
+/// call it after the `users` row was changed or deleted.
+pub(crate) async fn forget_user(pool: &AnyPool, username: &str) {
+    if connection().await.is_none() {
+        return;
+    }
+    match db::active_keys(pool, username).await {
+        Ok(keys) => {
+            for key in keys {
+                forget_key(&key).await;
+            }
+        }
+        Err(e) => warn!("DB select keys error {}: {}", username, e),
+    }
+}
@@ -0,0 +1,92 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::storage;
+
+/// The bytes the data path took up on disk when it was last measured, with the unix timestamp of the measurement.
+static DISK_USAGE: Mutex<Option<(u64, i64)>> = Mutex::new(None);
+
+/// Returns the bytes the files of every user take up according to the `files` table.
+pub(crate) async fn file_bytes(pool: &AnyPool) -> Result<u64, sqlx::Error> {
+    let total = sqlx::query_scalar::<_, i64>(&format!("SELECT {} FROM files", db::sum(pool, "file_size")))
+        .fetch_one(pool)
+        .await?;
+    Ok(u64::try_from(total).unwrap_or_default())
+}
+
+/// Returns the bytes the data path took up on disk at the last check and when it was measured,
+/// `None` before the first check finished.
+pub(crate) fn disk_usage() -> Option<(u64, i64)> {
+    *DISK_USAGE.lock().unwrap()
+}
+
+/// Returns the bytes counted against `max_store_bytes`.
+/// The `files` table is exact for new uploads but misses old versions, thumbnails and orphaned blobs,
+/// the disk misses nothing but is only measured now and then, so the larger of both counts.
+pub(crate) fn stored_bytes(file_bytes: u64) -> u64 {
+    disk_usage().map_or(file_bytes, |(disk_bytes, _)| disk_bytes.max(file_bytes))
+}
+
+/// Returns true if the stored bytes passed `store_alert_percent` of `max_store_bytes`.
+pub(crate) fn alert(config: &data::Config, stored_bytes: u64) -> bool {
+    config.max_store_bytes > 0
+        && stored_bytes.saturating_mul(100) >= config.max_store_bytes.saturating_mul(config.store_alert_percent)
+}
+
+/// Helper to check that storing `additional` more bytes keeps the instance within `max_store_bytes`.
+/// Unlike the storage quota this applies to every upload, anonymous ones included,
+/// and is answered with 507 Insufficient Storage.
+pub(crate) async fn check(pool: &AnyPool, config: &data::Config, additional: i64) -> Result<(), Response> {
+    if config.max_store_bytes == 0 || additional <= 0 {
+        return Ok(());
+    }
+    let file_bytes = match file_bytes(pool).await {
+        Ok(file_bytes) => file_bytes,
+        Err(e) => {
+            error!("DB select stored bytes error: {}", e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    let stored = stored_bytes(file_bytes);
+    if stored.saturating_add(additional as u64) <= config.max_store_bytes {
+        return Ok(());
+    }
+    warn!("Upload of {} bytes refused, the store holds {} of {} bytes", additional, stored, config.max_store_bytes);
+    Err(ApiError::Rejected {
+        status: StatusCode::INSUFFICIENT_STORAGE,
+        error: "store_full",
+        message: "The server is out of storage space".to_string(),
+        details: json!({ "max_store_bytes": config.max_store_bytes, "stored_bytes": stored }),
+    }
+    .into_response())
+}
+
+/// This function measures the bytes the data path takes up on disk
+/// and compares them with the `files` table.
+/// It warns once the store passes `store_alert_percent` of `max_store_bytes`,
+/// the measurement shows up in the admin statistics.
+pub async fn verify(pool: &AnyPool, config: &data::Config) -> Result<(), String> {
+    let disk_bytes = storage::disk_usage(config).await.map_err(|e| e.to_string())?;
+    let file_bytes = file_bytes(pool).await.map_err(|e| e.to_string())?;
+    *DISK_USAGE.lock().unwrap() = Some((disk_bytes, Utc::now().timestamp()));
+    info!("The data path takes up {} bytes, the files table counts {} bytes", disk_bytes, file_bytes);
+
+    let stored = disk_bytes.max(file_bytes);
+    if alert(config, stored) {
+        warn!(
+            "The store holds {} of {} bytes, past the alert at {}%",
+            stored, config.max_store_bytes, config.store_alert_percent
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::data;
+
+/// How long a proof of work challenge can be solved, in seconds.
+const CHALLENGE_LIFETIME: i64 = 10 * 60;
+
+/// How long the captcha provider may take to answer.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The key challenges are signed with, a restart only invalidates pending challenges.
+static SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// The challenges that were already used together with their expiry time,
+/// so a solved challenge can't register more than one account.
+static USED: Mutex<BTreeMap<String, i64>> = Mutex::new(BTreeMap::new());
+
+/// This struct is the part of the captcha provider's answer that is checked.
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+/// Returns a MAC keyed with the challenge secret.
+fn mac() -> Hmac<Sha256> {
+    let secret = SECRET.get_or_init(|| rand::rng().random());
+    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+/// Returns the hex encoded signature of a challenge.
+fn sign(payload: &str) -> String {
+    let mut mac = mac();
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Returns the number of leading zero bits of a hash.
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Handler to get the registration challenge
+/// This function tells clients which challenge `/user/register` expects.
+/// For `pow` it returns a fresh challenge and the number of leading zero bits
+/// the SHA-256 of `<challenge>:<nonce>` needs, for `hcaptcha` and `turnstile` the site key of the widget.
+/// example request: curl http://localhost:3000/user/register/challenge
+#[instrument(skip_all)]
+pub async fn challenge(Extension(config): Extension<data::Config>) -> Response {
+    match config.captcha.as_str() {
+        "pow" => {
+            let expires = Utc::now().timestamp() + CHALLENGE_LIFETIME;
+            let payload = format!("{}.{}", expires, hex::encode(rand::rng().random::<[u8; 16]>()));
+            Json(json!({
+                "type": "pow",
+                "challenge": format!("{}.{}", payload, sign(&payload)),
+                "difficulty": config.pow_difficulty,
+                "expires": expires,
+            }))
+            .into_response()
+        }
+        "hcaptcha" | "turnstile" => Json(json!({
+            "type": config.captcha,
+            "site_key": config.captcha_site_key,
+        }))
+        .into_response(),
+        _ => Json(json!({ "type": "none" })).into_response(),
+    }
+}
+
+/// Helper to check the registration challenge of a request
+/// This function checks the `captcha` header or JSON field against the configured challenge.
+/// For `hcaptcha` and `turnstile` it carries the token of the widget,
+/// for `pow` the challenge and the nonce that solves it separated by a colon.
+/// It returns a ready-made error response if the challenge is missing or not solved.
+pub(crate) async fn check(config: &data::Config, answer: Option<&str>, ip: &str) -> Result<(), Response> {
+    if config.captcha == "none" {
+        return Ok(());
+    }
+    let Some(answer) = answer else {
+        return Err((StatusCode::BAD_REQUEST, "Captcha not supplied").into_response());
+    };
+    let solved = match config.captcha.as_str() {
+        "pow" => check_pow(answer, config.pow_difficulty),
+        provider => site_verify(config, provider, answer, ip).await?,
+    };
+    if !solved {
+        warn!("Failed registration challenge from {}", ip);
+        return Err((StatusCode::FORBIDDEN, "Captcha verification failed").into_response());
+    }
+    Ok(())
+}
+
+/// Returns true if `<challenge>:<nonce>` is a valid, unused and solved proof of work.
+/// A valid answer marks the challenge as used.
+fn check_pow(answer: &str, difficulty: u32) -> bool {
+    let Some((challenge, _nonce)) = answer.rsplit_once(':') else {
+        return false;
+    };
+    let Some((payload, signature)) = challenge.rsplit_once('.') else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = mac();
+    mac.update(payload.as_bytes());
+    if mac.verify_slice(&signature).is_err() {
+        return false;
+    }
+    let now = Utc::now().timestamp();
+    let expires = payload
+        .split_once('.')
+        .and_then(|(expires, _)| expires.parse::<i64>().ok())
+        .unwrap_or_default();
+    if expires <= now || leading_zero_bits(&Sha256::digest(answer.as_bytes())) < difficulty {
+        return false;
+    }
+    let mut used = USED.lock().unwrap_or_else(|e| e.into_inner());
+    used.retain(|_, expires| *expires > now);
+    used.insert(challenge.to_string(), expires).is_none()
+}
+
+/// Helper to verify a captcha token with hCaptcha or Cloudflare Turnstile.
+/// It returns whether the provider accepted the token,
+/// or a ready-made error response if the provider could not be asked.
+async fn site_verify(config: &data::Config, provider: &str, token: &str, ip: &str) -> Result<bool, Response> {
+    let url = match provider {
+        "hcaptcha" => "https://api.hcaptcha.com/siteverify",
+        _ => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+    };
+    let secret = config.captcha_secret.as_deref().unwrap_or_default();
+    let answer = reqwest::Client::new()
+        .post(url)
+        .timeout(VERIFY_TIMEOUT)
+        .form(&[("secret", secret), ("response", token), ("remoteip", ip)])
+        .send()
+        .await;
+    let body = match answer {
+        Ok(answer) => answer.bytes().await,
+        Err(e) => Err(e),
+    };
+    match body.map(|body| serde_json::from_slice::<SiteVerifyResponse>(&body)) {
+        Ok(Ok(answer)) => {
+            info!("{} answered {} for {}", provider, answer.success, ip);
+            Ok(answer.success)
+        }
+        Ok(Err(e)) => {
+            error!("Unexpected {} answer: {}", provider, e);
+            Err((StatusCode::BAD_GATEWAY, "Could not verify the captcha").into_response())
+        }
+        Err(e) => {
+            error!("{} verification error: {}", provider, e);
+            Err((StatusCode::BAD_GATEWAY, "Could not verify the captcha").into_response())
+        }
+    }
+}
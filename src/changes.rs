@@ -0,0 +1,249 @@
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::{AuthUser, ListScope};
+
+/// The number of changes `/files/changes` returns at once if the client doesn't ask for another.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// How often recording a change is tried when another instance took the same sequence number.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// This enum represents what happened to a file, stored in the `kind` column of `file_changes`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    /// Returns the name of the change as clients see it.
+    fn as_str(self) -> &'static str {
+        match self {
+            Change::Created => "created",
+            Change::Updated => "updated",
+            Change::Deleted => "deleted",
+        }
+    }
+}
+
+/// Returns the sequence number of the newest change, 0 if there is none.
+async fn latest(pool: &AnyPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(seq), 0) FROM file_changes")
+        .fetch_one(pool)
+        .await
+}
+
+/// This function records a change of a file of the owner with the next sequence number.
+/// Two instances can pick the same number at once, the loser tries again with the next one.
+/// A change that can't be recorded is logged, the operation that made it is not undone.
+pub(crate) async fn record(pool: &AnyPool, owner: &str, file_id: &str, change: Change) {
+    let mut attempt = 1;
+    loop {
+        let inserted = async {
+            let seq = latest(pool).await? + 1;
+            sqlx::query(
+                r#"
+                INSERT INTO file_changes
+                    (seq, owner, file_id, kind, changed_at)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(seq)
+            .bind(owner)
+            .bind(file_id)
+            .bind(change.as_str())
+            .bind(Utc::now().timestamp())
+            .execute(pool)
+            .await
+        };
+        match inserted.await {
+            Ok(_) => return,
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                debug!("Change number was taken, trying again: {}", e);
+                attempt += 1;
+            }
+            Err(e) => {
+                error!("Could not record the {} change of file {}: {}", change.as_str(), file_id, e);
+                return;
+            }
+        }
+    }
+}
+
+/// This function removes the changes older than `change_log_retention` seconds, the newest one is always kept
+/// so the sequence numbers keep counting up. It is run by the cleanup task.
+pub(crate) async fn remove_old(pool: &AnyPool, config: &data::Config) -> Result<(), sqlx::Error> {
+    let newest = latest(pool).await?;
+    sqlx::query("DELETE FROM file_changes WHERE changed_at <= ? AND seq < ?")
+        .bind(Utc::now().timestamp().saturating_sub(config.change_log_retention as i64))
+        .bind(newest)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Helper to build the error for a cursor whose changes are no longer retained or that the server never handed out.
+fn cursor_expired(oldest: i64) -> ApiError {
+    ApiError::Rejected {
+        status: StatusCode::GONE,
+        error: "cursor_expired",
+        message: "The changes since this cursor are no longer available, list the files again and continue from since=latest"
+            .to_string(),
+        details: json!({ "oldest_seq": oldest }),
+    }
+}
+
+/// Handler to return the changes to the files of a user since a cursor
+/// This function lets backup tools and mirrors keep a copy of the files of a user in sync
+/// without listing every file again: they list the files once, ask for `since=latest`,
+/// and from then on only fetch what was created, updated or deleted after the returned `cursor`.
+/// Every change carries the file as it is now, several changes of the same file all carry its current state.
+/// Changes are kept for `change_log_retention` seconds, an older cursor is answered with 410 `cursor_expired`
+/// and the client has to list the files again.
+/// Download counts are not changes, they are in the file when it changes for another reason.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" "http://localhost:3000/files/changes?since=latest"
+/// example next request: curl -X GET -H "key: <key>" "http://localhost:3000/files/changes?since=<cursor>"
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+///
+/// accepts the following query parameters:
+/// - since: the `cursor` of the previous response or `latest`, every retained change if missing (optional)
+/// - limit: the maximum number of changes, at most 1000, 100 if missing (optional)
+#[utoipa::path(
+    get,
+    path = "/files/changes",
+    tag = "files",
+    params(data::ChangesQuery),
+    responses(
+        (status = 200, description = "The changes after the cursor, oldest first", body = data::ChangePage),
+        (status = 400, description = "The cursor or limit is invalid"),
+        (status = 401, description = "The key is invalid"),
+        (status = 410, description = "The changes since the cursor are no longer retained"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn list_changes(
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::ChangesQuery>,
+    AuthUser { user, .. }: AuthUser<ListScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a changes request from IP: {}", ip);
+
+    let (oldest, newest) = match sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COALESCE(MIN(seq), 0), COALESCE(MAX(seq), 0) FROM file_changes",
+    )
+    .fetch_one(&replica)
+    .await
+    {
+        Ok(bounds) => bounds,
+        Err(e) => {
+            error!("DB select changes error: {}", e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    let since = match query.since.as_deref().map(str::trim) {
+        None => 0,
+        Some("latest") => {
+            return Ok(Json(data::ChangePage {
+                changes: Vec::new(),
+                cursor: newest,
+                has_more: false,
+            })
+            .into_response());
+        }
+        Some(since) => match since.parse::<i64>() {
+            Ok(since) if since >= 0 => since,
+            _ => return Err(ApiError::BadRequest("since must be a cursor or latest".to_string())),
+        },
+    };
+    // changes between the cursor and the oldest one left were removed, the newest one is always kept
+    if since > newest || (oldest > 0 && since < oldest - 1) {
+        warn!("User {} asked for the changes since expired cursor {}", user.username, since);
+        return Err(cursor_expired(oldest));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, api::MAX_PAGE_SIZE);
+
+    // only changes up to the newest one read above, so later ones are never skipped
+    let rows = sqlx::query_as::<_, (i64, String, String, i64)>(
+        r#"
+        SELECT seq, file_id, kind, changed_at
+        FROM file_changes
+        WHERE owner = ? AND seq > ? AND seq <= ?
+        ORDER BY seq
+        LIMIT ?
+        "#,
+    )
+    .bind(&user.username)
+    .bind(since)
+    .bind(newest)
+    // one more change than asked for tells whether there are more
+    .bind(limit + 1)
+    .fetch_all(&replica)
+    .await;
+    let mut rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("DB select changes error: {}", e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+    let cursor = match has_more {
+        true => rows.last().map_or(since, |(seq, ..)| *seq),
+        false => newest,
+    };
+
+    let ids: Vec<&str> = rows.iter().map(|(_, file_id, ..)| file_id.as_str()).collect();
+    let files = match db::find_owned_files(&replica, &user.username, &ids).await {
+        Ok(mut files) => api::attach_tags(&replica, &mut files).await.map(|()| files),
+        Err(e) => Err(e),
+    };
+    let files: HashMap<String, data::File> = match files {
+        Ok(files) => files.into_iter().map(|file| (file.id.clone(), file)).collect(),
+        Err(e) => {
+            error!("DB select changed files error: {}", e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    let changes = rows
+        .iter()
+        .map(|(seq, file_id, change, changed_at)| data::FileChange {
+            seq: *seq,
+            change: change.clone(),
+            file_id: file_id.clone(),
+            changed_at: *changed_at,
+            file: match change.as_str() {
+                "deleted" => None,
+                _ => files.get(file_id).cloned(),
+            },
+        })
+        .collect();
+    Ok(Json(data::ChangePage {
+        changes,
+        cursor,
+        has_more,
+    })
+    .into_response())
+}
@@ -0,0 +1,65 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The size of the chunks the upload is streamed to clamd in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// This enum represents the verdict of a virus scan.
+pub enum ScanResult {
+    /// No virus was found.
+    Clean,
+    /// A virus was found, the signature name is included.
+    Infected(String),
+}
+
+/// This function scans data with clamd using the INSTREAM protocol.
+/// The address is either `host:port` for a TCP socket
+/// or `unix:/path/to/clamd.sock` for a local unix socket.
+/// Errors reported by clamd (e.g. a stream size limit) are returned as IO errors.
+pub async fn scan(addr: &str, body: &[u8]) -> std::io::Result<ScanResult> {
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        return instream(stream, body).await;
+    }
+    #[cfg(not(unix))]
+    if addr.starts_with("unix:") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix sockets are only supported on unix systems, use host:port",
+        ));
+    }
+    let stream = TcpStream::connect(addr).await?;
+    instream(stream, body).await
+}
+
+/// Helper that speaks the INSTREAM protocol on an open connection.
+/// The data is sent as length prefixed chunks terminated by a zero length chunk,
+/// clamd answers with a single NUL terminated line.
+async fn instream<S>(mut stream: S, body: &[u8]) -> std::io::Result<ScanResult>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in body.chunks(CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches('\0').trim();
+
+    // replies look like "stream: OK" or "stream: <signature> FOUND"
+    let verdict = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if verdict == "OK" {
+        Ok(ScanResult::Clean)
+    } else if let Some(signature) = verdict.strip_suffix("FOUND") {
+        Ok(ScanResult::Infected(signature.trim().to_string()))
+    } else {
+        Err(std::io::Error::other(format!("clamd error: {}", reply)))
+    }
+}
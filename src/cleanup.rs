@@ -0,0 +1,219 @@
+use chrono::Utc;
+use lettre::message::Mailbox;
+use sqlx::AnyPool;
+use tracing::{info, warn};
+
+use crate::anonymous;
+use crate::api;
+use crate::changes;
+use crate::cluster;
+use crate::data;
+use crate::db;
+use crate::email;
+use crate::events;
+use crate::idempotency;
+use crate::ip_quota;
+use crate::parts;
+use crate::webhook;
+
+/// This function purges the files that were in the trash for longer than `trash_retention` seconds,
+/// moves the files that used up their download limit `exhausted_grace_period` seconds ago to the trash,
+/// removes every file whose expiry time has passed,
+/// every file that was not downloaded for its `expire_if_unused_days`,
+/// every file older than `max_file_age` seconds
+/// and, while all files together are larger than `max_total_bytes`, the oldest files.
+/// A `max_file_age` or `max_total_bytes` of 0 turns that rule off.
+/// Pinned files are left alone by every rule, even in the trash, until they are unpinned.
+/// Owners are told about removed files by the `file.expired` webhook and by email.
+/// It also forgets anonymous uploads that no longer count against a daily quota,
+/// idempotency keys older than a day and multipart uploads that were not completed within a week.
+/// Instances that share the database take turns, if another one is cleaning up this run is skipped,
+/// so no owner is notified twice about the same file.
+/// The jobs module runs it every `cleanup_interval` seconds,
+/// with a `cleanup_interval` of 0 expired files are only refused on download.
+pub async fn run(pool: &AnyPool, config: &data::Config) -> Result<(), sqlx::Error> {
+    let Some(_lock) = cluster::try_lock(pool, "cleanup").await? else {
+        info!("Another instance is cleaning up, skipping this run");
+        return Ok(());
+    };
+    let now = Utc::now().timestamp();
+    let trashed = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE deleted_at IS NOT NULL AND deleted_at <= ? AND pinned = 0
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(now.saturating_sub(config.trash_retention as i64))
+    .fetch_all(pool)
+    .await?;
+    for file in &trashed {
+        match api::remove_stored_file(pool, config, file).await {
+            Ok(()) => info!("Purged file {} of {} from the trash", file.id, file.owner),
+            Err(_) => warn!("Could not purge file {} from the trash", file.id),
+        }
+    }
+
+    // the download that used up the limit only marked the file, see `api::finish_download`
+    let exhausted = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE exhausted_at IS NOT NULL AND exhausted_at <= ? AND deleted_at IS NULL AND pinned = 0
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(now.saturating_sub(config.exhausted_grace_period as i64))
+    .fetch_all(pool)
+    .await?;
+    for file in &exhausted {
+        if api::trash_file(pool, config, file).await.is_err() {
+            warn!("Could not move exhausted file {} to the trash", file.id);
+            continue;
+        }
+        info!("Moved file {} of {} to the trash because it reached its download limit", file.id, file.owner);
+        notify_owner(pool, config, file, "it reached its download limit").await;
+    }
+
+    let expired = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE expires IS NOT NULL AND expires <= ? AND pinned = 0
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    for file in &expired {
+        expire(pool, config, file, "it reached its expiry time").await;
+    }
+
+    // files the owner asked to remove once nobody downloads them anymore
+    let unused = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE expire_if_unused_days IS NOT NULL
+            AND deleted_at IS NULL
+            AND pinned = 0
+            AND COALESCE(last_downloaded_at, upload_time) + expire_if_unused_days * 86400 <= ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    for file in &unused {
+        let days = file.expire_if_unused_days.unwrap_or_default();
+        expire(pool, config, file, &format!("it was not downloaded for {} days", days)).await;
+    }
+
+    if config.max_file_age > 0 {
+        let old = sqlx::query_as::<_, data::File>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE upload_time <= ? AND pinned = 0
+            "#,
+            data::File::COLUMNS
+        ))
+        .bind(now.saturating_sub(config.max_file_age as i64))
+        .fetch_all(pool)
+        .await?;
+        for file in &old {
+            expire(pool, config, file, "it is older than the server keeps files").await;
+        }
+    }
+
+    if config.max_total_bytes > 0 {
+        let mut files = sqlx::query_as::<_, data::File>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE pinned = 0
+            ORDER BY upload_time
+            "#,
+            data::File::COLUMNS
+        ))
+        .fetch_all(pool)
+        .await?;
+        // the trash is given up before any file that can still be downloaded
+        files.sort_by_key(|file| file.deleted_at.is_none());
+        let mut total: u64 = files.iter().map(|file| file.file_size.max(0) as u64).sum();
+        for file in &files {
+            if total <= config.max_total_bytes {
+                break;
+            }
+            if expire(pool, config, file, "the server ran out of space and it was one of the oldest files").await {
+                total = total.saturating_sub(file.file_size.max(0) as u64);
+            }
+        }
+    }
+
+    sqlx::query("DELETE FROM anonymous_uploads WHERE upload_time <= ?")
+        .bind(now - anonymous::QUOTA_WINDOW)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM ip_uploads WHERE upload_time <= ?")
+        .bind(now - ip_quota::WINDOW)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM idempotency_keys WHERE created_at <= ?")
+        .bind(now - idempotency::KEY_TTL)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM presigned_uploads WHERE expires <= ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    parts::remove_stale(pool, config).await?;
+    events::remove_old(pool).await?;
+    changes::remove_old(pool, config).await?;
+    Ok(())
+}
+
+/// Helper to remove a file for the retention policy and notify its owner.
+/// It returns false if the file could not be removed.
+async fn expire(pool: &AnyPool, config: &data::Config, file: &data::File, reason: &str) -> bool {
+    if api::remove_stored_file(pool, config, file).await.is_err() {
+        warn!("Could not remove expired file {}", file.id);
+        return false;
+    }
+    info!("Removed file {} of {} because {}", file.id, file.owner, reason);
+    webhook::emit(webhook::EventKind::Expired, file);
+    notify_owner(pool, config, file, reason).await;
+    true
+}
+
+/// Helper to email the owner of a removed file if they turned removal notices on.
+async fn notify_owner(pool: &AnyPool, config: &data::Config, file: &data::File, reason: &str) {
+    if config.smtp_host.is_none() {
+        return;
+    }
+    let email = sqlx::query_scalar::<_, Option<db::Text>>(
+        r#"
+        SELECT email
+        FROM users
+        WHERE username = ? AND email_verified != 0 AND removal_notices != 0
+        "#,
+    )
+    .bind(&file.owner)
+    .fetch_optional(pool)
+    .await;
+    let mailbox = match email {
+        Ok(Some(Some(email))) => String::from(email).parse::<Mailbox>().ok(),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("DB select email error {}: {}", file.owner, e);
+            None
+        }
+    };
+    if let Some(mailbox) = mailbox {
+        if let Err(e) = email::send_removal_notice(config, mailbox, file, reason).await {
+            warn!("Could not email {} about the removal of {}: {}", file.owner, file.id, e);
+        }
+    }
+}
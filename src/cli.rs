@@ -0,0 +1,407 @@
+use chrono::Utc;
+use clap::Subcommand;
+use rand::Rng;
+use sqlx::AnyPool;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::admin;
+use crate::api;
+use crate::audit;
+use crate::backup;
+use crate::cache;
+use crate::data;
+use crate::db;
+
+/// This enum represents the commands of the `bitbeam` binary.
+/// Everything but `serve` works directly on the configured database and data path,
+/// the server does not have to run.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Start the server (the default)
+    Serve,
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage stored files
+    File {
+        #[command(subcommand)]
+        command: FileCommand,
+    },
+    /// Check the audit log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// Create or update the database schema and exit
+    Migrate,
+    /// Write all users and files to a JSON dump, to move to another host or database
+    Export {
+        /// Path of the JSON dump
+        #[arg(long)]
+        out: PathBuf,
+        /// Also write the content of every file into this ZIP archive
+        #[arg(long)]
+        blobs: Option<PathBuf>,
+    },
+    /// Add the users and files of a JSON dump, existing ones are skipped
+    Import {
+        /// Path of the JSON dump
+        dump: PathBuf,
+        /// Store the contents of a ZIP archive written by `export --blobs` first,
+        /// or of a directory laid out like the data path, linked instead of copied where the file system allows
+        #[arg(long)]
+        blobs: Option<PathBuf>,
+    },
+}
+
+/// This enum represents the `bitbeam user` commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum UserCommand {
+    /// Create a user and print their key
+    Add {
+        username: String,
+        /// Password of the user, a random one is printed if it is not given
+        #[arg(long)]
+        password: Option<String>,
+        /// Give the user admin privileges
+        #[arg(long)]
+        admin: bool,
+        /// Email address of the user, it is treated as verified
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Delete a user together with all of their files
+    Remove { username: String },
+    /// List all users with their storage usage
+    List,
+    /// Set the storage quota of a user, like 500M or 10G, 0 for unlimited or `default` for the server quota
+    SetQuota { username: String, quota: String },
+}
+
+/// This enum represents the `bitbeam audit` commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuditCommand {
+    /// Check the hash chain of the audit log and print the hash of the newest entry
+    Verify {
+        /// Hash of the newest entry from an earlier run, to also find entries removed from the end since
+        #[arg(long)]
+        head: Option<String>,
+    },
+}
+
+/// This enum represents the `bitbeam file` commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum FileCommand {
+    /// Delete files uploaded longer ago than the given age
+    Prune {
+        /// Age like 3600s, 90m, 12h, 30d or 2w
+        #[arg(long, value_parser = parse_duration)]
+        older_than: i64,
+        /// Only list the files that would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Returns a number of seconds from a duration like `30d`, a plain number is taken as seconds.
+pub(crate) fn parse_duration(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {:?}, use s, m, h, d or w", unit)),
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .and_then(|number| number.checked_mul(seconds))
+        .ok_or_else(|| format!("{:?} is not a duration like 30d", value))
+}
+
+/// Returns a number of bytes from a size like `500M` or `10G`, a plain number is taken as bytes.
+fn parse_size(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+    let factor: i64 = match unit.to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    number.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// This function runs an administration command and returns the exit code of the process.
+pub async fn run(command: Command, pool: &AnyPool, config: &data::Config) -> i32 {
+    // changes made here must also drop what a running server has cached
+    cache::init(config);
+    let result = match command {
+        Command::User { command } => user(command, pool, config).await,
+        Command::File { command } => file(command, pool, config).await,
+        Command::Audit { command } => audit(command, pool).await,
+        Command::Export { out, blobs } => export(pool, config, out, blobs).await,
+        Command::Import { dump, blobs } => import(pool, config, dump, blobs).await,
+        // both are handled by main, the server needs the whole setup
+        Command::Serve | Command::Migrate => Ok(()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    }
+}
+
+/// Helper to run a `bitbeam user` command.
+async fn user(command: UserCommand, pool: &AnyPool, config: &data::Config) -> Result<(), String> {
+    match command {
+        UserCommand::Add {
+            username,
+            password,
+            admin,
+            email,
+        } => {
+            // anonymous uploads are owned by the empty username
+            if username.trim().is_empty() {
+                return Err("the username must not be empty".to_string());
+            }
+            let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
+                .bind(&username)
+                .fetch_one(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            if exists > 0 {
+                return Err(format!("user {} already exists", username));
+            }
+            let key = Uuid::from_u128(rand::rng().random::<u128>()).to_string();
+            let generated = password.is_none();
+            let password = password.unwrap_or_else(|| hex::encode(rand::rng().random::<[u8; 12]>()));
+            sqlx::query(
+                r#"
+                INSERT INTO users
+                    ("key", username, password, is_admin, email, email_verified)
+                VALUES (?, ?, ?, ?, ?, 1)
+                "#,
+            )
+            .bind(&key)
+            .bind(&username)
+            .bind(&password)
+            .bind((admin || config.admin_users.contains(&username)) as i32)
+            .bind(&email)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            let mut connection = pool.acquire().await.map_err(|e| e.to_string())?;
+            db::insert_api_key(&mut connection, &username, &key, Some(db::DEFAULT_KEY_NAME))
+                .await
+                .map_err(|e| e.to_string())?;
+            println!("Created user {}", username);
+            println!("key: {}", key);
+            if generated {
+                println!("password: {}", password);
+            }
+        }
+        UserCommand::Remove { username } => match admin::remove_user(pool, config, &username).await {
+            Ok(Some(files)) => println!("Deleted user {} and {} files", username, files.len()),
+            Ok(None) => return Err(format!("user {} not found", username)),
+            Err(response) => return Err(format!("could not delete user {}: {}", username, response.status())),
+        },
+        UserCommand::List => {
+            let users = sqlx::query_as::<_, data::UserInfo>(&format!(
+                r#"
+                SELECT users.username AS username,
+                       users.is_admin AS is_admin,
+                       users.max_upload_bytes AS max_upload_bytes,
+                       users.storage_quota AS storage_quota,
+                       COUNT(files.id) AS file_count,
+                       {} AS total_bytes
+                FROM users
+                LEFT JOIN files ON files.owner = users.username
+                GROUP BY users.username, users.is_admin, users.max_upload_bytes, users.storage_quota
+                ORDER BY users.username
+                "#,
+                db::sum(pool, "files.file_size"),
+            ))
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            println!("{:<24} {:<6} {:>8} {:>16} {:>16}", "USERNAME", "ADMIN", "FILES", "BYTES", "QUOTA");
+            for user in users {
+                let quota = match api::storage_quota(config, user.storage_quota) {
+                    Some(quota) => quota.to_string(),
+                    None => "unlimited".to_string(),
+                };
+                println!(
+                    "{:<24} {:<6} {:>8} {:>16} {:>16}",
+                    user.username,
+                    if user.is_admin != 0 { "yes" } else { "no" },
+                    user.file_count,
+                    user.total_bytes,
+                    quota,
+                );
+            }
+        }
+        UserCommand::SetQuota { username, quota } => {
+            let quota = match quota.trim() {
+                "default" => None,
+                value => Some(parse_size(value).ok_or_else(|| format!("{:?} is not a size like 10G", value))?),
+            };
+            let result = sqlx::query("UPDATE users SET storage_quota = ? WHERE username = ?")
+                .bind(quota)
+                .bind(&username)
+                .execute(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            if result.rows_affected() == 0 {
+                return Err(format!("user {} not found", username));
+            }
+            cache::forget_user(pool, &username).await;
+            match quota {
+                Some(0) => println!("{} can store an unlimited amount of data", username),
+                Some(quota) => println!("Set the storage quota of {} to {} bytes", username, quota),
+                None => println!("{} uses the storage quota of the server again", username),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Helper to run a `bitbeam file` command.
+async fn file(command: FileCommand, pool: &AnyPool, config: &data::Config) -> Result<(), String> {
+    match command {
+        FileCommand::Prune { older_than, dry_run } => {
+            let files = sqlx::query_as::<_, data::File>(&format!(
+                r#"
+                SELECT {}
+                FROM files
+                WHERE upload_time <= ?
+                ORDER BY upload_time
+                "#,
+                data::File::COLUMNS
+            ))
+            .bind(Utc::now().timestamp().saturating_sub(older_than))
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            let mut failed = 0;
+            for file in &files {
+                if dry_run {
+                    println!("would delete {} {} of {}", file.id, file.file_name, file.owner);
+                    continue;
+                }
+                match api::remove_stored_file(pool, config, file).await {
+                    Ok(()) => println!("deleted {} {} of {}", file.id, file.file_name, file.owner),
+                    Err(response) => {
+                        eprintln!("could not delete {}: {}", file.id, response.status());
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} of {} files could not be deleted", failed, files.len()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Helper to run a `bitbeam audit` command.
+async fn audit(command: AuditCommand, pool: &AnyPool) -> Result<(), String> {
+    match command {
+        AuditCommand::Verify { head } => {
+            let verification = audit::verify(pool).await.map_err(|e| e.to_string())?;
+            println!(
+                "Checked {} entries, {} of them anonymized, {} older entries are not chained",
+                verification.entries, verification.anonymized, verification.unchained
+            );
+            if let Some(problem) = verification.problem {
+                return match verification.broken_at {
+                    Some(seq) => Err(format!("the audit log does not verify at entry {}: {}", seq, problem)),
+                    None => Err(format!("the audit log does not verify: {}", problem)),
+                };
+            }
+            // an earlier head must still be part of the chain, else entries were removed from the end
+            if let Some(head) = head {
+                let known = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM audit_log WHERE entry_hash = ?")
+                    .bind(&head)
+                    .fetch_one(pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if known == 0 {
+                    return Err(format!("the audit log does not verify: {} is no longer part of it", head));
+                }
+            }
+            match (verification.head_seq, verification.head) {
+                (Some(seq), Some(head)) => println!("The audit log verifies, entry {} has the hash {}", seq, head),
+                _ => println!("The audit log verifies, no entry is chained yet"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Helper to run `bitbeam export`.
+async fn export(pool: &AnyPool, config: &data::Config, out: PathBuf, blobs: Option<PathBuf>) -> Result<(), String> {
+    let dump = backup::export(pool).await.map_err(|e| e.to_string())?;
+    let json = serde_json::to_vec_pretty(&dump).map_err(|e| e.to_string())?;
+    tokio::fs::write(&out, json)
+        .await
+        .map_err(|e| format!("could not write {}: {}", out.display(), e))?;
+    println!("Exported {} users and {} files to {}", dump.users.len(), dump.files.len(), out.display());
+    if let Some(blobs) = blobs {
+        let written = backup::export_blobs(config, &dump.files, &blobs)
+            .await
+            .map_err(|e| format!("could not write {}: {}", blobs.display(), e))?;
+        println!("Exported {} blobs to {}", written, blobs.display());
+    }
+    Ok(())
+}
+
+/// Helper to run `bitbeam import`.
+async fn import(pool: &AnyPool, config: &data::Config, dump: PathBuf, blobs: Option<PathBuf>) -> Result<(), String> {
+    let json = tokio::fs::read(&dump)
+        .await
+        .map_err(|e| format!("could not read {}: {}", dump.display(), e))?;
+    let dump = serde_json::from_slice::<data::Dump>(&json).map_err(|e| format!("invalid dump: {}", e))?;
+    if !backup::is_supported(&dump) {
+        return Err(format!("unsupported dump version {}", dump.version));
+    }
+    // the blobs go first, so no imported file is ever without its content
+    if let Some(blobs) = blobs {
+        let imported = backup::import_blobs(config, &blobs)
+            .await
+            .map_err(|e| format!("could not import {}: {}", blobs.display(), e))?;
+        println!("Imported {} blobs from {}", imported, blobs.display());
+    }
+    let summary = backup::import(pool, config, &dump).await.map_err(|e| e.to_string())?;
+    println!(
+        "Imported {} users and {} files, skipped {} users and {} files that already exist",
+        summary.users_imported, summary.files_imported, summary.users_skipped, summary.files_skipped
+    );
+    if summary.files_rejected > 0 {
+        println!(
+            "warning: rejected {} files whose content type or cache control is no valid header value",
+            summary.files_rejected
+        );
+    }
+    if summary.missing_blobs > 0 {
+        println!("warning: the content of {} files is missing from the data path", summary.missing_blobs);
+    }
+    Ok(())
+}
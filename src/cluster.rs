@@ -0,0 +1,117 @@
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Any, AnyPool, Connection, Executor};
+use tracing::{debug, warn};
+
+/// This struct represents a lock shared by every instance that uses the same database,
+/// so several bitBeam servers can share one database and one data path.
+/// PostgreSQL holds it as an advisory lock, MySQL as a named lock,
+/// both on a connection that is kept out of the pool until the lock is dropped.
+/// SQLite databases can't be shared between hosts, there the lock holds nothing
+/// and callers only rely on their in-process lock.
+pub(crate) struct Lock {
+    name: &'static str,
+    held: Option<(PoolConnection<Any>, String)>,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let Some((mut connection, unlock)) = self.held.take() else {
+            return;
+        };
+        let name = self.name;
+        tokio::spawn(async move {
+            if let Err(e) = connection.execute(unlock.as_str()).await {
+                warn!("Could not release the {} lock: {}", name, e);
+                // the database drops the locks of a session when its connection closes
+                let _ = connection.detach().close().await;
+                return;
+            }
+            debug!("Released the {} lock", name);
+        });
+    }
+}
+
+/// Returns the key of the PostgreSQL advisory lock with the given name.
+fn advisory_key(name: &str) -> i64 {
+    let digest = Sha256::digest(format!("bitbeam.{}", name));
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Returns the name of the MySQL lock with the given name, MySQL lock names are global to the server.
+fn mysql_name(name: &str) -> String {
+    format!("bitbeam.{}", name)
+}
+
+/// Returns true if the database can be shared between instances and needs a lock connection.
+fn is_shared(pool: &AnyPool) -> bool {
+    !matches!(pool.connect_options().database_url.scheme(), "sqlite")
+}
+
+/// This function takes the lock with the given name and waits until no other instance holds it.
+/// Locks are not reentrant, every instance must also serialize its own tasks,
+/// otherwise one task could hold a connection waiting for a lock another of its tasks holds.
+pub(crate) async fn lock(pool: &AnyPool, name: &'static str) -> Result<Lock, sqlx::Error> {
+    if !is_shared(pool) {
+        return Ok(Lock { name, held: None });
+    }
+    let mut connection = pool.acquire().await?;
+    let unlock = match connection.backend_name() {
+        "PostgreSQL" => {
+            let key = advisory_key(name);
+            connection.execute(format!("SELECT pg_advisory_lock({})", key).as_str()).await?;
+            format!("SELECT pg_advisory_unlock({})", key)
+        }
+        "MySQL" => {
+            // a negative timeout waits forever
+            let locked = sqlx::query_scalar::<_, Option<i64>>("SELECT GET_LOCK(?, -1)")
+                .bind(mysql_name(name))
+                .fetch_one(&mut *connection)
+                .await?;
+            if locked != Some(1) {
+                return Err(sqlx::Error::Protocol(format!("could not take the {} lock", name)));
+            }
+            format!("SELECT RELEASE_LOCK('{}')", mysql_name(name))
+        }
+        _ => return Ok(Lock { name, held: None }),
+    };
+    debug!("Took the {} lock", name);
+    Ok(Lock {
+        name,
+        held: Some((connection, unlock)),
+    })
+}
+
+/// This function takes the lock with the given name if no other instance holds it.
+/// It returns `None` if another instance holds the lock,
+/// for work that only one instance should do at a time, like the periodic cleanup.
+pub(crate) async fn try_lock(pool: &AnyPool, name: &'static str) -> Result<Option<Lock>, sqlx::Error> {
+    if !is_shared(pool) {
+        return Ok(Some(Lock { name, held: None }));
+    }
+    let mut connection = pool.acquire().await?;
+    let (locked, unlock) = match connection.backend_name() {
+        "PostgreSQL" => {
+            let key = advisory_key(name);
+            let locked = sqlx::query_scalar::<_, bool>(&format!("SELECT pg_try_advisory_lock({})", key))
+                .fetch_one(&mut *connection)
+                .await?;
+            (locked, format!("SELECT pg_advisory_unlock({})", key))
+        }
+        "MySQL" => {
+            let locked = sqlx::query_scalar::<_, Option<i64>>("SELECT GET_LOCK(?, 0)")
+                .bind(mysql_name(name))
+                .fetch_one(&mut *connection)
+                .await?;
+            (locked == Some(1), format!("SELECT RELEASE_LOCK('{}')", mysql_name(name)))
+        }
+        _ => return Ok(Some(Lock { name, held: None })),
+    };
+    if !locked {
+        debug!("The {} lock is held by another instance", name);
+        return Ok(None);
+    }
+    Ok(Some(Lock {
+        name,
+        held: Some((connection, unlock)),
+    }))
+}
@@ -0,0 +1,453 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::share;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// Handler to create a collection
+/// This function creates an empty collection owned by the requesting user
+/// and returns it as a JSON response.
+/// The collection ID is random, anybody who knows it can view the collection.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"name": "holiday photos"}' http://localhost:3000/collection
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following JSON body:
+/// - name: the name of the collection (not optional)
+#[instrument(skip_all)]
+pub async fn create_collection(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::NewCollection>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received create collection request from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let name = request.name.trim().to_string();
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Collection name must not be empty").into_response();
+    }
+
+    let collection = data::Collection {
+        id: Uuid::new_v4().to_string(),
+        name,
+        owner: user.username,
+        created_at: Utc::now().timestamp(),
+    };
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO collections (id, name, owner, created_at)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(&collection.id)
+    .bind(&collection.name)
+    .bind(&collection.owner)
+    .bind(collection.created_at)
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert error {}: {}", collection.id, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("Collection {} created by {}", collection.id, collection.owner);
+
+    Json(collection).into_response()
+}
+
+/// Handler to list the collections of a user
+/// This function returns the collections owned by the requesting user as JSON.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/collections
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn list_collections(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received list collections request from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as::<_, data::Collection>(
+        r#"
+        SELECT *
+        FROM collections
+        WHERE owner = ?
+        ORDER BY created_at
+        "#,
+    )
+    .bind(&user.username)
+    .fetch_all(&replica)
+    .await
+    {
+        Ok(collections) => Json(collections).into_response(),
+        Err(e) => {
+            error!("DB select error {}: {}", user.username, e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to return a collection and its files
+/// This function returns the collection with the metadata of every file in it.
+/// No key is needed, the collection ID is what is shared.
+/// Private files are left out, they are only shared by share tokens,
+/// or listed to whoever has a collection token of the collection, with the token in their download URL.
+/// Downloading the listed files counts against their download limits as usual.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/collection/<id>
+/// requires the following path parameter:
+/// - id: the ID of the collection (not optional)
+///
+/// accepts the following query parameter:
+/// - token: a collection token of the collection (optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn get_collection(
+    Path(id): Path<String>,
+    Query(query): Query<data::CollectionQuery>,
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received collection request for {} from IP: {}", id, ip);
+
+    let collection = match find_collection(&replica, &id).await {
+        Ok(collection) => collection,
+        Err(response) => return response,
+    };
+    // tokens are checked on the primary, a fresh token may not have reached the replica yet
+    let token = match &query.token {
+        Some(token) => match tokens::find_collection_token(&pool, token).await {
+            Ok(Some(found)) if found.collection_id.as_deref() == Some(id.as_str()) => Some(token),
+            Ok(_) => {
+                warn!("Invalid collection token for {} from {}", id, ip);
+                return (StatusCode::UNAUTHORIZED, "Your token is not valid").into_response();
+            }
+            Err(e) => {
+                error!("DB select token error: {}", e);
+                return db::error_response(&e, "Database select error");
+            }
+        },
+        None => None,
+    };
+
+    let files = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        JOIN collection_files ON collection_files.file_id = files.id
+        WHERE collection_files.collection_id = ? AND files.deleted_at IS NULL
+            AND (files.visibility = 'public' OR (? = 1 AND files.owner = ?))
+        ORDER BY files.upload_time
+        "#,
+        data::File::qualified_columns()
+    ))
+    .bind(&id)
+    .bind(token.is_some() as i32)
+    .bind(&collection.owner)
+    .fetch_all(&replica)
+    .await;
+    match files {
+        Ok(mut files) => {
+            if let Some(token) = token {
+                for file in files.iter_mut().filter(|file| file.is_private()) {
+                    file.download_url = share::token_url(file, token);
+                }
+            }
+            Json(data::CollectionContents { collection, files }).into_response()
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", id, e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to delete a collection
+/// This function deletes a collection, the files in it are kept.
+/// Only the owner of the collection can delete it.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/collection/<id>
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - id: the ID of the collection (not optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn delete_collection(
+    Path(id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received delete collection request for {} from IP: {}", id, ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let collection = match owned_collection(&pool, &id, &user).await {
+        Ok(collection) => collection,
+        Err(response) => return response,
+    };
+
+    for statement in [
+        "DELETE FROM collection_files WHERE collection_id = ?",
+        "DELETE FROM collections WHERE id = ?",
+    ] {
+        if let Err(e) = sqlx::query(statement).bind(&id).execute(&pool).await {
+            error!("DB delete error {}: {}", id, e);
+            return db::error_response(&e, "Database delete error");
+        }
+    }
+    info!("Collection {} deleted by {}", id, user.username);
+
+    Json(collection).into_response()
+}
+
+/// Handler to add a file to a collection
+/// This function adds one of the user's files to one of the user's collections.
+/// Adding a file that is already in the collection does nothing.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" http://localhost:3000/collection/<id>/files/<uuid>
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameters:
+/// - id: the ID of the collection (not optional)
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(id = %id, uuid = %uuid))]
+pub async fn add_file(
+    Path((id, uuid)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received add file {} to collection {} from IP: {}", uuid, id, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Err(response) = owned_collection(&pool, &id, &user).await {
+        return response;
+    }
+    if let Err(response) = owned_file(&pool, &uuid, &user).await {
+        return response;
+    }
+
+    let already_added = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*)
+        FROM collection_files
+        WHERE collection_id = ? AND file_id = ?
+        "#,
+    )
+    .bind(&id)
+    .bind(&uuid)
+    .fetch_one(&pool)
+    .await;
+    match already_added {
+        Ok(0) => {}
+        Ok(_) => return StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("DB select error {}: {}", id, e);
+            return db::error_response(&e, "Database select error");
+        }
+    }
+
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO collection_files (collection_id, file_id)
+        VALUES (?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(&uuid)
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert error {}: {}", id, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("File {} added to collection {}", uuid, id);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Handler to remove a file from a collection
+/// This function removes a file from one of the user's collections,
+/// the file itself is kept.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/collection/<id>/files/<uuid>
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameters:
+/// - id: the ID of the collection (not optional)
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(id = %id, uuid = %uuid))]
+pub async fn remove_file(
+    Path((id, uuid)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received remove file {} from collection {} from IP: {}", uuid, id, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Err(response) = owned_collection(&pool, &id, &user).await {
+        return response;
+    }
+
+    match sqlx::query(
+        r#"
+        DELETE FROM collection_files
+        WHERE collection_id = ? AND file_id = ?
+        "#,
+    )
+    .bind(&id)
+    .bind(&uuid)
+    .execute(&pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            (StatusCode::NOT_FOUND, "File not in collection").into_response()
+        }
+        Ok(_) => {
+            info!("File {} removed from collection {}", uuid, id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("DB delete error {}: {}", id, e);
+            db::error_response(&e, "Database delete error")
+        }
+    }
+}
+
+/// Helper to look up a collection by its ID.
+pub(crate) async fn find_collection(pool: &AnyPool, id: &str) -> Result<data::Collection, Response> {
+    let collection = sqlx::query_as::<_, data::Collection>(
+        r#"
+        SELECT *
+        FROM collections
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await;
+    match collection {
+        Ok(Some(collection)) => Ok(collection),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Collection not found").into_response()),
+        Err(e) => {
+            error!("DB select error {}: {}", id, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to look up a collection and make sure the user owns it.
+pub(crate) async fn owned_collection(
+    pool: &AnyPool,
+    id: &str,
+    user: &data::User,
+) -> Result<data::Collection, Response> {
+    let collection = find_collection(pool, id).await?;
+    if collection.owner != user.username {
+        warn!("User {} tried to change collection {} owned by {}", user.username, id, collection.owner);
+        return Err((StatusCode::FORBIDDEN, "You do not own this collection").into_response());
+    }
+    Ok(collection)
+}
+
+/// This function returns true if the file is in the collection.
+pub(crate) async fn contains(pool: &AnyPool, collection_id: &str, file_id: &str) -> Result<bool, sqlx::Error> {
+    let count = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*)
+        FROM collection_files
+        WHERE collection_id = ? AND file_id = ?
+        "#,
+    )
+    .bind(collection_id)
+    .bind(file_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Helper to make sure a file exists and the user owns it.
+async fn owned_file(pool: &AnyPool, uuid: &str, user: &data::User) -> Result<(), Response> {
+    let owner = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT owner
+        FROM files
+        WHERE id = ?
+        "#,
+    )
+    .bind(uuid)
+    .fetch_optional(pool)
+    .await;
+    match owner {
+        Ok(Some(owner)) if owner == user.username => Ok(()),
+        Ok(Some(owner)) => {
+            warn!("User {} tried to collect file {} owned by {}", user.username, uuid, owner);
+            Err((StatusCode::FORBIDDEN, "You do not own this file").into_response())
+        }
+        Ok(None) => Err((StatusCode::NOT_FOUND, "File not found").into_response()),
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
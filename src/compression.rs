@@ -0,0 +1,107 @@
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{Read, Write};
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+/// Content types of downloads that are worth compressing.
+/// Entries ending in `/` match a whole group.
+/// Most other uploads, like images, video and archives, are compressed already
+/// and would only cost CPU time to compress again.
+const COMPRESSIBLE_TYPES: [&str; 7] = [
+    "text/",
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/x-ndjson",
+    "application/x-yaml",
+    "image/svg+xml",
+];
+
+/// Every compressed blob starts with these bytes, followed by a gzip member.
+/// They only check the format, whether a blob is compressed is part of its stored name, see `storage::Form`.
+const MAGIC: &[u8; 8] = b"bitBeamZ";
+
+/// Returns true if the content type is one of `COMPRESSIBLE_TYPES`.
+pub fn is_compressible_type(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    COMPRESSIBLE_TYPES.iter().any(|compressible| match compressible.ends_with('/') {
+        true => content_type.starts_with(compressible),
+        false => content_type.split(';').next() == Some(compressible),
+    })
+}
+
+/// Returns true if the response carries a content type from `COMPRESSIBLE_TYPES`.
+fn is_compressible(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(is_compressible_type)
+}
+
+/// Returns true if the client accepts gzip encoded responses.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+/// This function compresses data for storage with gzip, the layout is `MAGIC | gzip member`.
+/// gzip is used so the member can be sent as it is to clients that accept `Content-Encoding: gzip`.
+/// Returns `None` if compressing would not make the data smaller.
+pub fn compress(data: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+    let mut encoder = GzEncoder::new(MAGIC.to_vec(), Compression::default());
+    encoder.write_all(data)?;
+    let blob = encoder.finish()?;
+    Ok((blob.len() < data.len()).then_some(blob))
+}
+
+/// Returns the gzip member of a compressed blob.
+pub fn gzip_member(blob: &[u8]) -> Option<&[u8]> {
+    blob.strip_prefix(MAGIC)
+}
+
+/// This function decompresses a blob written by `compress`.
+/// It stops once the data grows past `limit` bytes, the size of the file the blob holds,
+/// so a blob that decompresses to far more never fills the memory.
+pub fn decompress(blob: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+    let Some(member) = gzip_member(blob) else {
+        return Err(std::io::Error::other("blob is not compressed"));
+    };
+    let mut data = Vec::new();
+    GzDecoder::new(member).take(limit.saturating_add(1)).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("blob decompresses to more than the {} bytes of its file", limit),
+        ));
+    }
+    Ok(data)
+}
+
+/// Returns the layer that compresses the JSON responses of the API and the web pages
+/// for clients that accept gzip or brotli.
+pub fn api() -> CompressionLayer {
+    CompressionLayer::new()
+}
+
+/// Returns the layer that compresses downloads, but only of compressible content types.
+pub fn downloads() -> CompressionLayer<impl Predicate> {
+    let is_compressible = is_compressible as fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool;
+    CompressionLayer::new().compress_when(SizeAbove::default().and(is_compressible))
+}
@@ -1,26 +1,581 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db;
+use crate::proxy;
+use crate::route_policy;
 
 /// This struct represents a file in the database.
 /// It contains fields for the file's ID, content type,
 /// upload time, download limit, download count,
-/// and file size.
+/// file size and the SHA-256 hash of its content.
+/// Files with the same content hash share one blob on disk,
+/// files uploaded before hashing was introduced have no hash
+/// and are stored under their ID.
+/// `encrypted` is 1 for end to end encrypted uploads, stored as an integer
+/// like the other flags because the sqlx Any driver can not decode SQLite booleans.
+/// `detected_content_type` is sniffed from the content, `content_type` is what the client declared.
+/// `visibility` is `public` or `private`, private files can only be downloaded
+/// by their owner or with a share token.
+/// `cid` is the IPFS CID of the content if the file was mirrored to IPFS.
+/// `expire_if_unused_days` removes the file once it was not downloaded for that many days,
+/// counted from `last_downloaded_at` or from the upload if it was never downloaded.
+/// `verified` is 1 if the file has a detached signature made with the public key of its owner.
+/// `cache_control` is the `Cache-Control` header downloads of the file are sent with, set by the uploader.
+/// `published` is 1 if the owner listed the file in the public directory at `/public`.
+/// `blob_missing` is 1 if the startup consistency check found its blob missing, such files answer with 410.
+/// `pinned` is 1 if the owner or an administrator put the file on hold, see `is_pinned`.
+/// `exhausted_at` is when the file used up its download limit, the cleanup task trashes it
+/// once `exhausted_grace_period` passed since then.
+/// `description` is a free text note of the uploader, so similarly named files can be told apart.
+/// `human` holds the formatted fields listings add with `?human=true`.
+/// `tags` live in the `file_tags` table and are filled in by the listing endpoint.
 /// It derives the `FromRow` trait from `sqlx`
 /// to allow it to be created from a database row.
 /// It also derives the `Serialize` trait
 /// from `serde`
 /// to allow it to be serialized into JSON.
-#[derive(FromRow, Serialize)]
+#[derive(Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct File {
     pub id: String,
+    #[sqlx(try_from = "db::Text")]
     pub file_name: String,
+    #[sqlx(try_from = "db::Text")]
     pub content_type: String,
     pub upload_time: i64,
     pub download_limit: i32,
     pub download_count: i32,
     pub file_size: i64,
+    #[sqlx(try_from = "db::Text")]
     pub download_url: String,
     pub owner: String,
+    pub content_hash: Option<String>,
+    pub slug: Option<String>,
+    pub encrypted: i32,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub detected_content_type: Option<String>,
+    pub visibility: String,
+    pub expires: Option<i64>,
+    pub deleted_at: Option<i64>,
+    pub version: i32,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub cid: Option<String>,
+    pub expire_if_unused_days: Option<i32>,
+    pub last_downloaded_at: Option<i64>,
+    #[serde(default)]
+    pub verified: i32,
+    #[serde(default)]
+    #[sqlx(try_from = "db::OptionalText")]
+    pub cache_control: Option<String>,
+    #[serde(default)]
+    pub published: i32,
+    #[serde(default)]
+    pub blob_missing: i32,
+    #[serde(default)]
+    pub pinned: i32,
+    #[serde(default)]
+    pub exhausted_at: Option<i64>,
+    #[serde(default)]
+    #[sqlx(try_from = "db::OptionalText")]
+    pub description: Option<String>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human: Option<HumanFile>,
+}
+
+/// This struct represents the fields of a file formatted for people, see `human::file`.
+/// Sizes are in binary units like `14.2 MiB`, times relative to now like `3 days ago` or `in 2 hours`.
+/// Clients that show files to people can print them as they are, the raw values stay next to them.
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct HumanFile {
+    pub file_size: String,
+    pub upload_time: String,
+    pub downloads: String,
+    pub expires: Option<String>,
+    pub last_downloaded_at: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+impl File {
+    /// The columns of the `files` table read into a `File`, for queries that list or look up files.
+    pub const COLUMNS: &'static str = "id, file_name, content_type, upload_time, download_limit, download_count, \
+        file_size, download_url, owner, content_hash, slug, encrypted, detected_content_type, visibility, \
+        expires, deleted_at, version, cid, expire_if_unused_days, last_downloaded_at, verified, cache_control, published, \
+        blob_missing, pinned, exhausted_at, description";
+
+    /// Returns `COLUMNS` prefixed with `files.`, for queries that join other tables.
+    pub fn qualified_columns() -> String {
+        Self::COLUMNS
+            .split(',')
+            .map(|column| format!("files.{}", column.trim()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the name of the blob that holds the content of the file.
+    pub fn blob_name(&self) -> &str {
+        self.content_hash.as_deref().unwrap_or(&self.id)
+    }
+
+    /// Returns the hex SHA-256 digest of the contents, `None` for files stored before contents were hashed.
+    pub fn sha256(&self) -> Option<&str> {
+        self.content_hash
+            .as_deref()
+            .filter(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    /// Returns true if only the owner and holders of a share token can download the file.
+    pub fn is_private(&self) -> bool {
+        self.visibility == "private"
+    }
+
+    /// Returns the number of downloads the file has left, `None` if its downloads are unlimited.
+    pub fn downloads_remaining(&self) -> Option<i32> {
+        match self.download_limit {
+            0 => None,
+            limit => Some((limit - self.download_count).max(0)),
+        }
+    }
+
+    /// Returns what limits the life of the file: `downloads`, `date`, `both` or `none`.
+    pub fn limited_by(&self) -> &'static str {
+        match (self.download_limit > 0, self.expires.is_some()) {
+            (true, true) => "both",
+            (true, false) => "downloads",
+            (false, true) => "date",
+            (false, false) => "none",
+        }
+    }
+
+    /// Returns true if the file has an expiry time and it has passed.
+    /// Expired files can't be downloaded anymore and are removed by the cleanup task.
+    /// Pinned files never expire while they are pinned.
+    pub fn is_expired(&self) -> bool {
+        !self.is_pinned() && self.expires.is_some_and(|expires| expires <= chrono::Utc::now().timestamp())
+    }
+
+    /// Returns true if the file is on hold.
+    /// Nothing removes a pinned file on its own, neither the download limit,
+    /// the expiry time nor the retention rules of the cleanup task, only its owner or an administrator can.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned != 0
+    }
+
+    /// Returns true if the owner listed the file in the public directory.
+    pub fn is_published(&self) -> bool {
+        self.published != 0
+    }
+
+    /// Returns true if the file was moved to the trash.
+    /// Files in the trash can be restored by their owner until the cleanup task purges them.
+    pub fn is_trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Returns true if the blob of the file was found missing, see `integrity::reconcile`.
+    pub fn is_blob_missing(&self) -> bool {
+        self.blob_missing != 0
+    }
+
+    /// Returns true if the file can be downloaded, it is neither expired, in the trash nor missing its blob.
+    pub fn is_available(&self) -> bool {
+        !self.is_expired() && !self.is_trashed() && !self.is_blob_missing()
+    }
+}
+
+/// This struct represents an earlier version of a file in the `file_versions` table.
+/// It keeps everything about the contents of the file at that version,
+/// the rest of the metadata is shared by all versions.
+#[derive(FromRow, Serialize)]
+pub struct FileVersion {
+    pub file_id: String,
+    pub version: i32,
+    #[sqlx(try_from = "db::Text")]
+    pub file_name: String,
+    #[sqlx(try_from = "db::Text")]
+    pub content_type: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub detected_content_type: Option<String>,
+    pub file_size: i64,
+    pub content_hash: Option<String>,
+    pub encrypted: i32,
+    pub upload_time: i64,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub cid: Option<String>,
+}
+
+impl FileVersion {
+    /// Returns the file as it was at this version.
+    pub fn apply(&self, file: &File) -> File {
+        File {
+            file_name: self.file_name.clone(),
+            content_type: self.content_type.clone(),
+            detected_content_type: self.detected_content_type.clone(),
+            file_size: self.file_size,
+            content_hash: self.content_hash.clone(),
+            encrypted: self.encrypted,
+            upload_time: self.upload_time,
+            version: self.version,
+            cid: self.cid.clone(),
+            ..file.clone()
+        }
+    }
+}
+
+/// This struct represents the JSON body of the `/download/zip` endpoint.
+/// `token` is a collection token, it lets the private files of its collection into the archive.
+#[derive(Deserialize)]
+pub struct ArchiveRequest {
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// This struct represents a file in the `manifest.json` of a `/my_files/archive` download.
+/// `path` is the entry of the file in the archive, the rest is its metadata.
+#[derive(Serialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    #[serde(flatten)]
+    pub file: File,
+}
+
+/// This struct represents a collection of files in the database.
+/// Collections only group files of their owner, the files themselves stay where they are.
+#[derive(FromRow, Serialize)]
+pub struct Collection {
+    pub id: String,
+    #[sqlx(try_from = "db::Text")]
+    pub name: String,
+    pub owner: String,
+    pub created_at: i64,
+}
+
+/// This struct represents the response to an upload of an archive with `extract: true`.
+/// `files` are the stored files of the archive, named after their path in it,
+/// `collection_url` is the page of the collection they were put in.
+#[derive(Serialize)]
+pub struct ExtractedArchive {
+    pub collection: Collection,
+    pub collection_url: String,
+    pub files: Vec<UploadedFile>,
+}
+
+/// This struct represents a multipart upload in the `upload_sessions` table,
+/// started with `/upload/init` and stored as a file once it is completed.
+/// It keeps the options of the upload until the file is stored,
+/// `tags` is a comma separated list.
+#[derive(Clone, FromRow)]
+pub struct UploadSession {
+    pub id: String,
+    pub owner: String,
+    #[sqlx(try_from = "db::Text")]
+    pub file_name: String,
+    #[sqlx(try_from = "db::Text")]
+    pub content_type: String,
+    pub download_limit: i32,
+    pub slug: Option<String>,
+    pub encrypted: i32,
+    #[sqlx(try_from = "db::Text")]
+    pub tags: String,
+    pub visibility: String,
+    pub expires: Option<i64>,
+    pub expire_if_unused_days: Option<i32>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub cache_control: Option<String>,
+    pub published: i32,
+    pub created_at: i64,
+    pub completing: i32,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub description: Option<String>,
+}
+
+/// This struct represents a part of a multipart upload in the `upload_parts` table.
+/// `sha256` is the hex digest of the part, it is also sent as the `ETag` of the part upload.
+/// `encrypted` is whether the part file is encrypted with the master key, it is not sent to clients.
+#[derive(Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UploadPart {
+    pub part_number: i32,
+    pub size: i64,
+    pub sha256: String,
+    pub uploaded_at: i64,
+    #[serde(skip)]
+    #[schema(ignore)]
+    pub encrypted: Option<i32>,
+}
+
+/// This struct represents a multipart upload returned by `/upload/init` and `/upload/{upload_id}`.
+/// `expires_at` is when the upload is given up if it is not completed,
+/// `parts` are the parts received so far, ordered by their number.
+#[derive(Serialize, ToSchema)]
+pub struct MultipartUpload {
+    pub upload_id: String,
+    pub file_name: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub max_part_number: i32,
+    pub max_bytes: u64,
+    pub parts: Vec<UploadPart>,
+}
+
+/// This struct represents a part listed in the body of `/upload/{upload_id}/complete`.
+#[derive(Deserialize, ToSchema)]
+pub struct CompletedPart {
+    pub part_number: i32,
+    pub sha256: String,
+}
+
+/// This struct represents the optional JSON body of `/upload/{upload_id}/complete`,
+/// the parts to join in the order of their numbers.
+#[derive(Deserialize, ToSchema)]
+pub struct CompleteUpload {
+    pub parts: Vec<CompletedPart>,
+}
+
+/// This struct represents a collection together with the files in it.
+#[derive(Serialize)]
+pub struct CollectionContents {
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub files: Vec<File>,
+}
+
+/// This struct represents the query parameter of `/collection/{id}`,
+/// a collection token that also lists the private files of the collection.
+#[derive(Deserialize)]
+pub struct CollectionQuery {
+    pub token: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/collection` endpoint.
+#[derive(Deserialize)]
+pub struct NewCollection {
+    pub name: String,
+}
+
+/// This struct represents the JSON body of the `/file/{uuid}/email` endpoint.
+#[derive(Deserialize)]
+pub struct EmailRequest {
+    pub to: String,
+    pub password_hint: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/report/{uuid}` endpoint.
+#[derive(Deserialize)]
+pub struct ReportRequest {
+    pub reason: String,
+}
+
+/// This struct represents the query parameters of the `/admin/reports` endpoint.
+#[derive(Deserialize)]
+pub struct ReportsQuery {
+    pub status: Option<String>,
+}
+
+/// This struct represents an abuse report in the database.
+/// `file_name` and `owner` come from the reported file and are empty once it is gone.
+#[derive(FromRow, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub file_id: String,
+    #[sqlx(try_from = "db::Text")]
+    pub reason: String,
+    #[sqlx(try_from = "db::Text")]
+    pub ip: String,
+    pub created: i64,
+    pub status: String,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub file_name: Option<String>,
+    pub owner: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/file/{uuid}/transfer` endpoint.
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    pub to: String,
+}
+
+/// This struct represents the JSON body of the `/admin/users/{name}/max_upload_bytes` endpoint.
+/// A missing or null value removes the per user limit.
+#[derive(Deserialize)]
+pub struct UploadLimitRequest {
+    pub max_upload_bytes: Option<i64>,
+}
+
+/// This struct represents the JSON body of the `/user/notifications` endpoint.
+#[derive(Deserialize)]
+pub struct NotificationRequest {
+    pub service: Option<String>,
+    pub url: Option<String>,
+    pub token: Option<String>,
+    pub events: Option<Vec<String>>,
+}
+
+/// This struct represents the settings of a user returned by `/user/me`.
+/// `default_download_limit`, `default_expiry` and `default_visibility` apply to uploads that don't set their own,
+/// `None` means the server default, no expiry and public files.
+/// `default_expiry` is in seconds after the upload.
+/// `removal_notices` turns the emails about files removed by the retention policy on or off.
+#[derive(Serialize, ToSchema)]
+pub struct Profile {
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub display_email: Option<String>,
+    pub is_admin: bool,
+    pub default_download_limit: Option<i32>,
+    pub default_expiry: Option<i64>,
+    pub default_visibility: Option<String>,
+    pub removal_notices: bool,
+}
+
+/// This struct represents the JSON body of a `PATCH` to `/user/me`.
+/// Missing fields are left as they are, a null resets a setting to the server default.
+#[derive(Deserialize, ToSchema)]
+pub struct ProfileUpdate {
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<String>)]
+    pub display_email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<i32>)]
+    pub default_download_limit: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<i64>)]
+    pub default_expiry: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<String>)]
+    pub default_visibility: Option<Option<String>>,
+    pub removal_notices: Option<bool>,
+}
+
+/// This struct represents the JSON body of a `PATCH` to `/file/{uuid}`.
+/// A missing description is left as it is, a null or empty one removes it.
+#[derive(Deserialize, ToSchema)]
+pub struct FileUpdate {
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<String>)]
+    pub description: Option<Option<String>>,
+}
+
+/// Deserializes a field that is present, null included, as `Some`,
+/// so a missing field can be told apart from a null one.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// This struct represents the receipt of an account deleted with `DELETE /user/me`.
+/// `files` are the IDs of the removed files, `bytes_deleted` their combined size.
+/// `audit_entries_anonymized` counts the audit log entries the username and IP address were removed from.
+#[derive(Serialize, ToSchema)]
+pub struct DeletionReceipt {
+    pub username: String,
+    pub deleted_at: i64,
+    pub files: Vec<String>,
+    pub bytes_deleted: i64,
+    pub audit_entries_anonymized: u64,
+}
+
+/// This struct represents the JSON body of the `/user/pgp_key` endpoint.
+#[derive(Deserialize)]
+pub struct PgpKeyRequest {
+    pub public_key: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/user/webhook` endpoint.
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+    pub url: Option<String>,
+}
+
+/// This struct represents the query parameters of the `/sharex` endpoint.
+#[derive(Deserialize)]
+pub struct ShareXQuery {
+    pub download_limit: Option<i32>,
+}
+
+/// This struct represents the JSON body of the `/upload/remote` endpoint.
+#[derive(Deserialize)]
+pub struct RemoteUploadRequest {
+    pub url: String,
+    pub file_name: Option<String>,
+    pub download_limit: Option<i32>,
+    pub visibility: Option<String>,
+    pub expires_at: Option<String>,
+    pub max_age: Option<String>,
+}
+
+/// This struct holds the metadata of a file that is about to be stored.
+/// It is filled in by the upload handlers
+/// from headers or multipart form fields.
+/// The expected checksums are verified against the uploaded data before it is stored.
+/// A non empty `allowed_content_types` restricts the content type, see `tokens::content_type_matches`.
+/// `expires` is a unix timestamp, files without one are kept until their download limit is reached.
+/// A `download_limit` of 0 means the file can be downloaded any number of times.
+/// `cache_control` is normalized by `api::parse_cache_control` already.
+/// `published` lists the file in the public directory, only public files can be published.
+/// `collection_id` is the collection the file is added to together with its row, see `tokens::collection`.
+/// `description` is checked by `api::parse_description` already.
+#[derive(Clone)]
+pub struct NewFile {
+    pub file_name: String,
+    pub content_type: String,
+    pub download_limit: i32,
+    pub owner: String,
+    pub expected_sha256: Option<String>,
+    pub expected_md5: Option<String>,
+    pub slug: Option<String>,
+    pub encrypted: bool,
+    pub tags: Vec<String>,
+    pub visibility: String,
+    pub allowed_content_types: Vec<String>,
+    pub expires: Option<i64>,
+    pub expire_if_unused_days: Option<i32>,
+    pub cache_control: Option<String>,
+    pub published: bool,
+    pub collection_id: Option<String>,
+    pub description: Option<String>,
+}
+
+/// This struct represents the response to a successful upload.
+/// It contains the metadata of the stored file, the links to it
+/// and the MD5 digest of the upload if the client asked for MD5 verification.
+/// `delete_token` deletes the file once without a key, see `api::delete_by_token`.
+#[derive(Serialize, ToSchema)]
+pub struct UploadedFile {
+    #[serde(flatten)]
+    pub file: File,
+    pub links: UploadLinks,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_url_template: Option<String>,
+}
+
+/// This struct represents the links of an uploaded file, so clients don't have to build them.
+/// `view` shows the file in the browser, `None` for end to end encrypted files.
+/// `delete` removes the file without a key, it carries a one-time delete token
+/// that only the response to the upload itself includes.
+/// `share` is a landing page with a preview for chat apps, `qr` is a QR code of the download link.
+#[derive(Serialize, ToSchema)]
+pub struct UploadLinks {
+    pub download: String,
+    pub view: Option<String>,
+    pub share: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<String>,
+    pub qr: String,
 }
 
 /// This struct is used to represent the configuration settings for the application.
@@ -32,19 +587,851 @@ pub struct File {
 pub struct Config {
     pub db_type: String,
     pub database_url: String,
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    pub db_acquire_timeout: u64,
+    pub db_statement_timeout: u64,
+    pub redis_url: Option<String>,
+    pub cache_ttl: u64,
     pub data_path: String,
     pub port: String,
     pub listener_addr: String,
+    pub unix_socket: Option<String>,
+    pub unix_socket_mode: u32,
     pub log_level: String,
     pub log_location: String,
+    pub log_format: String,
+    pub access_log: Option<String>,
+    pub access_log_format: String,
+    pub sentry_dsn: Option<String>,
+    pub sentry_environment: Option<String>,
     pub use_tls: bool,
     pub base_url: String,
+    pub base_path: String,
     pub allow_register: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_redirect_port: Option<String>,
+    pub admin_users: Vec<String>,
+    pub trusted_proxies: Vec<proxy::Network>,
+    pub clamav_addr: Option<String>,
+    pub remote_upload_allow_private: bool,
+    pub webhook_urls: Vec<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_allow_private: bool,
+    pub smtp_host: Option<String>,
+    pub smtp_port: String,
+    pub smtp_tls: String,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_from: Option<String>,
+    pub rate_limit_downloads: u32,
+    pub rate_limit_uploads: u32,
+    pub rate_limit_register: u32,
+    pub rate_limit_reports: u32,
+    pub rate_limit_public: u32,
+    pub max_download_rate: u32,
+    pub max_download_rate_per_connection: u32,
+    pub max_upload_size: u64,
+    pub storage_quota: u64,
+    pub master_key: Option<[u8; 32]>,
+    pub gc_interval: u64,
+    pub reject_mime_mismatch: bool,
+    pub default_download_limit: i32,
+    pub max_download_limit: i32,
+    pub blocked_types: Vec<String>,
+    pub blocked_extensions: Vec<String>,
+    pub compress_downloads: bool,
+    pub session_secret: Option<String>,
+    pub session_ttl: u64,
+    pub require_email_verification: bool,
+    pub captcha: String,
+    pub captcha_site_key: Option<String>,
+    pub captcha_secret: Option<String>,
+    pub pow_difficulty: u32,
+    pub anonymous_uploads: bool,
+    pub anonymous_max_upload_size: u64,
+    pub anonymous_expiry: u64,
+    pub anonymous_daily_quota: u64,
+    pub cleanup_interval: u64,
+    pub max_file_age: u64,
+    pub max_total_bytes: u64,
+    pub trash_retention: u64,
+    pub matrix_server_name: Option<String>,
+    pub ipfs_api_url: Option<String>,
+    pub public_blobs: bool,
+    pub max_store_bytes: u64,
+    pub store_alert_percent: u64,
+    pub store_check_interval: u64,
+    pub scrub_interval: u64,
+    pub scrub_batch_size: u64,
+    pub header_read_timeout: u64,
+    pub request_timeout: u64,
+    pub max_concurrent_uploads: usize,
+    pub ip_daily_upload_count: u64,
+    pub ip_daily_upload_bytes: u64,
+    pub monthly_upload_cap: u64,
+    pub monthly_download_cap: u64,
+    pub startup_check: bool,
+    pub id_format: String,
+    pub id_length: usize,
+    pub geoip_country_database: Option<String>,
+    pub geoip_asn_database: Option<String>,
+    pub extract_max_files: usize,
+    pub extract_max_bytes: u64,
+    pub maintenance_message: String,
+    pub stream_downloads: bool,
+    pub memory_cache_bytes: u64,
+    pub memory_cache_max_file_size: u64,
+    pub database_read_url: Option<String>,
+    pub event_poll_interval: u64,
+    pub lockout_attempts: u32,
+    pub lockout_duration: u64,
+    pub lockout_max_duration: u64,
+    pub lockout_alert_attempts: u32,
+    pub download_offload: String,
+    pub download_offload_location: String,
+    pub compress_blobs: bool,
+    pub self_check: String,
+    pub self_check_min_free_bytes: u64,
+    pub route_policies: Vec<route_policy::RoutePolicy>,
+    pub replica_path: Option<String>,
+    pub replica_sync_interval: u64,
+    pub exhausted_grace_period: u64,
+    pub allowed_types: Vec<String>,
+    pub download_redirect_url: Option<String>,
+    pub download_redirect_region: String,
+    pub download_redirect_access_key: Option<String>,
+    pub download_redirect_secret_key: Option<String>,
+    pub download_redirect_expiry: u64,
+    pub quota_warning_thresholds: Vec<u64>,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub username_charset: String,
+    pub reserved_usernames: Vec<String>,
+    pub allowed_email_domains: Vec<String>,
+    pub log_max_bytes: u64,
+    pub log_max_files: usize,
+    pub log_rotate_interval: u64,
+    pub log_compress: bool,
+    pub change_log_retention: u64,
 }
 
-#[derive(FromRow, Serialize)]
-pub struct user {
+/// This struct represents a user in the database.
+/// It contains the user's API key, username, password,
+/// whether the user is an administrator and whether their email address is verified.
+/// It derives the `FromRow` trait from `sqlx`
+/// to allow it to be created from a database row.
+/// Flags are stored as integers because the sqlx Any driver
+/// can not decode SQLite booleans.
+#[derive(Clone, FromRow, Serialize, Deserialize)]
+pub struct User {
     pub key: String,
     pub username: String,
+    #[sqlx(try_from = "db::Text")]
     pub password: String,
+    pub is_admin: i32,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub webhook_url: Option<String>,
+    pub max_upload_bytes: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub email: Option<String>,
+    pub email_verified: i32,
+    pub storage_quota: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub pgp_key: Option<String>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub display_email: Option<String>,
+    pub default_download_limit: Option<i32>,
+    pub default_expiry: Option<i64>,
+    #[serde(default = "enabled")]
+    pub removal_notices: i32,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub default_visibility: Option<String>,
+}
+
+/// Returns 1, the default of flags that are on unless a user turns them off.
+fn enabled() -> i32 {
+    1
+}
+
+impl User {
+    /// Returns true if the user has the admin flag set.
+    pub fn is_admin(&self) -> bool {
+        self.is_admin != 0
+    }
+
+    /// Returns true if the user has confirmed their email address,
+    /// or registered while verification was not required.
+    pub fn is_verified(&self) -> bool {
+        self.email_verified != 0
+    }
+}
+
+/// This struct represents the query parameters of the `/all_files` and `/files` endpoints.
+/// `all` is only honoured for admin users
+/// and returns the files of every user instead of only the caller's.
+/// The other parameters narrow the listing down, all of them have to match.
+/// `uploaded_after` is a unix timestamp or an RFC 3339 date.
+/// `trash` lists the files in the trash instead of the available ones.
+/// `limit` returns the listing in pages, `after` is the cursor of the previous page.
+/// `order` is `asc` for the oldest files first or `desc` for the newest first.
+/// `human` adds the formatted fields of `HumanFile` to every file.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AllFilesQuery {
+    pub all: Option<bool>,
+    pub trash: Option<bool>,
+    pub tag: Option<String>,
+    pub name_contains: Option<String>,
+    pub content_type: Option<String>,
+    pub uploaded_after: Option<String>,
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+    pub order: Option<String>,
+    pub human: Option<bool>,
+}
+
+/// This struct represents a page of files returned by `/files`.
+/// `next` is passed as `after` to get the next page, it is null on the last page.
+#[derive(Serialize, ToSchema)]
+pub struct FilePage {
+    pub files: Vec<File>,
+    pub next: Option<String>,
+}
+
+/// This struct represents the query parameters of the `/files/changes` endpoint.
+/// `since` is the `cursor` of the previous response, or `latest` to only get the current cursor,
+/// every retained change is returned if it is missing.
+/// `limit` is the most changes in one response, at most 1000.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesQuery {
+    pub since: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// This struct represents a file that was created, updated or deleted, returned by `/files/changes`.
+/// `change` is `created`, `updated` or `deleted`, a file given to another user is deleted for its old owner
+/// and created for the new one. `file` is the file as it is now, null if it is gone or no longer owned by the user.
+#[derive(Serialize, ToSchema)]
+pub struct FileChange {
+    pub seq: i64,
+    pub change: String,
+    pub file_id: String,
+    pub changed_at: i64,
+    pub file: Option<File>,
+}
+
+/// This struct represents the changes returned by `/files/changes`, oldest first.
+/// `cursor` is passed as `since` to get the changes after these,
+/// `has_more` tells whether there are more changes right away.
+#[derive(Serialize, ToSchema)]
+pub struct ChangePage {
+    pub changes: Vec<FileChange>,
+    pub cursor: i64,
+    pub has_more: bool,
+}
+
+/// This struct represents the query parameters of the `/events` endpoint.
+/// `events` is a comma separated list of the event names of `/user/notifications`.
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    pub events: Option<String>,
+}
+
+/// This struct represents the query parameters of the `/public` endpoint.
+/// `q` matches the name of a file or one of its tags,
+/// `limit` is the size of a page and `after` the `x-next-cursor` of the previous one.
+/// `human` adds the formatted fields of `HumanFile` to every file.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PublicQuery {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+    pub human: Option<bool>,
+}
+
+/// This struct represents a file in the public directory.
+/// It only carries what visitors need to pick and download a file,
+/// `share_url` is the landing page of the file with its preview.
+#[derive(Serialize, ToSchema)]
+pub struct PublicFile {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub file_size: i64,
+    pub upload_time: i64,
+    pub owner: String,
+    pub download_url: String,
+    pub share_url: String,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human: Option<HumanFile>,
+}
+
+/// This struct represents a scoped token in the database, without the token itself.
+/// `scopes` and `content_types` are comma separated lists,
+/// `expires` is a unix timestamp, tokens without one never expire.
+/// A token with a `collection_id` only reaches the files of that collection, see `tokens::Scope::Download`.
+#[derive(Clone, FromRow)]
+pub struct ApiToken {
+    pub id: String,
+    pub username: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub name: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    pub scopes: String,
+    pub max_bytes: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub content_types: Option<String>,
+    pub expires: Option<i64>,
+    pub created: i64,
+    pub collection_id: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/user/tokens` endpoint.
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    pub name: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub max_bytes: Option<i64>,
+    pub expires_in: Option<u64>,
+    pub content_types: Option<Vec<String>>,
+    pub collection: Option<String>,
+}
+
+/// This struct represents the JSON body of the `/upload/presign` endpoint.
+#[derive(Deserialize)]
+pub struct PresignRequest {
+    pub max_size: Option<u64>,
+    pub content_types: Option<Vec<String>>,
+    pub expires_in: Option<u64>,
+}
+
+/// This struct represents a pre-signed upload URL returned by `/upload/presign`.
+/// `expires` is a unix timestamp, `max_size` is `None` if the upload limit of the user applies.
+#[derive(Serialize)]
+pub struct PresignedUpload {
+    pub url: String,
+    pub expires: i64,
+    pub max_size: Option<u64>,
+    pub content_types: Vec<String>,
+}
+
+/// This struct represents an API key of a user in the database, without the key itself.
+/// A user can have several keys, each with a name to tell them apart.
+/// `created`, `last_used_at` and `revoked_at` are unix timestamps,
+/// revoked keys are kept so the listing shows when they stopped working.
+#[derive(FromRow, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub username: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub name: Option<String>,
+    pub created: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiKey {
+    /// The columns `ApiKey` is read from, everything but the key itself.
+    pub const COLUMNS: &'static str = "id, username, name, created, last_used_at, revoked_at";
+}
+
+/// This struct represents the JSON body of the `/user/keys` endpoint.
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    pub name: Option<String>,
+}
+
+/// This struct represents the query parameters of the upload endpoints.
+/// `format=txt` returns only the download URL instead of JSON.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadQuery {
+    pub format: Option<String>,
+}
+
+/// This struct represents the options of an upload.
+/// Every option can be sent as a header or as a query parameter of the same name,
+/// for clients behind proxies that drop custom headers. A header wins over the query parameter.
+/// The values are kept as text and checked by the upload handler.
+#[derive(Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct UploadOptions {
+    /// The name of the file
+    pub file_name: Option<String>,
+    /// The download limit of the file, 0 for unlimited
+    pub download_limit: Option<String>,
+    /// A unique vanity name to download the file from `/d/{slug}`
+    pub slug: Option<String>,
+    /// `true` if the body was encrypted by the client
+    pub encrypted: Option<String>,
+    /// A comma separated list of tags
+    pub tags: Option<String>,
+    /// `public` or `private`
+    pub visibility: Option<String>,
+    /// An ID to follow the upload at `/upload/{upload_id}/progress`
+    pub upload_id: Option<String>,
+    /// The number of days without a download after which the file is removed
+    pub expire_if_unused_days: Option<String>,
+    /// `true` to unpack a zip or tar archive into a collection of its files
+    pub extract: Option<String>,
+    /// A unix timestamp or RFC 3339 date after which the file is removed, `never` to keep it
+    pub expires_at: Option<String>,
+    /// The seconds or a duration like `7d` after the upload after which the file is removed, `never` to keep it
+    pub max_age: Option<String>,
+    /// The `Cache-Control` header of downloads of the file, like `public, max-age=3600`
+    pub cache: Option<String>,
+    /// `true` to list the file in the public directory at `/public`
+    pub publish: Option<String>,
+    /// A note shown with the file, like what a build contains
+    pub description: Option<String>,
+}
+
+/// This struct represents the credentials of the `/user/register` and `/user/login` endpoints.
+/// They can be sent as a JSON body or as headers of the same name.
+#[derive(Deserialize, ToSchema, Default)]
+pub struct Credentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub email: Option<String>,
+    pub captcha: Option<String>,
+}
+
+/// This struct represents the query parameters of the download endpoints.
+/// `token` is a share token that unlocks a private file.
+/// `version` selects an earlier version of the file, the latest one is served without it.
+/// `stream` is the grant of a player page from `/play/{uuid}`, its range requests don't count as downloads.
+/// `inline` asks the browser to show the file instead of saving it.
+/// `human` adds the formatted fields of `HumanFile` to `/file/{uuid}/info`, downloads ignore it.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    pub token: Option<String>,
+    pub version: Option<i32>,
+    pub stream: Option<String>,
+    pub inline: Option<bool>,
+    pub human: Option<bool>,
+}
+
+/// This struct represents the query parameter of `DELETE /download/{uuid}`,
+/// the delete token returned with the upload.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteTokenQuery {
+    pub token: Option<String>,
+}
+
+/// This struct represents the query parameters of the torrent endpoint.
+/// `format=magnet` returns a magnet link instead of the .torrent.
+#[derive(Deserialize)]
+pub struct TorrentQuery {
+    pub format: Option<String>,
+}
+
+/// This struct represents the query parameters of the Atom feed of a user.
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    pub token: Option<String>,
+}
+
+/// This struct represents the query parameters of the Matrix media endpoints.
+/// `access_token` is an alternative to the bearer token for clients that can't send headers.
+#[derive(Deserialize)]
+pub struct MatrixUploadQuery {
+    pub filename: Option<String>,
+    pub access_token: Option<String>,
+}
+
+/// This struct represents the query parameters of an S3 `ListObjectsV2` request.
+/// The names follow the S3 API.
+#[derive(Deserialize)]
+pub struct ListObjectsQuery {
+    #[serde(rename = "list-type")]
+    pub list_type: Option<String>,
+    pub prefix: Option<String>,
+    pub delimiter: Option<String>,
+    #[serde(rename = "max-keys")]
+    pub max_keys: Option<usize>,
+    #[serde(rename = "continuation-token")]
+    pub continuation_token: Option<String>,
+    #[serde(rename = "start-after")]
+    pub start_after: Option<String>,
+}
+
+/// This struct represents the response to a successful registration.
+/// `key` authenticates the user from now on,
+/// `email_verified` is false until the link in the verification email was opened.
+#[derive(Serialize, ToSchema)]
+pub struct RegisteredUser {
+    pub key: String,
+    pub username: String,
+    pub email_verified: bool,
+}
+
+/// This struct represents a user as shown to admins.
+/// It leaves out the key and password
+/// and adds the number of files and bytes the user stores.
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct UserInfo {
+    pub username: String,
+    pub is_admin: i32,
+    pub max_upload_bytes: Option<i64>,
+    pub storage_quota: Option<i64>,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+/// This struct represents an entry of the audit log.
+/// `username` is empty for anonymous actions like downloads
+/// and `target` is the file ID or username the action was done to.
+/// `seq` is the place of the entry in the hash chain and `entry_hash` its hash,
+/// both are `None` for entries recorded before the chain existed.
+#[derive(FromRow, Serialize)]
+pub struct AuditEntry {
+    pub time: i64,
+    pub username: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    pub action: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub target: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    pub ip: String,
+    pub seq: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub entry_hash: Option<String>,
+}
+
+/// This struct represents the result of checking the hash chain of the audit log.
+/// `head` is the hash of the newest entry, kept somewhere else it proves later that no entry was removed from the end.
+/// `broken_at` is the sequence number of the first entry that does not fit the chain.
+/// `anonymized` entries belonged to deleted accounts, `unchained` ones were recorded before the chain existed.
+#[derive(Serialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: i64,
+    pub anonymized: i64,
+    pub unchained: i64,
+    pub head: Option<String>,
+    pub head_seq: Option<i64>,
+    pub broken_at: Option<i64>,
+    pub problem: Option<String>,
+}
+
+/// This struct represents the query parameters of the `/admin/audit` endpoint.
+/// `since` and `until` are unix timestamps or RFC 3339 dates.
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// This struct represents a single recorded download of a file.
+/// `ip` is anonymized before it is stored,
+/// `country` and `asn` are looked up from the full address if GeoIP databases are configured.
+#[derive(FromRow, Serialize)]
+pub struct Download {
+    pub time: i64,
+    #[sqlx(try_from = "db::Text")]
+    pub ip: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    pub asn: Option<i64>,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub asn_organization: Option<String>,
+}
+
+/// This struct represents the number of downloads of a file on one UTC day.
+#[derive(Serialize)]
+pub struct DailyDownloads {
+    pub day: String,
+    pub downloads: i64,
+}
+
+/// This struct represents the number of downloads from one country by its ISO code.
+#[derive(FromRow, Serialize)]
+pub struct CountryDownloads {
+    pub country: String,
+    pub downloads: i64,
+}
+
+/// This struct represents the number of downloads from one autonomous system,
+/// the network of a provider or company.
+#[derive(FromRow, Serialize)]
+pub struct NetworkDownloads {
+    pub asn: i64,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub asn_organization: Option<String>,
+    pub downloads: i64,
+}
+
+/// This struct represents the download statistics of a file shown to its owner.
+/// `countries` and `networks` leave out downloads without a known location,
+/// they stay empty without GeoIP databases.
+#[derive(Serialize)]
+pub struct FileStats {
+    pub id: String,
+    pub download_count: i32,
+    pub download_limit: i32,
+    pub per_day: Vec<DailyDownloads>,
+    pub countries: Vec<CountryDownloads>,
+    pub networks: Vec<NetworkDownloads>,
+    pub downloads: Vec<Download>,
+}
+
+/// This struct represents the download statistics of every file of a user returned by `/user/stats`.
+/// `countries` and `networks` are counted like in `FileStats`.
+#[derive(Serialize)]
+pub struct UserStats {
+    pub username: String,
+    pub downloads: i64,
+    pub per_day: Vec<DailyDownloads>,
+    pub countries: Vec<CountryDownloads>,
+    pub networks: Vec<NetworkDownloads>,
+}
+
+/// This struct represents what a garbage collection run cleaned up.
+/// `removed_files` are the IDs of files whose blob was missing
+/// and `pruned_rows` counts tag, collection and download rows of files that no longer exist.
+#[derive(Default, Serialize)]
+pub struct GcReport {
+    pub removed_blobs: Vec<String>,
+    pub removed_files: Vec<String>,
+    pub pruned_rows: u64,
+}
+
+/// This struct represents a blob the scrub job found missing, unreadable or changed.
+/// `files` are the IDs of the files that use the blob.
+#[derive(FromRow, Serialize)]
+pub struct IntegrityIssue {
+    pub content_hash: String,
+    pub issue: String,
+    #[sqlx(try_from = "db::Text")]
+    pub detail: String,
+    pub detected_at: i64,
+    pub checked_at: i64,
+    #[sqlx(skip)]
+    pub files: Vec<String>,
+}
+
+/// This struct represents the status of a background job returned by `/admin/jobs`.
+/// `interval` is the number of seconds between two runs, 0 if the job only runs when triggered.
+/// The times are unix timestamps, `last_error` is the error of the last run if it failed.
+#[derive(Clone, Default, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub interval: u64,
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_started: Option<i64>,
+    pub last_finished: Option<i64>,
+    pub last_duration_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub next_run: Option<i64>,
+}
+
+/// This struct represents the storage usage of a user returned by `/user/usage`.
+/// `quota_bytes` and `remaining_bytes` are `None` if the user has no storage quota.
+#[derive(Serialize, ToSchema)]
+pub struct Usage {
+    pub file_count: i64,
+    pub total_bytes: i64,
+    pub quota_bytes: Option<u64>,
+    pub remaining_bytes: Option<u64>,
+    pub transfer: TransferUsage,
+}
+
+/// This struct represents the bytes a user uploaded and downloaded this month.
+/// `upload_cap` and `download_cap` are `None` if the instance has no monthly cap,
+/// `history` lists the last months, newest first.
+#[derive(Serialize, ToSchema)]
+pub struct TransferUsage {
+    pub month: String,
+    pub upload_bytes: i64,
+    pub download_bytes: i64,
+    pub upload_cap: Option<u64>,
+    pub download_cap: Option<u64>,
+    pub history: Vec<UsagePeriod>,
+}
+
+/// This struct represents the transfer of a user in one month, like `2026-10`.
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct UsagePeriod {
+    pub month: String,
+    pub upload_bytes: i64,
+    pub download_bytes: i64,
+}
+
+/// This struct represents the public metadata of a file returned by `/file/<uuid>/info`.
+/// Unlike `File` it leaves out the owner and the content hash.
+/// `verified` is true if the owner signed the file with their registered public key.
+/// `limited_by` tells whether the download limit, the expiry date, both or neither end the file,
+/// `expires_in` is the number of seconds left until it expires.
+/// `sha256` is the hex digest of the contents, a download can be checked against it.
+#[derive(Serialize, ToSchema)]
+pub struct FileInfo {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub file_size: i64,
+    pub upload_time: i64,
+    pub download_limit: i32,
+    pub download_count: i32,
+    pub downloads_remaining: Option<i32>,
+    pub download_url: String,
+    pub encrypted: bool,
+    pub visibility: String,
+    pub expires: Option<i64>,
+    pub expires_in: Option<i64>,
+    pub limited_by: String,
+    pub version: i32,
+    pub verified: bool,
+    pub description: Option<String>,
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human: Option<HumanFile>,
+}
+
+/// This struct represents the response to `POST /admin/reload`,
+/// the config keys whose new values were applied.
+#[derive(Serialize)]
+pub struct Reload {
+    pub changed: Vec<String>,
+}
+
+/// This struct represents the log level at `/admin/log_level`, `previous` is the level a change replaced.
+#[derive(Serialize)]
+pub struct LogLevel {
+    pub level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+}
+
+/// This struct represents the JSON body of a `PUT` to `/admin/log_level`.
+#[derive(Deserialize)]
+pub struct LogLevelRequest {
+    pub level: String,
+}
+
+/// This struct represents the maintenance state of the instance at `/admin/maintenance`.
+/// `message` is what refused uploads and registrations get, `None` outside of maintenance.
+#[derive(Serialize)]
+pub struct Maintenance {
+    pub enabled: bool,
+    pub message: Option<String>,
+}
+
+/// This struct represents the JSON body of a `POST` to `/admin/maintenance`.
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    pub message: Option<String>,
+}
+
+/// This struct represents the instance statistics shown to admins.
+/// `stored_bytes` are counted against `max_store_bytes`, 0 means there is no cap,
+/// `disk_bytes` is the size of the data path at `disk_checked_at`, `None` before the first check.
+/// `store_alert` is true once the store passed `store_alert_percent` of the cap.
+/// The recent uploads and downloads are counted from the audit log, so removed files still count.
+/// `top_users` are the users storing the most bytes, largest first.
+#[derive(Serialize)]
+pub struct Stats {
+    pub total_users: i64,
+    pub total_files: i64,
+    pub total_bytes: i64,
+    pub total_downloads: i64,
+    pub stored_bytes: u64,
+    pub max_store_bytes: u64,
+    pub disk_bytes: Option<u64>,
+    pub disk_checked_at: Option<i64>,
+    pub store_alert: bool,
+    pub uploads_last_24h: i64,
+    pub uploads_last_7d: i64,
+    pub downloads_last_24h: i64,
+    pub downloads_last_7d: i64,
+    pub top_users: Vec<UserStorage>,
+    pub health: Health,
+}
+
+/// This struct represents the storage of a user in the instance statistics.
+#[derive(Serialize, FromRow)]
+pub struct UserStorage {
+    pub username: String,
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// This struct represents the health of the database and the storage in the instance statistics.
+/// `database_latency_ms` is the time a trivial query took,
+/// `storage_writable` is false if a probe file could not be written to the data path
+/// and `integrity_issues` counts the blobs the scrub job found missing or changed.
+#[derive(Serialize)]
+pub struct Health {
+    pub database_latency_ms: u64,
+    pub storage_writable: bool,
+    pub integrity_issues: i64,
+}
+
+/// This struct represents the deduplication report returned by `/admin/dedup/report`.
+/// Files and versions with the same content share one blob in the store,
+/// `logical_bytes` is what they would take without that and `stored_bytes` what the blobs take.
+/// `shared_blobs` are the blobs referenced more than once and `shared_files` the files and versions pointing to them.
+/// `top_groups` are the duplicate groups that save the most bytes, largest first.
+#[derive(Serialize)]
+pub struct DedupReport {
+    pub logical_files: i64,
+    pub unique_blobs: i64,
+    pub shared_blobs: i64,
+    pub shared_files: i64,
+    pub logical_bytes: i64,
+    pub stored_bytes: i64,
+    pub saved_bytes: i64,
+    pub top_groups: Vec<DuplicateGroup>,
+}
+
+/// This struct represents a blob shared by several files in the deduplication report.
+/// `saved_bytes` is the size of every copy after the first.
+#[derive(Serialize, FromRow)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub files: i64,
+    pub file_size: i64,
+    pub saved_bytes: i64,
+}
+
+/// This struct represents a metadata dump written by `bitbeam export` and `/admin/export`.
+/// It holds every user, with their key and password, and every file with its tags,
+/// so keep it as safe as the database itself.
+/// `version` is the format of the dump, `exported` the unix timestamp it was written at.
+#[derive(Serialize, Deserialize)]
+pub struct Dump {
+    pub version: u32,
+    pub exported: i64,
+    pub users: Vec<User>,
+    pub files: Vec<File>,
+}
+
+/// This struct represents the result of importing a dump.
+/// Users and files that already exist are skipped,
+/// `files_rejected` counts files with a content type or cache control that can't be sent as a header,
+/// `missing_blobs` counts imported files whose content is not in the data path.
+#[derive(Serialize, Default)]
+pub struct ImportSummary {
+    pub users_imported: u64,
+    pub users_skipped: u64,
+    pub files_imported: u64,
+    pub files_skipped: u64,
+    pub files_rejected: u64,
+    pub missing_blobs: u64,
 }
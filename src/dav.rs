@@ -0,0 +1,632 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Path, Request},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use http_body_util::Limited;
+use sqlx::AnyPool;
+use std::collections::HashSet;
+use std::fmt::Write;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::s3::{escape, uri_encode};
+use crate::storage;
+use crate::webhook;
+use std::net::SocketAddr;
+
+/// The methods the WebDAV endpoint answers.
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MKCOL, LOCK, UNLOCK";
+
+/// This enum represents what a WebDAV path points at.
+/// The root lists every file of the user and their collections as folders,
+/// a collection folder lists the files in that collection.
+enum Resource {
+    Root,
+    Collection(data::Collection),
+    /// A file name at the root or in a collection, the file may not exist yet.
+    File {
+        collection: Option<data::Collection>,
+        name: String,
+    },
+}
+
+/// Returns the response that asks the client for the username and key.
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"bitBeam\"")],
+        "Log in with your username and your key as the password",
+    )
+        .into_response()
+}
+
+/// Helper to authenticate a WebDAV request
+/// File managers only speak basic auth, so the username and the key of the user
+/// are sent as username and password.
+/// It returns the user or a response that asks for credentials again.
+async fn authenticate(pool: &AnyPool, headers: &HeaderMap, ip: &str) -> Result<data::User, Response> {
+    let credentials = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let Some((username, key)) = credentials.as_deref().and_then(|credentials| credentials.split_once(':')) else {
+        return Err(unauthorized());
+    };
+    let user = api::user_by_key(pool, key, ip).await.map_err(|_| unauthorized())?;
+    if user.username != username {
+        warn!("WebDAV login as {} with the key of {}", username, user.username);
+        audit::record_failed_login(pool, None, None, ip);
+        return Err(unauthorized());
+    }
+    Ok(user)
+}
+
+/// Helper to look up the collection of a user with the given name
+/// Collection names don't have to be unique, the oldest collection with the name is the folder.
+async fn find_collection(pool: &AnyPool, owner: &str, name: &str) -> Result<Option<data::Collection>, Response> {
+    sqlx::query_as::<_, data::Collection>(
+        r#"
+        SELECT *
+        FROM collections
+        WHERE owner = ? AND name = ?
+        ORDER BY created_at
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .fetch_all(pool)
+    .await
+    .map(|collections| collections.into_iter().next())
+    .map_err(|e| {
+        error!("DB select error {}: {}", name, e);
+        db::error_response(&e, "Database select error")
+    })
+}
+
+/// Helper to find out what the segments of a WebDAV path point at
+/// It returns `None` if a collection on the way does not exist or the path is too deep.
+async fn resolve(pool: &AnyPool, user: &data::User, segments: &[&str]) -> Result<Option<Resource>, Response> {
+    match segments {
+        [] => Ok(Some(Resource::Root)),
+        // a collection hides a file at the root with the same name
+        [name] => Ok(Some(match find_collection(pool, &user.username, name).await? {
+            Some(collection) => Resource::Collection(collection),
+            None => Resource::File {
+                collection: None,
+                name: name.to_string(),
+            },
+        })),
+        [folder, name] => Ok(find_collection(pool, &user.username, folder)
+            .await?
+            .map(|collection| Resource::File {
+                collection: Some(collection),
+                name: name.to_string(),
+            })),
+        _ => Ok(None),
+    }
+}
+
+/// Helper to list the files of a user at the root or in a collection
+/// Files in the trash or expired are left out, of the files that share a name only the newest is listed.
+/// With a name only the newest file with that name is returned.
+async fn list_files(
+    pool: &AnyPool,
+    owner: &str,
+    collection: Option<&data::Collection>,
+    name: Option<&str>,
+) -> Result<Vec<data::File>, Response> {
+    let mut sql = format!("SELECT {} FROM files", data::File::qualified_columns());
+    if collection.is_some() {
+        sql.push_str(" JOIN collection_files ON collection_files.file_id = files.id");
+    }
+    sql.push_str(" WHERE files.owner = ? AND files.deleted_at IS NULL");
+    if collection.is_some() {
+        sql.push_str(" AND collection_files.collection_id = ?");
+    }
+    if name.is_some() {
+        sql.push_str(" AND files.file_name = ?");
+    }
+    let mut query = sqlx::query_as::<_, data::File>(&sql).bind(owner);
+    if let Some(collection) = collection {
+        query = query.bind(&collection.id);
+    }
+    if let Some(name) = name {
+        query = query.bind(name);
+    }
+    let mut files = match query.fetch_all(pool).await {
+        Ok(files) => files,
+        Err(e) => {
+            error!("DB select error {}: {}", owner, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    files.retain(|file| file.is_available());
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name).then(b.upload_time.cmp(&a.upload_time)));
+    files.dedup_by(|older, newer| older.file_name == newer.file_name);
+    Ok(files)
+}
+
+/// Returns the href of a path below `/dav`, folders end with a slash.
+/// Hrefs start with the `base_path` the server is reached under.
+fn href(base_path: &str, segments: &[&str], folder: bool) -> String {
+    let mut href = format!("{}/dav/", base_path);
+    let encoded: Vec<String> = segments.iter().map(|segment| uri_encode(segment)).collect();
+    href.push_str(&encoded.join("/"));
+    if folder && !segments.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+/// Returns a timestamp in the format of HTTP dates.
+fn http_date(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Returns the ETag of a file, the SHA-256 of its contents.
+fn etag(file: &data::File) -> String {
+    format!("\"{}\"", file.content_hash.as_deref().unwrap_or(&file.id))
+}
+
+/// Appends the PROPFIND response of a folder, the root folder has no date.
+fn folder_response(xml: &mut String, href: &str, name: &str, created_at: Option<i64>) {
+    let modified = created_at
+        .map(|created_at| format!("<D:getlastmodified>{}</D:getlastmodified>", http_date(created_at)))
+        .unwrap_or_default();
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>{}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape(href),
+        escape(name),
+        modified
+    );
+}
+
+/// Appends the PROPFIND response of a file.
+fn file_response(xml: &mut String, href: &str, file: &data::File) {
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype/>\
+         <D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>\
+         <D:getetag>{}</D:getetag><D:getlastmodified>{}</D:getlastmodified>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape(href),
+        escape(&file.file_name),
+        file.file_size,
+        escape(&file.content_type),
+        escape(&etag(file)),
+        http_date(file.upload_time)
+    );
+}
+
+/// Handler for every WebDAV request below `/dav`
+/// This function lets file managers and tools like rclone mount the files of a user.
+/// The root folder holds every file of the user and one folder per collection,
+/// a collection folder holds the files in that collection.
+/// Files are identified by their name, of files that share a name the newest one is shown.
+/// - PROPFIND lists a folder, with `Depth: 0` only the folder or file itself
+/// - GET and HEAD read a file, reading through WebDAV does not count against the download limit,
+///   file managers read files on their own to show previews
+/// - PUT uploads a private file, a file with the same name is moved to the trash
+///   and the new one takes its place in its collections
+/// - DELETE moves a file at the root to the trash, removes a file from a collection folder
+///   or deletes a collection, the files in it are kept
+/// - MKCOL creates a collection at the root
+/// - LOCK and UNLOCK are accepted for clients that refuse to write without them,
+///   the locks are not enforced
+///
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PROPFIND -u <username>:<key> -H "Depth: 1" http://localhost:3000/dav/
+/// requires the following headers:
+/// - authorization: basic auth with the username and the key of the user as the password (not optional)
+/// - depth: `0` or `1` for PROPFIND, deeper listings are answered like `1` (optional)
+#[instrument(skip_all)]
+pub async fn handle(
+    path: Option<Path<String>>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    let path = path.map(|Path(path)| path).unwrap_or_default();
+    info!("Received WebDAV {} request for /{} from IP: {}", request.method(), path, ip);
+
+    // clients probe the server before they send credentials
+    if request.method() == Method::OPTIONS {
+        return (StatusCode::OK, [(header::ALLOW, ALLOW), (header::HeaderName::from_static("dav"), "1, 2")]).into_response();
+    }
+    let user = match authenticate(&pool, request.headers(), &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let resource = match resolve(&pool, &user, &segments).await {
+        Ok(Some(resource)) => resource,
+        Ok(None) if request.method().as_str() == "PUT" || request.method().as_str() == "MKCOL" => {
+            return (StatusCode::CONFLICT, "The parent folder does not exist").into_response()
+        }
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(response) => return response,
+    };
+
+    match request.method().as_str() {
+        "PROPFIND" => propfind(&pool, &config, &user, &segments, resource, request.headers()).await,
+        "GET" | "HEAD" => get(&pool, &config, &user, resource, request.method() == Method::HEAD).await,
+        "PUT" => put(&pool, &config, &user, resource, request, &ip).await,
+        "DELETE" => delete(&pool, &config, &user, resource, &ip).await,
+        "MKCOL" => mkcol(&pool, &user, resource).await,
+        "LOCK" => lock(),
+        "UNLOCK" => StatusCode::NO_CONTENT.into_response(),
+        _ => (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response(),
+    }
+}
+
+/// Helper to answer a PROPFIND request with the properties of a folder and its files or of a file.
+async fn propfind(
+    pool: &AnyPool,
+    config: &data::Config,
+    user: &data::User,
+    segments: &[&str],
+    resource: Resource,
+    headers: &HeaderMap,
+) -> Response {
+    let depth_zero = headers.get("depth").and_then(|hv| hv.to_str().ok()) == Some("0");
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+    match resource {
+        Resource::Root => {
+            folder_response(&mut xml, &href(&config.base_path, &[], true), &user.username, None);
+            if !depth_zero {
+                let collections = sqlx::query_as::<_, data::Collection>(
+                    r#"
+                    SELECT *
+                    FROM collections
+                    WHERE owner = ?
+                    ORDER BY created_at
+                    "#,
+                )
+                .bind(&user.username)
+                .fetch_all(pool)
+                .await;
+                let collections = match collections {
+                    Ok(collections) => collections,
+                    Err(e) => {
+                        error!("DB select error {}: {}", user.username, e);
+                        return db::error_response(&e, "Database select error");
+                    }
+                };
+                let mut listed = HashSet::new();
+                for collection in &collections {
+                    if listed.insert(collection.name.as_str()) {
+                        folder_response(&mut xml, &href(&config.base_path, &[&collection.name], true), &collection.name, Some(collection.created_at));
+                    }
+                }
+                let files = match list_files(pool, &user.username, None, None).await {
+                    Ok(files) => files,
+                    Err(response) => return response,
+                };
+                for file in files.iter().filter(|file| !listed.contains(file.file_name.as_str())) {
+                    file_response(&mut xml, &href(&config.base_path, &[&file.file_name], false), file);
+                }
+            }
+        }
+        Resource::Collection(collection) => {
+            folder_response(&mut xml, &href(&config.base_path, segments, true), &collection.name, Some(collection.created_at));
+            if !depth_zero {
+                let files = match list_files(pool, &user.username, Some(&collection), None).await {
+                    Ok(files) => files,
+                    Err(response) => return response,
+                };
+                for file in &files {
+                    file_response(&mut xml, &href(&config.base_path, &[&collection.name, &file.file_name], false), file);
+                }
+            }
+        }
+        Resource::File { collection, name } => {
+            let file = match list_files(pool, &user.username, collection.as_ref(), Some(&name)).await {
+                Ok(files) => files.into_iter().next(),
+                Err(response) => return response,
+            };
+            match file {
+                Some(file) => file_response(&mut xml, &href(&config.base_path, segments, false), &file),
+                None => return StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+    }
+    xml.push_str("</D:multistatus>");
+
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
+
+/// Helper to answer a GET or HEAD request for a file.
+async fn get(pool: &AnyPool, config: &data::Config, user: &data::User, resource: Resource, head: bool) -> Response {
+    let Resource::File { collection, name } = resource else {
+        return (StatusCode::METHOD_NOT_ALLOWED, "Folders can't be downloaded").into_response();
+    };
+    let file = match list_files(pool, &user.username, collection.as_ref(), Some(&name)).await {
+        Ok(files) => files.into_iter().next(),
+        Err(response) => return response,
+    };
+    let Some(file) = file else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, &file.content_type)
+        .header(header::ETAG, etag(&file))
+        .header(header::LAST_MODIFIED, http_date(file.upload_time));
+    if head {
+        let response = response.header(header::CONTENT_LENGTH, file.file_size);
+        return api::built(response.body(Body::empty()), &file.id);
+    }
+    match storage::read_cached_blob(config, file.blob_name(), file.file_size.max(0) as u64).await {
+        Ok(contents) => {
+            let response = response.header(header::CONTENT_LENGTH, contents.len());
+            api::built(response.body(Body::from(contents)), &file.id)
+        }
+        Err(e) => {
+            error!("File read error {}: {}", file.id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "File read error").into_response()
+        }
+    }
+}
+
+/// Helper to answer a PUT request
+/// This function uploads the body as a private file and moves the files it replaces to the trash.
+/// The new file is added to the collection of the folder and to every collection of the replaced files.
+async fn put(
+    pool: &AnyPool,
+    config: &data::Config,
+    user: &data::User,
+    resource: Resource,
+    request: Request,
+    ip: &str,
+) -> Response {
+    let Resource::File { collection, name } = resource else {
+        return (StatusCode::METHOD_NOT_ALLOWED, "A folder can't be overwritten").into_response();
+    };
+    if !user.is_verified() {
+        return api::unverified(user).into_response();
+    }
+
+    // like `/upload`, announced sizes over the limit are refused before the body is read
+    let limit = api::upload_limit(config, user, None);
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return api::too_large(limit).into_response();
+    }
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+    let body = match Bytes::from_request(request, &()).await {
+        Ok(body) => body,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return api::too_large(limit).into_response(),
+        Err(e) => {
+            warn!("Body read error: {}", e);
+            return e.into_response();
+        }
+    };
+
+    let replaced = match api::find_named(pool, &user.username, &name).await {
+        Ok(files) => files,
+        Err(response) => return response,
+    };
+    let mut collections: HashSet<String> = collection.iter().map(|collection| collection.id.clone()).collect();
+    for old in &replaced {
+        let ids = sqlx::query_scalar::<_, String>("SELECT collection_id FROM collection_files WHERE file_id = ?")
+            .bind(&old.id)
+            .fetch_all(pool)
+            .await;
+        match ids {
+            Ok(ids) => collections.extend(ids),
+            Err(e) => {
+                error!("DB select error {}: {}", old.id, e);
+                return db::error_response(&e, "Database select error");
+            }
+        }
+    }
+
+    let download_limit = match api::download_limit_allowed(config, 0) {
+        true => 0,
+        false => config.default_download_limit,
+    };
+    let new_file = data::NewFile {
+        file_name: name.clone(),
+        content_type,
+        download_limit,
+        owner: user.username.clone(),
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility: "private".to_string(),
+        allowed_content_types: Vec::new(),
+        expires: None,
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: None,
+        description: None,
+    };
+    let file = match api::store_file(pool, config, ip, new_file, body).await {
+        Ok(uploaded_file) => uploaded_file.file,
+        Err(response) => return response,
+    };
+    info!("WebDAV file {} stored as {} by {}", name, file.id, user.username);
+    audit::record(pool, audit::Action::Upload, Some(&user.username), Some(&file.id), ip).await;
+
+    for collection_id in &collections {
+        if let Err(e) = sqlx::query("INSERT INTO collection_files (collection_id, file_id) VALUES (?, ?)")
+            .bind(collection_id)
+            .bind(&file.id)
+            .execute(pool)
+            .await
+        {
+            error!("DB insert error {}: {}", collection_id, e);
+            return db::error_response(&e, "Database insert error");
+        }
+    }
+    for old in &replaced {
+        if api::trash_file(pool, config, old).await.is_err() {
+            warn!("Could not trash replaced WebDAV file {}", old.id);
+        }
+    }
+
+    match replaced.is_empty() {
+        true => StatusCode::CREATED.into_response(),
+        false => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Helper to answer a DELETE request.
+async fn delete(pool: &AnyPool, config: &data::Config, user: &data::User, resource: Resource, ip: &str) -> Response {
+    match resource {
+        Resource::Root => (StatusCode::FORBIDDEN, "The root folder can't be deleted").into_response(),
+        Resource::Collection(collection) => {
+            for statement in [
+                "DELETE FROM collection_files WHERE collection_id = ?",
+                "DELETE FROM collections WHERE id = ?",
+            ] {
+                if let Err(e) = sqlx::query(statement).bind(&collection.id).execute(pool).await {
+                    error!("DB delete error {}: {}", collection.id, e);
+                    return db::error_response(&e, "Database delete error");
+                }
+            }
+            info!("Collection {} deleted by {} over WebDAV", collection.id, user.username);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Resource::File {
+            collection: Some(collection),
+            name,
+        } => {
+            let files = match list_files(pool, &user.username, Some(&collection), Some(&name)).await {
+                Ok(files) => files,
+                Err(response) => return response,
+            };
+            let Some(file) = files.first() else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            if let Err(e) = sqlx::query("DELETE FROM collection_files WHERE collection_id = ? AND file_id = ?")
+                .bind(&collection.id)
+                .bind(&file.id)
+                .execute(pool)
+                .await
+            {
+                error!("DB delete error {}: {}", collection.id, e);
+                return db::error_response(&e, "Database delete error");
+            }
+            info!("File {} removed from collection {} over WebDAV", file.id, collection.id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Resource::File { collection: None, name } => {
+            let files = match api::find_named(pool, &user.username, &name).await {
+                Ok(files) => files,
+                Err(response) => return response,
+            };
+            if files.is_empty() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            for file in &files {
+                if let Err(response) = api::trash_file(pool, config, file).await {
+                    return response;
+                }
+                info!("WebDAV file {} deleted by owner {}: {}", name, user.username, file.id);
+                webhook::emit(webhook::EventKind::Deleted, file);
+                audit::record(pool, audit::Action::Delete, Some(&user.username), Some(&file.id), ip).await;
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// Helper to answer a MKCOL request by creating a collection.
+async fn mkcol(pool: &AnyPool, user: &data::User, resource: Resource) -> Response {
+    let name = match resource {
+        Resource::File { collection: None, name } => name,
+        // collections can't be nested
+        Resource::File { collection: Some(_), .. } => {
+            return (StatusCode::FORBIDDEN, "Folders can only be created at the root").into_response()
+        }
+        Resource::Root | Resource::Collection(_) => return StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    };
+    match api::find_named(pool, &user.username, &name).await {
+        Ok(files) if !files.is_empty() => return StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        Ok(_) => {}
+        Err(response) => return response,
+    }
+
+    let collection = data::Collection {
+        id: Uuid::new_v4().to_string(),
+        name,
+        owner: user.username.clone(),
+        created_at: Utc::now().timestamp(),
+    };
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO collections (id, name, owner, created_at)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(&collection.id)
+    .bind(&collection.name)
+    .bind(&collection.owner)
+    .bind(collection.created_at)
+    .execute(pool)
+    .await
+    {
+        error!("DB insert error {}: {}", collection.id, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("Collection {} created by {} over WebDAV", collection.id, collection.owner);
+
+    StatusCode::CREATED.into_response()
+}
+
+/// Helper to answer a LOCK request with a lock nobody else has to respect.
+fn lock() -> Response {
+    let token = format!("opaquelocktoken:{}", Uuid::new_v4());
+    let xml = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><D:prop xmlns:D="DAV:"><D:lockdiscovery><D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope><D:depth>0</D:depth><D:timeout>Second-3600</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken></D:activelock></D:lockdiscovery></D:prop>"#,
+        token
+    );
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/xml; charset=utf-8".to_string()),
+            (header::HeaderName::from_static("lock-token"), format!("<{}>", token)),
+        ],
+        xml,
+    )
+        .into_response()
+}
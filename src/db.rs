@@ -0,0 +1,1024 @@
+use axum::response::{IntoResponse, Response};
+use chrono::Utc;
+use rand::Rng;
+use sqlx::any::{AnyPoolOptions, AnyTypeInfo, AnyTypeInfoKind, AnyValueRef};
+use sqlx::error::BoxDynError;
+use sqlx::{Any, AnyConnection, AnyPool, Decode, Executor, QueryBuilder, Type, ValueRef};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::data;
+use crate::error::ApiError;
+
+/// The pool of the read replica, handed to handlers as `Extension<db::ReadPool>`.
+/// Listings, statistics and metadata lookups read from it, everything that writes
+/// or has to see its own writes, like authentication and downloads, uses the primary pool.
+/// Without `database_read_url` it is the primary pool.
+#[derive(Clone)]
+pub struct ReadPool(pub AnyPool);
+
+/// This function opens the pool of the read replica configured with `database_read_url`,
+/// with the same settings as the primary pool.
+/// It connects on first use, so a replica that is down only fails the reads sent to it.
+/// Without a replica, or with SQLite which has none, reads go to the primary pool.
+pub(crate) fn read_pool(config: &data::Config, pool: &AnyPool) -> Result<ReadPool, sqlx::Error> {
+    let Some(url) = &config.database_read_url else {
+        return Ok(ReadPool(pool.clone()));
+    };
+    if config.db_type == "sqlite" {
+        warn!("SQLite has no read replicas, database_read_url is ignored");
+        return Ok(ReadPool(pool.clone()));
+    }
+    let statement_timeout = config.db_statement_timeout;
+    let replica = AnyPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(Duration::from_secs(config.db_acquire_timeout))
+        .after_connect(move |connection, _meta| Box::pin(prepare_connection(connection, statement_timeout)))
+        .connect_lazy(url)?;
+    info!("Sending listings, statistics and metadata lookups to the read replica");
+    Ok(ReadPool(replica))
+}
+
+/// The session settings every MySQL connection starts with.
+/// `ANSI_QUOTES` lets `"key"` name the column like in SQLite and Postgres,
+/// `NO_BACKSLASH_ESCAPES` keeps `ESCAPE '\'` in LIKE filters a plain backslash.
+const MYSQL_SESSION: &str = "SET SESSION sql_mode = CONCAT(@@sql_mode, ',ANSI_QUOTES,NO_BACKSLASH_ESCAPES')";
+
+/// This function sets up a new connection of the pool.
+/// MySQL gets the settings it needs to read the same SQL as the other databases,
+/// and a `statement_timeout` in seconds, 0 for none, limits how long a query may run.
+/// SQLite has no statement timeout.
+pub(crate) async fn prepare_connection(connection: &mut AnyConnection, statement_timeout: u64) -> Result<(), sqlx::Error> {
+    let millis = statement_timeout * 1000;
+    match connection.backend_name() {
+        "MySQL" => {
+            connection.execute(MYSQL_SESSION).await?;
+            if statement_timeout > 0 {
+                let mysql = format!("SET SESSION max_execution_time = {}", millis);
+                if connection.execute(mysql.as_str()).await.is_err() {
+                    // MariaDB names it differently and counts in seconds
+                    let mariadb = format!("SET SESSION max_statement_time = {}", statement_timeout);
+                    connection.execute(mariadb.as_str()).await?;
+                }
+            }
+        }
+        "PostgreSQL" if statement_timeout > 0 => {
+            connection.execute(format!("SET statement_timeout = {}", millis).as_str()).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns the error for a failed query.
+/// If every connection of the pool stayed busy for `db_acquire_timeout`,
+/// the server is overloaded rather than broken, so the client is asked to retry later.
+/// Otherwise the client only sees the message, never the database error.
+pub(crate) fn error(e: &sqlx::Error, message: &'static str) -> ApiError {
+    match e {
+        sqlx::Error::PoolTimedOut => {
+            warn!("All database connections are busy");
+            ApiError::Busy
+        }
+        _ => ApiError::Internal(message),
+    }
+}
+
+/// Returns the response for a failed query, see `error`.
+pub(crate) fn error_response(e: &sqlx::Error, message: &'static str) -> Response {
+    error(e, message).into_response()
+}
+
+/// Returns true if the pool is connected to MySQL or MariaDB.
+pub(crate) fn is_mysql(pool: &AnyPool) -> bool {
+    matches!(pool.connect_options().database_url.scheme(), "mysql" | "mariadb")
+}
+
+/// Returns the SQL for the sum of an integer column as a BIGINT, 0 if there are no rows.
+/// MySQL and Postgres sum integers into decimals, which the Any driver can't decode.
+pub(crate) fn sum(pool: &AnyPool, column: &str) -> String {
+    let bigint = match is_mysql(pool) {
+        true => "SIGNED",
+        false => "BIGINT",
+    };
+    format!("CAST(COALESCE(SUM({}), 0) AS {})", column, bigint)
+}
+
+/// Returns a schema statement written for SQLite in the dialect of the database.
+/// On MySQL TEXT holds only 64 KiB, so it becomes MEDIUMTEXT there,
+/// and tables compare text byte by byte like the other databases do.
+/// MySQL can't index TEXT columns, so keys and indexed columns are declared VARCHAR(255) everywhere.
+pub(crate) fn ddl(pool: &AnyPool, sql: &str) -> String {
+    if !is_mysql(pool) {
+        return sql.to_string();
+    }
+    let mut sql = sql.replace(" TEXT", " MEDIUMTEXT");
+    if sql.trim_start().starts_with("CREATE TABLE") {
+        if let Some(end) = sql.rfind(')') {
+            sql.insert_str(end + 1, " CHARACTER SET utf8mb4 COLLATE utf8mb4_bin");
+        }
+    }
+    sql
+}
+
+/// This function adds a column to a table created before the column existed.
+/// The column is looked up first, so a failing `ALTER TABLE` is a real error and returned.
+pub(crate) async fn add_column(pool: &AnyPool, table: &str, column: &str, definition: &str) -> Result<(), sqlx::Error> {
+    let probe = format!("SELECT {} FROM {} WHERE 1 = 0", column, table);
+    if sqlx::query(&probe).execute(pool).await.is_ok() {
+        debug!("{}.{} already exists", table, column);
+        return Ok(());
+    }
+    let alter = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+    if let Err(e) = sqlx::query(&ddl(pool, &alter)).execute(pool).await {
+        error!("Could not add {}.{}: {}", table, column, e);
+        return Err(e);
+    }
+    info!("Added {}.{}", table, column);
+    Ok(())
+}
+
+/// This function turns the VARCHAR(2048) columns earlier versions created for TEXT on MySQL into MEDIUMTEXT,
+/// so longer values like PGP keys fit. It does nothing on the other databases.
+pub(crate) async fn widen_text_columns(pool: &AnyPool) -> Result<(), sqlx::Error> {
+    if !is_mysql(pool) {
+        return Ok(());
+    }
+    let columns = sqlx::query_as::<_, (String, String, String)>(
+        r#"
+        SELECT CAST(table_name AS CHAR(255)), CAST(column_name AS CHAR(255)), CAST(is_nullable AS CHAR(3))
+        FROM information_schema.columns
+        WHERE table_schema = DATABASE() AND data_type = 'varchar' AND character_maximum_length = 2048
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for (table, column, nullable) in columns {
+        let null = match nullable.as_str() {
+            "YES" => "NULL",
+            _ => "NOT NULL",
+        };
+        let sql = format!("ALTER TABLE {} MODIFY {} MEDIUMTEXT {}", table, column, null);
+        if let Err(e) = sqlx::query(&sql).execute(pool).await {
+            error!("Could not widen {}.{}: {}", table, column, e);
+            return Err(e);
+        }
+        info!("Widened {}.{} to MEDIUMTEXT", table, column);
+    }
+    Ok(())
+}
+
+/// This struct reads a TEXT column.
+/// MySQL hands TEXT columns to the Any driver as blobs, which `String` refuses,
+/// so fields and queries reading them go through `Text`. Other databases hand over plain text.
+pub(crate) struct Text(String);
+
+impl Type<Any> for Text {
+    fn type_info() -> AnyTypeInfo {
+        <String as Type<Any>>::type_info()
+    }
+
+    fn compatible(ty: &AnyTypeInfo) -> bool {
+        matches!(ty.kind(), AnyTypeInfoKind::Text | AnyTypeInfoKind::Blob)
+    }
+}
+
+impl<'r> Decode<'r, Any> for Text {
+    fn decode(value: AnyValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.type_info().kind() {
+            AnyTypeInfoKind::Blob => Ok(Text(String::from_utf8(<Vec<u8> as Decode<Any>>::decode(value)?)?)),
+            _ => Ok(Text(<String as Decode<Any>>::decode(value)?)),
+        }
+    }
+}
+
+impl From<Text> for String {
+    fn from(text: Text) -> Self {
+        text.0
+    }
+}
+
+/// This struct reads a TEXT column that can be NULL, see `Text`.
+pub(crate) struct OptionalText(Option<String>);
+
+impl Type<Any> for OptionalText {
+    fn type_info() -> AnyTypeInfo {
+        <Text as Type<Any>>::type_info()
+    }
+
+    fn compatible(ty: &AnyTypeInfo) -> bool {
+        <Text as Type<Any>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Any> for OptionalText {
+    fn decode(value: AnyValueRef<'r>) -> Result<Self, BoxDynError> {
+        if value.is_null() {
+            return Ok(OptionalText(None));
+        }
+        Ok(OptionalText(Some(Text::decode(value)?.into())))
+    }
+}
+
+impl From<OptionalText> for Option<String> {
+    fn from(text: OptionalText) -> Self {
+        text.0
+    }
+}
+
+/// This function creates an index from a `CREATE INDEX IF NOT EXISTS` statement.
+/// MySQL doesn't know `IF NOT EXISTS` for indexes, so the index is looked up first there.
+pub(crate) async fn create_index(pool: &AnyPool, name: &str, sql: &str) -> Result<(), sqlx::Error> {
+    if !is_mysql(pool) {
+        sqlx::query(sql).execute(pool).await?;
+        return Ok(());
+    }
+    let exists = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*)
+        FROM information_schema.statistics
+        WHERE table_schema = DATABASE() AND index_name = ?
+        "#,
+    )
+    .bind(name)
+    .fetch_one(pool)
+    .await?;
+    if exists > 0 {
+        debug!("index {} already exists", name);
+        return Ok(());
+    }
+    sqlx::query(&sql.replace(" IF NOT EXISTS", "")).execute(pool).await?;
+    Ok(())
+}
+
+// The typed queries below hold the SQL of the handlers, so the handlers only deal with
+// HTTP and errors and every query of a table can be found in one place.
+// They are checked when they run, not when they are compiled: the `query!` macros of sqlx
+// need a fixed database and can't check queries for the Any driver every pool uses.
+
+/// This struct narrows down a listing of files, see `list_files`.
+/// Every filter that is set has to match.
+#[derive(Default)]
+pub(crate) struct FileFilter<'a> {
+    /// Only the files of this owner, every file if `None`.
+    pub owner: Option<&'a str>,
+    /// The files in the trash instead of the available ones.
+    pub trash: bool,
+    pub tag: Option<&'a str>,
+    /// Only files whose name contains this text.
+    pub name_contains: Option<&'a str>,
+    /// Only files of this content type, `image/*` matches a whole group.
+    pub content_type: Option<&'a str>,
+    /// Only files uploaded after this unix timestamp.
+    pub uploaded_after: Option<i64>,
+    /// Only files after this upload time and ID in the order of the listing, the last file of the previous page.
+    pub after: Option<(i64, &'a str)>,
+    /// Newest files first instead of oldest first.
+    pub descending: bool,
+    /// The most files returned.
+    pub limit: Option<i64>,
+}
+
+/// Returns the text with the LIKE wildcards escaped, so it only matches literally.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Returns the files that match the filter, ordered by upload time and ID, the newest first if `descending` is set.
+/// User input is only ever bound, never formatted into the query.
+pub(crate) async fn list_files(pool: &AnyPool, filter: &FileFilter<'_>) -> Result<Vec<data::File>, sqlx::Error> {
+    let mut select = QueryBuilder::<Any>::new(format!("SELECT {} FROM files WHERE 1 = 1", data::File::COLUMNS));
+    match filter.trash {
+        true => select.push(" AND deleted_at IS NOT NULL"),
+        false => select.push(" AND deleted_at IS NULL"),
+    };
+    if let Some(owner) = filter.owner {
+        select.push(" AND owner = ").push_bind(owner.to_string());
+    }
+    if let Some(tag) = filter.tag {
+        select
+            .push(" AND id IN (SELECT file_id FROM file_tags WHERE tag = ")
+            .push_bind(tag.trim().to_lowercase())
+            .push(")");
+    }
+    if let Some(name) = filter.name_contains {
+        select
+            .push(" AND file_name LIKE ")
+            .push_bind(format!("%{}%", escape_like(name)))
+            .push(" ESCAPE '\\'");
+    }
+    if let Some(content_type) = filter.content_type {
+        // `image/*` matches every image type
+        match content_type.strip_suffix('*') {
+            Some(prefix) => select
+                .push(" AND content_type LIKE ")
+                .push_bind(format!("{}%", escape_like(prefix)))
+                .push(" ESCAPE '\\'"),
+            None => select.push(" AND content_type = ").push_bind(content_type.to_string()),
+        };
+    }
+    if let Some(uploaded_after) = filter.uploaded_after {
+        select.push(" AND upload_time > ").push_bind(uploaded_after);
+    }
+    // pages continue after the last file of the previous page, so no rows are skipped by an offset
+    let (comparison, order) = match filter.descending {
+        true => ("<", " ORDER BY upload_time DESC, id DESC"),
+        false => (">", " ORDER BY upload_time, id"),
+    };
+    if let Some((upload_time, id)) = filter.after {
+        select
+            .push(format!(" AND (upload_time {} ", comparison))
+            .push_bind(upload_time)
+            .push(" OR (upload_time = ")
+            .push_bind(upload_time)
+            .push(format!(" AND id {} ", comparison))
+            .push_bind(id.to_string())
+            .push("))");
+    }
+    select.push(order);
+    if let Some(limit) = filter.limit {
+        select.push(" LIMIT ").push_bind(limit);
+    }
+    select.build_query_as::<data::File>().fetch_all(pool).await
+}
+
+/// Returns the published files that can still be downloaded, newest first.
+/// `search` matches the name of a file or one of its tags,
+/// `before` is the upload time and ID of the last file of the previous page.
+pub(crate) async fn list_published(
+    pool: &AnyPool,
+    search: Option<&str>,
+    before: Option<(i64, &str)>,
+    limit: i64,
+) -> Result<Vec<data::File>, sqlx::Error> {
+    let mut select = QueryBuilder::<Any>::new(format!(
+        "SELECT {} FROM files WHERE published != 0 AND visibility = 'public' AND deleted_at IS NULL",
+        data::File::COLUMNS
+    ));
+    select
+        .push(" AND (pinned != 0 OR expires IS NULL OR expires > ")
+        .push_bind(Utc::now().timestamp())
+        .push(") AND (download_limit = 0 OR download_count < download_limit)");
+    if let Some(search) = search.map(str::trim).filter(|search| !search.is_empty()) {
+        select
+            .push(" AND (file_name LIKE ")
+            .push_bind(format!("%{}%", escape_like(search)))
+            .push(" ESCAPE '\\' OR id IN (SELECT file_id FROM file_tags WHERE tag = ")
+            .push_bind(search.to_lowercase())
+            .push("))");
+    }
+    if let Some((upload_time, id)) = before {
+        select
+            .push(" AND (upload_time < ")
+            .push_bind(upload_time)
+            .push(" OR (upload_time = ")
+            .push_bind(upload_time)
+            .push(" AND id < ")
+            .push_bind(id.to_string())
+            .push("))");
+    }
+    select.push(" ORDER BY upload_time DESC, id DESC LIMIT ").push_bind(limit);
+    select.build_query_as::<data::File>().fetch_all(pool).await
+}
+
+/// This function lists or unlists a file in the public directory and returns it.
+pub(crate) async fn set_published(pool: &AnyPool, id: &str, published: bool) -> Result<data::File, sqlx::Error> {
+    sqlx::query("UPDATE files SET published = ? WHERE id = ?")
+        .bind(published as i32)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+/// This function pins or unpins a file and returns it.
+pub(crate) async fn set_pinned(pool: &AnyPool, id: &str, pinned: bool) -> Result<data::File, sqlx::Error> {
+    sqlx::query("UPDATE files SET pinned = ? WHERE id = ?")
+        .bind(pinned as i32)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+/// Returns the file IDs and tags of the files, ordered by tag.
+pub(crate) async fn file_tags(pool: &AnyPool, file_ids: &[&str]) -> Result<Vec<(String, String)>, sqlx::Error> {
+    if file_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut select = QueryBuilder::<Any>::new("SELECT file_id, tag FROM file_tags WHERE file_id IN (");
+    let mut ids = select.separated(", ");
+    for id in file_ids {
+        ids.push_bind(id.to_string());
+    }
+    select.push(") ORDER BY tag");
+    select.build_query_as::<(String, String)>().fetch_all(pool).await
+}
+
+/// Returns the file with the ID, including expired and trashed files.
+pub(crate) async fn find_file(pool: &AnyPool, id: &str) -> Result<Option<data::File>, sqlx::Error> {
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns the files of the owner with one of the IDs, including expired and trashed files.
+pub(crate) async fn find_owned_files(pool: &AnyPool, owner: &str, ids: &[&str]) -> Result<Vec<data::File>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut select = QueryBuilder::<Any>::new(format!("SELECT {} FROM files WHERE owner = ", data::File::COLUMNS));
+    select.push_bind(owner.to_string());
+    select.push(" AND id IN (");
+    let mut separated = select.separated(", ");
+    for id in ids {
+        separated.push_bind(id.to_string());
+    }
+    select.push(")");
+    select.build_query_as::<data::File>().fetch_all(pool).await
+}
+
+/// Returns the file with the slug, including expired and trashed files.
+pub(crate) async fn find_file_by_slug(pool: &AnyPool, slug: &str) -> Result<Option<data::File>, sqlx::Error> {
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE slug = ?", data::File::COLUMNS))
+        .bind(slug)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns the files of the owner with the name that aren't in the trash, the newest first.
+pub(crate) async fn find_named_files(pool: &AnyPool, owner: &str, file_name: &str) -> Result<Vec<data::File>, sqlx::Error> {
+    sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE owner = ? AND file_name = ? AND deleted_at IS NULL
+        ORDER BY upload_time DESC
+        "#,
+        data::File::COLUMNS,
+    ))
+    .bind(owner)
+    .bind(file_name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns true if a file uses the slug.
+pub(crate) async fn slug_exists(pool: &AnyPool, slug: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*)
+        FROM files
+        WHERE slug = ?
+        "#,
+    )
+    .bind(slug)
+    .fetch_one(pool)
+    .await
+    .map(|count| count > 0)
+}
+
+/// This function inserts the `files` row and the tags of a file.
+pub(crate) async fn insert_file(connection: &mut AnyConnection, file: &data::File) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO files
+            (id, content_type, upload_time, download_limit, download_count, file_size, download_url, file_name, owner, content_hash, slug, encrypted, detected_content_type, visibility, expires, cid, expire_if_unused_days, cache_control, published, description)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&file.id)
+    .bind(&file.content_type)
+    .bind(file.upload_time)
+    .bind(file.download_limit)
+    .bind(file.download_count)
+    .bind(file.file_size)
+    .bind(&file.download_url)
+    .bind(&file.file_name)
+    .bind(&file.owner)
+    .bind(&file.content_hash)
+    .bind(&file.slug)
+    .bind(file.encrypted)
+    .bind(&file.detected_content_type)
+    .bind(&file.visibility)
+    .bind(file.expires)
+    .bind(&file.cid)
+    .bind(file.expire_if_unused_days)
+    .bind(&file.cache_control)
+    .bind(file.published)
+    .bind(&file.description)
+    .execute(&mut *connection)
+    .await?;
+    for tag in &file.tags {
+        sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?, ?)")
+            .bind(&file.id)
+            .bind(tag)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+/// This function counts a download of the file unless its download limit is already reached.
+/// The limit is checked in the same statement so concurrent downloads
+/// can never push the count past the limit.
+/// It returns false if the limit was reached.
+pub(crate) async fn increment_download(pool: &AnyPool, id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET download_count = download_count + 1, last_downloaded_at = ?
+        WHERE id = ? AND (download_limit = 0 OR download_count < download_limit)
+        "#,
+    )
+    .bind(Utc::now().timestamp())
+    .bind(id)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// This function takes back a download counted by `increment_download`.
+pub(crate) async fn decrement_download(pool: &AnyPool, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET download_count = download_count - 1
+        WHERE id = ? AND download_count > 0
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Returns the current download count of the file, `None` if it is gone.
+pub(crate) async fn download_count(pool: &AnyPool, id: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>(
+        r#"
+        SELECT download_count
+        FROM files
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns the number of files of the owner and their size in bytes, including the trash.
+pub(crate) async fn storage_usage(pool: &AnyPool, owner: &str) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as::<_, (i64, i64)>(&format!(
+        r#"
+        SELECT COUNT(*), {}
+        FROM files
+        WHERE owner = ?
+        "#,
+        sum(pool, "file_size"),
+    ))
+    .bind(owner)
+    .fetch_one(pool)
+    .await
+}
+
+/// This function records the IPFS CID the blob of a file was mirrored as.
+pub(crate) async fn set_cid(pool: &AnyPool, id: &str, cid: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE files SET cid = ? WHERE id = ?")
+        .bind(cid)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+/// This function marks a file that used up its download limit, the cleanup task trashes it later.
+/// It returns false if the file was marked already.
+pub(crate) async fn mark_exhausted(pool: &AnyPool, id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query("UPDATE files SET exhausted_at = ? WHERE id = ? AND exhausted_at IS NULL")
+        .bind(Utc::now().timestamp())
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// This function moves the file to the trash.
+pub(crate) async fn trash_file(pool: &AnyPool, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET deleted_at = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(Utc::now().timestamp())
+    .bind(id)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// This function takes the file out of the trash and returns it.
+/// A file that was trashed for reaching its download limit gets its downloads back.
+pub(crate) async fn restore_file(pool: &AnyPool, id: &str) -> Result<data::File, sqlx::Error> {
+    // MySQL has no RETURNING, the row is read again after the update
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET deleted_at = NULL,
+            exhausted_at = NULL,
+            download_count = CASE WHEN download_limit > 0 AND download_count >= download_limit THEN 0 ELSE download_count END
+        WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+/// This function gives the file to another owner and returns it.
+/// Collections only hold files of their owner, so the file leaves them.
+pub(crate) async fn transfer_file(pool: &AnyPool, id: &str, to: &str) -> Result<data::File, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET owner = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(to)
+    .bind(id)
+    .execute(&mut *transaction)
+    .await?;
+    let transferred = sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_one(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM collection_files WHERE file_id = ?")
+        .bind(id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(transferred)
+}
+
+/// This function changes the description of a file and returns the file.
+pub(crate) async fn set_description(pool: &AnyPool, id: &str, description: Option<&str>) -> Result<data::File, sqlx::Error> {
+    sqlx::query("UPDATE files SET description = ? WHERE id = ?")
+        .bind(description)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_one(pool)
+        .await
+}
+
+/// This function deletes the `files` row and returns it,
+/// `None` if it was already deleted, by a concurrent removal for example.
+pub(crate) async fn delete_file(pool: &AnyPool, id: &str) -> Result<Option<data::File>, sqlx::Error> {
+    // MySQL has no RETURNING, so the row is read and deleted in one transaction
+    let mut transaction = pool.begin().await?;
+    let row = sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(id)
+        .fetch_optional(&mut *transaction)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM files WHERE id = ?")
+        .bind(id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(row.filter(|_| deleted.rows_affected() > 0))
+}
+
+/// This function deletes the earlier versions of the file and returns them.
+pub(crate) async fn delete_versions(pool: &AnyPool, file_id: &str) -> Result<Vec<data::FileVersion>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let versions = sqlx::query_as::<_, data::FileVersion>("SELECT * FROM file_versions WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_all(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM file_versions WHERE file_id = ?")
+        .bind(file_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(versions)
+}
+
+/// The tables with rows that belong to a file and go with it.
+pub(crate) const FILE_TABLES: [&str; 6] = [
+    "collection_files",
+    "file_tags",
+    "downloads",
+    "share_tokens",
+    "delete_tokens",
+    "file_signatures",
+];
+
+/// This function deletes the rows of the file in one of the `FILE_TABLES`.
+pub(crate) async fn delete_file_rows(pool: &AnyPool, table: &'static str, file_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("DELETE FROM {} WHERE file_id = ?", table))
+        .bind(file_id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+/// This function inserts the one-time delete token of a new file.
+pub(crate) async fn insert_delete_token(
+    connection: &mut AnyConnection,
+    file_id: &str,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO delete_tokens
+            (token, file_id, created)
+        VALUES (?, ?, ?)
+        "#,
+    )
+    .bind(token)
+    .bind(file_id)
+    .bind(Utc::now().timestamp())
+    .execute(&mut *connection)
+    .await
+    .map(|_| ())
+}
+
+/// This function adds a new file to a collection.
+pub(crate) async fn insert_collection_file(
+    connection: &mut AnyConnection,
+    collection_id: &str,
+    file_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO collection_files (collection_id, file_id)
+        VALUES (?, ?)
+        "#,
+    )
+    .bind(collection_id)
+    .bind(file_id)
+    .execute(&mut *connection)
+    .await
+    .map(|_| ())
+}
+
+/// This function uses up the delete token of a file,
+/// it returns false if the token does not belong to the file or was used already.
+pub(crate) async fn take_delete_token(pool: &AnyPool, file_id: &str, token: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query("DELETE FROM delete_tokens WHERE token = ? AND file_id = ?")
+        .bind(token)
+        .bind(file_id)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() == 1)
+}
+
+/// Returns the user with the API key, `None` if the key is unknown or revoked.
+pub(crate) async fn find_user_by_key(pool: &AnyPool, key: &str) -> Result<Option<data::User>, sqlx::Error> {
+    sqlx::query_as::<_, data::User>(
+        r#"
+        SELECT users.*
+        FROM api_keys
+        JOIN users ON users.username = api_keys.username
+        WHERE api_keys."key" = ? AND api_keys.revoked_at IS NULL
+        "#,
+    )
+    .bind(key)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns the user with the name, `None` if there is none.
+pub(crate) async fn find_user(pool: &AnyPool, username: &str) -> Result<Option<data::User>, sqlx::Error> {
+    sqlx::query_as::<_, data::User>("SELECT * FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns true if there is a user with the name.
+pub(crate) async fn user_exists(pool: &AnyPool, username: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .map(|count| count > 0)
+}
+
+/// Returns the storage quota set for the user, `None` if the server's quota applies.
+pub(crate) async fn user_storage_quota(pool: &AnyPool, username: &str) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<i64>>("SELECT storage_quota FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+        .map(Option::flatten)
+}
+
+/// This struct is a user to be inserted by `insert_user`.
+pub(crate) struct NewUser<'a> {
+    pub key: &'a str,
+    pub username: &'a str,
+    pub password: &'a str,
+    pub is_admin: bool,
+    pub email: Option<&'a str>,
+    /// The token of the verification link, the email address counts as verified without one.
+    pub verification_token: Option<&'a str>,
+}
+
+/// This function inserts a new user, its key becomes the first API key of the user.
+pub(crate) async fn insert_user(pool: &AnyPool, user: &NewUser<'_>) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query(
+        r#"
+        INSERT INTO users
+            ("key", username, password, is_admin, email, email_verified, verification_token)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(user.key)
+    .bind(user.username)
+    .bind(user.password)
+    .bind(user.is_admin as i32)
+    .bind(user.email)
+    .bind(user.verification_token.is_none() as i32)
+    .bind(user.verification_token)
+    .execute(&mut *transaction)
+    .await?;
+    insert_api_key(&mut transaction, user.username, user.key, Some(DEFAULT_KEY_NAME)).await?;
+    transaction.commit().await
+}
+
+/// This function deletes the user with the API key.
+pub(crate) async fn delete_user_by_key(pool: &AnyPool, key: &str) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query(
+        r#"
+        DELETE FROM users
+        WHERE "key" = ?
+        "#,
+    )
+    .bind(key)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(r#"DELETE FROM api_keys WHERE "key" = ?"#)
+        .bind(key)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await
+}
+
+/// The name of the key a user gets when registering.
+pub(crate) const DEFAULT_KEY_NAME: &str = "default";
+
+/// How often `touch_api_key` updates `last_used_at` of a key at most, in seconds,
+/// so a busy client doesn't cause a write with every request.
+const LAST_USED_RESOLUTION: i64 = 60;
+
+/// This function stores an API key of the user and returns its ID.
+pub(crate) async fn insert_api_key(
+    connection: &mut AnyConnection,
+    username: &str,
+    key: &str,
+    name: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    let id = Uuid::from_u128(rand::rng().random::<u128>()).to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO api_keys
+            (id, "key", username, name, created)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(key)
+    .bind(username)
+    .bind(name)
+    .bind(Utc::now().timestamp())
+    .execute(connection)
+    .await?;
+    Ok(id)
+}
+
+/// Returns the API keys of the user, revoked ones included, the oldest first.
+pub(crate) async fn list_api_keys(pool: &AnyPool, username: &str) -> Result<Vec<data::ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, data::ApiKey>(&format!(
+        r#"
+        SELECT {}
+        FROM api_keys
+        WHERE username = ?
+        ORDER BY created, id
+        "#,
+        data::ApiKey::COLUMNS,
+    ))
+    .bind(username)
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the keys of the user that are not revoked, to drop their cached user.
+pub(crate) async fn active_keys(pool: &AnyPool, username: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(r#"SELECT "key" FROM api_keys WHERE username = ? AND revoked_at IS NULL"#)
+        .bind(username)
+        .fetch_all(pool)
+        .await
+}
+
+/// This function revokes an API key of the user and returns the key,
+/// `None` if the user has no such key or it is already revoked.
+pub(crate) async fn revoke_api_key(pool: &AnyPool, id: &str, username: &str) -> Result<Option<String>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let key = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT "key"
+        FROM api_keys
+        WHERE id = ? AND username = ? AND revoked_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .bind(username)
+    .fetch_optional(&mut *transaction)
+    .await?;
+    sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE id = ? AND username = ? AND revoked_at IS NULL")
+        .bind(Utc::now().timestamp())
+        .bind(id)
+        .bind(username)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(key)
+}
+
+/// Returns the ID of the API key, `None` if there is no such key or it is revoked.
+pub(crate) async fn api_key_id(pool: &AnyPool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(r#"SELECT id FROM api_keys WHERE "key" = ? AND revoked_at IS NULL"#)
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns true if the API key with the ID is revoked or no longer exists.
+pub(crate) async fn api_key_revoked(pool: &AnyPool, id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_keys WHERE id = ? AND revoked_at IS NULL")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map(|count| count == 0)
+}
+
+/// This function records that the API key was just used.
+pub(crate) async fn touch_api_key(pool: &AnyPool, key: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now().timestamp();
+    sqlx::query(
+        r#"
+        UPDATE api_keys
+        SET last_used_at = ?
+        WHERE "key" = ? AND (last_used_at IS NULL OR last_used_at <= ?)
+        "#,
+    )
+    .bind(now)
+    .bind(key)
+    .bind(now - LAST_USED_RESOLUTION)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// This function gives every user without an entry in `api_keys` one for the key in their row,
+/// so accounts created before keys had their own table, or imported from a dump, keep working.
+pub(crate) async fn migrate_user_keys(pool: &AnyPool) -> Result<u64, sqlx::Error> {
+    let users = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT "key", username
+        FROM users
+        WHERE "key" NOT IN (SELECT "key" FROM api_keys)
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut connection = pool.acquire().await?;
+    for (key, username) in &users {
+        insert_api_key(&mut connection, username, key, Some(DEFAULT_KEY_NAME)).await?;
+    }
+    Ok(users.len() as u64)
+}
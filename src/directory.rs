@@ -0,0 +1,211 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::changes;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::AuthUser;
+use crate::human;
+use crate::ids;
+use std::net::SocketAddr;
+
+/// The number of files on a page of the public directory if the client doesn't ask for another.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// Returns the error for publishing a private file, the directory only lists public files.
+pub(crate) fn private_unpublishable() -> ApiError {
+    ApiError::BadRequest("Only public files can be published".to_string())
+}
+
+/// Handler to list a file in the public directory
+/// This function publishes a public file of the requesting user,
+/// it shows up at `/public` until it is unpublished, removed, expired or out of downloads.
+/// Files stay unlisted unless they are published, private files can't be published.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" http://localhost:3000/file/<uuid>/publish
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn publish(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received publish request for {} from IP: {}", uuid, ip);
+
+    set_published(&pool, &uuid, &user, true, &ip).await
+}
+
+/// Handler to remove a file from the public directory
+/// This function unpublishes a file of the requesting user, its links keep working.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/file/<uuid>/publish
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn unpublish(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received unpublish request for {} from IP: {}", uuid, ip);
+
+    set_published(&pool, &uuid, &user, false, &ip).await
+}
+
+/// Helper to publish or unpublish a file of `user` and answer with the changed file.
+async fn set_published(
+    pool: &AnyPool,
+    uuid: &str,
+    user: &data::User,
+    published: bool,
+    ip: &str,
+) -> Result<Response, ApiError> {
+    if !ids::is_valid_file_id(uuid) {
+        return Err(ids::invalid_file_id(uuid));
+    }
+    let file = match api::find_file_any(pool, uuid).await {
+        Ok(Some(file)) if file.owner == user.username => file,
+        Ok(Some(file)) => {
+            warn!("User {} tried to publish file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(response) => return Err(response.into()),
+    };
+    if published && file.is_private() {
+        return Err(private_unpublishable());
+    }
+    if published && !file.is_available() {
+        return Err(api::gone(&file));
+    }
+
+    match db::set_published(pool, uuid, published).await {
+        Ok(changed) => {
+            cache::forget_file(uuid).await;
+            changes::record(pool, &changed.owner, uuid, changes::Change::Updated).await;
+            let action = match published {
+                true => audit::Action::Publish,
+                false => audit::Action::Unpublish,
+            };
+            info!("File {} published: {} by {}", uuid, published, user.username);
+            audit::record(pool, action, Some(&user.username), Some(uuid), ip).await;
+            Ok(Json(changed).into_response())
+        }
+        Err(e) => {
+            error!("DB publish error {}: {}", uuid, e);
+            Err(db::error(&e, "Database update error"))
+        }
+    }
+}
+
+/// Handler to list the public directory
+/// This function returns the published files that can still be downloaded, newest first,
+/// so bitBeam can serve as a small release or download portal.
+/// Every other file stays unlisted, only the owner of a file can publish it.
+/// The files are returned in pages, the `x-next-cursor` header of a page is passed as `after`
+/// to get the next one. The last page has no `x-next-cursor`.
+/// Searches are rate limited per IP address by `rate_limit_public`.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET "http://localhost:3000/public?q=release"
+/// accepts the following query parameters:
+/// - q: only return files whose name contains this text or that carry it as a tag (optional)
+/// - limit: the maximum number of files, at most 1000, 50 if missing (optional)
+/// - after: the `x-next-cursor` of the previous page (optional)
+/// - human: `true` to add a `human` object with the size, times and downloads formatted, see `/all_files` (optional)
+#[utoipa::path(
+    get,
+    path = "/public",
+    tag = "files",
+    params(data::PublicQuery),
+    responses(
+        (status = 200, description = "A page of the published files", body = [data::PublicFile]),
+        (status = 400, description = "The cursor is invalid"),
+        (status = 429, description = "Too many requests"),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn list_public(
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::PublicQuery>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a public directory request from IP: {}", ip);
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, api::MAX_PAGE_SIZE);
+    let before = match query.after.as_deref().map(api::parse_cursor) {
+        Some(Some(before)) => Some(before),
+        Some(None) => {
+            return Err(ApiError::BadRequest(
+                "after must be the x-next-cursor of the previous page".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let before = before.as_ref().map(|(upload_time, id)| (*upload_time, id.as_str()));
+    // one more file than asked for tells whether there is a next page
+    let files = db::list_published(&replica, query.q.as_deref(), before, limit + 1).await;
+    let mut files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            warn!("DB select public error: {}", e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    let mut next_cursor = None;
+    if files.len() as i64 > limit {
+        files.truncate(limit as usize);
+        next_cursor = files.last().map(|file| format!("{}.{}", file.upload_time, file.id));
+    }
+    if let Err(e) = api::attach_tags(&replica, &mut files).await {
+        warn!("DB select public tags error: {}", e);
+        return Err(db::error(&e, "Database select error"));
+    }
+
+    let files: Vec<data::PublicFile> = files
+        .into_iter()
+        .map(|file| data::PublicFile {
+            share_url: api::public_url(&config, &format!("s/{}", file.id)),
+            human: query.human.unwrap_or(false).then(|| human::file(&file)),
+            id: file.id,
+            file_name: file.file_name,
+            content_type: file.content_type,
+            file_size: file.file_size,
+            upload_time: file.upload_time,
+            owner: file.owner,
+            download_url: file.download_url,
+            tags: file.tags,
+            description: file.description,
+        })
+        .collect();
+    let mut response = (StatusCode::OK, Json(files)).into_response();
+    if let Some(cursor) = next_cursor.and_then(|cursor| HeaderValue::from_str(&cursor).ok()) {
+        response.headers_mut().insert("x-next-cursor", cursor);
+    }
+    Ok(response)
+}
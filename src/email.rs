@@ -0,0 +1,391 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::share;
+use crate::transfer;
+use std::net::SocketAddr;
+
+/// Handler to email the download link of a file
+/// This function sends the download link of a file to a recipient
+/// together with its size and the number of downloads left.
+/// Private files are sent with a fresh share token in the link.
+/// Only the owner of the file can send it and SMTP must be configured.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"to": "friend@example.com"}' http://localhost:3000/file/<uuid>/email
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// requires the following JSON body:
+/// - to: the address to send the link to (not optional)
+/// - password_hint: a hint for the password of the file (optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn email_file(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    Json(request): Json<data::EmailRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received email request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let (Some(smtp_host), Some(smtp_from)) = (&config.smtp_host, &config.smtp_from) else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Email is not configured").into_response();
+    };
+
+    let to = match request.to.parse::<Mailbox>() {
+        Ok(to) => to,
+        Err(e) => {
+            warn!("Invalid recipient {}: {}", request.to, e);
+            return (StatusCode::BAD_REQUEST, "Invalid recipient address").into_response();
+        }
+    };
+    let from = match smtp_from.parse::<Mailbox>() {
+        Ok(from) => from,
+        Err(e) => {
+            error!("Invalid smtp_from {}: {}", smtp_from, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid sender address").into_response();
+        }
+    };
+
+    // find the file in the database
+    let file = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE id = ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(&uuid)
+    .fetch_optional(&pool)
+    .await;
+    let file = match file {
+        Ok(Some(file)) if file.is_available() => file,
+        Ok(_) => {
+            info!("File not found in DB: {}", uuid);
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    // only the owner is allowed to share the file
+    if file.owner != user.username {
+        warn!("User {} tried to email file {} owned by {}", user.username, uuid, file.owner);
+        return (StatusCode::FORBIDDEN, "You do not own this file").into_response();
+    }
+
+    // the recipient can't download a private file without a token
+    let url = match file.is_private() {
+        true => match share::issue_token(&pool, &file).await {
+            Ok(token) => share::token_url(&file, &token),
+            Err(response) => return response,
+        },
+        false => file.download_url.clone(),
+    };
+
+    let message = match Message::builder()
+        .from(from)
+        .to(to)
+        .subject(format!("{} shared {} with you", user.username, file.file_name))
+        .header(ContentType::TEXT_PLAIN)
+        .body(message_body(&user.username, &file, &url, request.password_hint.as_deref()))
+    {
+        Ok(message) => message,
+        Err(e) => {
+            error!("Email build error {}: {}", uuid, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Email build error").into_response();
+        }
+    };
+
+    let mailer = match transport(&config, smtp_host) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            error!("SMTP transport error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "SMTP transport error").into_response();
+        }
+    };
+    if let Err(e) = mailer.send(message).await {
+        error!("Email send error {}: {}", uuid, e);
+        return (StatusCode::BAD_GATEWAY, "Could not send the email").into_response();
+    }
+    info!("Download link of {} emailed to {}", uuid, request.to);
+
+    Json(json!({
+        "id": file.id,
+        "to": request.to,
+    }))
+    .into_response()
+}
+
+/// This function sends the confirmation link of a new account to its email address.
+/// It returns an error message if SMTP is not configured or the email could not be sent.
+pub(crate) async fn send_verification(
+    config: &data::Config,
+    username: &str,
+    to: Mailbox,
+    token: &str,
+) -> Result<(), String> {
+    let (Some(smtp_host), Some(smtp_from)) = (&config.smtp_host, &config.smtp_from) else {
+        return Err("Email is not configured".to_string());
+    };
+    let from = smtp_from.parse::<Mailbox>().map_err(|e| e.to_string())?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject("Confirm your bitBeam account")
+        .header(ContentType::TEXT_PLAIN)
+        .body(format!(
+            "Hello {username},\n\
+             \n\
+             open this link to confirm your email address and start uploading:\n\
+             {url}\n\
+             \n\
+             If you did not register, ignore this email.\n",
+            username = username,
+            url = api::public_url(config, &format!("user/verify/{}", token)),
+        ))
+        .map_err(|e| e.to_string())?;
+    transport(config, smtp_host)
+        .map_err(|e| e.to_string())?
+        .send(message)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// This function tells the owner of a file that the retention policy removed it.
+/// `reason` completes the sentence "It was removed because ...".
+/// It returns an error message if SMTP is not configured or the email could not be sent.
+pub(crate) async fn send_removal_notice(
+    config: &data::Config,
+    to: Mailbox,
+    file: &data::File,
+    reason: &str,
+) -> Result<(), String> {
+    let (Some(smtp_host), Some(smtp_from)) = (&config.smtp_host, &config.smtp_from) else {
+        return Err("Email is not configured".to_string());
+    };
+    let from = smtp_from.parse::<Mailbox>().map_err(|e| e.to_string())?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(format!("{} was removed from bitBeam", file.file_name))
+        .header(ContentType::TEXT_PLAIN)
+        .body(format!(
+            "Hello {owner},\n\
+             \n\
+             your file {name} ({size}) was removed from bitBeam.\n\
+             It was removed because {reason}.\n",
+            owner = file.owner,
+            name = file.file_name,
+            size = human_size(file.file_size),
+            reason = reason,
+        ))
+        .map_err(|e| e.to_string())?;
+    transport(config, smtp_host)
+        .map_err(|e| e.to_string())?
+        .send(message)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// This function warns a user that they used up a share of one of their quotas,
+/// so they can clean up before uploads are refused.
+/// It returns an error message if SMTP is not configured or the email could not be sent.
+pub(crate) async fn send_quota_warning(
+    config: &data::Config,
+    to: Mailbox,
+    warning: &transfer::Warning,
+) -> Result<(), String> {
+    let (Some(smtp_host), Some(smtp_from)) = (&config.smtp_host, &config.smtp_from) else {
+        return Err("Email is not configured".to_string());
+    };
+    let from = smtp_from.parse::<Mailbox>().map_err(|e| e.to_string())?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(format!("You used {}% of your bitBeam {}", warning.threshold, warning.quota.describe()))
+        .header(ContentType::TEXT_PLAIN)
+        .body(format!(
+            "Hello {username},\n\
+             \n\
+             you used {used} of your {quota} of {limit}, more than {threshold}%.\n\
+             {consequence}\n",
+            username = warning.username,
+            used = human_size(i64::try_from(warning.used_bytes).unwrap_or(i64::MAX)),
+            quota = warning.quota.describe(),
+            limit = human_size(i64::try_from(warning.limit_bytes).unwrap_or(i64::MAX)),
+            threshold = warning.threshold,
+            consequence = match warning.quota {
+                transfer::Quota::Storage => "Once it is used up uploads are refused, delete files you no longer need to make room.",
+                transfer::Quota::MonthlyUpload => "Once it is used up uploads are refused until next month.",
+                transfer::Quota::MonthlyDownload => "Once it is used up downloads of your files are refused until next month.",
+            },
+        ))
+        .map_err(|e| e.to_string())?;
+    transport(config, smtp_host)
+        .map_err(|e| e.to_string())?
+        .send(message)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Handler to confirm the email address of an account
+/// This function marks the account of a verification link as verified,
+/// after that the account can upload files.
+/// Every link works only once.
+/// It also logs the IP address of the client making the request.
+/// example request: curl http://localhost:3000/user/verify/<token>
+/// requires the following path parameter:
+/// - token: the token from the verification email (not optional)
+#[instrument(skip_all)]
+pub async fn verify_email(
+    Path(token): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received email verification from IP: {}", ip);
+
+    let username = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT username
+        FROM users
+        WHERE verification_token = ?
+        "#,
+    )
+    .bind(&token)
+    .fetch_optional(&pool)
+    .await;
+    let username = match username {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            info!("Unknown verification token");
+            return (StatusCode::NOT_FOUND, "Unknown or already used verification link").into_response();
+        }
+        Err(e) => {
+            error!("DB select error: {}", e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE users
+        SET email_verified = 1, verification_token = NULL
+        WHERE verification_token = ?
+        "#,
+    )
+    .bind(&token)
+    .execute(&pool)
+    .await
+    {
+        error!("DB update error {}: {}", username, e);
+        return db::error_response(&e, "Database update error");
+    }
+    cache::forget_user(&pool, &username).await;
+    info!("Email address of {} verified", username);
+    audit::record(&pool, audit::Action::VerifyEmail, Some(&username), None, &ip).await;
+
+    "Your email address is verified, you can upload files now".into_response()
+}
+
+/// Helper to render the text of a download link email.
+fn message_body(sender: &str, file: &data::File, url: &str, password_hint: Option<&str>) -> String {
+    let mut body = format!(
+        "{sender} shared a file with you.\n\
+         \n\
+         File: {name}\n\
+         Size: {size}\n\
+         Download: {url}\n",
+        sender = sender,
+        name = file.file_name,
+        size = human_size(file.file_size),
+        url = url,
+    );
+    if let Some(downloads_left) = file.downloads_remaining() {
+        body.push_str(&format!(
+            "\nThe link expires after {} more download{}.\n",
+            downloads_left,
+            if downloads_left == 1 { "" } else { "s" },
+        ));
+    }
+    if let Some(hint) = password_hint {
+        body.push_str(&format!("Password hint: {}\n", hint));
+    }
+    body
+}
+
+/// Returns a size in bytes in a human readable unit.
+pub(crate) fn human_size(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Helper to build the SMTP transport from the config.
+/// `smtp_tls` selects STARTTLS, implicit TLS or a plain connection.
+fn transport(
+    config: &data::Config,
+    host: &str,
+) -> Result<AsyncSmtpTransport<Tokio1Executor>, lettre::transport::smtp::Error> {
+    let builder = match config.smtp_tls.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+    };
+    // the port was validated when the config was loaded
+    let mut builder = builder.port(config.smtp_port.parse().unwrap_or(587));
+    if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
@@ -0,0 +1,71 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+
+/// Every encrypted blob starts with these bytes.
+const MAGIC: &[u8; 8] = b"bitBeam\x01";
+
+/// The size of an AES-GCM nonce in bytes.
+const NONCE_SIZE: usize = 12;
+
+/// The size of a per file key wrapped by the master key, the key plus the GCM tag.
+const WRAPPED_KEY_SIZE: usize = 32 + 16;
+
+/// The size of the header in front of the encrypted data.
+const HEADER_SIZE: usize = MAGIC.len() + NONCE_SIZE + WRAPPED_KEY_SIZE + NONCE_SIZE;
+
+/// Returns true if the data has the layout of an encrypted blob.
+/// Plain contents can start the same way, whether a blob is encrypted is recorded where it is stored.
+fn is_encrypted(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE && data.starts_with(MAGIC)
+}
+
+/// This function encrypts data for storage with AES-256-GCM.
+/// Every blob gets its own random key, which is stored in front of the data
+/// wrapped (encrypted) with the master key.
+/// The layout is `MAGIC | key nonce | wrapped key | data nonce | ciphertext`.
+pub fn encrypt(master_key: &[u8; 32], plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+    let file_key = Aes256Gcm::generate_key(OsRng);
+    let key_nonce = Aes256Gcm::generate_nonce(OsRng);
+    let wrapped_key = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key))
+        .encrypt(&key_nonce, file_key.as_slice())
+        .map_err(|_| std::io::Error::other("could not wrap the file key"))?;
+    let data_nonce = Aes256Gcm::generate_nonce(OsRng);
+    let ciphertext = Aes256Gcm::new(&file_key)
+        .encrypt(&data_nonce, plaintext)
+        .map_err(|_| std::io::Error::other("could not encrypt the blob"))?;
+
+    let mut blob = Vec::with_capacity(HEADER_SIZE + ciphertext.len());
+    blob.extend_from_slice(MAGIC);
+    blob.extend_from_slice(&key_nonce);
+    blob.extend_from_slice(&wrapped_key);
+    blob.extend_from_slice(&data_nonce);
+    blob.extend_from_slice(&ciphertext);
+    Ok(blob)
+}
+
+/// This function decrypts a blob written by `encrypt`.
+/// It fails if the blob was encrypted with another master key or was tampered with.
+pub fn decrypt(master_key: &[u8; 32], blob: &[u8]) -> std::io::Result<Vec<u8>> {
+    if !is_encrypted(blob) {
+        return Err(std::io::Error::other("blob is not encrypted"));
+    }
+    let (key_nonce, rest) = blob[MAGIC.len()..].split_at(NONCE_SIZE);
+    let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_SIZE);
+    let (data_nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+    let file_key = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key))
+        .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+        .map_err(|_| std::io::Error::other("could not unwrap the file key, wrong master key?"))?;
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&file_key))
+        .decrypt(Nonce::from_slice(data_nonce), ciphertext)
+        .map_err(|_| std::io::Error::other("could not decrypt the blob, it may be corrupted"))
+}
+
+/// Returns the plaintext of data stored before this server recorded whether it is encrypted,
+/// `None` if it is no blob encrypted with the master key.
+/// The GCM tags tell an encrypted blob from plain contents that only start like one.
+pub fn decrypt_legacy(master_key: &[u8; 32], data: &[u8]) -> Option<Vec<u8>> {
+    is_encrypted(data).then(|| decrypt(master_key, data).ok()).flatten()
+}
@@ -0,0 +1,177 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Map, Value};
+
+/// This enum represents the errors the API answers with.
+/// Every error renders into a JSON body with a stable machine readable `error` code
+/// and a human readable `message`, like `{"error": "invalid_key", "message": "Your key is not valid"}`.
+/// Some errors add fields that tell the client what to change, like the size limit of an upload.
+pub enum ApiError {
+    /// Neither a key nor a session cookie was sent.
+    MissingKey,
+    /// The key or session is unknown.
+    InvalidKey,
+    /// The user may not do this.
+    Forbidden(String),
+    /// The email address of the user is not confirmed yet.
+    Unverified,
+    /// A file ID is not a UUID.
+    InvalidFileId,
+    /// The request is malformed, the message says what is wrong.
+    BadRequest(String),
+    /// The file or resource doesn't exist.
+    NotFound(String),
+    /// The file existed but is expired, deleted or used up.
+    Gone(String),
+    /// The request conflicts with the current state, like a slug that is already taken.
+    Conflict(String),
+    /// The upload is larger than the limit in bytes.
+    TooLarge(u64),
+    /// The upload would take the user over their storage quota.
+    QuotaExceeded { quota: u64, used: u64 },
+    /// The server does not accept the download limit.
+    InvalidDownloadLimit { download_limit: i32, max_download_limit: i32 },
+    /// The upload does not match the checksum the client sent.
+    ChecksumMismatch {
+        algorithm: String,
+        expected: String,
+        computed: String,
+    },
+    /// The upload was refused by a content check, `details` are added to the body.
+    Rejected {
+        status: StatusCode,
+        error: &'static str,
+        message: String,
+        details: Value,
+    },
+    /// The requested byte range is outside a file of this size.
+    RangeNotSatisfiable(i64),
+    /// Every database connection stayed busy, the client should retry later.
+    Busy,
+    /// Something failed on the server, the message is safe to show to clients.
+    Internal(&'static str),
+    /// A response built by a helper shared with the other modules, passed on as it is.
+    Response(Response),
+}
+
+impl ApiError {
+    /// Returns the HTTP status of the error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::MissingKey | ApiError::InvalidKey => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::Unverified => StatusCode::FORBIDDEN,
+            ApiError::InvalidFileId | ApiError::BadRequest(_) | ApiError::InvalidDownloadLimit { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::TooLarge(_) | ApiError::QuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Rejected { status, .. } => *status,
+            ApiError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Response(response) => response.status(),
+        }
+    }
+
+    /// Returns the machine readable code of the error.
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::MissingKey => "missing_key",
+            ApiError::InvalidKey => "invalid_key",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Unverified => "email_not_verified",
+            ApiError::InvalidFileId => "invalid_file_id",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Gone(_) => "gone",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::TooLarge(_) => "too_large",
+            ApiError::QuotaExceeded { .. } => "storage_quota_exceeded",
+            ApiError::InvalidDownloadLimit { .. } => "invalid_download_limit",
+            ApiError::ChecksumMismatch { .. } => "checksum_mismatch",
+            ApiError::Rejected { error, .. } => error,
+            ApiError::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            ApiError::Busy => "busy",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Response(_) => "error",
+        }
+    }
+
+    /// Returns the human readable message of the error.
+    fn message(&self) -> String {
+        match self {
+            ApiError::MissingKey => "Key header or session cookie not supplied".to_string(),
+            ApiError::InvalidKey => "Your key is not valid".to_string(),
+            ApiError::Unverified => "Confirm your email address before uploading".to_string(),
+            ApiError::InvalidFileId => "Invalid file ID".to_string(),
+            ApiError::TooLarge(limit) => format!("The upload is larger than the limit of {} bytes", limit),
+            ApiError::QuotaExceeded { .. } => "The upload would exceed your storage quota".to_string(),
+            ApiError::InvalidDownloadLimit { .. } => "The server does not accept this download limit".to_string(),
+            ApiError::ChecksumMismatch { algorithm, .. } => format!("The {} checksum does not match the upload", algorithm),
+            ApiError::RangeNotSatisfiable(_) => "The requested range is not satisfiable".to_string(),
+            ApiError::Busy => "The server is busy, try again later".to_string(),
+            ApiError::Forbidden(message)
+            | ApiError::BadRequest(message)
+            | ApiError::NotFound(message)
+            | ApiError::Gone(message)
+            | ApiError::Conflict(message)
+            | ApiError::Rejected { message, .. } => message.clone(),
+            ApiError::Internal(message) => message.to_string(),
+            ApiError::Response(_) => String::new(),
+        }
+    }
+
+    /// Returns the fields the error adds to the body next to `error` and `message`.
+    fn details(&self) -> Value {
+        match self {
+            ApiError::TooLarge(limit) => json!({ "max_upload_bytes": limit }),
+            ApiError::QuotaExceeded { quota, used } => json!({ "quota_bytes": quota, "used_bytes": used }),
+            ApiError::InvalidDownloadLimit {
+                download_limit,
+                max_download_limit,
+            } => json!({ "download_limit": download_limit, "max_download_limit": max_download_limit }),
+            ApiError::ChecksumMismatch {
+                algorithm,
+                expected,
+                computed,
+            } => json!({ "algorithm": algorithm, "expected": expected, "computed": computed }),
+            ApiError::Rejected { details, .. } => details.clone(),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let mut body = Map::new();
+        body.insert("error".to_string(), self.code().into());
+        body.insert("message".to_string(), self.message().into());
+        if let Value::Object(details) = self.details() {
+            body.extend(details);
+        }
+        let body = Json(Value::Object(body));
+        match self {
+            ApiError::Response(response) => response,
+            ApiError::Busy => (status, [(header::RETRY_AFTER, "5".to_string())], body).into_response(),
+            ApiError::RangeNotSatisfiable(size) => {
+                (status, [(header::CONTENT_RANGE, format!("bytes */{}", size))], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
+    }
+}
+
+/// Helpers shared with the other modules hand out ready-made responses,
+/// handlers returning `ApiError` pass them on unchanged.
+impl From<Response> for ApiError {
+    fn from(response: Response) -> Self {
+        ApiError::Response(response)
+    }
+}
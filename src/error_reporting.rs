@@ -0,0 +1,99 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use sentry::protocol::{Event, Level, Map};
+use tracing::{error, info};
+
+use crate::data;
+use crate::logging;
+use std::net::SocketAddr;
+
+/// The request headers sent along with an error report.
+/// Keys, tokens and cookies never leave the server.
+const REPORTED_HEADERS: [header::HeaderName; 3] = [header::USER_AGENT, header::CONTENT_TYPE, header::CONTENT_LENGTH];
+
+/// This function sets up error reporting to Sentry if `sentry_dsn` is configured.
+/// Panics are reported from then on, 5xx responses by the `capture_errors` middleware.
+/// The returned guard flushes pending reports when it is dropped, keep it alive until the server stops.
+pub fn init(config: &data::Config) -> Option<sentry::ClientInitGuard> {
+    let dsn = config.sentry_dsn.as_deref()?;
+    let dsn = match dsn.parse::<sentry::types::Dsn>() {
+        Ok(dsn) => dsn,
+        Err(e) => {
+            error!("Invalid Sentry DSN, error reporting disabled: {}", e);
+            return None;
+        }
+    };
+    let guard = sentry::init(sentry::ClientOptions {
+        dsn: Some(dsn),
+        release: sentry::release_name!(),
+        environment: config.sentry_environment.clone().map(Into::into),
+        ..Default::default()
+    });
+    info!("Reporting errors to Sentry");
+    Some(guard)
+}
+
+/// This middleware reports every response with a 5xx status to Sentry
+/// together with the method, URL, request ID and client IP of the request.
+/// Without a configured DSN nothing is sent.
+pub async fn capture_errors(State(config): State<data::Config>, request: Request, next: Next) -> Response {
+    if config.sentry_dsn.is_none() {
+        return next.run(request).await;
+    }
+
+    let scheme = match config.use_tls {
+        true => "https",
+        false => "http",
+    };
+    // the route instead of the path and no query string, they can carry keys and share tokens
+    let url = format!("{}://{}{}", scheme, config.base_url, logging::logged_path(&request));
+    let method = request.method().to_string();
+    let mut headers = Map::new();
+    for name in &REPORTED_HEADERS {
+        if let Some(value) = request.headers().get(name).and_then(|hv| hv.to_str().ok()) {
+            headers.insert(name.to_string(), value.to_string());
+        }
+    }
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|hv| hv.to_str().ok())
+        .map(str::to_string);
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+    let response = next.run(request).await;
+    if !response.status().is_server_error() {
+        return response;
+    }
+
+    let mut tags = Map::new();
+    tags.insert("status".to_string(), response.status().as_u16().to_string());
+    if let Some(request_id) = request_id {
+        tags.insert("request_id".to_string(), request_id);
+    }
+    let event = Event {
+        level: Level::Error,
+        message: Some(format!("{} {} answered with {}", method, url, response.status())),
+        request: Some(sentry::protocol::Request {
+            url: url.parse().ok(),
+            method: Some(method),
+            headers,
+            ..Default::default()
+        }),
+        user: client_ip.map(|ip_address| sentry::User {
+            ip_address: ip_address.parse().ok(),
+            ..Default::default()
+        }),
+        tags,
+        ..Default::default()
+    };
+    sentry::capture_event(event);
+    response
+}
@@ -0,0 +1,231 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, AnyPool};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::maintenance;
+use crate::memory_cache;
+
+/// The number of events that can wait to be published before new ones are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// The Postgres channel instances are woken up on when an event was published.
+const CHANNEL: &str = "bitbeam_events";
+
+/// How long published events are kept for instances to read them, in seconds.
+const EVENT_TTL: i64 = 60 * 60;
+
+/// How often publishing an event is tried when another instance took the same sequence number.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The queue events are sent to, it is set once the publisher is started.
+static QUEUE: OnceLock<mpsc::Sender<Event>> = OnceLock::new();
+
+/// The ID of this instance, so it skips the events it published itself.
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// This enum represents the changes every instance of a cluster has to apply to its in-memory state.
+/// Events are stored in the `cluster_events` table, numbered in the order they were published.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event {
+    /// A file was moved to the trash or removed, its cached row and blob are dropped.
+    FileDeleted { id: String, blob: String },
+    /// A revoked key or the key of a removed user, its cached user is dropped.
+    KeyRevoked { key: String },
+    /// Maintenance mode was switched on with the message or off with `None`.
+    Maintenance { message: Option<String> },
+}
+
+/// This function starts the tasks that publish events to and read events from the other instances.
+/// Postgres wakes the readers up with `LISTEN`/`NOTIFY`, other databases are polled
+/// every `event_poll_interval` seconds, which Postgres falls back to if a notification is missed.
+/// An `event_poll_interval` of 0 turns the propagation off.
+pub fn start(pool: AnyPool, config: data::Config) {
+    if config.event_poll_interval == 0 {
+        return;
+    }
+    let (sender, mut receiver) = mpsc::channel::<Event>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        warn!("Event propagation already started");
+        return;
+    }
+    let instance = INSTANCE.get_or_init(|| uuid::Uuid::new_v4().to_string()).clone();
+
+    let publisher = pool.clone();
+    let from = instance.clone();
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if let Err(e) = publish_now(&publisher, &from, &event).await {
+                error!("Could not publish an event to the other instances: {}", e);
+            }
+        }
+    });
+
+    let interval = Duration::from_secs(config.event_poll_interval);
+    tokio::spawn(async move {
+        // events published before this instance started are already part of the state it loaded
+        let mut seen = match latest(&pool).await {
+            Ok(seen) => seen,
+            Err(e) => {
+                error!("Could not read the events of the other instances: {}", e);
+                return;
+            }
+        };
+        let mut listener = listen(&pool, &config).await;
+        info!("Reading the events of other instances");
+        loop {
+            match listener.as_mut() {
+                // a notification, a timeout and a lost connection all lead to the same check
+                Some(listener) => {
+                    let _ = tokio::time::timeout(interval, listener.recv()).await;
+                }
+                None => tokio::time::sleep(interval).await,
+            }
+            match receive(&pool, &instance, seen).await {
+                Ok(latest) => seen = latest,
+                Err(e) => warn!("Could not read the events of the other instances: {}", e),
+            }
+        }
+    });
+}
+
+/// This function queues an event for the other instances.
+/// It never blocks, events are dropped with a warning if the queue is full.
+pub(crate) fn publish(event: Event) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    if let Err(e) = queue.try_send(event) {
+        warn!("Dropped an event for the other instances: {}", e);
+    }
+}
+
+/// Returns the listener on the events channel if the database is Postgres.
+async fn listen(pool: &AnyPool, config: &data::Config) -> Option<PgListener> {
+    if !matches!(pool.connect_options().database_url.scheme(), "postgres" | "postgresql") {
+        return None;
+    }
+    let listener = async {
+        let mut listener = PgListener::connect(&config.database_url).await?;
+        listener.listen(CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    };
+    match listener.await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            warn!("Could not listen for events, polling instead: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the sequence number of the newest event, 0 if there is none.
+async fn latest(pool: &AnyPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(seq), 0) FROM cluster_events")
+        .fetch_one(pool)
+        .await
+}
+
+/// This function stores an event with the next sequence number and wakes up Postgres listeners.
+/// Two instances can pick the same number at once, the loser tries again with the next one.
+async fn publish_now(pool: &AnyPool, instance: &str, event: &Event) -> Result<(), sqlx::Error> {
+    let body = serde_json::to_string(event).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let mut attempt = 1;
+    loop {
+        let seq = latest(pool).await? + 1;
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO cluster_events
+                (seq, instance, event, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(seq)
+        .bind(instance)
+        .bind(&body)
+        .bind(Utc::now().timestamp())
+        .execute(pool)
+        .await;
+        match inserted {
+            Ok(_) => break,
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                debug!("Event number {} was taken, trying again: {}", seq, e);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if matches!(pool.connect_options().database_url.scheme(), "postgres" | "postgresql") {
+        sqlx::query(&format!("NOTIFY {}", CHANNEL)).execute(pool).await?;
+    }
+    Ok(())
+}
+
+/// This function applies the events other instances published after `seen`
+/// and returns the sequence number of the newest event it read.
+async fn receive(pool: &AnyPool, instance: &str, seen: i64) -> Result<i64, sqlx::Error> {
+    let events = sqlx::query_as::<_, (i64, String, db::Text)>(
+        r#"
+        SELECT seq, instance, event
+        FROM cluster_events
+        WHERE seq > ?
+        ORDER BY seq
+        "#,
+    )
+    .bind(seen)
+    .fetch_all(pool)
+    .await?;
+    let mut latest = seen;
+    for (seq, from, body) in events {
+        latest = seq;
+        if from == instance {
+            continue;
+        }
+        match serde_json::from_str::<Event>(&String::from(body)) {
+            Ok(event) => apply(event).await,
+            Err(e) => warn!("Skipped event {} of instance {}: {}", seq, from, e),
+        }
+    }
+    Ok(latest)
+}
+
+/// This function applies an event of another instance to the state of this one.
+async fn apply(event: Event) {
+    match event {
+        Event::FileDeleted { id, blob } => {
+            debug!("File {} was deleted by another instance", id);
+            cache::forget_file(&id).await;
+            memory_cache::remove(&blob);
+        }
+        Event::KeyRevoked { key } => {
+            debug!("A key was revoked by another instance");
+            cache::forget_key(&key).await;
+        }
+        Event::Maintenance { message } => {
+            match &message {
+                Some(message) => warn!("Maintenance started by another instance: {}", message),
+                None => info!("Maintenance ended by another instance"),
+            }
+            maintenance::set(message);
+        }
+    }
+}
+
+/// This function removes the events older than `EVENT_TTL`, the newest one is always kept
+/// so the sequence numbers keep counting up. It is run by the cleanup task.
+pub(crate) async fn remove_old(pool: &AnyPool) -> Result<(), sqlx::Error> {
+    let newest = latest(pool).await?;
+    sqlx::query("DELETE FROM cluster_events WHERE created_at <= ? AND seq < ?")
+        .bind(Utc::now().timestamp() - EVENT_TTL)
+        .bind(newest)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use axum::{
+    extract::{ConnectInfo, FromRequest, FromRequestParts, Query, Request},
+    http::{header, request::Parts, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::AnyPool;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+use crate::api;
+use crate::data;
+use crate::error::ApiError;
+use crate::tokens::{self, Scope};
+
+/// Returns the value of a header as text, `None` if it is missing or not valid text.
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get(name).and_then(|hv| hv.to_str().ok()).map(str::to_string)
+}
+
+/// Returns true if the request announces a JSON body.
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .is_some_and(|value| value.trim().starts_with("application/json"))
+}
+
+/// The upload options are read from the headers first and from the query string second,
+/// so clients that can't send custom headers can put them in the URL.
+impl<S: Send + Sync> FromRequestParts<S> for data::UploadOptions {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<data::UploadOptions>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        let headers = &parts.headers;
+        Ok(data::UploadOptions {
+            file_name: header_value(headers, "file_name").or(query.file_name),
+            download_limit: header_value(headers, "download_limit").or(query.download_limit),
+            slug: header_value(headers, "slug").or(query.slug),
+            encrypted: header_value(headers, "encrypted").or(query.encrypted),
+            tags: header_value(headers, "tags").or(query.tags),
+            visibility: header_value(headers, "visibility").or(query.visibility),
+            upload_id: header_value(headers, "upload_id").or(query.upload_id),
+            expire_if_unused_days: header_value(headers, "expire_if_unused_days").or(query.expire_if_unused_days),
+            extract: header_value(headers, "extract").or(query.extract),
+            expires_at: header_value(headers, "expires_at").or(query.expires_at),
+            max_age: header_value(headers, "max_age").or(query.max_age),
+            cache: header_value(headers, "cache").or(query.cache),
+            publish: header_value(headers, "publish").or(query.publish),
+            description: header_value(headers, "description").or(query.description),
+        })
+    }
+}
+
+/// The credentials are read from a JSON body if the request has one,
+/// fields missing from the body fall back to the headers of the same name.
+impl<S: Send + Sync> FromRequest<S> for data::Credentials {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = request.headers().clone();
+        let body = match is_json(&headers) {
+            true => {
+                let Json(body) = Json::<data::Credentials>::from_request(request, state)
+                    .await
+                    .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+                body
+            }
+            false => data::Credentials::default(),
+        };
+        Ok(data::Credentials {
+            username: body.username.or_else(|| header_value(&headers, "username")),
+            password: body.password.or_else(|| header_value(&headers, "password")),
+            email: body.email.or_else(|| header_value(&headers, "email")),
+            captcha: body.captcha.or_else(|| header_value(&headers, "captcha")),
+        })
+    }
+}
+
+/// This trait names the scope a scoped token needs to be accepted by `AuthUser`.
+pub trait TokenScope {
+    /// The scope, `None` if scoped tokens are refused.
+    const SCOPE: Option<Scope>;
+}
+
+/// Only the account key or the session cookie, scoped tokens are refused.
+pub struct AccountOnly;
+
+/// Scoped tokens need the `upload` scope.
+pub struct UploadScope;
+
+/// Scoped tokens need the `list` scope.
+pub struct ListScope;
+
+/// Scoped tokens need the `delete` scope.
+pub struct DeleteScope;
+
+impl TokenScope for AccountOnly {
+    const SCOPE: Option<Scope> = None;
+}
+
+impl TokenScope for UploadScope {
+    const SCOPE: Option<Scope> = Some(Scope::Upload);
+}
+
+impl TokenScope for ListScope {
+    const SCOPE: Option<Scope> = Some(Scope::List);
+}
+
+impl TokenScope for DeleteScope {
+    const SCOPE: Option<Scope> = Some(Scope::Delete);
+}
+
+/// The user making a request, resolved from the `key` header, the session cookie
+/// or a scoped token that has the scope `T` names.
+/// Requests without valid credentials are rejected with a 401 before the handler runs.
+pub struct AuthUser<T: TokenScope = AccountOnly> {
+    pub user: data::User,
+    /// The scoped token the request was made with, `None` for the account key or a session.
+    pub token: Option<data::ApiToken>,
+    scope: PhantomData<T>,
+}
+
+/// The user and token a request was authenticated as, kept in the request extensions
+/// so the key is only looked up once however many extractors ask for it.
+#[derive(Clone)]
+struct Authenticated(data::User, Option<data::ApiToken>);
+
+impl<S: Send + Sync, T: TokenScope> FromRequestParts<S> for AuthUser<T> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Authenticated(user, token) = match parts.extensions.get::<Authenticated>() {
+            Some(authenticated) => authenticated.clone(),
+            None => {
+                let Some(pool) = parts.extensions.get::<AnyPool>() else {
+                    return Err(ApiError::Internal("Database not available").into_response());
+                };
+                let ip = parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip().to_string())
+                    .unwrap_or_default();
+                let (user, token) = match T::SCOPE {
+                    Some(scope) => tokens::authenticate(pool, &parts.headers, &ip, scope).await?,
+                    None => (api::authenticate(pool, &parts.headers, &ip).await?, None),
+                };
+                let authenticated = Authenticated(user, token);
+                parts.extensions.insert(authenticated.clone());
+                authenticated
+            }
+        };
+        // a token accepted by an earlier extractor still needs the scope of this one
+        if let Some(token) = &token {
+            let refused = match T::SCOPE {
+                Some(scope) => tokens::missing_scope(token, scope),
+                None => Some(ApiError::Forbidden("Scoped tokens can't be used for this request".to_string()).into_response()),
+            };
+            if let Some(response) = refused {
+                return Err(response);
+            }
+        }
+        Ok(AuthUser {
+            user,
+            token,
+            scope: PhantomData,
+        })
+    }
+}
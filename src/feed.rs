@@ -0,0 +1,235 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rand::Rng;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::fmt::Write;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::s3::escape;
+use std::net::SocketAddr;
+
+/// The number of uploads a feed lists, newest first.
+const FEED_LENGTH: i64 = 50;
+
+/// Returns a unix timestamp as an RFC 3339 date like Atom wants it.
+fn atom_date(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Returns the URL of the feed of a user.
+fn feed_url(config: &data::Config, username: &str, token: &str) -> String {
+    api::public_url(config, &format!("feed/{}.atom?token={}", username, token))
+}
+
+/// Handler to turn on the Atom feed of a user
+/// This function creates a secret feed URL that lists the public uploads of the user,
+/// so teammates and automation can watch it for new files.
+/// Calling it again replaces the URL, the old one stops working.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" http://localhost:3000/user/feed
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn enable_feed(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received feed request from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Err(response) = remove_feed(&pool, &user.username).await {
+        return response;
+    }
+    let token = hex::encode(rand::rng().random::<[u8; 32]>());
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO feeds (username, token, created)
+        VALUES (?, ?, ?)
+        "#,
+    )
+    .bind(&user.username)
+    .bind(&token)
+    .bind(Utc::now().timestamp())
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert feed error {}: {}", user.username, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("Feed of {} turned on", user.username);
+
+    Json(json!({
+        "username": user.username,
+        "feed_url": feed_url(&config, &user.username, &token),
+    }))
+    .into_response()
+}
+
+/// Handler to turn off the Atom feed of a user
+/// This function removes the feed URL of the user, it stops working right away.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/user/feed
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn disable_feed(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received feed removal from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Err(response) = remove_feed(&pool, &user.username).await {
+        return response;
+    }
+    info!("Feed of {} turned off", user.username);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Helper to remove the feed of a user.
+async fn remove_feed(pool: &AnyPool, username: &str) -> Result<(), Response> {
+    if let Err(e) = sqlx::query("DELETE FROM feeds WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await
+    {
+        error!("DB delete feed error {}: {}", username, e);
+        return Err(db::error_response(&e, "Database delete error"));
+    }
+    Ok(())
+}
+
+/// Handler to return the Atom feed of a user
+/// This function lists the newest public uploads of the user with their names, sizes and download links.
+/// Private files, files in the trash and expired files are left out.
+/// Feeds are off until the user turns them on with `PUT /user/feed`,
+/// which returns the URL with the token.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/feed/<username>.atom?token=<token>
+/// requires the following path parameter:
+/// - username: the username of the user followed by `.atom` (not optional)
+///
+/// requires the following query parameters:
+/// - token: the feed token of the user (not optional)
+#[instrument(skip_all)]
+pub async fn atom_feed(
+    Path(feed): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::FeedQuery>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received feed request for {} from IP: {}", feed, ip);
+
+    let Some(username) = feed.strip_suffix(".atom") else {
+        return (StatusCode::NOT_FOUND, "Feed not found").into_response();
+    };
+    let Some(token) = query.token else {
+        return (StatusCode::UNAUTHORIZED, "The feed token is missing").into_response();
+    };
+    let enabled = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM feeds WHERE username = ? AND token = ?")
+        .bind(username)
+        .bind(&token)
+        .fetch_one(&pool)
+        .await;
+    match enabled {
+        Ok(0) => {
+            warn!("Invalid feed token for {} from {}", username, ip);
+            return (StatusCode::NOT_FOUND, "Feed not found").into_response();
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("DB select feed error {}: {}", username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    }
+
+    let files = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE owner = ? AND visibility = 'public' AND deleted_at IS NULL
+        ORDER BY upload_time DESC
+        LIMIT ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(username)
+    .bind(FEED_LENGTH)
+    .fetch_all(&pool)
+    .await;
+    let mut files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            error!("DB select error {}: {}", username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+    files.retain(|file| file.is_available());
+
+    let updated = files.first().map(|file| file.upload_time).unwrap_or_default();
+    let self_url = feed_url(&config, username, &token);
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom">"#);
+    let _ = write!(
+        xml,
+        "<id>{}</id><title>Uploads of {}</title><updated>{}</updated>\
+         <link rel=\"self\" href=\"{}\"/><author><name>{}</name></author>",
+        escape(&api::public_url(&config, &format!("feed/{}.atom", username))),
+        escape(username),
+        atom_date(updated),
+        escape(&self_url),
+        escape(username)
+    );
+    for file in &files {
+        let _ = write!(
+            xml,
+            "<entry><id>urn:uuid:{}</id><title>{}</title><updated>{}</updated>\
+             <link href=\"{}\"/><link rel=\"enclosure\" href=\"{}\" type=\"{}\" length=\"{}\"/>\
+             <summary>{} bytes, {}</summary></entry>",
+            escape(&file.id),
+            escape(&file.file_name),
+            atom_date(file.upload_time),
+            escape(&file.download_url),
+            escape(&file.download_url),
+            escape(&file.content_type),
+            file.file_size,
+            file.file_size,
+            escape(&file.content_type)
+        );
+    }
+    xml.push_str("</feed>");
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
@@ -0,0 +1,182 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use sqlx::AnyPool;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::audit;
+use crate::cache;
+use crate::changes;
+use crate::data;
+use crate::events;
+use crate::storage;
+use crate::thumbnail;
+
+/// Blobs and rows younger than this are left alone.
+/// An upload writes its blob before it inserts its row, so a fresh blob may not be referenced yet.
+const GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// The tables that reference files by their ID.
+const FILE_TABLES: [&str; 7] = [
+    "file_tags",
+    "collection_files",
+    "downloads",
+    "share_tokens",
+    "delete_tokens",
+    "file_versions",
+    "file_signatures",
+];
+
+/// This function reconciles the stored blobs with the database.
+/// It removes blobs that no file references, removes `files` rows whose blob is missing
+/// and prunes tag, collection, download, share token, delete token and version rows of files that no longer exist.
+/// It runs under the blob lock, so uploads and deletes wait until it is done.
+/// The jobs module runs it every `gc_interval` seconds, with a `gc_interval` of 0 only `POST /admin/gc` runs it.
+pub async fn collect(
+    pool: &AnyPool,
+    config: &data::Config,
+) -> Result<data::GcReport, Box<dyn std::error::Error + Send + Sync>> {
+    let _guard = storage::lock(pool).await?;
+    let mut report = data::GcReport::default();
+    let cutoff = SystemTime::now() - GRACE_PERIOD;
+
+    let blobs = storage::list_blobs(config).await?;
+    let stored: HashSet<&str> = blobs
+        .iter()
+        .filter_map(|(stored, _)| storage::parse_stored_name(stored))
+        .map(|(name, _)| name)
+        .collect();
+
+    // rows whose blob is gone can never be downloaded again
+    // the rows are streamed and only the IDs of the missing ones are kept
+    let mut missing = Vec::new();
+    let mut rows = sqlx::query_as::<_, (String, Option<String>)>("SELECT id, content_hash FROM files WHERE upload_time < ?")
+        .bind(Utc::now().timestamp() - GRACE_PERIOD.as_secs() as i64)
+        .fetch(pool);
+    while let Some((id, content_hash)) = rows.try_next().await? {
+        let blob = content_hash.unwrap_or_else(|| id.clone());
+        if !stored.contains(blob.as_str()) {
+            missing.push((id, blob));
+        }
+    }
+    drop(rows);
+    for (id, blob) in missing {
+        let owner = sqlx::query_scalar::<_, String>("SELECT owner FROM files WHERE id = ?")
+            .bind(&id)
+            .fetch_optional(pool)
+            .await?;
+        let Some(owner) = owner else {
+            continue;
+        };
+        sqlx::query("DELETE FROM files WHERE id = ?")
+            .bind(&id)
+            .execute(pool)
+            .await?;
+        cache::forget_file(&id).await;
+        changes::record(pool, &owner, &id, changes::Change::Deleted).await;
+        events::publish(events::Event::FileDeleted {
+            id: id.clone(),
+            blob: blob.clone(),
+        });
+        warn!("Removed file {} whose blob {} is missing", id, blob);
+        report.removed_files.push(id);
+    }
+
+    // blobs nobody references, and the thumbnails of those blobs
+    // earlier versions keep their blobs as long as their file exists
+    let mut referenced = HashSet::new();
+    let mut rows = sqlx::query_as::<_, (String, Option<String>)>(
+        r#"
+        SELECT id, content_hash FROM files
+        UNION ALL
+        SELECT file_versions.file_id, file_versions.content_hash
+        FROM file_versions
+        JOIN files ON files.id = file_versions.file_id
+        "#,
+    )
+    .fetch(pool);
+    while let Some((id, content_hash)) = rows.try_next().await? {
+        let name = content_hash.unwrap_or(id);
+        referenced.insert(thumbnail::thumbnail_name(&name));
+        referenced.insert(name);
+    }
+    drop(rows);
+    for (stored, modified) in &blobs {
+        // anything else in the data path is never touched
+        let Some((name, _)) = storage::parse_stored_name(stored) else {
+            continue;
+        };
+        if referenced.contains(name) || *modified > cutoff {
+            continue;
+        }
+        match storage::remove_blob(config, name).await {
+            Ok(()) => {
+                warn!("Removed orphaned blob {}", name);
+                report.removed_blobs.push(name.to_string());
+            }
+            Err(e) => warn!("Could not remove orphaned blob {}: {}", name, e),
+        }
+    }
+
+    // rows of other tables that point at removed files
+    for table in FILE_TABLES {
+        let pruned = sqlx::query(&format!(
+            "DELETE FROM {} WHERE file_id NOT IN (SELECT id FROM files)",
+            table
+        ))
+        .execute(pool)
+        .await?;
+        report.pruned_rows += pruned.rows_affected();
+    }
+
+    info!(
+        "Garbage collection removed {} blobs, {} files and {} dangling rows",
+        report.removed_blobs.len(),
+        report.removed_files.len(),
+        report.pruned_rows
+    );
+    Ok(report)
+}
+
+/// Handler to collect garbage now
+/// This function runs the same reconciliation as the periodic task
+/// and returns what it cleaned up as a JSON response.
+/// example request: curl -X POST -H "key: <admin key>" http://localhost:3000/admin/gc
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn run_gc(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin gc request from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match collect(&pool, &config).await {
+        Ok(report) => {
+            audit::record(&pool, audit::Action::AdminGc, Some(&admin.username), None, &ip).await;
+            Json(report).into_response()
+        }
+        Err(e) => {
+            error!("Garbage collection error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Garbage collection failed").into_response()
+        }
+    }
+}
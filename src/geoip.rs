@@ -0,0 +1,77 @@
+use maxminddb::{geoip2, Reader};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+use crate::data;
+
+/// The GeoIP databases, loaded once at startup.
+static DATABASES: OnceLock<Databases> = OnceLock::new();
+
+/// This struct holds the configured MaxMind databases,
+/// a GeoLite2 Country or City database and a GeoLite2 ASN database.
+struct Databases {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+/// This struct represents where a download came from.
+/// Every field is `None` if the database is not configured or does not know the address.
+#[derive(Default)]
+pub(crate) struct Location {
+    pub country: Option<String>,
+    pub asn: Option<i64>,
+    pub asn_organization: Option<String>,
+}
+
+/// Helper to open a MaxMind database.
+fn open(path: &str) -> std::io::Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path).map_err(|e| std::io::Error::other(format!("GeoIP database {}: {}", path, e)))
+}
+
+/// This function loads the GeoIP databases from `geoip_country_database` and `geoip_asn_database`.
+/// Without them downloads are recorded without a location.
+/// It fails if a configured database can't be read.
+pub fn init(config: &data::Config) -> std::io::Result<()> {
+    let databases = Databases {
+        country: config.geoip_country_database.as_deref().map(open).transpose()?,
+        asn: config.geoip_asn_database.as_deref().map(open).transpose()?,
+    };
+    if databases.country.is_some() || databases.asn.is_some() {
+        info!("Recording the country and network of downloads");
+    }
+    if DATABASES.set(databases).is_err() {
+        warn!("GeoIP databases already loaded");
+    }
+    Ok(())
+}
+
+/// Returns the country and the autonomous system of an IP address.
+pub(crate) fn locate(ip: &str) -> Location {
+    let (Some(databases), Ok(ip)) = (DATABASES.get(), ip.parse::<IpAddr>()) else {
+        return Location::default();
+    };
+    let mut location = Location::default();
+    if let Some(reader) = &databases.country {
+        match reader.lookup::<geoip2::Country>(ip) {
+            Ok(country) => {
+                location.country = country
+                    .and_then(|country| country.country)
+                    .and_then(|country| country.iso_code)
+                    .map(str::to_string);
+            }
+            Err(e) => warn!("GeoIP country lookup error {}: {}", ip, e),
+        }
+    }
+    if let Some(reader) = &databases.asn {
+        match reader.lookup::<geoip2::Asn>(ip) {
+            Ok(Some(asn)) => {
+                location.asn = asn.autonomous_system_number.map(i64::from);
+                location.asn_organization = asn.autonomous_system_organization.map(str::to_string);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("GeoIP ASN lookup error {}: {}", ip, e),
+        }
+    }
+    location
+}
@@ -0,0 +1,51 @@
+use chrono::Utc;
+
+use crate::data;
+use crate::email;
+
+/// The units relative times are told in, with their length in seconds, the longest first.
+const TIME_UNITS: [(&str, i64); 6] = [
+    ("year", 365 * 86400),
+    ("month", 30 * 86400),
+    ("week", 7 * 86400),
+    ("day", 86400),
+    ("hour", 3600),
+    ("minute", 60),
+];
+
+/// Returns a unix timestamp relative to `now`, like `3 days ago` or `in 2 hours`.
+/// Anything less than a minute away is `just now`.
+pub(crate) fn relative_time(timestamp: i64, now: i64) -> String {
+    let distance = timestamp.saturating_sub(now);
+    let Some((unit, length)) = TIME_UNITS.iter().find(|(_, length)| distance.abs() >= *length) else {
+        return "just now".to_string();
+    };
+    let count = distance.abs() / length;
+    let span = format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
+    match distance < 0 {
+        true => format!("{} ago", span),
+        false => format!("in {}", span),
+    }
+}
+
+/// Returns the download count of a file together with its limit, like `3 of 5 downloads`.
+fn downloads(file: &data::File) -> String {
+    let plural = if file.download_count == 1 { "" } else { "s" };
+    match file.download_limit {
+        0 => format!("{} download{}", file.download_count, plural),
+        limit => format!("{} of {} download{}", file.download_count, limit, if limit == 1 { "" } else { "s" }),
+    }
+}
+
+/// Returns the formatted fields of a file that `?human=true` adds to listings and info.
+pub(crate) fn file(file: &data::File) -> data::HumanFile {
+    let now = Utc::now().timestamp();
+    data::HumanFile {
+        file_size: email::human_size(file.file_size),
+        upload_time: relative_time(file.upload_time, now),
+        downloads: downloads(file),
+        expires: file.expires.map(|expires| relative_time(expires, now)),
+        last_downloaded_at: file.last_downloaded_at.map(|time| relative_time(time, now)),
+        deleted_at: file.deleted_at.map(|time| relative_time(time, now)),
+    }
+}
@@ -0,0 +1,197 @@
+use axum::response::{IntoResponse, Response};
+use chrono::Utc;
+use sqlx::AnyPool;
+use tracing::{error, info, warn};
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+
+/// How long the file of a key is remembered, a retry after that uploads again.
+pub(crate) const KEY_TTL: i64 = 24 * 60 * 60;
+
+/// How long a key can stay reserved by an upload that never finished,
+/// for example because the instance receiving it stopped.
+const PENDING_TTL: i64 = 60 * 60;
+
+/// The longest `Idempotency-Key` that is accepted.
+const MAX_KEY_LENGTH: usize = 255;
+
+/// This enum represents the outcome of claiming an idempotency key for an upload.
+pub(crate) enum Claim {
+    /// The key is new, the upload goes ahead and records its file in the reservation.
+    New(Reservation),
+    /// An earlier upload with the key stored this file, it is returned instead of storing the upload again.
+    Replay(Box<data::File>),
+}
+
+/// This struct holds an idempotency key while its upload runs.
+/// If the upload fails, the reservation is dropped without a file
+/// and the key is released, so the client can retry with it.
+pub(crate) struct Reservation {
+    pool: AnyPool,
+    owner: String,
+    key: String,
+    finished: bool,
+}
+
+impl Reservation {
+    /// This function records the file the upload stored under the key.
+    pub(crate) async fn finish(mut self, file_id: &str) {
+        self.finished = true;
+        if let Err(e) = sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET file_id = ?
+            WHERE owner = ? AND idempotency_key = ?
+            "#,
+        )
+        .bind(file_id)
+        .bind(&self.owner)
+        .bind(&self.key)
+        .execute(&self.pool)
+        .await
+        {
+            error!("DB update idempotency key error {}: {}", self.key, e);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let (pool, owner, key) = (self.pool.clone(), self.owner.clone(), self.key.clone());
+        tokio::spawn(async move {
+            if let Err(e) = sqlx::query("DELETE FROM idempotency_keys WHERE owner = ? AND idempotency_key = ? AND file_id IS NULL")
+                .bind(&owner)
+                .bind(&key)
+                .execute(&pool)
+                .await
+            {
+                warn!("DB delete idempotency key error {}: {}", key, e);
+            }
+        });
+    }
+}
+
+/// Returns the error for an `Idempotency-Key` header that is empty or too long.
+fn invalid_key() -> Response {
+    ApiError::BadRequest(format!("Invalid Idempotency-Key, use 1 to {} characters", MAX_KEY_LENGTH)).into_response()
+}
+
+/// Returns a reservation of the key for an upload of the owner.
+fn reserve(pool: &AnyPool, owner: &str, key: &str) -> Reservation {
+    Reservation {
+        pool: pool.clone(),
+        owner: owner.to_string(),
+        key: key.to_string(),
+        finished: false,
+    }
+}
+
+/// Returns the error for a retry that arrives while the upload with its key still runs.
+fn running() -> Response {
+    ApiError::Conflict("An upload with this Idempotency-Key is running".to_string()).into_response()
+}
+
+/// This function claims the `Idempotency-Key` of an upload for its owner.
+/// If an earlier upload with the same key and owner stored a file in the last 24 hours,
+/// that file is returned instead, so a retried upload never stores the file twice.
+/// If the earlier upload is still running the retry is refused with 409.
+/// A file that was deleted since no longer holds its key, the retry is stored again.
+pub(crate) async fn claim(pool: &AnyPool, owner: &str, key: &str) -> Result<Claim, Response> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(invalid_key());
+    }
+    let now = Utc::now().timestamp();
+
+    // keys run out after a day, reservations of uploads that never finished after an hour
+    if let Err(e) = sqlx::query(
+        r#"
+        DELETE FROM idempotency_keys
+        WHERE owner = ? AND idempotency_key = ? AND (created_at <= ? OR (file_id IS NULL AND created_at <= ?))
+        "#,
+    )
+    .bind(owner)
+    .bind(key)
+    .bind(now - KEY_TTL)
+    .bind(now - PENDING_TTL)
+    .execute(pool)
+    .await
+    {
+        error!("DB delete idempotency key error {}: {}", key, e);
+        return Err(db::error_response(&e, "Database delete error"));
+    }
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys
+            (owner, idempotency_key, file_id, created_at)
+        VALUES (?, ?, NULL, ?)
+        "#,
+    )
+    .bind(owner)
+    .bind(key)
+    .bind(now)
+    .execute(pool)
+    .await;
+    match inserted {
+        Ok(_) => return Ok(Claim::New(reserve(pool, owner, key))),
+        // the key is taken, either by a finished upload or by one that is still running
+        Err(e) if e.as_database_error().is_some_and(|e| e.is_unique_violation()) => {}
+        Err(e) => {
+            error!("DB insert idempotency key error {}: {}", key, e);
+            return Err(db::error_response(&e, "Database insert error"));
+        }
+    }
+
+    let file_id = sqlx::query_scalar::<_, Option<String>>(
+        r#"
+        SELECT file_id
+        FROM idempotency_keys
+        WHERE owner = ? AND idempotency_key = ?
+        "#,
+    )
+    .bind(owner)
+    .bind(key)
+    .fetch_optional(pool)
+    .await;
+    let file_id = match file_id {
+        Ok(Some(Some(file_id))) => file_id,
+        Ok(_) => return Err(running()),
+        Err(e) => {
+            error!("DB select idempotency key error {}: {}", key, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    if let Some(file) = api::find_file(pool, "id", &file_id).await?.filter(|file| file.owner == owner) {
+        info!("Replaying upload {} of {} for Idempotency-Key {}", file.id, owner, key);
+        return Ok(Claim::Replay(Box::new(file)));
+    }
+
+    // the file is gone, the key is handed to this upload unless another retry took it first
+    let taken = sqlx::query(
+        r#"
+        UPDATE idempotency_keys
+        SET file_id = NULL, created_at = ?
+        WHERE owner = ? AND idempotency_key = ? AND file_id = ?
+        "#,
+    )
+    .bind(now)
+    .bind(owner)
+    .bind(key)
+    .bind(&file_id)
+    .execute(pool)
+    .await;
+    match taken {
+        Ok(result) if result.rows_affected() > 0 => Ok(Claim::New(reserve(pool, owner, key))),
+        Ok(_) => Err(running()),
+        Err(e) => {
+            error!("DB update idempotency key error {}: {}", key, e);
+            Err(db::error_response(&e, "Database update error"))
+        }
+    }
+}
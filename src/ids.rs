@@ -0,0 +1,64 @@
+use chrono::Utc;
+use rand::Rng;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::data;
+use crate::error::ApiError;
+
+/// The characters of a nanoid, every one of them is safe in a URL path.
+const NANOID_ALPHABET: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz_-";
+
+/// The Crockford base32 characters a ULID is written with.
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The shortest nanoid `id_length` allows, shorter ones would collide too soon.
+pub(crate) const MIN_LENGTH: usize = 8;
+
+/// The longest nanoid `id_length` allows.
+pub(crate) const MAX_LENGTH: usize = 64;
+
+/// Returns the ID of a new file in the `id_format` of the server:
+/// a random UUID, a nanoid of `id_length` characters or a ULID, which sorts by the time it was made.
+pub(crate) fn new_file_id(config: &data::Config) -> String {
+    match config.id_format.as_str() {
+        "nanoid" => nanoid(config.id_length),
+        "ulid" => ulid(),
+        _ => Uuid::from_u128(rand::rng().random::<u128>()).to_string(),
+    }
+}
+
+/// Returns a random nanoid of `length` characters.
+fn nanoid(length: usize) -> String {
+    let mut rng = rand::rng();
+    (0..length)
+        .map(|_| NANOID_ALPHABET[rng.random_range(0..NANOID_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Returns a ULID, 48 bits of the time in milliseconds followed by 80 random bits,
+/// written as 26 base32 characters.
+fn ulid() -> String {
+    let time = Utc::now().timestamp_millis().max(0) as u128 & ((1 << 48) - 1);
+    let random = rand::rng().random::<u128>() & ((1 << 80) - 1);
+    let value = time << 80 | random;
+    (0..26)
+        .rev()
+        .map(|i| ULID_ALPHABET[(value >> (i * 5)) as usize & 31] as char)
+        .collect()
+}
+
+/// Returns true if a file ID taken from the request is an ID the server could have made.
+/// Every format is accepted, so files keep their links when `id_format` changes.
+/// Handlers reject anything else with `invalid_file_id`
+/// before it reaches the database or the file system.
+pub(crate) fn is_valid_file_id(id: &str) -> bool {
+    Uuid::try_parse(id).is_ok()
+        || ((MIN_LENGTH..=MAX_LENGTH).contains(&id.len()) && id.bytes().all(|b| NANOID_ALPHABET.contains(&b)))
+}
+
+/// Returns the error for a file ID the server could not have made.
+pub(crate) fn invalid_file_id(id: &str) -> ApiError {
+    warn!("Invalid file ID: {:?}", id);
+    ApiError::InvalidFileId
+}
@@ -0,0 +1,269 @@
+use axum::{
+    extract::ConnectInfo,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use sqlx::AnyPool;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::cache;
+use crate::changes;
+use crate::data;
+use crate::db;
+use crate::storage;
+use crate::thumbnail;
+
+/// The content hash the next scrub starts after, the scrubs walk the blobs in order of their hash
+/// and start over once they reached the end.
+/// It is kept in memory, after a restart the scrubs start at the beginning again.
+static CURSOR: Mutex<String> = Mutex::new(String::new());
+
+/// The number of unreferenced blobs the startup check logs by name, the rest are only counted.
+const MAX_LOGGED_BLOBS: usize = 20;
+
+/// Returns the number of `files` and `file_versions` rows that reference a blob.
+async fn references(pool: &AnyPool, content_hash: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT (SELECT COUNT(*) FROM files WHERE content_hash = ?)
+            + (SELECT COUNT(*) FROM file_versions WHERE content_hash = ?)
+        "#,
+    )
+    .bind(content_hash)
+    .bind(content_hash)
+    .fetch_one(pool)
+    .await
+}
+
+/// Records an issue with a blob, an issue found again keeps the time it was first detected.
+async fn flag(pool: &AnyPool, content_hash: &str, issue: &str, detail: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now().timestamp();
+    warn!("Integrity issue with blob {}: {} {}", content_hash, issue, detail);
+    let updated = sqlx::query(
+        r#"
+        UPDATE integrity_issues
+        SET issue = ?, detail = ?, checked_at = ?
+        WHERE content_hash = ?
+        "#,
+    )
+    .bind(issue)
+    .bind(detail)
+    .bind(now)
+    .bind(content_hash)
+    .execute(pool)
+    .await?;
+    if updated.rows_affected() == 0 {
+        sqlx::query(
+            r#"
+            INSERT INTO integrity_issues
+                (content_hash, issue, detail, detected_at, checked_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(content_hash)
+        .bind(issue)
+        .bind(detail)
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// This function re-hashes the next `scrub_batch_size` blobs and compares them with their content hash.
+/// Blobs that are missing, can't be read or whose contents changed are flagged in `integrity_issues`,
+/// issues of blobs that check out again are removed.
+/// Blobs stored before content hashes existed have nothing to compare with and are skipped.
+/// The jobs module runs it every `scrub_interval` seconds.
+pub async fn scrub(pool: &AnyPool, config: &data::Config) -> Result<(), sqlx::Error> {
+    let cursor = CURSOR.lock().unwrap().clone();
+    // the size caps the decompressed blob, see `storage::read_blob`
+    let batch = sqlx::query_as::<_, (String, i64)>(
+        r#"
+        SELECT content_hash, MAX(file_size) FROM (
+            SELECT content_hash, file_size FROM files WHERE content_hash > ?
+            UNION ALL
+            SELECT content_hash, file_size FROM file_versions WHERE content_hash > ?
+        ) AS blobs
+        GROUP BY content_hash
+        ORDER BY content_hash
+        LIMIT ?
+        "#,
+    )
+    .bind(&cursor)
+    .bind(&cursor)
+    .bind(config.scrub_batch_size as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let mut issues = 0;
+    for (content_hash, file_size) in &batch {
+        let issue = match storage::read_blob(config, content_hash, (*file_size).max(0) as u64).await {
+            Ok(contents) => {
+                let computed = storage::content_hash(&contents);
+                (computed != *content_hash).then_some(("mismatch", computed))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Some(("missing", String::new())),
+            Err(e) => Some(("unreadable", e.to_string())),
+        };
+        match issue {
+            // the last file of a blob may have been deleted while it was read
+            Some(("missing", _)) if references(pool, content_hash).await? == 0 => {}
+            Some((issue, detail)) => {
+                flag(pool, content_hash, issue, &detail).await?;
+                issues += 1;
+            }
+            None => {
+                sqlx::query("DELETE FROM integrity_issues WHERE content_hash = ?")
+                    .bind(content_hash)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+    }
+
+    // a short batch reached the end of the blobs, the next scrub starts over
+    *CURSOR.lock().unwrap() = match batch.len() < config.scrub_batch_size as usize {
+        true => String::new(),
+        false => batch.last().map(|(content_hash, _)| content_hash.clone()).unwrap_or_default(),
+    };
+    info!("Scrubbed {} blobs, {} with issues", batch.len(), issues);
+    Ok(())
+}
+
+/// This function compares the `files` table with the stored blobs once at startup if `startup_check` is set,
+/// so a restart after a crash or a restored backup starts from a known state.
+/// It only looks at which blobs exist, nothing is read or hashed, that is left to the scrub job.
+/// Files whose blob is missing are marked with `blob_missing` and answer with 410,
+/// the mark is taken off again once the blob is back. Their blobs are flagged in `integrity_issues` too.
+/// Blobs no file or version references are only logged, the gc job removes them.
+/// It runs under the blob lock, so uploads and deletes of other instances wait until it is done.
+pub async fn reconcile(pool: &AnyPool, config: &data::Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let _guard = storage::lock(pool).await?;
+    let blobs = storage::list_blobs(config).await?;
+    let stored: HashSet<&str> = blobs
+        .iter()
+        .filter_map(|(stored, _)| storage::parse_stored_name(stored))
+        .map(|(name, _)| name)
+        .collect();
+    let files = sqlx::query_as::<_, (String, String, Option<String>, i32)>(
+        "SELECT id, owner, content_hash, blob_missing FROM files",
+    )
+    .fetch_all(pool)
+    .await?;
+    let versions = sqlx::query_scalar::<_, Option<String>>("SELECT content_hash FROM file_versions")
+        .fetch_all(pool)
+        .await?;
+
+    let (mut missing, mut recovered) = (0, 0);
+    for (id, owner, content_hash, blob_missing) in &files {
+        let blob = content_hash.as_deref().unwrap_or(id);
+        let is_missing = !stored.contains(blob);
+        if is_missing == (*blob_missing != 0) {
+            continue;
+        }
+        sqlx::query("UPDATE files SET blob_missing = ? WHERE id = ?")
+            .bind(is_missing as i32)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        cache::forget_file(id).await;
+        changes::record(pool, owner, id, changes::Change::Updated).await;
+        if is_missing {
+            warn!("File {} is unavailable, its blob {} is missing", id, blob);
+            if let Some(content_hash) = content_hash {
+                flag(pool, content_hash, "missing", "").await?;
+            }
+            missing += 1;
+        } else {
+            info!("File {} is available again, its blob {} is back", id, blob);
+            recovered += 1;
+        }
+    }
+
+    let referenced: HashSet<String> = files
+        .iter()
+        .map(|(id, _, content_hash, _)| content_hash.clone().unwrap_or_else(|| id.clone()))
+        .chain(versions.into_iter().flatten())
+        .flat_map(|name| [thumbnail::thumbnail_name(&name), name])
+        .collect();
+    let unexpected: Vec<&str> = stored
+        .iter()
+        .copied()
+        .filter(|name| !referenced.contains(*name))
+        .collect();
+    for name in unexpected.iter().take(MAX_LOGGED_BLOBS) {
+        warn!("Blob {} is not referenced by any file", name);
+    }
+
+    info!(
+        "Startup check found {} of {} files missing their blob, {} available again and {} unreferenced blobs",
+        missing,
+        files.len(),
+        recovered,
+        unexpected.len()
+    );
+    Ok(())
+}
+
+/// Handler to list integrity issues
+/// This function returns every blob the scrub job found missing, unreadable
+/// or with contents that no longer match their content hash, the most recently detected first,
+/// together with the IDs of the files that use the blob.
+/// `issue` is `missing`, `unreadable` or `mismatch`,
+/// `detail` is the hash of the stored contents for a mismatch and the error for an unreadable blob.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/integrity
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn list_issues(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin integrity request from IP: {}", ip);
+
+    if let Err(response) = admin::require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    let issues = sqlx::query_as::<_, data::IntegrityIssue>(
+        r#"
+        SELECT *
+        FROM integrity_issues
+        ORDER BY detected_at DESC
+        "#,
+    )
+    .fetch_all(&pool)
+    .await;
+    let mut issues = match issues {
+        Ok(issues) => issues,
+        Err(e) => {
+            error!("DB select integrity issues error: {}", e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+    for issue in &mut issues {
+        let files = sqlx::query_scalar::<_, String>("SELECT id FROM files WHERE content_hash = ?")
+            .bind(&issue.content_hash)
+            .fetch_all(&pool)
+            .await;
+        match files {
+            Ok(files) => issue.files = files,
+            Err(e) => {
+                error!("DB select integrity files error {}: {}", issue.content_hash, e);
+                return db::error_response(&e, "Database select error");
+            }
+        }
+    }
+    Json(issues).into_response()
+}
@@ -0,0 +1,98 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, warn};
+
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+
+/// The window the daily upload caps of an IP address are counted over, in seconds.
+pub(crate) const WINDOW: i64 = 24 * 60 * 60;
+
+/// Returns true if any daily cap per IP address is configured.
+fn enabled(config: &data::Config) -> bool {
+    config.ip_daily_upload_count > 0 || config.ip_daily_upload_bytes > 0
+}
+
+/// Helper to check that `count` more uploads of `bytes` bytes keep an IP address within its daily caps.
+/// The caps count the uploads of every account and of anonymous uploaders from the address,
+/// so a leaked key used from a single address can't upload without end.
+/// Uploads over a cap are refused with 429.
+pub(crate) async fn check(pool: &AnyPool, config: &data::Config, ip: &str, count: usize, bytes: i64) -> Result<(), Response> {
+    if !enabled(config) {
+        return Ok(());
+    }
+    let used = sqlx::query_as::<_, (i64, i64)>(&format!(
+        r#"
+        SELECT COUNT(*), {}
+        FROM ip_uploads
+        WHERE ip = ? AND upload_time > ?
+        "#,
+        db::sum(pool, "file_size"),
+    ))
+    .bind(ip)
+    .bind(Utc::now().timestamp() - WINDOW)
+    .fetch_one(pool)
+    .await;
+    let (used_count, used_bytes) = match used {
+        Ok((used_count, used_bytes)) => (
+            u64::try_from(used_count).unwrap_or_default(),
+            u64::try_from(used_bytes).unwrap_or_default(),
+        ),
+        Err(e) => {
+            error!("DB select IP uploads error {}: {}", ip, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    let over_count = config.ip_daily_upload_count > 0
+        && used_count.saturating_add(count as u64) > config.ip_daily_upload_count;
+    let over_bytes = config.ip_daily_upload_bytes > 0
+        && used_bytes.saturating_add(bytes.max(0) as u64) > config.ip_daily_upload_bytes;
+    if !over_count && !over_bytes {
+        return Ok(());
+    }
+    warn!("Upload from {} over the daily IP caps refused", ip);
+    Err(ApiError::Rejected {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        error: "ip_upload_limit",
+        message: "Too many uploads from your address today".to_string(),
+        details: json!({
+            "daily_upload_count": config.ip_daily_upload_count,
+            "daily_upload_bytes": config.ip_daily_upload_bytes,
+            "used_count": used_count,
+            "used_bytes": used_bytes,
+        }),
+    }
+    .into_response())
+}
+
+/// This function counts stored uploads against the daily caps of the IP address they came from.
+/// Nothing is recorded while no cap is configured.
+pub(crate) async fn record(pool: &AnyPool, config: &data::Config, ip: &str, sizes: &[i64]) {
+    if !enabled(config) {
+        return;
+    }
+    let now = Utc::now().timestamp();
+    for size in sizes {
+        if let Err(e) = sqlx::query(
+            r#"
+            INSERT INTO ip_uploads
+                (ip, file_size, upload_time)
+            VALUES (?, ?, ?)
+            "#,
+        )
+        .bind(ip)
+        .bind(size)
+        .bind(now)
+        .execute(pool)
+        .await
+        {
+            error!("DB insert IP upload error {}: {}", ip, e);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use bytes::Bytes;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::data;
+use crate::trace_context;
+
+/// How long the IPFS node may take to add or unpin a blob.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The boundary of the multipart body the blob is added with.
+const BOUNDARY: &str = "bitbeam-ipfs-boundary";
+
+/// This struct represents the answer of the node to `/api/v0/add`.
+#[derive(Deserialize)]
+struct Added {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+/// Returns the URL of an RPC endpoint of the node at `ipfs_api_url`.
+fn endpoint(api_url: &str, path: &str) -> String {
+    format!("{}/api/v0/{}", api_url.trim_end_matches('/'), path)
+}
+
+/// This function adds a blob to the IPFS node at `ipfs_api_url` and pins it.
+/// It speaks the Kubo RPC API, which pinning services offer as well,
+/// their credentials go in the URL like `https://<user>:<secret>@<host>`.
+/// The blob is added as a raw CIDv1, so identical contents always get the same CID.
+/// It returns the CID of the blob.
+pub async fn add(api_url: &str, body: &Bytes) -> Result<String, String> {
+    let mut multipart = Vec::with_capacity(body.len() + 256);
+    multipart.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"blob\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            BOUNDARY
+        )
+        .as_bytes(),
+    );
+    multipart.extend_from_slice(body);
+    multipart.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+    let request = reqwest::Client::new()
+        .post(endpoint(api_url, "add"))
+        .query(&[("pin", "true"), ("cid-version", "1"), ("raw-leaves", "true")])
+        .header("content-type", format!("multipart/form-data; boundary={}", BOUNDARY))
+        .timeout(REQUEST_TIMEOUT)
+        .body(multipart);
+    let answer = trace_context::inject(request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !answer.status().is_success() {
+        return Err(format!("IPFS node answered {}", answer.status()));
+    }
+    let added: Added = answer.json().await.map_err(|e| e.to_string())?;
+    Ok(added.hash)
+}
+
+/// This function mirrors the blob of an upload to IPFS if `ipfs_api_url` is configured.
+/// The IPFS network is public, anyone who learns the CID can fetch the contents,
+/// so only public files are mirrored.
+/// Mirroring is best effort, an unreachable node never fails the upload.
+/// It returns the CID of the blob, `None` if it was not mirrored.
+pub async fn mirror(config: &data::Config, id: &str, visibility: &str, body: &Bytes) -> Option<String> {
+    let api_url = config.ipfs_api_url.as_deref()?;
+    if visibility != "public" {
+        return None;
+    }
+    match add(api_url, body).await {
+        Ok(cid) => {
+            info!("Upload {} mirrored to IPFS as {}", id, cid);
+            Some(cid)
+        }
+        Err(e) => {
+            warn!("IPFS add error for upload {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// This function unpins a blob that is no longer referenced from the IPFS node,
+/// so the node can collect it. The contents stay on the network while other nodes have them.
+pub async fn unpin(config: &data::Config, cid: &str) {
+    let Some(api_url) = config.ipfs_api_url.as_deref() else {
+        return;
+    };
+    let request = reqwest::Client::new()
+        .post(endpoint(api_url, "pin/rm"))
+        .query(&[("arg", cid)])
+        .timeout(REQUEST_TIMEOUT);
+    let answer = trace_context::inject(request)
+        .send()
+        .await;
+    match answer {
+        Ok(answer) if answer.status().is_success() => info!("Unpinned {} from IPFS", cid),
+        Ok(answer) => warn!("IPFS unpin error for {}: node answered {}", cid, answer.status()),
+        Err(e) => warn!("IPFS unpin error for {}: {}", cid, e),
+    }
+}
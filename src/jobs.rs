@@ -0,0 +1,258 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use rand::Rng;
+use sqlx::AnyPool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::audit;
+use crate::capacity;
+use crate::cleanup;
+use crate::data;
+use crate::gc;
+use crate::integrity;
+use crate::replica;
+
+/// A run of a job, it fails with a message for the job status.
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+/// This struct represents a recurring task of the server.
+/// `interval` returns how many seconds lie between two runs, 0 means the job only runs when triggered.
+struct Job {
+    name: &'static str,
+    interval: fn(&data::Config) -> u64,
+    run: fn(AnyPool, data::Config) -> JobFuture,
+}
+
+/// The registered jobs, every entry runs on its own schedule.
+const JOBS: [Job; 5] = [
+    Job {
+        name: "cleanup",
+        interval: |config| config.cleanup_interval,
+        run: run_cleanup,
+    },
+    Job {
+        name: "gc",
+        interval: |config| config.gc_interval,
+        run: run_gc,
+    },
+    Job {
+        name: "store_check",
+        interval: |config| config.store_check_interval,
+        run: run_store_check,
+    },
+    Job {
+        name: "scrub",
+        interval: |config| config.scrub_interval,
+        run: run_scrub,
+    },
+    Job {
+        name: "replicate",
+        interval: |config| match config.replica_path {
+            Some(_) => config.replica_sync_interval,
+            None => 0,
+        },
+        run: run_replicate,
+    },
+];
+
+/// The status of every job, kept in the memory of this instance.
+static STATUS: LazyLock<Mutex<HashMap<&'static str, data::JobStatus>>> = LazyLock::new(Default::default);
+
+/// Enforces expiry times and the retention policy, see the cleanup module.
+fn run_cleanup(pool: AnyPool, config: data::Config) -> JobFuture {
+    Box::pin(async move { cleanup::run(&pool, &config).await.map_err(|e| e.to_string()) })
+}
+
+/// Removes orphaned blobs and dangling rows, see the gc module.
+fn run_gc(pool: AnyPool, config: data::Config) -> JobFuture {
+    Box::pin(async move { gc::collect(&pool, &config).await.map(|_| ()).map_err(|e| e.to_string()) })
+}
+
+/// Measures the disk usage of the store, see the capacity module.
+fn run_store_check(pool: AnyPool, config: data::Config) -> JobFuture {
+    Box::pin(async move { capacity::verify(&pool, &config).await })
+}
+
+/// Re-hashes the next blobs, see the integrity module.
+fn run_scrub(pool: AnyPool, config: data::Config) -> JobFuture {
+    Box::pin(async move { integrity::scrub(&pool, &config).await.map_err(|e| e.to_string()) })
+}
+
+/// Copies the blobs the replica is missing, see the replica module.
+fn run_replicate(_pool: AnyPool, config: data::Config) -> JobFuture {
+    Box::pin(async move { replica::sync(&config).await.map(|_| ()) })
+}
+
+/// Returns a random delay of up to a tenth of the interval,
+/// so instances that share a database don't all run a job at the same moment.
+fn jitter(interval: u64) -> Duration {
+    Duration::from_millis(rand::rng().random_range(0..=interval.saturating_mul(100)))
+}
+
+/// Returns the status of a job, registering it on first use.
+fn status<'a>(
+    statuses: &'a mut HashMap<&'static str, data::JobStatus>,
+    job: &Job,
+    config: &data::Config,
+) -> &'a mut data::JobStatus {
+    statuses.entry(job.name).or_insert_with(|| data::JobStatus {
+        name: job.name.to_string(),
+        interval: (job.interval)(config),
+        ..Default::default()
+    })
+}
+
+/// This function runs a job once and records the outcome in its status.
+/// It returns false without running the job if it is already running.
+async fn execute(job: &Job, pool: AnyPool, config: data::Config) -> bool {
+    {
+        let mut statuses = STATUS.lock().unwrap();
+        let status = status(&mut statuses, job, &config);
+        if status.running {
+            return false;
+        }
+        status.running = true;
+        status.last_started = Some(Utc::now().timestamp());
+    }
+    let started = Instant::now();
+    // the job runs in its own task, so a panic fails the run instead of the scheduler
+    let result = match tokio::spawn((job.run)(pool, config.clone())).await {
+        Ok(result) => result,
+        Err(e) => Err(format!("the job panicked: {}", e)),
+    };
+    let duration = started.elapsed();
+
+    let mut statuses = STATUS.lock().unwrap();
+    let status = status(&mut statuses, job, &config);
+    status.running = false;
+    status.runs += 1;
+    status.last_finished = Some(Utc::now().timestamp());
+    status.last_duration_ms = Some(duration.as_millis() as u64);
+    match result {
+        Ok(()) => {
+            info!("Job {} finished in {:?}", job.name, duration);
+            status.last_error = None;
+        }
+        Err(e) => {
+            error!("Job {} failed after {:?}: {}", job.name, duration, e);
+            status.failures += 1;
+            status.last_error = Some(e);
+        }
+    }
+    true
+}
+
+/// This function starts the scheduler that runs every registered job on its interval.
+/// A job runs once at startup and then every interval plus a random jitter of up to a tenth of it.
+/// Jobs with an interval of 0 never run on their own but can still be triggered by an admin.
+pub fn start(pool: AnyPool, config: data::Config) {
+    for job in &JOBS {
+        let interval = (job.interval)(&config);
+        status(&mut STATUS.lock().unwrap(), job, &config);
+        if interval == 0 {
+            info!("Periodic job {} disabled", job.name);
+            continue;
+        }
+        let (pool, config) = (pool.clone(), config.clone());
+        tokio::spawn(async move {
+            loop {
+                if !execute(job, pool.clone(), config.clone()).await {
+                    warn!("Job {} is still running, skipping this run", job.name);
+                }
+                let delay = Duration::from_secs(interval) + jitter(interval);
+                if let Some(status) = STATUS.lock().unwrap().get_mut(job.name) {
+                    status.next_run = Some(Utc::now().timestamp() + delay.as_secs() as i64);
+                }
+                tokio::time::sleep(delay).await;
+            }
+        });
+    }
+}
+
+/// Handler to list the background jobs
+/// This function returns every job with its interval, whether it is running,
+/// how often it ran and failed and the time, duration and error of its last run.
+/// The status is kept by each instance, behind a load balancer it belongs to the instance that answers.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/jobs
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn list_jobs(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin jobs request from IP: {}", ip);
+
+    if let Err(response) = admin::require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    let mut statuses = STATUS.lock().unwrap();
+    let jobs: Vec<data::JobStatus> = JOBS
+        .iter()
+        .map(|job| status(&mut statuses, job, &config).clone())
+        .collect();
+    Json(jobs).into_response()
+}
+
+/// Handler to run a background job now
+/// This function starts a run of the job in the background and returns its status,
+/// the outcome shows up at `/admin/jobs` once the run finished.
+/// It answers 409 if the job is already running.
+/// example request: curl -X POST -H "key: <admin key>" http://localhost:3000/admin/jobs/gc/run
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - name: the name of the job, `cleanup`, `gc`, `store_check` or `scrub` (not optional)
+#[instrument(skip_all, fields(name = %name))]
+pub async fn run_job(
+    Path(name): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin run job request for {} from IP: {}", name, ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+    let Some(job) = JOBS.iter().find(|job| job.name == name) else {
+        return (StatusCode::NOT_FOUND, "Job not found").into_response();
+    };
+    if status(&mut STATUS.lock().unwrap(), job, &config).running {
+        return (StatusCode::CONFLICT, "The job is already running").into_response();
+    }
+
+    audit::record(&pool, audit::Action::AdminRunJob, Some(&admin.username), Some(job.name), &ip).await;
+    let (run_pool, run_config) = (pool.clone(), config.clone());
+    tokio::spawn(async move {
+        if !execute(job, run_pool, run_config).await {
+            warn!("Job {} is already running", job.name);
+        }
+    });
+    // give the run a moment to register, so the answer shows it running
+    tokio::task::yield_now().await;
+    let status = status(&mut STATUS.lock().unwrap(), job, &config).clone();
+    (StatusCode::ACCEPTED, Json(status)).into_response()
+}
@@ -0,0 +1,150 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rand::Rng;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+use crate::audit;
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::events;
+use crate::extract::AuthUser;
+use std::net::SocketAddr;
+
+/// The maximum length of the name of a key in bytes.
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Returns a short SHA-256 prefix of a key, which tells keys apart in logs without revealing them.
+pub(crate) fn fingerprint(key: &str) -> String {
+    let digest = hex::encode(Sha256::digest(key.as_bytes()));
+    digest[..12].to_string()
+}
+
+/// Handler to create an API key
+/// This function adds another key to the account of the user, for example one per device,
+/// so each of them can be revoked on its own. Every key has the full rights of the account.
+/// The key itself is only returned once.
+/// Scoped tokens can't create keys, the account key or a session is needed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"name": "laptop"}' http://localhost:3000/user/keys
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// accepts the following JSON body:
+/// - name: a name to recognize the key by (optional)
+#[instrument(skip_all)]
+pub async fn create_key(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+    Json(request): Json<data::NewApiKey>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received key creation from IP: {}", ip);
+
+    let name = request.name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty());
+    if name.as_ref().is_some_and(|name| name.len() > MAX_NAME_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "The name of a key can be at most {} bytes long",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    let key = Uuid::from_u128(rand::rng().random::<u128>()).to_string();
+    let inserted = async {
+        let mut connection = pool.acquire().await?;
+        db::insert_api_key(&mut connection, &user.username, &key, name.as_deref()).await
+    };
+    let id = match inserted.await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("DB insert key error {}: {}", user.username, e);
+            return Err(db::error(&e, "Database insert error"));
+        }
+    };
+    info!("Key {} created for {}", id, user.username);
+    audit::record(&pool, audit::Action::CreateKey, Some(&user.username), Some(&id), &ip).await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "id": id,
+            "name": name,
+            "key": key,
+        })),
+    )
+        .into_response())
+}
+
+/// Handler to list the API keys of a user
+/// This function returns every key of the user without the key itself,
+/// with the time it was last used and, for revoked keys, the time it was revoked.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/user/keys
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn list_keys(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received key listing from IP: {}", ip);
+
+    match db::list_api_keys(&pool, &user.username).await {
+        Ok(keys) => Ok(Json(keys).into_response()),
+        Err(e) => {
+            error!("DB select keys error {}: {}", user.username, e);
+            Err(db::error(&e, "Database select error"))
+        }
+    }
+}
+
+/// Handler to revoke an API key
+/// This function revokes a key of the user, requests with it are rejected immediately,
+/// on every instance of a cluster. Revoking the key the request is made with is allowed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/user/keys/<id>
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - id: the ID of the key (not optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn revoke_key(
+    Path(id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received key revocation for {} from IP: {}", id, ip);
+
+    match db::revoke_api_key(&pool, &id, &user.username).await {
+        Ok(Some(key)) => {
+            cache::forget_key(&key).await;
+            events::publish(events::Event::KeyRevoked { key });
+            info!("Key {} of {} revoked", id, user.username);
+            audit::record(&pool, audit::Action::RevokeKey, Some(&user.username), Some(&id), &ip).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Ok(None) => Err(ApiError::NotFound("Key not found".to_string())),
+        Err(e) => {
+            error!("DB revoke key error {}: {}", id, e);
+            Err(db::error(&e, "Database update error"))
+        }
+    }
+}
@@ -0,0 +1,1342 @@
+//! bitBeam is a small self-hosted file sharing server.
+//! The `bitBeam` binary is a thin wrapper around this crate,
+//! other Axum applications can embed the server with `build_app`.
+//! With the `test-support` feature `test_support::TestServer` runs it in-process for integration tests.
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{any, delete, get, post, put},
+    Extension, Router,
+};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::{error, info, warn};
+use sqlx::{any::AnyPoolOptions, migrate::MigrateDatabase, AnyPool, Sqlite};
+
+use std::path::Path;
+use tokio::fs;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper_util::rt::TokioTimer;
+mod access_log;
+mod admin;
+mod anonymous;
+mod api;
+mod archive;
+mod audit;
+mod backup;
+mod batch;
+mod blob;
+mod cache;
+mod capacity;
+mod changes;
+mod captcha;
+mod clamav;
+mod cleanup;
+pub mod cli;
+mod cluster;
+mod collections;
+mod compression;
+pub mod config;
+pub mod data;
+mod dav;
+mod db;
+mod directory;
+mod email;
+mod encryption;
+mod error;
+mod events;
+pub mod error_reporting;
+mod extract;
+mod feed;
+mod gc;
+mod geoip;
+mod human;
+mod idempotency;
+mod ids;
+mod integrity;
+mod ip_quota;
+mod ipfs;
+mod jobs;
+mod keys;
+mod lockout;
+pub mod logging;
+mod maintenance;
+mod markdown;
+mod memory_cache;
+mod matrix;
+mod notify;
+mod otlp;
+mod openapi;
+mod parts;
+mod pin;
+mod profile;
+mod progress;
+mod player;
+mod presign;
+mod proxy;
+mod qr;
+mod ratelimit;
+mod route_policy;
+mod registration;
+mod reload;
+mod remote;
+mod replica;
+mod reports;
+mod s3;
+pub mod self_check;
+mod session;
+mod share;
+mod signature;
+mod sse;
+mod sharex;
+mod stats;
+mod storage;
+#[cfg(unix)]
+mod systemd;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+mod throttle;
+mod thumbnail;
+mod tls;
+mod tokens;
+mod torrent;
+mod trace_context;
+mod transfer;
+#[cfg(unix)]
+mod unix;
+mod unpack;
+mod versions;
+mod web;
+mod webhook;
+
+/// This function builds the router with every route and layer of the server.
+/// The pool must be connected and migrated, see `connect` and `migrate`.
+/// The router expects `ConnectInfo<SocketAddr>`, serve it with
+/// `into_make_service_with_connect_info::<SocketAddr>()` or add the extension yourself.
+/// Background tasks like webhook delivery and cleanup are not started, see `start_background_tasks`.
+/// It only fails if the configured access log, GeoIP databases or read replica can't be opened.
+pub fn build_app(config: data::Config, pool: AnyPool) -> std::io::Result<Router> {
+    // limit the bandwidth downloads may use
+    throttle::init(&config);
+
+    // sign session cookies with the configured or a random secret
+    session::init(&config);
+
+    // lock out clients and usernames that keep failing to log in
+    lockout::init(&config);
+
+    // cache hot file rows and key lookups if Redis is configured
+    cache::init(&config);
+
+    // keep small, often downloaded blobs in memory if a memory cache size is configured
+    memory_cache::init(&config);
+
+    // look up where downloads come from if GeoIP databases are configured
+    geoip::init(&config)?;
+
+    // send listings, statistics and metadata lookups to the read replica if one is configured
+    let read_pool = db::read_pool(&config, &pool)
+        .map_err(|e| std::io::Error::other(format!("the read replica {}", e)))?;
+
+    // Setting up the web server
+    // The web server is created using the Axum framework
+    // these are the routes
+    // routes that share a rate limit budget are grouped together
+    let rate_limits = ratelimit::RateLimits::from_config(&config);
+    // the config and rate limits can be reloaded while the server runs
+    reload::init(&config, &rate_limits);
+    let access_log = access_log::AccessLog::from_config(&config)?;
+    let uploads = Router::new()
+        .route("/upload", post(api::upload))
+        .route("/upload/remote", post(remote::upload_remote))
+        .route("/upload/batch", post(batch::upload_batch))
+        .route("/upload/anonymous", post(anonymous::upload_anonymous))
+        .route("/upload/presigned/{signed}", post(presign::upload_presigned))
+        // the segment names the file, it shares the path and so the parameter name with the multipart uploads
+        .route("/upload/{upload_id}", put(api::upload_named))
+        .route("/upload/init", post(parts::init_upload))
+        .route("/upload/{upload_id}/part/{part_number}", put(parts::upload_part))
+        .route("/upload/{upload_id}/complete", post(parts::complete_upload))
+        .route("/file/{uuid}", put(versions::upload_version))
+        .route("/s3/{bucket}/{*key}", put(s3::put_object))
+        .route("/_matrix/media/v3/upload", post(matrix::upload))
+        .route_layer(middleware::from_fn_with_state(rate_limits.uploads, ratelimit::limit))
+        // a stampede of uploads would hold every body in memory at once
+        .route_layer(middleware::from_fn_with_state(
+            ratelimit::ConcurrencyLimit::new("uploads", config.max_concurrent_uploads),
+            ratelimit::limit_concurrency,
+        ))
+        // uploads are refused while the instance is drained for maintenance
+        .route_layer(middleware::from_fn(maintenance::reject))
+        // uploads enforce the size limit of the uploading user themselves
+        .layer(DefaultBodyLimit::disable());
+    let downloads = Router::new()
+        // HEAD is answered separately, otherwise it would run the GET handler and count a download
+        .route(
+            "/download/{uuid}",
+            get(api::download_file)
+                .head(api::download_head)
+                .delete(api::delete_by_token),
+        )
+        .route("/download/zip", post(archive::download_zip))
+        .route("/d/{slug}", get(api::download_slug).head(api::download_slug_head))
+        .route("/thumbnail/{uuid}", get(api::thumbnail))
+        .route("/qr/{uuid}", get(qr::qr_code))
+        .route("/view/{uuid}", get(markdown::view))
+        .route("/play/{uuid}", get(player::play))
+        .route("/blob/{sha256}", get(blob::blob))
+        .route("/s3/{bucket}/{*key}", get(s3::get_object).head(s3::head_object))
+        .route("/_matrix/media/v3/download/{server_name}/{media_id}", get(matrix::download))
+        .route("/_matrix/media/v3/download/{server_name}/{media_id}/{file_name}", get(matrix::download))
+        .route_layer(middleware::from_fn_with_state(rate_limits.downloads, ratelimit::limit));
+    // file contents are only compressed when enabled and only if they compress well
+    let downloads = match config.compress_downloads {
+        true => downloads.layer(compression::downloads()),
+        false => downloads,
+    };
+    // WebDAV clients read and write through the same paths, uploads check the size limit themselves
+    let dav = Router::new()
+        .route("/dav", any(dav::handle))
+        .route("/dav/", any(dav::handle))
+        .route("/dav/{*path}", any(dav::handle))
+        // WebDAV clients can still read during maintenance, but not write
+        .layer(middleware::from_fn(maintenance::reject))
+        .layer(DefaultBodyLimit::disable());
+    let register = Router::new()
+        .route(
+            "/user/register",
+            post(api::register_user).layer(middleware::from_fn(maintenance::reject)),
+        )
+        // logins share the budget so passwords can't be guessed quickly
+        .route("/user/login", post(session::login))
+        .route_layer(middleware::from_fn_with_state(rate_limits.register, ratelimit::limit));
+    let reports = Router::new()
+        .route("/report/{uuid}", post(reports::report_file))
+        .route_layer(middleware::from_fn_with_state(rate_limits.reports, ratelimit::limit));
+    let public = Router::new()
+        .route("/public", get(directory::list_public))
+        .route_layer(middleware::from_fn_with_state(rate_limits.public, ratelimit::limit));
+    let routes = Router::new()
+        .route("/", get(web::index))
+        .route("/e/{uuid}", get(web::decrypt_page))
+        .route("/s/{uuid}", get(web::share_page))
+        .route("/c/{id}", get(web::collection_page))
+        .route("/p", get(web::public_page))
+        .merge(uploads)
+        .merge(register)
+        .merge(reports)
+        .merge(public)
+        .route("/api/spec", get(openapi::spec))
+        .route("/upload/presign", post(presign::presign))
+        .route("/upload/{upload_id}/progress", get(progress::upload_progress))
+        .route("/upload/{upload_id}", get(parts::get_upload).delete(parts::abort_upload))
+        .route("/all_files", get(api::all_files))
+        .route("/files", get(api::list_files))
+        .route("/files/changes", get(changes::list_changes))
+        .route("/my_files/archive", get(archive::my_files_archive))
+        .route("/file/{uuid}", delete(api::delete_file).patch(api::update_file))
+        .route("/file/{uuid}/versions", get(versions::list_versions))
+        .route("/file/{uuid}/email", post(email::email_file))
+        .route("/file/{uuid}/stats", get(stats::file_stats))
+        .route("/file/{uuid}/info", get(api::file_info))
+        .route("/file/{uuid}/torrent", get(torrent::torrent))
+        .route("/file/{uuid}/restore", post(api::restore_file))
+        .route("/file/{uuid}/transfer", post(api::transfer_file))
+        .route("/file/{uuid}/share", post(share::create_token).delete(share::revoke_tokens))
+        .route("/file/{uuid}/publish", put(directory::publish).delete(directory::unpublish))
+        .route("/file/{uuid}/pin", put(pin::pin).delete(pin::unpin))
+        .route("/file/{uuid}/signature", post(signature::upload_signature))
+        .route("/sharex", get(sharex::sharex_config))
+        .route("/user/register/challenge", get(captcha::challenge))
+        .route("/user/logout", post(session::logout))
+        .route("/user/verify/{token}", get(email::verify_email))
+        .route("/user/webhook", put(webhook::set_webhook))
+        .route("/user/pgp_key", put(signature::set_pgp_key))
+        .route("/user/notifications", put(notify::set_notifications))
+        .route("/user/feed", put(feed::enable_feed).delete(feed::disable_feed))
+        .route("/feed/{feed}", get(feed::atom_feed))
+        .route("/events", get(sse::events))
+        .route("/user/tokens", post(tokens::create_token).get(tokens::list_tokens))
+        .route("/user/tokens/{id}", delete(tokens::revoke_token))
+        .route("/user/keys", post(keys::create_key).get(keys::list_keys))
+        .route("/user/keys/{id}", delete(keys::revoke_key))
+        .route("/user/usage", get(api::user_usage))
+        .route("/user/stats", get(stats::user_stats))
+        .route(
+            "/user/me",
+            get(profile::get_profile)
+                .patch(profile::update_profile)
+                .delete(profile::delete_account),
+        )
+        .route("/collection", post(collections::create_collection))
+        .route("/collections", get(collections::list_collections))
+        .route(
+            "/collection/{id}",
+            get(collections::get_collection).delete(collections::delete_collection),
+        )
+        .route(
+            "/collection/{id}/files/{uuid}",
+            put(collections::add_file).delete(collections::remove_file),
+        )
+        .route("/admin/users", get(admin::list_users))
+        .route("/admin/users/{name}", delete(admin::delete_user))
+        .route("/admin/users/{name}/max_upload_bytes", put(admin::set_max_upload_bytes))
+        .route("/admin/files/{uuid}", delete(admin::delete_file))
+        .route("/admin/stats", get(admin::stats))
+        .route("/admin/dedup/report", get(admin::dedup_report))
+        .route("/admin/audit", get(admin::audit_log))
+        .route("/admin/audit/verify", get(admin::verify_audit_log))
+        .route("/admin/gc", post(gc::run_gc))
+        .route("/admin/reload", post(reload::reload_config))
+        .route("/admin/log_level", get(reload::get_log_level).put(reload::set_log_level))
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get_maintenance).post(maintenance::set_maintenance),
+        )
+        .route("/admin/jobs", get(jobs::list_jobs))
+        .route("/admin/jobs/{name}/run", post(jobs::run_job))
+        .route("/admin/integrity", get(integrity::list_issues))
+        .route("/admin/reports", get(reports::list_reports))
+        .route("/admin/reports/{id}/dismiss", post(reports::dismiss_report))
+        .route("/admin/reports/{id}/takedown", post(reports::takedown))
+        .route("/admin/export", get(backup::export_metadata))
+        .route("/admin/import", post(backup::import_metadata))
+        .route("/s3/{bucket}", get(s3::list_objects))
+        .route("/_matrix/media/v3/config", get(matrix::media_config))
+        .route("/s3/{bucket}/{*key}", delete(s3::delete_object).post(s3::unsupported))
+        // the layer only wraps the routes above, downloads bring their own
+        .layer(compression::api())
+        .merge(downloads)
+        .merge(dav);
+    // the body limit and timeout of every route, see `route_policies`
+    // paths are matched below the base path, the policies apply to the routes above
+    let routes = routes.layer(middleware::from_fn_with_state(
+        route_policy::Policies::from_config(&config),
+        route_policy::enforce,
+    ));
+    // behind a reverse proxy that forwards a path prefix every route lives below the prefix,
+    // the web UI answers with and without the trailing slash
+    let routes = match config.base_path.as_str() {
+        "" => routes,
+        base_path => Router::new()
+            .route(&format!("{}/", base_path), get(web::index))
+            .nest(base_path, routes),
+    };
+    let app = routes
+        .layer(DefaultBodyLimit::max(config.max_upload_size as usize))
+        .layer(middleware::from_fn_with_state(config.clone(), error_reporting::capture_errors))
+        // assign every request an ID and handle it in a span carrying that ID and its W3C trace
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(middleware::from_fn(trace_context::propagate))
+                .layer(TraceLayer::new_for_http().make_span_with(logging::make_request_span))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
+        // one line per request in the access log, including requests refused by the layers above
+        .layer(middleware::from_fn_with_state(access_log, access_log::log))
+        // everything above sees the real client behind a trusted reverse proxy
+        .layer(middleware::from_fn_with_state(
+            Arc::from(config.trusted_proxies.clone()),
+            proxy::real_client_ip,
+        ))
+        .layer(Extension(pool))
+        .layer(Extension(read_pool))
+        // handlers get the config as it is after the last reload
+        .layer(middleware::from_fn(reload::current_config));
+    Ok(app)
+}
+
+/// This function starts the tasks that run next to the server:
+/// webhook delivery, the scheduler of the recurring jobs,
+/// garbage collection, the removal of expired files, the events of other instances,
+/// the audit log writer and the export of request spans.
+pub fn start_background_tasks(pool: &AnyPool, config: &data::Config) {
+    // start delivering webhooks in the background
+    webhook::start(pool.clone(), config.clone());
+    // remove orphaned blobs, dangling rows and expired files in the background
+    jobs::start(pool.clone(), config.clone());
+    // apply deletions, revoked keys and maintenance switches of the other instances
+    events::start(pool.clone(), config.clone());
+    // record failed logins without holding up the requests that failed
+    audit::start(pool.clone());
+    // copy new blobs to the replica if one is configured
+    replica::start(config.clone());
+    // export request spans if an OTLP endpoint is configured
+    otlp::start(config);
+}
+
+/// This function starts the background tasks and the web server
+/// and runs until the server stops.
+pub async fn serve(pool: AnyPool, config: data::Config) {
+    // mark files whose blob went missing before the first request is answered
+    if config.startup_check {
+        if let Err(e) = integrity::reconcile(&pool, &config).await {
+            error!("Startup consistency check failed: {}", e);
+        }
+    }
+    start_background_tasks(&pool, &config);
+    // `kill -HUP` reloads the config like `POST /admin/reload`
+    #[cfg(unix)]
+    tokio::spawn(reload::reload_on_hangup());
+    // `kill -USR1` makes the log more verbose, after debug it starts over at error
+    #[cfg(unix)]
+    tokio::spawn(reload::cycle_log_level_on_user1());
+    // a `Type=notify` unit with `WatchdogSec=` restarts the server once the pings stop
+    #[cfg(unix)]
+    systemd::start_watchdog(pool.clone());
+
+    let app = match build_app(config.clone(), pool) {
+        Ok(app) => app,
+        Err(e) => {
+            error!("Could not open {}", e);
+            return;
+        }
+    };
+
+    // behind a local reverse proxy the server can listen on a unix socket instead of a TCP port
+    if let Some(path) = &config.unix_socket {
+        #[cfg(unix)]
+        if let Err(e) = unix::serve(app, path, config.unix_socket_mode).await {
+            error!("Unix socket server error {}: {}", path, e);
+        }
+        #[cfg(not(unix))]
+        error!("Can't listen on {}, unix_socket is only supported on unix systems", path);
+        return;
+    }
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    // The web server is started using the Axum framework
+    // The server listens on the socket systemd passed in,
+    // otherwise on the address and port specified in the configuration
+    #[cfg(unix)]
+    let activated = match systemd::listener() {
+        Ok(activated) => activated.map(tokio::net::TcpListener::from_std),
+        Err(e) => {
+            error!("Could not take over the socket passed in by systemd: {}", e);
+            return;
+        }
+    };
+    #[cfg(not(unix))]
+    let activated = None;
+    let listener = match activated {
+        Some(listener) => listener,
+        None => tokio::net::TcpListener::bind(format!("{}:{}", &config.listener_addr, &config.port)).await,
+    };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Error binding to address {}:{} : {}",
+                &config.listener_addr, &config.port, e
+            );
+            return;
+        }
+    };
+    #[cfg(unix)]
+    systemd::notify_ready();
+
+    // terminate TLS ourselves if a certificate and key are configured
+    // otherwise use_tls only means a reverse proxy in front of us terminates it
+    if let (true, Some(cert), Some(key)) = (config.use_tls, &config.tls_cert, &config.tls_key) {
+        if let Some(port) = &config.tls_redirect_port {
+            tokio::spawn(tls::redirect_http(config.clone(), port.clone()));
+        }
+        if let Err(e) = tls::serve(listener, app, cert, key, config.header_read_timeout).await {
+            error!("TLS server error: {}", e);
+        }
+        return;
+    }
+
+    let listener = match listener.into_std() {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not take over the listener: {}", e);
+            return;
+        }
+    };
+    let mut server = axum_server::from_tcp(listener);
+    set_header_read_timeout(&mut server, config.header_read_timeout);
+    server.serve(app).await.unwrap();
+}
+
+/// This function sets how long a client may take to send the headers of a request,
+/// so slow-loris clients can't hold connections open forever. A timeout of 0 turns it off.
+fn set_header_read_timeout<A>(server: &mut axum_server::Server<A>, seconds: u64) {
+    let timeout = (seconds > 0).then(|| Duration::from_secs(seconds));
+    server
+        .http_builder()
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(timeout);
+}
+
+/// This function creates the SQLite database if it does not exist yet
+/// and connects to the configured SQLite, Postgres or MySQL database.
+/// Errors are returned so the caller can explain them, see `self_check::database_unreachable`.
+pub async fn connect(config: &data::Config) -> Result<AnyPool, sqlx::Error> {
+    // Create the data path if it doesn't exist
+    // only if the db type is sqlite
+    // otherwise, the data path is not used
+    if config.db_type == "sqlite" {
+        if !Sqlite::database_exists(&config.database_url)
+            .await
+            .unwrap_or(false)
+        {
+            println!("Creating database {}", config.database_url);
+            Sqlite::create_database(&config.database_url).await?;
+            info!("Create db success");
+        } else {
+            info!("Database already exists");
+        }
+    }
+
+    // Create the database connection any pool
+    // The connection pool is created using the database URL from the configuration
+    if config.db_statement_timeout > 0 && config.db_type == "sqlite" {
+        warn!("SQLite has no statement timeout, db_statement_timeout is ignored");
+    }
+    let statement_timeout = config.db_statement_timeout;
+    AnyPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(Duration::from_secs(config.db_acquire_timeout))
+        .after_connect(move |connection, _meta| Box::pin(db::prepare_connection(connection, statement_timeout)))
+        .connect(&config.database_url)
+        .await
+}
+
+/// This function brings the database schema up to date
+/// by creating missing tables, indexes and columns,
+/// promotes the configured admin users
+/// and moves blobs stored by older versions into the current layout.
+/// Every step is safe to run again on an up to date database.
+/// A table, index or column that can't be created is returned as an error,
+/// the server must not start on a schema it can't use.
+pub async fn migrate(pool: &AnyPool, config: &data::Config) -> Result<(), sqlx::Error> {
+    // Setting up the database schema
+    // The database schema is created if it doesn't exist
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS files (
+            id VARCHAR(255) PRIMARY KEY,
+            file_name TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            upload_time BIGINT NOT NULL,
+            download_limit INTEGER NOT NULL,
+            download_count INTEGER NOT NULL,
+            file_size BIGINT NOT NULL,
+            download_url TEXT NOT NULL,
+            owner VARCHAR(255) NOT NULL,
+            content_hash VARCHAR(255),
+            slug VARCHAR(255),
+            encrypted INTEGER NOT NULL DEFAULT 0,
+            detected_content_type TEXT,
+            visibility VARCHAR(255) NOT NULL DEFAULT 'public',
+            expires BIGINT,
+            deleted_at BIGINT,
+            version INTEGER NOT NULL DEFAULT 1
+        );
+    "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create files table: {}", e);
+        return Err(e);
+    }
+    // add the content hash to file tables created before it existed
+    db::add_column(pool, "files", "content_hash", "VARCHAR(255)").await?;
+    // add the vanity slug to file tables created before it existed
+    db::add_column(pool, "files", "slug", "VARCHAR(255)").await?;
+    // add the end to end encryption flag to file tables created before it existed
+    db::add_column(pool, "files", "encrypted", "INTEGER NOT NULL DEFAULT 0").await?;
+    // add the sniffed content type to file tables created before it existed
+    db::add_column(pool, "files", "detected_content_type", "TEXT").await?;
+    // add the visibility to file tables created before it existed, older files stay public
+    db::add_column(pool, "files", "visibility", "VARCHAR(255) NOT NULL DEFAULT 'public'").await?;
+    // add the expiry time to file tables created before it existed, older files never expire
+    db::add_column(pool, "files", "expires", "BIGINT").await?;
+    // add the trash time to file tables created before it existed
+    db::add_column(pool, "files", "deleted_at", "BIGINT").await?;
+    // add the version number to file tables created before it existed
+    db::add_column(pool, "files", "version", "INTEGER NOT NULL DEFAULT 1").await?;
+    // add the IPFS CID to file tables created before it existed, see the ipfs module
+    db::add_column(pool, "files", "cid", "TEXT").await?;
+    // inactivity based expiry, see the cleanup module
+    db::add_column(pool, "files", "expire_if_unused_days", "INTEGER").await?;
+    db::add_column(pool, "files", "last_downloaded_at", "BIGINT").await?;
+    db::add_column(pool, "files", "verified", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "files", "cache_control", "TEXT").await?;
+    db::add_column(pool, "files", "published", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "files", "blob_missing", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "files", "pinned", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "files", "exhausted_at", "BIGINT").await?;
+    db::add_column(pool, "files", "description", "TEXT").await?;
+    // detached signatures of files, see the signature module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS file_signatures (
+            file_id VARCHAR(255) PRIMARY KEY,
+            signature TEXT NOT NULL,
+            fingerprint TEXT,
+            created_at BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create file_signatures table: {}", e);
+        return Err(e);
+    }
+    // earlier versions of re-uploaded files, see the versions module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS file_versions (
+            file_id VARCHAR(255) NOT NULL,
+            version INTEGER NOT NULL,
+            file_name TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            detected_content_type TEXT,
+            file_size BIGINT NOT NULL,
+            content_hash VARCHAR(255),
+            encrypted INTEGER NOT NULL DEFAULT 0,
+            upload_time BIGINT NOT NULL,
+            PRIMARY KEY (file_id, version)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create file_versions table: {}", e);
+        return Err(e);
+    }
+    // add the IPFS CID to version tables created before it existed
+    db::add_column(pool, "file_versions", "cid", "TEXT").await?;
+    // slugs must be unique, files without a slug leave it NULL
+    if let Err(e) = db::create_index(
+        pool,
+        "files_slug",
+        "CREATE UNIQUE INDEX IF NOT EXISTS files_slug ON files (slug)",
+    )
+    .await
+    {
+        error!("Could not create files_slug index: {}", e);
+        return Err(e);
+    }
+    // create the collection tables
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS collections (
+            id VARCHAR(255) PRIMARY KEY,
+            name TEXT NOT NULL,
+            owner VARCHAR(255) NOT NULL,
+            created_at BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create collections table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS collection_files (
+            collection_id VARCHAR(255) NOT NULL,
+            file_id VARCHAR(255) NOT NULL,
+            PRIMARY KEY (collection_id, file_id)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create collection_files table: {}", e);
+        return Err(e);
+    }
+    // tags are kept in their own table so a file can have any number of them
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS file_tags (
+            file_id VARCHAR(255) NOT NULL,
+            tag VARCHAR(255) NOT NULL,
+            PRIMARY KEY (file_id, tag)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create file_tags table: {}", e);
+        return Err(e);
+    }
+    // every download of a file, see the stats module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS downloads (
+            file_id VARCHAR(255) NOT NULL,
+            time BIGINT NOT NULL,
+            ip TEXT NOT NULL,
+            user_agent TEXT
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create downloads table: {}", e);
+        return Err(e);
+    }
+    db::add_column(pool, "downloads", "country", "VARCHAR(2)").await?;
+    db::add_column(pool, "downloads", "asn", "BIGINT").await?;
+    db::add_column(pool, "downloads", "asn_organization", "TEXT").await?;
+    // usage and quota checks sum up the files of one owner
+    if let Err(e) = db::create_index(
+        pool,
+        "files_owner",
+        "CREATE INDEX IF NOT EXISTS files_owner ON files (owner)",
+    )
+    .await
+    {
+        error!("Could not create files index: {}", e);
+        return Err(e);
+    }
+    // listings of one owner and of every file are ordered and paged by upload time
+    if let Err(e) = db::create_index(
+        pool,
+        "files_owner_upload_time",
+        "CREATE INDEX IF NOT EXISTS files_owner_upload_time ON files (owner, upload_time, id)",
+    )
+    .await
+    {
+        error!("Could not create files index: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "files_upload_time",
+        "CREATE INDEX IF NOT EXISTS files_upload_time ON files (upload_time, id)",
+    )
+    .await
+    {
+        error!("Could not create files index: {}", e);
+        return Err(e);
+    }
+    // the public directory lists the published files, newest first
+    if let Err(e) = db::create_index(
+        pool,
+        "files_published_upload_time",
+        "CREATE INDEX IF NOT EXISTS files_published_upload_time ON files (published, upload_time, id)",
+    )
+    .await
+    {
+        error!("Could not create files index: {}", e);
+        return Err(e);
+    }
+    // blob references are counted and blobs looked up by their content hash
+    if let Err(e) = db::create_index(
+        pool,
+        "files_content_hash",
+        "CREATE INDEX IF NOT EXISTS files_content_hash ON files (content_hash)",
+    )
+    .await
+    {
+        error!("Could not create files index: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "downloads_file_id",
+        "CREATE INDEX IF NOT EXISTS downloads_file_id ON downloads (file_id)",
+    )
+    .await
+    {
+        error!("Could not create downloads index: {}", e);
+        return Err(e);
+    }
+    // tokens that unlock private files, see the share module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS share_tokens (
+            token VARCHAR(255) PRIMARY KEY,
+            file_id VARCHAR(255) NOT NULL,
+            created BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create share_tokens table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "share_tokens_file_id",
+        "CREATE INDEX IF NOT EXISTS share_tokens_file_id ON share_tokens (file_id)",
+    )
+    .await
+    {
+        error!("Could not create share_tokens index: {}", e);
+        return Err(e);
+    }
+    // one-time tokens that delete a file without a key, returned with its upload
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS delete_tokens (
+            token VARCHAR(255) PRIMARY KEY,
+            file_id VARCHAR(255) NOT NULL,
+            created BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create delete_tokens table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "delete_tokens_file_id",
+        "CREATE INDEX IF NOT EXISTS delete_tokens_file_id ON delete_tokens (file_id)",
+    )
+    .await
+    {
+        error!("Could not create delete_tokens index: {}", e);
+        return Err(e);
+    }
+    // security relevant actions, see the audit module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            time BIGINT NOT NULL,
+            username VARCHAR(255),
+            action TEXT NOT NULL,
+            target TEXT,
+            ip TEXT NOT NULL,
+            seq BIGINT,
+            salt TEXT,
+            digest TEXT,
+            entry_hash TEXT
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create audit_log table: {}", e);
+        return Err(e);
+    }
+    // the hash chain of the audit log, older entries stay outside of it
+    db::add_column(pool, "audit_log", "seq", "BIGINT").await?;
+    db::add_column(pool, "audit_log", "salt", "TEXT").await?;
+    db::add_column(pool, "audit_log", "digest", "TEXT").await?;
+    db::add_column(pool, "audit_log", "entry_hash", "TEXT").await?;
+    // two servers sharing the database can't give their entries the same place in the chain
+    if let Err(e) = db::create_index(
+        pool,
+        "audit_log_seq",
+        "CREATE UNIQUE INDEX IF NOT EXISTS audit_log_seq ON audit_log (seq)",
+    )
+    .await
+    {
+        error!("Could not create audit_log index: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "audit_log_time",
+        "CREATE INDEX IF NOT EXISTS audit_log_time ON audit_log (time)",
+    )
+    .await
+    {
+        error!("Could not create audit_log index: {}", e);
+        return Err(e);
+    }
+    // scoped tokens for automation, see the tokens module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS tokens (
+            id VARCHAR(255) PRIMARY KEY,
+            token VARCHAR(255) NOT NULL UNIQUE,
+            username VARCHAR(255) NOT NULL,
+            name TEXT,
+            scopes TEXT NOT NULL,
+            max_bytes BIGINT,
+            content_types TEXT,
+            expires BIGINT,
+            created BIGINT NOT NULL,
+            collection_id VARCHAR(255)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create tokens table: {}", e);
+        return Err(e);
+    }
+    db::add_column(pool, "tokens", "collection_id", "VARCHAR(255)").await?;
+    // the bytes anonymous uploads used per IP address, see the anonymous module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS anonymous_uploads (
+            ip VARCHAR(255) NOT NULL,
+            file_size BIGINT NOT NULL,
+            upload_time BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create anonymous_uploads table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "anonymous_uploads_ip",
+        "CREATE INDEX IF NOT EXISTS anonymous_uploads_ip ON anonymous_uploads (ip)",
+    )
+    .await
+    {
+        error!("Could not create anonymous_uploads_ip index: {}", e);
+        return Err(e);
+    }
+    // the uploads of every IP address in the last day, see the ip_quota module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS ip_uploads (
+            ip VARCHAR(255) NOT NULL,
+            file_size BIGINT NOT NULL,
+            upload_time BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create ip_uploads table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "ip_uploads_ip",
+        "CREATE INDEX IF NOT EXISTS ip_uploads_ip ON ip_uploads (ip)",
+    )
+    .await
+    {
+        error!("Could not create ip_uploads_ip index: {}", e);
+        return Err(e);
+    }
+    // the pre-signed upload URLs and whether they were used, see the presign module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS presigned_uploads (
+            id VARCHAR(64) PRIMARY KEY,
+            username VARCHAR(255) NOT NULL,
+            expires BIGINT NOT NULL,
+            used_at BIGINT
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create presigned_uploads table: {}", e);
+        return Err(e);
+    }
+    db::add_column(pool, "presigned_uploads", "token_id", "VARCHAR(255)").await?;
+    db::add_column(pool, "presigned_uploads", "key_id", "VARCHAR(255)").await?;
+    // the bytes every user uploaded and downloaded per month, see the transfer module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_periods (
+            username VARCHAR(255) NOT NULL,
+            month VARCHAR(7) NOT NULL,
+            upload_bytes BIGINT NOT NULL DEFAULT 0,
+            download_bytes BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (username, month)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create usage_periods table: {}", e);
+        return Err(e);
+    }
+    // the highest quota warning threshold each user was warned about in the month, see the transfer module
+    db::add_column(pool, "usage_periods", "storage_warned", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "usage_periods", "upload_warned", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "usage_periods", "download_warned", "INTEGER NOT NULL DEFAULT 0").await?;
+    // the files stored by uploads with an `Idempotency-Key`, see the idempotency module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS idempotency_keys (
+            owner VARCHAR(255) NOT NULL,
+            idempotency_key VARCHAR(255) NOT NULL,
+            file_id VARCHAR(255),
+            created_at BIGINT NOT NULL,
+            PRIMARY KEY (owner, idempotency_key)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create idempotency_keys table: {}", e);
+        return Err(e);
+    }
+    // multipart uploads and their parts until they are completed, see the parts module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS upload_sessions (
+            id VARCHAR(255) PRIMARY KEY,
+            owner VARCHAR(255) NOT NULL,
+            file_name TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            download_limit INTEGER NOT NULL,
+            slug VARCHAR(255),
+            encrypted INTEGER NOT NULL DEFAULT 0,
+            tags TEXT NOT NULL,
+            visibility VARCHAR(255) NOT NULL,
+            expires BIGINT,
+            expire_if_unused_days INTEGER,
+            created_at BIGINT NOT NULL,
+            completing INTEGER NOT NULL DEFAULT 0
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create upload_sessions table: {}", e);
+        return Err(e);
+    }
+    db::add_column(pool, "upload_sessions", "cache_control", "TEXT").await?;
+    db::add_column(pool, "upload_sessions", "published", "INTEGER NOT NULL DEFAULT 0").await?;
+    db::add_column(pool, "upload_sessions", "description", "TEXT").await?;
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS upload_parts (
+            upload_id VARCHAR(255) NOT NULL,
+            part_number INTEGER NOT NULL,
+            size BIGINT NOT NULL,
+            sha256 VARCHAR(64) NOT NULL,
+            uploaded_at BIGINT NOT NULL,
+            PRIMARY KEY (upload_id, part_number)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create upload_parts table: {}", e);
+        return Err(e);
+    }
+    // NULL for parts written before it was recorded, see `parts::read_part`
+    db::add_column(pool, "upload_parts", "encrypted", "INTEGER").await?;
+    // changes other instances have to apply to their in-memory state, see the events module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS cluster_events (
+            seq BIGINT PRIMARY KEY,
+            instance VARCHAR(255) NOT NULL,
+            event TEXT NOT NULL,
+            created_at BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create cluster_events table: {}", e);
+        return Err(e);
+    }
+    // the files that were created, changed or deleted, for `/files/changes`, see the changes module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS file_changes (
+            seq BIGINT PRIMARY KEY,
+            owner VARCHAR(255) NOT NULL,
+            file_id VARCHAR(255) NOT NULL,
+            kind VARCHAR(16) NOT NULL,
+            changed_at BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create file_changes table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "file_changes_owner",
+        "CREATE INDEX IF NOT EXISTS file_changes_owner ON file_changes (owner, seq)",
+    )
+    .await
+    {
+        error!("Could not create file_changes index: {}", e);
+        return Err(e);
+    }
+    // blobs the scrub job found missing or changed, see the integrity module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS integrity_issues (
+            content_hash VARCHAR(255) PRIMARY KEY,
+            issue VARCHAR(255) NOT NULL,
+            detail TEXT NOT NULL,
+            detected_at BIGINT NOT NULL,
+            checked_at BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create integrity_issues table: {}", e);
+        return Err(e);
+    }
+    // abuse reports and the files taken down because of them, see the reports module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS reports (
+            id VARCHAR(255) PRIMARY KEY,
+            file_id VARCHAR(255) NOT NULL,
+            reason TEXT NOT NULL,
+            ip TEXT NOT NULL,
+            created BIGINT NOT NULL,
+            status VARCHAR(255) NOT NULL DEFAULT 'open',
+            resolved_by VARCHAR(255),
+            resolved_at BIGINT
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create reports table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS blocked_files (
+            file_id VARCHAR(255) PRIMARY KEY,
+            reason TEXT NOT NULL,
+            blocked_by VARCHAR(255) NOT NULL,
+            created BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create blocked_files table: {}", e);
+        return Err(e);
+    }
+    // browser sessions, see the session module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id VARCHAR(255) PRIMARY KEY,
+            username VARCHAR(255) NOT NULL,
+            expires BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create sessions table: {}", e);
+        return Err(e);
+    }
+    // the secret tokens of the Atom feeds users turned on, see the feed module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS feeds (
+            username VARCHAR(255) PRIMARY KEY,
+            token VARCHAR(255) NOT NULL UNIQUE,
+            created BIGINT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create feeds table: {}", e);
+        return Err(e);
+    }
+    // ntfy and Gotify push notifications, see the notify module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS notifications (
+            username VARCHAR(255) PRIMARY KEY,
+            service TEXT NOT NULL,
+            url TEXT NOT NULL,
+            token TEXT,
+            events TEXT NOT NULL
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create notifications table: {}", e);
+        return Err(e);
+    }
+    // create the user table
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            "key" VARCHAR(255) PRIMARY KEY,
+            username VARCHAR(255) NOT NULL,
+            password TEXT NOT NULL,
+            is_admin INTEGER NOT NULL DEFAULT 0,
+            webhook_url TEXT,
+            max_upload_bytes BIGINT,
+            email TEXT,
+            email_verified INTEGER NOT NULL DEFAULT 1,
+            verification_token VARCHAR(255)
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create users table: {}", e);
+        return Err(e);
+    }
+    // add the admin flag to user tables created before it existed
+    db::add_column(pool, "users", "is_admin", "INTEGER NOT NULL DEFAULT 0").await?;
+    // add the per user webhook to user tables created before it existed
+    db::add_column(pool, "users", "webhook_url", "TEXT").await?;
+    // add the per user upload limit to user tables created before it existed
+    db::add_column(pool, "users", "max_upload_bytes", "BIGINT").await?;
+    // add the email address and its verification state to user tables created before they existed
+    // accounts that existed before verification was introduced count as verified
+    db::add_column(pool, "users", "email", "TEXT").await?;
+    db::add_column(pool, "users", "email_verified", "INTEGER NOT NULL DEFAULT 1").await?;
+    db::add_column(pool, "users", "verification_token", "VARCHAR(255)").await?;
+    db::add_column(pool, "users", "storage_quota", "BIGINT").await?;
+    db::add_column(pool, "users", "pgp_key", "TEXT").await?;
+    db::add_column(pool, "users", "display_email", "TEXT").await?;
+    db::add_column(pool, "users", "default_download_limit", "INTEGER").await?;
+    db::add_column(pool, "users", "default_expiry", "BIGINT").await?;
+    db::add_column(pool, "users", "default_visibility", "TEXT").await?;
+    db::add_column(pool, "users", "removal_notices", "INTEGER NOT NULL DEFAULT 1").await?;
+    // the API keys of the users, a user can have several and revoke them, see the keys module
+    if let Err(e) = sqlx::query(&db::ddl(
+        pool,
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id VARCHAR(255) PRIMARY KEY,
+            "key" VARCHAR(255) NOT NULL UNIQUE,
+            username VARCHAR(255) NOT NULL,
+            name TEXT,
+            created BIGINT NOT NULL,
+            last_used_at BIGINT,
+            revoked_at BIGINT
+        );
+        "#,
+    ))
+    .execute(pool)
+    .await
+    {
+        error!("Could not create api_keys table: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = db::create_index(
+        pool,
+        "api_keys_username",
+        "CREATE INDEX IF NOT EXISTS api_keys_username ON api_keys (username)",
+    )
+    .await
+    {
+        error!("Could not create api_keys index: {}", e);
+        return Err(e);
+    }
+    // earlier versions created TEXT columns as VARCHAR(2048) on MySQL
+    db::widen_text_columns(pool).await?;
+    // the keys of older accounts used to live only in the users table
+    match db::migrate_user_keys(pool).await {
+        Ok(0) => {}
+        Ok(count) => info!("Moved the keys of {} users into api_keys", count),
+        Err(e) => error!("Could not move user keys into api_keys: {}", e),
+    }
+    // promote the configured admin users
+    for admin in &config.admin_users {
+        if let Err(e) = sqlx::query(
+            r#"
+            UPDATE users
+            SET is_admin = 1
+            WHERE username = ?
+            "#,
+        )
+        .bind(admin)
+        .execute(pool)
+        .await
+        {
+            warn!("could not promote {} to admin: {}", admin, e);
+        }
+    }
+
+    //create the directory if it doesn't exist
+    let dir = Path::new(&config.data_path);
+    if let Err(e) = fs::create_dir_all(dir).await {
+        warn!("could not make dir at {} error: {}", &config.data_path, e);
+    }
+    // older versions stored every blob directly in the data path
+    if let Err(e) = storage::migrate_flat_layout(config).await {
+        error!("could not move blobs into shard directories: {}", e);
+    }
+    // older versions told compressed blobs by their first bytes
+    if let Err(e) = storage::migrate_layout(config).await {
+        error!("could not update the layout of the data path: {}", e);
+    }
+    // uploads that were cut off by a crash leave their part files behind
+    if let Err(e) = storage::remove_stale_parts(config).await {
+        error!("could not remove stale part files: {}", e);
+    }
+    Ok(())
+}
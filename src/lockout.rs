@@ -0,0 +1,237 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::data;
+use crate::error::ApiError;
+
+/// The most clients and usernames that are tracked, entries that ran out are dropped past it.
+const MAX_ENTRIES: usize = 100_000;
+
+/// The failed attempts per client and username, `None` while the lockout is disabled.
+static LOCKOUT: Mutex<Option<Lockout>> = Mutex::new(None);
+
+/// This struct holds the failed login and key attempts and the configured lockout.
+struct Lockout {
+    /// The failures after which every further failure locks out.
+    attempts: u32,
+    /// The first lockout, every further failure doubles it.
+    duration: Duration,
+    /// The longest lockout, failures are also forgotten after this long without a new one.
+    max_duration: Duration,
+    /// The failures after which a sustained attack is reported, 0 for never.
+    alert_attempts: u32,
+    /// The attempts by `ip:<address>` and `user:<name>`.
+    entries: HashMap<String, Attempts>,
+}
+
+/// This struct counts the failures of a client or username.
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Lockout {
+    /// Returns the time left on the lockout of the entry, `None` if it is not locked out.
+    fn locked(&self, key: &str, now: Instant) -> Option<Duration> {
+        let until = self.entries.get(key)?.locked_until?;
+        until.checked_duration_since(now).filter(|left| !left.is_zero())
+    }
+
+    /// Counts a failure of the entry and locks it out once it failed `attempts` times,
+    /// for twice as long with every further failure.
+    fn fail(&mut self, key: String, now: Instant) {
+        self.forget_stale(now);
+        let max_duration = self.max_duration;
+        let attempts = self.entries.entry(key.clone()).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        if now.duration_since(attempts.last_failure) > max_duration {
+            attempts.failures = 0;
+        }
+        attempts.failures += 1;
+        attempts.last_failure = now;
+        let failures = attempts.failures;
+        if failures >= self.attempts {
+            let doublings = (failures - self.attempts).min(31);
+            let lockout = self.duration.saturating_mul(1 << doublings).min(self.max_duration);
+            attempts.locked_until = Some(now + lockout);
+            info!("{} is locked out for {}s after {} failures", key, lockout.as_secs(), failures);
+        }
+        if self.alert_attempts > 0 && failures.is_multiple_of(self.alert_attempts) {
+            let message = format!("Sustained attack: {} failed to authenticate {} times", key, failures);
+            error!("{}", message);
+            sentry::capture_message(&message, sentry::Level::Warning);
+        }
+    }
+
+    /// Drops the entries that are no longer locked out and had no failure for `max_duration`,
+    /// once there are too many of them.
+    fn forget_stale(&mut self, now: Instant) {
+        if self.entries.len() < MAX_ENTRIES {
+            return;
+        }
+        let max_duration = self.max_duration;
+        self.entries.retain(|_, attempts| {
+            now.duration_since(attempts.last_failure) <= max_duration
+                || attempts.locked_until.is_some_and(|until| until > now)
+        });
+        if self.entries.len() >= MAX_ENTRIES {
+            warn!("Tracking {} clients with failed logins, forgetting all of them", self.entries.len());
+            self.entries.clear();
+        }
+    }
+}
+
+/// Returns the entry keys of a client and a username.
+fn keys(ip: &str, username: Option<&str>) -> Vec<String> {
+    let mut keys = vec![format!("ip:{}", ip)];
+    if let Some(username) = username {
+        keys.push(format!("user:{}", username.trim().to_lowercase()));
+    }
+    keys
+}
+
+/// This function enables the lockout if `lockout_attempts` is set.
+pub fn init(config: &data::Config) {
+    let lockout = (config.lockout_attempts > 0).then(|| Lockout {
+        attempts: config.lockout_attempts,
+        duration: Duration::from_secs(config.lockout_duration.max(1)),
+        max_duration: Duration::from_secs(config.lockout_max_duration.max(config.lockout_duration).max(1)),
+        alert_attempts: config.lockout_alert_attempts,
+        entries: HashMap::new(),
+    });
+    if lockout.is_some() {
+        info!(
+            "Locking out clients and usernames after {} failed logins",
+            config.lockout_attempts
+        );
+    }
+    *LOCKOUT.lock().unwrap() = lockout;
+}
+
+/// Helper to refuse a login or key while the client or the username is locked out
+/// It returns a 429 with a `Retry-After` header in seconds, `None` if the attempt may go on.
+/// It is checked before the credentials, so a locked out client learns nothing about them.
+pub(crate) fn check(ip: &str, username: Option<&str>) -> Option<Response> {
+    let guard = LOCKOUT.lock().unwrap();
+    let lockout = guard.as_ref()?;
+    let now = Instant::now();
+    let left = keys(ip, username).iter().filter_map(|key| lockout.locked(key, now)).max()?;
+    // round up so clients never retry too early
+    let retry_after = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    warn!("Locked out attempt from {}, retry after {}s", ip, retry_after);
+    let mut response = ApiError::Rejected {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        error: "locked_out",
+        message: "Too many failed attempts, try again later".to_string(),
+        details: json!({ "retry_after": retry_after }),
+    }
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    Some(response)
+}
+
+/// This function counts a failed login or an unknown key of the client,
+/// and of the username if the attempt named one.
+pub(crate) fn failed(ip: &str, username: Option<&str>) {
+    let mut guard = LOCKOUT.lock().unwrap();
+    let Some(lockout) = guard.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    for key in keys(ip, username) {
+        lockout.fail(key, now);
+    }
+}
+
+/// This function forgets the failures of a username after a successful login.
+/// The failures of the client stay, so one valid account can't hide guesses at others.
+pub(crate) fn succeeded(username: &str) {
+    if let Some(lockout) = LOCKOUT.lock().unwrap().as_mut() {
+        lockout.entries.remove(&format!("user:{}", username.trim().to_lowercase()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lockout, MAX_ENTRIES};
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn lockout() -> Lockout {
+        Lockout {
+            attempts: 3,
+            duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(600),
+            alert_attempts: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn locks_out_once_the_attempts_are_used_up() {
+        let mut lockout = lockout();
+        let now = Instant::now();
+        lockout.fail("ip:192.0.2.1".to_string(), now);
+        lockout.fail("ip:192.0.2.1".to_string(), now);
+        assert_eq!(lockout.locked("ip:192.0.2.1", now), None);
+
+        lockout.fail("ip:192.0.2.1".to_string(), now);
+        assert_eq!(lockout.locked("ip:192.0.2.1", now), Some(Duration::from_secs(60)));
+        assert_eq!(lockout.locked("ip:192.0.2.2", now), None);
+        assert_eq!(lockout.locked("ip:192.0.2.1", now + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn doubles_the_lockout_up_to_the_longest() {
+        let mut lockout = lockout();
+        let now = Instant::now();
+        for _ in 0..4 {
+            lockout.fail("user:alice".to_string(), now);
+        }
+        assert_eq!(lockout.locked("user:alice", now), Some(Duration::from_secs(120)));
+        for _ in 0..10 {
+            lockout.fail("user:alice".to_string(), now);
+        }
+        assert_eq!(lockout.locked("user:alice", now), Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn forgets_failures_after_the_longest_lockout() {
+        let mut lockout = lockout();
+        let now = Instant::now();
+        lockout.fail("ip:192.0.2.1".to_string(), now);
+        lockout.fail("ip:192.0.2.1".to_string(), now);
+
+        let later = now + Duration::from_secs(601);
+        lockout.fail("ip:192.0.2.1".to_string(), later);
+        assert_eq!(lockout.locked("ip:192.0.2.1", later), None);
+    }
+
+    #[test]
+    fn drops_stale_entries_once_there_are_too_many() {
+        let mut lockout = lockout();
+        let now = Instant::now();
+        for i in 0..MAX_ENTRIES {
+            lockout.fail(format!("ip:{}", i), now);
+        }
+        assert_eq!(lockout.entries.len(), MAX_ENTRIES);
+
+        let later = now + Duration::from_secs(601);
+        lockout.fail("ip:192.0.2.1".to_string(), later);
+        assert_eq!(lockout.entries.len(), 1);
+        assert!(lockout.entries.contains_key("ip:192.0.2.1"));
+    }
+}
@@ -0,0 +1,362 @@
+use axum::{
+    extract::{ConnectInfo, MatchedPath},
+    http::Request,
+};
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Span, Subscriber,
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+    reload, Registry,
+};
+
+use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::data;
+use crate::trace_context::TraceContext;
+
+/// This function creates the span every request is handled in.
+/// It is used by the `TraceLayer` after the request ID layer assigned an ID and the trace context was read,
+/// so all log lines of one request carry the same request ID, W3C trace ID and client IP.
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or("unknown");
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let trace_id = request
+        .extensions()
+        .get::<TraceContext>()
+        .map(TraceContext::trace_id_hex)
+        .unwrap_or_else(|| "unknown".to_string());
+    tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        trace_id = %trace_id,
+        client_ip = %client_ip,
+        method = %request.method(),
+        path = %logged_path(request),
+    )
+}
+
+/// Returns the path of a request as logs and exported spans record it.
+/// It is the route the request matched, like `/user/verify/{token}`, not the path itself,
+/// which can carry keys, feed tokens, verification tokens and signed upload URLs.
+/// Of a request that matched no route only the first segment is kept.
+pub(crate) fn logged_path<B>(request: &Request<B>) -> String {
+    if let Some(matched) = request.extensions().get::<MatchedPath>() {
+        return matched.as_str().to_string();
+    }
+    let mut segments = request.uri().path().trim_start_matches('/').splitn(2, '/');
+    let first = segments.next().unwrap_or_default();
+    match segments.next() {
+        Some(_) => format!("/{}/...", first),
+        None => format!("/{}", first),
+    }
+}
+
+/// This struct holds the request fields of a request span.
+/// It is stored in the span extensions by `RequestFieldsLayer`
+/// so the JSON formatter can put them on every line.
+#[derive(Clone, Default)]
+struct RequestFields {
+    request_id: Option<String>,
+    trace_id: Option<String>,
+    client_ip: Option<String>,
+}
+
+impl Visit for RequestFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "request_id" => self.request_id = Some(format!("{:?}", value)),
+            "trace_id" => self.trace_id = Some(format!("{:?}", value)),
+            "client_ip" => self.client_ip = Some(format!("{:?}", value)),
+            _ => {}
+        }
+    }
+}
+
+/// This layer remembers the request ID, trace ID and client IP of request spans.
+pub struct RequestFieldsLayer;
+
+impl<S> Layer<S> for RequestFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = RequestFields::default();
+        attrs.record(&mut fields);
+        if fields.request_id.is_none() && fields.client_ip.is_none() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+}
+
+/// This struct collects the fields of a single log event.
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl Visit for EventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), Value::from(value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields
+                .insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+        }
+    }
+}
+
+/// This formatter writes every event as one JSON object per line.
+/// Each object carries the timestamp, level, target, message,
+/// the request ID, trace ID and client IP of the enclosing request
+/// and the names of the spans the event happened in.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+
+        let mut request = RequestFields::default();
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                spans.push(span.name());
+                if let Some(span_fields) = span.extensions().get::<RequestFields>() {
+                    request = span_fields.clone();
+                }
+            }
+        }
+
+        let metadata = event.metadata();
+        let mut line = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": metadata.level().to_string(),
+            "target": metadata.target(),
+            "msg": fields.message,
+            "request_id": request.request_id,
+            "trace_id": request.trace_id,
+            "client_ip": request.client_ip,
+            "spans": spans,
+        });
+        if !fields.fields.is_empty() {
+            line["fields"] = Value::Object(fields.fields);
+        }
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// The handle the log level is changed through while the server runs,
+/// set by the binary once it installed the subscriber.
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Returns the filter of a `log_level` value, `info` for unknown values.
+pub fn parse_level(level: &str) -> LevelFilter {
+    match level {
+        "debug" => LevelFilter::DEBUG,
+        "info" => LevelFilter::INFO,
+        "warn" => LevelFilter::WARN,
+        "error" => LevelFilter::ERROR,
+        _ => LevelFilter::INFO,
+    }
+}
+
+/// This function hands the log level of the installed subscriber to the server,
+/// so reloading the configuration can change it.
+pub fn set_level_handle(handle: reload::Handle<LevelFilter, Registry>) {
+    let _ = LEVEL.set(handle);
+}
+
+/// Changes the log level to a `log_level` value.
+/// It returns false if the level can't be changed, like when the server is embedded
+/// and the application installed its own subscriber.
+pub(crate) fn set_level(level: &str) -> bool {
+    match LEVEL.get() {
+        Some(handle) => handle.modify(|filter| *filter = parse_level(level)).is_ok(),
+        None => false,
+    }
+}
+
+/// This struct is the log file, it is rotated once it grows over `log_max_bytes`
+/// or was written to for `log_rotate_interval` seconds.
+/// Rotated files are renamed to `<log file>.<timestamp>`, gzipped in the background if `log_compress` is on,
+/// and only the newest `log_max_files` of them are kept.
+pub struct RollingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    max_bytes: u64,
+    max_files: usize,
+    interval: Duration,
+    compress: bool,
+}
+
+impl RollingFile {
+    /// Opens the log file of the config for appending.
+    pub fn open(config: &data::Config) -> io::Result<Self> {
+        let path = PathBuf::from(&config.log_location);
+        let file = open_append(&path)?;
+        Ok(RollingFile {
+            size: file.metadata()?.len(),
+            path,
+            file,
+            opened: Instant::now(),
+            max_bytes: config.log_max_bytes,
+            max_files: config.log_max_files,
+            interval: Duration::from_secs(config.log_rotate_interval),
+            compress: config.log_compress,
+        })
+    }
+
+    /// Returns whether the file has to be rotated before `len` more bytes are written.
+    fn due(&self, len: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self.max_bytes > 0 && self.size.saturating_add(len as u64) > self.max_bytes;
+        let too_old = !self.interval.is_zero() && self.opened.elapsed() >= self.interval;
+        too_big || too_old
+    }
+
+    /// Helper to move the log file aside and start a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let stamp = Utc::now().format("%Y%m%d-%H%M%S%.3f");
+        let mut rotated = PathBuf::from(format!("{}.{}", self.path.display(), stamp));
+        // two rotations within the same millisecond
+        let mut counter = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{}-{}", self.path.display(), stamp, counter));
+            counter += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        let (path, max_files, compress) = (self.path.clone(), self.max_files, self.compress);
+        // compressing a large file would hold up every log line, so it happens next to the server
+        std::thread::spawn(move || {
+            if compress {
+                if let Err(e) = gzip(&rotated) {
+                    eprintln!("Could not compress the rotated log {}: {}", rotated.display(), e);
+                }
+            }
+            if let Err(e) = prune(&path, max_files) {
+                eprintln!("Could not remove old logs of {}: {}", path.display(), e);
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            // the error can't be logged, the log is what failed
+            if let Err(e) = self.rotate() {
+                eprintln!("Could not rotate the log file {}: {}", self.path.display(), e);
+                // try again after another full file instead of on every line
+                self.size = 0;
+                self.opened = Instant::now();
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Helper to open a file for appending, it is created if it doesn't exist.
+fn open_append(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Helper to replace a rotated log with a gzipped copy named `<rotated>.gz`.
+fn gzip(rotated: &Path) -> io::Result<()> {
+    let compressed = PathBuf::from(format!("{}.gz", rotated.display()));
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+    io::copy(&mut File::open(rotated)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(rotated)
+}
+
+/// Helper to remove the oldest rotated logs of the log file until `max_files` are left, 0 keeps all of them.
+/// Rotated logs are the files named `<log file>.<timestamp>`, with or without `.gz`.
+fn prune(path: &Path, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return Ok(());
+    }
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(());
+    };
+    let prefix = format!("{}.", file_name);
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .is_some_and(|stamp| stamp.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    // the timestamps sort like the times they were rotated at
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(max_files);
+    for old in &rotated[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
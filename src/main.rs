@@ -1,26 +1,16 @@
-use axum::{
-    extract::DefaultBodyLimit,
-    //response::IntoResponse,
-    routing::{get, post},
-    Extension, Router,
-};
-use log::{debug, error, info, warn};
-use sqlx::{any::AnyPoolOptions, migrate::MigrateDatabase, AnyPool, Sqlite};
+use clap::Parser;
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use std::path::Path;
-use tokio::fs;
-
-use std::net::SocketAddr;
-mod api;
-mod data;
+use bitbeam::{cli, config, data, error_reporting, logging, self_check};
 
 /// This is the main function of the application.
 /// It sets up the database connection,
 /// initializes the logging system,
-/// and starts the web server.
+/// and starts the web server or runs the given administration command.
 /// It uses the Axum framework to handle HTTP requests.
 /// It also uses SQLx for database interactions.
-/// It uses the Fern library for logging.
+/// It uses the tracing library for logging.
 /// It uses the Tokio runtime for asynchronous programming.
 /// It uses the Chrono library for date and time handling.
 /// It uses the UUID library for generating unique identifiers.
@@ -29,220 +19,110 @@ mod data;
 #[tokio::main]
 async fn main() {
     sqlx::any::install_default_drivers();
-    // Load the configuration from environment variables
-    let config = data::Config {
-        db_type: std::env::var("BITBEAM_DB_TYPE").unwrap_or_else(|_| "sqlite".to_string()),
-        // Determine the correct database URL
-        database_url: match std::env::var("BITBEAM_DB_TYPE")
-            .unwrap_or_else(|_| "sqlite".to_string())
-            .as_str()
-        {
-            "postgres" => {
-                // For Postgres, BITBEAM_DATABASE_URL must be set
-                std::env::var("BITBEAM_DATABASE_URL")
-                    .expect("BITBEAM_DATABASE_URL must be set for Postgres")
-            }
-
-            "sqlite" => {
-                // For SQLite, use BITBEAM_DATABASE_URL if set, otherwise default
-                std::env::var("BITBEAM_DATABASE_URL")
-                    .unwrap_or_else(|_| "sqlite://./bitbeam.sqlite".to_string())
-            }
-
-            other => {
-                panic!("Unsupported BITBEAM_DB_TYPE: {}", other);
-            }
-        },
-        data_path: std::env::var("BITBEAM_DATA_PATH")
-            .unwrap_or_else(|_| "./media_store".to_string()),
-        port: std::env::var("BITBEAM_PORT").unwrap_or_else(|_| "3000".to_string()),
-        listener_addr: std::env::var("BITBEAM_ADDR").unwrap_or_else(|_| "127.0.0.1".to_string()),
-        log_level: std::env::var("BITBEAM_LOG_LEVEL").unwrap_or_else(|_| "info".to_string()),
-        log_location: std::env::var("BITBEAM_LOG_LOCATION")
-            .unwrap_or_else(|_| "./bitbeam.log".to_string()),
-        use_tls: std::env::var("BITBEAM_USE_TLS")
-            .unwrap_or_else(|_| "false".to_string())
-            .parse()
-            .unwrap_or(false),
-        base_url: std::env::var("BITBEAM_BASE_URL").unwrap_or_else(|_| {
-            format!(
-                "localhost:{}",
-                std::env::var("BITBEAM_PORT").unwrap_or_else(|_| "3000".to_string())
-            )
-            .to_string()
-        }),
-        allow_register: std::env::var("BITBEAM_ALLOW_REGISTER")
-            .unwrap_or_else(|_| "true".to_string())
-            .parse()
-            .unwrap_or(true),
+    // Load the configuration from the command line, environment variables and config file
+    // without a subcommand the server is started
+    let mut flags = config::Cli::parse();
+    let command = flags.command.take();
+    let config = match config::from_cli(flags) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
     };
     // Setting up the logging system
     // The log level is set based on the environment variable BITBEAM_LOG_LEVEL
-    let level = match config.log_level.as_str() {
-        "debug" => log::LevelFilter::Debug,
-        "info" => log::LevelFilter::Info,
-        "warn" => log::LevelFilter::Warn,
-        "error" => log::LevelFilter::Error,
-        _ => log::LevelFilter::Info,
+    let level = logging::parse_level(&config.log_level);
+    // administration commands print their own output, only problems are logged
+    let level = match command {
+        None | Some(cli::Command::Serve) => level,
+        Some(_) => level.min(LevelFilter::WARN),
     };
     // Initialize the logging system
-    let log_path = &config.log_location;
-    let _logs = init_logging(log_path, level);
+    let _logs = init_logging(&config, level);
     info!("done loading config");
+    // report panics and server errors if a Sentry DSN is configured
+    let _sentry = error_reporting::init(&config);
 
-    // Create the data path if it doesn't exist
-    // only if the db type is sqlite
-    // otherwise, the data path is not used
-    if config.db_type == "sqlite" {
-        if !Sqlite::database_exists(&config.database_url)
-            .await
-            .unwrap_or(false)
-        {
-            println!("Creating database {}", config.database_url);
-            match Sqlite::create_database(&config.database_url).await {
-                Ok(_) => info!("Create db success"),
-                Err(error) => {
-                    error!("Error creating database: {}", error);
-                    panic!("error: {}", error)
-                }
-            }
-        } else {
-            info!("Database already exists");
+    let pool = match bitbeam::connect(&config).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            self_check::database_unreachable(&config, &e);
+            std::process::exit(1);
         }
-    }
-
-    // Create the database connection any pool
-    // The connection pool is created using the database URL from the configuration
-    let pool: AnyPool = AnyPoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await
-        .expect("could not connect to database");
-
-    // Setting up the database schema
-    // The database schema is created if it doesn't exist
-    if let Err(_e) = sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS files (
-            id TEXT PRIMARY KEY,
-            file_name TEXT NOT NULL,
-            content_type TEXT NOT NULL,
-            upload_time BIGINT NOT NULL,
-            download_limit INTEGER NOT NULL,
-            download_count INTEGER NOT NULL,
-            file_size BIGINT NOT NULL,
-            download_url TEXT NOT NULL,
-            owner TEXT NOT NULL
-        );
-    "#,
-    )
-    .execute(&pool)
-    .await
-    {
-        info!("DB created");
-    };
-    // create the user table
-    if let Err(_e) = sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS users (
-            key TEXT PRIMARY KEY,
-            username TEXT NOT NULL,
-            password TEXT NOT NULL
-        );
-        "#,
-    )
-    .execute(&pool)
-    .await
-    {
-        info!("DB created");
     };
-    //create the directory if it doesn't exist
-    let dir = Path::new(&config.data_path);
-    if let Err(e) = fs::create_dir_all(dir).await {
-        warn!("could not make dir at {} error: {}", &config.data_path, e);
+    // a server that can't work refuses to start before the schema or anything else is touched
+    if matches!(command, None | Some(cli::Command::Serve)) && !self_check::run(&pool, &config).await {
+        std::process::exit(1);
+    }
+    if let Err(e) = bitbeam::migrate(&pool, &config).await {
+        eprintln!("Could not bring the database schema up to date: {}", e);
+        std::process::exit(1);
     }
-    //let file_path = dir.join(&id);
-
-    // Setting up the web server
-    // The web server is created using the Axum framework
-    // these are the routes
-    let app = Router::new()
-        .route("/", get(|| async { "Hello, World!" }))
-        .route("/upload", post(api::upload))
-        .route("/all_files", get(api::all_files))
-        .route("/download/{uuid}", get(api::download_file))
-        .route("/user/register", post(api::register_user))
-        .layer(DefaultBodyLimit::max(100 * 1024 * 1024))
-        .layer(Extension(pool))
-        .layer(Extension(config.clone()))
-        .into_make_service_with_connect_info::<SocketAddr>();
 
-    // The web server is started using the Axum framework
-    // The server listens on the address and port specified in the configuration
-    axum::serve(
-        match tokio::net::TcpListener::bind(format!("{}:{}", &config.listener_addr, &config.port))
-            .await
-        {
-            Ok(listener) => listener,
-            Err(e) => {
-                error!(
-                    "Error binding to address {}:{} : {}",
-                    &config.listener_addr, &config.port, e
-                );
-                return;
-            }
-        },
-        app,
-    )
-    .await
-    .unwrap();
+    match command {
+        None | Some(cli::Command::Serve) => bitbeam::serve(pool, config).await,
+        Some(cli::Command::Migrate) => println!("Database schema is up to date"),
+        Some(command) => std::process::exit(cli::run(command, &pool, &config).await),
+    }
 }
 
 /// This function initializes the logging system.
-/// It sets up a logger that writes to both stdout and a log file.
-/// It uses the Fern library for logging.
-/// It formats the log messages to include the date, time, log level, target, and message.
-/// It also sets the log level based on the provided level filter.
-/// It takes the log file path and log level as parameters.
+/// It sets up a tracing subscriber that writes to both stdout and a log file.
+/// It uses the tracing-subscriber library for logging.
+/// It formats the log messages to include the date, time, log level, target, and message
+/// together with the spans they were emitted in.
+/// With the `json` log format every message is written as one JSON object per line
+/// that also carries the request ID and client IP of the request being handled.
+/// It also sets the log level based on the provided level filter,
+/// the level can be changed later by reloading the configuration.
+/// The log file is rotated by size or age, see `logging::RollingFile`.
+/// It takes the configuration with the log file path and log format and the log level as parameters.
 fn init_logging(
-    log_file_path: &str,
-    level: log::LevelFilter,
+    config: &data::Config,
+    level: LevelFilter,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Build a Dispatch for stdout
-    let stdout_dispatch = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{date}][{lvl}][{target}] {msg}",
-                date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                lvl = record.level(),
-                target = record.target(),
-                msg = message,
-            ))
-        })
-        .level(level)
-        .chain(std::io::stdout());
+    let json = config.log_format == "json";
+    let log_file = std::sync::Mutex::new(logging::RollingFile::open(config)?);
 
-    // Build a Dispatch for a rolling log file
-    let file_dispatch = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{date}][{lvl}][{target}] {msg}",
-                date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                lvl = record.level(),
-                target = record.target(),
-                msg = message,
-            ))
-        })
-        .level(level)
-        .chain(fern::log_file(log_file_path)?);
+    // Build a layer for stdout and one for the log file
+    // only the layers of the selected format are enabled
+    // colors are disabled because span fields are formatted once and shared with the file layer
+    let stdout_text = (!json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(std::io::stdout)
+    });
+    let stdout_json = json.then(|| {
+        tracing_subscriber::fmt::layer()
+            .event_format(logging::JsonFormat)
+            .with_writer(std::io::stdout)
+    });
+    let (file_text, file_json) = if json {
+        let layer = tracing_subscriber::fmt::layer()
+            .event_format(logging::JsonFormat)
+            .with_writer(log_file);
+        (None, Some(layer))
+    } else {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(log_file);
+        (Some(layer), None)
+    };
 
-    // Combine the stdout and file dispatches
-    // and apply them
+    // Combine the stdout and file layers
+    // and install them
     // This sets up the logger to write to both stdout and the log file
-    fern::Dispatch::new()
-        .chain(stdout_dispatch)
-        .chain(file_dispatch)
-        .apply()?;
+    let (level, level_handle) = tracing_subscriber::reload::Layer::new(level);
+    tracing_subscriber::registry()
+        .with(level)
+        .with(logging::RequestFieldsLayer)
+        .with(stdout_text)
+        .with(stdout_json)
+        .with(file_text)
+        .with(file_json)
+        .try_init()?;
+    logging::set_level_handle(level_handle);
 
     Ok(())
 }
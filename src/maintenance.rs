@@ -0,0 +1,124 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde_json::Value;
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tracing::{info, instrument, warn};
+
+use crate::admin;
+use crate::audit;
+use crate::data;
+use crate::error::ApiError;
+use crate::events;
+use crate::logging;
+
+/// The message uploads and registrations are refused with while the instance is in maintenance,
+/// `None` while it is not.
+/// It lives in the memory of the instance, switching it is passed on to the other instances of a cluster.
+static MAINTENANCE: Mutex<Option<String>> = Mutex::new(None);
+
+/// This function switches maintenance mode of this instance on with the message or off with `None`.
+pub(crate) fn set(message: Option<String>) {
+    *MAINTENANCE.lock().unwrap() = message;
+}
+
+/// Returns the maintenance state of this instance.
+fn state() -> data::Maintenance {
+    let message = MAINTENANCE.lock().unwrap().clone();
+    data::Maintenance {
+        enabled: message.is_some(),
+        message,
+    }
+}
+
+/// This middleware refuses uploads and registrations with a 503 while the instance is in maintenance.
+/// Requests that only read, like WebDAV listings and downloads, are let through.
+pub async fn reject(request: Request, next: Next) -> Response {
+    let read_only = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || request.method().as_str() == "PROPFIND";
+    let message = MAINTENANCE.lock().unwrap().clone();
+    match message {
+        Some(message) if !read_only => {
+            warn!("Refused {} {} during maintenance", request.method(), logging::logged_path(&request));
+            ApiError::Rejected {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                error: "maintenance",
+                message,
+                details: Value::Null,
+            }
+            .into_response()
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// Handler to show whether the instance is in maintenance
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/maintenance
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn get_maintenance(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin maintenance request from IP: {}", ip);
+
+    if let Err(response) = admin::require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+    Json(state()).into_response()
+}
+
+/// Handler to switch maintenance mode on or off
+/// This function makes the instance refuse uploads and registrations with a 503 and the message,
+/// while downloads, listings and everything else keep working,
+/// so the instance can be drained before an upgrade.
+/// The mode is kept in memory until it is switched off or the server restarts,
+/// the other instances of a cluster are switched with it, see `event_poll_interval`.
+/// Instances started during maintenance don't know about it until it is switched again.
+/// example request: curl -X POST -H "key: <admin key>" -H "content-type: application/json" -d '{"enabled": true, "message": "Back in 10 minutes"}' http://localhost:3000/admin/maintenance
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following JSON body:
+/// - enabled: true to start maintenance, false to end it (not optional)
+/// - message: the message refused requests get, `maintenance_message` if missing (optional)
+#[instrument(skip_all)]
+pub async fn set_maintenance(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    Json(request): Json<data::MaintenanceRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin maintenance change from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    let message = request
+        .enabled
+        .then(|| request.message.filter(|message| !message.trim().is_empty()))
+        .map(|message| message.unwrap_or_else(|| config.maintenance_message.clone()));
+    match &message {
+        Some(message) => warn!("Maintenance started by {}: {}", admin.username, message),
+        None => info!("Maintenance ended by {}", admin.username),
+    }
+    set(message.clone());
+    events::publish(events::Event::Maintenance { message });
+    audit::record(&pool, audit::Action::AdminMaintenance, Some(&admin.username), None, &ip).await;
+    Json(state()).into_response()
+}
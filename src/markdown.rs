@@ -0,0 +1,145 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use pulldown_cmark::{html, Options, Parser};
+use serde_json::Value;
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::data;
+use crate::error::ApiError;
+use crate::ids;
+use crate::reports;
+use crate::share;
+use crate::storage;
+
+/// The largest markdown file that is rendered, larger ones can still be downloaded.
+const MAX_VIEW_SIZE: i64 = 1024 * 1024;
+
+/// The page the rendered markdown is shown in.
+const MARKDOWN_HTML: &str = include_str!("web/markdown.html");
+
+/// Keeps the page from loading scripts, styles or frames from anywhere,
+/// in case something slips through the sanitizer. Images may come from anywhere.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src * data:; style-src 'unsafe-inline'";
+
+/// Returns true if the content type, or the one detected from the contents, is markdown.
+pub(crate) fn is_markdown(file: &data::File) -> bool {
+    [Some(file.content_type.as_str()), file.detected_content_type.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|content_type| content_type.split(';').next())
+        .any(|essence| {
+            let essence = essence.trim();
+            essence.eq_ignore_ascii_case("text/markdown") || essence.eq_ignore_ascii_case("text/x-markdown")
+        })
+}
+
+/// Renders markdown to HTML without anything that could run in the browser of the viewer,
+/// like scripts, event handlers, styles or `javascript:` links.
+fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    // the checkboxes of task lists are kept, they are disabled and outside of any form
+    ammonia::Builder::default()
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+/// Handler to view a markdown file in the browser
+/// This function renders a `text/markdown` file to sanitized HTML,
+/// so READMEs and notes can be read without downloading them.
+/// Viewing a file counts against its download limit like a download,
+/// private files need the same key or share token.
+/// Files over 1 MiB and end to end encrypted files can only be downloaded.
+/// It also logs the IP address of the client making the request.
+/// example request: curl http://localhost:3000/view/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following query parameter:
+/// - token: a share token of a private file (optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn view(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received view request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let file = match api::find_file(&pool, "id", &uuid).await? {
+        Some(file) => file,
+        None if reports::is_blocked(&pool, &uuid).await => return Err(reports::taken_down().into()),
+        None => return Err(ApiError::Gone("Download limit reached".to_string())),
+    };
+    if !file.is_available() {
+        return Err(api::gone(&file));
+    }
+    share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+
+    if !is_markdown(&file) || file.encrypted != 0 {
+        return Err(ApiError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            error: "not_markdown",
+            message: "Only markdown files can be viewed".to_string(),
+            details: Value::Null,
+        });
+    }
+    if file.file_size > MAX_VIEW_SIZE {
+        warn!("File {} is too large to view", uuid);
+        return Err(ApiError::TooLarge(MAX_VIEW_SIZE as u64));
+    }
+
+    // the download is given back if the page is not sent to the end
+    let pending = match api::claim_download(&pool, &config, &file).await {
+        Ok(true) => api::PendingDownload::new(&pool, &config, &file, None, &ip, &headers),
+        Ok(false) => return Err(api::download_limit_reached(&file)),
+        Err(response) => return Err(response.into()),
+    };
+    let contents = match storage::read_blob(&config, file.blob_name(), file.file_size.max(0) as u64).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("File read error {}: {}", uuid, e);
+            return Err(ApiError::Internal("File read error"));
+        }
+    };
+
+    // the file name is escaped and the contents sanitized, neither is searched for placeholders
+    let (head, tail) = MARKDOWN_HTML.split_once("{{content}}").unwrap_or((MARKDOWN_HTML, ""));
+    let page = format!(
+        "{}{}{}",
+        head.replace("{{title}}", &ammonia::clean_text(&file.file_name)),
+        render(&String::from_utf8_lossy(&contents)),
+        tail,
+    );
+    let length = page.len();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8".to_string()),
+            (header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY.to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        pending.body(Body::from(page), length),
+    )
+        .into_response())
+}
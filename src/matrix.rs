@@ -0,0 +1,267 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Path, Query, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http_body_util::Limited;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// How much of an error response of the rest of the API is read to pass its message on.
+const MAX_ERROR_LENGTH: usize = 64 * 1024;
+
+/// Returns an error response in the format of the Matrix client-server API.
+fn error(status: StatusCode, errcode: &str, message: &str) -> Response {
+    (status, Json(json!({ "errcode": errcode, "error": message }))).into_response()
+}
+
+/// Returns the Matrix error response for a ready-made error response of the rest of the API.
+/// The message of the original response is kept.
+async fn translate(response: Response) -> Response {
+    let status = response.status();
+    let errcode = match status {
+        StatusCode::UNAUTHORIZED => "M_UNKNOWN_TOKEN",
+        StatusCode::FORBIDDEN => "M_FORBIDDEN",
+        StatusCode::NOT_FOUND | StatusCode::GONE => "M_NOT_FOUND",
+        StatusCode::PAYLOAD_TOO_LARGE => "M_TOO_LARGE",
+        StatusCode::TOO_MANY_REQUESTS => "M_LIMIT_EXCEEDED",
+        _ if status.is_success() => return response,
+        _ => "M_UNKNOWN",
+    };
+    // Matrix has no 410, media that is gone is not found
+    let status = match status {
+        StatusCode::GONE => StatusCode::NOT_FOUND,
+        status => status,
+    };
+    let body = to_bytes(response.into_body(), MAX_ERROR_LENGTH).await.unwrap_or_default();
+    // the API answers with a JSON error, Matrix clients only get its message
+    let message = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body.get("message")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+    error(status, errcode, &message)
+}
+
+/// Returns the response for a request while the Matrix endpoints are disabled.
+fn disabled() -> Response {
+    error(StatusCode::NOT_FOUND, "M_UNRECOGNIZED", "The Matrix media endpoints are disabled")
+}
+
+/// Helper to authenticate a Matrix request
+/// Homeservers and clients send the access token as a bearer token or as the `access_token` query parameter,
+/// bitBeam takes the key of the user or a scoped token with the `upload` scope as the access token.
+async fn authenticate(
+    pool: &AnyPool,
+    headers: &HeaderMap,
+    access_token: Option<&str>,
+    ip: &str,
+) -> Result<(data::User, Option<data::ApiToken>), Response> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(access_token);
+    let Some(key) = bearer.and_then(|key| HeaderValue::from_str(key.trim()).ok()) else {
+        return Err(error(StatusCode::UNAUTHORIZED, "M_MISSING_TOKEN", "Missing access token"));
+    };
+    let mut headers = headers.clone();
+    headers.insert("key", key);
+    match tokens::authenticate(pool, &headers, ip, tokens::Scope::Upload).await {
+        Ok(authenticated) => Ok(authenticated),
+        Err(response) => Err(translate(response).await),
+    }
+}
+
+/// Handler to upload media like the Matrix content repository
+/// This function stores the body as a public file owned by the user of the access token
+/// and returns its `mxc://` URI, the media ID is the UUID of the file.
+/// Media can be downloaded until its download limit is reached, unlimited if unlimited downloads are allowed.
+/// It only works if `matrix_server_name` is configured.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "Authorization: Bearer <key>" -H "content-type: image/png" --data-binary @<file_path> http://localhost:3000/_matrix/media/v3/upload?filename=<file_name>
+/// requires the following headers:
+/// - authorization: `Bearer` and the key of the user or a token with the `upload` scope (optional, not optional without `access_token`)
+/// - content-type: the content type of the media (optional)
+///
+/// accepts the following query parameters:
+/// - filename: the name of the media (optional)
+/// - access_token: the key of the user or a token, for clients that can't send headers (optional)
+#[instrument(skip_all)]
+pub async fn upload(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::MatrixUploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received Matrix media upload from IP: {}", ip);
+
+    let Some(server_name) = config.matrix_server_name.as_deref() else {
+        return disabled();
+    };
+    let (user, token) = match authenticate(&pool, &headers, query.access_token.as_deref(), &ip).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    if !user.is_verified() {
+        return translate(api::unverified(&user).into_response()).await;
+    }
+
+    // like `/upload`, announced sizes over the limit are refused before the body is read
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return translate(api::too_large(limit).into_response()).await;
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+    let body = match Bytes::from_request(request, &()).await {
+        Ok(body) => body,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return translate(api::too_large(limit).into_response()).await,
+        Err(e) => {
+            warn!("Body read error: {}", e);
+            return error(StatusCode::BAD_REQUEST, "M_UNKNOWN", "The body could not be read");
+        }
+    };
+
+    let download_limit = match api::download_limit_allowed(&config, 0) {
+        true => 0,
+        false => config.default_download_limit,
+    };
+    let new_file = data::NewFile {
+        file_name: query.filename.unwrap_or_else(|| "unknown".to_string()),
+        content_type: headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        download_limit,
+        owner: user.username.clone(),
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility: "public".to_string(),
+        allowed_content_types: tokens::allowed_content_types(token.as_ref()),
+        expires: None,
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: tokens::collection(token.as_ref()),
+        description: None,
+    };
+    let file = match api::store_file(&pool, &config, &ip, new_file, body).await {
+        Ok(uploaded_file) => uploaded_file.file,
+        Err(response) => return translate(response).await,
+    };
+    info!("Matrix media {} uploaded by {}", file.id, user.username);
+    audit::record(&pool, audit::Action::Upload, Some(&user.username), Some(&file.id), &ip).await;
+
+    Json(json!({ "content_uri": format!("mxc://{}/{}", server_name, file.id) })).into_response()
+}
+
+/// Handler to download media like the Matrix content repository
+/// This function serves the file with the media ID as its UUID exactly like `/download/<uuid>`,
+/// counting the download.
+/// Only media of the configured `matrix_server_name` is served, bitBeam does not fetch remote media.
+/// The file name in the path, if given, replaces the stored name in the Content-Disposition header.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/_matrix/media/v3/download/<server_name>/<media_id>
+/// requires the following path parameters:
+/// - server_name: the server name of the `mxc://` URI (not optional)
+/// - media_id: the media ID of the `mxc://` URI (not optional)
+/// - file_name: the name to save the media as (optional)
+#[instrument(skip_all)]
+pub async fn download(
+    Path(path): Path<Vec<String>>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    let (server_name, media_id, file_name) = match path.as_slice() {
+        [server_name, media_id] => (server_name, media_id, None),
+        [server_name, media_id, file_name] => (server_name, media_id, Some(file_name)),
+        _ => return error(StatusCode::NOT_FOUND, "M_NOT_FOUND", "Media not found"),
+    };
+    info!("Received Matrix media download for {}/{} from IP: {}", server_name, media_id, ip);
+
+    let Some(local_name) = config.matrix_server_name.as_deref() else {
+        return disabled();
+    };
+    if server_name != local_name || !ids::is_valid_file_id(media_id) {
+        return error(StatusCode::NOT_FOUND, "M_NOT_FOUND", "Media not found");
+    }
+    let file = match cache::file(&pool, media_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "M_NOT_FOUND", "Media not found"),
+        Err(e) => {
+            error!("DB select error {}: {}", media_id, e);
+            return translate(db::error_response(&e, "Database select error")).await;
+        }
+    };
+    let name = file_name.cloned().unwrap_or_else(|| file.file_name.clone());
+
+    let mut response = api::send_download(&pool, &config, file, &ip, &headers, None).await;
+    if !response.status().is_success() {
+        return translate(response).await;
+    }
+    if let Ok(disposition) = HeaderValue::from_str(&api::content_disposition("attachment", &name, media_id)) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    // the Matrix spec asks media repositories to keep served media from running scripts
+    response.headers_mut().insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox; default-src 'none'; script-src 'none'; plugin-types application/pdf; style-src 'unsafe-inline'; object-src 'self';"),
+    );
+    response
+}
+
+/// Handler to return the media repository configuration of the Matrix content repository
+/// This function tells clients how large their uploads may be.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "Authorization: Bearer <key>" http://localhost:3000/_matrix/media/v3/config
+/// requires the following headers:
+/// - authorization: `Bearer` and the key of the user or a token with the `upload` scope (not optional)
+#[instrument(skip_all)]
+pub async fn media_config(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::MatrixUploadQuery>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received Matrix media config request from IP: {}", ip);
+
+    if config.matrix_server_name.is_none() {
+        return disabled();
+    }
+    let (user, token) = match authenticate(&pool, &headers, query.access_token.as_deref(), &ip).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+
+    Json(json!({ "m.upload.size": api::upload_limit(&config, &user, token.as_ref()) })).into_response()
+}
@@ -0,0 +1,122 @@
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::data;
+
+/// The cached blobs, `None` while the cache is disabled.
+static CACHE: Mutex<Option<MemoryCache>> = Mutex::new(None);
+
+/// This struct holds the contents of small blobs that were downloaded recently.
+/// Blobs are named after their content, so a cached blob never goes stale,
+/// it only has to be dropped when the blob is removed from disk.
+/// When the cache is full the least recently used blobs are dropped first.
+struct MemoryCache {
+    /// The most bytes all cached blobs together may take.
+    capacity: u64,
+    /// The largest blob that is cached.
+    max_entry: u64,
+    /// The bytes the cached blobs take now.
+    used: u64,
+    /// Counts up on every use, the tick of an entry tells how recently it was used.
+    tick: u64,
+    /// The cached blobs by name, with the tick of their last use.
+    entries: HashMap<String, (Bytes, u64)>,
+    /// The names of the cached blobs by the tick of their last use, the oldest first.
+    order: BTreeMap<u64, String>,
+}
+
+impl MemoryCache {
+    /// Marks the entry as just used and returns its contents.
+    fn touch(&mut self, name: &str) -> Option<Bytes> {
+        self.tick += 1;
+        let (data, used) = self.entries.get_mut(name)?;
+        self.order.remove(used);
+        *used = self.tick;
+        self.order.insert(self.tick, name.to_string());
+        Some(data.clone())
+    }
+
+    /// Drops the entry and returns true if it was cached.
+    fn remove(&mut self, name: &str) -> bool {
+        match self.entries.remove(name) {
+            Some((data, used)) => {
+                self.order.remove(&used);
+                self.used -= data.len() as u64;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// This function enables the memory cache if `memory_cache_bytes` is set.
+/// Only blobs up to `memory_cache_max_file_size` bytes are cached.
+pub fn init(config: &data::Config) {
+    let cache = (config.memory_cache_bytes > 0).then(|| MemoryCache {
+        capacity: config.memory_cache_bytes,
+        max_entry: config.memory_cache_max_file_size.min(config.memory_cache_bytes),
+        used: 0,
+        tick: 0,
+        entries: HashMap::new(),
+        order: BTreeMap::new(),
+    });
+    if let Some(cache) = &cache {
+        info!(
+            "Caching blobs of up to {} bytes in {} bytes of memory",
+            cache.max_entry, cache.capacity
+        );
+    }
+    *CACHE.lock().unwrap() = cache;
+}
+
+/// Returns true if a blob of this size is kept in the cache once it was read.
+pub fn fits(size: i64) -> bool {
+    CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|cache| u64::try_from(size).is_ok_and(|size| size <= cache.max_entry))
+}
+
+/// Returns the contents of a cached blob, `None` if it is not cached.
+pub fn get(name: &str) -> Option<Bytes> {
+    CACHE.lock().unwrap().as_mut()?.touch(name)
+}
+
+/// This function caches the contents of a blob if it is small enough,
+/// dropping the least recently used blobs until it fits.
+pub fn insert(name: &str, data: &Bytes) {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut() else {
+        return;
+    };
+    let size = data.len() as u64;
+    if size > cache.max_entry {
+        return;
+    }
+    cache.remove(name);
+    while cache.used + size > cache.capacity {
+        let Some((_, oldest)) = cache.order.pop_first() else {
+            break;
+        };
+        if let Some((dropped, _)) = cache.entries.remove(&oldest) {
+            cache.used -= dropped.len() as u64;
+        }
+    }
+    cache.tick += 1;
+    let tick = cache.tick;
+    cache.entries.insert(name.to_string(), (data.clone(), tick));
+    cache.order.insert(tick, name.to_string());
+    cache.used += size;
+}
+
+/// This function drops a blob from the cache, for when it is removed from disk.
+pub fn remove(name: &str) {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        if cache.remove(name) {
+            debug!("Dropped blob {} from the memory cache", name);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument};
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::webhook::EventKind;
+use std::net::SocketAddr;
+
+/// The events a user is notified about if they don't pick any.
+const DEFAULT_EVENTS: [&str; 2] = ["downloaded", "expired"];
+
+/// Returns the name of an event in the `events` list of a notification target.
+pub(crate) fn event_name(kind: EventKind) -> &'static str {
+    match kind {
+        EventKind::Uploaded => "uploaded",
+        EventKind::Downloaded => "downloaded",
+        EventKind::Deleted => "deleted",
+        EventKind::LimitReached => "limit_reached",
+        EventKind::Expired => "expired",
+    }
+}
+
+/// Returns the event with the given name.
+pub(crate) fn parse_event(name: &str) -> Option<EventKind> {
+    match name {
+        "uploaded" => Some(EventKind::Uploaded),
+        "downloaded" => Some(EventKind::Downloaded),
+        "deleted" => Some(EventKind::Deleted),
+        "limit_reached" => Some(EventKind::LimitReached),
+        "expired" => Some(EventKind::Expired),
+        _ => None,
+    }
+}
+
+/// Returns the title and the text of the push message for an event.
+fn message(kind: EventKind, file: &data::File) -> (String, String) {
+    let title = match kind {
+        EventKind::Uploaded => "File uploaded",
+        EventKind::Downloaded => "File downloaded",
+        EventKind::Deleted => "File deleted",
+        EventKind::LimitReached => "Download limit reached",
+        EventKind::Expired => "File expired",
+    };
+    let text = match kind {
+        EventKind::Downloaded => format!("{} was downloaded: {}", file.file_name, file.download_url),
+        EventKind::Uploaded => format!("{} was uploaded: {}", file.file_name, file.download_url),
+        EventKind::Deleted => format!("{} was deleted", file.file_name),
+        EventKind::LimitReached => format!("{} reached its download limit and will be removed", file.file_name),
+        EventKind::Expired => format!("{} expired and was removed", file.file_name),
+    };
+    (title.to_string(), text)
+}
+
+/// This struct represents the push notification service a user is notified through.
+/// `service` is `ntfy` or `gotify`, `url` is the ntfy topic URL or the Gotify server URL,
+/// `token` is the ntfy access token or the Gotify application token.
+/// `events` is a comma separated list of the events the user is notified about.
+#[derive(sqlx::FromRow)]
+pub(crate) struct Target {
+    #[sqlx(try_from = "db::Text")]
+    pub service: String,
+    #[sqlx(try_from = "db::Text")]
+    pub url: String,
+    #[sqlx(try_from = "db::OptionalText")]
+    pub token: Option<String>,
+    #[sqlx(try_from = "db::Text")]
+    pub events: String,
+}
+
+/// Helper to look up the notification target of a user.
+pub(crate) async fn target(pool: &AnyPool, owner: &str) -> Option<Target> {
+    sqlx::query_as::<_, Target>(
+        r#"
+        SELECT service, url, token, events
+        FROM notifications
+        WHERE username = ?
+        "#,
+    )
+    .bind(owner)
+    .fetch_optional(pool)
+    .await
+    .unwrap_or_else(|e| {
+        error!("DB select notification error {}: {}", owner, e);
+        None
+    })
+}
+
+/// Helper to remove the notification target of a user.
+async fn remove_target(pool: &AnyPool, username: &str) -> Result<(), Response> {
+    if let Err(e) = sqlx::query("DELETE FROM notifications WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await
+    {
+        error!("DB delete error {}: {}", username, e);
+        return Err(db::error_response(&e, "Database delete error"));
+    }
+    Ok(())
+}
+
+/// This struct represents a push message ready to be posted by the webhook delivery.
+pub(crate) struct Message {
+    pub url: String,
+    pub body: Vec<u8>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+/// Returns the push message for an event,
+/// or `None` if the target is not notified about the event.
+pub(crate) fn message_for(target: &Target, kind: EventKind, file: &data::File) -> Option<Message> {
+    if !target.events.split(',').any(|event| event == event_name(kind)) {
+        return None;
+    }
+    let (title, text) = message(kind, file);
+    match target.service.as_str() {
+        // ntfy takes the text as the body and everything else as headers
+        "ntfy" => {
+            let mut headers = vec![
+                ("content-type", "text/plain".to_string()),
+                ("title", title),
+                ("tags", "bitbeam".to_string()),
+            ];
+            if let Some(token) = &target.token {
+                headers.push(("authorization", format!("Bearer {}", token)));
+            }
+            Some(Message {
+                url: target.url.clone(),
+                body: text.into_bytes(),
+                headers,
+            })
+        }
+        "gotify" => {
+            let url = format!("{}/message", target.url.trim_end_matches('/'));
+            let body = json!({ "title": title, "message": text, "priority": 5 }).to_string();
+            let mut headers = vec![("content-type", "application/json".to_string())];
+            if let Some(token) = &target.token {
+                headers.push(("x-gotify-key", token.clone()));
+            }
+            Some(Message {
+                url,
+                body: body.into_bytes(),
+                headers,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Handler to set the push notifications of a user
+/// This function stores an ntfy topic or a Gotify server that is notified about the user's files,
+/// messages are delivered like webhooks and retried the same way.
+/// An empty or missing URL turns the notifications off.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" -H "content-type: application/json" -d '{"service": "ntfy", "url": "https://ntfy.sh/<topic>"}' http://localhost:3000/user/notifications
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following JSON body:
+/// - service: `ntfy` or `gotify` (not optional with a URL)
+/// - url: the ntfy topic URL or the Gotify server URL (optional, turns notifications off if missing)
+/// - token: the ntfy access token or the Gotify application token (optional, not optional for Gotify)
+/// - events: the events to be notified about, out of `uploaded`, `downloaded`, `deleted`, `limit_reached` and `expired` (optional, defaults to `downloaded` and `expired`)
+#[instrument(skip_all)]
+pub async fn set_notifications(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::NotificationRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received notification update from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let Some(url) = request.url.filter(|url| !url.trim().is_empty()) else {
+        if let Err(response) = remove_target(&pool, &user.username).await {
+            return response;
+        }
+        info!("Notifications of {} turned off", user.username);
+        audit::record(&pool, audit::Action::SetNotifications, Some(&user.username), None, &ip).await;
+        return Json(json!({ "username": user.username, "service": null })).into_response();
+    };
+
+    match reqwest::Url::parse(&url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+        _ => return (StatusCode::BAD_REQUEST, "Only http and https URLs are supported").into_response(),
+    }
+    let service = request.service.unwrap_or_default();
+    if service != "ntfy" && service != "gotify" {
+        return (StatusCode::BAD_REQUEST, "The service must be ntfy or gotify").into_response();
+    }
+    let token = request.token.filter(|token| !token.is_empty());
+    if service == "gotify" && token.is_none() {
+        return (StatusCode::BAD_REQUEST, "Gotify needs an application token").into_response();
+    }
+    let events = match request.events {
+        Some(events) => events,
+        None => DEFAULT_EVENTS.iter().map(|event| event.to_string()).collect(),
+    };
+    if let Some(unknown) = events.iter().find(|event| parse_event(event).is_none()) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown event {}", unknown)).into_response();
+    }
+
+    if let Err(response) = remove_target(&pool, &user.username).await {
+        return response;
+    }
+
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO notifications (username, service, url, token, events)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&user.username)
+    .bind(&service)
+    .bind(&url)
+    .bind(&token)
+    .bind(events.join(","))
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert error {}: {}", user.username, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("Notifications of {} sent to {} {}", user.username, service, url);
+    audit::record(&pool, audit::Action::SetNotifications, Some(&user.username), Some(&url), &ip).await;
+
+    Json(json!({
+        "username": user.username,
+        "service": service,
+        "url": url,
+        "events": events,
+    }))
+    .into_response()
+}
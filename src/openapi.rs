@@ -0,0 +1,106 @@
+use axum::{
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        Server,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{admin, api, changes, data, directory, parts, profile};
+
+/// This struct describes the API as an OpenAPI 3 document.
+/// The paths and schemas are generated from the handlers and the types in `data`,
+/// add new handlers to `paths` to document them.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "bitBeam", description = "A small self-hosted file sharing server"),
+    paths(
+        api::upload,
+        parts::init_upload,
+        parts::upload_part,
+        parts::get_upload,
+        parts::abort_upload,
+        parts::complete_upload,
+        api::download_file,
+        api::file_info,
+        api::delete_file,
+        api::update_file,
+        api::delete_by_token,
+        api::all_files,
+        api::list_files,
+        changes::list_changes,
+        directory::list_public,
+        api::register_user,
+        api::user_usage,
+        profile::get_profile,
+        profile::update_profile,
+        profile::delete_account,
+        admin::list_users,
+        admin::delete_user,
+    ),
+    components(schemas(
+        data::File,
+        data::UploadedFile,
+        data::UploadLinks,
+        data::MultipartUpload,
+        data::UploadPart,
+        data::CompletedPart,
+        data::CompleteUpload,
+        data::FileInfo,
+        data::HumanFile,
+        data::FileUpdate,
+        data::PublicFile,
+        data::RegisteredUser,
+        data::Credentials,
+        data::Usage,
+        data::FilePage,
+        data::ChangePage,
+        data::FileChange,
+        data::TransferUsage,
+        data::UsagePeriod,
+        data::Profile,
+        data::ProfileUpdate,
+        data::DeletionReceipt,
+        data::UserInfo,
+    )),
+    modifiers(&KeyHeader),
+    tags(
+        (name = "files", description = "Uploading, downloading and listing files"),
+        (name = "users", description = "Registration and the account of a user"),
+        (name = "admin", description = "User management for admins"),
+    )
+)]
+struct ApiDoc;
+
+/// This struct adds the `key` header the handlers authenticate with to the document.
+struct KeyHeader;
+
+impl Modify for KeyHeader {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "key",
+                "The key of the user or a scoped token",
+            ))),
+        );
+    }
+}
+
+/// Handler to return the description of the API
+/// This function returns an OpenAPI 3 document of the upload, download, listing
+/// and user management endpoints, so clients can be generated from it.
+/// Behind a reverse proxy with a `base_path` the paths are relative to a server at that prefix.
+/// example request: curl -X GET http://localhost:3000/api/spec
+pub async fn spec(Extension(config): Extension<data::Config>) -> Response {
+    let mut openapi = ApiDoc::openapi();
+    if !config.base_path.is_empty() {
+        openapi.servers = Some(vec![Server::new(&config.base_path)]);
+    }
+    Json(openapi).into_response()
+}
@@ -0,0 +1,160 @@
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+use crate::data;
+use crate::trace_context::TraceContext;
+
+/// The number of spans that can wait for export before new ones are dropped.
+const QUEUE_SIZE: usize = 4096;
+
+/// The most spans exported in one request.
+const MAX_BATCH: usize = 512;
+
+/// How long spans are collected before a batch is exported.
+const EXPORT_DELAY: Duration = Duration::from_secs(5);
+
+/// How long a single export may take.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The OTLP span kind of spans that handle requests of clients.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// The OTLP status code of failed spans.
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// The queue finished spans are sent to, it is set once the exporter is started.
+static QUEUE: OnceLock<mpsc::Sender<ServerSpan>> = OnceLock::new();
+
+/// This struct represents a finished request, exported as an OTLP server span.
+pub(crate) struct ServerSpan {
+    pub context: TraceContext,
+    pub method: String,
+    /// The route the request matched, see `logging::logged_path`.
+    pub route: String,
+    pub client_ip: Option<String>,
+    pub status: u16,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl ServerSpan {
+    /// Returns the span in the OTLP JSON encoding.
+    fn to_json(&self) -> Value {
+        let mut attributes = vec![
+            string_attribute("http.request.method", &self.method),
+            string_attribute("http.route", &self.route),
+            json!({"key": "http.response.status_code", "value": {"intValue": self.status.to_string()}}),
+        ];
+        if let Some(client_ip) = &self.client_ip {
+            attributes.push(string_attribute("client.address", client_ip));
+        }
+        let mut span = json!({
+            "traceId": hex::encode(self.context.trace_id),
+            "spanId": hex::encode(self.context.span_id),
+            "name": self.method,
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(self.end).to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent_id) = self.context.parent_id {
+            span["parentSpanId"] = Value::from(hex::encode(parent_id));
+        }
+        if let Some(state) = &self.context.state {
+            span["traceState"] = Value::from(state.as_str());
+        }
+        // only server errors fail the span, client errors are the client's
+        if self.status >= 500 {
+            span["status"] = json!({"code": STATUS_CODE_ERROR});
+        }
+        span
+    }
+}
+
+/// Returns an OTLP string attribute.
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// Returns the nanoseconds since the unix epoch of a point in time.
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or_default()
+}
+
+/// This function starts the background task that exports request spans to `otlp_endpoint`.
+/// Spans are posted in batches to the OTLP/HTTP traces endpoint in the JSON encoding,
+/// a batch that can't be delivered is dropped. Nothing is started if no endpoint is configured.
+pub fn start(config: &data::Config) {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return;
+    };
+    let (sender, mut receiver) = mpsc::channel::<ServerSpan>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        warn!("OTLP exporter already started");
+        return;
+    }
+    let client = match reqwest::Client::builder().timeout(EXPORT_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Could not build the OTLP HTTP client: {}", e);
+            return;
+        }
+    };
+    let url = format!("{}/v1/traces", endpoint);
+    let resource = json!({
+        "attributes": [
+            string_attribute("service.name", &config.otlp_service_name),
+            string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+        ],
+    });
+    tokio::spawn(async move {
+        while let Some(first) = receiver.recv().await {
+            let mut batch = vec![first];
+            let deadline = tokio::time::sleep(EXPORT_DELAY);
+            tokio::pin!(deadline);
+            while batch.len() < MAX_BATCH {
+                tokio::select! {
+                    span = receiver.recv() => match span {
+                        Some(span) => batch.push(span),
+                        None => break,
+                    },
+                    _ = &mut deadline => break,
+                }
+            }
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": resource,
+                    "scopeSpans": [{
+                        "scope": {"name": "bitbeam", "version": env!("CARGO_PKG_VERSION")},
+                        "spans": batch.iter().map(ServerSpan::to_json).collect::<Vec<_>>(),
+                    }],
+                }],
+            });
+            let request = client
+                .post(&url)
+                .header("content-type", "application/json")
+                .body(body.to_string());
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Exported {} spans to {}", batch.len(), url)
+                }
+                Ok(response) => warn!("OTLP export of {} spans to {} returned {}", batch.len(), url, response.status()),
+                Err(e) => warn!("OTLP export of {} spans to {} failed: {}", batch.len(), url, e),
+            }
+        }
+    });
+}
+
+/// This function queues a finished request span for export.
+/// It never blocks, spans are dropped if the exporter is not started or the queue is full.
+pub(crate) fn export(span: ServerSpan) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    if queue.try_send(span).is_err() {
+        debug!("Dropped a span, the OTLP export queue is full");
+    }
+}
@@ -0,0 +1,764 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Path, Query, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use http_body_util::Limited;
+use md5::Md5;
+use sha2::Digest;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::encryption;
+use crate::error::ApiError;
+use crate::share;
+use crate::storage;
+use crate::tokens;
+
+/// The highest part number of a multipart upload, like S3.
+const MAX_PART_NUMBER: i32 = 10_000;
+
+/// How long a multipart upload can take before the cleanup task gives it up, in seconds.
+pub(crate) const SESSION_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// Returns the directory the parts of a multipart upload are kept in until it is completed.
+/// It is below the directory of part files, so it is never mistaken for blobs.
+fn upload_path(config: &data::Config, upload_id: &str) -> PathBuf {
+    PathBuf::from(&config.data_path).join("tmp").join("uploads").join(upload_id)
+}
+
+/// Returns the error of a part that doesn't match what the client or the server expects.
+fn invalid_part(part_number: i32, message: &str) -> ApiError {
+    warn!("Invalid part {}: {}", part_number, message);
+    ApiError::Rejected {
+        status: StatusCode::BAD_REQUEST,
+        error: "invalid_part",
+        message: message.to_string(),
+        details: json!({ "part_number": part_number }),
+    }
+}
+
+/// Helper to look up a multipart upload of a user
+/// Uploads of other users are reported as missing, so their IDs can't be probed.
+async fn find_session(pool: &AnyPool, upload_id: &str, owner: &str) -> Result<data::UploadSession, ApiError> {
+    if Uuid::parse_str(upload_id).is_err() {
+        return Err(ApiError::NotFound("Upload not found".to_string()));
+    }
+    let session = sqlx::query_as::<_, data::UploadSession>(
+        r#"
+        SELECT *
+        FROM upload_sessions
+        WHERE id = ? AND owner = ?
+        "#,
+    )
+    .bind(upload_id)
+    .bind(owner)
+    .fetch_optional(pool)
+    .await;
+    match session {
+        Ok(Some(session)) => Ok(session),
+        Ok(None) => Err(ApiError::NotFound("Upload not found".to_string())),
+        Err(e) => {
+            error!("DB select upload session error {}: {}", upload_id, e);
+            Err(db::error(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to list the parts of a multipart upload, ordered by their number.
+async fn list_parts(pool: &AnyPool, upload_id: &str) -> Result<Vec<data::UploadPart>, ApiError> {
+    sqlx::query_as::<_, data::UploadPart>(
+        r#"
+        SELECT part_number, size, sha256, uploaded_at, encrypted
+        FROM upload_parts
+        WHERE upload_id = ?
+        ORDER BY part_number
+        "#,
+    )
+    .bind(upload_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        error!("DB select upload parts error {}: {}", upload_id, e);
+        db::error(&e, "Database select error")
+    })
+}
+
+/// Returns the multipart upload as the client sees it.
+fn describe(session: &data::UploadSession, max_bytes: u64, parts: Vec<data::UploadPart>) -> data::MultipartUpload {
+    data::MultipartUpload {
+        upload_id: session.id.clone(),
+        file_name: session.file_name.clone(),
+        created_at: session.created_at,
+        expires_at: session.created_at.saturating_add(SESSION_TTL),
+        max_part_number: MAX_PART_NUMBER,
+        max_bytes,
+        parts,
+    }
+}
+
+/// Helper to remove a multipart upload, its parts and the directory they are kept in.
+async fn remove_session(pool: &AnyPool, config: &data::Config, upload_id: &str) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query("DELETE FROM upload_parts WHERE upload_id = ?")
+        .bind(upload_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM upload_sessions WHERE id = ?")
+        .bind(upload_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    match fs::remove_dir_all(upload_path(config, upload_id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Could not remove the parts of upload {}: {}", upload_id, e),
+    }
+    Ok(())
+}
+
+/// This function removes the multipart uploads that were not completed within `SESSION_TTL`.
+/// It is run by the cleanup task.
+pub(crate) async fn remove_stale(pool: &AnyPool, config: &data::Config) -> Result<(), sqlx::Error> {
+    let stale = sqlx::query_scalar::<_, String>("SELECT id FROM upload_sessions WHERE created_at <= ?")
+        .bind(Utc::now().timestamp() - SESSION_TTL)
+        .fetch_all(pool)
+        .await?;
+    for upload_id in &stale {
+        remove_session(pool, config, upload_id).await?;
+        info!("Gave up multipart upload {} that was not completed", upload_id);
+    }
+    Ok(())
+}
+
+/// This function removes every multipart upload of a user, for when the user is removed.
+pub(crate) async fn remove_user_uploads(pool: &AnyPool, config: &data::Config, owner: &str) -> Result<(), sqlx::Error> {
+    let uploads = sqlx::query_scalar::<_, String>("SELECT id FROM upload_sessions WHERE owner = ?")
+        .bind(owner)
+        .fetch_all(pool)
+        .await?;
+    for upload_id in &uploads {
+        remove_session(pool, config, upload_id).await?;
+    }
+    Ok(())
+}
+
+/// Writes a part to its file, replacing an earlier upload of the same part.
+/// The part is written next to it first, so a failed write never leaves half a part behind.
+/// Parts are encrypted like blobs if a master key is configured, it returns whether the part is encrypted.
+async fn write_part(config: &data::Config, path: PathBuf, body: &[u8]) -> std::io::Result<bool> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let written = path.with_extension(format!("{}.part", Uuid::new_v4()));
+    let mut file = fs::File::create(&written).await?;
+    let result = async {
+        match &config.master_key {
+            Some(master_key) => file.write_all(&encryption::encrypt(master_key, body)?).await?,
+            None => file.write_all(body).await?,
+        }
+        file.sync_all().await?;
+        fs::rename(&written, &path).await
+    };
+    if let Err(e) = result.await {
+        let _ = fs::remove_file(&written).await;
+        return Err(e);
+    }
+    Ok(config.master_key.is_some())
+}
+
+/// Reads a part back, decrypting it if its row says it was written encrypted.
+/// Parts written before the row recorded it count as encrypted if they decrypt with the master key.
+async fn read_part(config: &data::Config, upload_id: &str, part: &data::UploadPart) -> std::io::Result<Vec<u8>> {
+    let data = fs::read(upload_path(config, upload_id).join(part.part_number.to_string())).await?;
+    match (&config.master_key, part.encrypted) {
+        (Some(master_key), Some(1)) => encryption::decrypt(master_key, &data),
+        (None, Some(1)) => Err(std::io::Error::other("the part is encrypted, but no master key is configured")),
+        (Some(master_key), None) => Ok(encryption::decrypt_legacy(master_key, &data).unwrap_or(data)),
+        _ => Ok(data),
+    }
+}
+
+/// Handler to start a multipart upload
+/// This function starts an upload that is sent in parts with `PUT /upload/<upload_id>/part/<n>`
+/// and stored as one file with `POST /upload/<upload_id>/complete`,
+/// so very large files can be sent in parallel chunks and a failed chunk can be sent again
+/// instead of the whole file.
+/// The options of the file are given here, like for `/upload`, the expiry of `max_age` counts from now.
+/// The parts are kept for 7 days, an upload that is not completed by then is given up.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "file_name: <file_name>" -H "content-type: <content_type>" http://localhost:3000/upload/init
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - file_name, content-type, download_limit, slug, encrypted, tags, visibility, expire_if_unused_days, expires_at, max_age, cache, publish, description:
+///   the options of the file like for `/upload` (optional, can also be query parameters)
+#[utoipa::path(
+    post,
+    path = "/upload/init",
+    tag = "files",
+    params(data::UploadOptions),
+    responses(
+        (status = 200, description = "The started upload", body = data::MultipartUpload),
+        (status = 400, description = "An option is invalid"),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn init_upload(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    options: data::UploadOptions,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received multipart upload start from IP: {}", ip);
+
+    let (user, token) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    if !user.is_verified() {
+        return Err(api::unverified(&user));
+    }
+    if options.extract.is_some_and(|s| s.trim().eq_ignore_ascii_case("true")) {
+        return Err(ApiError::BadRequest("extract can't be used with multipart uploads".to_string()));
+    }
+
+    let content_type = headers
+        .get("content-type")
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+    let download_limit = options
+        .download_limit
+        .and_then(|s| api::parse_download_limit(&s))
+        .unwrap_or_else(|| api::default_download_limit(&config, &user));
+    if !api::download_limit_allowed(&config, download_limit) {
+        return Err(api::invalid_download_limit(&config, download_limit));
+    }
+    let slug = options.slug.map(|s| s.trim().to_string());
+    if slug.as_deref().is_some_and(|slug| !api::is_valid_slug(slug)) {
+        return Err(ApiError::BadRequest(
+            "Invalid slug, use 1 to 64 letters, digits, '-', '_' or '.'".to_string(),
+        ));
+    }
+    let encrypted = options
+        .encrypted
+        .is_some_and(|s| s.trim().eq_ignore_ascii_case("true"));
+    let tags = match options.tags.as_deref().map(api::parse_tags) {
+        Some(Some(tags)) => tags,
+        Some(None) => return Err(api::invalid_tags()),
+        None => Vec::new(),
+    };
+    let visibility = match options.visibility.as_deref() {
+        Some(visibility) => match share::parse_visibility(visibility) {
+            Some(visibility) => visibility,
+            None => return Err(share::invalid_visibility().into()),
+        },
+        None => api::default_visibility(&user),
+    };
+    let expire_if_unused_days = match options.expire_if_unused_days.as_deref().map(|s| s.trim().parse::<i32>()) {
+        Some(Ok(days)) if days > 0 => Some(days),
+        Some(_) => {
+            return Err(ApiError::BadRequest(
+                "expire_if_unused_days must be a positive number of days".to_string(),
+            ));
+        }
+        None => None,
+    };
+    let expires = api::upload_expires(&user, options.expires_at.as_deref(), options.max_age.as_deref())
+        .map_err(ApiError::BadRequest)?;
+    let cache_control = match options.cache.as_deref().map(api::parse_cache_control) {
+        Some(Some(cache_control)) => Some(cache_control),
+        Some(None) => return Err(api::invalid_cache_control()),
+        None => None,
+    };
+    let description = api::parse_description(options.description.as_deref()).map_err(ApiError::BadRequest)?;
+
+    let session = data::UploadSession {
+        id: Uuid::new_v4().to_string(),
+        owner: user.username.clone(),
+        file_name: options.file_name.unwrap_or_else(|| "unknown".to_string()),
+        content_type,
+        download_limit,
+        slug,
+        encrypted: encrypted as i32,
+        tags: tags.join(","),
+        visibility,
+        expires,
+        expire_if_unused_days,
+        cache_control,
+        published: options
+            .publish
+            .is_some_and(|s| s.trim().eq_ignore_ascii_case("true")) as i32,
+        created_at: Utc::now().timestamp(),
+        completing: 0,
+        description,
+    };
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO upload_sessions
+            (id, owner, file_name, content_type, download_limit, slug, encrypted, tags, visibility, expires, expire_if_unused_days, cache_control, published, created_at, description)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&session.id)
+    .bind(&session.owner)
+    .bind(&session.file_name)
+    .bind(&session.content_type)
+    .bind(session.download_limit)
+    .bind(&session.slug)
+    .bind(session.encrypted)
+    .bind(&session.tags)
+    .bind(&session.visibility)
+    .bind(session.expires)
+    .bind(session.expire_if_unused_days)
+    .bind(&session.cache_control)
+    .bind(session.published)
+    .bind(session.created_at)
+    .bind(&session.description)
+    .execute(&pool)
+    .await;
+    if let Err(e) = inserted {
+        error!("DB insert upload session error {}: {}", session.id, e);
+        return Err(db::error(&e, "Database insert error"));
+    }
+    info!("Started multipart upload {} of {}", session.id, session.owner);
+
+    let max_bytes = api::upload_limit(&config, &user, token.as_ref());
+    Ok(Json(describe(&session, max_bytes, Vec::new())).into_response())
+}
+
+/// Handler to upload a part of a multipart upload
+/// This function stores one part of an upload started with `/upload/init`.
+/// Parts can be sent in any order and in parallel, they are joined by their number when the upload is completed.
+/// Sending a part with the same number again replaces it, so a failed part is simply sent again.
+/// All parts together can't be larger than the upload size limit of the user.
+/// The answer carries the SHA-256 of the part as JSON and as `ETag`, to be listed when the upload is completed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" --data-binary @<chunk> http://localhost:3000/upload/<upload_id>/part/1
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - content-sha256: the hex SHA-256 of the part to verify it against (optional)
+/// - content-md5: the hex or base64 MD5 of the part to verify it against (optional)
+///
+/// requires the following path parameters:
+/// - upload_id: the ID `/upload/init` returned (not optional)
+/// - part_number: the number of the part, 1 to 10000 (not optional)
+#[utoipa::path(
+    put,
+    path = "/upload/{upload_id}/part/{part_number}",
+    tag = "files",
+    request_body(content = Vec<u8>, description = "The contents of the part"),
+    params(
+        ("upload_id" = String, Path, description = "The ID of the multipart upload"),
+        ("part_number" = i32, Path, description = "The number of the part, 1 to 10000"),
+        ("content-sha256" = Option<String>, Header, description = "The hex SHA-256 of the part"),
+        ("content-md5" = Option<String>, Header, description = "The hex or base64 MD5 of the part"),
+    ),
+    responses(
+        (status = 200, description = "The stored part", body = data::UploadPart),
+        (status = 400, description = "The part number is invalid"),
+        (status = 404, description = "The upload does not exist"),
+        (status = 413, description = "The parts are larger than the upload limit or the storage quota"),
+        (status = 422, description = "The part does not match its checksum"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all, fields(upload_id = %upload_id, part_number = %part_number))]
+pub async fn upload_part(
+    Path((upload_id, part_number)): Path<(String, i32)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received part {} of upload {} from IP: {}", part_number, upload_id, ip);
+
+    let (user, token) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    let session = find_session(&pool, &upload_id, &user.username).await?;
+    if session.completing != 0 {
+        return Err(ApiError::Conflict("The upload is being completed".to_string()));
+    }
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(invalid_part(part_number, "Part numbers go from 1 to 10000"));
+    }
+
+    // the other parts already take up some of the upload limit
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let others: u64 = list_parts(&pool, &upload_id)
+        .await?
+        .iter()
+        .filter(|part| part.part_number != part_number)
+        .map(|part| part.size.max(0) as u64)
+        .sum();
+    let remaining = limit.saturating_sub(others);
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > remaining) {
+        return Err(api::too_large(limit));
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, remaining as usize)));
+    let body = match Bytes::from_request(request, &()).await {
+        Ok(body) => body,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return Err(api::too_large(limit)),
+        Err(e) => {
+            warn!("Body read error: {}", e);
+            return Err(ApiError::BadRequest(e.body_text()));
+        }
+    };
+
+    // every part can be verified on its own, so a corrupted one is sent again right away
+    let sha256 = storage::content_hash(&body);
+    if let Some(expected) = headers.get("content-sha256").and_then(|hv| hv.to_str().ok()) {
+        if !api::digest_matches(expected.trim(), &hex::decode(&sha256).unwrap_or_default()) {
+            return Err(api::checksum_mismatch("sha256", expected.trim(), &sha256));
+        }
+    }
+    if let Some(expected) = headers.get("content-md5").and_then(|hv| hv.to_str().ok()) {
+        let computed = Md5::digest(&body);
+        if !api::digest_matches(expected.trim(), &computed) {
+            return Err(api::checksum_mismatch("md5", expected.trim(), &hex::encode(computed)));
+        }
+    }
+    api::check_storage_quota(&pool, &config, &user.username, (others + body.len() as u64) as i64).await?;
+
+    let path = upload_path(&config, &upload_id).join(part_number.to_string());
+    let encrypted = match write_part(&config, path, &body).await {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            error!("Part write error {} {}: {}", upload_id, part_number, e);
+            return Err(ApiError::Internal("File write error"));
+        }
+    };
+    let part = data::UploadPart {
+        part_number,
+        size: body.len() as i64,
+        sha256,
+        uploaded_at: Utc::now().timestamp(),
+        encrypted: Some(encrypted as i32),
+    };
+    let store = async {
+        let mut transaction = pool.begin().await?;
+        sqlx::query("DELETE FROM upload_parts WHERE upload_id = ? AND part_number = ?")
+            .bind(&upload_id)
+            .bind(part_number)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO upload_parts (upload_id, part_number, size, sha256, uploaded_at, encrypted)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&upload_id)
+        .bind(part.part_number)
+        .bind(part.size)
+        .bind(&part.sha256)
+        .bind(part.uploaded_at)
+        .bind(part.encrypted)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await
+    };
+    if let Err(e) = store.await {
+        error!("DB insert upload part error {} {}: {}", upload_id, part_number, e);
+        return Err(db::error(&e, "Database insert error"));
+    }
+    info!("Stored part {} of upload {} with {} bytes", part_number, upload_id, part.size);
+
+    let etag = format!("\"{}\"", part.sha256);
+    Ok(([(header::ETAG, etag)], Json(part)).into_response())
+}
+
+/// Handler to show a multipart upload
+/// This function returns the parts of an upload received so far,
+/// so a client that was interrupted can tell which parts it still has to send.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/upload/<upload_id>
+/// requires the following headers:
+/// - key: the key of the user that started the upload (not optional)
+///
+/// requires the following path parameter:
+/// - upload_id: the ID `/upload/init` returned (not optional)
+#[utoipa::path(
+    get,
+    path = "/upload/{upload_id}",
+    tag = "files",
+    params(("upload_id" = String, Path, description = "The ID of the multipart upload")),
+    responses(
+        (status = 200, description = "The upload and its parts", body = data::MultipartUpload),
+        (status = 404, description = "The upload does not exist"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all, fields(upload_id = %upload_id))]
+pub async fn get_upload(
+    Path(upload_id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received multipart upload request for {} from IP: {}", upload_id, ip);
+
+    let (user, token) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    let session = find_session(&pool, &upload_id, &user.username).await?;
+    let parts = list_parts(&pool, &upload_id).await?;
+    let max_bytes = api::upload_limit(&config, &user, token.as_ref());
+    Ok(Json(describe(&session, max_bytes, parts)).into_response())
+}
+
+/// Handler to abort a multipart upload
+/// This function removes an upload and the parts received so far.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/upload/<upload_id>
+/// requires the following headers:
+/// - key: the key of the user that started the upload (not optional)
+///
+/// requires the following path parameter:
+/// - upload_id: the ID `/upload/init` returned (not optional)
+#[utoipa::path(
+    delete,
+    path = "/upload/{upload_id}",
+    tag = "files",
+    params(("upload_id" = String, Path, description = "The ID of the multipart upload")),
+    responses(
+        (status = 204, description = "The upload was removed"),
+        (status = 404, description = "The upload does not exist"),
+        (status = 409, description = "The upload is being completed"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all, fields(upload_id = %upload_id))]
+pub async fn abort_upload(
+    Path(upload_id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received multipart upload abort for {} from IP: {}", upload_id, ip);
+
+    let (user, _) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    let session = find_session(&pool, &upload_id, &user.username).await?;
+    if session.completing != 0 {
+        return Err(ApiError::Conflict("The upload is being completed".to_string()));
+    }
+    if let Err(e) = remove_session(&pool, &config, &upload_id).await {
+        error!("DB delete upload session error {}: {}", upload_id, e);
+        return Err(db::error(&e, "Database delete error"));
+    }
+    info!("Aborted multipart upload {} of {}", upload_id, user.username);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Handler to complete a multipart upload
+/// This function joins the parts of an upload in the order of their numbers
+/// and stores them as one file with the options given to `/upload/init`,
+/// with the same checks and answer as `/upload`.
+/// Without a body every part received is used, gaps in the numbers are fine.
+/// With a body only the listed parts are used, each has to match the SHA-256 its upload answered with,
+/// so parts that were replaced in the meantime are noticed.
+/// Once the file is stored the parts are removed, if it is refused the upload can be completed again.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" http://localhost:3000/upload/<upload_id>/complete
+/// example request with parts: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"parts": [{"part_number": 1, "sha256": "<sha256>"}]}' http://localhost:3000/upload/<upload_id>/complete
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - content-sha256, content-md5: the checksum of the whole file to verify it against (optional)
+/// - accept: `text/plain` to get only the download URL back instead of JSON (optional)
+///
+/// requires the following path parameter:
+/// - upload_id: the ID `/upload/init` returned (not optional)
+///
+/// accepts the following JSON body:
+/// - parts: the parts to join with their `part_number` and `sha256`, in ascending order (optional)
+#[utoipa::path(
+    post,
+    path = "/upload/{upload_id}/complete",
+    tag = "files",
+    request_body(content = Option<data::CompleteUpload>, description = "The parts to join, every part if missing"),
+    params(("upload_id" = String, Path, description = "The ID of the multipart upload"), data::UploadQuery),
+    responses(
+        (status = 200, description = "The stored file", body = data::UploadedFile),
+        (status = 400, description = "A listed part is missing or changed"),
+        (status = 404, description = "The upload does not exist"),
+        (status = 409, description = "The upload is being completed"),
+        (status = 413, description = "The file is larger than the upload limit or the storage quota"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all, fields(upload_id = %upload_id))]
+pub async fn complete_upload(
+    Path(upload_id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    request: Option<Json<data::CompleteUpload>>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received multipart upload completion for {} from IP: {}", upload_id, ip);
+
+    let (user, token) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    if !user.is_verified() {
+        return Err(api::unverified(&user));
+    }
+    let session = find_session(&pool, &upload_id, &user.username).await?;
+
+    // parts can't be replaced and the upload can't be completed twice while it is joined
+    let claimed = sqlx::query("UPDATE upload_sessions SET completing = 1 WHERE id = ? AND completing = 0")
+        .bind(&upload_id)
+        .execute(&pool)
+        .await;
+    match claimed {
+        Ok(result) if result.rows_affected() == 1 => {}
+        Ok(_) => return Err(ApiError::Conflict("The upload is being completed".to_string())),
+        Err(e) => {
+            error!("DB update upload session error {}: {}", upload_id, e);
+            return Err(db::error(&e, "Database update error"));
+        }
+    }
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let new_file = new_file(&session, &headers, token.as_ref());
+    let listed = request.map(|Json(request)| request.parts);
+    let stored = store_parts(&pool, &config, &ip, &session.id, new_file, limit, listed).await;
+    let uploaded_file = match stored {
+        Ok(uploaded_file) => uploaded_file,
+        Err(e) => {
+            // the client can fix what was refused and complete the upload again
+            let released = sqlx::query("UPDATE upload_sessions SET completing = 0 WHERE id = ?")
+                .bind(&upload_id)
+                .execute(&pool)
+                .await;
+            if let Err(e) = released {
+                error!("DB update upload session error {}: {}", upload_id, e);
+            }
+            return Err(e);
+        }
+    };
+
+    if let Err(e) = remove_session(&pool, &config, &upload_id).await {
+        warn!("Could not remove completed upload {}: {}", upload_id, e);
+    }
+    let file = &uploaded_file.file;
+    info!("Completed multipart upload {} as file {}", upload_id, file.id);
+    audit::record(&pool, audit::Action::Upload, Some(&file.owner), Some(&file.id), &ip).await;
+    Ok(api::upload_response(&headers, &query, uploaded_file))
+}
+
+/// Returns the file a multipart upload is stored as, with the options given to `/upload/init`
+/// and the checksums of the whole file from the headers of the completion.
+fn new_file(session: &data::UploadSession, headers: &HeaderMap, token: Option<&data::ApiToken>) -> data::NewFile {
+    data::NewFile {
+        file_name: session.file_name.clone(),
+        content_type: session.content_type.clone(),
+        download_limit: session.download_limit,
+        owner: session.owner.clone(),
+        expected_sha256: headers
+            .get("content-sha256")
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.trim().to_string()),
+        expected_md5: headers
+            .get("content-md5")
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.trim().to_string()),
+        slug: session.slug.clone(),
+        encrypted: session.encrypted != 0,
+        tags: session
+            .tags
+            .split(',')
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+        visibility: session.visibility.clone(),
+        allowed_content_types: tokens::allowed_content_types(token),
+        expires: session.expires,
+        expire_if_unused_days: session.expire_if_unused_days,
+        cache_control: session.cache_control.clone(),
+        published: session.published != 0,
+        collection_id: tokens::collection(token),
+        description: session.description.clone(),
+    }
+}
+
+/// Helper to join the parts of a multipart upload and store them as `new_file`
+/// `listed` are the parts the client asked for, every part if `None`.
+/// Every part is checked against the SHA-256 it was stored with before it is used.
+async fn store_parts(
+    pool: &AnyPool,
+    config: &data::Config,
+    ip: &str,
+    upload_id: &str,
+    new_file: data::NewFile,
+    limit: u64,
+    listed: Option<Vec<data::CompletedPart>>,
+) -> Result<data::UploadedFile, ApiError> {
+    let parts = list_parts(pool, upload_id).await?;
+    let parts = match listed {
+        Some(listed) => {
+            let mut selected = Vec::with_capacity(listed.len());
+            for (index, wanted) in listed.iter().enumerate() {
+                if index > 0 && wanted.part_number <= listed[index - 1].part_number {
+                    return Err(invalid_part(wanted.part_number, "Parts must be listed in ascending order"));
+                }
+                match parts.iter().find(|part| part.part_number == wanted.part_number) {
+                    Some(part) if part.sha256.eq_ignore_ascii_case(wanted.sha256.trim()) => selected.push(part.clone()),
+                    Some(_) => return Err(invalid_part(wanted.part_number, "The part does not match its sha256")),
+                    None => return Err(invalid_part(wanted.part_number, "The part was not uploaded")),
+                }
+            }
+            selected
+        }
+        None => parts,
+    };
+    if parts.is_empty() {
+        return Err(ApiError::BadRequest("The upload has no parts".to_string()));
+    }
+    let total = parts.iter().map(|part| part.size.max(0) as u64).sum::<u64>();
+    if total > limit {
+        return Err(api::too_large(limit));
+    }
+
+    let mut body = Vec::with_capacity(total as usize);
+    for part in &parts {
+        let contents = match read_part(config, upload_id, part).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                error!("Part read error {} {}: {}", upload_id, part.part_number, e);
+                return Err(invalid_part(part.part_number, "The part is missing, upload it again"));
+            }
+        };
+        if storage::content_hash(&contents) != part.sha256 {
+            return Err(invalid_part(part.part_number, "The part changed on the server, upload it again"));
+        }
+        body.extend_from_slice(&contents);
+    }
+
+    Ok(api::store_file(pool, config, ip, new_file, Bytes::from(body)).await?)
+}
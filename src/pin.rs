@@ -0,0 +1,121 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::changes;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::AuthUser;
+use crate::ids;
+
+/// Handler to put a file on hold
+/// This function pins a file of the requesting user, administrators can pin any file.
+/// A pinned file is never removed on its own: reaching the download limit refuses further downloads
+/// but keeps the file, its expiry time is ignored and the cleanup task skips it, even in the trash.
+/// The owner can still delete it.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" http://localhost:3000/file/<uuid>/pin
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn pin(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    Extension(config): Extension<data::Config>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received pin request for {} from IP: {}", uuid, ip);
+
+    set_pinned(&pool, &config, &uuid, &user, true, &ip).await
+}
+
+/// Handler to take a file off hold
+/// This function unpins a file of the requesting user, administrators can unpin any file.
+/// The rules that were held back apply again: a file that used up its downloads is trashed
+/// after `exhausted_grace_period`, an expired one is removed by the next cleanup run.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/file/<uuid>/pin
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn unpin(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    Extension(config): Extension<data::Config>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AuthUser { user, .. }: AuthUser,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received unpin request for {} from IP: {}", uuid, ip);
+
+    set_pinned(&pool, &config, &uuid, &user, false, &ip).await
+}
+
+/// Helper to pin or unpin a file of `user`, or any file if `user` is an administrator,
+/// and answer with the changed file.
+async fn set_pinned(
+    pool: &AnyPool,
+    config: &data::Config,
+    uuid: &str,
+    user: &data::User,
+    pinned: bool,
+    ip: &str,
+) -> Result<Response, ApiError> {
+    if !ids::is_valid_file_id(uuid) {
+        return Err(ids::invalid_file_id(uuid));
+    }
+    match api::find_file_any(pool, uuid).await {
+        Ok(Some(file)) if file.owner == user.username || user.is_admin() => {}
+        Ok(Some(file)) => {
+            warn!("User {} tried to pin file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        Ok(None) => return Err(ApiError::NotFound("File not found".to_string())),
+        Err(response) => return Err(response.into()),
+    }
+
+    let changed = match db::set_pinned(pool, uuid, pinned).await {
+        Ok(changed) => changed,
+        Err(e) => {
+            error!("DB pin error {}: {}", uuid, e);
+            return Err(db::error(&e, "Database update error"));
+        }
+    };
+    cache::forget_file(uuid).await;
+    changes::record(pool, &changed.owner, uuid, changes::Change::Updated).await;
+    let action = match pinned {
+        true => audit::Action::Pin,
+        false => audit::Action::Unpin,
+    };
+    info!("File {} pinned: {} by {}", uuid, pinned, user.username);
+    audit::record(pool, action, Some(&user.username), Some(uuid), ip).await;
+
+    // the download limit was held back while the file was pinned
+    if pinned || changed.is_trashed() {
+        return Ok(Json(changed).into_response());
+    }
+    api::finish_download(pool, config, &changed).await?;
+    match api::find_file_any(pool, uuid).await? {
+        Some(file) => Ok(Json(file).into_response()),
+        None => Err(ApiError::NotFound("File not found".to_string())),
+    }
+}
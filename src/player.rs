@@ -0,0 +1,216 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::data;
+use crate::error::ApiError;
+use crate::ids;
+use crate::reports;
+use crate::session;
+use crate::share;
+use crate::storage;
+use crate::throttle;
+
+/// How long the player of a page can seek in the file, in seconds.
+const GRANT_LIFETIME: i64 = 6 * 60 * 60;
+
+/// The page the player is shown in.
+const PLAY_HTML: &str = include_str!("web/play.html");
+
+/// Only lets the page play media from this server.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; media-src 'self'; style-src 'unsafe-inline'";
+
+/// Returns the content type a browser can play the file as,
+/// the uploaded one if it is audio or video or else the one detected from the contents.
+pub(crate) fn playable_type(file: &data::File) -> Option<&str> {
+    [Some(file.content_type.as_str()), file.detected_content_type.as_deref()]
+        .into_iter()
+        .flatten()
+        .find(|content_type| content_type.starts_with("video/") || content_type.starts_with("audio/"))
+}
+
+/// Returns a signed grant to stream the file until it expires.
+fn grant(uuid: &str) -> String {
+    session::sign(&format!("stream.{}.{}", uuid, Utc::now().timestamp() + GRANT_LIFETIME))
+}
+
+/// Returns true if the grant was signed by this server for the file and did not expire yet.
+fn grant_valid(grant: &str, uuid: &str) -> bool {
+    let Some(id) = session::verify(grant) else {
+        return false;
+    };
+    match id.strip_prefix("stream.").and_then(|id| id.rsplit_once('.')) {
+        Some((file_id, expires)) => {
+            file_id == uuid && expires.parse::<i64>().is_ok_and(|expires| expires > Utc::now().timestamp())
+        }
+        None => false,
+    }
+}
+
+/// Handler to play a video or audio file in the browser
+/// This function returns a page with a player for the file.
+/// Opening the page counts as one download of the file, after that the player can seek in the file
+/// with range requests for 6 hours without counting more downloads,
+/// even once the download limit moved the file to the trash.
+/// Private files need the same key or share token as a download.
+/// The grant is signed with the session secret, set `session_secret` so it works on every instance.
+/// It also logs the IP address of the client making the request.
+/// example request: curl http://localhost:3000/play/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following query parameter:
+/// - token: a share token of a private file (optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn play(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received play request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let file = match api::find_file(&pool, "id", &uuid).await? {
+        Some(file) => file,
+        None if reports::is_blocked(&pool, &uuid).await => return Err(reports::taken_down().into()),
+        None => return Err(ApiError::Gone("Download limit reached".to_string())),
+    };
+    if !file.is_available() {
+        return Err(api::gone(&file));
+    }
+    share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+
+    let element = match playable_type(&file) {
+        Some(content_type) if file.encrypted == 0 && content_type.starts_with("video/") => "video",
+        Some(_) if file.encrypted == 0 => "audio",
+        _ => {
+            return Err(ApiError::Rejected {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                error: "not_playable",
+                message: "Only video and audio files can be played".to_string(),
+                details: Value::Null,
+            });
+        }
+    };
+
+    // the page is the download, it is given back if the page is not sent to the end
+    let pending = match api::claim_download(&pool, &config, &file).await {
+        Ok(true) => api::PendingDownload::new(&pool, &config, &file, None, &ip, &headers),
+        Ok(false) => return Err(api::download_limit_reached(&file)),
+        Err(response) => return Err(response.into()),
+    };
+
+    let player = format!(
+        r#"<{element} controls preload="metadata" src="{base_path}/download/{uuid}?stream={grant}"></{element}>"#,
+        element = element,
+        base_path = config.base_path,
+        uuid = uuid,
+        grant = grant(&uuid),
+    );
+    let page = PLAY_HTML
+        .replace("{{hours}}", &(GRANT_LIFETIME / 3600).to_string())
+        .replace("{{player}}", &player)
+        .replace("{{title}}", &ammonia::clean_text(&file.file_name));
+    let length = page.len();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8".to_string()),
+            (header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY.to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        pending.body(Body::from(page), length),
+    )
+        .into_response())
+}
+
+/// Helper to send the file or a range of it to the player of a `/play/<uuid>` page
+/// The grant in the `stream` query parameter stands in for the access check and the download count,
+/// so seeking does not use up the download limit.
+/// The file is sent inline with its playable content type so the browser plays it instead of saving it.
+/// Only the requested range is read from disk, so seeking in a large video never loads the whole file.
+/// Blobs encrypted with the master key or stored compressed can only be read whole, see `storage::read_blob`.
+pub(crate) async fn send_stream(
+    config: &data::Config,
+    file: data::File,
+    headers: &HeaderMap,
+    grant: &str,
+) -> Result<Response, ApiError> {
+    if !grant_valid(grant, &file.id) {
+        warn!("Invalid or expired stream grant for {}", file.id);
+        return Err(ApiError::Forbidden("The player link expired, open the page again".to_string()));
+    }
+    let range = match headers.get(header::RANGE).and_then(|hv| hv.to_str().ok()) {
+        Some(value) => match api::byte_range(value, file.file_size as usize) {
+            Some(range) => Some(range),
+            None => return Err(ApiError::RangeNotSatisfiable(file.file_size)),
+        },
+        None => None,
+    };
+
+    let content_type = playable_type(&file).unwrap_or(&file.content_type).to_string();
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            api::content_disposition("inline", &file.file_name, &file.id),
+        )
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let plain = storage::blob_form(config, file.blob_name()).await.is_some_and(storage::Form::is_plain);
+    if plain {
+        let size = file.file_size.max(0) as u64;
+        let sent = range.map_or(0..size, |range| range.start as u64..range.end as u64);
+        let stream = match storage::stream_blob(config, file.blob_name(), sent.clone()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("File read error {}: {}", file.id, e);
+                return Err(ApiError::NotFound("File not found".to_string()));
+            }
+        };
+        let response = match sent.end - sent.start < size {
+            true => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", sent.start, sent.end - 1, size)),
+            false => response.status(StatusCode::OK),
+        };
+        let response = response.header(header::CONTENT_LENGTH, sent.end - sent.start);
+        return Ok(api::built(response.body(throttle::stream_body(stream)), &file.id));
+    }
+
+    let contents = match storage::read_cached_blob(config, file.blob_name(), file.file_size.max(0) as u64).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("File read error {}: {}", file.id, e);
+            return Err(ApiError::NotFound("File not found".to_string()));
+        }
+    };
+    let (response, contents) = match range {
+        Some(range) if range.end <= contents.len() => (
+            response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, contents.len()),
+            ),
+            contents.slice(range),
+        ),
+        _ => (response.status(StatusCode::OK), contents),
+    };
+    let response = response.header(header::CONTENT_LENGTH, contents.len());
+    Ok(api::built(response.body(throttle::body(contents)), &file.id))
+}
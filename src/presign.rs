@@ -0,0 +1,398 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Multipart, Path, Query, Request},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use base64::prelude::*;
+use chrono::Utc;
+use http_body_util::Limited;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::{AuthUser, UploadScope};
+use crate::session;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// How long an upload URL is valid if the user doesn't ask for another lifetime, in seconds.
+const DEFAULT_LIFETIME: u64 = 60 * 60;
+
+/// The longest an upload URL can be valid, in seconds.
+const MAX_LIFETIME: u64 = 7 * 24 * 60 * 60;
+
+/// The maximum number of allowed content types of an upload URL.
+const MAX_CONTENT_TYPES: usize = 50;
+
+/// What an upload URL allows, it is signed with the session secret and carried in the URL itself.
+#[derive(Serialize, Deserialize)]
+struct Grant {
+    /// The random ID of the URL, every URL can be used for a single upload.
+    id: String,
+    owner: String,
+    expires: i64,
+    max_size: Option<u64>,
+    content_types: Vec<String>,
+}
+
+/// Returns the signed grant as it is put in the upload URL.
+fn sign(grant: &Grant) -> Result<String, serde_json::Error> {
+    let payload = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(grant)?);
+    Ok(session::sign(&format!("upload.{}", payload)))
+}
+
+/// Returns the grant of an upload URL if it was signed by this server.
+fn verify(signed: &str) -> Option<Grant> {
+    let payload = session::verify(signed)?.strip_prefix("upload.")?;
+    let payload = BASE64_URL_SAFE_NO_PAD.decode(payload).ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+/// Handler to create a pre-signed upload URL
+/// This function returns a URL that lets anybody upload a single file into the account of the user
+/// without a key, for example a customer sending in a document.
+/// The size limit, the allowed content types and the expiry time are part of the signed URL,
+/// so they can't be changed by whoever holds it.
+/// The URL can't allow more than the key or token that created it: the content types must be ones the token allows,
+/// and the URL expires with the token at the latest.
+/// The URL stops working once a file was uploaded with it, once it expired, once the user is deleted
+/// or once the token or key that created it is revoked.
+/// The URL is signed with the session secret, set `session_secret` so it works on every instance
+/// and after a restart.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"max_size": 10485760, "content_types": ["application/pdf"], "expires_in": 86400}' http://localhost:3000/upload/presign
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+///
+/// accepts the following JSON body:
+/// - max_size: the maximum size of the upload in bytes, at most the upload limit of the user (optional)
+/// - content_types: the content types the upload may have, `image/*` allows a whole group,
+///   those of the token if missing (optional)
+/// - expires_in: the number of seconds the URL is valid, at most 7 days, 1 hour if missing (optional)
+#[instrument(skip_all)]
+pub async fn presign(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    AuthUser { user, token, .. }: AuthUser<UploadScope>,
+    headers: HeaderMap,
+    Json(request): Json<data::PresignRequest>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a presign request from {} from IP: {}", user.username, ip);
+
+    if !user.is_verified() {
+        return Err(api::unverified(&user));
+    }
+    // the upload would land outside of the collection the token is bound to
+    if tokens::collection(token.as_ref()).is_some() {
+        return Err(ApiError::Forbidden("Collection tokens can't presign uploads".to_string()));
+    }
+    // the URL can't allow more than the key that created it
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let max_size = match request.max_size {
+        Some(0) => return Err(ApiError::BadRequest("max_size must be positive".to_string())),
+        Some(max_size) if max_size > limit => return Err(api::too_large(limit)),
+        max_size => max_size,
+    };
+    let content_types = request.content_types.unwrap_or_default();
+    if content_types.len() > MAX_CONTENT_TYPES || content_types.iter().any(|t| !t.contains('/') || t.contains(',')) {
+        return Err(ApiError::BadRequest(
+            "Invalid content types, use types like image/png or image/*".to_string(),
+        ));
+    }
+    let expires_in = request.expires_in.unwrap_or(DEFAULT_LIFETIME);
+    if !(1..=MAX_LIFETIME).contains(&expires_in) {
+        return Err(ApiError::BadRequest(format!(
+            "expires_in must be between 1 and {} seconds",
+            MAX_LIFETIME
+        )));
+    }
+    let content_types: Vec<String> = content_types.iter().map(|t| t.trim().to_ascii_lowercase()).collect();
+    let allowed = tokens::allowed_content_types(token.as_ref());
+    let wider = content_types
+        .iter()
+        .find(|content_type| !allowed.is_empty() && !tokens::content_type_matches(&allowed, content_type));
+    if let Some(wider) = wider {
+        return Err(ApiError::Forbidden(format!("The token does not allow uploads of {}", wider)));
+    }
+    // without content types of its own the URL allows those of the token
+    let content_types = match content_types.is_empty() {
+        true => allowed,
+        false => content_types,
+    };
+    let expires = Utc::now().timestamp().saturating_add(expires_in as i64);
+    let expires = match token.as_ref().and_then(|token| token.expires) {
+        Some(token_expires) => expires.min(token_expires),
+        None => expires,
+    };
+    // the URL dies with the token or key that created it, a session has neither
+    let key_id = match (&token, headers.get("key").and_then(|hv| hv.to_str().ok())) {
+        (None, Some(key)) => db::api_key_id(&pool, key).await.map_err(|e| {
+            error!("DB select key error {}: {}", user.username, e);
+            db::error(&e, "Database select error")
+        })?,
+        _ => None,
+    };
+
+    let grant = Grant {
+        id: hex::encode(rand::rng().random::<[u8; 16]>()),
+        owner: user.username,
+        expires,
+        max_size,
+        content_types,
+    };
+    let signed = match sign(&grant) {
+        Ok(signed) => signed,
+        Err(e) => {
+            error!("Could not encode the upload grant of {}: {}", grant.owner, e);
+            return Err(ApiError::Internal("Could not create the upload URL"));
+        }
+    };
+    // the row is what makes the URL single use, it goes away with the user
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO presigned_uploads
+            (id, username, expires, token_id, key_id)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&grant.id)
+    .bind(&grant.owner)
+    .bind(grant.expires)
+    .bind(token.as_ref().map(|token| token.id.as_str()))
+    .bind(&key_id)
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert presigned upload error {}: {}", grant.owner, e);
+        return Err(db::error(&e, "Database insert error"));
+    }
+    info!("Upload URL {} created for {}, valid until {}", grant.id, grant.owner, grant.expires);
+    audit::record(&pool, audit::Action::PresignUpload, Some(&grant.owner), None, &ip).await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(data::PresignedUpload {
+            url: api::public_url(&config, &format!("upload/presigned/{}", signed)),
+            expires: grant.expires,
+            max_size: grant.max_size,
+            content_types: grant.content_types,
+        }),
+    )
+        .into_response())
+}
+
+/// Handler to upload a file with a pre-signed upload URL
+/// This function stores a file like `/upload` does, but needs no key,
+/// the file belongs to the user who created the URL and gets their default download limit,
+/// expiry and visibility.
+/// The upload is refused if it is larger than the URL allows or its content type is not allowed,
+/// an URL that was already used, expired or whose token or key was revoked answers with 410.
+/// If the upload fails the URL can be used again.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "file_name: <file_name>" -H "content-type: <content_type>" --data-binary @<file_path> http://localhost:3000/upload/presigned/<signed>
+/// example multipart request: curl -X POST -F "file=@<file_path>" http://localhost:3000/upload/presigned/<signed>
+/// requires the following path parameter:
+/// - signed: the signed part of the URL returned by `/upload/presign` (not optional)
+///
+/// requires the following headers:
+/// - file_name: the name of the file (optional, taken from the part for multipart)
+/// - content-type: the content type of the file (optional, taken from the part for multipart)
+/// - accept: `text/plain` to get only the download URL back instead of JSON (optional)
+///
+/// accepts the following query parameters:
+/// - format: `txt` to get only the download URL back instead of JSON (optional)
+#[instrument(skip_all)]
+pub async fn upload_presigned(
+    Path(signed): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a presigned upload from IP: {}", ip);
+
+    let Some(grant) = verify(&signed) else {
+        warn!("Presigned upload with an invalid signature from IP: {}", ip);
+        return Err(ApiError::Forbidden("Invalid upload URL".to_string()));
+    };
+    if grant.expires <= Utc::now().timestamp() {
+        return Err(ApiError::Gone("The upload URL expired".to_string()));
+    }
+    let user = match db::find_user(&pool, &grant.owner).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(ApiError::Gone("The upload URL was revoked".to_string())),
+        Err(e) => {
+            error!("DB select user error {}: {}", grant.owner, e);
+            return Err(db::error(&e, "Database select error"));
+        }
+    };
+    if creator_revoked(&pool, &grant).await? {
+        return Err(ApiError::Gone("The upload URL was revoked".to_string()));
+    }
+
+    // the limit of the user may have been lowered since the URL was created
+    let limit = grant
+        .max_size
+        .unwrap_or(u64::MAX)
+        .min(api::upload_limit(&config, &user, None));
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return Err(api::too_large(limit));
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+
+    if !claim(&pool, &grant).await? {
+        return Err(ApiError::Gone("The upload URL was already used".to_string()));
+    }
+    let uploaded = upload(&pool, &config, &ip, &headers, &grant, &user, request).await;
+    let uploaded_file = match uploaded {
+        Ok(uploaded_file) => uploaded_file,
+        Err(response) => {
+            // a failed upload doesn't use up the URL
+            release(&pool, &grant).await;
+            return Err(response.into());
+        }
+    };
+    let file = &uploaded_file.file;
+    info!("Presigned upload {} stored {} for {}", grant.id, file.id, file.owner);
+    audit::record(&pool, audit::Action::Upload, Some(&file.owner), Some(&file.id), &ip).await;
+    Ok(api::upload_response(&headers, &query, uploaded_file))
+}
+
+/// Helper to read and store the upload of a presigned URL for its owner.
+async fn upload(
+    pool: &AnyPool,
+    config: &data::Config,
+    ip: &str,
+    headers: &HeaderMap,
+    grant: &Grant,
+    user: &data::User,
+    request: Request,
+) -> Result<data::UploadedFile, Response> {
+    let mut new_file = data::NewFile {
+        file_name: headers
+            .get("file_name")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+        content_type: headers
+            .get("content-type")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+        download_limit: api::default_download_limit(config, user),
+        owner: user.username.clone(),
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility: api::default_visibility(user),
+        allowed_content_types: grant.content_types.clone(),
+        expires: api::default_expires(user),
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: None,
+        description: None,
+    };
+
+    let body = if new_file.content_type.starts_with("multipart/form-data") {
+        let multipart = Multipart::from_request(request, &()).await.map_err(|e| {
+            warn!("Multipart parse error: {}", e);
+            e.into_response()
+        })?;
+        api::read_multipart(multipart, &mut new_file).await?
+    } else {
+        Bytes::from_request(request, &()).await.map_err(|e| {
+            warn!("Body read error: {}", e);
+            e.into_response()
+        })?
+    };
+    // the one uploading only chooses the contents, the owner decides the rest
+    new_file.download_limit = api::default_download_limit(config, user);
+    new_file.visibility = api::default_visibility(user);
+    new_file.encrypted = false;
+    new_file.tags.clear();
+
+    api::store_file(pool, config, ip, new_file, body).await
+}
+
+/// Helper to tell whether the token or key that created an upload URL was revoked since,
+/// or the URL is gone with its user.
+async fn creator_revoked(pool: &AnyPool, grant: &Grant) -> Result<bool, ApiError> {
+    let select_error = |e: sqlx::Error| {
+        error!("DB select presigned upload error {}: {}", grant.id, e);
+        db::error(&e, "Database select error")
+    };
+    let creator = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+        "SELECT token_id, key_id FROM presigned_uploads WHERE id = ?",
+    )
+    .bind(&grant.id)
+    .fetch_optional(pool)
+    .await
+    .map_err(select_error)?;
+    let Some((token_id, key_id)) = creator else {
+        return Ok(true);
+    };
+    if let Some(token_id) = token_id {
+        if tokens::is_revoked(pool, &token_id).await.map_err(select_error)? {
+            return Ok(true);
+        }
+    }
+    match key_id {
+        Some(key_id) => db::api_key_revoked(pool, &key_id).await.map_err(select_error),
+        None => Ok(false),
+    }
+}
+
+/// Helper to mark an upload URL as used.
+/// It returns false if the URL was already used or its user was deleted.
+async fn claim(pool: &AnyPool, grant: &Grant) -> Result<bool, ApiError> {
+    sqlx::query(
+        r#"
+        UPDATE presigned_uploads
+        SET used_at = ?
+        WHERE id = ? AND username = ? AND used_at IS NULL
+        "#,
+    )
+    .bind(Utc::now().timestamp())
+    .bind(&grant.id)
+    .bind(&grant.owner)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        error!("DB update presigned upload error {}: {}", grant.id, e);
+        db::error(&e, "Database update error")
+    })
+}
+
+/// Helper to make an upload URL usable again after its upload failed.
+async fn release(pool: &AnyPool, grant: &Grant) {
+    if let Err(e) = sqlx::query("UPDATE presigned_uploads SET used_at = NULL WHERE id = ?")
+        .bind(&grant.id)
+        .execute(pool)
+        .await
+    {
+        error!("DB update presigned upload error {}: {}", grant.id, e);
+    }
+}
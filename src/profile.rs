@@ -0,0 +1,245 @@
+use axum::{
+    extract::ConnectInfo,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use lettre::message::Mailbox;
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::share;
+
+/// Returns the settings of a user as shown at `/user/me`.
+fn profile(user: &data::User) -> data::Profile {
+    data::Profile {
+        username: user.username.clone(),
+        email: user.email.clone(),
+        email_verified: user.is_verified(),
+        display_email: user.display_email.clone(),
+        is_admin: user.is_admin(),
+        default_download_limit: user.default_download_limit,
+        default_expiry: user.default_expiry,
+        default_visibility: user.default_visibility.clone(),
+        removal_notices: user.removal_notices != 0,
+    }
+}
+
+/// Handler to show the settings of a user
+/// This function returns the account of the user with the defaults their uploads get
+/// and whether they are emailed about files the retention policy removed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/user/me
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[utoipa::path(
+    get,
+    path = "/user/me",
+    tag = "users",
+    responses(
+        (status = 200, description = "The settings of the user", body = data::Profile),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn get_profile(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a profile request from IP: {}", ip);
+
+    let user = api::authenticate(&pool, &headers, &ip).await?;
+    Ok(Json(profile(&user)).into_response())
+}
+
+/// Handler to change the settings of a user
+/// This function changes the fields of the body and leaves the others as they are,
+/// a null resets a setting to the server default.
+/// The defaults apply to uploads through `/upload`, `/upload/batch`, `/upload/remote`
+/// and `/upload/multipart` that don't set their own download limit, expiry or visibility.
+/// The email address of the account is changed by registering it again,
+/// `display_email` is only shown here.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PATCH -H "key: <key>" -H "content-type: application/json" -d '{"default_download_limit": 5, "default_expiry": 604800, "default_visibility": "private"}' http://localhost:3000/user/me
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// accepts the following JSON body:
+/// - display_email: an email address to show with the account (optional)
+/// - default_download_limit: the download limit of new uploads, 0 for unlimited (optional)
+/// - default_expiry: the seconds after which new uploads expire (optional)
+/// - default_visibility: `public` or `private` for new uploads (optional)
+/// - removal_notices: false to stop the emails about files the retention policy removed (optional)
+#[utoipa::path(
+    patch,
+    path = "/user/me",
+    tag = "users",
+    request_body = data::ProfileUpdate,
+    responses(
+        (status = 200, description = "The changed settings of the user", body = data::Profile),
+        (status = 400, description = "A setting is invalid"),
+        (status = 401, description = "The key is invalid"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn update_profile(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    Json(request): Json<data::ProfileUpdate>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received a profile update from IP: {}", ip);
+
+    let mut user = api::authenticate(&pool, &headers, &ip).await?;
+
+    if let Some(display_email) = request.display_email {
+        user.display_email = match display_email.as_deref().map(str::trim) {
+            Some("") | None => None,
+            Some(email) => match email.parse::<Mailbox>() {
+                Ok(_) => Some(email.to_string()),
+                Err(e) => {
+                    warn!("Invalid display email {}: {}", email, e);
+                    return Err(ApiError::BadRequest("Invalid email address".to_string()));
+                }
+            },
+        };
+    }
+    if let Some(default_download_limit) = request.default_download_limit {
+        if let Some(limit) = default_download_limit.filter(|limit| !api::download_limit_allowed(&config, *limit)) {
+            return Err(api::invalid_download_limit(&config, limit));
+        }
+        user.default_download_limit = default_download_limit;
+    }
+    if let Some(default_expiry) = request.default_expiry {
+        if default_expiry.is_some_and(|expiry| expiry <= 0) {
+            return Err(ApiError::BadRequest(
+                "default_expiry must be a positive number of seconds".to_string(),
+            ));
+        }
+        user.default_expiry = default_expiry;
+    }
+    if let Some(default_visibility) = request.default_visibility {
+        user.default_visibility = match default_visibility.as_deref().map(share::parse_visibility) {
+            Some(Some(visibility)) => Some(visibility),
+            Some(None) => return Err(share::invalid_visibility().into()),
+            None => None,
+        };
+    }
+    if let Some(removal_notices) = request.removal_notices {
+        user.removal_notices = removal_notices as i32;
+    }
+
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE users
+        SET display_email = ?, default_download_limit = ?, default_expiry = ?, default_visibility = ?,
+            removal_notices = ?
+        WHERE "key" = ?
+        "#,
+    )
+    .bind(&user.display_email)
+    .bind(user.default_download_limit)
+    .bind(user.default_expiry)
+    .bind(&user.default_visibility)
+    .bind(user.removal_notices)
+    .bind(&user.key)
+    .execute(&pool)
+    .await
+    {
+        error!("DB update error {}: {}", user.username, e);
+        return Err(db::error(&e, "Database update error"));
+    }
+    cache::forget_user(&pool, &user.username).await;
+    info!("Profile of {} updated", user.username);
+    audit::record(&pool, audit::Action::UpdateProfile, Some(&user.username), None, &ip).await;
+
+    Ok(Json(profile(&user)).into_response())
+}
+
+/// Handler to delete the account of a user
+/// This function removes every file of the user from storage, their collections, tokens, sessions and settings,
+/// deletes the account and anonymizes the entries of the user in the audit log.
+/// The password of the account has to be sent along, so a leaked key alone can't delete it.
+/// It returns a receipt of what was removed, the account can't be restored afterwards,
+/// `/my_files/archive` downloads a copy of the files before.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" -H "password: <password>" http://localhost:3000/user/me
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+/// - password: the password of the user (not optional)
+#[utoipa::path(
+    delete,
+    path = "/user/me",
+    tag = "users",
+    params(("password" = String, Header, description = "The password of the user")),
+    responses(
+        (status = 200, description = "The receipt of the deleted account", body = data::DeletionReceipt),
+        (status = 400, description = "The password is missing"),
+        (status = 401, description = "The key is invalid"),
+        (status = 403, description = "The password is wrong"),
+    ),
+    security(("key" = []))
+)]
+#[instrument(skip_all)]
+pub async fn delete_account(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an account deletion request from IP: {}", ip);
+
+    let user = api::authenticate(&pool, &headers, &ip).await?;
+    let Some(password) = headers.get("password").and_then(|hv| hv.to_str().ok()) else {
+        return Err(ApiError::BadRequest("Password required".to_string()));
+    };
+    if password != user.password {
+        warn!("Wrong password for the deletion of {} from {}", user.username, ip);
+        audit::record_failed_login(&pool, Some(&user.username), None, &ip);
+        return Err(ApiError::Forbidden("Wrong password".to_string()));
+    }
+
+    let files = match admin::remove_user(&pool, &config, &user.username).await? {
+        Some(files) => files,
+        // a concurrent request deleted the account first
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+    let audit_entries_anonymized = match audit::anonymize(&pool, &user.username).await {
+        Ok(count) => count,
+        Err(e) => {
+            error!("DB anonymize audit log error {}: {}", user.username, e);
+            return Err(db::error(&e, "Database update error"));
+        }
+    };
+    info!("Account {} deleted with {} files", user.username, files.len());
+    // the deletion itself is logged without the name of the account
+    audit::record(&pool, audit::Action::DeleteAccount, None, None, "").await;
+
+    Ok(Json(data::DeletionReceipt {
+        username: user.username,
+        deleted_at: Utc::now().timestamp(),
+        bytes_deleted: files.iter().map(|file| file.file_size).sum(),
+        files: files.into_iter().map(|file| file.id).collect(),
+        audit_entries_anonymized,
+    })
+    .into_response())
+}
@@ -0,0 +1,138 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures_util::StreamExt;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::api;
+use std::net::SocketAddr;
+
+/// How long the progress of a finished upload can still be queried.
+const KEEP_FINISHED: Duration = Duration::from_secs(60);
+
+/// This struct represents the progress of one upload.
+/// `expected` is the announced size of the body, `None` if the client didn't announce it.
+struct Progress {
+    received: AtomicU64,
+    expected: Option<u64>,
+    started: Instant,
+    finished: Mutex<Option<Instant>>,
+}
+
+/// An upload is identified by its owner and the upload ID, so users can't see or block each other's uploads.
+type UploadKey = (String, String);
+
+/// The uploads in flight and the recently finished ones.
+/// Progress lives in the memory of the instance that receives the upload,
+/// behind a load balancer the progress has to be asked from the same instance.
+static UPLOADS: LazyLock<Mutex<HashMap<UploadKey, Arc<Progress>>>> = LazyLock::new(Default::default);
+
+/// Returns true if an upload ID is 1 to 64 letters, digits, '-' or '_'.
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// This struct counts the bytes of an upload body while it is read.
+/// The upload counts as finished once the body was read or dropped.
+pub(crate) struct Tracker {
+    progress: Arc<Progress>,
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        *self.progress.finished.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+impl Tracker {
+    /// Returns the body that counts its bytes into the progress of the upload.
+    pub(crate) fn wrap(self, body: Body) -> Body {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                self.progress.received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }
+            chunk
+        }))
+    }
+}
+
+/// This function starts tracking the progress of an upload with a client chosen ID.
+/// It returns `None` if an upload of the user with the same ID is still running.
+/// Finished uploads are forgotten after `KEEP_FINISHED`.
+pub(crate) fn track(id: &str, owner: &str, expected: Option<u64>) -> Option<Tracker> {
+    let mut uploads = UPLOADS.lock().unwrap();
+    uploads.retain(|_, progress| {
+        progress.finished.lock().unwrap().is_none_or(|finished| finished.elapsed() < KEEP_FINISHED)
+    });
+    let key = (owner.to_string(), id.to_string());
+    if uploads.get(&key).is_some_and(|progress| progress.finished.lock().unwrap().is_none()) {
+        return None;
+    }
+    let progress = Arc::new(Progress {
+        received: AtomicU64::new(0),
+        expected,
+        started: Instant::now(),
+        finished: Mutex::new(None),
+    });
+    uploads.insert(key, progress.clone());
+    debug!("Tracking the progress of upload {}", id);
+    Some(Tracker { progress })
+}
+
+/// Handler to return the progress of an upload
+/// This function returns how many bytes of an upload the server received so far
+/// and how many the client announced, so progress can be shown for uploads
+/// whose client side progress is hidden by a proxy.
+/// The upload has to be started with an `upload_id` header, the progress can be queried
+/// while it runs and for a minute after the server received the whole body.
+/// `expected_bytes` is null if the client didn't send a Content-Length.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/upload/<upload_id>/progress
+/// requires the following headers:
+/// - key: the key of the user that started the upload (not optional)
+///
+/// requires the following path parameter:
+/// - upload_id: the `upload_id` header of the upload (not optional)
+#[instrument(skip_all, fields(upload_id = %upload_id))]
+pub async fn upload_progress(
+    Path(upload_id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received progress request for {} from IP: {}", upload_id, ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let progress = UPLOADS
+        .lock()
+        .unwrap()
+        .get(&(user.username, upload_id.clone()))
+        .cloned();
+    let Some(progress) = progress else {
+        return (StatusCode::NOT_FOUND, "Upload not found").into_response();
+    };
+
+    Json(json!({
+        "upload_id": upload_id,
+        "received_bytes": progress.received.load(Ordering::Relaxed),
+        "expected_bytes": progress.expected,
+        "finished": progress.finished.lock().unwrap().is_some(),
+        "elapsed_seconds": progress.started.elapsed().as_secs(),
+    }))
+    .into_response()
+}
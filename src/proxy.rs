@@ -0,0 +1,194 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// This struct is a range of IP addresses like `10.0.0.0/8`, or a single address.
+#[derive(Clone, Debug)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// Parses an address with an optional prefix length like `192.168.0.0/16` or `::1`.
+    pub fn parse(value: &str) -> Option<Network> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (value.parse::<IpAddr>().ok()?, None),
+        };
+        let addr = addr.to_canonical();
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Network { addr, prefix })
+    }
+
+    /// Returns true if the address is in the range.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the addresses a request passed through according to its proxy headers,
+/// the client first and the last proxy at the end.
+/// The standard `Forwarded` header wins over `X-Forwarded-For`.
+/// Entries that are not an IP address, like `unknown` or obfuscated names, are kept as `None`.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+            })
+            .collect();
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Returns the IP address of a forwarded node like `203.0.113.7`, `203.0.113.7:4711`,
+/// `2001:db8::1` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(addr, _)| addr.parse().ok());
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+/// This middleware replaces the address of a trusted reverse proxy with the address of the client it forwards.
+/// The forwarded addresses are followed from the proxy back towards the client
+/// as long as every hop is trusted, so clients can't forge their address
+/// by sending proxy headers themselves.
+/// Handlers, rate limits, the audit log and the logs all see the result as `ConnectInfo`.
+/// Requests from anyone else keep the address of the connection.
+pub async fn real_client_ip(
+    State(trusted): State<Arc<[Network]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(peer)) if !trusted.is_empty() => *peer,
+        _ => return next.run(request).await,
+    };
+    let is_trusted = |addr: &IpAddr| trusted.iter().any(|network| network.contains(addr));
+
+    let mut client = peer.ip().to_canonical();
+    for node in forwarded_chain(request.headers()).into_iter().rev() {
+        if !is_trusted(&client) {
+            break;
+        }
+        match node {
+            Some(addr) => client = addr.to_canonical(),
+            None => break,
+        }
+    }
+    if client != peer.ip() {
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(client, 0)));
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forwarded_chain, Network};
+    use axum::http::{HeaderMap, HeaderValue};
+    use std::net::IpAddr;
+
+    fn contains(network: &str, ip: &str) -> bool {
+        Network::parse(network).unwrap().contains(&ip.parse::<IpAddr>().unwrap())
+    }
+
+    fn chain(name: &'static str, value: &'static str) -> Vec<Option<IpAddr>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        forwarded_chain(&headers)
+    }
+
+    #[test]
+    fn matches_ipv4_ranges() {
+        assert!(contains("10.0.0.0/8", "10.255.1.2"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.168.1.0/24", "192.168.1.200"));
+        assert!(!contains("192.168.1.0/24", "192.168.2.1"));
+        assert!(contains("172.16.0.0/12", "172.31.255.255"));
+        assert!(!contains("172.16.0.0/12", "172.32.0.0"));
+    }
+
+    #[test]
+    fn matches_single_addresses_and_everything() {
+        assert!(contains("203.0.113.7", "203.0.113.7"));
+        assert!(!contains("203.0.113.7", "203.0.113.8"));
+        assert!(contains("0.0.0.0/0", "198.51.100.1"));
+        assert!(contains("::/0", "2001:db8::1"));
+    }
+
+    #[test]
+    fn matches_ipv6_ranges() {
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(contains("::1", "::1"));
+        assert!(!contains("2001:db8::/32", "10.0.0.1"));
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses_as_ipv4() {
+        assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+        assert!(contains("::ffff:10.0.0.0/8", "10.1.2.3"));
+    }
+
+    #[test]
+    fn refuses_invalid_networks() {
+        for network in ["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0/8", "localhost", ""] {
+            assert!(Network::parse(network).is_none(), "{} should be invalid", network);
+        }
+    }
+
+    #[test]
+    fn reads_the_chain_of_x_forwarded_for() {
+        let chain = chain("x-forwarded-for", "203.0.113.7, unknown, 10.0.0.2:4711");
+        let expected = vec![Some("203.0.113.7".parse().unwrap()), None, Some("10.0.0.2".parse().unwrap())];
+        assert_eq!(chain, expected);
+    }
+
+    #[test]
+    fn reads_the_chain_of_forwarded() {
+        let chain = chain("forwarded", "for=203.0.113.7;proto=https, For=\"[2001:db8::1]:4711\", for=_hidden");
+        let expected = vec![Some("203.0.113.7".parse().unwrap()), Some("2001:db8::1".parse().unwrap()), None];
+        assert_eq!(chain, expected);
+    }
+}
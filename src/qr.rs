@@ -0,0 +1,69 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use qrcode::{render::svg, QrCode};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument};
+
+use crate::api;
+use crate::ids;
+use crate::reports;
+use std::net::SocketAddr;
+
+/// The smallest size of the QR code in pixels, scanners struggle with less.
+const MIN_SIZE: u32 = 256;
+
+/// Handler to return a QR code of the download link of a file
+/// This function returns an SVG QR code of the download URL,
+/// so a file can be opened on a phone by pointing its camera at the screen.
+/// It only encodes the link, so it neither counts as a download nor checks access to private files.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/qr/<uuid> -o qr.svg
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn qr_code(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received QR code request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+    let file = match api::find_file(&pool, "id", &uuid).await {
+        Ok(Some(file)) => file,
+        Ok(None) if reports::is_blocked(&pool, &uuid).await => return reports::taken_down(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(response) => return response,
+    };
+
+    let code = match QrCode::new(file.download_url.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            error!("QR code error {}: {}", uuid, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "QR code error").into_response();
+        }
+    };
+    let image = code
+        .render::<svg::Color>()
+        .min_dimensions(MIN_SIZE, MIN_SIZE)
+        .build();
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            // the link of a file never changes
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        image,
+    )
+        .into_response()
+}
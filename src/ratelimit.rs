@@ -0,0 +1,197 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::{clock::Clock, DefaultKeyedRateLimiter, Quota, RateLimiter};
+use std::num::NonZeroU32;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use crate::data;
+use crate::error::ApiError;
+use std::net::SocketAddr;
+
+/// How often idle clients are forgotten so the limiter state doesn't grow forever.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// This struct is one rate limit budget that can be put in front of routes.
+/// Clients are told by their IP address, or by their API key if `by_key` is set
+/// and the request carries one.
+/// The budget can be changed while the server runs, see `set_per_minute`.
+#[derive(Clone)]
+pub struct RateLimit {
+    budget: Arc<RwLock<Budget>>,
+    by_key: bool,
+    name: &'static str,
+}
+
+/// This struct is the current limit of a budget, `None` if it is disabled.
+struct Budget {
+    requests: u32,
+    limiter: Option<Arc<DefaultKeyedRateLimiter<String>>>,
+}
+
+/// This struct holds the separate budgets of the server.
+#[derive(Clone)]
+pub struct RateLimits {
+    /// Anonymous downloads, counted per IP address.
+    pub downloads: RateLimit,
+    /// Authenticated uploads, counted per API key.
+    pub uploads: RateLimit,
+    /// User registration, counted per IP address.
+    pub register: RateLimit,
+    /// Abuse reports, counted per IP address.
+    pub reports: RateLimit,
+    /// Searches of the public directory, counted per IP address.
+    pub public: RateLimit,
+}
+
+impl RateLimits {
+    /// Builds the budgets from the config, a limit of 0 disables a budget.
+    pub fn from_config(config: &data::Config) -> Self {
+        RateLimits {
+            downloads: RateLimit::per_minute("downloads", config.rate_limit_downloads, false),
+            uploads: RateLimit::per_minute("uploads", config.rate_limit_uploads, true),
+            register: RateLimit::per_minute("register", config.rate_limit_register, false),
+            reports: RateLimit::per_minute("reports", config.rate_limit_reports, false),
+            public: RateLimit::per_minute("public", config.rate_limit_public, false),
+        }
+    }
+
+    /// Changes the budgets to the limits of the config,
+    /// budgets whose limit stayed the same keep what their clients used up.
+    pub fn update(&self, config: &data::Config) {
+        self.downloads.set_per_minute(config.rate_limit_downloads);
+        self.uploads.set_per_minute(config.rate_limit_uploads);
+        self.register.set_per_minute(config.rate_limit_register);
+        self.reports.set_per_minute(config.rate_limit_reports);
+        self.public.set_per_minute(config.rate_limit_public);
+    }
+}
+
+impl RateLimit {
+    /// Builds a budget that allows `requests` requests per minute for every client.
+    fn per_minute(name: &'static str, requests: u32, by_key: bool) -> Self {
+        RateLimit {
+            budget: Arc::new(RwLock::new(Budget::new(requests))),
+            by_key,
+            name,
+        }
+    }
+
+    /// Changes the budget to `requests` requests per minute, 0 disables it.
+    /// Clients start with a full budget if the limit changed.
+    fn set_per_minute(&self, requests: u32) {
+        let mut budget = self.budget.write().unwrap();
+        if budget.requests != requests {
+            info!("Rate limit {} changed from {} to {} requests per minute", self.name, budget.requests, requests);
+            *budget = Budget::new(requests);
+        }
+    }
+
+    /// Returns the limiter of the budget, `None` if it is disabled.
+    fn limiter(&self) -> Option<Arc<DefaultKeyedRateLimiter<String>>> {
+        self.budget.read().unwrap().limiter.clone()
+    }
+}
+
+impl Budget {
+    /// Builds a limiter for `requests` requests per minute
+    /// that forgets idle clients until it is replaced.
+    fn new(requests: u32) -> Self {
+        let limiter = NonZeroU32::new(requests).map(|requests| {
+            let limiter = Arc::new(RateLimiter::keyed(Quota::per_minute(requests)));
+            let cleanup = Arc::downgrade(&limiter);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    match cleanup.upgrade() {
+                        Some(limiter) => limiter.retain_recent(),
+                        None => return,
+                    }
+                }
+            });
+            limiter
+        });
+        Budget { requests, limiter }
+    }
+}
+
+/// This middleware rejects requests that exceed the rate limit of their client.
+/// Rejected requests get a 429 with a `Retry-After` header in seconds.
+pub async fn limit(
+    State(rate_limit): State<RateLimit>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = rate_limit.limiter() else {
+        return next.run(request).await;
+    };
+
+    let api_key = request
+        .headers()
+        .get("key")
+        .and_then(|hv| hv.to_str().ok())
+        .filter(|_| rate_limit.by_key);
+    let client = match api_key {
+        Some(key) => format!("key:{}", key),
+        None => format!("ip:{}", addr.ip()),
+    };
+
+    if let Err(not_until) = limiter.check_key(&client) {
+        let wait = not_until.wait_time_from(limiter.clock().now());
+        // round up so clients never retry too early
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        warn!(
+            "Rate limit {} exceeded by {}, retry after {}s",
+            rate_limit.name,
+            addr.ip(),
+            retry_after
+        );
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Too many requests",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// This struct caps how many requests behind it run at the same time.
+/// A limit of 0 lets every request through.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    permits: Option<Arc<Semaphore>>,
+    name: &'static str,
+}
+
+impl ConcurrencyLimit {
+    /// Builds a cap that lets `limit` requests run at once.
+    pub fn new(name: &'static str, limit: usize) -> Self {
+        ConcurrencyLimit {
+            permits: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+            name,
+        }
+    }
+}
+
+/// This middleware rejects requests while the concurrency limit is reached,
+/// instead of queueing them with their connections and bodies held open.
+/// Rejected requests get a 503 with a `Retry-After` header in seconds.
+pub async fn limit_concurrency(State(limit): State<ConcurrencyLimit>, request: Request, next: Next) -> Response {
+    let Some(permits) = &limit.permits else {
+        return next.run(request).await;
+    };
+    let Ok(_permit) = permits.clone().try_acquire_owned() else {
+        warn!("Concurrency limit {} reached, refusing the request", limit.name);
+        return ApiError::Busy.into_response();
+    };
+    next.run(request).await
+}
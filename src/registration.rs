@@ -0,0 +1,116 @@
+use axum::http::StatusCode;
+use lettre::message::Mailbox;
+use serde_json::{json, Value};
+
+use crate::data;
+use crate::error::ApiError;
+
+/// The longest username the `users` table holds.
+pub(crate) const MAX_USERNAME_LENGTH: usize = 255;
+
+/// The values `username_charset` accepts.
+pub(crate) const CHARSETS: [&str; 3] = ["any", "ascii", "alphanumeric"];
+
+/// This struct represents a registration the policy refuses, it becomes a 422 with `details`.
+#[derive(Debug)]
+pub(crate) struct Refusal {
+    error: &'static str,
+    message: String,
+    details: Value,
+}
+
+impl From<Refusal> for ApiError {
+    fn from(refusal: Refusal) -> Self {
+        ApiError::Rejected {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            error: refusal.error,
+            message: refusal.message,
+            details: refusal.details,
+        }
+    }
+}
+
+/// Helper to build the refusal of a username, `reason` tells clients which rule it broke.
+fn invalid_username(config: &data::Config, reason: &str, message: String) -> Refusal {
+    Refusal {
+        error: "invalid_username",
+        message,
+        details: json!({
+            "reason": reason,
+            "min_length": config.username_min_length,
+            "max_length": config.username_max_length,
+            "charset": config.username_charset,
+        }),
+    }
+}
+
+/// Returns whether the character may be part of a username of the charset.
+/// `ascii` allows printable ASCII without spaces, `alphanumeric` letters, digits, `-`, `_` and `.`,
+/// `any` everything but control characters.
+fn allowed_char(charset: &str, c: char) -> bool {
+    match charset {
+        "ascii" => c.is_ascii_graphic(),
+        "alphanumeric" => c.is_ascii_alphanumeric() || "-_.".contains(c),
+        _ => !c.is_control(),
+    }
+}
+
+/// This function checks a new username against `username_min_length`, `username_max_length`,
+/// `username_charset` and `reserved_usernames`, reserved names are compared ignoring case.
+/// The `admin_users` are reserved too, otherwise whoever signs up first with one of them
+/// would be promoted to admin, those accounts are created with `bitbeam user create` instead.
+pub(crate) fn check_username(config: &data::Config, username: &str) -> Result<(), Refusal> {
+    let length = username.chars().count();
+    if length < config.username_min_length {
+        return Err(invalid_username(
+            config,
+            "too_short",
+            format!("Usernames have at least {} characters", config.username_min_length),
+        ));
+    }
+    if length > config.username_max_length {
+        return Err(invalid_username(
+            config,
+            "too_long",
+            format!("Usernames have at most {} characters", config.username_max_length),
+        ));
+    }
+    if !username.chars().all(|c| allowed_char(&config.username_charset, c)) {
+        let message = match config.username_charset.as_str() {
+            "ascii" => "Usernames may only contain printable ASCII characters without spaces",
+            "alphanumeric" => "Usernames may only contain letters, digits, '-', '_' and '.'",
+            _ => "Usernames may not contain control characters",
+        };
+        return Err(invalid_username(config, "invalid_characters", message.to_string()));
+    }
+    if config
+        .reserved_usernames
+        .iter()
+        .chain(&config.admin_users)
+        .any(|reserved| reserved.eq_ignore_ascii_case(username))
+    {
+        return Err(invalid_username(config, "reserved", "This username is reserved".to_string()));
+    }
+    Ok(())
+}
+
+/// This function checks that the email address of a new account is at one of the `allowed_email_domains`.
+/// An entry like `*.example.com` allows every subdomain of example.com, every address is allowed if there are none.
+pub(crate) fn check_email_domain(config: &data::Config, mailbox: &Mailbox) -> Result<(), Refusal> {
+    if config.allowed_email_domains.is_empty() {
+        return Ok(());
+    }
+    let domain = mailbox.email.domain().to_lowercase();
+    let allowed = config.allowed_email_domains.iter().any(|allowed| match allowed.strip_prefix("*.") {
+        Some(parent) => domain.ends_with(&format!(".{}", parent)),
+        None => domain == *allowed,
+    });
+    if allowed {
+        return Ok(());
+    }
+    Err(Refusal {
+        error: "email_domain_not_allowed",
+        message: "Accounts can't be registered with email addresses of this domain".to_string(),
+        details: json!({ "domain": domain }),
+    })
+}
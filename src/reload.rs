@@ -0,0 +1,262 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use std::sync::{Mutex, RwLock};
+use tracing::{error, info, instrument, warn};
+
+use crate::admin;
+use crate::audit;
+use crate::config;
+use crate::data;
+use crate::logging;
+use crate::ratelimit;
+
+/// The configuration requests are handled with, changed by `reload`.
+static CONFIG: RwLock<Option<data::Config>> = RwLock::new(None);
+
+/// The rate limit budgets of the router, changed by `reload`.
+static RATE_LIMITS: Mutex<Option<ratelimit::RateLimits>> = Mutex::new(None);
+
+/// This function sets the configuration and rate limits a reload changes.
+pub(crate) fn init(config: &data::Config, rate_limits: &ratelimit::RateLimits) {
+    *CONFIG.write().unwrap() = Some(config.clone());
+    *RATE_LIMITS.lock().unwrap() = Some(rate_limits.clone());
+}
+
+/// This middleware hands every request the current configuration as `Extension<data::Config>`,
+/// a request keeps the configuration it started with even if it is reloaded meanwhile.
+pub async fn current_config(mut request: Request, next: Next) -> Response {
+    let config = CONFIG.read().unwrap().clone();
+    if let Some(config) = config {
+        request.extensions_mut().insert(config);
+    }
+    next.run(request).await
+}
+
+/// The log levels from the quietest to the most verbose, SIGUSR1 steps through them in this order.
+const LOG_LEVELS: [&str; 4] = ["error", "warn", "info", "debug"];
+
+/// Sets `current` to `new` and remembers the key if the value changed.
+fn apply<T: PartialEq>(changed: &mut Vec<String>, key: &str, current: &mut T, new: T) {
+    if *current != new {
+        *current = new;
+        changed.push(key.to_string());
+    }
+}
+
+/// This function reads the configuration again and applies the settings that can change at runtime:
+/// the log level, the rate limits, the storage and daily upload quotas and the blocked types.
+/// Everything else keeps its value until the server is restarted.
+/// Requests that are running, like long transfers, are not interrupted.
+/// It returns the keys that changed.
+pub fn reload() -> Result<Vec<String>, config::ConfigError> {
+    let new = config::reload()?;
+    let mut guard = CONFIG.write().unwrap();
+    let Some(current) = guard.as_mut() else {
+        return Ok(Vec::new());
+    };
+
+    let mut changed = Vec::new();
+    if current.log_level != new.log_level {
+        if logging::set_level(&new.log_level) {
+            apply(&mut changed, "log_level", &mut current.log_level, new.log_level);
+        } else {
+            warn!("The log level can't be changed without a restart");
+        }
+    }
+    apply(&mut changed, "rate_limit_downloads", &mut current.rate_limit_downloads, new.rate_limit_downloads);
+    apply(&mut changed, "rate_limit_uploads", &mut current.rate_limit_uploads, new.rate_limit_uploads);
+    apply(&mut changed, "rate_limit_register", &mut current.rate_limit_register, new.rate_limit_register);
+    apply(&mut changed, "rate_limit_reports", &mut current.rate_limit_reports, new.rate_limit_reports);
+    apply(&mut changed, "rate_limit_public", &mut current.rate_limit_public, new.rate_limit_public);
+    apply(&mut changed, "storage_quota", &mut current.storage_quota, new.storage_quota);
+    apply(&mut changed, "anonymous_daily_quota", &mut current.anonymous_daily_quota, new.anonymous_daily_quota);
+    apply(&mut changed, "ip_daily_upload_count", &mut current.ip_daily_upload_count, new.ip_daily_upload_count);
+    apply(&mut changed, "ip_daily_upload_bytes", &mut current.ip_daily_upload_bytes, new.ip_daily_upload_bytes);
+    apply(&mut changed, "monthly_upload_cap", &mut current.monthly_upload_cap, new.monthly_upload_cap);
+    apply(&mut changed, "monthly_download_cap", &mut current.monthly_download_cap, new.monthly_download_cap);
+    apply(&mut changed, "quota_warning_thresholds", &mut current.quota_warning_thresholds, new.quota_warning_thresholds);
+    apply(&mut changed, "username_min_length", &mut current.username_min_length, new.username_min_length);
+    apply(&mut changed, "username_max_length", &mut current.username_max_length, new.username_max_length);
+    apply(&mut changed, "username_charset", &mut current.username_charset, new.username_charset);
+    apply(&mut changed, "reserved_usernames", &mut current.reserved_usernames, new.reserved_usernames);
+    apply(&mut changed, "allowed_email_domains", &mut current.allowed_email_domains, new.allowed_email_domains);
+    apply(&mut changed, "blocked_types", &mut current.blocked_types, new.blocked_types);
+    apply(&mut changed, "compress_blobs", &mut current.compress_blobs, new.compress_blobs);
+    apply(&mut changed, "blocked_extensions", &mut current.blocked_extensions, new.blocked_extensions);
+    apply(&mut changed, "allowed_types", &mut current.allowed_types, new.allowed_types);
+
+    if let Some(rate_limits) = RATE_LIMITS.lock().unwrap().as_ref() {
+        rate_limits.update(current);
+    }
+    info!("Configuration reloaded, changed: {}", changed.join(", "));
+    Ok(changed)
+}
+
+/// This function reloads the configuration every time the process gets a SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_hangup() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("Could not listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration");
+        if let Err(e) = reload() {
+            error!("Configuration reload failed, keeping the current one: {}", e);
+        }
+    }
+}
+
+/// This function changes the log level of the running server until the next reload or restart,
+/// which go back to the configured `log_level`.
+/// It returns the level before, `None` if the level can't be changed, see `logging::set_level`.
+fn change_log_level(level: &str) -> Option<String> {
+    let mut guard = CONFIG.write().unwrap();
+    let current = guard.as_mut()?;
+    if !logging::set_level(level) {
+        return None;
+    }
+    Some(std::mem::replace(&mut current.log_level, level.to_string()))
+}
+
+/// This function makes the log more verbose every time the process gets a SIGUSR1,
+/// after `debug` it starts over at `error`.
+#[cfg(unix)]
+pub async fn cycle_log_level_on_user1() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Could not listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        let current = CONFIG.read().unwrap().as_ref().map(|config| config.log_level.clone());
+        let position = LOG_LEVELS.iter().position(|level| Some(*level) == current.as_deref());
+        let next = LOG_LEVELS[position.map_or(0, |position| (position + 1) % LOG_LEVELS.len())];
+        match change_log_level(next) {
+            Some(_) => warn!("Received SIGUSR1, the log level is now {}", next),
+            None => warn!("Received SIGUSR1, but the log level can't be changed without a restart"),
+        }
+    }
+}
+
+/// Handler to return the log level
+/// This function returns the log level the server logs with right now.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/log_level
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn get_log_level(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin log level request from IP: {}", ip);
+
+    if let Err(response) = admin::require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    Json(data::LogLevel {
+        level: config.log_level,
+        previous: None,
+    })
+    .into_response()
+}
+
+/// Handler to change the log level
+/// This function changes the log level of this instance right away, without a restart that loses its state,
+/// for example to debug a problem in production and go back to quieter logs afterwards.
+/// The change lasts until the configuration is reloaded or the server restarts,
+/// which go back to `log_level`. Sending the process a SIGUSR1 steps through the levels instead.
+/// Other instances of a cluster keep their level.
+/// example request: curl -X PUT -H "key: <admin key>" -H "content-type: application/json" -d '{"level": "debug"}' http://localhost:3000/admin/log_level
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following JSON body:
+/// - level: one of debug, info, warn or error (not optional)
+#[instrument(skip_all)]
+pub async fn set_log_level(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::LogLevelRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin log level change from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    let level = request.level.trim().to_lowercase();
+    if !LOG_LEVELS.contains(&level.as_str()) {
+        return (StatusCode::BAD_REQUEST, "level must be one of debug, info, warn or error").into_response();
+    }
+    let Some(previous) = change_log_level(&level) else {
+        return (StatusCode::CONFLICT, "The log level can't be changed without a restart").into_response();
+    };
+    warn!("Log level changed from {} to {} by {}", previous, level, admin.username);
+    audit::record(&pool, audit::Action::AdminLogLevel, Some(&admin.username), None, &ip).await;
+    Json(data::LogLevel {
+        level,
+        previous: Some(previous),
+    })
+    .into_response()
+}
+
+/// Handler to reload the configuration
+/// This function reads the config file and the environment variables again,
+/// like sending the server a SIGHUP, and applies the log level, rate limits,
+/// quotas and blocked types without a restart or dropping running transfers.
+/// Other settings need a restart. A config that doesn't load is refused and the current one kept.
+/// example request: curl -X POST -H "key: <admin key>" http://localhost:3000/admin/reload
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+#[instrument(skip_all)]
+pub async fn reload_config(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin reload request from IP: {}", ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    match reload() {
+        Ok(changed) => {
+            audit::record(&pool, audit::Action::AdminReload, Some(&admin.username), None, &ip).await;
+            Json(data::Reload { changed }).into_response()
+        }
+        Err(e) => {
+            error!("Configuration reload failed, keeping the current one: {}", e);
+            (StatusCode::UNPROCESSABLE_ENTITY, format!("Configuration error: {}", e)).into_response()
+        }
+    }
+}
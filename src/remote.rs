@@ -0,0 +1,373 @@
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use bytes::Bytes;
+use reqwest::{header, redirect, Url};
+use sqlx::AnyPool;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::share;
+use crate::storage;
+use crate::tokens;
+use std::net::{IpAddr, SocketAddr};
+
+/// The maximum number of redirects followed when fetching a remote file.
+const MAX_REDIRECTS: usize = 5;
+
+/// How long connecting to the remote host may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long fetching a remote file may take from the request to the end of the body,
+/// so a server that trickles its bytes can't keep the upload open forever.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Handler to upload a file from a remote URL
+/// This function fetches the file at the given URL,
+/// stores it like a normal upload
+/// and returns the file metadata as a JSON response.
+/// The download is streamed to a part file and aborted once it grows past the upload size limit of the user
+/// or takes longer than ten minutes.
+/// Only http and https URLs are fetched and private network addresses are refused
+/// unless `remote_upload_allow_private` is enabled.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"url": "https://example.com/file.tar.gz"}' http://localhost:3000/upload/remote
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+///
+/// requires the following JSON body:
+/// - url: the URL to fetch (not optional)
+/// - file_name: the name of the file (optional, defaults to the name the server sends or the URL path)
+/// - download_limit: the download limit of the file (optional)
+/// - visibility: `public` or `private` (optional, defaults to the `default_visibility` of the user from `/user/me` or public)
+/// - expires_at, max_age: when the file expires, like the headers of `/upload` (optional)
+///
+/// accepts the same `accept` header and `format` query parameter as `/upload`.
+#[instrument(skip_all)]
+pub async fn upload_remote(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    Json(request): Json<data::RemoteUploadRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received remote upload of {} from IP: {}", request.url, ip);
+
+    let (user, token) = match tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    if !user.is_verified() {
+        return api::unverified(&user).into_response();
+    }
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+
+    let url = match Url::parse(&request.url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+        _ => return (StatusCode::BAD_REQUEST, "Only http and https URLs are supported").into_response(),
+    };
+
+    let visibility = match request.visibility.as_deref().map(share::parse_visibility) {
+        Some(Some(visibility)) => visibility,
+        Some(None) => return share::invalid_visibility(),
+        None => api::default_visibility(&user),
+    };
+
+    let expires = match api::upload_expires(&user, request.expires_at.as_deref(), request.max_age.as_deref()) {
+        Ok(expires) => expires,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let fetched = match fetch(&config, url, limit).await {
+        Ok(fetched) => fetched,
+        Err(response) => return response,
+    };
+
+    let new_file = data::NewFile {
+        file_name: request
+            .file_name
+            .or(fetched.file_name)
+            .unwrap_or_else(|| "unknown".to_string()),
+        content_type: fetched.content_type,
+        download_limit: request
+            .download_limit
+            .unwrap_or_else(|| api::default_download_limit(&config, &user)),
+        owner: user.username,
+        expected_sha256: None,
+        expected_md5: None,
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility,
+        allowed_content_types: tokens::allowed_content_types(token.as_ref()),
+        expires,
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: tokens::collection(token.as_ref()),
+        description: None,
+    };
+    match api::store_file(&pool, &config, &ip, new_file, fetched.body).await {
+        Ok(uploaded_file) => {
+            let file = &uploaded_file.file;
+            audit::record(&pool, audit::Action::Upload, Some(&file.owner), Some(&file.id), &ip).await;
+            api::upload_response(&headers, &query, uploaded_file)
+        }
+        Err(response) => response,
+    }
+}
+
+/// This struct holds a fetched remote file.
+struct Fetched {
+    body: Bytes,
+    content_type: String,
+    file_name: Option<String>,
+}
+
+/// Helper to fetch a remote file.
+/// Redirects are followed by hand so every hop is checked against private addresses,
+/// and the resolved address is pinned so DNS can't change between check and connect.
+/// The body is written to a part file as it arrives and only read back once it is complete.
+async fn fetch(config: &data::Config, mut url: Url, limit: u64) -> Result<Fetched, Response> {
+    for _ in 0..=MAX_REDIRECTS {
+        let host = url
+            .host_str()
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, "URL has no host").into_response())?
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(80);
+        let target = resolve(&host, port, config.remote_upload_allow_private).await?;
+
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(FETCH_TIMEOUT)
+            .resolve(&host, target)
+            .build()
+            .map_err(|e| {
+                warn!("HTTP client error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "HTTP client error").into_response()
+            })?;
+        let mut response = client.get(url.clone()).send().await.map_err(|e| {
+            warn!("Remote fetch error {}: {}", url, e);
+            unreachable(&e, "Could not fetch the remote URL")
+        })?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|hv| hv.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+                .filter(|next| next.scheme() == "http" || next.scheme() == "https");
+            match location {
+                Some(next) => {
+                    info!("Following redirect from {} to {}", url, next);
+                    url = next;
+                    continue;
+                }
+                None => {
+                    return Err((StatusCode::BAD_GATEWAY, "Remote URL sent an invalid redirect")
+                        .into_response())
+                }
+            }
+        }
+        if !response.status().is_success() {
+            warn!("Remote fetch of {} returned {}", url, response.status());
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                format!("Remote URL returned {}", response.status()),
+            )
+                .into_response());
+        }
+
+        // stream the body and stop as soon as it gets too large
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit)
+        {
+            return Err(api::too_large(limit).into_response());
+        }
+        let declared_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.to_string());
+        let disposition_name = response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(disposition_file_name);
+        let spool_error = |e: std::io::Error| {
+            error!("Spool write error {}: {}", url, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "File write error").into_response()
+        };
+        let mut spool = storage::Spool::new(config).await.map_err(spool_error)?;
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    if spool.size() + chunk.len() as u64 > limit {
+                        return Err(api::too_large(limit).into_response());
+                    }
+                    spool.write(&chunk).await.map_err(spool_error)?;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Remote read error {}: {}", url, e);
+                    return Err(unreachable(&e, "Could not read the remote URL"));
+                }
+            }
+        }
+        let body = spool.into_body().await.map_err(spool_error)?;
+
+        // trust a specific declared type, otherwise sniff the content
+        let content_type = match declared_type {
+            Some(declared) if !declared.starts_with("application/octet-stream") => declared,
+            _ => infer::get(&body)
+                .map(|kind| kind.mime_type().to_string())
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+        };
+        let file_name = disposition_name.or_else(|| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.to_string())
+        });
+        return Ok(Fetched {
+            body,
+            content_type,
+            file_name,
+        });
+    }
+    Err((StatusCode::BAD_GATEWAY, "Too many redirects").into_response())
+}
+
+/// Returns the response for a remote host that could not be fetched from, 504 if it took too long.
+fn unreachable(e: &reqwest::Error, message: &'static str) -> Response {
+    match e.is_timeout() {
+        true => (StatusCode::GATEWAY_TIMEOUT, "The remote URL took too long to answer").into_response(),
+        false => (StatusCode::BAD_GATEWAY, message).into_response(),
+    }
+}
+
+/// Helper to resolve a host and refuse private network addresses.
+pub(crate) async fn resolve(host: &str, port: u16, allow_private: bool) -> Result<SocketAddr, Response> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| {
+            warn!("Could not resolve {}: {}", host, e);
+            (StatusCode::BAD_GATEWAY, "Could not resolve the remote host").into_response()
+        })?
+        .collect();
+    if !allow_private && addrs.iter().any(|addr| is_private(addr.ip())) {
+        warn!("Refused remote upload from private address {}", host);
+        return Err((StatusCode::FORBIDDEN, "Remote host resolves to a private address").into_response());
+    }
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| (StatusCode::BAD_GATEWAY, "Could not resolve the remote host").into_response())
+}
+
+/// Returns true for loopback, private, link local and other non public addresses.
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network" 0.0.0.0/8, Linux connects to localhost for any of it
+                || octets[0] == 0
+                // carrier grade NAT 100.64.0.0/10
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+                // benchmarking 198.18.0.0/15
+                || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+                // reserved 240.0.0.0/4, including the broadcast address
+                || octets[0] >= 240
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local fc00::/7 and link local fe80::/10
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80
+                || ip.to_ipv4_mapped().is_some_and(|ip| is_private(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Helper to read the file name from a Content-Disposition header.
+fn disposition_file_name(disposition: &str) -> Option<String> {
+    disposition.split(';').find_map(|part| {
+        let value = part.trim().strip_prefix("filename=")?;
+        let value = value.trim_matches('"');
+        let name = value.rsplit(['/', '\\']).next().unwrap_or(value);
+        (!name.is_empty()).then(|| name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_private;
+    use std::net::IpAddr;
+
+    fn private(ip: &str) -> bool {
+        is_private(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn refuses_non_public_ipv4_addresses() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "100.127.255.255",
+            "198.18.0.1",
+            "198.19.255.255",
+            "192.0.2.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(private(ip), "{} should be private", ip);
+        }
+    }
+
+    #[test]
+    fn allows_public_ipv4_addresses() {
+        for ip in ["1.1.1.1", "8.8.8.8", "100.128.0.1", "198.17.255.255", "198.20.0.1", "223.255.255.255"] {
+            assert!(!private(ip), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn refuses_non_public_ipv6_addresses() {
+        for ip in ["::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "ff02::1", "::ffff:127.0.0.1", "::ffff:0.0.0.1"] {
+            assert!(private(ip), "{} should be private", ip);
+        }
+    }
+
+    #[test]
+    fn allows_public_ipv6_addresses() {
+        for ip in ["2606:4700:4700::1111", "2001:4860:4860::8888", "::ffff:8.8.8.8"] {
+            assert!(!private(ip), "{} should be public", ip);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use tokio::fs;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::data;
+use crate::storage;
+
+/// The number of new blobs that can wait to be copied before further ones are left to the next sync.
+const QUEUE_SIZE: usize = 4096;
+
+/// The queue new blobs are sent to, it is set once replication is started.
+static QUEUE: OnceLock<mpsc::Sender<String>> = OnceLock::new();
+
+/// This function starts copying blobs to `replica_path` in the background if it is set,
+/// a second disk or a network mount that keeps the files when the disk of `data_path` fails.
+/// It copies every new blob once it is stored,
+/// the replicate job catches up with the blobs the replica is missing, see `sync`.
+/// Uploads never wait for the copy, a blob that is lost before it was copied is only in the data path.
+/// Reads fall back to the replica when a blob can't be read from the data path.
+pub fn start(config: data::Config) {
+    if config.replica_path.is_none() {
+        return;
+    }
+    let (sender, mut receiver) = mpsc::channel::<String>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        warn!("Replication already started");
+        return;
+    }
+    tokio::spawn(async move {
+        while let Some(name) = receiver.recv().await {
+            if let Err(e) = copy(&config, &name).await {
+                warn!("Could not copy blob {} to the replica: {}", name, e);
+            }
+        }
+    });
+}
+
+/// Queues a newly stored blob to be copied to the replica, if replication is started.
+/// A blob that does not fit in the queue is copied by the next sync.
+pub(crate) fn queue(name: &str) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    if let Err(e) = queue.try_send(name.to_string()) {
+        warn!("Left blob {} to the next replica sync: {}", name, e);
+    }
+}
+
+/// This function copies every blob of the data path the replica does not have yet
+/// and returns how many it copied.
+/// Blobs only the replica has are kept, they may be all that is left of a failed data path,
+/// blobs are removed from the replica when they are removed from the data path.
+/// The replicate job runs it at startup and every `replica_sync_interval` seconds after.
+pub async fn sync(config: &data::Config) -> Result<usize, String> {
+    let Some(root) = &config.replica_path else {
+        return Ok(0);
+    };
+    let blobs = storage::list_blobs(config).await.map_err(|e| e.to_string())?;
+    let replicated: HashSet<String> = match storage::list_blobs_below(root).await {
+        Ok(replicated) => replicated.into_iter().map(|(name, _)| name).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(format!("could not list the replica: {}", e)),
+    };
+    let mut copied = 0;
+    for (name, _) in blobs.iter().filter(|(name, _)| !replicated.contains(name)) {
+        match copy(config, name).await {
+            Ok(true) => copied += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Could not copy blob {} to the replica: {}", name, e),
+        }
+    }
+    info!("Copied {} of {} blobs to the replica", copied, blobs.len());
+    Ok(copied)
+}
+
+/// Copies a blob as it is stored, encrypted or compressed alike, unless the replica has it already.
+/// It is written to a part file of the replica first, so a copy that is cut off never shows up as a blob.
+/// Returns true if the blob was copied.
+async fn copy(config: &data::Config, name: &str) -> std::io::Result<bool> {
+    let Some(root) = &config.replica_path else {
+        return Ok(false);
+    };
+    let parts = storage::parts_below(root);
+    fs::create_dir_all(&parts).await?;
+    let target = storage::checked_path_below(root, name).await?;
+    if fs::try_exists(&target).await.unwrap_or(false) {
+        return Ok(false);
+    }
+    let source = storage::checked_path(config, name).await?;
+    let part = parts.join(format!("{}.part", uuid::Uuid::new_v4()));
+    let copied = async {
+        fs::copy(&source, &part).await?;
+        fs::File::open(&part).await?.sync_all().await?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&part, &target).await
+    };
+    if let Err(e) = copied.await {
+        let _ = fs::remove_file(&part).await;
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// Opens the copy of a blob in the replica, for reads the data path can't answer.
+pub(crate) async fn open(config: &data::Config, name: &str) -> std::io::Result<fs::File> {
+    let Some(root) = &config.replica_path else {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no replica_path is set"));
+    };
+    let file = fs::File::open(storage::checked_path_below(root, name).await?).await?;
+    warn!("Reading blob {} from the replica, it can't be read from the data path", name);
+    Ok(file)
+}
+
+/// Removes the copy of a blob from the replica, a blob that was never copied is no error.
+pub(crate) async fn remove(config: &data::Config, name: &str) {
+    let Some(root) = &config.replica_path else {
+        return;
+    };
+    let removed = match storage::checked_path_below(root, name).await {
+        Ok(path) => fs::remove_file(path).await,
+        Err(e) => Err(e),
+    };
+    match removed {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Could not remove blob {} from the replica: {}", name, e)
+        }
+        _ => {}
+    }
+}
+
+/// Moves the copy of a blob in the replica to another stored name, a blob that was never copied is no error.
+pub(crate) async fn rename(config: &data::Config, from: &str, to: &str) {
+    let Some(root) = &config.replica_path else {
+        return;
+    };
+    let renamed = async {
+        let source = storage::checked_path_below(root, from).await?;
+        let target = storage::checked_path_below(root, to).await?;
+        fs::rename(source, target).await
+    };
+    match renamed.await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Could not rename blob {} to {} in the replica: {}", from, to, e)
+        }
+        _ => {}
+    }
+}
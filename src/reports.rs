@@ -0,0 +1,334 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::admin;
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::webhook;
+use std::net::SocketAddr;
+
+/// The maximum length of a report reason in bytes.
+const MAX_REASON_LENGTH: usize = 2000;
+
+/// Returns true if the file was taken down and must never be served again.
+pub(crate) async fn is_blocked(pool: &AnyPool, uuid: &str) -> bool {
+    let blocked = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM blocked_files WHERE file_id = ?")
+        .bind(uuid)
+        .fetch_one(pool)
+        .await;
+    match blocked {
+        Ok(count) => count > 0,
+        Err(e) => {
+            error!("DB select blocked file error {}: {}", uuid, e);
+            false
+        }
+    }
+}
+
+/// Returns the response for a file that was taken down.
+pub(crate) fn taken_down() -> Response {
+    (StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "This file was taken down").into_response()
+}
+
+/// Helper to look up a report by its ID.
+async fn find_report(pool: &AnyPool, id: &str) -> Result<data::Report, Response> {
+    let report = sqlx::query_as::<_, data::Report>(
+        r#"
+        SELECT reports.*, files.file_name, files.owner
+        FROM reports
+        LEFT JOIN files ON files.id = reports.file_id
+        WHERE reports.id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await;
+    match report {
+        Ok(Some(report)) => Ok(report),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Report not found").into_response()),
+        Err(e) => {
+            error!("DB select report error {}: {}", id, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to close the open reports of a file with the given status.
+async fn resolve(pool: &AnyPool, file_id: &str, status: &str, admin: &str) -> Result<u64, Response> {
+    let resolved = sqlx::query(
+        r#"
+        UPDATE reports
+        SET status = ?, resolved_by = ?, resolved_at = ?
+        WHERE file_id = ? AND status = 'open'
+        "#,
+    )
+    .bind(status)
+    .bind(admin)
+    .bind(Utc::now().timestamp())
+    .bind(file_id)
+    .execute(pool)
+    .await;
+    match resolved {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(e) => {
+            error!("DB update reports error {}: {}", file_id, e);
+            Err(db::error_response(&e, "Database update error"))
+        }
+    }
+}
+
+/// Handler to report a file
+/// This function records a report about a file that breaks the rules of the instance,
+/// admins review it with `/admin/reports`.
+/// Anybody can report a file, no key is needed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "content-type: application/json" -d '{"reason": "malware"}' http://localhost:3000/report/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// requires the following JSON body:
+/// - reason: why the file should be taken down (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn report_file(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<data::ReportRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received report for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+    let reason = request.reason.trim();
+    if reason.is_empty() || reason.len() > MAX_REASON_LENGTH {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Give a reason of at most {} bytes", MAX_REASON_LENGTH),
+        )
+            .into_response();
+    }
+
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM files WHERE id = ?")
+        .bind(&uuid)
+        .fetch_one(&pool)
+        .await;
+    match exists {
+        Ok(0) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Ok(_) => {}
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    }
+
+    let id = Uuid::from_u128(rand::rng().random::<u128>()).to_string();
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO reports
+            (id, file_id, reason, ip, created, status)
+        VALUES (?, ?, ?, ?, ?, 'open')
+        "#,
+    )
+    .bind(&id)
+    .bind(&uuid)
+    .bind(reason)
+    .bind(&ip)
+    .bind(Utc::now().timestamp())
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert report error {}: {}", uuid, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    warn!("File {} reported from {}", uuid, ip);
+    audit::record(&pool, audit::Action::Report, None, Some(&uuid), &ip).await;
+
+    (StatusCode::CREATED, Json(json!({ "id": id }))).into_response()
+}
+
+/// Handler to list abuse reports
+/// This function returns the reports with the given status, the newest first,
+/// together with the name and owner of the reported file if it still exists.
+/// example request: curl -X GET -H "key: <admin key>" http://localhost:3000/admin/reports
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// accepts the following query parameters:
+/// - status: `open`, `dismissed` or `taken_down`, `open` by default (optional)
+#[instrument(skip_all)]
+pub async fn list_reports(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::ReportsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin reports request from IP: {}", ip);
+
+    if let Err(response) = admin::require_admin(&pool, &headers, &ip).await {
+        return response;
+    }
+
+    let reports = sqlx::query_as::<_, data::Report>(
+        r#"
+        SELECT reports.*, files.file_name, files.owner
+        FROM reports
+        LEFT JOIN files ON files.id = reports.file_id
+        WHERE reports.status = ?
+        ORDER BY reports.created DESC
+        "#,
+    )
+    .bind(query.status.as_deref().unwrap_or("open"))
+    .fetch_all(&pool)
+    .await;
+    match reports {
+        Ok(reports) => Json(reports).into_response(),
+        Err(e) => {
+            error!("DB select reports error: {}", e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to dismiss a report
+/// This function closes every open report of the reported file without touching the file.
+/// example request: curl -X POST -H "key: <admin key>" http://localhost:3000/admin/reports/<id>/dismiss
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - id: the ID of the report (not optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn dismiss_report(
+    Path(id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin dismiss report request for {} from IP: {}", id, ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+    let report = match find_report(&pool, &id).await {
+        Ok(report) => report,
+        Err(response) => return response,
+    };
+
+    match resolve(&pool, &report.file_id, "dismissed", &admin.username).await {
+        Ok(dismissed) => {
+            info!("{} reports of {} dismissed by admin {}", dismissed, report.file_id, admin.username);
+            audit::record(&pool, audit::Action::AdminDismissReport, Some(&admin.username), Some(&report.file_id), &ip).await;
+            Json(json!({
+                "file_id": report.file_id,
+                "dismissed": dismissed,
+            }))
+            .into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Handler to take a reported file down
+/// This function removes the reported file from the server
+/// and blocks its UUID, downloads of it are answered with 451 from then on.
+/// Every open report of the file is closed.
+/// example request: curl -X POST -H "key: <admin key>" http://localhost:3000/admin/reports/<id>/takedown
+/// requires the following headers:
+/// - key: the key of an admin user (not optional)
+///
+/// requires the following path parameter:
+/// - id: the ID of the report (not optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn takedown(
+    Path(id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an admin takedown request for {} from IP: {}", id, ip);
+
+    let admin = match admin::require_admin(&pool, &headers, &ip).await {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+    let report = match find_report(&pool, &id).await {
+        Ok(report) => report,
+        Err(response) => return response,
+    };
+
+    // block the UUID first, so the file is never served again even if removing it fails
+    if !is_blocked(&pool, &report.file_id).await {
+        if let Err(e) = sqlx::query(
+            r#"
+            INSERT INTO blocked_files
+                (file_id, reason, blocked_by, created)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&report.file_id)
+        .bind(&report.reason)
+        .bind(&admin.username)
+        .bind(Utc::now().timestamp())
+        .execute(&pool)
+        .await
+        {
+            error!("DB insert blocked file error {}: {}", report.file_id, e);
+            return db::error_response(&e, "Database insert error");
+        }
+    }
+
+    let file = sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+        .bind(&report.file_id)
+        .fetch_optional(&pool)
+        .await;
+    match file {
+        Ok(Some(file)) => {
+            if let Err(response) = api::remove_stored_file(&pool, &config, &file).await {
+                return response;
+            }
+            webhook::emit(webhook::EventKind::Deleted, &file);
+        }
+        Ok(None) => info!("Reported file {} was already removed", report.file_id),
+        Err(e) => {
+            error!("DB select error {}: {}", report.file_id, e);
+            return db::error_response(&e, "Database select error");
+        }
+    }
+
+    match resolve(&pool, &report.file_id, "taken_down", &admin.username).await {
+        Ok(resolved) => {
+            warn!("File {} taken down by admin {}", report.file_id, admin.username);
+            audit::record(&pool, audit::Action::AdminTakedown, Some(&admin.username), Some(&report.file_id), &ip).await;
+            Json(json!({
+                "file_id": report.file_id,
+                "resolved": resolved,
+            }))
+            .into_response()
+        }
+        Err(response) => response,
+    }
+}
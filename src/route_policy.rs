@@ -0,0 +1,133 @@
+use axum::{
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::Limited;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::{Layer, ServiceExt};
+use tracing::warn;
+
+use crate::data;
+use crate::error::ApiError;
+
+/// This struct is the body limit and timeout of the routes below a path, like `/user/register body_limit=16384 timeout=10`.
+/// `body_limit` is in bytes and `timeout` in seconds, 0 turns either off and a missing one keeps the default.
+#[derive(Clone, Debug)]
+pub struct RoutePolicy {
+    prefix: String,
+    body_limit: Option<u64>,
+    timeout: Option<u64>,
+}
+
+impl RoutePolicy {
+    /// Parses a path prefix followed by `body_limit=<bytes>` and `timeout=<seconds>`, at least one of them.
+    pub fn parse(value: &str) -> Option<RoutePolicy> {
+        let mut parts = value.split_whitespace();
+        let prefix = parts.next().filter(|prefix| prefix.starts_with('/'))?;
+        let mut policy = RoutePolicy {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            body_limit: None,
+            timeout: None,
+        };
+        for part in parts {
+            match part.split_once('=')? {
+                ("body_limit", bytes) => policy.body_limit = Some(bytes.parse().ok()?),
+                ("timeout", seconds) => policy.timeout = Some(seconds.parse().ok()?),
+                _ => return None,
+            }
+        }
+        (policy.body_limit.is_some() || policy.timeout.is_some()).then_some(policy)
+    }
+
+    /// Returns true if the path is the prefix or below it, `/upload` covers `/upload/init` but not `/uploads`.
+    fn matches(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+/// This struct holds the route policies and the defaults the middleware enforces.
+#[derive(Clone)]
+pub struct Policies {
+    policies: Arc<[RoutePolicy]>,
+    request_timeout: u64,
+}
+
+impl Policies {
+    /// Returns the `route_policies` of the config, with `request_timeout` for routes no policy sets a timeout for.
+    pub fn from_config(config: &data::Config) -> Self {
+        Policies {
+            policies: Arc::from(config.route_policies.clone()),
+            request_timeout: config.request_timeout,
+        }
+    }
+
+    /// Returns the body limit and timeout of a path, the most specific policy wins for each.
+    /// A `None` body limit leaves the limit of the route alone.
+    fn lookup(&self, path: &str) -> (Option<u64>, u64) {
+        let mut matching: Vec<&RoutePolicy> = self.policies.iter().filter(|policy| policy.matches(path)).collect();
+        matching.sort_by_key(|policy| std::cmp::Reverse(policy.prefix.len()));
+        let body_limit = matching.iter().find_map(|policy| policy.body_limit);
+        let timeout = matching.iter().find_map(|policy| policy.timeout);
+        (body_limit, timeout.unwrap_or(self.request_timeout))
+    }
+}
+
+/// This middleware applies the body limit and timeout of the route policy that matches the path.
+/// A body limit replaces the one of the route, `max_upload_size` or none for uploads, a limit of 0 turns it off:
+/// a larger `Content-Length` is answered with 413 right away, a longer body fails once it is read.
+/// A handler that takes longer than the timeout, usually reading the body of a slow client, is answered with 408.
+/// The body of a response is not limited, large downloads take as long as they take.
+pub async fn enforce(State(policies): State<Policies>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let (body_limit, timeout) = policies.lookup(&path);
+
+    let handled = async {
+        let Some(limit) = body_limit else {
+            return next.run(request).await;
+        };
+        let mut request = request;
+        if limit > 0 {
+            let length = request
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|hv| hv.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            if let Some(length) = length.filter(|length| *length > limit) {
+                warn!("Refused a body of {} bytes for {}, the route takes {} bytes", length, path, limit);
+                return body_too_large(limit);
+            }
+            request = request.map(|body| axum::body::Body::new(Limited::new(body, limit as usize)));
+        }
+        match DefaultBodyLimit::disable().layer(next).oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        }
+    };
+    if timeout == 0 {
+        return handled.await;
+    }
+    match tokio::time::timeout(Duration::from_secs(timeout), handled).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("Request for {} took longer than {} seconds", path, timeout);
+            StatusCode::REQUEST_TIMEOUT.into_response()
+        }
+    }
+}
+
+/// Returns the error for a body larger than the route takes.
+fn body_too_large(limit: u64) -> Response {
+    ApiError::Rejected {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        error: "body_too_large",
+        message: format!("This route takes request bodies of up to {} bytes", limit),
+        details: serde_json::json!({ "body_limit": limit }),
+    }
+    .into_response()
+}
@@ -0,0 +1,1062 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, OriginalUri, Path, Query, Request},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::prelude::*;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use http_body_util::Limited;
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use std::fmt::Write;
+use tracing::{error, info, instrument, warn};
+
+use crate::access_log;
+use crate::api;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::lockout;
+use crate::storage;
+use crate::throttle;
+use crate::webhook;
+use std::net::SocketAddr;
+
+/// How far the time a request was signed at may be off from the time of the server, in seconds.
+const MAX_CLOCK_SKEW: i64 = 15 * 60;
+
+/// The headers every signature has to cover, AWS refuses requests that leave them out too.
+const REQUIRED_SIGNED_HEADERS: [&str; 2] = ["host", "x-amz-date"];
+
+/// The most keys a listing returns, also the default.
+const MAX_KEYS: usize = 1000;
+
+/// The longest object key S3 allows, in bytes.
+const MAX_KEY_LENGTH: usize = 1024;
+
+/// The payload hash of requests whose body is not signed.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// The payload hash of `aws-chunked` bodies without chunk signatures.
+const STREAMING_UNSIGNED_PAYLOAD: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+/// Returns an S3 error response, S3 clients read the code from the XML body.
+fn error(status: StatusCode, code: &str, message: &str) -> Response {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>{}</Code><Message>{}</Message></Error>"#,
+        code,
+        escape(message)
+    );
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+/// Returns the S3 error response for a ready-made error response of the rest of the API.
+fn translate(response: Response) -> Response {
+    let code = match response.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => "EntityTooLarge",
+        StatusCode::FORBIDDEN => "AccessDenied",
+        StatusCode::SERVICE_UNAVAILABLE => "ServiceUnavailable",
+        status if status.is_client_error() => "InvalidRequest",
+        _ => return response,
+    };
+    let status = response.status();
+    error(status, code, status.canonical_reason().unwrap_or("Request failed"))
+}
+
+/// Escapes text for an XML element.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Encodes text like SigV4 does, everything but unreserved characters is percent encoded.
+pub(crate) fn uri_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes percent encoded text, invalid escapes are kept as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| text.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the canonical query string of a request, its parameters encoded and sorted.
+fn canonical_query(uri: &Uri) -> String {
+    let mut parameters: Vec<(String, String)> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            (uri_encode(&percent_decode(name)), uri_encode(&percent_decode(value)))
+        })
+        .collect();
+    parameters.sort();
+    parameters
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Returns the canonical form of the signed headers, one `name:value` line each.
+fn canonical_headers(headers: &HeaderMap, signed_headers: &str) -> String {
+    let mut canonical = String::new();
+    for name in signed_headers.split(';') {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            })
+            .collect();
+        let _ = writeln!(canonical, "{}:{}", name, values.join(","));
+    }
+    canonical
+}
+
+/// Returns the canonical request of an AWS Signature Version 4, the part of a request that is signed.
+fn canonical_request(method: &Method, uri: &Uri, headers: &HeaderMap, signed_headers: &str, payload_hash: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        uri.path(),
+        canonical_query(uri),
+        canonical_headers(headers, signed_headers),
+        signed_headers,
+        payload_hash
+    )
+}
+
+/// Returns the string an AWS Signature Version 4 signs, the date and scope with the hash of the canonical request.
+fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    )
+}
+
+/// Returns the HMAC-SHA256 of a message.
+fn hmac(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the key derived from the secret for the date, region and service of the credential scope.
+fn signing_key(secret: &str, scope: &str) -> Vec<u8> {
+    let mut key = format!("AWS4{}", secret).into_bytes();
+    for part in scope.split('/') {
+        key = hmac(&key, part);
+    }
+    key
+}
+
+/// Returns true if the signature was made over `string_to_sign` with the secret, compared in constant time.
+fn signature_matches(secret: &str, scope: &str, string_to_sign: &str, signature: &[u8]) -> bool {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&signing_key(secret, scope)).expect("HMAC accepts keys of any length");
+    mac.update(string_to_sign.as_bytes());
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Returns a presigned URL to download a blob from the bucket of `download_redirect_url`,
+/// signed with AWS Signature Version 4 and valid for `download_redirect_expiry` seconds.
+/// `overrides` are `response-*` parameters the bucket answers with instead of the stored headers,
+/// like `response-content-disposition`. It returns `None` if redirected downloads are not configured.
+pub(crate) fn presigned_url(config: &data::Config, key: &str, overrides: &[(&str, &str)]) -> Option<String> {
+    let (Some(url), Some(access_key), Some(secret_key)) = (
+        &config.download_redirect_url,
+        &config.download_redirect_access_key,
+        &config.download_redirect_secret_key,
+    ) else {
+        return None;
+    };
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str()?, port),
+        None => url.host_str()?.to_string(),
+    };
+    let key = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let path = format!("{}/{}", url.path().trim_end_matches('/'), key);
+
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), config.download_redirect_region);
+    let credential = format!("{}/{}", access_key, scope);
+    let expiry = config.download_redirect_expiry.to_string();
+    let mut parameters = vec![
+        ("X-Amz-Algorithm", "AWS4-HMAC-SHA256"),
+        ("X-Amz-Credential", credential.as_str()),
+        ("X-Amz-Date", amz_date.as_str()),
+        ("X-Amz-Expires", expiry.as_str()),
+        ("X-Amz-SignedHeaders", "host"),
+    ];
+    parameters.extend_from_slice(overrides);
+    let mut parameters: Vec<String> = parameters
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", uri_encode(name), uri_encode(value)))
+        .collect();
+    parameters.sort();
+    let query = parameters.join("&");
+
+    let canonical_request = format!("GET\n{}\n{}\nhost:{}\n\nhost\n{}", path, query, host, UNSIGNED_PAYLOAD);
+    let string_to_sign = string_to_sign(&amz_date, &scope, &canonical_request);
+    let signature = hex::encode(hmac(&signing_key(secret_key, &scope), &string_to_sign));
+    Some(format!("{}://{}{}?{}&X-Amz-Signature={}", url.scheme(), host, path, query, signature))
+}
+
+/// Helper to authenticate an S3 request
+/// This function checks the AWS Signature Version 4 of the request.
+/// The access key ID is the username and the secret access key is any key of the user that is not revoked.
+/// It returns the user if the signature is valid,
+/// or a ready-made S3 error response if it is missing, too old or wrong.
+/// Wrong signatures are recorded in the audit log together with the IP address of the client.
+async fn authenticate(
+    pool: &AnyPool,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    ip: &str,
+) -> Result<data::User, Response> {
+    let header = |name: &str| headers.get(name).and_then(|hv| hv.to_str().ok());
+    let Some(fields) = header("authorization").and_then(|value| value.strip_prefix("AWS4-HMAC-SHA256 ")) else {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "Requests must be signed with AWS Signature Version 4",
+        ));
+    };
+    let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+    for field in fields.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) => credential = Some(value),
+            Some(("SignedHeaders", value)) => signed_headers = Some(value),
+            Some(("Signature", value)) => signature = Some(value),
+            _ => {}
+        }
+    }
+    let malformed = || error(StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed", "The authorization header is malformed");
+    let (Some(credential), Some(signed_headers), Some(signature)) = (credential, signed_headers, signature) else {
+        return Err(malformed());
+    };
+    let Some((access_key, scope)) = credential.split_once('/') else {
+        return Err(malformed());
+    };
+    let scope_parts: Vec<&str> = scope.split('/').collect();
+    if scope_parts.len() != 4 || scope_parts[2] != "s3" || scope_parts[3] != "aws4_request" {
+        return Err(malformed());
+    }
+
+    // signatures are only valid for a short time, so a captured request can't be replayed later
+    let Some(amz_date) = header("x-amz-date") else {
+        return Err(error(StatusCode::FORBIDDEN, "AccessDenied", "The x-amz-date header is missing"));
+    };
+    let signed_at = NaiveDateTime::parse_from_str(amz_date, "%Y%m%dT%H%M%SZ").map(|date| date.and_utc().timestamp());
+    match signed_at {
+        Ok(signed_at) if (Utc::now().timestamp() - signed_at).abs() <= MAX_CLOCK_SKEW => {}
+        Ok(_) => {
+            return Err(error(
+                StatusCode::FORBIDDEN,
+                "RequestTimeTooSkewed",
+                "The difference between the request time and the server's time is too large",
+            ))
+        }
+        Err(_) => return Err(malformed()),
+    }
+    if !amz_date.starts_with(scope_parts[0]) {
+        return Err(malformed());
+    }
+    let Some(payload_hash) = header("x-amz-content-sha256") else {
+        return Err(error(StatusCode::BAD_REQUEST, "InvalidRequest", "The x-amz-content-sha256 header is missing"));
+    };
+    // without the host and date in the signature it could be replayed against another endpoint or later
+    let signed: Vec<&str> = signed_headers.split(';').collect();
+    if let Some(missing) = REQUIRED_SIGNED_HEADERS.iter().find(|name| !signed.contains(name)) {
+        warn!("S3 request that does not sign the {} header", missing);
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "The host and x-amz-date headers must be signed",
+        ));
+    }
+
+    if let Some(response) = lockout::check(ip, None) {
+        return Err(response);
+    }
+    let user = sqlx::query_as::<_, data::User>("SELECT * FROM users WHERE username = ?")
+        .bind(access_key)
+        .fetch_optional(pool)
+        .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            warn!("S3 request with unknown access key {}", access_key);
+            lockout::failed(ip, None);
+            audit::record_failed_login(pool, None, None, ip);
+            return Err(error(
+                StatusCode::FORBIDDEN,
+                "InvalidAccessKeyId",
+                "The access key ID is not a username of this server",
+            ));
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", access_key, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+
+    let canonical_request = canonical_request(method, uri, headers, signed_headers, payload_hash);
+    let string_to_sign = string_to_sign(amz_date, scope, &canonical_request);
+    // any key of the user that is not revoked can sign requests
+    let keys = match db::active_keys(pool, &user.username).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            error!("DB select keys error {}: {}", user.username, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    let signature = hex::decode(signature).unwrap_or_default();
+    let valid = keys
+        .iter()
+        .any(|secret| signature_matches(secret, scope, &string_to_sign, &signature));
+    if !valid {
+        warn!("S3 request of {} with a wrong signature", user.username);
+        lockout::failed(ip, None);
+        audit::record_failed_login(pool, None, None, ip);
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch",
+            "The signature does not match, the secret access key is a key of the user",
+        ));
+    }
+    access_log::set_user(&user.username);
+    Ok(user)
+}
+
+/// Returns the built response of an object, or an S3 error if it could not be built,
+/// like for a stored content type that is no valid header value.
+fn built(response: Result<Response, axum::http::Error>, id: &str) -> Response {
+    response.unwrap_or_else(|e| {
+        error!("Response error {}: {}", id, e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "The object could not be read")
+    })
+}
+
+/// Returns the response for a bucket other than the one of the user.
+/// Every user has exactly one bucket, named after them.
+fn check_bucket(user: &data::User, bucket: &str) -> Option<Response> {
+    (bucket != user.username).then(|| {
+        warn!("User {} tried to access bucket {}", user.username, bucket);
+        error(StatusCode::FORBIDDEN, "AccessDenied", "The bucket of a user is named after them")
+    })
+}
+
+/// Returns the ETag of an object, the SHA-256 of its contents.
+fn etag(file: &data::File) -> String {
+    format!("\"{}\"", file.content_hash.as_deref().unwrap_or(&file.id))
+}
+
+/// Returns the headers S3 clients expect on a `GetObject` or `HeadObject` response.
+fn object_headers(file: &data::File) -> axum::http::response::Builder {
+    let last_modified = DateTime::from_timestamp(file.upload_time, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    Response::builder()
+        .header(header::CONTENT_TYPE, &file.content_type)
+        .header(header::ETAG, etag(file))
+        .header(header::LAST_MODIFIED, last_modified)
+        .header(header::ACCEPT_RANGES, "bytes")
+}
+
+/// Returns the payload of an `aws-chunked` body, `None` if it is malformed.
+/// Trailing checksums are not checked.
+fn decode_chunked(body: &[u8]) -> Option<Vec<u8>> {
+    let mut payload = Vec::with_capacity(body.len());
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|window| window == b"\r\n")?;
+        let line = std::str::from_utf8(&rest[..line_end]).ok()?;
+        let size = usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some(payload);
+        }
+        payload.extend_from_slice(rest.get(..size)?);
+        rest = rest.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+/// Handler to store an object (S3 `PutObject`)
+/// This function uploads the body as a private file of the user, named after the object key.
+/// An object that already has the key is moved to the trash once the new one is stored.
+/// Objects have no download limit unless the server requires one, then they get the default limit.
+/// It also logs the IP address of the client making the request.
+/// example request: aws --endpoint-url http://localhost:3000/s3 s3 cp backup.tar s3://<username>/backups/backup.tar
+/// requires the following headers:
+/// - authorization: an AWS Signature Version 4 with the username as access key ID and the key as secret (not optional)
+/// - x-amz-content-sha256: the SHA-256 of the body, `UNSIGNED-PAYLOAD` or `STREAMING-UNSIGNED-PAYLOAD-TRAILER` (not optional)
+/// - content-md5: the base64 MD5 of the body to verify the upload against (optional)
+///
+/// requires the following path parameters:
+/// - bucket: the username (not optional)
+/// - key: the object key (not optional)
+#[instrument(skip_all)]
+pub async fn put_object(
+    Path((bucket, key)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received S3 PutObject request for {} from IP: {}", key, ip);
+
+    // the signature covers the path the client sent, including the `base_path` the router strips
+    let uri = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.clone(),
+        None => request.uri().clone(),
+    };
+    let (method, headers) = (request.method().clone(), request.headers().clone());
+    let user = match authenticate(&pool, &method, &uri, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_bucket(&user, &bucket) {
+        return response;
+    }
+    if !user.is_verified() {
+        return translate(api::unverified(&user).into_response());
+    }
+    // multipart uploads and copies would otherwise overwrite the object with a part or nothing
+    let query = uri.query().unwrap_or_default();
+    if query.split('&').any(|parameter| parameter.starts_with("uploadId=") || parameter.starts_with("partNumber="))
+        || headers.contains_key("x-amz-copy-source")
+    {
+        return unsupported().await;
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return error(StatusCode::BAD_REQUEST, "KeyTooLongError", "Object keys can be at most 1024 bytes long");
+    }
+    let payload_hash = headers
+        .get("x-amz-content-sha256")
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    // chunk signatures would have to be checked chunk by chunk, clients can sign the whole body instead
+    if payload_hash.starts_with("STREAMING-") && payload_hash != STREAMING_UNSIGNED_PAYLOAD {
+        return error(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            "Signed streaming uploads are not supported, sign the whole payload or send it unsigned",
+        );
+    }
+
+    // like `/upload`, announced sizes over the limit are refused before the body is read
+    let limit = api::upload_limit(&config, &user, None);
+    let announced = ["x-amz-decoded-content-length", "content-length"]
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if announced.is_some_and(|length| length > limit) {
+        return translate(api::too_large(limit).into_response());
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+    let body = match Bytes::from_request(request, &()).await {
+        Ok(body) => body,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return translate(api::too_large(limit).into_response()),
+        Err(e) => {
+            warn!("Body read error: {}", e);
+            return error(StatusCode::BAD_REQUEST, "IncompleteBody", "The body could not be read");
+        }
+    };
+    let body = match payload_hash.as_str() {
+        STREAMING_UNSIGNED_PAYLOAD => match decode_chunked(&body) {
+            Some(payload) => Bytes::from(payload),
+            None => return error(StatusCode::BAD_REQUEST, "IncompleteBody", "The aws-chunked body is malformed"),
+        },
+        UNSIGNED_PAYLOAD => body,
+        signed => {
+            if hex::encode(Sha256::digest(&body)) != signed.to_ascii_lowercase() {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "XAmzContentSHA256Mismatch",
+                    "The SHA-256 of the body does not match x-amz-content-sha256",
+                );
+            }
+            body
+        }
+    };
+
+    let download_limit = match api::download_limit_allowed(&config, 0) {
+        true => 0,
+        false => config.default_download_limit,
+    };
+    let new_file = data::NewFile {
+        file_name: key.clone(),
+        content_type: headers
+            .get("content-type")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        download_limit,
+        owner: user.username.clone(),
+        expected_sha256: None,
+        expected_md5: headers
+            .get("content-md5")
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.trim().to_string()),
+        slug: None,
+        encrypted: false,
+        tags: Vec::new(),
+        visibility: "private".to_string(),
+        allowed_content_types: Vec::new(),
+        expires: None,
+        expire_if_unused_days: None,
+        cache_control: None,
+        published: false,
+        collection_id: None,
+        description: None,
+    };
+    let file = match api::store_file(&pool, &config, &ip, new_file, body).await {
+        Ok(uploaded_file) => uploaded_file.file,
+        Err(response) => return translate(response),
+    };
+    info!("S3 object {} stored as {} by {}", key, file.id, user.username);
+    audit::record(&pool, audit::Action::Upload, Some(&user.username), Some(&file.id), &ip).await;
+
+    // the previous object with the key is replaced, it stays restorable from the trash
+    let replaced = match api::find_named(&pool, &user.username, &key).await {
+        Ok(objects) => objects,
+        Err(response) => return response,
+    };
+    for old in replaced.iter().filter(|old| old.id != file.id) {
+        if api::trash_file(&pool, &config, old).await.is_err() {
+            warn!("Could not trash replaced S3 object {}", old.id);
+        }
+    }
+
+    ([(header::ETAG, etag(&file))], "").into_response()
+}
+
+/// Handler for the S3 operations bitBeam does not implement, like multipart uploads
+/// This function answers with a `NotImplemented` error S3 clients understand.
+pub async fn unsupported() -> Response {
+    error(
+        StatusCode::NOT_IMPLEMENTED,
+        "NotImplemented",
+        "Only PutObject, GetObject, HeadObject, DeleteObject and ListObjectsV2 are supported",
+    )
+}
+
+/// Handler to read an object (S3 `GetObject`)
+/// This function returns the newest file of the user with the object key as its name.
+/// A `Range` header selects part of the object, ranged requests count as downloads too.
+/// It also logs the IP address of the client making the request.
+/// example request: aws --endpoint-url http://localhost:3000/s3 s3 cp s3://<username>/backups/backup.tar .
+/// requires the following headers:
+/// - authorization: an AWS Signature Version 4 with the username as access key ID and the key as secret (not optional)
+/// - range: a single byte range like `bytes=0-1023` (optional)
+///
+/// requires the following path parameters:
+/// - bucket: the username (not optional)
+/// - key: the object key (not optional)
+#[instrument(skip_all)]
+pub async fn get_object(
+    Path((bucket, key)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received S3 GetObject request for {} from IP: {}", key, ip);
+
+    let user = match authenticate(&pool, &method, &uri, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_bucket(&user, &bucket) {
+        return response;
+    }
+    let file = match api::find_named(&pool, &user.username, &key).await {
+        Ok(objects) => objects.into_iter().find(|file| file.is_available()),
+        Err(response) => return response,
+    };
+    let Some(file) = file else {
+        return error(StatusCode::NOT_FOUND, "NoSuchKey", "The object does not exist");
+    };
+
+    // the download is given back if the object is not sent to the end
+    let pending = match api::claim_download(&pool, &config, &file).await {
+        Ok(true) => api::PendingDownload::new(&pool, &config, &file, Some(&user.username), &ip, &headers),
+        Ok(false) => return error(StatusCode::NOT_FOUND, "NoSuchKey", "The download limit of the object was reached"),
+        Err(response) => return response,
+    };
+    let size = file.file_size.max(0) as u64;
+    let range = match headers.get(header::RANGE).and_then(|hv| hv.to_str().ok()) {
+        Some(value) => match api::byte_range(value, size as usize) {
+            Some(range) => Some(range.start as u64..range.end as u64),
+            None => {
+                return error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange", "The requested range is not satisfiable")
+            }
+        },
+        None => None,
+    };
+    let sent = range.clone().unwrap_or(0..size);
+
+    // plain blobs are streamed from disk, encrypted and compressed blobs can only be read whole
+    let plain = storage::blob_form(&config, file.blob_name()).await.is_some_and(storage::Form::is_plain);
+    let body = if plain {
+        match storage::stream_blob(&config, file.blob_name(), sent.clone()).await {
+            Ok(stream) => throttle::stream_body(stream),
+            Err(e) => {
+                error!("File read error {}: {}", file.id, e);
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "The object could not be read");
+            }
+        }
+    } else {
+        match storage::read_cached_blob(&config, file.blob_name(), size).await {
+            Ok(contents) if contents.len() as u64 == size => {
+                throttle::body(contents.slice(sent.start as usize..sent.end as usize))
+            }
+            Ok(contents) => {
+                error!("File read error {}: the blob has {} bytes instead of {}", file.id, contents.len(), size);
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "The object could not be read");
+            }
+            Err(e) => {
+                error!("File read error {}: {}", file.id, e);
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "The object could not be read");
+            }
+        }
+    };
+
+    let response = object_headers(&file);
+    let response = match range {
+        Some(range) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, size)),
+        None => response,
+    };
+    let length = (sent.end - sent.start) as usize;
+    let response = response.header(header::CONTENT_LENGTH, length);
+    built(response.body(pending.body(body, length)), &file.id)
+}
+
+/// Handler to inspect an object (S3 `HeadObject`)
+/// This function answers with the headers `GetObject` would have, the download is not counted.
+/// It also logs the IP address of the client making the request.
+/// example request: aws --endpoint-url http://localhost:3000/s3 s3api head-object --bucket <username> --key backups/backup.tar
+/// requires the following headers:
+/// - authorization: an AWS Signature Version 4 with the username as access key ID and the key as secret (not optional)
+///
+/// requires the following path parameters:
+/// - bucket: the username (not optional)
+/// - key: the object key (not optional)
+#[instrument(skip_all)]
+pub async fn head_object(
+    Path((bucket, key)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received S3 HeadObject request for {} from IP: {}", key, ip);
+
+    let user = match authenticate(&pool, &method, &uri, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_bucket(&user, &bucket) {
+        return response;
+    }
+    let file = match api::find_named(&pool, &user.username, &key).await {
+        Ok(objects) => objects.into_iter().find(|file| file.is_available()),
+        Err(response) => return response,
+    };
+    match file {
+        Some(file) => {
+            let response = object_headers(&file).header(header::CONTENT_LENGTH, file.file_size);
+            built(response.body(Body::empty()), &file.id)
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Handler to delete an object (S3 `DeleteObject`)
+/// This function moves every file of the user with the object key as its name to the trash.
+/// Like S3 it succeeds even if there is no such object.
+/// It also logs the IP address of the client making the request.
+/// example request: aws --endpoint-url http://localhost:3000/s3 s3 rm s3://<username>/backups/backup.tar
+/// requires the following headers:
+/// - authorization: an AWS Signature Version 4 with the username as access key ID and the key as secret (not optional)
+///
+/// requires the following path parameters:
+/// - bucket: the username (not optional)
+/// - key: the object key (not optional)
+#[instrument(skip_all)]
+pub async fn delete_object(
+    Path((bucket, key)): Path<(String, String)>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received S3 DeleteObject request for {} from IP: {}", key, ip);
+
+    let user = match authenticate(&pool, &method, &uri, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_bucket(&user, &bucket) {
+        return response;
+    }
+    let objects = match api::find_named(&pool, &user.username, &key).await {
+        Ok(objects) => objects,
+        Err(response) => return response,
+    };
+    for file in &objects {
+        if let Err(response) = api::trash_file(&pool, &config, file).await {
+            return response;
+        }
+        info!("S3 object {} deleted by owner {}: {}", key, user.username, file.id);
+        webhook::emit(webhook::EventKind::Deleted, file);
+        audit::record(&pool, audit::Action::Delete, Some(&user.username), Some(&file.id), &ip).await;
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Handler to list objects (S3 `ListObjectsV2`)
+/// This function lists the files of the user that aren't in the trash as objects, sorted by key.
+/// Files that share a name are listed once, as the newest of them.
+/// It also logs the IP address of the client making the request.
+/// example request: aws --endpoint-url http://localhost:3000/s3 s3 ls s3://<username>/backups/
+/// requires the following headers:
+/// - authorization: an AWS Signature Version 4 with the username as access key ID and the key as secret (not optional)
+///
+/// requires the following path parameter:
+/// - bucket: the username (not optional)
+///
+/// accepts the following query parameters:
+/// - list-type: must be `2` (not optional)
+/// - prefix: only list keys that start with it (optional)
+/// - delimiter: group keys that contain it after the prefix into common prefixes (optional)
+/// - max-keys: the most keys and common prefixes to return, at most 1000 (optional)
+/// - continuation-token: the `NextContinuationToken` of the previous page (optional)
+/// - start-after: only list keys after this one (optional)
+#[instrument(skip_all)]
+pub async fn list_objects(
+    Path(bucket): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::ListObjectsQuery>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received S3 ListObjectsV2 request for {} from IP: {}", bucket, ip);
+
+    let user = match authenticate(&pool, &method, &uri, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_bucket(&user, &bucket) {
+        return response;
+    }
+    if query.list_type.as_deref() != Some("2") {
+        return error(StatusCode::NOT_IMPLEMENTED, "NotImplemented", "Only ListObjectsV2 is supported");
+    }
+
+    let select = format!("SELECT {} FROM files WHERE owner = ? AND deleted_at IS NULL", data::File::COLUMNS);
+    let files = sqlx::query_as::<_, data::File>(&select)
+        .bind(&user.username)
+        .fetch_all(&pool)
+        .await;
+    let mut files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            error!("DB select error {}: {}", user.username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+    // sorted here, the collation of the database may not sort by bytes like S3 does
+    files.retain(|file| file.is_available());
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name).then(b.upload_time.cmp(&a.upload_time)));
+    files.dedup_by(|older, newer| older.file_name == newer.file_name);
+
+    let prefix = query.prefix.unwrap_or_default();
+    let delimiter = query.delimiter.filter(|delimiter| !delimiter.is_empty());
+    let max_keys = query.max_keys.unwrap_or(MAX_KEYS).min(MAX_KEYS);
+    // the continuation token is the last key or common prefix of the previous page
+    let after = match &query.continuation_token {
+        Some(token) => match BASE64_URL_SAFE_NO_PAD.decode(token).ok().and_then(|key| String::from_utf8(key).ok()) {
+            Some(key) => key,
+            None => return error(StatusCode::BAD_REQUEST, "InvalidArgument", "The continuation token is invalid"),
+        },
+        None => query.start_after.clone().unwrap_or_default(),
+    };
+
+    let mut contents = Vec::new();
+    let mut common_prefixes: Vec<String> = Vec::new();
+    let mut last = None;
+    let mut truncated = false;
+    for file in &files {
+        let key = &file.file_name;
+        if key.as_str() <= after.as_str() || !key.starts_with(&prefix) {
+            continue;
+        }
+        let common = delimiter.as_ref().and_then(|delimiter| {
+            let rest = &key[prefix.len()..];
+            rest.find(delimiter.as_str())
+                .map(|index| key[..prefix.len() + index + delimiter.len()].to_string())
+        });
+        // a common prefix is listed once, also across pages
+        if let Some(common) = &common {
+            if common.as_str() <= after.as_str() || common_prefixes.last() == Some(common) {
+                continue;
+            }
+        }
+        if contents.len() + common_prefixes.len() == max_keys {
+            truncated = true;
+            break;
+        }
+        match common {
+            Some(common) => {
+                last = Some(common.clone());
+                common_prefixes.push(common);
+            }
+            None => {
+                last = Some(key.clone());
+                contents.push(file);
+            }
+        }
+    }
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#);
+    let _ = write!(xml, "<Name>{}</Name><Prefix>{}</Prefix>", escape(&bucket), escape(&prefix));
+    let _ = write!(
+        xml,
+        "<KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        contents.len() + common_prefixes.len(),
+        max_keys,
+        truncated
+    );
+    if let Some(delimiter) = &delimiter {
+        let _ = write!(xml, "<Delimiter>{}</Delimiter>", escape(delimiter));
+    }
+    if let Some(token) = &query.continuation_token {
+        let _ = write!(xml, "<ContinuationToken>{}</ContinuationToken>", escape(token));
+    }
+    if let Some(start_after) = &query.start_after {
+        let _ = write!(xml, "<StartAfter>{}</StartAfter>", escape(start_after));
+    }
+    if let Some(last) = last.filter(|_| truncated) {
+        let _ = write!(
+            xml,
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            BASE64_URL_SAFE_NO_PAD.encode(last)
+        );
+    }
+    for file in contents {
+        let last_modified = DateTime::from_timestamp(file.upload_time, 0).unwrap_or_default();
+        let _ = write!(
+            xml,
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            escape(&file.file_name),
+            last_modified.format("%Y-%m-%dT%H:%M:%S.000Z"),
+            escape(&etag(file)),
+            file.file_size
+        );
+    }
+    for common in common_prefixes {
+        let _ = write!(xml, "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", escape(&common));
+    }
+    xml.push_str("</ListBucketResult>");
+
+    ([(header::CONTENT_TYPE, "application/xml")], xml).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_headers, canonical_query, canonical_request, signature_matches, string_to_sign};
+    use axum::http::{HeaderMap, HeaderValue, Method, Uri};
+
+    /// The secret access key of the AWS Signature Version 4 test suite.
+    const SUITE_SECRET: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+    /// The hash of an empty payload.
+    const EMPTY_PAYLOAD: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn query(uri: &str) -> String {
+        canonical_query(&uri.parse::<Uri>().unwrap())
+    }
+
+    #[test]
+    fn sorts_the_query_parameters() {
+        // get-vanilla-query-order-key-case
+        assert_eq!(query("/?Param2=value2&Param1=value1"), "Param1=value1&Param2=value2");
+        // get-vanilla-query-order-value
+        assert_eq!(query("/?Param1=value2&Param1=Value1"), "Param1=Value1&Param1=value2");
+        // get-vanilla-empty-query-key
+        assert_eq!(query("/?Param1=value1"), "Param1=value1");
+        assert_eq!(query("/"), "");
+    }
+
+    #[test]
+    fn encodes_the_query_parameters() {
+        // get-vanilla-query-unreserved
+        assert_eq!(
+            query("/?-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz=-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz"),
+            "-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz=-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz"
+        );
+        // get-vanilla-utf8-query, sent percent-encoded
+        assert_eq!(query("/?%E1%88%B4=bar"), "%E1%88%B4=bar");
+        assert_eq!(query("/?prefix=a%20b&delimiter=/"), "delimiter=%2F&prefix=a%20b");
+    }
+
+    #[test]
+    fn trims_and_joins_the_header_values() {
+        // get-header-value-trim
+        let trimmed = headers(&[
+            ("host", "example.amazonaws.com"),
+            ("my-header1", " value1"),
+            ("my-header2", " \"a   b   c\""),
+            ("x-amz-date", "20150830T123600Z"),
+        ]);
+        assert_eq!(
+            canonical_headers(&trimmed, "host;my-header1;my-header2;x-amz-date"),
+            "host:example.amazonaws.com\nmy-header1:value1\nmy-header2:\"a b c\"\nx-amz-date:20150830T123600Z\n"
+        );
+        // get-header-key-duplicate
+        let duplicate = headers(&[
+            ("host", "example.amazonaws.com"),
+            ("my-header1", "value2"),
+            ("my-header1", "value2"),
+            ("my-header1", "value1"),
+            ("x-amz-date", "20150830T123600Z"),
+        ]);
+        assert_eq!(
+            canonical_headers(&duplicate, "host;my-header1;x-amz-date"),
+            "host:example.amazonaws.com\nmy-header1:value2,value2,value1\nx-amz-date:20150830T123600Z\n"
+        );
+    }
+
+    #[test]
+    fn verifies_the_signature_of_get_vanilla() {
+        let uri: Uri = "/".parse().unwrap();
+        let headers = headers(&[("host", "example.amazonaws.com"), ("x-amz-date", "20150830T123600Z")]);
+        let canonical = canonical_request(&Method::GET, &uri, &headers, "host;x-amz-date", EMPTY_PAYLOAD);
+        let scope = "20150830/us-east-1/service/aws4_request";
+        let to_sign = string_to_sign("20150830T123600Z", scope, &canonical);
+
+        let signature = hex::decode("5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31").unwrap();
+        assert!(signature_matches(SUITE_SECRET, scope, &to_sign, &signature));
+    }
+
+    #[test]
+    fn verifies_the_signature_of_the_iam_example() {
+        let uri: Uri = "/?Action=ListUsers&Version=2010-05-08".parse().unwrap();
+        let headers = headers(&[
+            ("content-type", "application/x-www-form-urlencoded; charset=utf-8"),
+            ("host", "iam.amazonaws.com"),
+            ("x-amz-date", "20150830T123600Z"),
+        ]);
+        let signed_headers = "content-type;host;x-amz-date";
+        let canonical = canonical_request(&Method::GET, &uri, &headers, signed_headers, EMPTY_PAYLOAD);
+        assert_eq!(
+            canonical,
+            "GET\n/\nAction=ListUsers&Version=2010-05-08\n\
+             content-type:application/x-www-form-urlencoded; charset=utf-8\nhost:iam.amazonaws.com\n\
+             x-amz-date:20150830T123600Z\n\ncontent-type;host;x-amz-date\n"
+                .to_string()
+                + EMPTY_PAYLOAD
+        );
+        let scope = "20150830/us-east-1/iam/aws4_request";
+        let to_sign = string_to_sign("20150830T123600Z", scope, &canonical);
+        assert!(to_sign.ends_with("f536975d06c0309214f805bb90ccff089219ecd68b2577efef23edd43b7e1a59"));
+
+        let signature = hex::decode("5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7").unwrap();
+        assert!(signature_matches(SUITE_SECRET, scope, &to_sign, &signature));
+        assert!(!signature_matches("another secret", scope, &to_sign, &signature));
+        let other_day = to_sign.replace("20150830T123600Z", "20150831T123600Z");
+        assert!(!signature_matches(SUITE_SECRET, scope, &other_day, &signature));
+    }
+
+    #[test]
+    fn verifies_the_signature_of_the_s3_get_object_example() {
+        let uri: Uri = "/test.txt".parse().unwrap();
+        let headers = headers(&[
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"),
+            ("x-amz-content-sha256", EMPTY_PAYLOAD),
+            ("x-amz-date", "20130524T000000Z"),
+        ]);
+        let signed_headers = "host;range;x-amz-content-sha256;x-amz-date";
+        let canonical = canonical_request(&Method::GET, &uri, &headers, signed_headers, EMPTY_PAYLOAD);
+        let scope = "20130524/us-east-1/s3/aws4_request";
+        let to_sign = string_to_sign("20130524T000000Z", scope, &canonical);
+
+        let signature = hex::decode("f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41").unwrap();
+        assert!(signature_matches("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY", scope, &to_sign, &signature));
+        assert!(!signature_matches(SUITE_SECRET, scope, &to_sign, &signature));
+    }
+}
@@ -0,0 +1,243 @@
+use axum::http::uri::Authority;
+use sqlx::AnyPool;
+use std::net::IpAddr;
+use tracing::{error, info, warn};
+
+use crate::data;
+use crate::db;
+use crate::storage;
+use crate::tls;
+
+/// The table the database check creates, writes to and drops again.
+const PROBE_TABLE: &str = "bitbeam_self_check";
+
+/// This enum represents how a check of the self check went.
+/// A warning is logged but never keeps the server from starting.
+#[derive(PartialEq)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// This struct represents the result of one check, with a hint on how to fix it if it did not pass.
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    message: String,
+    hint: &'static str,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: String) -> Self {
+        Check { name, outcome: Outcome::Pass, message, hint: "" }
+    }
+
+    fn warn(name: &'static str, message: String, hint: &'static str) -> Self {
+        Check { name, outcome: Outcome::Warn, message, hint }
+    }
+
+    fn fail(name: &'static str, message: String, hint: &'static str) -> Self {
+        Check { name, outcome: Outcome::Fail, message, hint }
+    }
+
+    /// Logs the check as one line of the report.
+    fn log(&self) {
+        match self.outcome {
+            Outcome::Pass => info!("Self check [pass] {}: {}", self.name, self.message),
+            Outcome::Warn => warn!("Self check [warn] {}: {}, {}", self.name, self.message, self.hint),
+            Outcome::Fail => error!("Self check [FAIL] {}: {}, {}", self.name, self.message, self.hint),
+        }
+    }
+}
+
+/// This function checks that the server can work before it starts:
+/// that the database takes writes, that the data path is writable and has `self_check_min_free_bytes` free,
+/// that the TLS certificate and key load and that `base_url` makes working links.
+/// Every check is logged as a line of a report with a hint on how to fix it.
+/// With `self_check` set to `strict` it returns false if a check failed and the server must not start,
+/// with `warn` the server starts anyway with what works, `off` skips the checks.
+/// Low free space and a `base_url` other machines can't reach are only warnings.
+pub async fn run(pool: &AnyPool, config: &data::Config) -> bool {
+    if config.self_check == "off" {
+        return true;
+    }
+    let mut checks = vec![check_database(pool, config).await, check_data_path(config).await];
+    // the free space is only measured once the data path is known to exist
+    if checks[1].outcome == Outcome::Pass {
+        checks.push(check_free_space(config));
+    }
+    if let Some(check) = check_replica(config).await {
+        checks.push(check);
+    }
+    if let Some(check) = check_tls(config).await {
+        checks.push(check);
+    }
+    checks.push(check_base_url(config));
+
+    for check in &checks {
+        check.log();
+    }
+    let count = |outcome: Outcome| checks.iter().filter(|check| check.outcome == outcome).count();
+    let failed = count(Outcome::Fail);
+    info!(
+        "Self check finished: {} passed, {} warnings, {} failed",
+        count(Outcome::Pass),
+        count(Outcome::Warn),
+        failed
+    );
+    if failed == 0 {
+        return true;
+    }
+    if config.self_check == "warn" {
+        warn!("Starting with failed self checks because self_check is warn, some requests will fail");
+        return true;
+    }
+    error!("Refusing to start, fix the failed checks or set self_check to warn to start anyway");
+    false
+}
+
+/// This function reports a database that could not be connected to in the format of the self check.
+/// Without a database nothing works, so the server never starts this way, whatever `self_check` is.
+pub fn database_unreachable(config: &data::Config, e: &sqlx::Error) {
+    let hint = match config.db_type.as_str() {
+        "sqlite" => "check that the directory of database_url exists and the user bitBeam runs as may write to it",
+        _ => "check database_url and that the database server runs and can be reached from this host",
+    };
+    Check::fail("database", format!("could not connect to the {} database: {}", config.db_type, e), hint).log();
+    error!("Refusing to start without a database");
+}
+
+/// Checks that the database answers and that its user may create tables and write to them,
+/// which the schema migration at startup needs.
+async fn check_database(pool: &AnyPool, config: &data::Config) -> Check {
+    if let Err(e) = sqlx::query("SELECT 1").execute(pool).await {
+        return Check::fail(
+            "database",
+            format!("the {} database does not answer: {}", config.db_type, e),
+            "check that the database server runs and is not overloaded",
+        );
+    }
+    let probe = async {
+        let create = format!("CREATE TABLE IF NOT EXISTS {} (id INTEGER NOT NULL)", PROBE_TABLE);
+        sqlx::query(&db::ddl(pool, &create)).execute(pool).await?;
+        sqlx::query(&format!("INSERT INTO {} (id) VALUES (1)", PROBE_TABLE))
+            .execute(pool)
+            .await?;
+        sqlx::query(&format!("DROP TABLE {}", PROBE_TABLE)).execute(pool).await
+    };
+    match probe.await {
+        Ok(_) => Check::pass("database", format!("connected to {} and can write", config.db_type)),
+        Err(e) => Check::fail(
+            "database",
+            format!("the database user can't create tables or write rows: {}", e),
+            "grant the user of database_url the rights to create, change and write tables",
+        ),
+    }
+}
+
+/// Checks that blobs can be written to the data path, it is created if it does not exist.
+async fn check_data_path(config: &data::Config) -> Check {
+    match storage::check_writable(config).await {
+        Ok(()) => Check::pass("data_path", format!("{} is writable", config.data_path)),
+        Err(e) => Check::fail(
+            "data_path",
+            format!("can't write to {}: {}", config.data_path, e),
+            "create the directory and give the user bitBeam runs as write access, or set data_path to another one",
+        ),
+    }
+}
+
+/// Checks that the file system of the data path has at least `self_check_min_free_bytes` free.
+fn check_free_space(config: &data::Config) -> Check {
+    let free = match storage::free_bytes(&config.data_path) {
+        Ok(free) => free,
+        Err(e) => {
+            return Check::warn(
+                "free_space",
+                format!("could not measure the free space of {}: {}", config.data_path, e),
+                "uploads fail once the disk is full",
+            )
+        }
+    };
+    match free >= config.self_check_min_free_bytes {
+        true => Check::pass("free_space", format!("{} bytes free in {}", free, config.data_path)),
+        false => Check::warn(
+            "free_space",
+            format!(
+                "only {} bytes free in {}, less than self_check_min_free_bytes of {}",
+                free, config.data_path, config.self_check_min_free_bytes
+            ),
+            "free up space, move data_path to a larger disk or limit the store with max_store_bytes",
+        ),
+    }
+}
+
+/// Checks that blobs can be copied to the replica, `None` if no `replica_path` is set.
+/// Uploads work without it, so a replica that can't be written to is only a warning.
+async fn check_replica(config: &data::Config) -> Option<Check> {
+    let root = config.replica_path.as_ref()?;
+    Some(match storage::check_writable_below(root).await {
+        Ok(()) => Check::pass("replica_path", format!("{} is writable", root)),
+        Err(e) => Check::warn(
+            "replica_path",
+            format!("can't write to {}: {}", root, e),
+            "new blobs are only kept in data_path until the replica can be written to",
+        ),
+    })
+}
+
+/// Checks that the TLS certificate and key load, `None` if the server does not terminate TLS itself.
+async fn check_tls(config: &data::Config) -> Option<Check> {
+    let (true, Some(cert), Some(key)) = (config.use_tls, &config.tls_cert, &config.tls_key) else {
+        return None;
+    };
+    Some(match tls::load(cert, key).await {
+        Ok(_) => Check::pass("tls", format!("loaded the certificate {} and its key", cert)),
+        Err(e) => Check::fail(
+            "tls",
+            format!("could not load the certificate {} with the key {}: {}", cert, key, e),
+            "check that tls_cert is a PEM certificate chain and tls_key the PEM private key that belongs to it",
+        ),
+    })
+}
+
+/// Checks that `base_url` is a host with an optional port, the form links are built from,
+/// and warns if it points at this machine while other machines can connect.
+fn check_base_url(config: &data::Config) -> Check {
+    let hint = "set base_url to the host name and port clients reach the server at, like files.example.com, \
+        use_tls picks http or https and base_path the path";
+    if config.base_url.contains("://") {
+        return Check::fail("base_url", format!("{} includes a scheme", config.base_url), hint);
+    }
+    let authority = match config.base_url.parse::<Authority>() {
+        Ok(authority) if !config.base_url.contains('@') => authority,
+        _ => {
+            return Check::fail(
+                "base_url",
+                format!("{} is not a host name with an optional port", config.base_url),
+                hint,
+            )
+        }
+    };
+    let is_local = |host: &str| {
+        host == "localhost"
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    };
+    if is_local(authority.host()) && !is_local(&config.listener_addr) {
+        return Check::warn(
+            "base_url",
+            format!("links point at {}, which other machines can't reach", config.base_url),
+            "set base_url to the public host name of the server",
+        );
+    }
+    let scheme = if config.use_tls { "https" } else { "http" };
+    Check::pass(
+        "base_url",
+        format!("links point at {}://{}{}", scheme, config.base_url, config.base_path),
+    )
+}
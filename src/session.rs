@@ -0,0 +1,267 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::AnyPool;
+use std::sync::OnceLock;
+use tracing::{error, info, instrument, warn};
+
+use crate::access_log;
+use crate::audit;
+use crate::data;
+use crate::db;
+use crate::lockout;
+use std::net::SocketAddr;
+
+/// The name of the cookie that carries the session.
+const COOKIE_NAME: &str = "bitbeam_session";
+
+/// The key session cookies are signed with, it is set once at startup.
+static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// This function sets the key session cookies are signed with.
+/// Without a configured `session_secret` a random key is used,
+/// sessions then end when the server restarts.
+pub fn init(config: &data::Config) {
+    let secret = match &config.session_secret {
+        Some(secret) => secret.as_bytes().to_vec(),
+        None => {
+            info!("No session_secret configured, sessions end when the server restarts");
+            rand::rng().random::<[u8; 32]>().to_vec()
+        }
+    };
+    if SECRET.set(secret).is_err() {
+        warn!("Session secret already set");
+    }
+}
+
+/// Returns a MAC keyed with the session secret.
+fn mac() -> Hmac<Sha256> {
+    let secret = SECRET.get().map(Vec::as_slice).unwrap_or_default();
+    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+/// Returns the cookie value of a session, the ID followed by its hex encoded signature.
+/// Other short lived links like stream grants are signed the same way.
+pub(crate) fn sign(id: &str) -> String {
+    let mut mac = mac();
+    mac.update(id.as_bytes());
+    format!("{}.{}", id, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns the session ID of a cookie value if its signature is valid.
+pub(crate) fn verify(value: &str) -> Option<&str> {
+    let (id, signature) = value.rsplit_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    let mut mac = mac();
+    mac.update(id.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+    Some(id)
+}
+
+/// Returns the signed session ID from the `cookie` headers of a request, if there is one.
+/// Cookies with a forged or broken signature are ignored.
+pub(crate) fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .and_then(|(_, value)| verify(value))
+}
+
+/// Helper to look up the user a session belongs to
+/// It returns the user if the session exists and has not expired,
+/// or a ready-made error response otherwise.
+pub(crate) async fn user_by_session(pool: &AnyPool, id: &str) -> Result<data::User, Response> {
+    let user = sqlx::query_as::<_, data::User>(
+        r#"
+        SELECT users.*
+        FROM sessions
+        JOIN users ON users.username = sessions.username
+        WHERE sessions.id = ? AND sessions.expires > ?
+        "#,
+    )
+    .bind(id)
+    .bind(Utc::now().timestamp())
+    .fetch_optional(pool)
+    .await;
+    match user {
+        Ok(Some(user)) => {
+            info!("Session of {} found in DB", user.username);
+            access_log::set_user(&user.username);
+            Ok(user)
+        }
+        Ok(None) => {
+            // the cookie is signed, so this is a session that expired or was logged out, not a guess
+            info!("Session not found or expired");
+            Err((StatusCode::UNAUTHORIZED, "Your session is not valid, log in again").into_response())
+        }
+        Err(e) => {
+            error!("DB select session error: {}", e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Returns the `set-cookie` value of a session cookie that lives for `max_age` seconds.
+/// The cookie is hidden from scripts and never sent along with requests from other sites,
+/// which keeps other pages from acting with the session.
+/// It is only sent over HTTPS when the server serves TLS itself or sits behind a proxy that does, see `base_url`.
+fn cookie(config: &data::Config, value: &str, max_age: u64) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+        COOKIE_NAME, value, max_age
+    );
+    if config.use_tls || config.base_url.starts_with("https://") {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// Handler to log a user in
+/// This function checks the username and password of a user
+/// and starts a session that is kept in a signed cookie.
+/// Every handler that accepts the `key` header also accepts the session cookie.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -c cookies.txt -H "username: <username>" -H "password: <password>" http://localhost:3000/user/login
+/// example JSON request: curl -X POST -c cookies.txt -H "content-type: application/json" -d '{"username": "<username>", "password": "<password>"}' http://localhost:3000/user/login
+/// requires the following headers or JSON fields:
+/// - username: the username of the user (not optional)
+/// - password: the password of the user (not optional)
+#[instrument(skip_all)]
+pub async fn login(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    credentials: data::Credentials,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received login from IP: {}", ip);
+
+    let (Some(username), Some(password)) = (credentials.username.as_deref(), credentials.password.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Username and password required").into_response();
+    };
+    if let Some(response) = lockout::check(&ip, Some(username)) {
+        return response;
+    }
+
+    let user = sqlx::query_as::<_, data::User>(
+        r#"
+        SELECT *
+        FROM users
+        WHERE username = ? AND password = ?
+        "#,
+    )
+    .bind(username)
+    .bind(password)
+    .fetch_optional(&pool)
+    .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            warn!("Failed login for {}", username);
+            lockout::failed(&ip, Some(username));
+            audit::record_failed_login(&pool, None, Some(username), &ip);
+            return (StatusCode::UNAUTHORIZED, "Wrong username or password").into_response();
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    // forget sessions that ran out, there is no other place that cleans them up
+    let now = Utc::now().timestamp();
+    if let Err(e) = sqlx::query("DELETE FROM sessions WHERE expires <= ?")
+        .bind(now)
+        .execute(&pool)
+        .await
+    {
+        warn!("DB delete expired sessions error: {}", e);
+    }
+
+    let id = hex::encode(rand::rng().random::<[u8; 32]>());
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO sessions
+            (id, username, expires)
+        VALUES (?, ?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(&user.username)
+    .bind(now.saturating_add(config.session_ttl as i64))
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert session error {}: {}", user.username, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    lockout::succeeded(&user.username);
+    info!("User logged in: {}", user.username);
+    audit::record(&pool, audit::Action::Login, Some(&user.username), None, &ip).await;
+
+    (
+        [(header::SET_COOKIE, cookie(&config, &sign(&id), config.session_ttl))],
+        Json(json!({
+            "username": user.username,
+        })),
+    )
+        .into_response()
+}
+
+/// Handler to log a user out
+/// This function ends the session of the request and clears the session cookie.
+/// It succeeds even if there is no session, so a stale cookie can always be cleared.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -b cookies.txt http://localhost:3000/user/logout
+#[instrument(skip_all)]
+pub async fn logout(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received logout from IP: {}", ip);
+
+    if let Some(id) = session_id(&headers) {
+        // MySQL has no RETURNING, the owner of the session is looked up first
+        let end_session = async {
+            let username = sqlx::query_scalar::<_, String>("SELECT username FROM sessions WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&pool)
+                .await?;
+            let deleted = sqlx::query("DELETE FROM sessions WHERE id = ?")
+                .bind(id)
+                .execute(&pool)
+                .await?;
+            Ok::<_, sqlx::Error>(username.filter(|_| deleted.rows_affected() > 0))
+        };
+        match end_session.await {
+            Ok(Some(username)) => {
+                info!("User logged out: {}", username);
+                audit::record(&pool, audit::Action::Logout, Some(&username), None, &ip).await;
+            }
+            Ok(None) => info!("Session already ended"),
+            Err(e) => {
+                error!("DB delete session error: {}", e);
+                return db::error_response(&e, "Database delete error");
+            }
+        }
+    }
+
+    ([(header::SET_COOKIE, cookie(&config, "", 0))], StatusCode::NO_CONTENT).into_response()
+}
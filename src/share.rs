@@ -0,0 +1,267 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::collections;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::session;
+use crate::tokens;
+use std::net::SocketAddr;
+
+/// Returns the normalized visibility, or `None` if it is not one a file can have.
+pub(crate) fn parse_visibility(visibility: &str) -> Option<String> {
+    let visibility = visibility.trim().to_ascii_lowercase();
+    (visibility == "public" || visibility == "private").then_some(visibility)
+}
+
+/// Returns the response for an unknown visibility.
+pub(crate) fn invalid_visibility() -> Response {
+    (StatusCode::BAD_REQUEST, "Invalid visibility, use public or private").into_response()
+}
+
+/// Returns true if the request carries a key header or a session cookie.
+pub(crate) fn has_credentials(headers: &HeaderMap) -> bool {
+    headers.contains_key("key") || session::session_id(headers).is_some()
+}
+
+/// Helper to check whether a request may read a file
+/// Public files can be read by anybody with the link.
+/// Private files need the key or session of their owner,
+/// a share token issued for the file or a collection token of a collection it is in.
+/// It returns a ready-made error response if the request may not read the file.
+pub(crate) async fn check_access(
+    pool: &AnyPool,
+    file: &data::File,
+    headers: &HeaderMap,
+    token: Option<&str>,
+    ip: &str,
+) -> Result<(), Response> {
+    if !file.is_private() {
+        return Ok(());
+    }
+    // a collection token reaches the private files of its collection
+    if let Some(token) = token.filter(|token| token.starts_with(tokens::TOKEN_PREFIX)) {
+        let granted = match tokens::find_collection_token(pool, token).await {
+            Ok(Some(found)) if found.username == file.owner => {
+                let collection_id = found.collection_id.unwrap_or_default();
+                collections::contains(pool, &collection_id, &file.id).await
+            }
+            Ok(_) => Ok(false),
+            Err(e) => Err(e),
+        };
+        match granted {
+            Ok(true) => return Ok(()),
+            Ok(false) => warn!("Invalid collection token for {} from {}", file.id, ip),
+            Err(e) => {
+                error!("DB select collection token error {}: {}", file.id, e);
+                return Err(db::error_response(&e, "Database select error"));
+            }
+        }
+    } else if let Some(token) = token {
+        let granted = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*)
+            FROM share_tokens
+            WHERE token = ? AND file_id = ?
+            "#,
+        )
+        .bind(token)
+        .bind(&file.id)
+        .fetch_one(pool)
+        .await;
+        match granted {
+            Ok(count) if count > 0 => return Ok(()),
+            Ok(_) => warn!("Invalid share token for {} from {}", file.id, ip),
+            Err(e) => {
+                error!("DB select share token error {}: {}", file.id, e);
+                return Err(db::error_response(&e, "Database select error"));
+            }
+        }
+    }
+    if has_credentials(headers) {
+        let user = api::authenticate(pool, headers, ip).await?;
+        if user.username == file.owner {
+            return Ok(());
+        }
+        warn!("User {} tried to read private file {} owned by {}", user.username, file.id, file.owner);
+    }
+    Err((StatusCode::FORBIDDEN, "This file is private").into_response())
+}
+
+/// Helper to issue a share token for a file
+/// It returns the token, or a ready-made error response if it could not be stored.
+pub(crate) async fn issue_token(pool: &AnyPool, file: &data::File) -> Result<String, Response> {
+    let token = hex::encode(rand::rng().random::<[u8; 32]>());
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO share_tokens
+            (token, file_id, created)
+        VALUES (?, ?, ?)
+        "#,
+    )
+    .bind(&token)
+    .bind(&file.id)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await
+    {
+        error!("DB insert share token error {}: {}", file.id, e);
+        return Err(db::error_response(&e, "Database insert error"));
+    }
+    Ok(token)
+}
+
+/// Returns the download URL of a file with a share token attached.
+pub(crate) fn token_url(file: &data::File, token: &str) -> String {
+    format!("{}?token={}", file.download_url, token)
+}
+
+/// Helper to find a file that is owned by the user of the request.
+async fn owned_file(pool: &AnyPool, uuid: &str, user: &data::User) -> Result<data::File, Response> {
+    let file = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE id = ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(uuid)
+    .fetch_optional(pool)
+    .await;
+    match file {
+        Ok(Some(file)) if file.owner == user.username => Ok(file),
+        Ok(Some(file)) => {
+            warn!("User {} tried to share file {} owned by {}", user.username, uuid, file.owner);
+            Err((StatusCode::FORBIDDEN, "You do not own this file").into_response())
+        }
+        Ok(None) => {
+            info!("File not found in DB: {}", uuid);
+            Err((StatusCode::NOT_FOUND, "File not found").into_response())
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Handler to issue a share token for a file
+/// This function creates a token that lets anybody who has it download a private file.
+/// Downloads with a token still count against the download limit.
+/// Only the owner of the file can issue tokens.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" http://localhost:3000/file/<uuid>/share
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn create_token(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received share token request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let file = match owned_file(&pool, &uuid, &user).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    match issue_token(&pool, &file).await {
+        Ok(token) => {
+            info!("Share token issued for {}", uuid);
+            Json(json!({
+                "id": file.id,
+                "download_url": token_url(&file, &token),
+                "token": token,
+            }))
+            .into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Handler to revoke the share tokens of a file
+/// This function deletes every share token of a file,
+/// links that carry one of them stop working.
+/// Only the owner of the file can revoke tokens.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/file/<uuid>/share
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn revoke_tokens(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received share token revocation for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let file = match owned_file(&pool, &uuid, &user).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    match sqlx::query(
+        r#"
+        DELETE FROM share_tokens
+        WHERE file_id = ?
+        "#,
+    )
+    .bind(&file.id)
+    .execute(&pool)
+    .await
+    {
+        Ok(result) => {
+            info!("{} share tokens of {} revoked", result.rows_affected(), uuid);
+            Json(json!({
+                "id": file.id,
+                "revoked": result.rows_affected(),
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("DB delete share tokens error {}: {}", uuid, e);
+            db::error_response(&e, "Database delete error")
+        }
+    }
+}
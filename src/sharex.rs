@@ -0,0 +1,66 @@
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde_json::json;
+use tracing::{info, instrument};
+
+use crate::api;
+use crate::data;
+use crate::extract::AuthUser;
+use std::net::SocketAddr;
+
+/// Handler to download a ShareX custom uploader config
+/// This function returns a `.sxcu` file that makes ShareX upload
+/// screenshots and files to this server with the key of the requesting user.
+/// The key is taken from the `key` header or the session, never from the URL,
+/// so it does not end up in the logs of proxies or in the browser history.
+/// ShareX sends the file as a multipart form, which `/upload` already accepts,
+/// and reads the link from the `download_url` of the JSON response.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -O -J -H "key: <key>" http://localhost:3000/sharex?download_limit=10
+/// requires the following headers:
+/// - key: the key of the user, or the session cookie (not optional)
+///
+/// requires the following query parameters:
+/// - download_limit: the download limit of uploaded files (optional)
+#[instrument(skip_all)]
+pub async fn sharex_config(
+    AuthUser { user, .. }: AuthUser,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::ShareXQuery>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received ShareX config request from IP: {}", ip);
+
+    let sxcu = json!({
+        "Version": "15.0.0",
+        "Name": format!("bitBeam ({})", config.base_url),
+        "DestinationType": "ImageUploader, TextUploader, FileUploader",
+        "RequestMethod": "POST",
+        "RequestURL": api::public_url(&config, "upload"),
+        "Headers": {
+            "key": user.key,
+            "download_limit": query.download_limit.unwrap_or(config.default_download_limit).to_string(),
+        },
+        "Body": "MultipartFormData",
+        "FileFormName": "file",
+        "URL": "{json:download_url}",
+        "ErrorMessage": "{response}",
+    });
+
+    // keep the file name safe for every file system
+    let name = config.base_url.replace(|c: char| !c.is_ascii_alphanumeric() && c != '.', "_");
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"bitbeam-{}.sxcu\"", name),
+        )],
+        Json(sxcu),
+    )
+        .into_response()
+}
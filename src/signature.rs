@@ -0,0 +1,297 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use pgp::composed::{ArmorOptions, Deserializable, DetachedSignature, SignedPublicKey};
+use pgp::types::KeyDetails;
+use serde_json::{json, Value};
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::changes;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::ids;
+use crate::share;
+use crate::storage;
+use crate::tokens;
+
+/// The largest detached signature that is accepted, real ones are a few hundred bytes.
+const MAX_SIGNATURE_SIZE: usize = 64 * 1024;
+
+/// Parses a detached signature, ASCII armored like `gpg --armor --detach-sign` writes it or binary.
+fn parse_signature(body: &[u8]) -> Option<DetachedSignature> {
+    match body.trim_ascii_start().starts_with(b"-----BEGIN") {
+        true => DetachedSignature::from_string(std::str::from_utf8(body).ok()?)
+            .ok()
+            .map(|(signature, _)| signature),
+        false => DetachedSignature::from_bytes(body).ok(),
+    }
+}
+
+/// Parses an ASCII armored public key and checks the signatures that bind its subkeys and user IDs.
+fn parse_public_key(armored: &str) -> Option<SignedPublicKey> {
+    let (key, _) = SignedPublicKey::from_string(armored).ok()?;
+    key.verify_bindings().ok()?;
+    Some(key)
+}
+
+/// Returns the fingerprint of the key that made the signature over the contents,
+/// the primary key or one of its subkeys, `None` if none of them did.
+fn verify(key: &SignedPublicKey, signature: &DetachedSignature, contents: &[u8]) -> Option<String> {
+    if signature.verify(&key.primary_key, contents).is_ok() {
+        return Some(format!("{:X}", key.primary_key.fingerprint()));
+    }
+    key.public_subkeys
+        .iter()
+        .find(|subkey| signature.verify(&subkey.key, contents).is_ok())
+        .map(|subkey| format!("{:X}", subkey.key.fingerprint()))
+}
+
+/// Handler to attach a detached signature to a file
+/// This function stores a detached OpenPGP signature of the file, served at `/download/<uuid>.sig`,
+/// so release artifacts can be checked with `gpg --verify`.
+/// If the owner registered a public key at `/user/pgp_key` the signature must be made with it,
+/// the file is then marked as `verified`. Without a public key the signature is stored unchecked.
+/// A new signature replaces the old one, a new version of the file removes it.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" --data-binary @release.tar.gz.asc http://localhost:3000/file/<uuid>/signature
+/// requires the following headers:
+/// - key: the key of the owner or a token with the `upload` scope (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// requires the following body:
+/// - the detached signature, ASCII armored or binary (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn upload_signature(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received signature upload for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let (user, token) = tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await?;
+    let file = match api::find_file(&pool, "id", &uuid).await? {
+        Some(file) if file.owner == user.username => file,
+        Some(file) => {
+            warn!("User {} tried to sign file {} owned by {}", user.username, uuid, file.owner);
+            return Err(ApiError::Forbidden("You do not own this file".to_string()));
+        }
+        None => return Err(ApiError::NotFound("File not found".to_string())),
+    };
+    tokens::check_collection(&pool, token.as_ref(), &file.id).await?;
+
+    if body.len() > MAX_SIGNATURE_SIZE {
+        return Err(ApiError::TooLarge(MAX_SIGNATURE_SIZE as u64));
+    }
+    let Some(signature) = parse_signature(&body) else {
+        return Err(ApiError::BadRequest("The body is not an OpenPGP signature".to_string()));
+    };
+    let armored = match signature.to_armored_string(ArmorOptions::default()) {
+        Ok(armored) => armored,
+        Err(e) => {
+            error!("Signature armor error {}: {}", uuid, e);
+            return Err(ApiError::Internal("Signature encoding error"));
+        }
+    };
+
+    // only a signature made with the key of the owner marks the file as verified
+    let fingerprint = match user.pgp_key.as_deref().and_then(parse_public_key) {
+        Some(key) => {
+            let contents = match storage::read_blob(&config, file.blob_name(), file.file_size.max(0) as u64).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("File read error {}: {}", uuid, e);
+                    return Err(ApiError::Internal("File read error"));
+                }
+            };
+            // large files take a while to hash, it doesn't hold up other requests
+            let verified = tokio::task::spawn_blocking(move || verify(&key, &signature, &contents)).await;
+            match verified {
+                Ok(Some(fingerprint)) => Some(fingerprint),
+                Ok(None) => {
+                    warn!("Signature of {} does not match the key of {}", uuid, user.username);
+                    return Err(ApiError::Rejected {
+                        status: StatusCode::UNPROCESSABLE_ENTITY,
+                        error: "signature_invalid",
+                        message: "The signature was not made over this file with your public key".to_string(),
+                        details: Value::Null,
+                    });
+                }
+                Err(e) => {
+                    error!("Signature verification error {}: {}", uuid, e);
+                    return Err(ApiError::Internal("Signature verification error"));
+                }
+            }
+        }
+        None => None,
+    };
+
+    let store = async {
+        let mut transaction = pool.begin().await?;
+        sqlx::query("DELETE FROM file_signatures WHERE file_id = ?")
+            .bind(&uuid)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO file_signatures
+                (file_id, signature, fingerprint, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&uuid)
+        .bind(&armored)
+        .bind(&fingerprint)
+        .bind(Utc::now().timestamp())
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query("UPDATE files SET verified = ? WHERE id = ?")
+            .bind(fingerprint.is_some() as i32)
+            .bind(&uuid)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await
+    };
+    if let Err(e) = store.await {
+        error!("DB insert signature error {}: {}", uuid, e);
+        return Err(db::error(&e, "Database insert error"));
+    }
+    cache::forget_file(&uuid).await;
+    changes::record(&pool, &file.owner, &uuid, changes::Change::Updated).await;
+    info!("Signature of {} stored, verified with {:?}", uuid, fingerprint);
+    audit::record(&pool, audit::Action::UploadSignature, Some(&user.username), Some(&uuid), &ip).await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "id": uuid,
+            "verified": fingerprint.is_some(),
+            "fingerprint": fingerprint,
+        })),
+    )
+        .into_response())
+}
+
+/// Helper to serve the detached signature of a file at `/download/<uuid>.sig`
+/// Anyone who may download the file may fetch its signature, fetching it does not count as a download.
+pub(crate) async fn download_signature(
+    pool: &AnyPool,
+    uuid: &str,
+    ip: &str,
+    headers: &HeaderMap,
+    token: Option<&str>,
+) -> Result<Response, ApiError> {
+    if !ids::is_valid_file_id(uuid) {
+        return Err(ids::invalid_file_id(uuid));
+    }
+    let Some(file) = api::find_file(pool, "id", uuid).await? else {
+        return Err(ApiError::NotFound("File not found".to_string()));
+    };
+    if !file.is_available() {
+        return Err(api::gone(&file));
+    }
+    share::check_access(pool, &file, headers, token, ip).await?;
+
+    let signature = sqlx::query_scalar::<_, db::Text>("SELECT signature FROM file_signatures WHERE file_id = ?")
+        .bind(uuid)
+        .fetch_optional(pool)
+        .await;
+    match signature {
+        Ok(Some(signature)) => Ok((
+            [
+                (header::CONTENT_TYPE, "application/pgp-signature".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    api::content_disposition("attachment", &format!("{}.sig", file.file_name), &format!("{}.sig", file.id)),
+                ),
+            ],
+            String::from(signature),
+        )
+            .into_response()),
+        Ok(None) => Err(ApiError::NotFound("The file has no signature".to_string())),
+        Err(e) => {
+            error!("DB select signature error {}: {}", uuid, e);
+            Err(db::error(&e, "Database select error"))
+        }
+    }
+}
+
+/// Handler to set the OpenPGP public key of a user
+/// This function stores the ASCII armored public key signatures of the user's files are checked against.
+/// Signatures made with the key or one of its subkeys mark their file as verified.
+/// Files signed before the key changed keep their flag.
+/// An empty or missing key removes it.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" -H "content-type: application/json" -d "{\"public_key\": \"$(gpg --armor --export <id> | sed -z 's/\n/\\n/g')\"}" http://localhost:3000/user/pgp_key
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following JSON body:
+/// - public_key: the ASCII armored public key (optional, removes the key if missing)
+#[instrument(skip_all)]
+pub async fn set_pgp_key(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::PgpKeyRequest>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received PGP key update from IP: {}", ip);
+
+    let user = api::authenticate(&pool, &headers, &ip).await?;
+
+    let public_key = request.public_key.filter(|key| !key.trim().is_empty());
+    let fingerprint = match &public_key {
+        Some(public_key) => match parse_public_key(public_key) {
+            Some(key) => Some(format!("{:X}", key.primary_key.fingerprint())),
+            None => return Err(ApiError::BadRequest("The key is not an ASCII armored OpenPGP public key".to_string())),
+        },
+        None => None,
+    };
+
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE users
+        SET pgp_key = ?
+        WHERE "key" = ?
+        "#,
+    )
+    .bind(&public_key)
+    .bind(&user.key)
+    .execute(&pool)
+    .await
+    {
+        error!("DB update error {}: {}", user.username, e);
+        return Err(db::error(&e, "Database update error"));
+    }
+    cache::forget_user(&pool, &user.username).await;
+    info!("PGP key of {} set to {:?}", user.username, fingerprint);
+    audit::record(&pool, audit::Action::SetPgpKey, Some(&user.username), fingerprint.as_deref(), &ip).await;
+
+    Ok(Json(json!({
+        "username": user.username,
+        "fingerprint": fingerprint,
+    }))
+    .into_response())
+}
@@ -0,0 +1,119 @@
+use axum::{
+    extract::{ConnectInfo, Query},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use chrono::Utc;
+use futures_util::stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::{Arc, LazyLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, instrument, warn};
+
+use crate::data;
+use crate::error::ApiError;
+use crate::extract::{AuthUser, ListScope};
+use crate::notify;
+use crate::webhook::EventKind;
+use std::net::SocketAddr;
+
+/// The number of events a slow listener can fall behind before it misses some.
+const STREAM_SIZE: usize = 256;
+
+/// This struct is the data of a message on the event stream, the same as the body of a webhook.
+#[derive(Serialize)]
+struct FileEvent {
+    event: EventKind,
+    timestamp: i64,
+    file: data::File,
+}
+
+/// The file events of this instance, every open stream subscribes to it.
+static STREAM: LazyLock<broadcast::Sender<Arc<FileEvent>>> = LazyLock::new(|| broadcast::channel(STREAM_SIZE).0);
+
+/// This function hands a file event to the open event streams.
+/// It never blocks and does nothing if nobody is listening.
+pub(crate) fn publish(kind: EventKind, file: &data::File) {
+    if STREAM.receiver_count() == 0 {
+        return;
+    }
+    let event = FileEvent {
+        event: kind,
+        timestamp: Utc::now().timestamp(),
+        file: file.clone(),
+    };
+    // sending only fails if the last listener left in the meantime
+    let _ = STREAM.send(Arc::new(event));
+}
+
+/// Handler to stream the file events of a user
+/// This function keeps the connection open and sends a Server-Sent Events message
+/// whenever a file of the user is uploaded, downloaded, deleted, removed by its download limit or expired,
+/// so dashboards and bots can react right away instead of polling.
+/// The message is named like the event, `file.downloaded` for example,
+/// and carries the same JSON as a webhook: the event, a timestamp and the file.
+/// A listener that falls too far behind gets a `lagged` message with the number of events it missed.
+/// Only the events of the instance the stream is connected to are sent.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -N -H "key: <key>" http://localhost:3000/events
+/// example filtered request: curl -N -H "key: <key>" "http://localhost:3000/events?events=downloaded,expired"
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+///
+/// accepts the following query parameters:
+/// - events: a comma separated list of `uploaded`, `downloaded`, `deleted`, `limit_reached` and `expired`,
+///   every event if missing (optional)
+#[instrument(skip_all)]
+pub async fn events(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<data::EventsQuery>,
+    AuthUser { user, .. }: AuthUser<ListScope>,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received an event stream request from {} from IP: {}", user.username, ip);
+
+    let mut kinds = Vec::new();
+    for name in query.events.as_deref().unwrap_or_default().split(',').map(str::trim) {
+        match notify::parse_event(name) {
+            Some(kind) => kinds.push(kind),
+            None if name.is_empty() => {}
+            None => return Err(ApiError::BadRequest(format!("Unknown event {}", name))),
+        }
+    }
+
+    let owner = user.username;
+    let messages = stream::unfold(STREAM.subscribe(), move |mut receiver| {
+        let owner = owner.clone();
+        let kinds = kinds.clone();
+        async move {
+            loop {
+                let message = match receiver.recv().await {
+                    Ok(event) if event.file.owner != owner => continue,
+                    Ok(event) if !kinds.is_empty() && !kinds.contains(&event.event) => continue,
+                    Ok(event) => {
+                        let name = format!("file.{}", notify::event_name(event.event));
+                        match Event::default().event(name).json_data(&*event) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                warn!("Could not encode the event of {}: {}", event.file.id, e);
+                                continue;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Event stream of {} missed {} events", owner, missed);
+                        Event::default().event("lagged").data(missed.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok::<_, Infallible>(message), receiver));
+            }
+        }
+    });
+    // proxies and load balancers close connections that stay quiet for too long
+    Ok(Sse::new(messages).keep_alive(KeepAlive::default()).into_response())
+}
@@ -0,0 +1,284 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use sqlx::AnyPool;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::geoip;
+use crate::ids;
+
+/// The maximum length of a stored user agent, longer ones are cut off.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Returns the IP address with its host part zeroed,
+/// the last byte for IPv4 and everything after the /48 prefix for IPv6.
+/// Owners can tell downloads from different networks apart without learning who downloaded.
+fn anonymize(ip: &str) -> String {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::from([a, b, c, 0]).to_string()
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let mut segments = ip.segments();
+            segments[3..].fill(0);
+            IpAddr::from(segments).to_string()
+        }
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+/// This function records a download of a file in the `downloads` table
+/// with the anonymized IP address and the user agent of the client.
+/// The country and network are looked up before the address is anonymized.
+/// A failing insert is only logged, it never fails the download itself.
+pub async fn record_download(pool: &AnyPool, file_id: &str, ip: &str, headers: &HeaderMap) {
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|hv| hv.to_str().ok())
+        .map(|s| s.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
+    let location = geoip::locate(ip);
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO downloads
+            (file_id, time, ip, user_agent, country, asn, asn_organization)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(file_id)
+    .bind(Utc::now().timestamp())
+    .bind(anonymize(ip))
+    .bind(user_agent)
+    .bind(location.country)
+    .bind(location.asn)
+    .bind(location.asn_organization)
+    .execute(pool)
+    .await
+    {
+        warn!("Could not record download of {}: {}", file_id, e);
+    }
+}
+
+/// Returns the downloads per UTC day of the download times.
+fn per_day(times: impl Iterator<Item = i64>) -> Vec<data::DailyDownloads> {
+    let mut per_day: BTreeMap<String, i64> = BTreeMap::new();
+    for time in times {
+        if let Some(time) = DateTime::<Utc>::from_timestamp(time, 0) {
+            *per_day.entry(time.format("%Y-%m-%d").to_string()).or_default() += 1;
+        }
+    }
+    per_day
+        .into_iter()
+        .map(|(day, downloads)| data::DailyDownloads { day, downloads })
+        .collect()
+}
+
+/// Returns the downloads per country and per network of the files matching `filter`,
+/// a condition on `files` with one parameter, the most downloads first.
+async fn locations(
+    pool: &AnyPool,
+    filter: &str,
+    value: &str,
+) -> Result<(Vec<data::CountryDownloads>, Vec<data::NetworkDownloads>), sqlx::Error> {
+    let countries = sqlx::query_as::<_, data::CountryDownloads>(&format!(
+        r#"
+        SELECT downloads.country AS country, COUNT(*) AS downloads
+        FROM downloads
+        JOIN files ON files.id = downloads.file_id
+        WHERE {} AND downloads.country IS NOT NULL
+        GROUP BY downloads.country
+        ORDER BY COUNT(*) DESC, downloads.country
+        "#,
+        filter,
+    ))
+    .bind(value)
+    .fetch_all(pool)
+    .await?;
+    let networks = sqlx::query_as::<_, data::NetworkDownloads>(&format!(
+        r#"
+        SELECT downloads.asn AS asn, MAX(downloads.asn_organization) AS asn_organization, COUNT(*) AS downloads
+        FROM downloads
+        JOIN files ON files.id = downloads.file_id
+        WHERE {} AND downloads.asn IS NOT NULL
+        GROUP BY downloads.asn
+        ORDER BY COUNT(*) DESC, downloads.asn
+        "#,
+        filter,
+    ))
+    .bind(value)
+    .fetch_all(pool)
+    .await?;
+    Ok((countries, networks))
+}
+
+/// Handler to return the download statistics of a file
+/// This function returns every recorded download of a file
+/// together with the number of downloads per day,
+/// and per country and network if GeoIP databases are configured.
+/// IP addresses are anonymized, only the owner of the file can see its statistics.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/file/<uuid>/stats
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn file_stats(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received stats request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    // find the file in the database
+    let file = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE id = ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(&uuid)
+    .fetch_optional(&replica)
+    .await;
+    let file = match file {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            info!("File not found in DB: {}", uuid);
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    // only the owner is allowed to see who downloaded the file
+    if file.owner != user.username {
+        warn!("User {} tried to read the stats of file {} owned by {}", user.username, uuid, file.owner);
+        return (StatusCode::FORBIDDEN, "You do not own this file").into_response();
+    }
+
+    let downloads = sqlx::query_as::<_, data::Download>(
+        r#"
+        SELECT time, ip, user_agent, country, asn, asn_organization
+        FROM downloads
+        WHERE file_id = ?
+        ORDER BY time
+        "#,
+    )
+    .bind(&uuid)
+    .fetch_all(&replica)
+    .await;
+    let downloads = match downloads {
+        Ok(downloads) => downloads,
+        Err(e) => {
+            error!("DB select downloads error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    let (countries, networks) = match locations(&replica, "files.id = ?", &uuid).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            error!("DB select download locations error {}: {}", uuid, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    Json(data::FileStats {
+        id: file.id,
+        download_count: file.download_count,
+        download_limit: file.download_limit,
+        per_day: per_day(downloads.iter().map(|download| download.time)),
+        countries,
+        networks,
+        downloads,
+    })
+    .into_response()
+}
+
+/// Handler to return the download statistics of every file of a user
+/// This function returns how often the user's files were downloaded, per day,
+/// and per country and network if GeoIP databases are configured,
+/// so people who distribute public builds see where they end up.
+/// Downloads of deleted files are left out once the garbage collection removed them.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/user/stats
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn user_stats(
+    Extension(pool): Extension<AnyPool>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received user stats request from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let times = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT downloads.time
+        FROM downloads
+        JOIN files ON files.id = downloads.file_id
+        WHERE files.owner = ?
+        "#,
+    )
+    .bind(&user.username)
+    .fetch_all(&replica)
+    .await;
+    let times = match times {
+        Ok(times) => times,
+        Err(e) => {
+            error!("DB select downloads error {}: {}", user.username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+    let (countries, networks) = match locations(&replica, "files.owner = ?", &user.username).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            error!("DB select download locations error {}: {}", user.username, e);
+            return db::error_response(&e, "Database select error");
+        }
+    };
+
+    Json(data::UserStats {
+        username: user.username,
+        downloads: times.len() as i64,
+        per_day: per_day(times.into_iter()),
+        countries,
+        networks,
+    })
+    .into_response()
+}
@@ -0,0 +1,993 @@
+use axum::body::Bytes;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use tokio::fs;
+use tokio::io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use crate::cluster;
+use crate::compression;
+use crate::data;
+use crate::encryption;
+use crate::ipfs;
+use crate::memory_cache;
+use crate::replica;
+use crate::thumbnail;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// This lock serializes changes to blob references.
+/// Adding a reference (inserting a `files` row for an existing blob)
+/// and dropping the last reference (removing the blob from disk)
+/// must never interleave, otherwise a freshly uploaded file could lose its blob.
+/// Instances that share the database and data path also take the cluster lock `BLOB_LOCK_NAME`.
+static BLOB_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The name of the blob lock shared between instances.
+const BLOB_LOCK_NAME: &str = "blobs";
+
+/// The number of directory levels blobs are sharded into.
+const SHARD_DEPTH: usize = 2;
+
+/// The size of the chunks an upload is hashed and written in.
+const PIPELINE_CHUNK: usize = 1024 * 1024;
+
+/// The number of chunks that may wait for the hasher, so writing never runs far ahead of hashing.
+const PIPELINE_DEPTH: usize = 4;
+
+/// The size of the reads a streamed download is sent in.
+const STREAM_BUFFER: usize = 512 * 1024;
+
+/// How old a part file must be before it counts as left behind.
+const STALE_PART_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The suffix of the stored name of a compressed blob.
+const COMPRESSED_SUFFIX: &str = ".gz";
+
+/// The suffix of the stored name of a blob encrypted with the master key, after the one for compression.
+const ENCRYPTED_SUFFIX: &str = ".enc";
+
+/// The version of the layout of the data path this server writes, it is kept in `LAYOUT_FILE`.
+/// Version 1 records whether a blob is compressed in the name it is stored under, see `Form`,
+/// version 2 also whether it is encrypted.
+const LAYOUT_VERSION: u32 = 2;
+
+/// The file in the data path that holds the version of its layout.
+/// It sits next to the shard directories and is no blob name, so it is never mistaken for a blob.
+const LAYOUT_FILE: &str = "layout";
+
+/// This struct represents how a blob is stored.
+/// It is part of the name the blob is stored under, `<name>.gz` for a compressed blob,
+/// `<name>.enc` for an encrypted one and `<name>.gz.enc` for both,
+/// so it is never guessed from the contents, which are up to the uploader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Form {
+    /// The blob is stored compressed, see `compression::compress`.
+    pub compressed: bool,
+    /// The blob is stored encrypted with the master key, see `encryption::encrypt`.
+    pub encrypted: bool,
+}
+
+impl Form {
+    /// Every form a blob can be stored in.
+    const ALL: [Form; 4] = [
+        Form {
+            compressed: false,
+            encrypted: false,
+        },
+        Form {
+            compressed: true,
+            encrypted: false,
+        },
+        Form {
+            compressed: false,
+            encrypted: true,
+        },
+        Form {
+            compressed: true,
+            encrypted: true,
+        },
+    ];
+
+    /// Returns true if the blob is stored as it was uploaded,
+    /// only such blobs can be streamed from disk or handed to the reverse proxy.
+    pub fn is_plain(self) -> bool {
+        self == Form::default()
+    }
+
+    /// Returns the name a blob of this form is stored under.
+    pub fn stored_name(self, name: &str) -> String {
+        let mut stored = name.to_string();
+        if self.compressed {
+            stored.push_str(COMPRESSED_SUFFIX);
+        }
+        if self.encrypted {
+            stored.push_str(ENCRYPTED_SUFFIX);
+        }
+        stored
+    }
+}
+
+/// Returns the blob name and the form of a blob from the name it is stored under,
+/// `None` if it is not the stored name of a blob.
+pub fn parse_stored_name(stored: &str) -> Option<(&str, Form)> {
+    let (name, encrypted) = match stored.strip_suffix(ENCRYPTED_SUFFIX) {
+        Some(name) => (name, true),
+        None => (stored, false),
+    };
+    let (name, compressed) = match name.strip_suffix(COMPRESSED_SUFFIX) {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    is_blob_name(name).then_some((name, Form { compressed, encrypted }))
+}
+
+/// This struct represents the held blob lock of this instance and of every other instance.
+pub struct BlobGuard {
+    _cluster: cluster::Lock,
+    _local: MutexGuard<'static, ()>,
+}
+
+/// Takes the blob lock, nothing can add or drop a blob reference while it is held.
+/// The lock of this instance is taken first, so it never holds more than one connection for it.
+pub async fn lock(pool: &AnyPool) -> Result<BlobGuard, sqlx::Error> {
+    let local = BLOB_LOCK.lock().await;
+    let cluster = cluster::lock(pool, BLOB_LOCK_NAME).await?;
+    Ok(BlobGuard {
+        _cluster: cluster,
+        _local: local,
+    })
+}
+
+/// Returns the stored name and last modification time of every stored blob, thumbnails included.
+/// It walks the shard directories, files outside of them are not blobs.
+pub async fn list_blobs(config: &data::Config) -> std::io::Result<Vec<(String, SystemTime)>> {
+    list_blobs_below(&config.data_path).await
+}
+
+/// Returns the name and last modification time of every blob below `root`, see `list_blobs`.
+pub(crate) async fn list_blobs_below(root: &str) -> std::io::Result<Vec<(String, SystemTime)>> {
+    let mut blobs = Vec::new();
+    let mut directories = vec![(PathBuf::from(root), 0)];
+    while let Some((directory, depth)) = directories.pop() {
+        let mut entries = fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if metadata.is_dir() && depth < SHARD_DEPTH && is_shard_name(&name) {
+                directories.push((entry.path(), depth + 1));
+            } else if metadata.is_file() && depth == SHARD_DEPTH {
+                blobs.push((name, metadata.modified()?));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// Returns the bytes every file below the data path takes up,
+/// blobs, thumbnails and part files of running uploads alike.
+pub async fn disk_usage(config: &data::Config) -> std::io::Result<u64> {
+    let mut total = 0;
+    let mut directories = vec![PathBuf::from(&config.data_path)];
+    while let Some(directory) = directories.pop() {
+        let mut entries = fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                directories.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Removes a blob without checking for references, the caller must hold the blob lock.
+/// The blob is dropped from the memory cache and the replica as well.
+pub async fn remove_blob(config: &data::Config, name: &str) -> std::io::Result<()> {
+    memory_cache::remove(name);
+    let mut removed = Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("blob {} is not stored", name),
+    ));
+    for form in Form::ALL {
+        let stored = form.stored_name(name);
+        replica::remove(config, &stored).await;
+        match fs::remove_file(checked_path(config, &stored).await?).await {
+            Ok(()) => removed = Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    removed
+}
+
+/// Returns the path a blob is stored at, from the name it is stored under, see `Form`.
+/// Blobs are sharded into two levels of directories named after the start of the blob name,
+/// `data_path/ab/cd/abcd...`, so no directory grows past a few thousand entries.
+/// Thumbnails start with the name of their blob and end up next to it.
+pub fn blob_path(config: &data::Config, name: &str) -> PathBuf {
+    path_below(&config.data_path, name)
+}
+
+/// Returns the path of a blob in the shard directories below `root`, see `blob_path`.
+fn path_below(root: &str, name: &str) -> PathBuf {
+    let mut path = PathBuf::from(root);
+    for depth in 0..SHARD_DEPTH {
+        match name.get(depth * 2..depth * 2 + 2) {
+            Some(shard) => path.push(shard),
+            None => break,
+        }
+    }
+    path.join(name)
+}
+
+/// Returns the header that hands a blob to the reverse proxy with `download_offload`, `None` if it is off.
+/// nginx gets the path of the blob below the internal `download_offload_location` in `X-Accel-Redirect`,
+/// Apache and lighttpd get the absolute path of the blob in `X-Sendfile`.
+pub fn offload_header(config: &data::Config, name: &str) -> Option<(&'static str, String)> {
+    match config.download_offload.as_str() {
+        "x-accel-redirect" => Some((
+            "x-accel-redirect",
+            format!("{}/{}", config.download_offload_location, blob_key(config, name)?),
+        )),
+        "x-sendfile" => {
+            let path = std::path::absolute(blob_path(config, name)).ok()?;
+            Some(("x-sendfile", path.to_string_lossy().into_owned()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the path of a blob relative to the data path with `/` separators, like `ab/cd/abcd...`.
+/// It is the key of the blob in a bucket that mirrors the data path.
+pub(crate) fn blob_key(config: &data::Config, name: &str) -> Option<String> {
+    let path = blob_path(config, name);
+    let relative = path.strip_prefix(&config.data_path).ok()?;
+    let relative = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>();
+    Some(relative.join("/"))
+}
+
+/// Returns the path of a blob after making sure it can not point outside of the data path.
+/// Only names bitBeam generates are accepted, and if the shard directory exists
+/// its canonical path, with symlinks resolved, must still be inside the data path.
+/// Every file system access goes through this function.
+pub(crate) async fn checked_path(config: &data::Config, name: &str) -> std::io::Result<PathBuf> {
+    checked_path_below(&config.data_path, name).await
+}
+
+/// Returns the path of a blob below `root` after making sure it can not point outside of it, see `checked_path`.
+pub(crate) async fn checked_path_below(root: &str, name: &str) -> std::io::Result<PathBuf> {
+    if parse_stored_name(name).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid blob name {:?}", name),
+        ));
+    }
+    let path = path_below(root, name);
+    let root = fs::canonicalize(root).await?;
+    if let Some(parent) = path.parent() {
+        if let Ok(parent) = fs::canonicalize(parent).await {
+            if !parent.starts_with(&root) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("blob {} resolves outside of the data path", name),
+                ));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Returns true if the name is a valid shard directory name.
+fn is_shard_name(name: &str) -> bool {
+    name.len() == 2 && name.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// Returns true if the name looks like a blob bitBeam stored,
+/// a SHA-256 content hash or a file UUID with an optional thumbnail suffix.
+pub fn is_blob_name(name: &str) -> bool {
+    let name = name.strip_suffix(".thumb.png").unwrap_or(name);
+    let is_hash = name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit());
+    is_hash || uuid::Uuid::try_parse(name).is_ok()
+}
+
+/// This function moves blobs stored directly in the data path,
+/// as older versions did, into their shard directories.
+/// It runs once at startup and does nothing when every blob is already sharded.
+pub async fn migrate_flat_layout(config: &data::Config) -> std::io::Result<usize> {
+    let _guard = BLOB_LOCK.lock().await;
+    let mut moved = 0;
+    let mut entries = fs::read_dir(&config.data_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if !entry.metadata().await?.is_file() || !is_blob_name(&name) {
+            continue;
+        }
+        let path = blob_path(config, &name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(entry.path(), &path).await?;
+        moved += 1;
+    }
+    if moved > 0 {
+        info!("Moved {} blobs into shard directories", moved);
+    }
+    Ok(moved)
+}
+
+/// Returns the layout version of the data path at `root`, 0 for the data path of a version that did not record it.
+pub(crate) async fn layout_version(root: &str) -> std::io::Result<u32> {
+    match fs::read_to_string(PathBuf::from(root).join(LAYOUT_FILE)).await {
+        Ok(version) => version.trim().parse().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid layout version {:?} in {}", version, root),
+            )
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Fails unless the blobs below `root` are laid out like this version lays out the data path.
+pub(crate) async fn check_layout(root: &str) -> std::io::Result<()> {
+    match layout_version(root).await? {
+        LAYOUT_VERSION => Ok(()),
+        version => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the blobs below {} have layout version {}, this version reads {}, start a server on them first",
+                root, version, LAYOUT_VERSION
+            ),
+        )),
+    }
+}
+
+/// This function brings the blobs of older versions into the current layout of the data path.
+/// Older versions told a compressed or encrypted blob by its first bytes, which an upload can start with
+/// just as well, such blobs are moved to the stored name of their form, see `Form`.
+/// A blob only counts as encrypted if it decrypts with the master key. Without a master key
+/// the older versions sent every blob as it is stored, so blobs are left plain.
+/// It runs once at startup while the layout is older than `LAYOUT_VERSION`,
+/// the contents of blobs stored after that are never looked at to tell how they are stored.
+pub async fn migrate_layout(config: &data::Config) -> std::io::Result<usize> {
+    let _guard = BLOB_LOCK.lock().await;
+    let version = layout_version(&config.data_path).await?;
+    if version >= LAYOUT_VERSION {
+        return Ok(0);
+    }
+    let mut renamed = 0;
+    for (stored, _) in list_blobs(config).await? {
+        let Some((name, mut form)) = parse_stored_name(&stored) else {
+            continue;
+        };
+        // version 1 already recorded compression, but not encryption
+        if form.encrypted || (version >= 1 && config.master_key.is_none()) {
+            continue;
+        }
+        let path = checked_path(config, &stored).await?;
+        let data = match config.master_key {
+            Some(master_key) => {
+                let data = fs::read(&path).await?;
+                match tokio::task::spawn_blocking(move || encryption::decrypt_legacy(&master_key, &data))
+                    .await
+                    .map_err(std::io::Error::other)?
+                {
+                    Some(plaintext) => {
+                        form.encrypted = true;
+                        plaintext
+                    }
+                    None => data_start(&path).await?,
+                }
+            }
+            None => data_start(&path).await?,
+        };
+        // the compressed data of an encrypted blob is only seen once it is decrypted
+        if version < 1 && compression::gzip_member(&data).is_some() {
+            form.compressed = true;
+        }
+        let target = form.stored_name(name);
+        if target == stored {
+            continue;
+        }
+        fs::rename(&path, checked_path(config, &target).await?).await?;
+        replica::rename(config, &stored, &target).await;
+        renamed += 1;
+    }
+    fs::write(PathBuf::from(&config.data_path).join(LAYOUT_FILE), LAYOUT_VERSION.to_string()).await?;
+    if renamed > 0 {
+        info!("Renamed {} compressed or encrypted blobs to the stored name of their form", renamed);
+    }
+    Ok(renamed)
+}
+
+/// Reads the first bytes of a blob, enough to tell whether it starts with a gzip member.
+async fn data_start(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    let mut start = Vec::new();
+    fs::File::open(path).await?.take(128).read_to_end(&mut start).await?;
+    Ok(start)
+}
+
+/// Returns the hex encoded SHA-256 digest of the given data.
+/// New blobs are stored under this name so identical uploads share one blob.
+pub fn content_hash(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// Returns true if a blob with the given name is stored, in the data path or else in the replica.
+pub async fn blob_exists(config: &data::Config, name: &str) -> bool {
+    blob_form(config, name).await.is_some()
+}
+
+/// Returns how a blob is stored, in the data path or else in the replica, `None` if it is not stored.
+pub async fn blob_form(config: &data::Config, name: &str) -> Option<Form> {
+    if let Some(form) = form_below(&config.data_path, name).await {
+        return Some(form);
+    }
+    match &config.replica_path {
+        Some(root) => form_below(root, name).await,
+        None => None,
+    }
+}
+
+/// Returns how a blob is stored below `root`, `None` if it is not stored there.
+async fn form_below(root: &str, name: &str) -> Option<Form> {
+    for form in Form::ALL {
+        if let Ok(path) = checked_path_below(root, &form.stored_name(name)).await {
+            if fs::try_exists(path).await.unwrap_or(false) {
+                return Some(form);
+            }
+        }
+    }
+    None
+}
+
+/// Writes a blob unless a blob with the same name is already stored.
+/// Blobs are named by their content hash, so an existing blob has the same contents.
+/// The blob is encrypted if a master key is configured.
+/// It is written to a part file first, so a crash never leaves a partly written blob behind.
+pub async fn write_blob(config: &data::Config, name: &str, body: &[u8]) -> std::io::Result<()> {
+    if blob_exists(config, name).await {
+        info!("Blob {} already stored, skipping write", name);
+        return Ok(());
+    }
+    write_part(config, body).await?.commit(config, name).await
+}
+
+/// This enum represents how `link_blob` stored a blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Linked {
+    Reflink,
+    HardLink,
+    Copy,
+}
+
+/// Stores the file at `source` as the blob it is the stored name of, unless a blob with that name is stored.
+/// The file is taken as it is, it must hold the blob the way this server stores it in that form.
+/// On the same file system the data is shared instead of copied, which takes no space and no time:
+/// a reflink shares it copy on write, a hard link shares the file itself, blobs are never changed in place.
+/// Other file systems, or ones without either, get a copy.
+/// Returns how the blob was stored, `None` if it was already.
+pub(crate) async fn link_blob(
+    config: &data::Config,
+    source: &std::path::Path,
+    stored: &str,
+) -> std::io::Result<Option<Linked>> {
+    let Some((name, form)) = parse_stored_name(stored) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid blob name {:?}", stored),
+        ));
+    };
+    if blob_exists(config, name).await {
+        return Ok(None);
+    }
+    let directory = parts_path(config);
+    fs::create_dir_all(&directory).await?;
+    let path = directory.join(format!("{}.part", uuid::Uuid::new_v4()));
+    let (source, target) = (source.to_path_buf(), path.clone());
+    let linked = tokio::task::spawn_blocking(move || link_or_copy(&source, &target))
+        .await
+        .map_err(std::io::Error::other)??;
+    Part {
+        path,
+        form,
+        committed: false,
+    }
+    .commit(config, name)
+    .await?;
+    Ok(Some(linked))
+}
+
+/// Reflinks, hard links or copies `source` to `target`, whichever works first.
+fn link_or_copy(source: &std::path::Path, target: &std::path::Path) -> std::io::Result<Linked> {
+    if reflink(source, target).is_ok() {
+        return Ok(Linked::Reflink);
+    }
+    if std::fs::hard_link(source, target).is_ok() {
+        return Ok(Linked::HardLink);
+    }
+    let copied = std::fs::copy(source, target).and_then(|_| std::fs::File::open(target)?.sync_all());
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(target);
+        return Err(e);
+    }
+    Ok(Linked::Copy)
+}
+
+/// Creates `target` as a reflink of `source`, Btrfs, XFS and other copy on write file systems support it.
+#[cfg(target_os = "linux")]
+fn reflink(source: &std::path::Path, target: &std::path::Path) -> std::io::Result<()> {
+    let source = std::fs::File::open(source)?;
+    let file = std::fs::File::create_new(target)?;
+    if let Err(e) = rustix::fs::ioctl_ficlone(&file, &source) {
+        drop(file);
+        let _ = std::fs::remove_file(target);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Reflinks are only made on Linux, elsewhere the blob is hard linked or copied.
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &std::path::Path, _target: &std::path::Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// This struct represents the contents of a blob written to `data_path/tmp/<uuid>.part`.
+/// The contents only show up under the blob name once the part is committed,
+/// a part that is dropped without being committed is removed again.
+pub struct Part {
+    path: PathBuf,
+    form: Form,
+    committed: bool,
+}
+
+impl Part {
+    /// Moves the part into place as the blob with the given name, stored under the name of its form.
+    /// If the blob was stored in the meantime the part is dropped, the contents are the same.
+    pub async fn commit(mut self, config: &data::Config, name: &str) -> std::io::Result<()> {
+        if form_below(&config.data_path, name).await.is_some() {
+            info!("Blob {} already stored, dropping its part", name);
+            return Ok(());
+        }
+        let stored = self.form.stored_name(name);
+        let path = checked_path(config, &stored).await?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&self.path, &path).await?;
+        self.committed = true;
+        replica::queue(&stored);
+        Ok(())
+    }
+}
+
+impl Drop for Part {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove part {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Returns the number of bytes unprivileged users can still write to the file system of `path`.
+#[cfg(unix)]
+pub(crate) fn free_bytes(path: &str) -> std::io::Result<u64> {
+    let stat = rustix::fs::statvfs(path)?;
+    Ok(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+/// The free space is only measured on unix systems.
+#[cfg(not(unix))]
+pub(crate) fn free_bytes(_path: &str) -> std::io::Result<u64> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "measuring free space is only supported on unix systems",
+    ))
+}
+
+/// Returns a path from the configuration in the form of the platform, so blob paths built from it compare equal.
+/// Separators are unified, `/` becomes `\` on Windows, doubled and trailing separators and `.` parts are dropped.
+/// Windows drive letters and UNC paths like `\\server\share` keep their prefix.
+/// `..` parts are kept, they can't be resolved without looking at the file system.
+pub fn normalize_path(path: &str) -> String {
+    let normalized: PathBuf = std::path::Path::new(path).components().collect();
+    match normalized.as_os_str().is_empty() {
+        true => path.to_string(),
+        false => normalized.to_string_lossy().into_owned(),
+    }
+}
+
+/// Returns the directory part files are written to before they become blobs.
+/// Its name is no shard name, so it is never mistaken for blobs.
+fn parts_path(config: &data::Config) -> PathBuf {
+    parts_below(&config.data_path)
+}
+
+/// Returns the directory part files are written to below `root`, see `parts_path`.
+pub(crate) fn parts_below(root: &str) -> PathBuf {
+    PathBuf::from(root).join("tmp")
+}
+
+/// Creates a new, empty part file.
+async fn create_part(config: &data::Config) -> std::io::Result<(Part, fs::File)> {
+    let directory = parts_path(config);
+    fs::create_dir_all(&directory).await?;
+    let part = Part {
+        path: directory.join(format!("{}.part", uuid::Uuid::new_v4())),
+        form: Form::default(),
+        committed: false,
+    };
+    let file = fs::File::create(&part.path).await?;
+    Ok((part, file))
+}
+
+/// This function writes the contents of a blob to a new part file and flushes it to disk.
+/// The contents are encrypted if a master key is configured.
+pub async fn write_part(config: &data::Config, body: &[u8]) -> std::io::Result<Part> {
+    let (mut part, mut file) = create_part(config).await?;
+    match &config.master_key {
+        Some(master_key) => {
+            file.write_all(&encryption::encrypt(master_key, body)?).await?;
+            part.form.encrypted = true;
+        }
+        None => file.write_all(body).await?,
+    }
+    file.sync_all().await?;
+    Ok(part)
+}
+
+/// This struct holds the digests of an upload computed by `write_part_hashed`.
+/// `sha256` is hex encoded like `content_hash`, `md5` is only computed if it was asked for.
+pub struct Digests {
+    pub sha256: String,
+    pub md5: Option<Vec<u8>>,
+}
+
+/// Returns the contents of a blob as they are stored and the form they are stored in,
+/// compressed first if `compress` is set and then encrypted if a master key is given.
+fn seal(master_key: Option<[u8; 32]>, compress: bool, body: &[u8]) -> std::io::Result<(Vec<u8>, Form)> {
+    let compressed = match compress {
+        true => compression::compress(body)?,
+        false => None,
+    };
+    let form = Form {
+        compressed: compressed.is_some(),
+        encrypted: master_key.is_some(),
+    };
+    let body = compressed.as_deref().unwrap_or(body);
+    match master_key {
+        Some(master_key) => Ok((encryption::encrypt(&master_key, body)?, form)),
+        None => Ok((body.to_vec(), form)),
+    }
+}
+
+/// This function writes an upload to a new part file and hashes it at the same time.
+/// The body is handed to a hasher on the blocking pool in chunks over a bounded channel
+/// while the same chunks are written to disk, so hashing large uploads overlaps the disk write
+/// instead of adding to it and never blocks the async runtime.
+/// With a master key or `compress` the blob is sealed in one piece, so it is compressed and encrypted
+/// on the blocking pool while it is hashed and written once both are done.
+/// A blob that does not get smaller is stored uncompressed.
+pub async fn write_part_hashed(
+    config: &data::Config,
+    body: Bytes,
+    md5: bool,
+    compress: bool,
+) -> std::io::Result<(Part, Digests)> {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<Bytes>(PIPELINE_DEPTH);
+    let hasher = tokio::task::spawn_blocking(move || {
+        let mut sha256 = Sha256::new();
+        let mut md5 = md5.then(Md5::new);
+        while let Some(chunk) = receiver.blocking_recv() {
+            sha256.update(&chunk);
+            if let Some(md5) = &mut md5 {
+                md5.update(&chunk);
+            }
+        }
+        Digests {
+            sha256: hex::encode(sha256.finalize()),
+            md5: md5.map(|md5| md5.finalize().to_vec()),
+        }
+    });
+    let hasher_gone = || std::io::Error::other("the upload hasher stopped");
+
+    let (mut part, mut file) = create_part(config).await?;
+    let master_key = config.master_key;
+    let sealed = (master_key.is_some() || compress).then(|| {
+        let plaintext = body.clone();
+        tokio::task::spawn_blocking(move || seal(master_key, compress, &plaintext))
+    });
+    for start in (0..body.len()).step_by(PIPELINE_CHUNK) {
+        let chunk = body.slice(start..body.len().min(start + PIPELINE_CHUNK));
+        sender.send(chunk.clone()).await.map_err(|_| hasher_gone())?;
+        if sealed.is_none() {
+            file.write_all(&chunk).await?;
+        }
+    }
+    drop(sender);
+    if let Some(sealed) = sealed {
+        let (sealed, form) = sealed.await.map_err(std::io::Error::other)??;
+        file.write_all(&sealed).await?;
+        part.form = form;
+    }
+    file.sync_all().await?;
+    let digests = hasher.await.map_err(|_| hasher_gone())?;
+    Ok((part, digests))
+}
+
+/// This struct represents a body that is written to a part file as it arrives,
+/// so a slow sender never has more than a chunk of it in memory.
+/// The part is removed again once the spool is dropped, it never becomes a blob itself.
+pub struct Spool {
+    part: Part,
+    file: fs::File,
+    size: u64,
+}
+
+impl Spool {
+    /// Creates a new, empty spool in the parts directory.
+    pub async fn new(config: &data::Config) -> std::io::Result<Spool> {
+        let (part, file) = create_part(config).await?;
+        Ok(Spool { part, file, size: 0 })
+    }
+
+    /// Appends a chunk of the body.
+    pub async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads the complete body back in one piece, to be stored like any other upload.
+    pub async fn into_body(mut self) -> std::io::Result<Bytes> {
+        self.file.flush().await?;
+        Ok(Bytes::from(fs::read(&self.part.path).await?))
+    }
+}
+
+/// This function removes part files left behind by uploads that never finished,
+/// for example because the server crashed while writing them.
+/// It runs once at startup and only removes parts older than an hour,
+/// instances sharing the data path may be writing the newer ones.
+pub async fn remove_stale_parts(config: &data::Config) -> std::io::Result<usize> {
+    let mut entries = match fs::read_dir(parts_path(config)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        let stale = metadata
+            .modified()?
+            .elapsed()
+            .is_ok_and(|age| age >= STALE_PART_AGE);
+        if metadata.is_file() && stale && entry.file_name().to_string_lossy().ends_with(".part") {
+            fs::remove_file(entry.path()).await?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} stale part files", removed);
+    }
+    Ok(removed)
+}
+
+/// This function checks that the data path takes new blobs
+/// by writing a small probe file where part files go and removing it again.
+pub async fn check_writable(config: &data::Config) -> std::io::Result<()> {
+    check_writable_below(&config.data_path).await
+}
+
+/// Checks that blobs can be written below `root`, see `check_writable`.
+pub(crate) async fn check_writable_below(root: &str) -> std::io::Result<()> {
+    let directory = parts_below(root);
+    fs::create_dir_all(&directory).await?;
+    let path = directory.join(format!("{}.probe", uuid::Uuid::new_v4()));
+    fs::write(&path, b"probe").await?;
+    fs::remove_file(&path).await
+}
+
+/// Opens a blob for reading, from the replica if it can't be opened in the data path,
+/// and returns it with the form it is stored in.
+async fn open_blob(config: &data::Config, name: &str) -> std::io::Result<(fs::File, Form)> {
+    let opened = match form_below(&config.data_path, name).await {
+        Some(form) => match checked_path(config, &form.stored_name(name)).await {
+            Ok(path) => fs::File::open(path).await.map(|file| (file, form)),
+            Err(e) => Err(e),
+        },
+        None => Err(not_stored(name)),
+    };
+    match (opened, &config.replica_path) {
+        (Err(e), Some(root)) => {
+            let replicated = match form_below(root, name).await {
+                Some(form) => replica::open(config, &form.stored_name(name))
+                    .await
+                    .map(|file| (file, form)),
+                None => Err(not_stored(name)),
+            };
+            replicated.map_err(|replica_error| {
+                warn!("Could not read blob {} from the data path ({}) or the replica: {}", name, e, replica_error);
+                e
+            })
+        }
+        (opened, _) => opened,
+    }
+}
+
+/// Returns the error for a blob that is stored in no form.
+fn not_stored(name: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("blob {} is not stored", name))
+}
+
+/// Reads an opened blob into memory as it is stored, only decrypted.
+/// Blobs stored encrypted are decrypted with the master key,
+/// blobs stored before encryption was enabled are returned as they are.
+async fn read_stored(config: &data::Config, mut file: fs::File, form: Form) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+    if !form.encrypted {
+        return Ok(data);
+    }
+    match &config.master_key {
+        Some(master_key) => encryption::decrypt(master_key, &data),
+        None => Err(std::io::Error::other("the blob is encrypted, but no master key is configured")),
+    }
+}
+
+/// Reads a blob into memory.
+/// Encrypted blobs are decrypted with the master key and compressed blobs are decompressed,
+/// whether `compress_blobs` is still set or not.
+/// `size` is the size of the file the blob holds, a compressed blob that grows past it is refused.
+pub async fn read_blob(config: &data::Config, name: &str, size: u64) -> std::io::Result<Vec<u8>> {
+    let (file, form) = open_blob(config, name).await?;
+    let data = read_stored(config, file, form).await?;
+    match form.compressed {
+        true => tokio::task::spawn_blocking(move || compression::decompress(&data, size))
+            .await
+            .map_err(std::io::Error::other)?,
+        false => Ok(data),
+    }
+}
+
+/// Reads the gzip member of a compressed blob without decompressing it,
+/// so it can be sent with `Content-Encoding: gzip`. Returns `None` if the blob is not stored compressed.
+pub async fn read_gzip_blob(config: &data::Config, name: &str) -> std::io::Result<Option<Bytes>> {
+    let (file, form) = open_blob(config, name).await?;
+    if !form.compressed {
+        return Ok(None);
+    }
+    let data = Bytes::from(read_stored(config, file, form).await?);
+    match compression::gzip_member(&data) {
+        Some(member) => Ok(Some(data.slice_ref(member))),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("compressed blob {} has no gzip member", name),
+        )),
+    }
+}
+
+/// Reads a blob like `read_blob`, but answers from the memory cache if it holds the blob
+/// and keeps small blobs in it for the next read.
+pub async fn read_cached_blob(config: &data::Config, name: &str, size: u64) -> std::io::Result<Bytes> {
+    if let Some(data) = memory_cache::get(name) {
+        return Ok(data);
+    }
+    let data = Bytes::from(read_blob(config, name, size).await?);
+    memory_cache::insert(name, &data);
+    Ok(data)
+}
+
+/// Returns the bytes `range` of a blob as a stream of large reads from the file,
+/// so a download never holds the whole blob in memory.
+/// The reads run on the blocking pool of tokio.
+/// Only plain blobs can be streamed, encrypted and compressed blobs can only be read whole, see `read_blob`.
+pub async fn stream_blob(
+    config: &data::Config,
+    name: &str,
+    range: Range<u64>,
+) -> std::io::Result<ReaderStream<io::Take<fs::File>>> {
+    let (mut file, _) = open_blob(config, name).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let length = range.end.saturating_sub(range.start);
+    Ok(ReaderStream::with_capacity(file.take(length), STREAM_BUFFER))
+}
+
+/// This function adds references to blobs.
+/// It runs the given insert of the referencing `files` rows while holding the blob lock,
+/// then moves the part files of new blobs into place
+/// and writes every blob again that was removed before its row existed.
+/// `blobs` are the blob names and contents with the part written for them, if any.
+/// If the insert fails the parts are dropped and nothing shows up as a blob.
+pub async fn add_references<F, T, E>(
+    pool: &AnyPool,
+    config: &data::Config,
+    blobs: Vec<(&str, &[u8], Option<Part>)>,
+    insert: F,
+) -> Result<T, E>
+where
+    F: std::future::Future<Output = Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    let _guard = lock(pool).await?;
+    let inserted = insert.await?;
+    for (name, body, part) in blobs {
+        let written = match part {
+            Some(part) => part.commit(config, name).await,
+            None => write_blob(config, name, body).await,
+        };
+        if let Err(e) = written {
+            warn!("could not store blob {}: {}", name, e);
+        }
+    }
+    Ok(inserted)
+}
+
+/// This function drops a reference to the blob of a file
+/// whose `files` or `file_versions` row was already removed.
+/// The blob is only removed from disk once no other row of either table references it.
+pub async fn release_blob(pool: &AnyPool, config: &data::Config, file: &data::File) {
+    let _guard = match lock(pool).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            // keep the blob if another instance could be adding a reference
+            warn!("Could not take the blob lock to release {}: {}", file.blob_name(), e);
+            return;
+        }
+    };
+    let name = file.blob_name();
+    if file.content_hash.is_some() {
+        let references = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT (SELECT COUNT(*) FROM files WHERE content_hash = ?)
+                + (SELECT COUNT(*) FROM file_versions WHERE content_hash = ?)
+            "#,
+        )
+        .bind(name)
+        .bind(name)
+        .fetch_one(pool)
+        .await;
+        match references {
+            Ok(0) => {}
+            Ok(references) => {
+                info!("Blob {} still referenced by {} files", name, references);
+                return;
+            }
+            Err(e) => {
+                // keep the blob if we can not tell whether it is still needed
+                warn!("DB count error for blob {}: {}", name, e);
+                return;
+            }
+        }
+    }
+    if let Err(e) = remove_blob(config, name).await {
+        warn!("File delete error {}: {}", name, e);
+    }
+    // most blobs have no thumbnail, so a missing one is not worth a warning
+    let _ = remove_blob(config, &thumbnail::thumbnail_name(name)).await;
+    if let Some(cid) = &file.cid {
+        ipfs::unpin(config, cid).await;
+    }
+}
@@ -0,0 +1,56 @@
+use sd_notify::NotifyState;
+use sqlx::AnyPool;
+use std::os::fd::FromRawFd;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// This function takes over the TCP socket systemd passed in with socket activation, if there is one.
+/// With a socket unit the socket stays open while the service restarts,
+/// so connections made in between wait instead of being refused.
+/// Only the first socket is used, it has to be a TCP socket (`ListenStream=` with a port).
+pub fn listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    let Some(fd) = sd_notify::listen_fds()?.next() else {
+        return Ok(None);
+    };
+    // systemd hands the socket to this process only, nothing else owns the descriptor
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    info!("Listening on the socket passed in by systemd at {}", listener.local_addr()?);
+    Ok(Some(listener))
+}
+
+/// This function tells systemd the server is ready to take requests,
+/// so a `Type=notify` service is only started once it listens.
+/// Outside of systemd it does nothing.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Could not notify systemd: {}", e);
+    }
+}
+
+/// This function pings the systemd watchdog if the service has `WatchdogSec=` set.
+/// The ping is sent at half the watchdog interval and only while the database answers,
+/// so systemd restarts a server that hangs or lost its database.
+pub fn start_watchdog(pool: AnyPool) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    info!("Pinging the systemd watchdog every {:?}", interval);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match tokio::time::timeout(interval, sqlx::query("SELECT 1").execute(&pool)).await {
+                Ok(Ok(_)) => {
+                    if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                        warn!("Could not ping the systemd watchdog: {}", e);
+                    }
+                }
+                Ok(Err(e)) => warn!("Database check failed, skipping the watchdog ping: {}", e),
+                Err(_) => warn!("Database check timed out, skipping the watchdog ping"),
+            }
+        }
+    });
+}
@@ -0,0 +1,112 @@
+use axum::body::Body;
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use std::num::NonZeroU32;
+use std::sync::{Arc, OnceLock};
+use tracing::info;
+
+use crate::data;
+
+/// The largest chunk a throttled download is sent in.
+const MAX_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// The download bandwidth limits, set once at startup.
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// This struct holds the configured download bandwidth limits.
+struct Limits {
+    /// Shared by every download, caps the total upload bandwidth of the server.
+    global: Option<Arc<DefaultDirectRateLimiter>>,
+    /// The rate of the global limiter.
+    global_rate: Option<NonZeroU32>,
+    /// The rate every single download is capped at.
+    per_connection: Option<NonZeroU32>,
+}
+
+/// This function sets up the download bandwidth limits from the config.
+/// Rates are in bytes per second, 0 means unlimited.
+pub fn init(config: &data::Config) {
+    let global_rate = NonZeroU32::new(config.max_download_rate);
+    let global = global_rate.map(|rate| Arc::new(RateLimiter::direct(Quota::per_second(rate))));
+    let per_connection = NonZeroU32::new(config.max_download_rate_per_connection);
+    if global.is_some() || per_connection.is_some() {
+        info!(
+            "Download bandwidth limited to {} B/s total and {} B/s per connection",
+            config.max_download_rate, config.max_download_rate_per_connection
+        );
+    }
+    let _ = LIMITS.set(Limits {
+        global,
+        global_rate,
+        per_connection,
+    });
+}
+
+/// Returns a response body for in memory data that respects the bandwidth limits.
+pub fn body(data: Bytes) -> Body {
+    match LIMITS.get() {
+        Some(limits) if limits.global.is_some() || limits.per_connection.is_some() => {
+            stream_body(stream::once(async move { Ok::<_, std::io::Error>(data) }))
+        }
+        _ => Body::from(data),
+    }
+}
+
+/// Returns a response body for a stream of data that respects the bandwidth limits.
+/// The data is cut into chunks no larger than one second worth of the smallest rate,
+/// and every chunk waits for both the global and the per connection budget.
+pub fn stream_body<S>(data: S) -> Body
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    let Some(limits) = LIMITS.get() else {
+        return Body::from_stream(data);
+    };
+    if limits.global.is_none() && limits.per_connection.is_none() {
+        return Body::from_stream(data);
+    }
+
+    let global = limits.global.clone();
+    let per_connection = limits
+        .per_connection
+        .map(|rate| Arc::new(RateLimiter::direct(Quota::per_second(rate))));
+    // a chunk must fit into the burst of every limiter, which is one second worth of data
+    let chunk_size = [limits.global_rate, limits.per_connection]
+        .into_iter()
+        .flatten()
+        .map(NonZeroU32::get)
+        .fold(MAX_CHUNK_SIZE, u32::min) as usize;
+
+    let chunks = data
+        .map(move |item| match item {
+            Ok(bytes) => {
+                let chunks: Vec<_> = (0..bytes.len())
+                    .step_by(chunk_size)
+                    .map(|start| Ok(bytes.slice(start..(start + chunk_size).min(bytes.len()))))
+                    .collect();
+                stream::iter(chunks)
+            }
+            Err(e) => stream::iter(vec![Err(e)]),
+        })
+        .flatten();
+    let throttled = chunks.then(move |item| {
+        let global = global.clone();
+        let per_connection = per_connection.clone();
+        async move {
+            if let Ok(chunk) = &item {
+                if let Some(n) = NonZeroU32::new(chunk.len() as u32) {
+                    // the chunk size keeps n within the burst, so waiting can't fail
+                    if let Some(limiter) = &per_connection {
+                        let _ = limiter.until_n_ready(n).await;
+                    }
+                    if let Some(limiter) = &global {
+                        let _ = limiter.until_n_ready(n).await;
+                    }
+                }
+            }
+            item
+        }
+    });
+    Body::from_stream(throttled)
+}
@@ -0,0 +1,38 @@
+use image::ImageFormat;
+use std::io::Cursor;
+
+/// The maximum width and height of a thumbnail in pixels.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// The most bytes a thumbnail takes, a PNG of `THUMBNAIL_SIZE` squared RGBA pixels that did not compress at all,
+/// with room to spare for its chunk headers.
+pub const MAX_THUMBNAIL_BYTES: u64 = THUMBNAIL_SIZE as u64 * THUMBNAIL_SIZE as u64 * 5;
+
+/// The content type thumbnails are served with.
+pub const THUMBNAIL_CONTENT_TYPE: &str = "image/png";
+
+/// Returns true if a thumbnail should be generated for the content type.
+pub fn is_supported(content_type: &str) -> bool {
+    matches!(
+        content_type,
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp"
+    )
+}
+
+/// Returns the name of the thumbnail that belongs to a blob.
+/// Thumbnails live alongside their blob, so deduplicated files share them too.
+pub fn thumbnail_name(blob_name: &str) -> String {
+    format!("{}.thumb.png", blob_name)
+}
+
+/// This function generates a PNG thumbnail of an image.
+/// The image is scaled down to fit into `THUMBNAIL_SIZE` while keeping its aspect ratio.
+/// It returns `None` if the data can not be decoded as an image.
+/// Decoding is CPU bound, call this from a blocking task.
+pub fn generate(body: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(body).ok()?;
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut png = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut png, ImageFormat::Png).ok()?;
+    Some(png.into_inner())
+}
@@ -0,0 +1,81 @@
+use axum::{
+    extract::Request,
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use tracing::{error, info, warn};
+use tokio::net::TcpListener;
+
+use crate::data;
+use std::net::SocketAddr;
+
+/// This function serves the application over TLS.
+/// It loads the PEM encoded certificate chain and private key
+/// and serves the router on the already bound listener using rustls.
+/// It uses the ring crypto provider for rustls.
+/// Clients get `header_read_timeout` seconds to send the headers of a request.
+pub async fn serve(
+    listener: TcpListener,
+    app: axum::extract::connect_info::IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    cert: &str,
+    key: &str,
+    header_read_timeout: u64,
+) -> std::io::Result<()> {
+    let tls_config = load(cert, key).await?;
+    info!("Serving TLS with certificate {}", cert);
+
+    let mut server = axum_server::from_tcp_rustls(listener.into_std()?, tls_config);
+    crate::set_header_read_timeout(&mut server, header_read_timeout);
+    server.serve(app).await
+}
+
+/// This function loads the PEM encoded certificate chain and private key for rustls.
+/// It fails if either can't be read or parsed, or the key does not belong to the certificate.
+pub(crate) async fn load(cert: &str, key: &str) -> std::io::Result<RustlsConfig> {
+    // only the first call installs the provider, later calls are harmless
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(cert, key).await
+}
+
+/// This function serves plain HTTP redirects to the TLS listener.
+/// Every request on the given port is answered with a permanent redirect
+/// to the same path on `https://` + the configured base URL.
+pub async fn redirect_http(config: data::Config, port: String) {
+    let listener = match TcpListener::bind(format!("{}:{}", &config.listener_addr, &port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Error binding HTTP redirect to address {}:{} : {}",
+                &config.listener_addr, &port, e
+            );
+            return;
+        }
+    };
+    info!("Redirecting HTTP on port {} to HTTPS", port);
+
+    let base_url = config.base_url.clone();
+    let app = Router::new().fallback(move |headers: HeaderMap, request: Request| {
+        let base_url = base_url.clone();
+        async move { redirect(&base_url, &headers, request.uri()) }
+    });
+    if let Err(e) = axum::serve(listener, app).await {
+        warn!("HTTP redirect server error: {}", e);
+    }
+}
+
+/// Helper to build the redirect response for a single request.
+fn redirect(base_url: &str, headers: &HeaderMap, uri: &Uri) -> Response {
+    let path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    // fall back to the Host header if the base URL is not usable
+    let host = if base_url.is_empty() {
+        match headers.get("host").and_then(|hv| hv.to_str().ok()) {
+            Some(host) => host,
+            None => return (StatusCode::BAD_REQUEST, "Host header not supplied").into_response(),
+        }
+    } else {
+        base_url
+    };
+    Redirect::permanent(&format!("https://{}{}", host, path)).into_response()
+}
@@ -0,0 +1,475 @@
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::access_log;
+use crate::api;
+use crate::audit;
+use crate::collections;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+use crate::lockout;
+use std::net::SocketAddr;
+
+/// Every scoped token starts with this, so it can't be mistaken for an account key.
+pub(crate) const TOKEN_PREFIX: &str = "bbt_";
+
+/// The maximum number of allowed content types of a token.
+const MAX_CONTENT_TYPES: usize = 50;
+
+/// This enum represents what a scoped token may be used for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Uploading files with `/upload`, `/upload/batch` and `/upload/remote`.
+    Upload,
+    /// Listing the files of the user with `/all_files` or `/files` and their storage usage with `/user/usage`.
+    List,
+    /// Deleting files of the user with `DELETE /file/<uuid>`.
+    Delete,
+    /// Listing and downloading the files of one collection, private ones included,
+    /// with the token in the `token` query parameter. Only collection tokens have it.
+    Download,
+}
+
+impl Scope {
+    /// Returns the name of the scope as stored in the `tokens` table.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Upload => "upload",
+            Scope::List => "list",
+            Scope::Delete => "delete",
+            Scope::Download => "download",
+        }
+    }
+
+    /// Returns the scope with the given name.
+    fn parse(name: &str) -> Option<Scope> {
+        [Scope::Upload, Scope::List, Scope::Delete, Scope::Download]
+            .into_iter()
+            .find(|scope| scope.as_str() == name.trim())
+    }
+}
+
+/// Returns the scopes of a token.
+fn scopes(token: &data::ApiToken) -> Vec<Scope> {
+    token.scopes.split(',').filter_map(Scope::parse).collect()
+}
+
+/// Returns the error response for a token used for a request that needs `scope`,
+/// `None` if the token has the scope.
+pub(crate) fn missing_scope(token: &data::ApiToken, scope: Scope) -> Option<Response> {
+    if scopes(token).contains(&scope) {
+        return None;
+    }
+    warn!("Token {} of {} used without the {} scope", token.id, token.username, scope.as_str());
+    Some(
+        (
+            StatusCode::FORBIDDEN,
+            format!("This token does not have the {} scope", scope.as_str()),
+        )
+            .into_response(),
+    )
+}
+
+/// Returns the content types uploads with a token may have, empty if any type is allowed.
+pub(crate) fn allowed_content_types(token: Option<&data::ApiToken>) -> Vec<String> {
+    token
+        .and_then(|token| token.content_types.as_deref())
+        .map(|types| types.split(',').map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Returns true if the token with the ID was revoked or has expired.
+pub(crate) async fn is_revoked(pool: &AnyPool, id: &str) -> Result<bool, sqlx::Error> {
+    let expires = sqlx::query_scalar::<_, Option<i64>>("SELECT expires FROM tokens WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(match expires {
+        Some(Some(expires)) => expires <= Utc::now().timestamp(),
+        Some(None) => false,
+        None => true,
+    })
+}
+
+/// Returns the collection uploads with a token are added to, `None` for tokens of the whole account.
+pub(crate) fn collection(token: Option<&data::ApiToken>) -> Option<String> {
+    token.and_then(|token| token.collection_id.clone())
+}
+
+/// Helper to keep a collection token to the files of its collection
+/// It returns an error if the token is bound to a collection the file is not in.
+pub(crate) async fn check_collection(
+    pool: &AnyPool,
+    token: Option<&data::ApiToken>,
+    file_id: &str,
+) -> Result<(), ApiError> {
+    let Some(collection_id) = token.and_then(|token| token.collection_id.as_deref()) else {
+        return Ok(());
+    };
+    match collections::contains(pool, collection_id, file_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            warn!("Collection token used on file {} outside of collection {}", file_id, collection_id);
+            Err(ApiError::Forbidden("This token only reaches the files of its collection".to_string()))
+        }
+        Err(e) => {
+            error!("DB select collection error {}: {}", collection_id, e);
+            Err(db::error(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to look up a collection token that may list and download its collection
+/// It returns `None` for unknown and expired tokens and for tokens without the download scope.
+pub(crate) async fn find_collection_token(pool: &AnyPool, token: &str) -> Result<Option<data::ApiToken>, sqlx::Error> {
+    let found = sqlx::query_as::<_, data::ApiToken>(
+        r#"
+        SELECT *
+        FROM tokens
+        WHERE token = ? AND collection_id IS NOT NULL
+        "#,
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+    let now = Utc::now().timestamp();
+    Ok(found.filter(|found| {
+        scopes(found).contains(&Scope::Download) && found.expires.is_none_or(|expires| expires > now)
+    }))
+}
+
+/// Returns a token as JSON, with its scopes and content types as lists.
+fn token_json(token: &data::ApiToken) -> serde_json::Value {
+    json!({
+        "id": token.id,
+        "name": token.name,
+        "scopes": token.scopes.split(',').collect::<Vec<_>>(),
+        "max_bytes": token.max_bytes,
+        "content_types": allowed_content_types(Some(token)),
+        "expires": token.expires,
+        "created": token.created,
+        "collection_id": token.collection_id,
+    })
+}
+
+/// Returns true if a content type matches one of the patterns.
+/// A pattern is a full content type like `application/zip` or a whole group like `image/*`,
+/// parameters like `; charset=utf-8` are ignored.
+pub(crate) fn content_type_matches(patterns: &[String], content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    patterns.iter().any(|pattern| match pattern.strip_suffix("/*") {
+        Some(group) => content_type.strip_prefix(group).is_some_and(|rest| rest.starts_with('/')),
+        None => *pattern == content_type,
+    })
+}
+
+/// Helper to authenticate a request that scoped tokens may make
+/// This function accepts the account key, the session cookie
+/// or a scoped token that carries `scope` and has not expired.
+/// It returns the user together with the token if one was used,
+/// or a ready-made error response.
+pub(crate) async fn authenticate(
+    pool: &AnyPool,
+    headers: &HeaderMap,
+    ip: &str,
+    scope: Scope,
+) -> Result<(data::User, Option<data::ApiToken>), Response> {
+    let token = headers
+        .get("key")
+        .and_then(|hv| hv.to_str().ok())
+        .filter(|key| key.starts_with(TOKEN_PREFIX));
+    let Some(token) = token else {
+        return api::authenticate(pool, headers, ip).await.map(|user| (user, None));
+    };
+    if let Some(response) = lockout::check(ip, None) {
+        return Err(response);
+    }
+
+    let found = sqlx::query_as::<_, data::ApiToken>(
+        r#"
+        SELECT *
+        FROM tokens
+        WHERE token = ?
+        "#,
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await;
+    let found = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            warn!("Unknown token from {}", ip);
+            lockout::failed(ip, None);
+            audit::record_failed_login(pool, None, None, ip);
+            return Err((StatusCode::UNAUTHORIZED, "Your token is not valid").into_response());
+        }
+        Err(e) => {
+            error!("DB select token error: {}", e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    if found.expires.is_some_and(|expires| expires <= Utc::now().timestamp()) {
+        info!("Token {} of {} has expired", found.id, found.username);
+        return Err((StatusCode::UNAUTHORIZED, "Your token has expired").into_response());
+    }
+    if let Some(response) = missing_scope(&found, scope) {
+        return Err(response);
+    }
+
+    let user = sqlx::query_as::<_, data::User>(
+        r#"
+        SELECT *
+        FROM users
+        WHERE username = ?
+        "#,
+    )
+    .bind(&found.username)
+    .fetch_optional(pool)
+    .await;
+    match user {
+        Ok(Some(user)) => {
+            info!("Token {} of {} found in DB", found.id, user.username);
+            access_log::set_user(&user.username);
+            Ok((user, Some(found)))
+        }
+        Ok(None) => Err((StatusCode::UNAUTHORIZED, "Your token is not valid").into_response()),
+        Err(e) => {
+            error!("DB select error {}: {}", found.username, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Handler to create a scoped token
+/// This function mints a token that can be sent in the `key` header instead of the account key,
+/// but only for the requests its scopes allow.
+/// Uploads with a token can be further limited in size and content type.
+/// A collection token is bound to one collection of the user, so a team can share it without accounts:
+/// with the `download` scope it lists and downloads the files of the collection, private ones included,
+/// from `/collection/<id>?token=<token>` and `/download/<uuid>?token=<token>`,
+/// with the `upload` scope its uploads are added to the collection.
+/// The token itself is only returned once.
+/// Tokens can't create other tokens, the account key or a session is needed.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X POST -H "key: <key>" -H "content-type: application/json" -d '{"name": "ci", "scopes": ["upload"], "max_bytes": 104857600, "expires_in": 2592000, "content_types": ["application/zip"]}' http://localhost:3000/user/tokens
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// accepts the following JSON body:
+/// - name: a name to recognize the token by (optional)
+/// - scopes: any of `upload`, `list` and `delete` (optional, defaults to upload only)
+/// - max_bytes: the maximum size of one upload (optional)
+/// - expires_in: the number of seconds the token is valid (optional, never expires by default)
+/// - content_types: the content types uploads may have, `image/*` allows a whole group (optional)
+/// - collection: the ID of a collection to bind the token to, it then has `download` and optionally `upload` (optional)
+#[instrument(skip_all)]
+pub async fn create_token(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::NewApiToken>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received token creation from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let scopes = match request.scopes {
+        Some(names) => match names.iter().map(|name| Scope::parse(name)).collect::<Option<Vec<_>>>() {
+            Some(scopes) if !scopes.is_empty() => scopes,
+            _ => {
+                return (StatusCode::BAD_REQUEST, "Invalid scopes, use any of upload, list, delete and download")
+                    .into_response()
+            }
+        },
+        None if request.collection.is_some() => vec![Scope::Download],
+        None => vec![Scope::Upload],
+    };
+    // a collection token reaches the files of its collection and nothing else of the account
+    if let Some(collection) = &request.collection {
+        if let Err(response) = collections::owned_collection(&pool, collection, &user).await {
+            return response;
+        }
+        if scopes.iter().any(|scope| ![Scope::Download, Scope::Upload].contains(scope)) {
+            return (StatusCode::BAD_REQUEST, "Collection tokens can only have the download and upload scopes")
+                .into_response();
+        }
+    } else if scopes.contains(&Scope::Download) {
+        return (StatusCode::BAD_REQUEST, "The download scope needs a collection").into_response();
+    }
+    if request.max_bytes.is_some_and(|max_bytes| max_bytes <= 0) {
+        return (StatusCode::BAD_REQUEST, "max_bytes must be positive").into_response();
+    }
+    let content_types = match request.content_types {
+        Some(types) if types.len() > MAX_CONTENT_TYPES || types.iter().any(|t| !t.contains('/') || t.contains(',')) => {
+            return (StatusCode::BAD_REQUEST, "Invalid content types, use types like image/png or image/*")
+                .into_response();
+        }
+        Some(types) if !types.is_empty() => Some(
+            types
+                .iter()
+                .map(|t| t.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        _ => None,
+    };
+    let now = Utc::now().timestamp();
+    let expires = request
+        .expires_in
+        .map(|seconds| now.saturating_add(i64::try_from(seconds).unwrap_or(i64::MAX)));
+
+    let token = data::ApiToken {
+        id: Uuid::from_u128(rand::rng().random::<u128>()).to_string(),
+        username: user.username,
+        name: request.name,
+        scopes: scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(","),
+        max_bytes: request.max_bytes,
+        content_types,
+        expires,
+        created: now,
+        collection_id: request.collection,
+    };
+    let secret = format!("{}{}", TOKEN_PREFIX, hex::encode(rand::rng().random::<[u8; 32]>()));
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO tokens
+            (id, token, username, name, scopes, max_bytes, content_types, expires, created, collection_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&token.id)
+    .bind(&secret)
+    .bind(&token.username)
+    .bind(&token.name)
+    .bind(&token.scopes)
+    .bind(token.max_bytes)
+    .bind(&token.content_types)
+    .bind(token.expires)
+    .bind(token.created)
+    .bind(&token.collection_id)
+    .execute(&pool)
+    .await
+    {
+        error!("DB insert token error {}: {}", token.username, e);
+        return db::error_response(&e, "Database insert error");
+    }
+    info!("Token {} created for {}", token.id, token.username);
+    audit::record(&pool, audit::Action::CreateToken, Some(&token.username), Some(&token.id), &ip).await;
+
+    let mut body = token_json(&token);
+    body["token"] = json!(secret);
+    (StatusCode::CREATED, Json(body)).into_response()
+}
+
+/// Handler to list the scoped tokens of a user
+/// This function returns every token of the user without the token itself.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/user/tokens
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+#[instrument(skip_all)]
+pub async fn list_tokens(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received token listing from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as::<_, data::ApiToken>(
+        r#"
+        SELECT *
+        FROM tokens
+        WHERE username = ?
+        ORDER BY created
+        "#,
+    )
+    .bind(&user.username)
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(tokens) => Json(tokens.iter().map(token_json).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            error!("DB select tokens error {}: {}", user.username, e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
+
+/// Handler to revoke a scoped token
+/// This function deletes a token of the user, it stops working immediately.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X DELETE -H "key: <key>" http://localhost:3000/user/tokens/<id>
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following path parameter:
+/// - id: the ID of the token (not optional)
+#[instrument(skip_all, fields(id = %id))]
+pub async fn revoke_token(
+    Path(id): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received token revocation for {} from IP: {}", id, ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match sqlx::query(
+        r#"
+        DELETE FROM tokens
+        WHERE id = ? AND username = ?
+        "#,
+    )
+    .bind(&id)
+    .bind(&user.username)
+    .execute(&pool)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            (StatusCode::NOT_FOUND, "Token not found").into_response()
+        }
+        Ok(_) => {
+            info!("Token {} of {} revoked", id, user.username);
+            audit::record(&pool, audit::Action::RevokeToken, Some(&user.username), Some(&id), &ip).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("DB delete token error {}: {}", id, e);
+            db::error_response(&e, "Database delete error")
+        }
+    }
+}
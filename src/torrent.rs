@@ -0,0 +1,189 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use sha1::{Digest, Sha1};
+use sqlx::AnyPool;
+use tracing::{error, info, instrument};
+
+use crate::api;
+use crate::data;
+use crate::ids;
+use crate::reports;
+use crate::s3::uri_encode;
+use crate::storage;
+use std::net::SocketAddr;
+
+/// The smallest piece size, it doubles until a torrent has at most `MAX_PIECES` pieces.
+const MIN_PIECE_LENGTH: usize = 256 * 1024;
+
+/// The largest piece size, bigger pieces are badly supported by clients.
+const MAX_PIECE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The number of pieces a torrent aims to stay below.
+const MAX_PIECES: usize = 2000;
+
+/// This enum represents a bencoded value, dictionaries keep their keys sorted like bencoding requires.
+enum Bencode<'a> {
+    Integer(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Bencode<'a>>),
+    Dictionary(Vec<(&'static str, Bencode<'a>)>),
+    /// A value that is bencoded already.
+    Encoded(&'a [u8]),
+}
+
+impl Bencode<'_> {
+    /// Appends the bencoding of the value.
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Bencode::Integer(value) => out.extend_from_slice(format!("i{}e", value).as_bytes()),
+            Bencode::Bytes(bytes) => {
+                out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+            }
+            Bencode::List(values) => {
+                out.push(b'l');
+                values.iter().for_each(|value| value.encode(out));
+                out.push(b'e');
+            }
+            Bencode::Dictionary(entries) => {
+                let mut entries: Vec<_> = entries.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+                out.push(b'd');
+                for (key, value) in entries {
+                    Bencode::Bytes(key.as_bytes()).encode(out);
+                    value.encode(out);
+                }
+                out.push(b'e');
+            }
+            Bencode::Encoded(bytes) => out.extend_from_slice(bytes),
+        }
+    }
+}
+
+/// Returns the piece length for a file of the given size.
+fn piece_length(size: usize) -> usize {
+    let mut length = MIN_PIECE_LENGTH;
+    while size.div_ceil(length) > MAX_PIECES && length < MAX_PIECE_LENGTH {
+        length *= 2;
+    }
+    length
+}
+
+/// Returns the `info` dictionary of a torrent with the SHA-1 of every piece of the contents.
+fn info_dictionary(file: &data::File, contents: &[u8]) -> Vec<u8> {
+    let piece_length = piece_length(contents.len());
+    let pieces: Vec<u8> = contents
+        .chunks(piece_length)
+        .flat_map(|piece| Sha1::digest(piece).to_vec())
+        .collect();
+    let mut info = Vec::new();
+    Bencode::Dictionary(vec![
+        ("length", Bencode::Integer(contents.len() as i64)),
+        ("name", Bencode::Bytes(file.file_name.as_bytes())),
+        ("piece length", Bencode::Integer(piece_length as i64)),
+        ("pieces", Bencode::Bytes(&pieces)),
+    ])
+    .encode(&mut info);
+    info
+}
+
+/// Handler to return a torrent of a file
+/// This function returns a .torrent of the file with its download URL as a web seed,
+/// so large files can be shared peer to peer while bitBeam stays the origin.
+/// Web seeds fetch the file piece by piece and every piece counts as a download,
+/// so only public files with unlimited downloads can be made into torrents.
+/// The torrent has no trackers, peers find each other through the DHT.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET http://localhost:3000/file/<uuid>/torrent -o <file_name>.torrent
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following query parameters:
+/// - format: `magnet` to get a magnet link instead of the .torrent (optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn torrent(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::TorrentQuery>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received torrent request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+    let file = match api::find_file(&pool, "id", &uuid).await {
+        Ok(Some(file)) => file,
+        Ok(None) if reports::is_blocked(&pool, &uuid).await => return reports::taken_down(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(response) => return response,
+    };
+    if file.is_private() || file.download_limit != 0 {
+        return (
+            StatusCode::CONFLICT,
+            "Torrents can only be made of public files with unlimited downloads",
+        )
+            .into_response();
+    }
+
+    let contents = match storage::read_blob(&config, file.blob_name(), file.file_size.max(0) as u64).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("File read error {}: {}", file.id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "File read error").into_response();
+        }
+    };
+    // hashing every piece of a large file takes a while, it doesn't hold up other requests
+    let (info, file) = match tokio::task::spawn_blocking(move || (info_dictionary(&file, &contents), file)).await {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Torrent hashing error {}: {}", uuid, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Torrent creation error").into_response();
+        }
+    };
+
+    if query.format.as_deref() == Some("magnet") {
+        let info_hash = hex::encode(Sha1::digest(&info));
+        let torrent_url = api::public_url(&config, &format!("file/{}/torrent", file.id));
+        let magnet = format!(
+            "magnet:?xt=urn:btih:{}&dn={}&xl={}&ws={}&xs={}",
+            info_hash,
+            uri_encode(&file.file_name),
+            file.file_size,
+            uri_encode(&file.download_url),
+            uri_encode(&torrent_url)
+        );
+        return (StatusCode::OK, magnet).into_response();
+    }
+
+    let mut torrent = Vec::new();
+    Bencode::Dictionary(vec![
+        ("created by", Bencode::Bytes(b"bitBeam")),
+        ("creation date", Bencode::Integer(file.upload_time)),
+        ("info", Bencode::Encoded(&info)),
+        // BEP 19 web seed, clients download the pieces nobody seeds from here
+        ("url-list", Bencode::List(vec![Bencode::Bytes(file.download_url.as_bytes())])),
+    ])
+    .encode(&mut torrent);
+    info!("Torrent of {} created", file.id);
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/x-bittorrent".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.torrent\"", file.id),
+            ),
+        ],
+        torrent,
+    )
+        .into_response()
+}
@@ -0,0 +1,175 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use crate::logging;
+use crate::otlp;
+
+/// The longest `tracestate` header that is passed on, longer ones are dropped.
+const MAX_TRACESTATE_LENGTH: usize = 512;
+
+tokio::task_local! {
+    /// The trace context of the request the current task handles.
+    static CURRENT: TraceContext;
+}
+
+/// This struct represents the W3C trace context of a request.
+/// It continues the trace of a valid `traceparent` header or starts a new trace.
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// The span bitBeam handles the request in, outgoing requests name it as their parent.
+    pub span_id: [u8; 8],
+    /// The span of the caller, `None` if the request started the trace.
+    pub parent_id: Option<[u8; 8]>,
+    pub flags: u8,
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Returns the trace context of a request from its `traceparent` and `tracestate` headers.
+    /// Missing or malformed headers start a new sampled trace, `tracestate` is only kept with a valid `traceparent`.
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let parent = headers
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent);
+        let Some((trace_id, parent_id, flags)) = parent else {
+            return TraceContext {
+                trace_id: random_id(),
+                span_id: random_id(),
+                parent_id: None,
+                flags: 1,
+                state: None,
+            };
+        };
+        let state = headers
+            .get("tracestate")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|state| !state.is_empty() && state.len() <= MAX_TRACESTATE_LENGTH)
+            .map(str::to_string);
+        TraceContext {
+            trace_id,
+            span_id: random_id(),
+            parent_id: Some(parent_id),
+            flags,
+            state,
+        }
+    }
+
+    /// Returns the hex encoded trace ID, as sent in `x-trace-id`.
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    /// Returns the `traceparent` header of requests made while handling this one.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", hex::encode(self.trace_id), hex::encode(self.span_id), self.flags)
+    }
+
+    /// Returns whether the caller asked for the trace to be recorded.
+    pub(crate) fn sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+}
+
+/// Helper to parse a `traceparent` header into the trace ID, parent span ID and flags.
+/// Version `00` has exactly four fields, later versions may add fields which are ignored.
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], u8)> {
+    let value = value.trim();
+    if value.chars().any(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut fields = value.split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+    let version = <[u8; 1]>::try_from(hex::decode(version).ok()?).ok()?[0];
+    if version == 0xff || (version == 0 && fields.next().is_some()) {
+        return None;
+    }
+    let trace_id = <[u8; 16]>::try_from(hex::decode(trace_id).ok()?).ok()?;
+    let parent_id = <[u8; 8]>::try_from(hex::decode(parent_id).ok()?).ok()?;
+    let flags = <[u8; 1]>::try_from(hex::decode(flags).ok()?).ok()?[0];
+    // all zero IDs are invalid
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+    Some((trace_id, parent_id, flags))
+}
+
+/// Returns a random trace or span ID, never all zeros.
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    rand::rng().fill(&mut id[..]);
+    if id.iter().all(|byte| *byte == 0) {
+        id[N - 1] = 1;
+    }
+    id
+}
+
+/// Returns the trace context of the request the current task handles, `None` outside of requests.
+pub(crate) fn current() -> Option<TraceContext> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Returns the `traceparent` and `tracestate` headers that continue the trace of the current request,
+/// empty outside of requests.
+pub(crate) fn outgoing_headers() -> Vec<(&'static str, String)> {
+    let Some(context) = current() else {
+        return Vec::new();
+    };
+    let mut headers = vec![("traceparent", context.traceparent())];
+    if let Some(state) = context.state {
+        headers.push(("tracestate", state));
+    }
+    headers
+}
+
+/// Helper to add the trace context of the current request to an outgoing request.
+pub(crate) fn inject(mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    for (name, value) in outgoing_headers() {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// This middleware continues the trace of the `traceparent` header or starts a new one.
+/// The request span and outgoing requests carry the trace, the response tells its ID in `x-trace-id`.
+/// Sampled requests are exported as server spans if `otlp_endpoint` is set,
+/// the span ends once the response headers are ready, streamed bodies may still be sent after that.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let context = TraceContext::from_headers(request.headers());
+    request.extensions_mut().insert(context.clone());
+    let start = SystemTime::now();
+    let method = request.method().to_string();
+    let route = logging::logged_path(&request);
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let mut response = CURRENT.scope(context.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&context.trace_id_hex()) {
+        response.headers_mut().insert("x-trace-id", value);
+    }
+    if context.sampled() {
+        otlp::export(otlp::ServerSpan {
+            context,
+            method,
+            route,
+            client_ip,
+            status: response.status().as_u16(),
+            start,
+            end: SystemTime::now(),
+        });
+    }
+    response
+}
@@ -0,0 +1,410 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Datelike, TimeZone, Utc};
+use lettre::message::Mailbox;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, warn};
+
+use crate::anonymous;
+use crate::api;
+use crate::data;
+use crate::db;
+use crate::email;
+use crate::error::ApiError;
+use crate::webhook;
+
+/// The number of months `/user/usage` lists the transfer of, the current one included.
+const HISTORY_MONTHS: i64 = 12;
+
+/// Which way bytes were transferred, each has its own column and cap.
+#[derive(Clone, Copy)]
+pub(crate) enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    /// Returns the column of `usage_periods` the bytes are counted in.
+    fn column(self) -> &'static str {
+        match self {
+            Direction::Upload => "upload_bytes",
+            Direction::Download => "download_bytes",
+        }
+    }
+
+    /// Returns the monthly cap of the direction, `None` if there is none.
+    fn cap(self, config: &data::Config) -> Option<u64> {
+        let cap = match self {
+            Direction::Upload => config.monthly_upload_cap,
+            Direction::Download => config.monthly_download_cap,
+        };
+        (cap > 0).then_some(cap)
+    }
+}
+
+/// Returns the month transfers are counted in right now, like `2026-10`.
+pub(crate) fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+/// Returns the seconds until the next month starts and the caps are reset.
+fn seconds_until_next_month() -> i64 {
+    let now = Utc::now();
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .map(|next| (next - now).num_seconds().max(1))
+        .unwrap_or(1)
+}
+
+/// Returns the bytes the user uploaded and downloaded in the month.
+async fn transferred(pool: &AnyPool, username: &str, month: &str) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        SELECT upload_bytes, download_bytes
+        FROM usage_periods
+        WHERE username = ? AND month = ?
+        "#,
+    )
+    .bind(username)
+    .bind(month)
+    .fetch_optional(pool)
+    .await
+    .map(Option::unwrap_or_default)
+}
+
+/// Returns the transfer of the user in the last months, newest first.
+pub(crate) async fn history(pool: &AnyPool, username: &str) -> Result<Vec<data::UsagePeriod>, sqlx::Error> {
+    sqlx::query_as::<_, data::UsagePeriod>(
+        r#"
+        SELECT month, upload_bytes, download_bytes
+        FROM usage_periods
+        WHERE username = ?
+        ORDER BY month DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(username)
+    .bind(HISTORY_MONTHS)
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns the transfer of the user this month with the caps that apply to it, as shown at `/user/usage`.
+pub(crate) async fn usage(pool: &AnyPool, config: &data::Config, username: &str) -> Result<data::TransferUsage, sqlx::Error> {
+    let month = current_month();
+    let (upload_bytes, download_bytes) = transferred(pool, username, &month).await?;
+    Ok(data::TransferUsage {
+        month,
+        upload_bytes,
+        download_bytes,
+        upload_cap: Direction::Upload.cap(config),
+        download_cap: Direction::Download.cap(config),
+        history: history(pool, username).await?,
+    })
+}
+
+/// Helper to check that `bytes` more bytes keep the user within their monthly cap of the direction.
+/// Uploads over the cap are refused with 429 and a `Retry-After` until the next month,
+/// downloads of files whose owner used up their download transfer are refused with 403,
+/// since the one downloading can't do anything about it.
+pub(crate) async fn check(
+    pool: &AnyPool,
+    config: &data::Config,
+    username: &str,
+    direction: Direction,
+    bytes: i64,
+) -> Result<(), Response> {
+    let Some(cap) = direction.cap(config) else {
+        return Ok(());
+    };
+    let used = match transferred(pool, username, &current_month()).await {
+        Ok((upload_bytes, download_bytes)) => match direction {
+            Direction::Upload => upload_bytes,
+            Direction::Download => download_bytes,
+        },
+        Err(e) => {
+            error!("DB select usage error {}: {}", username, e);
+            return Err(db::error_response(&e, "Database select error"));
+        }
+    };
+    let used = u64::try_from(used).unwrap_or_default();
+    if used.saturating_add(bytes.max(0) as u64) <= cap {
+        return Ok(());
+    }
+    let (status, error, message) = match direction {
+        Direction::Upload => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "monthly_upload_cap",
+            "The monthly upload transfer of your account is used up",
+        ),
+        Direction::Download => (
+            StatusCode::FORBIDDEN,
+            "monthly_download_cap",
+            "The owner of this file used up their monthly download transfer",
+        ),
+    };
+    warn!("{} of {} over the monthly {} cap refused", error, username, direction.column());
+    let retry_after = seconds_until_next_month();
+    let mut response = ApiError::Rejected {
+        status,
+        error,
+        message: message.to_string(),
+        details: json!({
+            "month": current_month(),
+            "cap_bytes": cap,
+            "used_bytes": used,
+            "resets_in": retry_after,
+        }),
+    }
+    .into_response();
+    if let Direction::Upload = direction {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+    Err(response)
+}
+
+/// This function counts transferred bytes against the month of the user.
+/// The transfer is always counted, whether a cap is configured or not.
+/// If `quota_warning_thresholds` are configured the quotas of the user are checked for them in the background.
+pub(crate) async fn record(pool: &AnyPool, config: &data::Config, username: &str, direction: Direction, bytes: i64) {
+    if bytes <= 0 {
+        return;
+    }
+    let month = current_month();
+    if let Err(e) = add(pool, username, &month, direction, bytes).await {
+        error!("DB update usage error {}: {}", username, e);
+        return;
+    }
+    if !config.quota_warning_thresholds.is_empty() {
+        tokio::spawn(warn_thresholds(pool.clone(), config.clone(), username.to_string(), direction));
+    }
+}
+
+/// Helper to add bytes to the row of the month, the row is created by the first transfer of the month.
+async fn add(pool: &AnyPool, username: &str, month: &str, direction: Direction, bytes: i64) -> Result<(), sqlx::Error> {
+    let update = format!(
+        "UPDATE usage_periods SET {0} = {0} + ? WHERE username = ? AND month = ?",
+        direction.column()
+    );
+    let updated = sqlx::query(&update)
+        .bind(bytes)
+        .bind(username)
+        .bind(month)
+        .execute(pool)
+        .await?;
+    if updated.rows_affected() > 0 {
+        return Ok(());
+    }
+    let (upload_bytes, download_bytes) = match direction {
+        Direction::Upload => (bytes, 0),
+        Direction::Download => (0, bytes),
+    };
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO usage_periods
+            (username, month, upload_bytes, download_bytes)
+        VALUES (?, ?, ?, ?)
+        "#,
+    )
+    .bind(username)
+    .bind(month)
+    .bind(upload_bytes)
+    .bind(download_bytes)
+    .execute(pool)
+    .await;
+    match inserted {
+        // a concurrent transfer created the row first
+        Err(e) if e.as_database_error().is_some_and(|e| e.is_unique_violation()) => {
+            sqlx::query(&update)
+                .bind(bytes)
+                .bind(username)
+                .bind(month)
+                .execute(pool)
+                .await?;
+            Ok(())
+        }
+        inserted => inserted.map(|_| ()),
+    }
+}
+
+/// This enum represents the quotas users are warned about before they run out of them.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Quota {
+    Storage,
+    MonthlyUpload,
+    MonthlyDownload,
+}
+
+impl Quota {
+    /// Returns the column of `usage_periods` that holds the highest threshold warned about this month.
+    fn column(self) -> &'static str {
+        match self {
+            Quota::Storage => "storage_warned",
+            Quota::MonthlyUpload => "upload_warned",
+            Quota::MonthlyDownload => "download_warned",
+        }
+    }
+
+    /// Returns how the quota is called in warning emails.
+    pub(crate) fn describe(self) -> &'static str {
+        match self {
+            Quota::Storage => "storage quota",
+            Quota::MonthlyUpload => "monthly upload transfer",
+            Quota::MonthlyDownload => "monthly download transfer",
+        }
+    }
+}
+
+/// This struct is a warning that a user crossed a threshold of one of their quotas.
+#[derive(Clone, Serialize)]
+pub(crate) struct Warning {
+    pub username: String,
+    pub quota: Quota,
+    /// The percentage of `quota_warning_thresholds` that was crossed.
+    pub threshold: u64,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+    /// The month the warning was sent in, every threshold is warned about once per month.
+    pub period: String,
+}
+
+/// This function warns a user about every quota whose usage crossed one of the `quota_warning_thresholds`
+/// since the last warning of the month. It is started after a transfer was recorded,
+/// uploads check the storage quota and the upload cap, downloads the download cap.
+/// Warnings go to the webhooks of the user and the instance and to the verified email address of the user.
+async fn warn_thresholds(pool: AnyPool, config: data::Config, username: String, direction: Direction) {
+    if username == anonymous::ANONYMOUS_OWNER {
+        return;
+    }
+    let month = current_month();
+    let usage = match quota_usage(&pool, &config, &username, &month, direction).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            error!("DB select usage error {}: {}", username, e);
+            return;
+        }
+    };
+    for (quota, used, limit) in usage {
+        let percent = u128::from(used) * 100 / u128::from(limit.max(1));
+        let Some(&threshold) = config
+            .quota_warning_thresholds
+            .iter()
+            .rev()
+            .find(|threshold| u128::from(**threshold) <= percent)
+        else {
+            continue;
+        };
+        match claim_warning(&pool, &username, &month, quota, threshold).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!("DB update usage error {}: {}", username, e);
+                continue;
+            }
+        }
+        info!("{} of {} crossed {}%", quota.column(), username, threshold);
+        let warning = Warning {
+            username: username.clone(),
+            quota,
+            threshold,
+            used_bytes: used,
+            limit_bytes: limit,
+            period: month.clone(),
+        };
+        webhook::emit_quota_warning(&warning);
+        notify_user(&pool, &config, &warning).await;
+    }
+}
+
+/// Helper to collect the used bytes and limits of the quotas a transfer in the direction counts against.
+/// Quotas that are not configured are left out.
+async fn quota_usage(
+    pool: &AnyPool,
+    config: &data::Config,
+    username: &str,
+    month: &str,
+    direction: Direction,
+) -> Result<Vec<(Quota, u64, u64)>, sqlx::Error> {
+    let mut usage = Vec::new();
+    let (upload_bytes, download_bytes) = transferred(pool, username, month).await?;
+    let (quota, transferred) = match direction {
+        Direction::Upload => (Quota::MonthlyUpload, upload_bytes),
+        Direction::Download => (Quota::MonthlyDownload, download_bytes),
+    };
+    if let Some(cap) = direction.cap(config) {
+        usage.push((quota, u64::try_from(transferred).unwrap_or_default(), cap));
+    }
+    if let Direction::Upload = direction {
+        let user_quota = db::user_storage_quota(pool, username).await?;
+        if let Some(limit) = api::storage_quota(config, user_quota) {
+            let (_, used) = db::storage_usage(pool, username).await?;
+            usage.push((Quota::Storage, u64::try_from(used).unwrap_or_default(), limit));
+        }
+    }
+    Ok(usage)
+}
+
+/// Helper to raise the warned threshold of the quota in the month.
+/// It returns false if the threshold or a higher one was warned about already,
+/// so concurrent transfers warn only once.
+async fn claim_warning(
+    pool: &AnyPool,
+    username: &str,
+    month: &str,
+    quota: Quota,
+    threshold: u64,
+) -> Result<bool, sqlx::Error> {
+    let threshold = i64::try_from(threshold).unwrap_or(i64::MAX);
+    sqlx::query(&format!(
+        "UPDATE usage_periods SET {0} = ? WHERE username = ? AND month = ? AND {0} < ?",
+        quota.column()
+    ))
+    .bind(threshold)
+    .bind(username)
+    .bind(month)
+    .bind(threshold)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Helper to email a quota warning to the user if their email address is verified.
+async fn notify_user(pool: &AnyPool, config: &data::Config, warning: &Warning) {
+    if config.smtp_host.is_none() {
+        return;
+    }
+    let email = sqlx::query_scalar::<_, Option<db::Text>>(
+        r#"
+        SELECT email
+        FROM users
+        WHERE username = ? AND email_verified != 0
+        "#,
+    )
+    .bind(&warning.username)
+    .fetch_optional(pool)
+    .await;
+    let mailbox = match email {
+        Ok(Some(Some(email))) => String::from(email).parse::<Mailbox>().ok(),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("DB select email error {}: {}", warning.username, e);
+            None
+        }
+    };
+    if let Some(mailbox) = mailbox {
+        if let Err(e) = email::send_quota_warning(config, mailbox, warning).await {
+            warn!("Could not email {} about their {}: {}", warning.username, warning.quota.describe(), e);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use axum::{extract::ConnectInfo, Extension, Router};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use tokio::net::UnixListener;
+use tracing::{info, warn};
+
+use crate::systemd;
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// This function serves the application on a unix domain socket at `path`.
+/// A stale socket left behind by an earlier run is replaced, any other file at `path` is left alone,
+/// the new socket gets the permission bits in `mode`.
+/// Unix sockets have no peer address, requests look like they come from `127.0.0.1`,
+/// add that to `trusted_proxies` to see the clients behind a local reverse proxy.
+pub async fn serve(app: Router, path: &str, mode: u32) -> std::io::Result<()> {
+    // never remove anything but a socket, the path may be a typo
+    match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            tokio::fs::remove_file(path).await?;
+            warn!("Removed stale socket {}", path);
+        }
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "the path exists and is not a socket",
+            ))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    systemd::notify_ready();
+    info!("Listening on unix socket {} with mode {:o}", path, mode);
+
+    let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    axum::serve(listener, app.layer(Extension(ConnectInfo(local)))).await
+}
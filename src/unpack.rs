@@ -0,0 +1,280 @@
+use async_zip::base::read::mem::ZipFileReader;
+use axum::{
+    body::Bytes,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use futures_util::AsyncReadExt;
+use serde_json::json;
+use sqlx::AnyPool;
+use std::io::Read;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api;
+use crate::audit;
+use crate::batch;
+use crate::data;
+use crate::db;
+use crate::error::ApiError;
+
+/// Returns the error of an archive with more files or bytes than the server unpacks.
+fn too_large(config: &data::Config) -> ApiError {
+    warn!("Archive over the extraction limits refused");
+    ApiError::Rejected {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        error: "archive_too_large",
+        message: "The archive holds too many files or too many bytes to unpack".to_string(),
+        details: json!({
+            "max_files": config.extract_max_files,
+            "max_bytes": config.extract_max_bytes,
+        }),
+    }
+}
+
+/// Returns the path of an archive entry with empty and `.` parts removed,
+/// `None` for absolute paths and paths that climb out of the archive with `..`.
+/// Such entries would escape the directory when the collection is downloaded as a ZIP and unpacked again.
+fn safe_path(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') || name.split('/').next().is_some_and(|first| first.contains(':')) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Returns the files of a ZIP archive as path and contents, directories are skipped.
+/// The sizes the archive announces are checked before anything is inflated
+/// and every entry is cut off at the bytes that are left, so a ZIP bomb can't fill the memory.
+async fn read_zip(config: &data::Config, archive: Bytes) -> Result<Vec<(String, Bytes)>, ApiError> {
+    let malformed = |e: async_zip::error::ZipError| {
+        warn!("Malformed zip archive: {}", e);
+        ApiError::BadRequest("Malformed zip archive".to_string())
+    };
+    let reader = ZipFileReader::new(archive.to_vec()).await.map_err(malformed)?;
+    let mut files = Vec::new();
+    for (index, entry) in reader.file().entries().iter().enumerate() {
+        if entry.dir().map_err(malformed)? {
+            continue;
+        }
+        let name = entry.filename().as_str().map_err(malformed)?.to_string();
+        files.push((index, name, entry.uncompressed_size()));
+    }
+    let announced = files.iter().fold(0u64, |total, (_, _, size)| total.saturating_add(*size));
+    if files.len() > config.extract_max_files || announced > config.extract_max_bytes {
+        return Err(too_large(config));
+    }
+
+    let mut entries = Vec::with_capacity(files.len());
+    let mut remaining = config.extract_max_bytes;
+    for (index, name, _) in files {
+        let mut contents = Vec::new();
+        reader
+            .reader_with_entry(index)
+            .await
+            .map_err(malformed)?
+            .take(remaining.saturating_add(1))
+            .read_to_end(&mut contents)
+            .await
+            .map_err(|e| {
+                warn!("Zip entry read error {}: {}", name, e);
+                ApiError::BadRequest("Malformed zip archive".to_string())
+            })?;
+        remaining = remaining.checked_sub(contents.len() as u64).ok_or_else(|| too_large(config))?;
+        entries.push((name, Bytes::from(contents)));
+    }
+    Ok(entries)
+}
+
+/// Returns the tar archive inside a gzip stream, cut off after `limit` bytes.
+fn gunzip(archive: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    GzDecoder::new(archive).take(limit).read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Returns the files of a ZIP, tar or gzip compressed tar archive as path and contents.
+/// The format is told by the first bytes of the archive, not by its name.
+async fn read_archive(config: &data::Config, archive: Bytes) -> Result<Vec<(String, Bytes)>, ApiError> {
+    let entries = if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+        read_zip(config, archive).await?
+    } else {
+        let tar = match archive.starts_with(&[0x1f, 0x8b]) {
+            true => match gunzip(&archive, config.extract_max_bytes.saturating_add(1)) {
+                // the tar blocks around the files take up a little room as well
+                Ok(tar) if tar.len() as u64 > config.extract_max_bytes => return Err(too_large(config)),
+                Ok(tar) => Bytes::from(tar),
+                Err(e) => {
+                    warn!("Gzip read error: {}", e);
+                    return Err(ApiError::BadRequest("Malformed gzip stream".to_string()));
+                }
+            },
+            false => archive,
+        };
+        let Some(entries) = batch::read_tar(&tar) else {
+            return Err(ApiError::BadRequest("The body is not a zip or tar archive".to_string()));
+        };
+        entries.into_iter().map(|(name, _, contents)| (name, contents)).collect()
+    };
+    let total = entries.iter().fold(0u64, |total, (_, contents)| total.saturating_add(contents.len() as u64));
+    if entries.len() > config.extract_max_files || total > config.extract_max_bytes {
+        return Err(too_large(config));
+    }
+
+    let mut files = Vec::with_capacity(entries.len());
+    for (name, contents) in entries {
+        match safe_path(&name) {
+            Some(path) => files.push((path, contents)),
+            None => {
+                warn!("Archive entry with unsafe path {:?} refused", name);
+                return Err(ApiError::BadRequest(format!("Unsafe path in archive: {}", name)));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Helper to store the files of an uploaded archive in a new collection
+/// This function unpacks a ZIP, tar or tar.gz archive, stores every file in it like a batch upload
+/// with the options of the upload and groups them in a collection named after the archive.
+/// The files are named after their path in the archive.
+/// Archives with absolute paths or paths that climb out with `..` are refused,
+/// as are archives with more than `extract_max_files` files or `extract_max_bytes` unpacked bytes.
+/// If one file is rejected, none of them is stored,
+/// if the collection can't be stored the files are moved to the trash again.
+pub(crate) async fn store_archive(
+    pool: &AnyPool,
+    config: &data::Config,
+    ip: &str,
+    template: data::NewFile,
+    archive: Bytes,
+) -> Result<Response, ApiError> {
+    let entries = read_archive(config, archive).await?;
+    if entries.is_empty() {
+        return Err(ApiError::BadRequest("The archive holds no files".to_string()));
+    }
+
+    let collection = data::Collection {
+        id: Uuid::new_v4().to_string(),
+        name: template.file_name.clone(),
+        owner: template.owner.clone(),
+        created_at: Utc::now().timestamp(),
+    };
+    let uploads = entries
+        .into_iter()
+        .map(|(file_name, contents)| {
+            let new_file = data::NewFile {
+                file_name,
+                content_type: "application/octet-stream".to_string(),
+                expected_sha256: None,
+                expected_md5: None,
+                slug: None,
+                ..template.clone()
+            };
+            (new_file, contents)
+        })
+        .collect();
+    let files = api::store_files(pool, config, ip, uploads).await?;
+
+    let store = async {
+        let mut transaction = pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO collections (id, name, owner, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&collection.id)
+        .bind(&collection.name)
+        .bind(&collection.owner)
+        .bind(collection.created_at)
+        .execute(&mut *transaction)
+        .await?;
+        for uploaded in &files {
+            sqlx::query(
+                r#"
+                INSERT INTO collection_files (collection_id, file_id)
+                VALUES (?, ?)
+                "#,
+            )
+            .bind(&collection.id)
+            .bind(&uploaded.file.id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await
+    };
+    if let Err(e) = store.await {
+        error!("DB insert collection error {}: {}", collection.id, e);
+        // the files are already stored, without their collection they go to the trash
+        for uploaded in &files {
+            if api::trash_file(pool, config, &uploaded.file).await.is_err() {
+                warn!("Could not trash file {} of collection {}", uploaded.file.id, collection.id);
+            }
+        }
+        return Err(db::error(&e, "Database insert error"));
+    }
+    info!("Unpacked {} files of {} into collection {}", files.len(), collection.name, collection.id);
+    for uploaded in &files {
+        audit::record(pool, audit::Action::Upload, Some(&uploaded.file.owner), Some(&uploaded.file.id), ip).await;
+    }
+
+    Ok(Json(data::ExtractedArchive {
+        collection_url: api::public_url(config, &format!("c/{}", collection.id)),
+        collection,
+        files,
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::safe_path;
+
+    #[test]
+    fn keeps_paths_inside_the_archive() {
+        assert_eq!(safe_path("docs/readme.txt").as_deref(), Some("docs/readme.txt"));
+        assert_eq!(safe_path("./docs//readme.txt").as_deref(), Some("docs/readme.txt"));
+        assert_eq!(safe_path("docs\\readme.txt").as_deref(), Some("docs/readme.txt"));
+        assert_eq!(safe_path("docs/").as_deref(), Some("docs"));
+    }
+
+    #[test]
+    fn refuses_paths_that_climb_out() {
+        for name in ["..", "../etc/passwd", "docs/../../etc/passwd", "docs\\..\\..\\boot.ini"] {
+            assert_eq!(safe_path(name), None, "{} should be refused", name);
+        }
+    }
+
+    #[test]
+    fn refuses_absolute_paths() {
+        for name in ["/etc/passwd", "\\windows\\system32", "\\\\server\\share\\file.txt"] {
+            assert_eq!(safe_path(name), None, "{} should be refused", name);
+        }
+    }
+
+    #[test]
+    fn refuses_drive_prefixes() {
+        for name in ["C:/Windows/win.ini", "C:\\Windows\\win.ini", "C:win.ini", "c:"] {
+            assert_eq!(safe_path(name), None, "{} should be refused", name);
+        }
+    }
+
+    #[test]
+    fn refuses_empty_paths() {
+        for name in ["", ".", "./", "//"] {
+            assert_eq!(safe_path(name), None, "{:?} should be refused", name);
+        }
+    }
+}
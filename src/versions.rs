@@ -0,0 +1,398 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequest, Multipart, Path, Query, Request},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http_body_util::Limited;
+use serde_json::json;
+use sqlx::AnyPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::capacity;
+use crate::changes;
+use crate::data;
+use crate::db;
+use crate::ids;
+use crate::ip_quota;
+use crate::storage;
+use crate::tokens;
+use crate::transfer;
+use std::net::SocketAddr;
+
+/// Helper to look up a file that is owned by the user of the request and can still be downloaded.
+async fn owned_file(pool: &AnyPool, uuid: &str, user: &data::User) -> Result<data::File, Response> {
+    let file = sqlx::query_as::<_, data::File>(&format!(
+        r#"
+        SELECT {}
+        FROM files
+        WHERE id = ?
+        "#,
+        data::File::COLUMNS
+    ))
+    .bind(uuid)
+    .fetch_optional(pool)
+    .await;
+    match file {
+        Ok(Some(file)) if !file.is_available() => {
+            info!("File {} is expired or in the trash", uuid);
+            Err((StatusCode::NOT_FOUND, "File not found").into_response())
+        }
+        Ok(Some(file)) if file.owner == user.username => Ok(file),
+        Ok(Some(file)) => {
+            warn!("User {} tried to access versions of file {} owned by {}", user.username, uuid, file.owner);
+            Err((StatusCode::FORBIDDEN, "You do not own this file").into_response())
+        }
+        Ok(None) => {
+            info!("File not found in DB: {}", uuid);
+            Err((StatusCode::NOT_FOUND, "File not found").into_response())
+        }
+        Err(e) => {
+            error!("DB select error {}: {}", uuid, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Helper to pick the version of a file a download asked for
+/// It returns the file as it was at `version`,
+/// the file itself if no version or the latest one was asked for,
+/// or a ready-made error response if the version does not exist.
+pub(crate) async fn select(
+    pool: &AnyPool,
+    file: data::File,
+    version: Option<i32>,
+) -> Result<data::File, Response> {
+    let Some(version) = version.filter(|&version| version != file.version) else {
+        return Ok(file);
+    };
+    let earlier = sqlx::query_as::<_, data::FileVersion>(
+        r#"
+        SELECT *
+        FROM file_versions
+        WHERE file_id = ? AND version = ?
+        "#,
+    )
+    .bind(&file.id)
+    .bind(version)
+    .fetch_optional(pool)
+    .await;
+    match earlier {
+        Ok(Some(earlier)) => Ok(earlier.apply(&file)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Version not found").into_response()),
+        Err(e) => {
+            error!("DB select version error {}: {}", file.id, e);
+            Err(db::error_response(&e, "Database select error"))
+        }
+    }
+}
+
+/// Returns the JSON description of a version with the URL it can be downloaded from.
+fn version_json(file: &data::File, current: bool) -> serde_json::Value {
+    json!({
+        "version": file.version,
+        "current": current,
+        "file_name": file.file_name,
+        "content_type": file.content_type,
+        "file_size": file.file_size,
+        "upload_time": file.upload_time,
+        "encrypted": file.encrypted != 0,
+        "cid": file.cid,
+        "download_url": format!("{}?version={}", file.download_url, file.version),
+    })
+}
+
+/// Handler to upload a new version of a file
+/// This function replaces the contents of a file while it keeps its ID, download URL,
+/// download limit, visibility, tags and collections.
+/// The previous contents are kept as an earlier version that can still be downloaded
+/// with `?version=<n>` until the file itself is removed.
+/// The upload is checked exactly like a new upload.
+/// Only the owner of the file can upload new versions.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" -H "file_name: <file_name>" --data-binary @<file_path> http://localhost:3000/file/<uuid>
+/// example multipart request: curl -X PUT -H "key: <key>" -F "file=@<file_path>" http://localhost:3000/file/<uuid>
+/// requires the following headers:
+/// - key: the key of the user or a token with the `upload` scope (not optional)
+/// - file_name: the new name of the file (optional, keeps the old name, taken from the part for multipart)
+/// - content-type: the content type of the new version (optional, taken from the part for multipart)
+/// - content-sha256: the SHA-256 digest the upload is checked against (optional)
+/// - content-md5: the MD5 digest the upload is checked against (optional)
+/// - encrypted: `true` if the body was encrypted by the client (optional, can also be a multipart field)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn upload_version(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::UploadQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received new version of {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let (user, token) = match tokens::authenticate(&pool, &headers, &ip, tokens::Scope::Upload).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    if !user.is_verified() {
+        return api::unverified(&user).into_response();
+    }
+    let file = match owned_file(&pool, &uuid, &user).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+    if let Err(e) = tokens::check_collection(&pool, token.as_ref(), &file.id).await {
+        return e.into_response();
+    }
+
+    let limit = api::upload_limit(&config, &user, token.as_ref());
+    let content_length = headers
+        .get("content-length")
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return api::too_large(limit).into_response();
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, limit as usize)));
+
+    let mut new_file = data::NewFile {
+        file_name: headers
+            .get("file_name")
+            .and_then(|hv| hv.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| file.file_name.clone()),
+        content_type: headers
+            .get("content-type")
+            .and_then(|hv| hv.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+        download_limit: file.download_limit,
+        owner: user.username.clone(),
+        expected_sha256: headers
+            .get("content-sha256")
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.trim().to_string()),
+        expected_md5: headers
+            .get("content-md5")
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.trim().to_string()),
+        slug: None,
+        encrypted: headers
+            .get("encrypted")
+            .and_then(|hv| hv.to_str().ok())
+            .is_some_and(|s| s.trim().eq_ignore_ascii_case("true")),
+        tags: Vec::new(),
+        visibility: file.visibility.clone(),
+        allowed_content_types: tokens::allowed_content_types(token.as_ref()),
+        expires: file.expires,
+        expire_if_unused_days: file.expire_if_unused_days,
+        cache_control: file.cache_control.clone(),
+        published: file.is_published(),
+        collection_id: None,
+        description: file.description.clone(),
+    };
+
+    let body = if new_file.content_type.starts_with("multipart/form-data") {
+        let multipart = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart,
+            Err(e) => {
+                warn!("Multipart parse error: {}", e);
+                return e.into_response();
+            }
+        };
+        match api::read_multipart(multipart, &mut new_file).await {
+            Ok(body) => body,
+            Err(response) if response.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return api::too_large(limit).into_response()
+            }
+            Err(response) => return response,
+        }
+    } else {
+        match Bytes::from_request(request, &()).await {
+            Ok(body) => body,
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return api::too_large(limit).into_response(),
+            Err(e) => {
+                warn!("Body read error: {}", e);
+                return e.into_response();
+            }
+        }
+    };
+
+    // the new version replaces the size of the file, the old one is kept in the versions
+    if let Err(response) = api::check_storage_quota(&pool, &config, &file.owner, body.len() as i64 - file.file_size).await {
+        return response;
+    }
+    // the old version stays on disk, so the whole new version counts against the cap of the instance
+    if let Err(response) = capacity::check(&pool, &config, body.len() as i64).await {
+        return response;
+    }
+    if let Err(response) = ip_quota::check(&pool, &config, &ip, 1, body.len() as i64).await {
+        return response;
+    }
+    if let Err(response) =
+        transfer::check(&pool, &config, &file.owner, transfer::Direction::Upload, body.len() as i64).await
+    {
+        return response;
+    }
+    // only the contents of the staged file are used, everything else stays with the file
+    let mut staged = match api::stage_file(&pool, &config, new_file, body).await {
+        Ok(staged) => staged,
+        Err(response) => return response,
+    };
+    let part = staged.part.take();
+    let contents = &staged.file;
+    let blobs = vec![(contents.blob_name(), staged.body.as_ref(), part)];
+    let update = async {
+        let mut transaction = pool.begin().await?;
+        // a concurrent upload of the same version fails on the primary key
+        sqlx::query(
+            r#"
+            INSERT INTO file_versions
+                (file_id, version, file_name, content_type, detected_content_type, file_size, content_hash, encrypted, upload_time, cid)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&file.id)
+        .bind(file.version)
+        .bind(&file.file_name)
+        .bind(&file.content_type)
+        .bind(&file.detected_content_type)
+        .bind(file.file_size)
+        .bind(&file.content_hash)
+        .bind(file.encrypted)
+        .bind(file.upload_time)
+        .bind(&file.cid)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE files
+            SET file_name = ?, content_type = ?, detected_content_type = ?, file_size = ?,
+                content_hash = ?, encrypted = ?, upload_time = ?, cid = ?, version = version + 1, verified = 0
+            WHERE id = ?
+            "#,
+        )
+        .bind(&contents.file_name)
+        .bind(&contents.content_type)
+        .bind(&contents.detected_content_type)
+        .bind(contents.file_size)
+        .bind(&contents.content_hash)
+        .bind(contents.encrypted)
+        .bind(contents.upload_time)
+        .bind(&contents.cid)
+        .bind(&file.id)
+        .execute(&mut *transaction)
+        .await?;
+        // the signature was made over the old contents
+        sqlx::query("DELETE FROM file_signatures WHERE file_id = ?")
+            .bind(&file.id)
+            .execute(&mut *transaction)
+            .await?;
+        let updated = sqlx::query_as::<_, data::File>(&format!("SELECT {} FROM files WHERE id = ?", data::File::COLUMNS))
+            .bind(&file.id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(updated)
+    };
+    let updated = match storage::add_references(&pool, &config, blobs, update).await {
+        Ok(updated) => updated,
+        Err(e) if e.as_database_error().is_some_and(|e| e.is_unique_violation()) => {
+            warn!("Concurrent new version of {}: {}", uuid, e);
+            return (StatusCode::CONFLICT, "Another version was uploaded at the same time").into_response();
+        }
+        Err(e) => {
+            error!("DB update version error {}: {}", uuid, e);
+            return db::error_response(&e, "Database update error");
+        }
+    };
+    cache::forget_file(&uuid).await;
+    changes::record(&pool, &updated.owner, &uuid, changes::Change::Updated).await;
+    ip_quota::record(&pool, &config, &ip, &[updated.file_size]).await;
+    transfer::record(&pool, &config, &updated.owner, transfer::Direction::Upload, updated.file_size).await;
+    info!("File {} is now at version {}", uuid, updated.version);
+    audit::record(&pool, audit::Action::UploadVersion, Some(&user.username), Some(&uuid), &ip).await;
+
+    let staged = api::StagedFile {
+        file: updated,
+        ..staged
+    };
+    let uploaded_file = api::finish_file(&pool, &config, staged).await;
+    api::upload_response(&headers, &query, uploaded_file)
+}
+
+/// Handler to list the versions of a file
+/// This function returns every version of a file, the earliest first,
+/// together with the URL each of them can be downloaded from.
+/// Only the owner of the file can list its versions.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X GET -H "key: <key>" http://localhost:3000/file/<uuid>/versions
+/// requires the following headers:
+/// - key: the key of the user or a token with the `list` scope (not optional)
+///
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn list_versions(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received versions request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return ids::invalid_file_id(&uuid).into_response();
+    }
+
+    let (user, _) = match tokens::authenticate(&pool, &headers, &ip, tokens::Scope::List).await {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    let file = match owned_file(&pool, &uuid, &user).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    let earlier = sqlx::query_as::<_, data::FileVersion>(
+        r#"
+        SELECT *
+        FROM file_versions
+        WHERE file_id = ?
+        ORDER BY version
+        "#,
+    )
+    .bind(&uuid)
+    .fetch_all(&pool)
+    .await;
+    match earlier {
+        Ok(earlier) => {
+            let mut versions: Vec<serde_json::Value> = earlier
+                .iter()
+                .map(|version| version_json(&version.apply(&file), false))
+                .collect();
+            versions.push(version_json(&file, true));
+            Json(versions).into_response()
+        }
+        Err(e) => {
+            error!("DB select versions error {}: {}", uuid, e);
+            db::error_response(&e, "Database select error")
+        }
+    }
+}
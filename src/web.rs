@@ -0,0 +1,236 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Response},
+    Extension,
+};
+use sqlx::AnyPool;
+use std::net::SocketAddr;
+use tracing::{info, instrument};
+
+use crate::api;
+use crate::collections;
+use crate::data;
+use crate::db;
+use crate::email;
+use crate::error::ApiError;
+use crate::ids;
+use crate::reports;
+use crate::s3::uri_encode;
+use crate::share;
+use crate::thumbnail;
+
+/// Returns a page with the path prefix of the server filled in,
+/// the pages prefix every request to the API with it.
+/// The prefix is checked to be plain path characters when the configuration is loaded.
+fn render(page: &str, config: &data::Config) -> Html<String> {
+    Html(page.replace("{{base_path}}", &config.base_path))
+}
+
+/// Returns a page with its `{{name}}` placeholders replaced by the values of the same name.
+/// The page is filled in one pass, so a file name or description that looks like a placeholder stays as it is.
+fn fill(page: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(page.len());
+    let mut rest = page;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| values.iter().find(|(name, _)| *name == &after[..end]).map(|(name, value)| (name.len(), value)));
+        match value {
+            Some((length, value)) => {
+                filled.push_str(value);
+                rest = &after[length + 2..];
+            }
+            None => {
+                filled.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Returns the Open Graph and Twitter Card tags of a shared link,
+/// so chat apps that unfurl the link show its title, description and preview image.
+fn open_graph(title: &str, description: &str, url: &str, image: Option<&str>) -> String {
+    let mut tags = vec![
+        ("property", "og:type", "website"),
+        ("property", "og:site_name", "bitBeam"),
+        ("property", "og:title", title),
+        ("property", "og:description", description),
+        ("property", "og:url", url),
+        ("name", "twitter:title", title),
+        ("name", "twitter:description", description),
+    ];
+    match image {
+        Some(image) => tags.extend([
+            ("property", "og:image", image),
+            ("name", "twitter:image", image),
+            ("name", "twitter:card", "summary_large_image"),
+        ]),
+        None => tags.push(("name", "twitter:card", "summary")),
+    }
+    tags.iter()
+        .map(|(attribute, name, content)| {
+            format!(r#"<meta {}="{}" content="{}">"#, attribute, name, ammonia::clean_text(content))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The embedded web frontend, it only talks to the JSON API.
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+/// Handler to serve the built-in web UI
+/// This function returns a single page that can upload files by drag and drop,
+/// list the files of the user, copy their download links and delete them.
+/// Users log in with their password and keep a session cookie, or enter their key, which is kept in the browser.
+/// example request: curl http://localhost:3000/
+pub async fn index(Extension(config): Extension<data::Config>) -> Html<String> {
+    render(INDEX_HTML, &config)
+}
+
+/// The page that decrypts end to end encrypted downloads in the browser.
+const DECRYPT_HTML: &str = include_str!("web/decrypt.html");
+
+/// Handler to serve the decrypting download page of an end to end encrypted file
+/// This function returns a page that reads the key from the URL fragment,
+/// downloads the ciphertext from `/download/<uuid>` and decrypts it in the browser.
+/// The fragment is never sent to the server, so the server never sees the key.
+/// The page expects the key as base64url encoded raw AES-256-GCM key
+/// and the ciphertext as a 12 byte IV followed by the encrypted data.
+/// example request: curl http://localhost:3000/e/<uuid>#key=<key>
+pub async fn decrypt_page(Extension(config): Extension<data::Config>) -> Html<String> {
+    render(DECRYPT_HTML, &config)
+}
+
+/// The page that shows a shared collection.
+const COLLECTION_HTML: &str = include_str!("web/collection.html");
+
+/// Handler to serve the page of a shared collection
+/// This function returns a page that lists the files of the collection
+/// from `/collection/<id>` with download links and a button to download all of them as a ZIP.
+/// The name and owner of the collection are filled into its Open Graph tags,
+/// an unknown collection gets the page without them, which shows the error.
+/// example request: curl http://localhost:3000/c/<id>
+pub async fn collection_page(
+    Path(id): Path<String>,
+    Extension(db::ReadPool(replica)): Extension<db::ReadPool>,
+    Extension(config): Extension<data::Config>,
+) -> Html<String> {
+    let meta = match collections::find_collection(&replica, &id).await {
+        Ok(collection) => open_graph(
+            &collection.name,
+            &format!("A collection of files shared by {}", collection.owner),
+            &api::public_url(&config, &format!("c/{}", collection.id)),
+            None,
+        ),
+        Err(_) => String::new(),
+    };
+    let Html(page) = render(COLLECTION_HTML, &config);
+    Html(page.replace("{{meta}}", &meta))
+}
+
+/// The page of the public directory.
+const PUBLIC_HTML: &str = include_str!("web/public.html");
+
+/// Handler to serve the public directory
+/// This function returns a page that searches and lists the published files from `/public`
+/// with links to their landing pages and downloads.
+/// example request: curl http://localhost:3000/p
+pub async fn public_page(Extension(config): Extension<data::Config>) -> Html<String> {
+    render(PUBLIC_HTML, &config)
+}
+
+/// The landing page of a shared file.
+const SHARE_HTML: &str = include_str!("web/share.html");
+
+/// Keeps the landing page from loading anything but the thumbnail of the file.
+const SHARE_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src 'self'; style-src 'unsafe-inline'";
+
+/// Handler to serve the landing page of a shared file
+/// This function returns a page with the name, size, type and description of the file,
+/// its thumbnail if it has one and a button that starts the real download.
+/// The page carries Open Graph and Twitter Card tags, so the link unfurls with a preview in chat apps.
+/// Opening the page does not count as a download.
+/// Private files need the same key or share token as a download, the token is passed on to the button.
+/// It also logs the IP address of the client making the request.
+/// example request: curl http://localhost:3000/s/<uuid>
+/// requires the following path parameter:
+/// - uuid: the UUID of the file (not optional)
+///
+/// accepts the following query parameter:
+/// - token: a share token of a private file (optional)
+#[instrument(skip_all, fields(uuid = %uuid))]
+pub async fn share_page(
+    Path(uuid): Path<String>,
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<data::Config>,
+    Query(query): Query<data::DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received share page request for {} from IP: {}", uuid, ip);
+
+    if !ids::is_valid_file_id(&uuid) {
+        return Err(ids::invalid_file_id(&uuid));
+    }
+    let file = match api::find_file(&pool, "id", &uuid).await? {
+        Some(file) => file,
+        None if reports::is_blocked(&pool, &uuid).await => return Err(reports::taken_down().into()),
+        None => return Err(ApiError::Gone("Download limit reached".to_string())),
+    };
+    if !file.is_available() {
+        return Err(api::gone(&file));
+    }
+    share::check_access(&pool, &file, &headers, query.token.as_deref(), &ip).await?;
+
+    // the share token of a private file is passed on to the download and the thumbnail
+    let token = query
+        .token
+        .as_deref()
+        .map(|token| format!("?token={}", uri_encode(token)))
+        .unwrap_or_default();
+    let download = format!("{}{}", file.download_url, token);
+    let thumbnail = (file.encrypted == 0 && thumbnail::is_supported(&file.content_type))
+        .then(|| api::public_url(&config, &format!("thumbnail/{}{}", file.id, token)));
+    let summary = format!("{}, {}", email::human_size(file.file_size), file.content_type);
+    // chat apps fetch the preview image without the token
+    let image = thumbnail.as_deref().filter(|_| !file.is_private());
+    let meta = open_graph(
+        &file.file_name,
+        file.description.as_deref().unwrap_or(&summary),
+        &api::public_url(&config, &format!("s/{}", file.id)),
+        image,
+    );
+    let preview = thumbnail
+        .map(|url| format!(r#"<img src="{}" alt="">"#, ammonia::clean_text(&url)))
+        .unwrap_or_default();
+    let note = file
+        .description
+        .as_deref()
+        .map(|description| format!(r#"<p class="description">{}</p>"#, ammonia::clean_text(description)))
+        .unwrap_or_default();
+
+    let page = fill(
+        SHARE_HTML,
+        &[
+            ("meta", &meta),
+            ("preview", &preview),
+            ("download", &ammonia::clean_text(&download)),
+            ("summary", &ammonia::clean_text(&summary)),
+            ("title", &ammonia::clean_text(&file.file_name)),
+            ("description", &note),
+        ],
+    );
+    Ok((
+        [(header::CONTENT_SECURITY_POLICY, SHARE_CONTENT_SECURITY_POLICY)],
+        Html(page),
+    )
+        .into_response())
+}
@@ -0,0 +1,356 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::AnyPool;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, warn};
+
+use crate::api;
+use crate::audit;
+use crate::cache;
+use crate::data;
+use crate::db;
+use crate::notify;
+use crate::remote;
+use crate::sse;
+use crate::trace_context;
+use crate::transfer;
+use std::net::SocketAddr;
+
+/// The number of events that can wait for delivery before new ones are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// The number of times a delivery is attempted before it is given up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry, it doubles with every further attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a single delivery may take.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The queue events are sent to, it is set once the dispatcher is started.
+static QUEUE: OnceLock<mpsc::Sender<Queued>> = OnceLock::new();
+
+/// This enum represents the file events webhooks are notified about.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum EventKind {
+    #[serde(rename = "file.uploaded")]
+    Uploaded,
+    #[serde(rename = "file.downloaded")]
+    Downloaded,
+    #[serde(rename = "file.deleted")]
+    Deleted,
+    #[serde(rename = "file.limit_reached")]
+    LimitReached,
+    #[serde(rename = "file.expired")]
+    Expired,
+}
+
+/// This struct is the JSON body posted to webhooks.
+#[derive(Serialize)]
+struct Event {
+    event: EventKind,
+    timestamp: i64,
+    file: data::File,
+    /// The `traceparent` and `tracestate` headers of the request that caused the event.
+    #[serde(skip)]
+    trace_headers: Vec<(&'static str, String)>,
+}
+
+/// This struct is the JSON body posted to webhooks when a user crossed a quota warning threshold.
+#[derive(Serialize)]
+struct QuotaEvent {
+    event: &'static str,
+    timestamp: i64,
+    #[serde(flatten)]
+    warning: transfer::Warning,
+}
+
+/// This enum represents the events waiting in the queue.
+enum Queued {
+    File(Box<Event>),
+    Quota(QuotaEvent),
+}
+
+/// This function starts the background task that delivers webhooks.
+/// Events are queued by `emit` and posted to every global webhook URL,
+/// to the webhook URL of the file owner and to their push notification service.
+pub fn start(pool: AnyPool, config: data::Config) {
+    let (sender, mut receiver) = mpsc::channel::<Queued>(QUEUE_SIZE);
+    if QUEUE.set(sender).is_err() {
+        warn!("Webhook dispatcher already started");
+        return;
+    }
+    let client = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Could not build the webhook HTTP client: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while let Some(queued) = receiver.recv().await {
+            let (owner, body, trace_headers) = match &queued {
+                Queued::File(event) => {
+                    // push notifications go through the same delivery and retries as webhooks
+                    let notification = notify::target(&pool, &event.file.owner)
+                        .await
+                        .and_then(|target| notify::message_for(&target, event.event, &event.file));
+                    if let Some(message) = notification {
+                        tokio::spawn(deliver(
+                            client.clone(),
+                            message.url,
+                            message.body,
+                            message.headers,
+                            config.webhook_allow_private,
+                        ));
+                    }
+                    (&event.file.owner, serde_json::to_vec(event), event.trace_headers.as_slice())
+                }
+                Queued::Quota(event) => (&event.warning.username, serde_json::to_vec(event), &[][..]),
+            };
+            let user_url = owner_webhook(&pool, owner).await;
+            if config.webhook_urls.is_empty() && user_url.is_none() {
+                continue;
+            }
+            let body = match body {
+                Ok(body) => body,
+                Err(e) => {
+                    error!("Webhook serialize error: {}", e);
+                    continue;
+                }
+            };
+            let mut headers = vec![("content-type", "application/json".to_string())];
+            if let Some(secret) = config.webhook_secret.as_deref() {
+                headers.push(("x-bitbeam-signature", format!("sha256={}", sign(secret, &body))));
+            }
+            // receivers in the same OpenTelemetry setup see the delivery as part of the request's trace
+            headers.extend_from_slice(trace_headers);
+            // every target is delivered on its own so a slow one doesn't hold up the rest
+            for url in &config.webhook_urls {
+                tokio::spawn(deliver(
+                    client.clone(),
+                    url.clone(),
+                    body.clone(),
+                    headers.clone(),
+                    true,
+                ));
+            }
+            if let Some(url) = user_url {
+                tokio::spawn(deliver(
+                    client.clone(),
+                    url,
+                    body.clone(),
+                    headers.clone(),
+                    config.webhook_allow_private,
+                ));
+            }
+        }
+    });
+}
+
+/// This function queues a file event for webhook delivery and hands it to the open event streams.
+/// It never blocks, events are dropped with a warning if the queue is full.
+pub fn emit(kind: EventKind, file: &data::File) {
+    sse::publish(kind, file);
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let event = Event {
+        event: kind,
+        timestamp: Utc::now().timestamp(),
+        file: file.clone(),
+        trace_headers: trace_context::outgoing_headers(),
+    };
+    if let Err(e) = queue.try_send(Queued::File(Box::new(event))) {
+        warn!("Dropped webhook event for {}: {}", file.id, e);
+    }
+}
+
+/// This function queues a `quota.warning` event for webhook delivery.
+/// It never blocks, the event is dropped with a warning if the queue is full.
+pub(crate) fn emit_quota_warning(warning: &transfer::Warning) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let event = QuotaEvent {
+        event: "quota.warning",
+        timestamp: Utc::now().timestamp(),
+        warning: warning.clone(),
+    };
+    if let Err(e) = queue.try_send(Queued::Quota(event)) {
+        warn!("Dropped quota warning for {}: {}", warning.username, e);
+    }
+}
+
+/// Helper to look up the webhook URL of a user.
+async fn owner_webhook(pool: &AnyPool, owner: &str) -> Option<String> {
+    sqlx::query_scalar::<_, Option<db::Text>>(
+        r#"
+        SELECT webhook_url
+        FROM users
+        WHERE username = ?
+        "#,
+    )
+    .bind(owner)
+    .fetch_optional(pool)
+    .await
+    .unwrap_or_else(|e| {
+        error!("DB select error {}: {}", owner, e);
+        None
+    })
+    .flatten()
+    .map(String::from)
+}
+
+/// Returns the hex encoded HMAC-SHA256 of the body, keyed with the webhook secret.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Helper to deliver one event to one URL with the given headers
+/// Failed deliveries are retried with exponential backoff until `MAX_ATTEMPTS` is reached.
+/// URLs set by users are checked against private addresses on every attempt
+/// unless `allow_private` is set.
+async fn deliver(
+    client: reqwest::Client,
+    url: String,
+    body: Vec<u8>,
+    headers: Vec<(&'static str, String)>,
+    allow_private: bool,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        let client = match pinned_client(&client, &url, allow_private).await {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Webhook {} refused: {}", url, e);
+                return;
+            }
+        };
+        let mut request = client.post(&url).body(body.clone());
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("Webhook {} delivered", url);
+                return;
+            }
+            Ok(response) => warn!(
+                "Webhook {} attempt {} returned {}",
+                url,
+                attempt,
+                response.status()
+            ),
+            Err(e) => warn!("Webhook {} attempt {} failed: {}", url, attempt, e),
+        }
+    }
+    error!("Webhook {} given up after {} attempts", url, MAX_ATTEMPTS);
+}
+
+/// Helper to get a client for a webhook URL
+/// Trusted URLs use the shared client, other URLs get a client
+/// that is pinned to an address that was checked against private networks.
+async fn pinned_client(
+    client: &reqwest::Client,
+    url: &str,
+    allow_private: bool,
+) -> Result<reqwest::Client, String> {
+    if allow_private {
+        return Ok(client.clone());
+    }
+    let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let host = parsed.host_str().ok_or("URL has no host")?.to_string();
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let target = remote::resolve(&host, port, false)
+        .await
+        .map_err(|_| "host resolves to a private address or can not be resolved".to_string())?;
+    reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, target)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Handler to set the webhook URL of a user
+/// This function stores a URL that receives the file events of the user's files.
+/// An empty or missing URL removes the webhook.
+/// It also logs the IP address of the client making the request.
+/// example request: curl -X PUT -H "key: <key>" -H "content-type: application/json" -d '{"url": "https://example.com/hook"}' http://localhost:3000/user/webhook
+/// requires the following headers:
+/// - key: the key of the user (not optional)
+///
+/// requires the following JSON body:
+/// - url: the http or https URL to notify (optional, removes the webhook if missing)
+#[instrument(skip_all)]
+pub async fn set_webhook(
+    Extension(pool): Extension<AnyPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<data::WebhookRequest>,
+) -> Response {
+    //log the IP address of the client and the call
+    let ip = addr.ip().to_string();
+    info!("Received webhook update from IP: {}", ip);
+
+    let user = match api::authenticate(&pool, &headers, &ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let url = request.url.filter(|url| !url.trim().is_empty());
+    if let Some(url) = &url {
+        match reqwest::Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+            _ => {
+                return (StatusCode::BAD_REQUEST, "Only http and https URLs are supported")
+                    .into_response()
+            }
+        }
+    }
+
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE users
+        SET webhook_url = ?
+        WHERE "key" = ?
+        "#,
+    )
+    .bind(&url)
+    .bind(&user.key)
+    .execute(&pool)
+    .await
+    {
+        error!("DB update error {}: {}", user.username, e);
+        return db::error_response(&e, "Database update error");
+    }
+    cache::forget_user(&pool, &user.username).await;
+    info!("Webhook of {} set to {:?}", user.username, url);
+    audit::record(&pool, audit::Action::SetWebhook, Some(&user.username), url.as_deref(), &ip).await;
+
+    Json(json!({
+        "username": user.username,
+        "webhook_url": url,
+    }))
+    .into_response()
+}
@@ -0,0 +1,216 @@
+//! Authentication, download limits, private files, storage quotas, expiry, imports and garbage collection,
+//! driven through the router of an in-process `TestServer`.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use serde_json::Value;
+
+/// Uploads the body as the user with the given upload headers and returns the response.
+async fn upload(server: &TestServer, user: &TestUser, headers: &[(&str, &str)], body: &'static str) -> Response {
+    let mut request = Request::post("/upload")
+        .header("key", &user.key)
+        .header("file_name", "notes.txt");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    server.request(request.body(Body::from(body)).unwrap()).await
+}
+
+/// Uploads the body as the user and returns the ID of the stored file.
+async fn upload_id(server: &TestServer, user: &TestUser, headers: &[(&str, &str)], body: &'static str) -> String {
+    let response = upload(server, user, headers, body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json(response).await["id"].as_str().expect("the upload has an id").to_string()
+}
+
+/// Sends a GET request, with the key if there is one.
+async fn get(server: &TestServer, path: &str, key: Option<&str>) -> Response {
+    let mut request = Request::get(path);
+    if let Some(key) = key {
+        request = request.header("key", key);
+    }
+    server.request(request.body(Body::empty()).unwrap()).await
+}
+
+/// Returns the body of a response.
+async fn body(response: Response) -> Vec<u8> {
+    response.into_body().collect().await.unwrap().to_bytes().to_vec()
+}
+
+/// Returns the JSON body of a response.
+async fn json(response: Response) -> Value {
+    serde_json::from_slice(&body(response).await).expect("the body is JSON")
+}
+
+#[tokio::test]
+async fn a_bad_key_is_rejected() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+
+    let response = get(&server, "/files", Some("not-a-key")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = upload(
+        &server,
+        &TestUser {
+            key: "00000000-0000-0000-0000-000000000000".to_string(),
+            ..user.clone()
+        },
+        &[],
+        "hello",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = get(&server, "/files", Some(&user.key)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_file_is_gone_once_its_download_limit_is_reached() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let id = upload_id(&server, &user, &[("download_limit", "1")], "hello").await;
+
+    let response = get(&server, &format!("/download/{}", id), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, b"hello");
+
+    let response = get(&server, &format!("/download/{}", id), None).await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(json(response).await["reason"], "download_limit_reached");
+}
+
+#[tokio::test]
+async fn a_private_file_is_refused_to_everybody_but_its_owner() {
+    let server = TestServer::start().await;
+    let owner = server.create_user("alice").await;
+    let other = server.create_user("bob").await;
+    let id = upload_id(&server, &owner, &[("visibility", "private"), ("download_limit", "0")], "hello").await;
+
+    for path in ["/download", "/file", "/thumbnail", "/view", "/play", "/s"] {
+        let path = match path {
+            "/file" => format!("/file/{}/info", id),
+            path => format!("{}/{}", path, id),
+        };
+        let response = get(&server, &path, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} without a key", path);
+        let response = get(&server, &path, Some(&other.key)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} with the key of another user", path);
+    }
+
+    let response = get(&server, &format!("/download/{}", id), Some(&owner.key)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, b"hello");
+}
+
+#[tokio::test]
+async fn an_upload_over_the_storage_quota_is_refused() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    // the quota of the user, the configuration is shared with the tests running at the same time
+    sqlx::query("UPDATE users SET storage_quota = 8 WHERE username = ?")
+        .bind(&user.username)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    upload_id(&server, &user, &[], "hello").await;
+
+    let response = upload(&server, &user, &[], "world").await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(json(response).await["error"], "storage_quota_exceeded");
+
+    let files = json(get(&server, "/files", Some(&user.key)).await).await;
+    assert_eq!(files.to_string().matches("notes.txt").count(), 1);
+}
+
+#[tokio::test]
+async fn a_file_is_gone_once_it_expired() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let id = upload_id(&server, &user, &[("max_age", "3600"), ("download_limit", "0")], "hello").await;
+
+    let response = get(&server, &format!("/download/{}", id), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the test server caches no rows, the next download reads the expiry again
+    sqlx::query("UPDATE files SET expires = ? WHERE id = ?")
+        .bind(chrono::Utc::now().timestamp() - 1)
+        .bind(&id)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let response = get(&server, &format!("/download/{}", id), None).await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(json(response).await["reason"], "expired");
+}
+
+#[tokio::test]
+async fn the_sharex_config_takes_the_key_from_the_header() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+
+    let response = get(&server, "/sharex", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = get(&server, &format!("/sharex/{}", user.key), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = get(&server, "/sharex?download_limit=5", Some(&user.key)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let sxcu = json(response).await;
+    assert_eq!(sxcu["Headers"]["key"], user.key.as_str());
+    assert_eq!(sxcu["Headers"]["download_limit"], "5");
+}
+
+#[tokio::test]
+async fn garbage_collection_removes_the_files_whose_blob_is_missing() {
+    let server = TestServer::start().await;
+    let admin = server.create_admin("root").await;
+    let user = server.create_user("alice").await;
+    let kept = server.create_file(&user, "kept.txt", "still here").await;
+    let lost = server.create_file(&user, "lost.txt", "gone from the disk").await;
+    // both files are older than the grace period of the collection
+    sqlx::query("UPDATE files SET upload_time = 0")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let blob = lost.blob_name();
+    std::fs::remove_file(server.data_path().join(&blob[0..2]).join(&blob[2..4]).join(blob)).unwrap();
+
+    let request = Request::post("/admin/gc").header("key", &admin.key).body(Body::empty()).unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response).await["removed_files"], serde_json::json!([lost.id]));
+
+    let response = get(&server, &format!("/download/{}", kept.id), None).await;
+    assert_eq!(body(response).await, b"still here");
+}
+
+#[tokio::test]
+async fn an_import_rejects_files_whose_headers_could_not_be_sent() {
+    let server = TestServer::start().await;
+    let admin = server.create_admin("root").await;
+    let user = server.create_user("alice").await;
+    server.create_file(&user, "notes.txt", "hello").await;
+    let mut dump = json(get(&server, "/admin/export", Some(&admin.key)).await).await;
+    dump["users"] = serde_json::json!([]);
+    let file = dump["files"][0].clone();
+    let mut imported = Vec::new();
+    for (id, content_type) in [("a", "text/plain"), ("b", "text/plain\r\nx-injected: yes")] {
+        let mut file = file.clone();
+        file["id"] = format!("imported-{}", id).into();
+        file["content_type"] = content_type.into();
+        imported.push(file);
+    }
+    dump["files"] = imported.into();
+
+    let request = Request::post("/admin/import")
+        .header("key", &admin.key)
+        .header("content-type", "application/json")
+        .body(Body::from(dump.to_string()))
+        .unwrap();
+    let summary = json(server.request(request).await).await;
+    assert_eq!(summary["files_imported"], 1);
+    assert_eq!(summary["files_rejected"], 1);
+}
@@ -0,0 +1,150 @@
+//! The hash chain of the audit log and what `/admin/audit/verify` finds when it was tampered with.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Returns the JSON body of a response.
+async fn json(response: Response) -> Value {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).expect("the body is JSON")
+}
+
+/// Creates a key for the user, which records an entry in the audit log.
+async fn create_key(server: &TestServer, user: &TestUser) {
+    let request = Request::post("/user/keys")
+        .header("key", &user.key)
+        .header("content-type", "application/json")
+        .body(Body::from(json!({"name": "laptop"}).to_string()))
+        .unwrap();
+    assert_eq!(server.request(request).await.status(), StatusCode::CREATED);
+}
+
+/// Verifies the audit log as the admin and returns the result.
+async fn verify(server: &TestServer, admin: &TestUser) -> Value {
+    let request = Request::get("/admin/audit/verify")
+        .header("key", &admin.key)
+        .body(Body::empty())
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json(response).await
+}
+
+/// Starts a server whose audit log holds three entries of alice and returns it with its admin.
+async fn logged_server() -> (TestServer, TestUser) {
+    let server = TestServer::start().await;
+    let admin = server.create_admin("admin").await;
+    let user = server.create_user("alice").await;
+    for _ in 0..3 {
+        create_key(&server, &user).await;
+    }
+    (server, admin)
+}
+
+#[tokio::test]
+async fn an_untouched_audit_log_verifies() {
+    let (server, admin) = logged_server().await;
+
+    let verification = verify(&server, &admin).await;
+    assert_eq!(verification["valid"], json!(true), "{}", verification);
+    assert_eq!(verification["entries"], json!(3));
+    assert_eq!(verification["head_seq"], json!(3));
+}
+
+#[tokio::test]
+async fn a_changed_entry_breaks_the_chain() {
+    let (server, admin) = logged_server().await;
+    sqlx::query("UPDATE audit_log SET action = 'user.revoke_key' WHERE seq = 2")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    let verification = verify(&server, &admin).await;
+    assert_eq!(verification["valid"], json!(false));
+    assert_eq!(verification["broken_at"], json!(2));
+    assert_eq!(
+        verification["problem"],
+        json!("the entry was changed or does not follow the one before it")
+    );
+}
+
+#[tokio::test]
+async fn a_changed_username_breaks_the_chain() {
+    let (server, admin) = logged_server().await;
+    sqlx::query("UPDATE audit_log SET username = 'mallory' WHERE seq = 2")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    let verification = verify(&server, &admin).await;
+    assert_eq!(verification["valid"], json!(false));
+    assert_eq!(verification["broken_at"], json!(2));
+    assert_eq!(
+        verification["problem"],
+        json!("the username, target or IP of the entry was changed")
+    );
+}
+
+#[tokio::test]
+async fn a_removed_entry_breaks_the_chain() {
+    let (server, admin) = logged_server().await;
+    sqlx::query("DELETE FROM audit_log WHERE seq = 2")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    let verification = verify(&server, &admin).await;
+    assert_eq!(verification["valid"], json!(false));
+    assert_eq!(verification["broken_at"], json!(2));
+    assert_eq!(verification["problem"], json!("entry 2 is missing"));
+}
+
+#[tokio::test]
+async fn an_anonymized_entry_still_verifies() {
+    let server = TestServer::start().await;
+    let admin = server.create_admin("admin").await;
+    let user = server.create_user("alice").await;
+    create_key(&server, &user).await;
+    let request = Request::delete("/user/me")
+        .header("key", &user.key)
+        .header("password", &user.password)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(server.request(request).await.status(), StatusCode::OK);
+
+    let verification = verify(&server, &admin).await;
+    assert_eq!(verification["valid"], json!(true), "{}", verification);
+    assert_eq!(verification["entries"], json!(2));
+    assert_eq!(verification["anonymized"], json!(1));
+}
+
+#[tokio::test]
+async fn a_failed_login_is_recorded_in_the_background() {
+    let server = TestServer::start().await;
+    let admin = server.create_admin("admin").await;
+    let request = Request::get("/user/usage")
+        .header("key", "not-a-key")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(server.request(request).await.status(), StatusCode::UNAUTHORIZED);
+
+    // the request does not wait for the entry
+    let mut entries = Value::Null;
+    for _ in 0..100 {
+        let request = Request::get("/admin/audit?action=user.login_failed")
+            .header("key", &admin.key)
+            .body(Body::empty())
+            .unwrap();
+        entries = json(server.request(request).await).await;
+        if entries.as_array().is_some_and(|entries| !entries.is_empty()) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(entries.as_array().map(Vec::len), Some(1), "{}", entries);
+    assert_eq!(verify(&server, &admin).await["valid"], json!(true));
+}
@@ -0,0 +1,35 @@
+//! `/blob/{sha256}` with `public_blobs` turned on.
+//! The configuration is shared by the whole process, so these tests have a binary of their own.
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use bitbeam::test_support::TestServer;
+use http_body_util::BodyExt;
+
+#[tokio::test]
+async fn an_uploaded_page_is_never_run_from_its_blob() {
+    let server = TestServer::with_config(&[("public_blobs", "true")]).await;
+    let user = server.create_user("alice").await;
+    let page = "<script>alert(localStorage.key)</script>";
+    let upload = Request::post("/upload")
+        .header("key", &user.key)
+        .header("file_name", "page.html")
+        .header("content-type", "text/html")
+        .header("download_limit", "0")
+        .body(Body::from(page))
+        .unwrap();
+    let response = server.request(upload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let uploaded: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let sha256 = uploaded["content_hash"].as_str().unwrap();
+
+    let response = server
+        .request(Request::get(format!("/blob/{}", sha256)).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert!(headers[header::CONTENT_DISPOSITION].to_str().unwrap().starts_with("attachment"));
+    assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert!(headers[header::CONTENT_SECURITY_POLICY].to_str().unwrap().starts_with("sandbox"));
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), page);
+}
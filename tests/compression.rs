@@ -0,0 +1,83 @@
+//! Blobs stored compressed with `compress_blobs` turned on.
+//! The configuration is shared by the whole process, so these tests have a binary of their own.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use std::path::Path;
+
+/// Uploads a text file and returns its ID and content hash.
+async fn upload(server: &TestServer, user: &TestUser, body: Vec<u8>) -> (String, String) {
+    let request = Request::post("/upload")
+        .header("key", &user.key)
+        .header("file_name", "notes.txt")
+        .header("content-type", "text/plain")
+        .header("download_limit", "0")
+        .body(Body::from(body))
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let uploaded: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    (
+        uploaded["id"].as_str().unwrap().to_string(),
+        uploaded["content_hash"].as_str().unwrap().to_string(),
+    )
+}
+
+/// Downloads a file without accepting gzip and returns its contents.
+async fn download(server: &TestServer, id: &str) -> Vec<u8> {
+    let response = server
+        .request(Request::get(format!("/download/{}", id)).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.into_body().collect().await.unwrap().to_bytes().to_vec()
+}
+
+/// Returns the path of a stored blob in the shard directories of the data path.
+fn stored_path(root: &Path, stored: &str) -> std::path::PathBuf {
+    root.join(&stored[0..2]).join(&stored[2..4]).join(stored)
+}
+
+#[tokio::test]
+async fn an_upload_that_looks_compressed_is_sent_back_as_it_was() {
+    let server = TestServer::with_config(&[("compress_blobs", "true")]).await;
+    let user = server.create_user("alice").await;
+    // the first bytes of a compressed blob, but too short to be worth compressing
+    let body = b"bitBeamZ\x1f\x8b\x08\x00".to_vec();
+    let (id, content_hash) = upload(&server, &user, body.clone()).await;
+
+    assert!(stored_path(server.data_path(), &content_hash).exists());
+    assert_eq!(download(&server, &id).await, body);
+}
+
+#[tokio::test]
+async fn a_compressed_blob_is_stored_under_its_own_name() {
+    let server = TestServer::with_config(&[("compress_blobs", "true")]).await;
+    let user = server.create_user("alice").await;
+    let body = "all work and no play makes jack a dull boy\n".repeat(1000).into_bytes();
+    let (id, content_hash) = upload(&server, &user, body.clone()).await;
+
+    assert!(stored_path(server.data_path(), &format!("{}.gz", content_hash)).exists());
+    assert!(!stored_path(server.data_path(), &content_hash).exists());
+    assert_eq!(download(&server, &id).await, body);
+}
+
+#[tokio::test]
+async fn a_compressed_blob_that_grows_past_its_file_is_refused() {
+    let server = TestServer::with_config(&[("compress_blobs", "true")]).await;
+    let user = server.create_user("alice").await;
+    let body = "all work and no play makes jack a dull boy\n".repeat(1000).into_bytes();
+    let (id, _) = upload(&server, &user, body).await;
+    // the row claims a smaller file than the blob decompresses to
+    sqlx::query("UPDATE files SET file_size = 10 WHERE id = ?")
+        .bind(&id)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    let response = server
+        .request(Request::get(format!("/download/{}", id)).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,70 @@
+//! Blobs stored encrypted with a `master_key`.
+//! The configuration is shared by the whole process, so these tests have a binary of their own.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use std::path::Path;
+
+/// A master key, hex encoded.
+const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+/// Uploads a file and returns its ID and content hash.
+async fn upload(server: &TestServer, user: &TestUser, body: Vec<u8>) -> (String, String) {
+    let request = Request::post("/upload")
+        .header("key", &user.key)
+        .header("file_name", "notes.bin")
+        .header("download_limit", "0")
+        .body(Body::from(body))
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let uploaded: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    (
+        uploaded["id"].as_str().unwrap().to_string(),
+        uploaded["content_hash"].as_str().unwrap().to_string(),
+    )
+}
+
+/// Downloads a file and returns its contents.
+async fn download(server: &TestServer, id: &str) -> Vec<u8> {
+    let response = server
+        .request(Request::get(format!("/download/{}", id)).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.into_body().collect().await.unwrap().to_bytes().to_vec()
+}
+
+/// Returns the path of a stored blob in the shard directories of the data path.
+fn stored_path(root: &Path, stored: &str) -> std::path::PathBuf {
+    root.join(&stored[0..2]).join(&stored[2..4]).join(stored)
+}
+
+#[tokio::test]
+async fn an_encrypted_blob_is_stored_under_its_own_name() {
+    let server = TestServer::with_config(&[("master_key", MASTER_KEY)]).await;
+    let user = server.create_user("alice").await;
+    let body = b"nobody reads this on the disk".to_vec();
+    let (id, content_hash) = upload(&server, &user, body.clone()).await;
+
+    let stored = stored_path(server.data_path(), &format!("{}.enc", content_hash));
+    assert_ne!(std::fs::read(stored).unwrap(), body);
+    assert!(!stored_path(server.data_path(), &content_hash).exists());
+    assert_eq!(download(&server, &id).await, body);
+}
+
+#[tokio::test]
+async fn a_plain_blob_that_looks_encrypted_is_sent_as_it_is() {
+    let server = TestServer::with_config(&[("master_key", MASTER_KEY)]).await;
+    let user = server.create_user("alice").await;
+    // the header of an encrypted blob, followed by more than a header worth of plain bytes
+    let mut body = b"bitBeam\x01".to_vec();
+    body.extend_from_slice(&[7; 128]);
+    let (id, content_hash) = upload(&server, &user, body.clone()).await;
+    // stored as a blob written before the master key was set
+    std::fs::remove_file(stored_path(server.data_path(), &format!("{}.enc", content_hash))).unwrap();
+    std::fs::write(stored_path(server.data_path(), &content_hash), &body).unwrap();
+
+    assert_eq!(download(&server, &id).await, body);
+}
@@ -0,0 +1,66 @@
+//! Clients and usernames locked out after failed logins and unknown keys.
+//! The lockout is shared by the whole process, so these tests have a binary of their own.
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use std::net::SocketAddr;
+
+/// Starts a server that locks out after two failures.
+async fn server() -> TestServer {
+    TestServer::with_config(&[("lockout_attempts", "2"), ("lockout_duration", "60")]).await
+}
+
+/// Sends a request from the given client address and returns the response.
+async fn send_from(server: &TestServer, mut request: Request<Body>, client: &str) -> Response {
+    let addr: SocketAddr = client.parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(addr));
+    server.request(request).await
+}
+
+/// Lists the files with the key from the given client address and returns the response.
+async fn list_files(server: &TestServer, key: &str, client: &str) -> Response {
+    let request = Request::get("/files").header("key", key).body(Body::empty()).unwrap();
+    send_from(server, request, client).await
+}
+
+/// Logs in with the password from the given client address and returns the status.
+async fn login(server: &TestServer, user: &TestUser, password: &str, client: &str) -> StatusCode {
+    let request = Request::post("/user/login")
+        .header("username", &user.username)
+        .header("password", password)
+        .body(Body::empty())
+        .unwrap();
+    send_from(server, request, client).await.status()
+}
+
+#[tokio::test]
+async fn a_client_with_unknown_keys_is_locked_out() {
+    let server = server().await;
+    let user = server.create_user("alice").await;
+    for _ in 0..2 {
+        let response = list_files(&server, "not-a-key", "192.0.2.10:1000").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // even a valid key is refused until the lockout ran out
+    let response = list_files(&server, &user.key, "192.0.2.10:1000").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+
+    let response = list_files(&server, &user.key, "192.0.2.11:1000").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_username_with_wrong_passwords_is_locked_out_everywhere() {
+    let server = server().await;
+    let user = server.create_user("bob").await;
+    assert_eq!(login(&server, &user, "wrong", "192.0.2.20:1000").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(login(&server, &user, "wrong", "192.0.2.21:1000").await, StatusCode::UNAUTHORIZED);
+
+    let password = user.password.clone();
+    assert_eq!(login(&server, &user, &password, "192.0.2.22:1000").await, StatusCode::TOO_MANY_REQUESTS);
+}
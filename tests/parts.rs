@@ -0,0 +1,150 @@
+//! Multipart uploads, sent in numbered parts and stored as one file.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+
+/// Returns the JSON body of a response.
+async fn json(response: Response) -> Value {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).expect("the body is JSON")
+}
+
+/// Starts a multipart upload and returns its ID.
+async fn init(server: &TestServer, user: &TestUser) -> String {
+    let request = Request::post("/upload/init")
+        .header("key", &user.key)
+        .header("file_name", "notes.txt")
+        .header("download_limit", "0")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json(response).await["upload_id"].as_str().unwrap().to_string()
+}
+
+/// Sends a part of the upload with the given extra headers and returns the response.
+async fn put_part(
+    server: &TestServer,
+    user: &TestUser,
+    upload_id: &str,
+    part_number: i32,
+    headers: &[(&str, &str)],
+    body: &'static str,
+) -> Response {
+    let mut request = Request::put(format!("/upload/{}/part/{}", upload_id, part_number)).header("key", &user.key);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    server.request(request.body(Body::from(body)).unwrap()).await
+}
+
+/// Sends a part of the upload and returns its SHA-256.
+async fn part(server: &TestServer, user: &TestUser, upload_id: &str, part_number: i32, body: &'static str) -> String {
+    let response = put_part(server, user, upload_id, part_number, &[], body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json(response).await["sha256"].as_str().unwrap().to_string()
+}
+
+/// Completes the upload with the given body and returns the response.
+async fn complete(server: &TestServer, user: &TestUser, upload_id: &str, body: Option<Value>) -> Response {
+    let request = Request::post(format!("/upload/{}/complete", upload_id)).header("key", &user.key);
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    server.request(request.unwrap()).await
+}
+
+/// Downloads a file and returns its contents.
+async fn download(server: &TestServer, id: &str) -> Vec<u8> {
+    let response = server
+        .request(Request::get(format!("/download/{}", id)).body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.into_body().collect().await.unwrap().to_bytes().to_vec()
+}
+
+#[tokio::test]
+async fn parts_are_joined_by_their_number() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let upload_id = init(&server, &user).await;
+    part(&server, &user, &upload_id, 2, "world").await;
+    part(&server, &user, &upload_id, 1, "hello ").await;
+
+    let response = complete(&server, &user, &upload_id, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id = json(response).await["id"].as_str().unwrap().to_string();
+    assert_eq!(download(&server, &id).await, b"hello world");
+
+    let response = complete(&server, &user, &upload_id, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_part_sent_again_replaces_the_first_one() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let upload_id = init(&server, &user).await;
+    part(&server, &user, &upload_id, 1, "broken").await;
+    part(&server, &user, &upload_id, 1, "fixed").await;
+
+    let response = complete(&server, &user, &upload_id, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id = json(response).await["id"].as_str().unwrap().to_string();
+    assert_eq!(download(&server, &id).await, b"fixed");
+}
+
+#[tokio::test]
+async fn only_the_listed_parts_are_joined() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let upload_id = init(&server, &user).await;
+    let first = part(&server, &user, &upload_id, 1, "hello").await;
+    part(&server, &user, &upload_id, 2, " and goodbye").await;
+
+    // a listed part that does not match leaves the upload to be completed again
+    let listed = json!({"parts": [{"part_number": 1, "sha256": "00"}]});
+    let response = complete(&server, &user, &upload_id, Some(listed)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let listed = json!({"parts": [{"part_number": 1, "sha256": first}]});
+    let response = complete(&server, &user, &upload_id, Some(listed)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id = json(response).await["id"].as_str().unwrap().to_string();
+    assert_eq!(download(&server, &id).await, b"hello");
+}
+
+#[tokio::test]
+async fn a_part_with_a_wrong_checksum_or_number_is_refused() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let upload_id = init(&server, &user).await;
+    let wrong = "0".repeat(64);
+
+    let response = put_part(&server, &user, &upload_id, 1, &[("content-sha256", &wrong)], "hello").await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = put_part(&server, &user, &upload_id, 0, &[], "hello").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = complete(&server, &user, &upload_id, None).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn an_upload_is_only_seen_by_its_owner() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let other = server.create_user("bob").await;
+    let upload_id = init(&server, &user).await;
+
+    let response = put_part(&server, &other, &upload_id, 1, &[], "hello").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = complete(&server, &other, &upload_id, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
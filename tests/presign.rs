@@ -0,0 +1,119 @@
+//! Pre-signed upload URLs and what the key or token that created them allows.
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+
+/// Sends a JSON request with the key.
+async fn send_json(server: &TestServer, method: &str, path: &str, key: &str, body: Value) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header("key", key)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    server.request(request).await
+}
+
+/// Returns the JSON body of a response.
+async fn json(response: Response) -> Value {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).expect("the body is JSON")
+}
+
+/// Creates a scoped token for the user and returns its ID and secret.
+async fn create_token(server: &TestServer, user: &TestUser, body: Value) -> (String, String) {
+    let response = send_json(server, "POST", "/user/tokens", &user.key, body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = json(response).await;
+    (token["id"].as_str().unwrap().to_string(), token["token"].as_str().unwrap().to_string())
+}
+
+/// Creates an upload URL with the key and returns the response.
+async fn presign(server: &TestServer, key: &str, body: Value) -> Response {
+    send_json(server, "POST", "/upload/presign", key, body).await
+}
+
+/// Creates an upload URL with the key and returns the path it can be used at.
+async fn presigned_path(server: &TestServer, key: &str, body: Value) -> String {
+    let response = presign(server, key, body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let url = json(response).await["url"].as_str().unwrap().to_string();
+    let start = url.find("/upload/presigned/").expect("an upload URL");
+    url[start..].to_string()
+}
+
+/// Uploads a PNG file with an upload URL and returns the status.
+async fn upload(server: &TestServer, path: &str) -> StatusCode {
+    let request = Request::post(path)
+        .header("file_name", "picture.png")
+        .header("content-type", "image/png")
+        .body(Body::from("not really a picture"))
+        .unwrap();
+    server.request(request).await.status()
+}
+
+#[tokio::test]
+async fn an_upload_url_is_used_once() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let path = presigned_path(&server, &user.key, json!({})).await;
+
+    assert_eq!(upload(&server, &path).await, StatusCode::OK);
+    assert_eq!(upload(&server, &path).await, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn an_upload_url_allows_no_more_than_its_token() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let (_, token) = create_token(
+        &server,
+        &user,
+        json!({"scopes": ["upload"], "content_types": ["image/png"], "expires_in": 600}),
+    )
+    .await;
+
+    let response = presign(&server, &token, json!({"content_types": ["image/*"]})).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = presign(&server, &token, json!({"content_types": ["image/png"], "expires_in": 86400})).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = json(response).await;
+    let now = chrono::Utc::now().timestamp();
+    assert!(created["expires"].as_i64().unwrap() <= now + 600);
+
+    let response = presign(&server, &token, json!({})).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(json(response).await["content_types"], json!(["image/png"]));
+}
+
+#[tokio::test]
+async fn an_upload_url_is_revoked_with_its_token() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let (id, token) = create_token(&server, &user, json!({"scopes": ["upload"]})).await;
+    let path = presigned_path(&server, &token, json!({})).await;
+
+    let response = send_json(&server, "DELETE", &format!("/user/tokens/{}", id), &user.key, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(upload(&server, &path).await, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn an_upload_url_is_revoked_with_its_key() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let response = send_json(&server, "POST", "/user/keys", &user.key, json!({"name": "laptop"})).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let key = json(response).await;
+    let path = presigned_path(&server, key["key"].as_str().unwrap(), json!({})).await;
+
+    let revoke = format!("/user/keys/{}", key["id"].as_str().unwrap());
+    let response = send_json(&server, "DELETE", &revoke, &user.key, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(upload(&server, &path).await, StatusCode::GONE);
+}
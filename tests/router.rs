@@ -0,0 +1,82 @@
+//! The router of `bitbeam::build_app` embedded like another Axum app would,
+//! driven with `tower::ServiceExt::oneshot`.
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use bitbeam::test_support::TestServer;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+/// Builds the router from the configuration and pool of a test server.
+async fn app() -> (TestServer, Router) {
+    let server = TestServer::start().await;
+    let router = bitbeam::build_app(server.config.clone(), server.pool.clone()).expect("could not build the router");
+    (server, router)
+}
+
+/// Sends one request to the router, coming from the address the server would get from the socket.
+async fn send(router: &Router, mut request: Request<Body>) -> Response {
+    let addr: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(addr));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn the_openapi_spec_is_served() {
+    let (_server, router) = app().await;
+
+    let response = send(&router, Request::get("/api/spec").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let spec: Value = serde_json::from_slice(&body).unwrap();
+    assert!(spec["paths"]["/upload"].is_object());
+}
+
+#[tokio::test]
+async fn an_unknown_path_is_not_found() {
+    let (_server, router) = app().await;
+
+    let response = send(&router, Request::get("/no/such/route").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn an_upload_can_be_downloaded() {
+    let (server, router) = app().await;
+    let user = server.create_user("alice").await;
+
+    let upload = Request::post("/upload")
+        .header("key", &user.key)
+        .header("file_name", "notes.txt")
+        .body(Body::from("hello"))
+        .unwrap();
+    let response = send(&router, upload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let uploaded: Value = serde_json::from_slice(&body).unwrap();
+    let id = uploaded["id"].as_str().unwrap();
+
+    let download = Request::get(format!("/download/{}", id)).body(Body::empty()).unwrap();
+    let response = send(&router, download).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "hello");
+}
+
+#[tokio::test]
+async fn the_access_log_records_the_route_instead_of_the_token_in_the_path() {
+    let server = TestServer::start().await;
+    let log = server.data_path().join("access.log");
+    let mut config = server.config.clone();
+    config.access_log = Some(log.to_string_lossy().into_owned());
+    let router = bitbeam::build_app(config, server.pool.clone()).expect("could not build the router");
+
+    send(&router, Request::get("/user/verify/secret-token").body(Body::empty()).unwrap()).await;
+    send(&router, Request::get("/no/such/secret-token").body(Body::empty()).unwrap()).await;
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("/user/verify/{token}"), "{}", logged);
+    assert!(!logged.contains("secret-token"), "{}", logged);
+}
@@ -0,0 +1,135 @@
+//! The S3 API, driven with requests signed like an S3 client signs them.
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use bitbeam::test_support::{TestServer, TestUser};
+use hmac::{Hmac, Mac};
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+
+/// Returns the HMAC-SHA256 of a message.
+fn hmac(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Sends a request signed with AWS Signature Version 4 with the key of the user as secret,
+/// the range is sent and signed if there is one.
+async fn send(
+    server: &TestServer,
+    user: &TestUser,
+    secret: &str,
+    method: &str,
+    path: &str,
+    range: Option<&str>,
+    body: &'static [u8],
+) -> Response {
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{}/us-east-1/s3/aws4_request", now.format("%Y%m%d"));
+    let payload_hash = hex::encode(Sha256::digest(body));
+    let mut headers = vec![
+        ("host", "localhost".to_string()),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(range) = range {
+        headers.insert(1, ("range", range.to_string()));
+    }
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = format!("AWS4{}", secret).into_bytes();
+    for part in scope.split('/') {
+        key = hmac(&key, part);
+    }
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        user.username, scope, signed_headers, signature
+    );
+
+    let mut request = Request::builder().method(method).uri(path);
+    for (name, value) in &headers {
+        request = request.header(*name, value);
+    }
+    let request = request
+        .header(header::AUTHORIZATION, authorization)
+        .body(Body::from(body))
+        .unwrap();
+    server.request(request).await
+}
+
+/// Returns the body of a response.
+async fn body(response: Response) -> Vec<u8> {
+    response.into_body().collect().await.unwrap().to_bytes().to_vec()
+}
+
+/// Stores an object of the user.
+async fn put_object(server: &TestServer, user: &TestUser, path: &str, body: &'static [u8]) {
+    let response = send(server, user, &user.key, "PUT", path, None, body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn an_object_is_read_back_whole() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    put_object(&server, &user, "/s3/alice/backups/notes.txt", b"hello world").await;
+
+    let response = send(&server, &user, &user.key, "GET", "/s3/alice/backups/notes.txt", None, b"").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "11");
+    assert_eq!(body(response).await, b"hello world");
+}
+
+#[tokio::test]
+async fn a_range_of_an_object_is_read() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    put_object(&server, &user, "/s3/alice/notes.txt", b"hello world").await;
+
+    let response = send(&server, &user, &user.key, "GET", "/s3/alice/notes.txt", Some("bytes=6-10"), b"").await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 6-10/11");
+    assert_eq!(body(response).await, b"world");
+
+    let response = send(&server, &user, &user.key, "GET", "/s3/alice/notes.txt", Some("bytes=-5"), b"").await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body(response).await, b"world");
+
+    let response = send(&server, &user, &user.key, "GET", "/s3/alice/notes.txt", Some("bytes=20-"), b"").await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+#[tokio::test]
+async fn a_request_signed_with_another_secret_is_refused() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    put_object(&server, &user, "/s3/alice/notes.txt", b"hello world").await;
+
+    let response = send(&server, &user, "not-the-key", "GET", "/s3/alice/notes.txt", None, b"").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(String::from_utf8(body(response).await).unwrap().contains("SignatureDoesNotMatch"));
+}
+
+#[tokio::test]
+async fn the_bucket_of_another_user_is_refused() {
+    let server = TestServer::start().await;
+    let user = server.create_user("alice").await;
+    let other = server.create_user("bob").await;
+    put_object(&server, &other, "/s3/bob/notes.txt", b"hello world").await;
+
+    let response = send(&server, &user, &user.key, "GET", "/s3/bob/notes.txt", None, b"").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,35 @@
+//! Logging in with a username and password and the session cookie it sets.
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use bitbeam::test_support::{TestServer, TestUser};
+
+/// Logs the user in and returns the `set-cookie` value of the response.
+async fn login(server: &TestServer, user: &TestUser) -> String {
+    let request = Request::post("/user/login")
+        .header("username", &user.username)
+        .header("password", &user.password)
+        .body(Body::empty())
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.headers()[header::SET_COOKIE].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn the_session_cookie_is_only_sent_over_https_behind_an_https_base_url() {
+    let server = TestServer::with_config(&[("base_url", "https://files.example.com")]).await;
+    let user = server.create_user("alice").await;
+
+    let cookie = login(&server, &user).await;
+    assert!(cookie.ends_with("; Secure"), "{}", cookie);
+}
+
+#[tokio::test]
+async fn the_session_cookie_is_sent_over_http_behind_an_http_base_url() {
+    let server = TestServer::with_config(&[("base_url", "http://localhost:3000")]).await;
+    let user = server.create_user("alice").await;
+
+    let cookie = login(&server, &user).await;
+    assert!(cookie.contains("HttpOnly"), "{}", cookie);
+    assert!(!cookie.contains("Secure"), "{}", cookie);
+}